@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// Short names for the identifiers in a wallet, e.g. `bob` for `did:web:tsp-test.org:user:bob`
+///
+/// Every alias refers to a single identifier, and is stored as a JSON object from alias to
+/// identifier next to the VIDs of the wallet.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Aliases(BTreeMap<String, String>);
+
+/// Why an alias could not be added
+#[derive(Debug)]
+pub struct AliasTaken {
+    alias: String,
+    vid: String,
+}
+
+impl fmt::Display for AliasTaken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Error: alias {} already refers to {}",
+            self.alias, self.vid
+        )
+    }
+}
+
+impl std::error::Error for AliasTaken {}
+
+impl Aliases {
+    /// The identifier `name` is an alias of, or `name` itself if it is not an alias
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.0.get(name).map_or(name, String::as_str)
+    }
+
+    /// An alias of the identifier `vid`, if it has one
+    pub fn alias_of(&self, vid: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(_, target)| *target == vid)
+            .map(|(alias, _)| alias.as_str())
+    }
+
+    /// Let `alias` refer to `vid`; an alias that already refers to another identifier has
+    /// to be removed first
+    pub fn insert(&mut self, alias: String, vid: String) -> Result<(), AliasTaken> {
+        if let Some(target) = self.0.get(&alias).filter(|target| **target != vid) {
+            return Err(AliasTaken {
+                vid: target.clone(),
+                alias,
+            });
+        }
+
+        self.0.insert(alias, vid);
+
+        Ok(())
+    }
+
+    /// Remove `alias`; returns the identifier it referred to
+    pub fn remove(&mut self, alias: &str) -> Option<String> {
+        self.0.remove(alias)
+    }
+
+    /// The aliases and the identifiers they refer to, ordered by alias
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(alias, vid)| (alias.as_str(), vid.as_str()))
+    }
+}
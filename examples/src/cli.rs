@@ -1,13 +1,23 @@
+mod aliases;
+mod listen;
+mod replay;
+
+use aliases::Aliases;
 use base64ct::{Base64Unpadded, Base64UrlUnpadded, Encoding};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use futures::StreamExt;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::io::AsyncReadExt;
 use tracing::{info, trace};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use tsp::{
-    cesr::Part, AsyncStore, Error, ExportVid, OwnedVid, ReceivedTspMessage, Vault, VerifiedVid,
+    cesr::{AnnotatedPart, PartType},
+    vid::{publish_did_document, Provenance, PublishEndpoint},
+    AsyncStore, Error, MessageHeaders, OwnedVid, ReceivedTspMessage, VerifiedVid, Wallet,
 };
 
 #[derive(Debug, Parser)]
@@ -54,6 +64,30 @@ enum Commands {
     },
     #[command(arg_required_else_help = true)]
     Print { alias: String },
+    #[command(arg_required_else_help = true, about = "show an identifier")]
+    Show {
+        #[command(subcommand)]
+        format: ShowFormat,
+    },
+    #[command(arg_required_else_help = true, about = "manage the wallet database")]
+    Wallet {
+        #[command(subcommand)]
+        command: WalletCommand,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "same as `debug decode`, with the base64url-encoded message as an argument"
+    )]
+    Diagnose { message: String },
+    #[command(arg_required_else_help = true, about = "analyze TSP messages")]
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommand,
+    },
+    #[command(
+        about = "check whether the endpoints of an identifier, or of all identifiers, can be reached"
+    )]
+    Probe { vid: Option<String> },
     #[command(
         arg_required_else_help = true,
         about = "create and register a did:web identifier"
@@ -62,6 +96,16 @@ enum Commands {
         username: String,
         #[arg(short, long)]
         alias: Option<String>,
+        #[arg(
+            long,
+            help = "Publish the DID document with an HTTP PUT to this URL instead of the test server"
+        )]
+        publish_url: Option<url::Url>,
+        #[arg(
+            long,
+            help = "Bearer token used to authenticate the DID document upload"
+        )]
+        auth_token: Option<String>,
     },
     CreatePeer {
         alias: String,
@@ -82,6 +126,14 @@ enum Commands {
     },
     #[command(arg_required_else_help = true)]
     SetAlias { alias: String, vid: String },
+    #[command(
+        arg_required_else_help = true,
+        about = "list, look up and remove aliases"
+    )]
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
     #[command(arg_required_else_help = true)]
     SetRoute { vid: String, route: String },
     #[command(arg_required_else_help = true)]
@@ -94,8 +146,20 @@ enum Commands {
         sender_vid: String,
         #[arg(short, long, required = true)]
         receiver_vid: String,
-        #[arg(short, long)]
+        #[arg(short, long, conflicts_with = "file")]
         non_confidential_data: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Send the contents of a file instead of reading the message from stdin"
+        )]
+        file: Option<PathBuf>,
+        #[arg(
+            long,
+            conflicts_with_all = ["file", "non_confidential_data"],
+            help = "Only sign the message, without encrypting it"
+        )]
+        signed: bool,
     },
     #[command(arg_required_else_help = true, about = "listen for messages")]
     Receive {
@@ -103,6 +167,28 @@ enum Commands {
         #[arg(short, long)]
         one: bool,
     },
+    #[command(
+        arg_required_else_help = true,
+        about = "keep listening for messages, log them as JSON lines and handle relationships automatically"
+    )]
+    Listen {
+        vid: String,
+        #[arg(
+            long,
+            help = "Accept relationship requests from the senders the policy allows"
+        )]
+        auto_accept: bool,
+        #[arg(long, help = "Acknowledge every received message")]
+        auto_ack: bool,
+        #[arg(long, help = "YAML file with the rules for handling messages")]
+        policy: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 60,
+            help = "Seconds between saving the database"
+        )]
+        persist_interval: u64,
+    },
     #[command(arg_required_else_help = true, about = "propose a relationship")]
     Request {
         #[arg(short, long, required = true)]
@@ -129,6 +215,8 @@ enum Commands {
         sender_vid: String,
         #[arg(short, long, required = true)]
         receiver_vid: String,
+        #[arg(long)]
+        nested: bool,
     },
     #[command(arg_required_else_help = true, about = "send an identity referral")]
     Refer {
@@ -139,6 +227,23 @@ enum Commands {
         #[arg(short, long, required = true)]
         referred_vid: String,
     },
+    #[command(
+        arg_required_else_help = true,
+        about = "verify a referred identity, and optionally request a relationship with it"
+    )]
+    AcceptReferral {
+        #[arg(
+            short,
+            long,
+            required = true,
+            help = "the identity that sent the referral"
+        )]
+        referrer: String,
+        #[arg(long, required = true)]
+        referred_vid: String,
+        #[arg(short, long, help = "request a relationship from this identity")]
+        sender_vid: Option<String>,
+    },
     #[command(arg_required_else_help = true, about = "publish a new own identity")]
     Publish {
         #[arg(short, long, required = true)]
@@ -148,19 +253,78 @@ enum Commands {
         #[arg(short, long, required = true)]
         new_vid: String,
     },
+    #[command(
+        arg_required_else_help = true,
+        about = "run a scripted conversation between wallets and check the received messages"
+    )]
+    Replay { scenario: PathBuf },
 }
 
-type Aliases = HashMap<String, String>;
+#[derive(Debug, Subcommand)]
+enum ShowFormat {
+    #[command(
+        arg_required_else_help = true,
+        about = "render an identifier and its keys as a QR code for out-of-band exchange"
+    )]
+    Qr { alias: String },
+    #[command(
+        about = "show message counts per relationship, for every identifier if no alias is given"
+    )]
+    Stats { alias: Option<String> },
+    #[command(
+        arg_required_else_help = true,
+        about = "show how an identifier entered the wallet, and which identifiers it introduced"
+    )]
+    Provenance { alias: String },
+    #[command(about = "show every identifier with its relationships, parents and routes")]
+    Graph {
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+}
 
-#[derive(Serialize, Deserialize)]
-struct DatabaseContents {
-    data: Vec<ExportVid>,
-    aliases: Aliases,
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    /// Graphviz, e.g. `tsp show graph | dot -Tsvg > graph.svg`
+    Dot,
+    Json,
 }
 
-async fn write_database(vault: &Vault, db: &AsyncStore, aliases: Aliases) -> Result<(), Error> {
-    let aliases = serde_json::to_value(&aliases).ok();
-    vault.persist(db.export()?, aliases).await?;
+#[derive(Debug, Subcommand)]
+enum AliasCommand {
+    #[command(about = "list every alias with the identifier it refers to")]
+    List,
+    #[command(arg_required_else_help = true, about = "remove an alias")]
+    Remove { alias: String },
+    #[command(
+        arg_required_else_help = true,
+        about = "print the alias of an identifier"
+    )]
+    Of { vid: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum WalletCommand {
+    #[command(about = "upgrade the database to the format of this release")]
+    Migrate,
+}
+
+#[derive(Debug, Subcommand)]
+enum DebugCommand {
+    #[command(
+        about = "describe a binary or base64url-encoded CESR message, and open it if the wallet has the keys"
+    )]
+    Decode {
+        #[arg(help = "Read the message from a file instead of stdin")]
+        file: Option<PathBuf>,
+    },
+}
+
+async fn write_database(wallet: &mut Wallet, aliases: Aliases) -> Result<(), Error> {
+    if let Ok(aliases) = serde_json::to_value(&aliases) {
+        wallet.set_extra_data(aliases);
+    }
+    wallet.save().await?;
 
     trace!("persisted database");
 
@@ -170,62 +334,118 @@ async fn write_database(vault: &Vault, db: &AsyncStore, aliases: Aliases) -> Res
 async fn read_database(
     database_name: &str,
     password: &str,
-) -> Result<(Vault, AsyncStore, Aliases), Error> {
-    match Vault::open_sqlite(database_name, password.as_bytes()).await {
-        Ok(vault) => {
-            let (vids, aliases) = vault.load().await?;
-
-            let aliases: Aliases = match aliases {
-                Some(aliases) => serde_json::from_value(aliases).expect("Invalid aliases"),
-                None => Aliases::new(),
-            };
-
-            let db = AsyncStore::new();
-            db.import(vids)?;
-
-            trace!("opened database {database_name}");
+) -> Result<(Wallet, AsyncStore, Aliases), Error> {
+    let wallet = Wallet::open(database_name, password.as_bytes()).await?;
 
-            Ok((vault, db, aliases))
+    // the aliases are written with every change, so only a new database has none
+    let aliases: Aliases = match wallet.extra_data() {
+        Some(aliases) => serde_json::from_value(aliases.clone()).expect("Invalid aliases"),
+        None => {
+            info!("created new database");
+            Aliases::default()
         }
-        Err(_) => {
-            let vault = Vault::new_sqlite(database_name, password.as_bytes()).await?;
+    };
 
-            let db = AsyncStore::new();
-            info!("created new database");
+    trace!("opened database {database_name}");
 
-            Ok((vault, db, Aliases::new()))
-        }
-    }
+    // a clone of the store shares its VIDs with the wallet
+    let db = wallet.store().clone();
+
+    Ok((wallet, db, aliases))
 }
 
-fn color_print_part(part: Option<Part>, color: u8) {
-    if let Some(Part { prefix, data }) = part {
-        print!(
-            "\x1b[1;{color}m{}\x1b[0;{color}m{}\x1b[0m",
-            Base64UrlUnpadded::encode_string(prefix),
-            Base64UrlUnpadded::encode_string(data)
-        );
-    }
+fn color_print_part(message: &[u8], part: &AnnotatedPart) {
+    let color = match part.part_type {
+        PartType::Header => 31,
+        PartType::Sender => 35,
+        PartType::Receiver => 34,
+        PartType::NonconfidentialData => 32,
+        PartType::Ciphertext => 33,
+        PartType::Signature => 36,
+    };
+    let (prefix, data) = message[part.offset..][..part.len].split_at(part.code_len);
+
+    print!(
+        "\x1b[1;{color}m{}\x1b[0;{color}m{}\x1b[0m",
+        Base64UrlUnpadded::encode_string(prefix),
+        Base64UrlUnpadded::encode_string(data)
+    );
 }
 
 fn print_message(message: &[u8]) {
-    let Ok(parts) = tsp::cesr::open_message_into_parts(message) else {
+    let Ok(parts) = tsp::cesr::annotate(message) else {
         eprintln!("Invalid encoded message");
+        for diagnostic in tsp::cesr::diagnose(message) {
+            eprintln!("{diagnostic}");
+        }
         return;
     };
 
     println!("CESR-encoded message:");
 
-    color_print_part(Some(parts.prefix), 31);
-    color_print_part(Some(parts.sender), 35);
-    color_print_part(parts.receiver, 34);
-    color_print_part(parts.nonconfidential_data, 32);
-    color_print_part(parts.ciphertext, 33);
-    color_print_part(Some(parts.signature), 36);
+    for part in &parts {
+        color_print_part(message, part);
+    }
 
     println!();
 }
 
+/// Describe a captured message, binary or base64url-encoded, and open it if the wallet has
+/// the keys; shared by `tsp diagnose` and `tsp debug decode`
+fn decode_message(vid_database: &AsyncStore, input: Vec<u8>) {
+    let mut message = std::str::from_utf8(&input)
+        .ok()
+        .and_then(|text| Base64UrlUnpadded::decode_vec(text.trim()).ok())
+        .unwrap_or(input);
+
+    let Ok(parts) = tsp::cesr::open_message_into_parts(&message) else {
+        eprintln!("Invalid encoded message");
+        for diagnostic in tsp::cesr::diagnose(&message) {
+            eprintln!("{diagnostic}");
+        }
+
+        return;
+    };
+
+    let text = |data: &[u8]| String::from_utf8_lossy(data).into_owned();
+    println!("sender:         {}", text(parts.sender.data));
+    println!(
+        "receiver:       {}",
+        parts
+            .receiver
+            .as_ref()
+            .map_or("-".to_string(), |receiver| text(receiver.data))
+    );
+    println!("crypto type:    {:?}", parts.crypto_type);
+    println!("signature type: {:?}", parts.signature_type);
+    if let Some(data) = &parts.nonconfidential_data {
+        println!("nonconfidential data: {} bytes", data.data.len());
+    }
+    if let Some(ciphertext) = &parts.ciphertext {
+        println!("ciphertext:     {} bytes", ciphertext.data.len());
+    }
+
+    println!("fields:");
+    for diagnostic in tsp::cesr::diagnose(&message) {
+        println!("  {diagnostic}");
+    }
+
+    print_message(&message);
+
+    // the wallet is not written afterwards, so opening a captured control
+    // message does not change any relationship
+    match vid_database.as_store().open_message_owned(&mut message) {
+        Ok(ReceivedTspMessage::GenericMessage {
+            sender, message, ..
+        }) => {
+            println!("opened message from {sender} ({} bytes):", message.len());
+            println!("{}", String::from_utf8_lossy(&message));
+        }
+        Ok(received) => println!("opened {received:?}"),
+        Err(e) => println!("could not open the message: {e}"),
+    }
+}
+
 fn prompt(message: String) -> bool {
     use std::io::{self, BufRead, Write};
     print!("{message}? [y/n] ");
@@ -240,7 +460,194 @@ fn prompt(message: String) -> bool {
     matches!(line.trim(), "Y" | "YES")
 }
 
-async fn run() -> Result<(), Error> {
+/// Files are sent in chunks of at most this size, each in a separate message
+const FILE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Header fields with the name of a sent file and the "index/count" of a chunk
+const FILE_NAME_HEADER: &str = "fn";
+const FILE_PART_HEADER: &str = "part";
+
+struct FilePart {
+    name: String,
+    index: usize,
+    count: usize,
+}
+
+impl FilePart {
+    fn from_headers(headers: &MessageHeaders) -> Option<Self> {
+        let name = std::str::from_utf8(headers.get(FILE_NAME_HEADER)?).ok()?;
+        // only use the last path component, never write outside the working directory
+        let name = Path::new(name).file_name()?.to_str()?.to_string();
+
+        let part = std::str::from_utf8(headers.get(FILE_PART_HEADER)?).ok()?;
+        let (index, count) = part.split_once('/')?;
+        let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+
+        (1..=count)
+            .contains(&index)
+            .then_some(Self { name, index, count })
+    }
+}
+
+fn print_progress(action: &str, part: &FilePart) {
+    eprint!(
+        "\r{action} {}: chunk {}/{} ({}%)",
+        part.name,
+        part.index,
+        part.count,
+        part.index * 100 / part.count
+    );
+
+    if part.index == part.count {
+        eprintln!();
+    }
+}
+
+/// Send the contents of a file, split in chunks if it does not fit a single message;
+/// the file is read one chunk at a time. Returns the number of bytes sent
+async fn send_file(
+    vid_database: &AsyncStore,
+    sender_vid: &str,
+    receiver_vid: &str,
+    path: &Path,
+) -> Result<usize, Error> {
+    use std::io::{ErrorKind, Read};
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "not a file"))?
+        .to_string_lossy()
+        .into_owned();
+    let mut file = std::fs::File::open(path)?;
+    let size = usize::try_from(file.metadata()?.len())
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "file is too large"))?;
+
+    let count = size.div_ceil(FILE_CHUNK_SIZE).max(1);
+    let mut chunk = vec![0; size.min(FILE_CHUNK_SIZE)];
+    for index in 1..=count {
+        let chunk = &mut chunk[..FILE_CHUNK_SIZE.min(size - (index - 1) * FILE_CHUNK_SIZE)];
+        file.read_exact(chunk)?;
+
+        let headers = MessageHeaders::new()
+            .with_content_type("application/octet-stream")
+            .with_field(FILE_NAME_HEADER, name.as_bytes())
+            .with_field(FILE_PART_HEADER, format!("{index}/{count}"))
+            .to_bytes()?;
+
+        vid_database
+            .send(sender_vid, receiver_vid, Some(&headers), chunk)
+            .await?;
+
+        print_progress(
+            "sending",
+            &FilePart {
+                name: name.clone(),
+                index,
+                count,
+            },
+        );
+    }
+
+    Ok(size)
+}
+
+/// A file that is being received, into a temporary file until its last chunk arrived
+struct IncomingFile {
+    name: String,
+    count: usize,
+    received: usize,
+    path: PathBuf,
+    file: std::fs::File,
+}
+
+/// Write a received chunk of a file; returns whether more chunks of the file are expected.
+/// A chunk that does not continue the file being received, or a file that already
+/// exists, is refused, and the file being received is discarded
+fn save_file_part(incoming: &mut Option<IncomingFile>, part: &FilePart, data: &[u8]) -> bool {
+    match write_file_part(incoming, part, data) {
+        Ok(more) => more,
+        Err(e) => {
+            tracing::error!("could not save {}: {e}", part.name);
+
+            if let Some(file) = incoming.take() {
+                let _ = std::fs::remove_file(file.path);
+            }
+
+            false
+        }
+    }
+}
+
+fn write_file_part(
+    incoming: &mut Option<IncomingFile>,
+    part: &FilePart,
+    data: &[u8],
+) -> std::io::Result<bool> {
+    use std::io::{Error, ErrorKind, Write};
+
+    if part.index == 1 {
+        if let Some(file) = incoming.take() {
+            tracing::warn!("discarding the incomplete file {}", file.name);
+            let _ = std::fs::remove_file(file.path);
+        }
+
+        if Path::new(&part.name).exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "the file already exists",
+            ));
+        }
+
+        let path = PathBuf::from(format!(".{}.part", part.name));
+        let file = std::fs::File::create(&path)?;
+
+        *incoming = Some(IncomingFile {
+            name: part.name.clone(),
+            count: part.count,
+            received: 0,
+            path,
+            file,
+        });
+    }
+
+    let Some(file) = incoming
+        .as_mut()
+        .filter(|file| file.name == part.name && file.count == part.count)
+        .filter(|file| file.received + 1 == part.index)
+    else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected chunk {}/{}", part.index, part.count),
+        ));
+    };
+
+    file.file.write_all(data)?;
+    file.received = part.index;
+    print_progress("receiving", part);
+
+    if part.index < part.count {
+        return Ok(true);
+    }
+
+    file.file.sync_all()?;
+
+    // do not overwrite a file created while this one was received
+    if Path::new(&file.name).exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            "the file already exists",
+        ));
+    }
+    std::fs::rename(&file.path, &file.name)?;
+
+    info!("received file {} ({} chunks)", file.name, file.count);
+    println!("{}", file.name);
+    *incoming = None;
+
+    Ok(false)
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
 
     tracing_subscriber::registry()
@@ -262,22 +669,33 @@ async fn run() -> Result<(), Error> {
         )
         .init();
 
-    let (vault, mut vid_database, mut aliases) =
+    let (mut wallet, mut vid_database, mut aliases) =
         read_database(&args.database, &args.password).await?;
     let server: String = args.server;
 
     match args.command {
         Commands::Verify { vid, alias, sender } => {
-            vid_database.verify_vid(&vid).await?;
-            let sender = sender.map(|s| aliases.get(&s).cloned().unwrap_or(s));
+            // identifiers exchanged out-of-band, e.g. scanned from a QR code
+            let vid = if vid.starts_with("tspvid:") {
+                let compact = tsp::Vid::from_compact_string(&vid)?;
+                let id = compact.identifier().to_string();
+                vid_database.add_verified_vid(compact)?;
+
+                id
+            } else {
+                vid_database.verify_vid(&vid).await?;
+
+                vid
+            };
+            let sender = sender.map(|s| aliases.resolve(&s).to_string());
 
             if let Some(alias) = alias {
-                aliases.insert(alias.clone(), vid.clone());
+                aliases.insert(alias, vid.clone())?;
             }
 
             vid_database.set_relation_for_vid(&vid, sender.as_deref())?;
 
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
 
             info!(
                 "{vid} is verified and added to the database {}",
@@ -285,15 +703,184 @@ async fn run() -> Result<(), Error> {
             );
         }
         Commands::Print { alias } => {
-            let vid = aliases.get(&alias).unwrap_or(&alias);
+            let vid = aliases.resolve(&alias);
 
             print!("{vid}");
         }
-        Commands::Create { username, alias } => {
+        Commands::Wallet {
+            command: WalletCommand::Migrate,
+        } => {
+            let version = wallet.vault().migrate().await?;
+
+            if version == tsp::WALLET_VERSION {
+                info!("database is already at version {version}");
+            } else {
+                info!(
+                    "migrated database from version {version} to {}",
+                    tsp::WALLET_VERSION
+                );
+            }
+        }
+        Commands::Show {
+            format: ShowFormat::Qr { alias },
+        } => {
+            let vid = aliases.resolve(&alias);
+            let compact = vid_database.compact_vid(vid)?;
+
+            match qrcode::QrCode::new(compact.as_bytes()) {
+                Ok(code) => println!(
+                    "{}",
+                    code.render::<qrcode::render::unicode::Dense1x2>()
+                        .quiet_zone(true)
+                        .build()
+                ),
+                Err(e) => eprintln!("could not render a QR code: {e}"),
+            }
+
+            println!("{compact}");
+        }
+        Commands::Show {
+            format: ShowFormat::Stats { alias },
+        } => {
+            let summary = match alias {
+                Some(alias) => {
+                    let vid = aliases.resolve(&alias);
+                    [(vid.to_string(), vid_database.get_vid_stats(vid)?)].into()
+                }
+                None => vid_database.wallet_summary(),
+            };
+
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default();
+
+            for (vid, stats) in summary {
+                let last_received = match stats.last_received {
+                    Some(at) => format!("{}s ago", now.saturating_sub(at)),
+                    None => "never".to_string(),
+                };
+
+                let name = match aliases.alias_of(&vid) {
+                    Some(alias) => format!("{alias} ({vid})"),
+                    None => vid,
+                };
+
+                println!(
+                    "{name}: sent {}, received {}, last received {last_received}",
+                    stats.messages_sent, stats.messages_received
+                );
+
+                if let Some(error) = stats.last_error {
+                    println!("  last error: {error}");
+                }
+            }
+        }
+        Commands::Show {
+            format: ShowFormat::Provenance { alias },
+        } => {
+            let vid = aliases.resolve(&alias);
+
+            let name = |vid: &str| match aliases.alias_of(vid) {
+                Some(alias) => format!("{alias} ({vid})"),
+                None => vid.to_string(),
+            };
+
+            let provenance = match vid_database.provenance(vid)? {
+                Some(Provenance::Manual) => "added manually".to_string(),
+                Some(Provenance::Imported) => "imported from a trust registry".to_string(),
+                Some(Provenance::Resolved) => "resolved".to_string(),
+                Some(Provenance::Nested { parent }) => format!("nested in {}", name(&parent)),
+                Some(Provenance::NewIdentifier { sender }) => {
+                    format!("new identifier of {}", name(&sender))
+                }
+                Some(Provenance::Referral { sender }) => format!("referred by {}", name(&sender)),
+                Some(Provenance::GroupMember { group, sender }) => {
+                    format!("added to group '{group}' by {}", name(&sender))
+                }
+                Some(Provenance::FirstContact) => "first contact".to_string(),
+                None => "unknown".to_string(),
+            };
+
+            println!("{}: {provenance}", name(vid));
+
+            let introducers = vid_database.introducers(vid)?;
+            if !introducers.is_empty() {
+                let chain = introducers
+                    .iter()
+                    .map(|introducer| name(introducer))
+                    .collect::<Vec<_>>();
+
+                println!("  introduced through {}", chain.join(" <- "));
+            }
+
+            for introduced in vid_database.introduced_by(vid) {
+                println!("  introduced {}", name(&introduced));
+            }
+        }
+        Commands::Show {
+            format: ShowFormat::Graph { format },
+        } => {
+            let graph = vid_database.relationship_graph();
+
+            match format {
+                GraphFormat::Dot => print!("{}", graph.to_dot()),
+                GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graph)?),
+            }
+        }
+        Commands::Diagnose { message } => {
+            decode_message(&vid_database, message.into_bytes());
+        }
+        Commands::Debug {
+            command: DebugCommand::Decode { file },
+        } => {
+            let input = match file {
+                Some(path) => tokio::fs::read(&path).await?,
+                None => {
+                    let mut input = Vec::new();
+                    tokio::io::stdin()
+                        .read_to_end(&mut input)
+                        .await
+                        .expect("Could not read message from stdin");
+
+                    input
+                }
+            };
+
+            decode_message(&vid_database, input);
+        }
+        Commands::Probe { vid } => {
+            let probes = match vid {
+                Some(vid) => {
+                    let vid = aliases.resolve(&vid).to_string();
+                    let probes = vid_database.probe_endpoint(&vid).await?;
+
+                    BTreeMap::from([(vid, probes)])
+                }
+                None => vid_database.probe_all().await?,
+            };
+
+            for (vid, probes) in probes {
+                println!("{vid}");
+
+                for probe in probes {
+                    match probe.failure {
+                        None => println!("  {}: reachable in {:?}", probe.endpoint, probe.elapsed),
+                        Some(failure) => println!("  {}: unreachable: {failure}", probe.endpoint),
+                    }
+                }
+            }
+        }
+        Commands::Create {
+            username,
+            alias,
+            publish_url,
+            auth_token,
+        } => {
             let did = format!("did:web:{}:user:{username}", server.replace(":", "%3A"));
 
             if let Some(alias) = alias {
-                aliases.insert(alias.clone(), did.clone());
+                aliases.insert(alias.clone(), did.clone())?;
                 info!("added alias {alias} -> {did}");
             }
 
@@ -303,17 +890,21 @@ async fn run() -> Result<(), Error> {
             let private_vid = OwnedVid::bind(&did, transport);
             info!("created identity {}", private_vid.identifier());
 
-            reqwest::Client::new()
-                .post(format!("https://{server}/add-vid"))
-                .json(&private_vid)
-                .send()
+            let endpoint = match publish_url {
+                Some(publish_url) => PublishEndpoint::Put(publish_url),
+                None => PublishEndpoint::TestServer(
+                    url::Url::parse(&format!("https://{server}/add-vid")).unwrap(),
+                ),
+            };
+
+            publish_did_document(&endpoint, private_vid.vid(), auth_token.as_deref())
                 .await
                 .expect("Could not publish VID on server");
 
             trace!("published DID document to {url}/did.json");
 
             vid_database.add_private_vid(private_vid.clone())?;
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
         }
         Commands::CreatePeer { alias, tcp } => {
             let transport = if let Some(address) = tcp {
@@ -323,10 +914,10 @@ async fn run() -> Result<(), Error> {
             };
             let private_vid = OwnedVid::new_did_peer(transport);
 
-            aliases.insert(alias.clone(), private_vid.identifier().to_string());
+            aliases.insert(alias, private_vid.identifier().to_string())?;
 
             vid_database.add_private_vid(private_vid.clone())?;
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
 
             info!("created peer identity {}", private_vid.identifier());
         }
@@ -335,59 +926,114 @@ async fn run() -> Result<(), Error> {
             vid_database.add_private_vid(private_vid.clone())?;
 
             if let Some(alias) = alias {
-                aliases.insert(alias.clone(), private_vid.identifier().to_string());
+                aliases.insert(alias, private_vid.identifier().to_string())?;
             }
 
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
 
             info!("created identity from file {}", private_vid.identifier());
         }
         Commands::SetParent { vid, other_vid } => {
-            let vid = aliases.get(&vid).unwrap_or(&vid);
-            let other_vid = aliases.get(&other_vid).unwrap_or(&other_vid);
+            let vid = aliases.resolve(&vid);
+            let other_vid = aliases.resolve(&other_vid);
 
             vid_database.set_parent_for_vid(vid, Some(other_vid))?;
 
             info!("{vid} is now a child of {other_vid}");
 
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
         }
         Commands::SetAlias { vid, alias } => {
-            aliases.insert(alias.clone(), vid.clone());
+            let vid = aliases.resolve(&vid).to_string();
+
+            aliases.insert(alias.clone(), vid.clone())?;
             info!("added alias {alias} -> {vid}");
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
+        }
+        Commands::Alias {
+            command: AliasCommand::List,
+        } => {
+            for (alias, vid) in aliases.iter() {
+                println!("{alias}\t{vid}");
+            }
+        }
+        Commands::Alias {
+            command: AliasCommand::Remove { alias },
+        } => {
+            match aliases.remove(&alias) {
+                Some(vid) => info!("removed alias {alias} -> {vid}"),
+                None => info!("there is no alias {alias}"),
+            }
+
+            write_database(&mut wallet, aliases).await?;
         }
+        Commands::Alias {
+            command: AliasCommand::Of { vid },
+        } => match aliases.alias_of(&vid) {
+            Some(alias) => print!("{alias}"),
+            None => info!("{vid} has no alias"),
+        },
         Commands::SetRoute { vid, route } => {
-            let vid = aliases.get(&vid).cloned().unwrap_or(vid);
+            let vid = aliases.resolve(&vid).to_string();
 
             let route: Vec<_> = route
                 .split(',')
-                .map(|s| aliases.get(s).cloned().unwrap_or(s.to_string()))
+                .map(|s| aliases.resolve(s).to_string())
                 .collect();
 
             let route_ref = route.iter().map(|s| s.as_str()).collect::<Vec<_>>();
 
             vid_database.set_route_for_vid(&vid, &route_ref)?;
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
 
             info!("{vid} has route {route:?}");
         }
         Commands::SetRelation { vid, other_vid } => {
-            let vid = aliases.get(&vid).cloned().unwrap_or(vid);
-            let other_vid = aliases.get(&other_vid).cloned().unwrap_or(other_vid);
+            let vid = aliases.resolve(&vid).to_string();
+            let other_vid = aliases.resolve(&other_vid).to_string();
 
             vid_database.set_relation_for_vid(&vid, Some(&other_vid))?;
-            write_database(&vault, &vid_database, aliases).await?;
+            write_database(&mut wallet, aliases).await?;
 
             info!("{vid} has relation to {other_vid}");
         }
+        Commands::Send {
+            sender_vid,
+            receiver_vid,
+            file: Some(path),
+            ..
+        } => {
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
+
+            let result = send_file(&vid_database, sender_vid, receiver_vid, &path).await;
+
+            // keep the message counts and the last error of the relationship
+            write_database(&mut wallet, aliases.clone()).await?;
+
+            let size = match result {
+                Ok(size) => size,
+                Err(e) => {
+                    tracing::error!("error sending file from {sender_vid} to {receiver_vid}: {e}");
+
+                    return Ok(());
+                }
+            };
+
+            info!(
+                "sent file {} ({size} bytes) from {sender_vid} to {receiver_vid}",
+                path.display()
+            );
+        }
         Commands::Send {
             sender_vid,
             receiver_vid,
             non_confidential_data,
+            file: None,
+            signed,
         } => {
-            let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
-            let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
 
             let non_confidential_data = non_confidential_data.as_deref().map(|s| s.as_bytes());
 
@@ -397,25 +1043,31 @@ async fn run() -> Result<(), Error> {
                 .await
                 .expect("Could not read message from stdin");
 
-            match vid_database
-                .send(sender_vid, receiver_vid, non_confidential_data, &message)
-                .await
-            {
-                Ok(()) => {}
-                Err(e) => {
-                    tracing::error!(
-                        "error sending message from {sender_vid} to {receiver_vid}: {e}"
-                    );
-
-                    return Ok(());
-                }
+            let result = if signed {
+                vid_database
+                    .send_signed(sender_vid, receiver_vid, &message)
+                    .await
+            } else {
+                vid_database
+                    .send(sender_vid, receiver_vid, non_confidential_data, &message)
+                    .await
             };
 
+            write_database(&mut wallet, aliases.clone()).await?;
+
+            if let Err(e) = result {
+                tracing::error!("error sending message from {sender_vid} to {receiver_vid}: {e}");
+
+                return Ok(());
+            }
+
             if args.pretty_print {
-                let cesr_message = vid_database
-                    .as_store()
-                    .seal_message(sender_vid, receiver_vid, non_confidential_data, &message)?
-                    .1;
+                let store = vid_database.as_store();
+                let (_, cesr_message) = if signed {
+                    store.sign_message(sender_vid, receiver_vid, &message)?
+                } else {
+                    store.seal_message(sender_vid, receiver_vid, non_confidential_data, &message)?
+                };
                 print_message(&cesr_message);
             }
 
@@ -425,7 +1077,7 @@ async fn run() -> Result<(), Error> {
             );
         }
         Commands::Receive { vid, one } => {
-            let vid = aliases.get(&vid).cloned().unwrap_or(vid);
+            let vid = aliases.resolve(&vid).to_string();
             let mut messages = vid_database.receive(&vid).await?;
 
             info!("listening for messages...");
@@ -433,20 +1085,39 @@ async fn run() -> Result<(), Error> {
             // closures cannot be async, and async fn's don't easily do recursion
             enum Action {
                 Nothing,
-                Verify(String),
                 VerifyAndOpen(String, Vec<u8>),
-                Forward(String, Vec<Vec<u8>>, Vec<u8>),
+                AcceptReferral(ReceivedTspMessage),
+                AcceptNewIdentifier(ReceivedTspMessage),
+                Reject(String, Vec<u8>),
+                Forward(String, Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<u8>),
+                SaveFile(FilePart, Vec<u8>),
             }
 
+            // with --one, keep listening until the last chunk of a file has arrived
+            let mut incoming_file = None;
+            let mut incomplete_file = false;
+
             while let Some(Ok(message)) = messages.next().await {
                 let handle_message = |message: ReceivedTspMessage| {
+                    let file_part = message
+                        .headers()
+                        .and_then(|headers| FilePart::from_headers(&headers));
+
                     match message {
                         ReceivedTspMessage::GenericMessage {
                             sender,
                             nonconfidential_data: _,
                             message,
+                            content_type,
+                            segments,
+                            in_reply_to,
+                            digest: _,
                             message_type,
                         } => {
+                            if let Some(part) = file_part {
+                                return Action::SaveFile(part, message);
+                            }
+
                             let status = match message_type.crypto_type {
                                 tsp::cesr::CryptoType::Plaintext => "NON-CONFIDENTIAL",
                                 _ => "confidential",
@@ -457,6 +1128,7 @@ async fn run() -> Result<(), Error> {
                                 tsp::cesr::CryptoType::HpkeEssr => "HPKE ESSR",
                                 tsp::cesr::CryptoType::NaclAuth => "NaCl Auth",
                                 tsp::cesr::CryptoType::NaclEssr => "NaCl ESSR",
+                                tsp::cesr::CryptoType::Session => "Session key",
                             };
                             let signature_type = match message_type.signature_type {
                                 tsp::cesr::SignatureType::NoSignature => "no signature",
@@ -467,7 +1139,17 @@ async fn run() -> Result<(), Error> {
                                 message.len(),
                                 sender,
                             );
+                            if let Some(digest) = in_reply_to {
+                                info!("in reply to {}", Base64Unpadded::encode_string(&digest));
+                            }
+                            if let Some(content_type) = content_type {
+                                info!("content type {content_type}");
+                            }
                             println!("{}", String::from_utf8_lossy(&message),);
+                            for (content_type, data) in segments {
+                                info!("segment of type {content_type} ({} bytes)", data.len());
+                                println!("{}", String::from_utf8_lossy(&data));
+                            }
                         }
                         ReceivedTspMessage::RequestRelationship {
                             sender,
@@ -504,26 +1186,70 @@ async fn run() -> Result<(), Error> {
                             info!("received accept nested relationship from '{vid}' (new identity for {sender})");
                             println!("{vid}");
                         }
-                        ReceivedTspMessage::CancelRelationship { sender } => {
+                        ReceivedTspMessage::RejectRelationship {
+                            sender,
+                            thread_id: _,
+                            reason,
+                        } => {
+                            let reason = reason.as_deref().unwrap_or("no reason given");
+                            info!("received reject relationship from {sender}: {reason}");
+                        }
+                        ReceivedTspMessage::RenewRelationship {
+                            sender,
+                            thread_id: _,
+                            expires_at,
+                        } => {
+                            info!("received renew relationship from {sender}, expires at {expires_at}");
+                        }
+                        ReceivedTspMessage::SessionEstablished { sender } => {
+                            info!("received session key from {sender}");
+                        }
+                        ReceivedTspMessage::Acknowledgement { sender, digest } => {
+                            let digest = Base64Unpadded::encode_string(&digest);
+                            info!("received acknowledgement from {sender} for message '{digest}'");
+                        }
+                        ReceivedTspMessage::CancelRelationship {
+                            sender,
+                            nested_vid: None,
+                        } => {
                             info!("received cancel relationship from {sender}");
                         }
+                        ReceivedTspMessage::CancelRelationship {
+                            sender,
+                            nested_vid: Some(vid),
+                        } => {
+                            info!("received cancel nested relationship from '{vid}' (identity of {sender})");
+                        }
                         ReceivedTspMessage::ForwardRequest {
                             sender,
                             route,
                             next_hop,
+                            annotation,
+                            route_annotations,
                             opaque_payload,
+                            ..
                         } => {
                             info!("messaging forwarding request from {sender} to {next_hop} ({} hops)", route.len());
+                            if let Some(annotation) = annotation {
+                                info!("annotation: {}", String::from_utf8_lossy(&annotation));
+                            }
                             if args.yes
                                 || prompt("do you want to forward this message?".to_string())
                             {
-                                return Action::Forward(next_hop, route, opaque_payload);
+                                return Action::Forward(
+                                    next_hop,
+                                    route,
+                                    route_annotations,
+                                    opaque_payload,
+                                );
                             }
                         }
                         ReceivedTspMessage::NewIdentifier { sender, new_vid } => {
                             info!("received request for new identifier '{new_vid}' from {sender}");
                             println!("{new_vid}");
-                            return Action::Verify(new_vid);
+                            return Action::AcceptNewIdentifier(
+                                ReceivedTspMessage::NewIdentifier { sender, new_vid },
+                            );
                         }
                         ReceivedTspMessage::Referral {
                             sender,
@@ -533,7 +1259,53 @@ async fn run() -> Result<(), Error> {
                                 "received relationship referral for '{referred_vid}' from {sender}"
                             );
                             println!("{referred_vid}");
-                            return Action::Verify(referred_vid);
+                            return Action::AcceptReferral(ReceivedTspMessage::Referral {
+                                sender,
+                                referred_vid,
+                            });
+                        }
+                        ReceivedTspMessage::GroupMessage {
+                            sender,
+                            group,
+                            message,
+                            ..
+                        } => {
+                            info!(
+                                "received group message ({} bytes) in '{group}' from {sender}",
+                                message.len()
+                            );
+                            println!("{}", String::from_utf8_lossy(&message));
+                        }
+                        ReceivedTspMessage::GroupMembership {
+                            sender,
+                            group,
+                            member,
+                            change,
+                        } => {
+                            info!("received group membership change in '{group}' from {sender}: {member} {change:?}");
+                            println!("{group}\t{member}\t{change:?}");
+                        }
+                        ReceivedTspMessage::Extension {
+                            sender,
+                            typecode,
+                            message,
+                        } => {
+                            info!(
+                                "received extension message of type {typecode:#04x} from {sender}"
+                            );
+                            println!("{message}");
+                        }
+                        ReceivedTspMessage::UnknownControl {
+                            sender,
+                            typecode,
+                            raw,
+                        } => {
+                            info!(
+                                "received message of unknown type {:02x}{:02x} ({} bytes) from {sender}",
+                                typecode[0],
+                                typecode[1],
+                                raw.len()
+                            );
                         }
                         ReceivedTspMessage::PendingMessage {
                             unknown_vid,
@@ -550,6 +1322,8 @@ async fn run() -> Result<(), Error> {
                                 trace!("processing pending message");
                                 return Action::VerifyAndOpen(unknown_vid, payload);
                             }
+
+                            return Action::Reject(unknown_vid, payload);
                         }
                     }
 
@@ -559,64 +1333,146 @@ async fn run() -> Result<(), Error> {
                 match handle_message(message) {
                     Action::Nothing => {}
                     Action::VerifyAndOpen(vid, payload) => {
-                        let message = vid_database.verify_and_open(&vid, payload).await?;
+                        let message = vid_database.resolve_pending(&vid, payload).await?;
 
                         info!(
                             "{vid} is verified and added to the database {}",
                             &args.database
                         );
 
-                        let _ = handle_message(message);
+                        if let Action::SaveFile(part, data) = handle_message(message) {
+                            incomplete_file = save_file_part(&mut incoming_file, &part, &data);
+                        }
                     }
-                    Action::Verify(vid) => {
-                        vid_database.verify_vid(&vid).await?;
+                    Action::Reject(vid, payload) => {
+                        vid_database.reject_pending(&vid, payload);
+                    }
+                    Action::AcceptReferral(referral) => {
+                        vid_database.accept_referral(&referral, None).await?;
 
                         info!(
-                            "{vid} is verified and added to the database {}",
+                            "referred vid is verified and added to the database {}",
                             &args.database
                         );
                     }
-                    Action::Forward(next_hop, route, payload) => {
+                    Action::AcceptNewIdentifier(new_identifier) => {
+                        vid_database.accept_new_identifier(&new_identifier).await?;
+
+                        info!(
+                            "new identifier is verified and added to the database {}",
+                            &args.database
+                        );
+                    }
+                    Action::Forward(next_hop, route, annotations, payload) => {
                         vid_database
-                            .forward_routed_message(&next_hop, route, &payload)
+                            .forward_annotated_routed_message(
+                                &next_hop,
+                                route,
+                                annotations,
+                                &payload,
+                            )
                             .await?;
                         info!("forwarding to next hop: {next_hop}");
                     }
+                    Action::SaveFile(part, data) => {
+                        incomplete_file = save_file_part(&mut incoming_file, &part, &data);
+                    }
                 }
 
-                write_database(&vault, &vid_database, aliases.clone()).await?;
+                write_database(&mut wallet, aliases.clone()).await?;
 
-                if one {
+                if one && !incomplete_file {
                     break;
                 }
             }
         }
+        Commands::Listen {
+            vid,
+            auto_accept,
+            auto_ack,
+            policy,
+            persist_interval,
+        } => {
+            let vid = aliases.resolve(&vid).to_string();
+
+            let mut policy = match policy {
+                Some(path) => listen::Policy::read(&path).expect("Invalid policy"),
+                None => listen::Policy::default(),
+            };
+            policy.auto_accept |= auto_accept;
+            policy.auto_ack |= auto_ack;
+
+            let mut messages = vid_database.receive(&vid).await?;
+            let mut persist = tokio::time::interval(Duration::from_secs(persist_interval.max(1)));
+            let mut changed = false;
+
+            info!("listening for messages...");
+
+            loop {
+                tokio::select! {
+                    message = messages.next() => {
+                        let Some(message) = message else {
+                            break;
+                        };
+
+                        let result = match message {
+                            Ok(message) => {
+                                listen::handle_message(&mut vid_database, &vid, &policy, message).await
+                            }
+                            Err(e) => Err(e),
+                        };
+
+                        // keep listening, a single bad message should not stop the endpoint
+                        if let Err(e) = result {
+                            tracing::error!("error handling message: {e}");
+                        }
+
+                        changed = true;
+                    }
+                    _ = persist.tick() => {
+                        if std::mem::take(&mut changed) {
+                            write_database(&mut wallet, aliases.clone()).await?;
+                        }
+                    }
+                }
+            }
+
+            write_database(&mut wallet, aliases.clone()).await?;
+        }
         Commands::Cancel {
             sender_vid,
             receiver_vid,
+            nested,
         } => {
-            let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
-            let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
 
-            if let Err(e) = vid_database
-                .send_relationship_cancel(sender_vid, receiver_vid)
-                .await
-            {
+            let result = if nested {
+                vid_database
+                    .send_nested_relationship_cancel(sender_vid, receiver_vid)
+                    .await
+            } else {
+                vid_database
+                    .send_relationship_cancel(sender_vid, receiver_vid)
+                    .await
+            };
+
+            if let Err(e) = result {
                 tracing::error!("error sending message from {sender_vid} to {receiver_vid}: {e}");
 
                 return Ok(());
             }
 
             info!("sent control message from {sender_vid} to {receiver_vid}",);
-            write_database(&vault, &vid_database, aliases.clone()).await?;
+            write_database(&mut wallet, aliases.clone()).await?;
         }
         Commands::Request {
             sender_vid,
             receiver_vid,
             nested,
         } => {
-            let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
-            let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
 
             if nested {
                 match vid_database
@@ -646,7 +1502,7 @@ async fn run() -> Result<(), Error> {
             }
 
             info!("sent control message from {sender_vid} to {receiver_vid}",);
-            write_database(&vault, &vid_database, aliases.clone()).await?;
+            write_database(&mut wallet, aliases.clone()).await?;
         }
         Commands::Accept {
             sender_vid,
@@ -654,8 +1510,8 @@ async fn run() -> Result<(), Error> {
             thread_id,
             nested,
         } => {
-            let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
-            let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
 
             let mut digest: [u8; 32] = Default::default();
             Base64Unpadded::decode(&thread_id, &mut digest).unwrap();
@@ -689,16 +1545,16 @@ async fn run() -> Result<(), Error> {
             }
 
             info!("sent control message from {sender_vid} to {receiver_vid}",);
-            write_database(&vault, &vid_database, aliases.clone()).await?;
+            write_database(&mut wallet, aliases.clone()).await?;
         }
         Commands::Refer {
             sender_vid,
             receiver_vid,
             referred_vid,
         } => {
-            let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
-            let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
-            let referred_vid = aliases.get(&referred_vid).unwrap_or(&referred_vid);
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
+            let referred_vid = aliases.resolve(&referred_vid);
 
             if let Err(e) = vid_database
                 .send_relationship_referral(sender_vid, receiver_vid, referred_vid)
@@ -710,16 +1566,44 @@ async fn run() -> Result<(), Error> {
             }
 
             info!("sent control message from {sender_vid} to {receiver_vid}",);
-            write_database(&vault, &vid_database, aliases.clone()).await?;
+            write_database(&mut wallet, aliases.clone()).await?;
+        }
+        Commands::AcceptReferral {
+            referrer,
+            referred_vid,
+            sender_vid,
+        } => {
+            let referrer = aliases.resolve(&referrer).to_string();
+            let referred_vid = aliases.resolve(&referred_vid).to_string();
+            let sender_vid = sender_vid.map(|vid| aliases.resolve(&vid).to_string());
+
+            let referral = ReceivedTspMessage::Referral {
+                sender: referrer,
+                referred_vid: referred_vid.clone(),
+            };
+
+            vid_database
+                .accept_referral(&referral, sender_vid.as_deref())
+                .await?;
+
+            info!(
+                "{referred_vid} is verified and added to the database {}",
+                &args.database
+            );
+            if let Some(sender_vid) = sender_vid {
+                info!("sent relationship request from {sender_vid} to {referred_vid}");
+            }
+
+            write_database(&mut wallet, aliases).await?;
         }
         Commands::Publish {
             sender_vid,
             receiver_vid,
             new_vid,
         } => {
-            let sender_vid = aliases.get(&sender_vid).unwrap_or(&sender_vid);
-            let receiver_vid = aliases.get(&receiver_vid).unwrap_or(&receiver_vid);
-            let new_vid = aliases.get(&new_vid).unwrap_or(&new_vid);
+            let sender_vid = aliases.resolve(&sender_vid);
+            let receiver_vid = aliases.resolve(&receiver_vid);
+            let new_vid = aliases.resolve(&new_vid);
 
             if let Err(e) = vid_database
                 .send_new_identifier_notice(sender_vid, receiver_vid, new_vid)
@@ -731,11 +1615,18 @@ async fn run() -> Result<(), Error> {
             }
 
             info!("sent control message from {sender_vid} to {receiver_vid}",);
-            write_database(&vault, &vid_database, aliases.clone()).await?;
+            write_database(&mut wallet, aliases.clone()).await?;
+        }
+        Commands::Replay { scenario } => {
+            // the scenario brings its own in-memory wallets, the database is not used
+            if !replay::replay(&scenario).await {
+                wallet.close().await?;
+                std::process::exit(1);
+            }
         }
     }
 
-    vault.close().await?;
+    wallet.close().await?;
 
     Ok(())
 }
@@ -0,0 +1,295 @@
+use base64ct::{Base64Unpadded, Encoding};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{path::Path, time::SystemTime};
+use tracing::info;
+use tsp::{AsyncStore, Error, ReceivedTspMessage, VerifiedVid};
+
+/// Rules for handling incoming messages without user interaction in `tsp listen`
+///
+/// ```yaml
+/// auto_accept: true
+/// auto_ack: false
+/// accept_from:
+///   - did:web:did.tsp-test.org:*
+///   - did:peer:2.Vz6Mkq...
+/// answer_pings: true
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Accept relationship requests, and open messages from unknown VIDs, of allowed senders
+    pub auto_accept: bool,
+    /// Acknowledge every received message
+    pub auto_ack: bool,
+    /// The senders that are allowed; a pattern ending in `*` matches every VID that starts
+    /// with the rest of the pattern. Without patterns, every sender is allowed
+    accept_from: Vec<String>,
+    /// Reply "pong" to messages that only contain "ping"
+    answer_pings: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            auto_accept: false,
+            auto_ack: false,
+            accept_from: Vec::new(),
+            answer_pings: true,
+        }
+    }
+}
+
+impl Policy {
+    /// Read a policy from a YAML file
+    pub fn read(path: &Path) -> Result<Self, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_yaml::from_str(&contents).map_err(|e| e.to_string()))
+            .map_err(|e| format!("could not read policy {}: {e}", path.display()))
+    }
+
+    fn allows(&self, sender: &str) -> bool {
+        self.accept_from.is_empty()
+            || self
+                .accept_from
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => sender.starts_with(prefix),
+                    None => sender == pattern,
+                })
+    }
+}
+
+/// Log `message`, received by `vid`, as a JSON line on stdout and handle it according to `policy`
+pub async fn handle_message(
+    db: &mut AsyncStore,
+    vid: &str,
+    policy: &Policy,
+    message: ReceivedTspMessage,
+) -> Result<(), Error> {
+    let mut next = Some(message);
+
+    // opening a pending message yields another message to handle
+    while let Some(message) = next.take() {
+        println!("{}", to_json(&message));
+
+        match message {
+            ReceivedTspMessage::GenericMessage {
+                sender,
+                message,
+                digest,
+                ..
+            } => {
+                if policy.auto_ack {
+                    db.send_ack(vid, &sender, &digest).await?;
+                }
+
+                if policy.answer_pings && message == b"ping" {
+                    db.send_reply(vid, &sender, None, &digest, b"pong").await?;
+                    info!("answered ping from {sender}");
+                }
+            }
+            ReceivedTspMessage::RequestRelationship {
+                sender,
+                thread_id,
+                nested_vid,
+                ..
+            } if policy.auto_accept && policy.allows(&sender) => match nested_vid {
+                None => {
+                    db.send_relationship_accept(vid, &sender, thread_id, None)
+                        .await?;
+                    info!("accepted relationship with {sender}");
+                }
+                Some(nested_vid) => {
+                    let own_vid = db
+                        .send_nested_relationship_accept(vid, &nested_vid, thread_id)
+                        .await?;
+                    info!(
+                        "accepted nested relationship with '{nested_vid}' as '{}'",
+                        own_vid.identifier()
+                    );
+                }
+            },
+            ReceivedTspMessage::PendingMessage {
+                unknown_vid,
+                payload,
+            } => {
+                if policy.auto_accept && policy.allows(&unknown_vid) {
+                    next = Some(db.resolve_pending(&unknown_vid, payload).await?);
+                } else {
+                    db.reject_pending(&unknown_vid, payload);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Describe a received message as a JSON object; message contents are included as text
+fn to_json(message: &ReceivedTspMessage) -> Value {
+    let digest = |digest: &[u8; 32]| Base64Unpadded::encode_string(digest);
+    let text = |data: &[u8]| String::from_utf8_lossy(data).into_owned();
+
+    let mut line = match message {
+        ReceivedTspMessage::GenericMessage {
+            sender,
+            message,
+            content_type,
+            segments,
+            in_reply_to,
+            digest: message_digest,
+            message_type,
+            ..
+        } => json!({
+            "type": "message",
+            "sender": sender,
+            "message": text(message),
+            "content_type": content_type,
+            "segments": segments
+                .iter()
+                .map(|(content_type, data)| json!({ "content_type": content_type, "data": text(data) }))
+                .collect::<Vec<_>>(),
+            "in_reply_to": in_reply_to.as_ref().map(digest),
+            "digest": digest(message_digest),
+            "confidential": message_type.crypto_type != tsp::cesr::CryptoType::Plaintext,
+        }),
+        ReceivedTspMessage::RequestRelationship {
+            sender,
+            nested_vid,
+            thread_id,
+            ..
+        } => json!({
+            "type": "request_relationship",
+            "sender": sender,
+            "nested_vid": nested_vid,
+            "thread_id": digest(thread_id),
+        }),
+        ReceivedTspMessage::AcceptRelationship { sender, nested_vid } => json!({
+            "type": "accept_relationship",
+            "sender": sender,
+            "nested_vid": nested_vid,
+        }),
+        ReceivedTspMessage::CancelRelationship { sender, nested_vid } => json!({
+            "type": "cancel_relationship",
+            "sender": sender,
+            "nested_vid": nested_vid,
+        }),
+        ReceivedTspMessage::RejectRelationship {
+            sender,
+            thread_id,
+            reason,
+        } => json!({
+            "type": "reject_relationship",
+            "sender": sender,
+            "thread_id": digest(thread_id),
+            "reason": reason,
+        }),
+        ReceivedTspMessage::RenewRelationship {
+            sender,
+            thread_id,
+            expires_at,
+        } => json!({
+            "type": "renew_relationship",
+            "sender": sender,
+            "thread_id": digest(thread_id),
+            "expires_at": expires_at,
+        }),
+        ReceivedTspMessage::SessionEstablished { sender } => json!({
+            "type": "session_established",
+            "sender": sender,
+        }),
+        ReceivedTspMessage::Acknowledgement {
+            sender,
+            digest: acknowledged,
+        } => json!({
+            "type": "acknowledgement",
+            "sender": sender,
+            "digest": digest(acknowledged),
+        }),
+        ReceivedTspMessage::ForwardRequest {
+            sender,
+            next_hop,
+            route,
+            ..
+        } => json!({
+            "type": "forward_request",
+            "sender": sender,
+            "next_hop": next_hop,
+            "hops": route.len(),
+        }),
+        ReceivedTspMessage::NewIdentifier { sender, new_vid } => json!({
+            "type": "new_identifier",
+            "sender": sender,
+            "new_vid": new_vid,
+        }),
+        ReceivedTspMessage::Referral {
+            sender,
+            referred_vid,
+        } => json!({
+            "type": "referral",
+            "sender": sender,
+            "referred_vid": referred_vid,
+        }),
+        ReceivedTspMessage::GroupMessage {
+            sender,
+            group,
+            message,
+            ..
+        } => json!({
+            "type": "group_message",
+            "sender": sender,
+            "group": group,
+            "message": text(message),
+        }),
+        ReceivedTspMessage::GroupMembership {
+            sender,
+            group,
+            member,
+            change,
+        } => json!({
+            "type": "group_membership",
+            "sender": sender,
+            "group": group,
+            "member": member,
+            "change": format!("{change:?}"),
+        }),
+        ReceivedTspMessage::Extension {
+            sender,
+            typecode,
+            message,
+        } => json!({
+            "type": "extension",
+            "sender": sender,
+            "typecode": typecode,
+            "message": message,
+        }),
+        ReceivedTspMessage::UnknownControl {
+            sender,
+            typecode,
+            raw,
+        } => json!({
+            "type": "unknown_control",
+            "sender": sender,
+            "typecode": typecode,
+            "raw": Base64Unpadded::encode_string(raw),
+        }),
+        ReceivedTspMessage::PendingMessage {
+            unknown_vid,
+            payload,
+        } => json!({
+            "type": "pending_message",
+            "sender": unknown_vid,
+            "size": payload.len(),
+        }),
+    };
+
+    let received_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    line["received_at"] = json!(received_at);
+
+    line
+}
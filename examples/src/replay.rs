@@ -0,0 +1,443 @@
+use futures::StreamExt;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::Path,
+    time::Duration,
+};
+use tsp::{
+    definitions::{Digest, TSPStream},
+    AsyncStore, Error, OwnedVid, ReceivedTspMessage, VerifiedVid,
+};
+use url::Url;
+
+/// A conversation between wallets, with the messages every wallet should receive
+///
+/// ```yaml
+/// transport: memory
+/// wallets:
+///   alice: {}
+///   bob: {}
+/// steps:
+///   - verify: { wallet: alice, vid: bob }
+///   - verify: { wallet: bob, vid: alice }
+///   - request: { from: alice, to: bob }
+///   - expect: { wallet: bob, type: requestRelationship, from: alice }
+///   - accept: { from: bob, to: alice }
+///   - expect: { wallet: alice, type: acceptRelationship, from: bob }
+///   - send: { from: alice, to: bob, message: "Oh hello Bob" }
+///   - expect: { wallet: bob, type: message, from: alice, message: "Oh hello Bob" }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    transport: Transport,
+    /// How long to wait for a message to arrive, in seconds
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    wallets: BTreeMap<String, WalletConfig>,
+    steps: Vec<Step>,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Transport {
+    /// Sealed messages are handed to the receiving wallet directly
+    #[default]
+    Memory,
+    /// Sealed messages are sent to the endpoint of the receiving wallet
+    Network,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WalletConfig {
+    /// The endpoint of the did:peer of the wallet, by default a local TCP port
+    endpoint: Option<Url>,
+}
+
+/// A step of a scenario; wallets are named, other VIDs are given as their identifier
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    Verify {
+        wallet: String,
+        vid: String,
+    },
+    Send {
+        from: String,
+        to: String,
+        message: String,
+        nonconfidential_data: Option<String>,
+    },
+    Request {
+        from: String,
+        to: String,
+    },
+    /// Accept the last relationship request `from` received from `to`
+    Accept {
+        from: String,
+        to: String,
+    },
+    /// Reject the last relationship request `from` received from `to`
+    Reject {
+        from: String,
+        to: String,
+        reason: Option<String>,
+    },
+    Cancel {
+        from: String,
+        to: String,
+    },
+    Expect(Expectation),
+}
+
+/// The next message that arrives at `wallet`; fields that are left out are not checked
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expectation {
+    wallet: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    from: Option<String>,
+    message: Option<String>,
+    /// The message should arrive, but be refused by the wallet
+    #[serde(default)]
+    error: bool,
+}
+
+impl Step {
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Verify { .. } => "verify",
+            Step::Send { .. } => "send",
+            Step::Request { .. } => "request",
+            Step::Accept { .. } => "accept",
+            Step::Reject { .. } => "reject",
+            Step::Cancel { .. } => "cancel",
+            Step::Expect(_) => "expect",
+        }
+    }
+}
+
+/// The type of a received message, as used in the `type` of an expectation
+fn message_kind(message: &ReceivedTspMessage) -> &'static str {
+    match message {
+        ReceivedTspMessage::GenericMessage { .. } => "message",
+        ReceivedTspMessage::RequestRelationship { .. } => "requestRelationship",
+        ReceivedTspMessage::AcceptRelationship { .. } => "acceptRelationship",
+        ReceivedTspMessage::RejectRelationship { .. } => "rejectRelationship",
+        ReceivedTspMessage::RenewRelationship { .. } => "renewRelationship",
+        ReceivedTspMessage::SessionEstablished { .. } => "sessionEstablished",
+        ReceivedTspMessage::Acknowledgement { .. } => "acknowledgement",
+        ReceivedTspMessage::CancelRelationship { .. } => "cancelRelationship",
+        ReceivedTspMessage::ForwardRequest { .. } => "forwardRequest",
+        ReceivedTspMessage::NewIdentifier { .. } => "newIdentifier",
+        ReceivedTspMessage::Referral { .. } => "referral",
+        ReceivedTspMessage::GroupMessage { .. } => "groupMessage",
+        ReceivedTspMessage::GroupMembership { .. } => "groupMembership",
+        ReceivedTspMessage::Extension { .. } => "extension",
+        ReceivedTspMessage::UnknownControl { .. } => "unknownControl",
+        ReceivedTspMessage::PendingMessage { .. } => "pendingMessage",
+    }
+}
+
+fn message_sender(message: &ReceivedTspMessage) -> Option<&str> {
+    match message {
+        ReceivedTspMessage::GenericMessage { sender, .. }
+        | ReceivedTspMessage::RequestRelationship { sender, .. }
+        | ReceivedTspMessage::AcceptRelationship { sender, .. }
+        | ReceivedTspMessage::RejectRelationship { sender, .. }
+        | ReceivedTspMessage::RenewRelationship { sender, .. }
+        | ReceivedTspMessage::SessionEstablished { sender }
+        | ReceivedTspMessage::Acknowledgement { sender, .. }
+        | ReceivedTspMessage::CancelRelationship { sender, .. }
+        | ReceivedTspMessage::ForwardRequest { sender, .. }
+        | ReceivedTspMessage::NewIdentifier { sender, .. }
+        | ReceivedTspMessage::Referral { sender, .. }
+        | ReceivedTspMessage::GroupMessage { sender, .. }
+        | ReceivedTspMessage::GroupMembership { sender, .. }
+        | ReceivedTspMessage::Extension { sender, .. }
+        | ReceivedTspMessage::UnknownControl { sender, .. } => Some(sender.as_str()),
+        ReceivedTspMessage::PendingMessage { .. } => None,
+    }
+}
+
+enum Inbox {
+    Memory(VecDeque<Vec<u8>>),
+    Network(TSPStream<ReceivedTspMessage, Error>),
+}
+
+struct Wallet {
+    db: AsyncStore,
+    vid: String,
+    endpoint: Url,
+    inbox: Inbox,
+    /// The thread id of the last relationship request received from each VID
+    thread_ids: HashMap<String, Digest>,
+}
+
+struct Runner {
+    transport: Transport,
+    timeout: Duration,
+    wallets: HashMap<String, Wallet>,
+}
+
+impl Runner {
+    async fn new(scenario: &Scenario) -> Result<Self, Error> {
+        let mut wallets = HashMap::new();
+
+        for (port, (name, config)) in (13370..).zip(&scenario.wallets) {
+            let endpoint = match &config.endpoint {
+                Some(endpoint) => endpoint.clone(),
+                None => Url::parse(&format!("tcp://127.0.0.1:{port}")).unwrap(),
+            };
+
+            let db = AsyncStore::new();
+            let private_vid = OwnedVid::new_did_peer(endpoint.clone());
+            let vid = private_vid.identifier().to_string();
+            db.add_private_vid(private_vid)?;
+
+            let inbox = match scenario.transport {
+                Transport::Memory => Inbox::Memory(VecDeque::new()),
+                Transport::Network => Inbox::Network(db.receive(&vid).await?),
+            };
+
+            wallets.insert(
+                name.clone(),
+                Wallet {
+                    db,
+                    vid,
+                    endpoint,
+                    inbox,
+                    thread_ids: HashMap::new(),
+                },
+            );
+        }
+
+        Ok(Self {
+            transport: scenario.transport,
+            timeout: Duration::from_secs(scenario.timeout),
+            wallets,
+        })
+    }
+
+    fn wallet(&self, name: &str) -> Result<&Wallet, String> {
+        self.wallets
+            .get(name)
+            .ok_or_else(|| format!("unknown wallet {name}"))
+    }
+
+    /// The VID of a wallet, or `name` itself if no wallet has that name
+    fn vid(&self, name: &str) -> String {
+        self.wallets
+            .get(name)
+            .map_or_else(|| name.to_string(), |wallet| wallet.vid.clone())
+    }
+
+    fn thread_id(&self, from: &str, to: &str) -> Result<Digest, String> {
+        self.wallet(from)?
+            .thread_ids
+            .get(&self.vid(to))
+            .copied()
+            .ok_or_else(|| format!("{from} did not receive a relationship request from {to}"))
+    }
+
+    /// Hand a sealed message to the wallet that listens on `endpoint`
+    async fn deliver(&mut self, endpoint: &Url, message: Vec<u8>) -> Result<(), String> {
+        if self.transport == Transport::Network {
+            return tsp::transport::send_message(endpoint, &message)
+                .await
+                .map_err(|e| e.to_string());
+        }
+
+        let wallet = self
+            .wallets
+            .values_mut()
+            .find(|wallet| &wallet.endpoint == endpoint)
+            .ok_or_else(|| format!("no wallet listens on {endpoint}"))?;
+
+        if let Inbox::Memory(messages) = &mut wallet.inbox {
+            messages.push_back(message);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the next message of a wallet, and remember the thread id of relationship requests
+    async fn next_message(
+        &mut self,
+        name: &str,
+    ) -> Result<Result<ReceivedTspMessage, Error>, String> {
+        let timeout = self.timeout;
+        let wallet = self
+            .wallets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown wallet {name}"))?;
+
+        let message = match &mut wallet.inbox {
+            Inbox::Memory(messages) => {
+                let mut message = messages
+                    .pop_front()
+                    .ok_or_else(|| format!("no message arrived at {name}"))?;
+
+                wallet
+                    .db
+                    .open_message(&mut message)
+                    .map(|message| message.into_owned())
+            }
+            Inbox::Network(messages) => tokio::time::timeout(timeout, messages.next())
+                .await
+                .ok()
+                .flatten()
+                .ok_or_else(|| format!("no message arrived at {name} within {timeout:?}"))?,
+        };
+
+        if let Ok(ReceivedTspMessage::RequestRelationship {
+            sender, thread_id, ..
+        }) = &message
+        {
+            wallet.thread_ids.insert(sender.clone(), *thread_id);
+        }
+
+        Ok(message)
+    }
+
+    async fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        let (endpoint, message) = match step {
+            Step::Verify { wallet, vid } => {
+                let vid = self.vid(vid);
+                let mut db = self.wallet(wallet)?.db.clone();
+
+                return db.verify_vid(&vid).await.map_err(|e| e.to_string());
+            }
+            Step::Send {
+                from,
+                to,
+                message,
+                nonconfidential_data,
+            } => self.wallet(from)?.db.as_store().seal_message(
+                &self.vid(from),
+                &self.vid(to),
+                nonconfidential_data.as_deref().map(str::as_bytes),
+                message.as_bytes(),
+            ),
+            Step::Request { from, to } => self
+                .wallet(from)?
+                .db
+                .as_store()
+                .make_relationship_request(&self.vid(from), &self.vid(to), None),
+            Step::Accept { from, to } => self.wallet(from)?.db.as_store().make_relationship_accept(
+                &self.vid(from),
+                &self.vid(to),
+                self.thread_id(from, to)?,
+                None,
+            ),
+            Step::Reject { from, to, reason } => {
+                self.wallet(from)?.db.as_store().make_relationship_reject(
+                    &self.vid(from),
+                    &self.vid(to),
+                    self.thread_id(from, to)?,
+                    reason.as_deref(),
+                )
+            }
+            Step::Cancel { from, to } => self
+                .wallet(from)?
+                .db
+                .as_store()
+                .make_relationship_cancel(&self.vid(from), &self.vid(to)),
+            Step::Expect(expectation) => return self.check(expectation).await,
+        }
+        .map_err(|e| e.to_string())?;
+
+        self.deliver(&endpoint, message).await
+    }
+
+    async fn check(&mut self, expectation: &Expectation) -> Result<(), String> {
+        let message = match self.next_message(&expectation.wallet).await? {
+            Ok(_) if expectation.error => return Err("the message was accepted".to_string()),
+            Ok(message) => message,
+            Err(_) if expectation.error => return Ok(()),
+            Err(e) => return Err(format!("the message was refused: {e}")),
+        };
+
+        let kind = message_kind(&message);
+        if let Some(expected) = &expectation.kind {
+            if expected != kind {
+                return Err(format!("expected a {expected}, got a {kind}"));
+            }
+        }
+
+        if let Some(from) = &expectation.from {
+            let expected = self.vid(from);
+            let sender = message_sender(&message).unwrap_or_default();
+
+            if expected != sender {
+                return Err(format!(
+                    "expected a {kind} from {expected}, got one from {sender}"
+                ));
+            }
+        }
+
+        if let Some(expected) = &expectation.message {
+            let ReceivedTspMessage::GenericMessage { message, .. } = &message else {
+                return Err(format!("expected a message, got a {kind}"));
+            };
+
+            let message = String::from_utf8_lossy(message);
+            if expected.as_str() != message {
+                return Err(format!("expected message {expected:?}, got {message:?}"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the scenario in the file at `path` and print the outcome of every step;
+/// returns whether all steps passed. Steps after a failed step are skipped
+pub async fn replay(path: &Path) -> bool {
+    let scenario = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_yaml::from_str::<Scenario>(&contents).map_err(|e| e.to_string()))
+    {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("could not read scenario {}: {e}", path.display());
+            return false;
+        }
+    };
+
+    let mut runner = match Runner::new(&scenario).await {
+        Ok(runner) => runner,
+        Err(e) => {
+            eprintln!("could not set up the wallets: {e}");
+            return false;
+        }
+    };
+
+    for (index, step) in scenario.steps.iter().enumerate() {
+        let number = index + 1;
+
+        if let Err(e) = runner.run_step(step).await {
+            println!("step {number} {}: FAILED, {e}", step.name());
+            println!(
+                "{index}/{} steps passed, remaining steps skipped",
+                scenario.steps.len()
+            );
+
+            return false;
+        }
+
+        println!("step {number} {}: ok", step.name());
+    }
+
+    println!("{0}/{0} steps passed", scenario.steps.len());
+
+    true
+}
@@ -342,6 +342,7 @@ fn open_message(message: &[u8], payload: Option<&[u8]>) -> Option<serde_json::Va
             tsp::cesr::CryptoType::HpkeEssr => "HPKE ESSR",
             tsp::cesr::CryptoType::NaclAuth => "NaCl Auth",
             tsp::cesr::CryptoType::NaclEssr => "NaCl ESSR",
+            tsp::cesr::CryptoType::Session => "Session key",
         },
         "signatureType": match parts.signature_type {
             tsp::cesr::SignatureType::NoSignature => "No Signature",
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (tsp::cesr::fuzzing::EnvelopeWrapper, Vec<u8>)| {
+    let (envelope, ciphertext) = input;
+    let mut data = envelope.encode(&ciphertext).unwrap();
+
+    let opened = tsp::cesr::decode_envelope(&mut data)
+        .unwrap()
+        .into_opened::<&[u8]>()
+        .unwrap();
+
+    assert_eq!(envelope, opened.envelope);
+});
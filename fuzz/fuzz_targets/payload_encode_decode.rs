@@ -11,7 +11,7 @@ fuzz_target!(|data: tsp::cesr::fuzzing::Wrapper| {
             assert_eq!(data, result.payload);
         }
         Err(tsp::cesr::error::EncodeError::MissingHops) => match &data.0 {
-            tsp::cesr::Payload::RoutedMessage(route, _) => assert!(route.is_empty()),
+            tsp::cesr::Payload::RoutedMessage(route, _, _) => assert!(route.is_empty()),
             _ => todo!(),
         },
         _ => todo!(),
@@ -0,0 +1,163 @@
+/*
+ * C API for the Trust Spanning Protocol
+ *
+ * Every function that can fail returns a TspStatus; on failure, tsp_last_error describes
+ * the error. Objects and strings returned by the library are owned by the caller, and have
+ * to be released with the matching _free function. Pointers passed to the library are
+ * borrowed for the duration of the call only.
+ *
+ * Functions that send, receive or verify block the calling thread; they must not be called
+ * from a TspMessageCallback.
+ */
+
+#ifndef TSP_H
+#define TSP_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum TspStatus {
+    TSP_OK = 0,
+    /* a pointer was null, a string was not UTF-8, or a URL was malformed */
+    TSP_INVALID_ARGUMENT = 1,
+    /* the VID is not in the wallet, or we do not have its private keys */
+    TSP_UNKNOWN_VID = 2,
+    /* a message could not be sealed, opened or verified */
+    TSP_CRYPTO = 3,
+    /* a message could not be sent or received */
+    TSP_TRANSPORT = 4,
+    /* the wallet could not be read or written */
+    TSP_STORAGE = 5,
+    /* any other error of the library */
+    TSP_OTHER = 6,
+    /* the library panicked; the objects involved should no longer be used */
+    TSP_PANIC = 7,
+} TspStatus;
+
+typedef enum TspMessageKind {
+    TSP_MESSAGE_GENERIC = 0,
+    TSP_MESSAGE_REQUEST_RELATIONSHIP = 1,
+    TSP_MESSAGE_ACCEPT_RELATIONSHIP = 2,
+    TSP_MESSAGE_CANCEL_RELATIONSHIP = 3,
+    TSP_MESSAGE_REJECT_RELATIONSHIP = 4,
+    TSP_MESSAGE_RENEW_RELATIONSHIP = 5,
+    TSP_MESSAGE_SESSION_ESTABLISHED = 6,
+    TSP_MESSAGE_ACKNOWLEDGEMENT = 7,
+    TSP_MESSAGE_FORWARD_REQUEST = 8,
+    TSP_MESSAGE_NEW_IDENTIFIER = 9,
+    TSP_MESSAGE_REFERRAL = 10,
+    TSP_MESSAGE_GROUP_MESSAGE = 11,
+    TSP_MESSAGE_GROUP_MEMBERSHIP = 12,
+    TSP_MESSAGE_EXTENSION = 13,
+    TSP_MESSAGE_PENDING = 14,
+    TSP_MESSAGE_UNKNOWN_CONTROL = 15,
+} TspMessageKind;
+
+/* bytes owned by the library; data is NULL if there are none */
+typedef struct TspBuffer {
+    uint8_t *data;
+    size_t len;
+} TspBuffer;
+
+/*
+ * A received message. The fields that are set depend on the kind:
+ *
+ * - GENERIC: data is the message, digest its digest
+ * - REQUEST_RELATIONSHIP: other_vid is the nested VID if any, digest the thread id
+ * - ACCEPT_RELATIONSHIP, CANCEL_RELATIONSHIP: other_vid is the nested VID if any
+ * - REJECT_RELATIONSHIP: data is the reason if any, digest the thread id
+ * - RENEW_RELATIONSHIP: data is the expiry as a big endian u64, digest the thread id
+ * - ACKNOWLEDGEMENT: digest is the acknowledged digest
+ * - FORWARD_REQUEST: other_vid is the next hop, data the opaque payload
+ * - NEW_IDENTIFIER, REFERRAL: other_vid is the new or referred VID
+ * - GROUP_MESSAGE: other_vid is the group, data the message
+ * - GROUP_MEMBERSHIP: other_vid is the member, data describes the change and the group
+ * - EXTENSION: data is the type code followed by the JSON message
+ * - PENDING: sender is the unknown VID, data the sealed message to open once it is verified
+ * - UNKNOWN_CONTROL: data is the two byte type code followed by the fields of the message as is
+ *
+ * nonconfidential_data is set for GENERIC, FORWARD_REQUEST and GROUP_MESSAGE if present.
+ */
+typedef struct TspMessage {
+    TspMessageKind kind;
+    char *sender;
+    char *other_vid;
+    TspBuffer data;
+    TspBuffer nonconfidential_data;
+    bool has_digest;
+    uint8_t digest[32];
+} TspMessage;
+
+typedef struct TspWallet TspWallet;
+typedef struct TspOwnedVid TspOwnedVid;
+typedef struct TspReceiver TspReceiver;
+
+/*
+ * Called with every received message, or with a description of an error, from a thread of
+ * the library; message and error are only valid during the call
+ */
+typedef void (*TspMessageCallback)(void *user_data, const TspMessage *message, const char *error);
+
+/* the description of the last error on this thread, or NULL; valid until the next call */
+const char *tsp_last_error(void);
+
+void tsp_string_free(char *string);
+void tsp_buffer_free(TspBuffer *buffer);
+
+/* create a wallet that is kept in memory only */
+TspStatus tsp_wallet_new(TspWallet **out);
+/* open the SQLite wallet name with password, or create it if it does not exist */
+TspStatus tsp_wallet_open(const char *name, const char *password, TspWallet **out);
+/* write the VIDs that changed to the SQLite wallet */
+TspStatus tsp_wallet_save(TspWallet *wallet);
+/* write the VIDs that changed, close the SQLite wallet and release it; stop receivers first */
+TspStatus tsp_wallet_free(TspWallet *wallet);
+
+/* create a did:peer VID with new keys that is reachable at url */
+TspStatus tsp_owned_vid_new_did_peer(const char *url, TspOwnedVid **out);
+/* the identifier of vid, released with tsp_string_free */
+TspStatus tsp_owned_vid_identifier(const TspOwnedVid *vid, char **out);
+void tsp_owned_vid_free(TspOwnedVid *vid);
+
+/* add a copy of our own VID, with its private keys */
+TspStatus tsp_wallet_add_private_vid(const TspWallet *wallet, const TspOwnedVid *vid);
+/* add the public part of vid, as a VID we can send messages to */
+TspStatus tsp_wallet_add_verified_vid(const TspWallet *wallet, const TspOwnedVid *vid);
+/* resolve and verify the VID, e.g. a did:web, and add it */
+TspStatus tsp_wallet_verify_vid(const TspWallet *wallet, const char *vid);
+
+/*
+ * seal a message without sending it; nonconfidential_data may be NULL, and the sealed
+ * message is released with tsp_buffer_free
+ */
+TspStatus tsp_wallet_seal(const TspWallet *wallet, const char *sender, const char *receiver,
+                          const uint8_t *nonconfidential_data, size_t nonconfidential_data_len,
+                          const uint8_t *message, size_t message_len, TspBuffer *out);
+/* open a sealed message, released with tsp_message_free */
+TspStatus tsp_wallet_open_message(const TspWallet *wallet, const uint8_t *data, size_t len,
+                                  TspMessage **out);
+void tsp_message_free(TspMessage *message);
+
+/* seal a message and send it to the endpoint of receiver */
+TspStatus tsp_wallet_send(const TspWallet *wallet, const char *sender, const char *receiver,
+                          const uint8_t *nonconfidential_data, size_t nonconfidential_data_len,
+                          const uint8_t *message, size_t message_len);
+/* pass the messages for our VID to callback in the background, until tsp_receiver_stop */
+TspStatus tsp_wallet_receive(const TspWallet *wallet, const char *vid,
+                             TspMessageCallback callback, void *user_data, TspReceiver **out);
+/*
+ * stop receiving after the messages that already arrived are passed to the callback, and
+ * release the receiver; the callback is not called after this returns
+ */
+TspStatus tsp_receiver_stop(TspReceiver *receiver);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TSP_H */
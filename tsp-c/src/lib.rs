@@ -0,0 +1,920 @@
+//! A C API for the asynchronous store, see `include/tsp.h`
+//!
+//! Every function returns a [TspStatus]; the description of the last error on the calling
+//! thread is available from [tsp_last_error]. Objects created by the library are owned by
+//! the caller, and have to be released with the matching `_free` function.
+
+use futures::StreamExt;
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+use tsp::VerifiedVid;
+
+/// The outcome of a call
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TspStatus {
+    Ok = 0,
+    /// A pointer was null, a string was not UTF-8, or a URL was malformed
+    InvalidArgument = 1,
+    /// The VID is not in the wallet, or we do not have its private keys
+    UnknownVid = 2,
+    /// A message could not be sealed, opened or verified
+    Crypto = 3,
+    /// A message could not be sent or received
+    Transport = 4,
+    /// The wallet could not be read or written
+    Storage = 5,
+    /// Any other error of the library
+    Other = 6,
+    /// The library panicked; the objects involved should no longer be used
+    Panic = 7,
+}
+
+impl From<&tsp::Error> for TspStatus {
+    fn from(error: &tsp::Error) -> Self {
+        match error {
+            tsp::Error::UnverifiedVid(_)
+            | tsp::Error::MissingPrivateVid(_)
+            | tsp::Error::MissingVid(_)
+            | tsp::Error::UnverifiedSource(..) => TspStatus::UnknownVid,
+            tsp::Error::Crypto(_) | tsp::Error::Decode(_) | tsp::Error::Encode(_) => {
+                TspStatus::Crypto
+            }
+            tsp::Error::Transport(_) | tsp::Error::Delivery(_) => TspStatus::Transport,
+            tsp::Error::Storage(_) | tsp::Error::DecodeState(_) => TspStatus::Storage,
+            _ => TspStatus::Other,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `body`, recording its error or panic for [tsp_last_error]
+fn guard(body: impl FnOnce() -> Result<(), (TspStatus, String)>) -> TspStatus {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => TspStatus::Ok,
+        Ok(Err((status, message))) => {
+            set_last_error(message);
+            status
+        }
+        Err(_) => {
+            set_last_error("the library panicked");
+            TspStatus::Panic
+        }
+    }
+}
+
+fn tsp_error(error: tsp::Error) -> (TspStatus, String) {
+    ((&error).into(), error.to_string())
+}
+
+fn invalid(message: impl ToString) -> (TspStatus, String) {
+    (TspStatus::InvalidArgument, message.to_string())
+}
+
+/// Borrow the object behind a handle passed by the caller
+unsafe fn handle<'a, T>(pointer: *const T, name: &str) -> Result<&'a T, (TspStatus, String)> {
+    pointer
+        .as_ref()
+        .ok_or_else(|| invalid(format!("{name} is null")))
+}
+
+/// Borrow a null terminated UTF-8 string passed by the caller
+unsafe fn string<'a>(pointer: *const c_char, name: &str) -> Result<&'a str, (TspStatus, String)> {
+    if pointer.is_null() {
+        return Err(invalid(format!("{name} is null")));
+    }
+
+    CStr::from_ptr(pointer)
+        .to_str()
+        .map_err(|_| invalid(format!("{name} is not UTF-8")))
+}
+
+/// Borrow `len` bytes passed by the caller; null is allowed for no bytes
+unsafe fn bytes<'a>(
+    pointer: *const u8,
+    len: usize,
+    name: &str,
+) -> Result<&'a [u8], (TspStatus, String)> {
+    match (pointer.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(invalid(format!("{name} is null"))),
+        (false, _) => Ok(std::slice::from_raw_parts(pointer, len)),
+    }
+}
+
+/// Write `value` to the out parameter `out`
+unsafe fn write<T>(out: *mut T, value: T) -> Result<(), (TspStatus, String)> {
+    if out.is_null() {
+        return Err(invalid("the out parameter is null"));
+    }
+
+    out.write(value);
+
+    Ok(())
+}
+
+fn into_c_string(text: impl Into<Vec<u8>>) -> *mut c_char {
+    CString::new(text)
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Bytes owned by the library, released with [tsp_buffer_free]
+#[repr(C)]
+pub struct TspBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl TspBuffer {
+    fn null() -> Self {
+        TspBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn new(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+
+        TspBuffer { data, len }
+    }
+
+    unsafe fn release(&mut self) {
+        if !self.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.data, self.len,
+            )));
+        }
+
+        *self = Self::null();
+    }
+}
+
+/// A store of VIDs, either in memory or bound to a SQLite wallet, with a runtime that
+/// sends and receives its messages
+pub struct TspWallet {
+    runtime: tokio::runtime::Runtime,
+    store: tsp::AsyncStore,
+    wallet: Option<tsp::Wallet>,
+}
+
+impl TspWallet {
+    fn new(wallet: Option<tsp::Wallet>, runtime: tokio::runtime::Runtime) -> Self {
+        let store = match &wallet {
+            Some(wallet) => wallet.store().clone(),
+            None => tsp::AsyncStore::new(),
+        };
+
+        TspWallet {
+            runtime,
+            store,
+            wallet,
+        }
+    }
+}
+
+fn new_runtime() -> Result<tokio::runtime::Runtime, (TspStatus, String)> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| (TspStatus::Other, e.to_string()))
+}
+
+/// One of our own VIDs, with its private keys
+pub struct TspOwnedVid(tsp::OwnedVid);
+
+/// The kind of a [TspMessage]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TspMessageKind {
+    Generic = 0,
+    RequestRelationship = 1,
+    AcceptRelationship = 2,
+    CancelRelationship = 3,
+    RejectRelationship = 4,
+    RenewRelationship = 5,
+    SessionEstablished = 6,
+    Acknowledgement = 7,
+    ForwardRequest = 8,
+    NewIdentifier = 9,
+    Referral = 10,
+    GroupMessage = 11,
+    GroupMembership = 12,
+    Extension = 13,
+    Pending = 14,
+    UnknownControl = 15,
+}
+
+/// A received message; which fields are set depends on its kind, see `tsp.h`
+#[repr(C)]
+pub struct TspMessage {
+    pub kind: TspMessageKind,
+    pub sender: *mut c_char,
+    pub other_vid: *mut c_char,
+    pub data: TspBuffer,
+    pub nonconfidential_data: TspBuffer,
+    pub has_digest: bool,
+    pub digest: [u8; 32],
+}
+
+impl From<tsp::ReceivedTspMessage> for TspMessage {
+    fn from(message: tsp::ReceivedTspMessage) -> Self {
+        use tsp::ReceivedTspMessage as M;
+
+        let mut result = TspMessage {
+            kind: TspMessageKind::Generic,
+            sender: ptr::null_mut(),
+            other_vid: ptr::null_mut(),
+            data: TspBuffer::null(),
+            nonconfidential_data: TspBuffer::null(),
+            has_digest: false,
+            digest: [0; 32],
+        };
+
+        let (kind, sender, other_vid, data, nonconfidential_data, digest) = match message {
+            M::GenericMessage {
+                sender,
+                nonconfidential_data,
+                message,
+                digest,
+                ..
+            } => (
+                TspMessageKind::Generic,
+                sender,
+                None,
+                Some(message),
+                nonconfidential_data,
+                Some(digest),
+            ),
+            M::RequestRelationship {
+                sender,
+                nested_vid,
+                thread_id,
+                ..
+            } => (
+                TspMessageKind::RequestRelationship,
+                sender,
+                nested_vid,
+                None,
+                None,
+                Some(thread_id),
+            ),
+            M::AcceptRelationship { sender, nested_vid } => (
+                TspMessageKind::AcceptRelationship,
+                sender,
+                nested_vid,
+                None,
+                None,
+                None,
+            ),
+            M::CancelRelationship { sender, nested_vid } => (
+                TspMessageKind::CancelRelationship,
+                sender,
+                nested_vid,
+                None,
+                None,
+                None,
+            ),
+            M::RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            } => (
+                TspMessageKind::RejectRelationship,
+                sender,
+                None,
+                reason.map(String::into_bytes),
+                None,
+                Some(thread_id),
+            ),
+            M::RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            } => (
+                TspMessageKind::RenewRelationship,
+                sender,
+                None,
+                Some(expires_at.to_be_bytes().to_vec()),
+                None,
+                Some(thread_id),
+            ),
+            M::SessionEstablished { sender } => (
+                TspMessageKind::SessionEstablished,
+                sender,
+                None,
+                None,
+                None,
+                None,
+            ),
+            M::Acknowledgement { sender, digest } => (
+                TspMessageKind::Acknowledgement,
+                sender,
+                None,
+                None,
+                None,
+                Some(digest),
+            ),
+            M::ForwardRequest {
+                sender,
+                next_hop,
+                nonconfidential_data,
+                opaque_payload,
+                ..
+            } => (
+                TspMessageKind::ForwardRequest,
+                sender,
+                Some(next_hop),
+                Some(opaque_payload),
+                nonconfidential_data,
+                None,
+            ),
+            M::NewIdentifier { sender, new_vid } => (
+                TspMessageKind::NewIdentifier,
+                sender,
+                Some(new_vid),
+                None,
+                None,
+                None,
+            ),
+            M::Referral {
+                sender,
+                referred_vid,
+            } => (
+                TspMessageKind::Referral,
+                sender,
+                Some(referred_vid),
+                None,
+                None,
+                None,
+            ),
+            M::GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                ..
+            } => (
+                TspMessageKind::GroupMessage,
+                sender,
+                Some(group),
+                Some(message),
+                nonconfidential_data,
+                None,
+            ),
+            M::GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            } => (
+                TspMessageKind::GroupMembership,
+                sender,
+                Some(member),
+                Some(format!("{change:?} {group}").into_bytes()),
+                None,
+                None,
+            ),
+            M::Extension {
+                sender,
+                typecode,
+                message,
+            } => (
+                TspMessageKind::Extension,
+                sender,
+                None,
+                Some([&[typecode][..], message.to_string().as_bytes()].concat()),
+                None,
+                None,
+            ),
+            M::UnknownControl {
+                sender,
+                typecode,
+                raw,
+            } => (
+                TspMessageKind::UnknownControl,
+                sender,
+                None,
+                Some([&typecode[..], &raw].concat()),
+                None,
+                None,
+            ),
+            M::PendingMessage {
+                unknown_vid,
+                payload,
+            } => (
+                TspMessageKind::Pending,
+                unknown_vid,
+                None,
+                Some(payload),
+                None,
+                None,
+            ),
+        };
+
+        result.kind = kind;
+        result.sender = into_c_string(sender);
+        result.other_vid = other_vid.map_or(ptr::null_mut(), into_c_string);
+        result.data = data.map_or(TspBuffer::null(), TspBuffer::new);
+        result.nonconfidential_data =
+            nonconfidential_data.map_or(TspBuffer::null(), TspBuffer::new);
+        if let Some(digest) = digest {
+            result.has_digest = true;
+            result.digest = digest;
+        }
+
+        result
+    }
+}
+
+impl Drop for TspMessage {
+    fn drop(&mut self) {
+        unsafe {
+            tsp_string_free(self.sender);
+            tsp_string_free(self.other_vid);
+            self.data.release();
+            self.nonconfidential_data.release();
+        }
+    }
+}
+
+/// Called with every received message, or with a description of an error; the message and
+/// the error are only valid during the call
+pub type TspMessageCallback =
+    extern "C" fn(user_data: *mut c_void, message: *const TspMessage, error: *const c_char);
+
+/// The user data of a callback, which the caller promises can be used from another thread
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+/// Receives the messages for a VID until it is stopped
+pub struct TspReceiver {
+    handle: tsp::ReceiveHandle,
+    task: Option<tokio::task::JoinHandle<()>>,
+    runtime: tokio::runtime::Handle,
+}
+
+/// The description of the last error on this thread, or null; valid until the next call
+/// on this thread
+#[no_mangle]
+pub extern "C" fn tsp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Release a string returned by the library
+///
+/// # Safety
+///
+/// `string` is null, or was returned by the library and not released yet
+#[no_mangle]
+pub unsafe extern "C" fn tsp_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Release bytes returned by the library
+///
+/// # Safety
+///
+/// `buffer` is null, or points to a buffer returned by the library
+#[no_mangle]
+pub unsafe extern "C" fn tsp_buffer_free(buffer: *mut TspBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        buffer.release();
+    }
+}
+
+/// Create a wallet that is kept in memory only
+///
+/// # Safety
+///
+/// `out` points to writable memory for a wallet pointer
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_new(out: *mut *mut TspWallet) -> TspStatus {
+    guard(|| {
+        let wallet = TspWallet::new(None, new_runtime()?);
+
+        write(out, Box::into_raw(Box::new(wallet)))
+    })
+}
+
+/// Open the SQLite wallet `name` with `password`, or create it if it does not exist
+///
+/// # Safety
+///
+/// `name` and `password` are null terminated strings, and `out` points to writable memory
+/// for a wallet pointer
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_open(
+    name: *const c_char,
+    password: *const c_char,
+    out: *mut *mut TspWallet,
+) -> TspStatus {
+    guard(|| {
+        let name = string(name, "name")?;
+        let password = string(password, "password")?;
+
+        let runtime = new_runtime()?;
+        let wallet = runtime
+            .block_on(tsp::Wallet::open(name, password.as_bytes()))
+            .map_err(tsp_error)?;
+
+        write(
+            out,
+            Box::into_raw(Box::new(TspWallet::new(Some(wallet), runtime))),
+        )
+    })
+}
+
+/// Write the VIDs that changed to the SQLite wallet; does nothing for a wallet in memory
+///
+/// # Safety
+///
+/// `wallet` was returned by [tsp_wallet_new] or [tsp_wallet_open]
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_save(wallet: *mut TspWallet) -> TspStatus {
+    guard(|| {
+        let Some(wallet) = wallet.as_mut() else {
+            return Err(invalid("wallet is null"));
+        };
+
+        match &mut wallet.wallet {
+            Some(persistent) => wallet
+                .runtime
+                .block_on(persistent.save())
+                .map_err(tsp_error),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Write the VIDs that changed, close the SQLite wallet and release `wallet`; receivers
+/// have to be stopped first
+///
+/// # Safety
+///
+/// `wallet` is null, or was returned by [tsp_wallet_new] or [tsp_wallet_open] and is not
+/// used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_free(wallet: *mut TspWallet) -> TspStatus {
+    guard(|| {
+        if wallet.is_null() {
+            return Ok(());
+        }
+
+        let TspWallet {
+            runtime, wallet, ..
+        } = *Box::from_raw(wallet);
+
+        match wallet {
+            Some(wallet) => runtime.block_on(wallet.close()).map_err(tsp_error),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Create a `did:peer` VID with new keys that is reachable at `url`
+///
+/// # Safety
+///
+/// `url` is a null terminated string, and `out` points to writable memory for a VID pointer
+#[no_mangle]
+pub unsafe extern "C" fn tsp_owned_vid_new_did_peer(
+    url: *const c_char,
+    out: *mut *mut TspOwnedVid,
+) -> TspStatus {
+    guard(|| {
+        let url = string(url, "url")?
+            .parse()
+            .map_err(|_| invalid("url is not a valid URL"))?;
+
+        write(
+            out,
+            Box::into_raw(Box::new(TspOwnedVid(tsp::OwnedVid::new_did_peer(url)))),
+        )
+    })
+}
+
+/// The identifier of `vid`, released with [tsp_string_free]
+///
+/// # Safety
+///
+/// `vid` was returned by [tsp_owned_vid_new_did_peer], and `out` points to writable memory
+/// for a string pointer
+#[no_mangle]
+pub unsafe extern "C" fn tsp_owned_vid_identifier(
+    vid: *const TspOwnedVid,
+    out: *mut *mut c_char,
+) -> TspStatus {
+    guard(|| {
+        let vid = handle(vid, "vid")?;
+
+        write(out, into_c_string(vid.0.identifier()))
+    })
+}
+
+/// Release `vid`
+///
+/// # Safety
+///
+/// `vid` is null, or was returned by [tsp_owned_vid_new_did_peer] and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn tsp_owned_vid_free(vid: *mut TspOwnedVid) {
+    if !vid.is_null() {
+        drop(Box::from_raw(vid));
+    }
+}
+
+/// Add a copy of our own VID `vid`, with its private keys, to `wallet`
+///
+/// # Safety
+///
+/// `wallet` and `vid` were returned by the library and not released
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_add_private_vid(
+    wallet: *const TspWallet,
+    vid: *const TspOwnedVid,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let vid = handle(vid, "vid")?;
+
+        wallet
+            .store
+            .add_private_vid(vid.0.clone())
+            .map_err(tsp_error)
+    })
+}
+
+/// Add the public part of `vid` to `wallet`, as a VID we can send messages to
+///
+/// # Safety
+///
+/// `wallet` and `vid` were returned by the library and not released
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_add_verified_vid(
+    wallet: *const TspWallet,
+    vid: *const TspOwnedVid,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let vid = handle(vid, "vid")?;
+
+        wallet
+            .store
+            .add_verified_vid(vid.0.vid().clone())
+            .map_err(tsp_error)
+    })
+}
+
+/// Resolve and verify the VID `vid`, e.g. a `did:web`, and add it to `wallet`
+///
+/// # Safety
+///
+/// `wallet` was returned by the library and not released, and `vid` is a null terminated
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_verify_vid(
+    wallet: *const TspWallet,
+    vid: *const c_char,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let vid = string(vid, "vid")?;
+
+        // the clone shares the VIDs, so the verified VID ends up in this wallet
+        let mut store = wallet.store.clone();
+        wallet
+            .runtime
+            .block_on(store.verify_vid(vid))
+            .map_err(tsp_error)
+    })
+}
+
+/// Seal `message` from `sender` to `receiver` without sending it, e.g. to send it over a
+/// transport of the application; the sealed message is written to `out`
+///
+/// # Safety
+///
+/// `wallet` was returned by the library and not released, `sender` and `receiver` are null
+/// terminated strings, the data pointers point to the given number of bytes or are null if
+/// there are none, and `out` points to writable memory for a buffer
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn tsp_wallet_seal(
+    wallet: *const TspWallet,
+    sender: *const c_char,
+    receiver: *const c_char,
+    nonconfidential_data: *const u8,
+    nonconfidential_data_len: usize,
+    message: *const u8,
+    message_len: usize,
+    out: *mut TspBuffer,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let sender = string(sender, "sender")?;
+        let receiver = string(receiver, "receiver")?;
+        let nonconfidential_data = (!nonconfidential_data.is_null())
+            .then(|| {
+                bytes(
+                    nonconfidential_data,
+                    nonconfidential_data_len,
+                    "nonconfidential data",
+                )
+            })
+            .transpose()?;
+        let message = bytes(message, message_len, "message")?;
+
+        let (_, sealed) = wallet
+            .store
+            .as_store()
+            .seal_message(sender, receiver, nonconfidential_data, message)
+            .map_err(tsp_error)?;
+
+        write(out, TspBuffer::new(sealed))
+    })
+}
+
+/// Open the sealed message `data`; the message is written to `out`, and released with
+/// [tsp_message_free]
+///
+/// # Safety
+///
+/// `wallet` was returned by the library and not released, `data` points to `len` bytes,
+/// and `out` points to writable memory for a message pointer
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_open_message(
+    wallet: *const TspWallet,
+    data: *const u8,
+    len: usize,
+    out: *mut *mut TspMessage,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let mut data = bytes(data, len, "data")?.to_vec();
+
+        let message = wallet
+            .store
+            .as_store()
+            .open_message_owned(&mut data)
+            .map_err(tsp_error)?;
+
+        write(out, Box::into_raw(Box::new(TspMessage::from(message))))
+    })
+}
+
+/// Release `message`
+///
+/// # Safety
+///
+/// `message` is null, or was returned by [tsp_wallet_open_message] and is not used
+/// afterwards
+#[no_mangle]
+pub unsafe extern "C" fn tsp_message_free(message: *mut TspMessage) {
+    if !message.is_null() {
+        drop(Box::from_raw(message));
+    }
+}
+
+/// Seal `message` from `sender` to `receiver` and send it to the endpoint of `receiver`
+///
+/// # Safety
+///
+/// `wallet` was returned by the library and not released, `sender` and `receiver` are null
+/// terminated strings, and the data pointers point to the given number of bytes or are null
+/// if there are none
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_send(
+    wallet: *const TspWallet,
+    sender: *const c_char,
+    receiver: *const c_char,
+    nonconfidential_data: *const u8,
+    nonconfidential_data_len: usize,
+    message: *const u8,
+    message_len: usize,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let sender = string(sender, "sender")?;
+        let receiver = string(receiver, "receiver")?;
+        let nonconfidential_data = (!nonconfidential_data.is_null())
+            .then(|| {
+                bytes(
+                    nonconfidential_data,
+                    nonconfidential_data_len,
+                    "nonconfidential data",
+                )
+            })
+            .transpose()?;
+        let message = bytes(message, message_len, "message")?;
+
+        wallet
+            .runtime
+            .block_on(
+                wallet
+                    .store
+                    .send(sender, receiver, nonconfidential_data, message),
+            )
+            .map_err(tsp_error)
+    })
+}
+
+/// Receive the messages for our VID `vid` in the background, and call `callback` with each
+/// of them from a thread of the library, until [tsp_receiver_stop] is called
+///
+/// # Safety
+///
+/// `wallet` was returned by the library and not released, `vid` is a null terminated
+/// string, `user_data` can be used from another thread, and `out` points to writable
+/// memory for a receiver pointer
+#[no_mangle]
+pub unsafe extern "C" fn tsp_wallet_receive(
+    wallet: *const TspWallet,
+    vid: *const c_char,
+    callback: TspMessageCallback,
+    user_data: *mut c_void,
+    out: *mut *mut TspReceiver,
+) -> TspStatus {
+    guard(|| {
+        let wallet = handle(wallet, "wallet")?;
+        let vid = string(vid, "vid")?;
+
+        let (mut messages, handle) = wallet
+            .runtime
+            .block_on(wallet.store.receive_with_handle(vid))
+            .map_err(tsp_error)?;
+
+        let user_data = UserData(user_data);
+        let task = wallet.runtime.spawn(async move {
+            let user_data = user_data;
+
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(message) => {
+                        let message = TspMessage::from(message);
+                        callback(user_data.0, &message, ptr::null());
+                    }
+                    Err(e) => {
+                        let error = CString::new(e.to_string()).unwrap_or_default();
+                        callback(user_data.0, ptr::null(), error.as_ptr());
+                    }
+                }
+            }
+        });
+
+        let receiver = TspReceiver {
+            handle,
+            task: Some(task),
+            runtime: wallet.runtime.handle().clone(),
+        };
+
+        write(out, Box::into_raw(Box::new(receiver)))
+    })
+}
+
+/// Stop receiving, after the messages that already arrived are passed to the callback,
+/// and release `receiver`; the callback is not called after this returns
+///
+/// # Safety
+///
+/// `receiver` is null, or was returned by [tsp_wallet_receive] and is not used afterwards;
+/// it is not called from the callback
+#[no_mangle]
+pub unsafe extern "C" fn tsp_receiver_stop(receiver: *mut TspReceiver) -> TspStatus {
+    guard(|| {
+        if receiver.is_null() {
+            return Ok(());
+        }
+
+        let mut receiver = *Box::from_raw(receiver);
+        receiver.handle.close();
+
+        if let Some(task) = receiver.task.take() {
+            // the task only fails if the callback panicked
+            let _ = receiver.runtime.block_on(task);
+        }
+
+        Ok(())
+    })
+}
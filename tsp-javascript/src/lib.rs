@@ -98,8 +98,7 @@ impl Store {
     #[wasm_bindgen]
     pub fn open_message(&self, mut message: Vec<u8>) -> Result<FlatReceivedTspMessage, Error> {
         self.0
-            .open_message(&mut message)
-            .map(|msg| msg.into_owned())
+            .open_message_owned(&mut message)
             .map(FlatReceivedTspMessage::from)
             .map_err(Error)
     }
@@ -154,6 +153,48 @@ impl Store {
         })
     }
 
+    #[wasm_bindgen]
+    pub fn make_ack(
+        &self,
+        sender: String,
+        receiver: String,
+        digest: Vec<u8>,
+    ) -> Result<SealedMessage, Error> {
+        let (url, sealed) = self
+            .0
+            .make_ack(&sender, &receiver, &digest.try_into().unwrap())
+            .map_err(Error)?;
+
+        Ok(SealedMessage {
+            url: url.to_string(),
+            sealed,
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn make_relationship_reject(
+        &self,
+        sender: String,
+        receiver: String,
+        thread_id: Vec<u8>,
+        reason: Option<String>,
+    ) -> Result<SealedMessage, Error> {
+        let (url, sealed) = self
+            .0
+            .make_relationship_reject(
+                &sender,
+                &receiver,
+                thread_id.try_into().unwrap(),
+                reason.as_deref(),
+            )
+            .map_err(Error)?;
+
+        Ok(SealedMessage {
+            url: url.to_string(),
+            sealed,
+        })
+    }
+
     #[wasm_bindgen]
     pub fn make_relationship_cancel(
         &self,
@@ -171,6 +212,23 @@ impl Store {
         })
     }
 
+    #[wasm_bindgen]
+    pub fn make_nested_relationship_cancel(
+        &self,
+        nested_sender: String,
+        nested_receiver: String,
+    ) -> Result<SealedMessage, Error> {
+        let (url, sealed) = self
+            .0
+            .make_nested_relationship_cancel(&nested_sender, &nested_receiver)
+            .map_err(Error)?;
+
+        Ok(SealedMessage {
+            url: url.to_string(),
+            sealed,
+        })
+    }
+
     #[wasm_bindgen]
     pub fn make_new_identifier_notice(
         &self,
@@ -263,6 +321,42 @@ impl Store {
             sealed,
         })
     }
+
+    #[wasm_bindgen]
+    pub fn get_metadata(&self, vid: String) -> Result<JsValue, JsValue> {
+        let metadata = self.0.get_metadata(&vid).map_err(Error)?;
+
+        metadata
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(JsValue::from)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_metadata(&self, vid: String, metadata: JsValue) -> Result<(), JsValue> {
+        let metadata = serde_wasm_bindgen::from_value(metadata)?;
+
+        Ok(self.0.set_metadata(&vid, metadata).map_err(Error)?)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_vid_metadata(&self, vid: String) -> Result<JsValue, JsValue> {
+        let metadata = self.0.get_vid_metadata(&vid).map_err(Error)?;
+
+        metadata
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(JsValue::from)
+    }
+
+    #[wasm_bindgen]
+    pub fn find_vids_by_metadata(
+        &self,
+        key: String,
+        value: JsValue,
+    ) -> Result<Vec<String>, JsValue> {
+        let value = serde_wasm_bindgen::from_value(value)?;
+
+        Ok(self.0.find_vids_by_metadata(&key, &value).map_err(Error)?)
+    }
 }
 
 fn convert(value: JsValue) -> Result<Vec<Vec<u8>>, serde_wasm_bindgen::Error> {
@@ -378,6 +472,14 @@ pub fn message_parts(message: &[u8]) -> Result<String, Error> {
     })).unwrap())
 }
 
+/// Describe the parts of a CESR-encoded message as a JSON array, see [tsp::cesr::annotate]
+#[wasm_bindgen]
+pub fn annotate_message(message: &[u8]) -> Result<String, Error> {
+    let parts = tsp::cesr::annotate(message).map_err(|e| Error(e.into()))?;
+
+    Ok(serde_json::to_string(&parts).unwrap())
+}
+
 #[wasm_bindgen]
 pub fn probe_message(mut message: Vec<u8>) -> Result<String, Error> {
     tsp::cesr::probe(&mut message)
@@ -422,6 +524,14 @@ pub enum ReceivedTspMessageVariant {
     ForwardRequest = 4,
     NewIdentifier = 5,
     Referral = 6,
+    GroupMessage = 7,
+    GroupMembership = 8,
+    RejectRelationship = 9,
+    RenewRelationship = 10,
+    Acknowledgement = 11,
+    SessionEstablished = 12,
+    Extension = 13,
+    UnknownControl = 14,
 }
 
 impl From<&tsp::ReceivedTspMessage> for ReceivedTspMessageVariant {
@@ -431,9 +541,17 @@ impl From<&tsp::ReceivedTspMessage> for ReceivedTspMessageVariant {
             tsp::ReceivedTspMessage::RequestRelationship { .. } => Self::RequestRelationship,
             tsp::ReceivedTspMessage::AcceptRelationship { .. } => Self::AcceptRelationship,
             tsp::ReceivedTspMessage::CancelRelationship { .. } => Self::CancelRelationship,
+            tsp::ReceivedTspMessage::RejectRelationship { .. } => Self::RejectRelationship,
+            tsp::ReceivedTspMessage::RenewRelationship { .. } => Self::RenewRelationship,
+            tsp::ReceivedTspMessage::Acknowledgement { .. } => Self::Acknowledgement,
+            tsp::ReceivedTspMessage::SessionEstablished { .. } => Self::SessionEstablished,
             tsp::ReceivedTspMessage::ForwardRequest { .. } => Self::ForwardRequest,
             tsp::ReceivedTspMessage::NewIdentifier { .. } => Self::NewIdentifier,
             tsp::ReceivedTspMessage::Referral { .. } => Self::Referral,
+            tsp::ReceivedTspMessage::GroupMessage { .. } => Self::GroupMessage,
+            tsp::ReceivedTspMessage::GroupMembership { .. } => Self::GroupMembership,
+            tsp::ReceivedTspMessage::Extension { .. } => Self::Extension,
+            tsp::ReceivedTspMessage::UnknownControl { .. } => Self::UnknownControl,
             #[cfg(not(target_arch = "wasm32"))]
             tsp::ReceivedTspMessage::PendingMessage { .. } => unreachable!(),
         }
@@ -448,6 +566,7 @@ pub enum CryptoType {
     HpkeEssr = 2,
     NaclAuth = 3,
     NaclEssr = 4,
+    Session = 5,
 }
 
 #[wasm_bindgen]
@@ -475,6 +594,14 @@ pub struct FlatReceivedTspMessage {
     unknown_vid: Option<String>,
     referred_vid: Option<String>,
     new_vid: Option<String>,
+    group: Option<String>,
+    member: Option<String>,
+    membership_change: Option<String>,
+    reason: Option<Option<String>>,
+    expires_at: Option<u64>,
+    digest: Option<Vec<u8>>,
+    typecode: Option<u8>,
+    control_typecode: Option<[u8; 2]>,
 }
 
 #[wasm_bindgen]
@@ -555,6 +682,70 @@ impl FlatReceivedTspMessage {
             None => JsValue::NULL,
         }
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn group(&self) -> JsValue {
+        match &self.group {
+            Some(group) => JsValue::from_str(group),
+            None => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn member(&self) -> JsValue {
+        match &self.member {
+            Some(member) => JsValue::from_str(member),
+            None => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn membership_change(&self) -> JsValue {
+        match &self.membership_change {
+            Some(change) => JsValue::from_str(change),
+            None => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn reason(&self) -> JsValue {
+        match &self.reason {
+            Some(Some(reason)) => JsValue::from_str(reason),
+            _ => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn expires_at(&self) -> JsValue {
+        match self.expires_at {
+            Some(expires_at) => JsValue::from_f64(expires_at as f64),
+            None => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn digest(&self) -> JsValue {
+        match &self.digest {
+            Some(data) => serde_wasm_bindgen::to_value(data).unwrap(),
+            None => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn typecode(&self) -> JsValue {
+        match self.typecode {
+            Some(typecode) => JsValue::from_f64(typecode as f64),
+            None => JsValue::NULL,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn control_typecode(&self) -> JsValue {
+        match &self.control_typecode {
+            Some(typecode) => serde_wasm_bindgen::to_value(typecode).unwrap(),
+            None => JsValue::NULL,
+        }
+    }
 }
 
 impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
@@ -577,6 +768,14 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
             unknown_vid: None,
             referred_vid: None,
             new_vid: None,
+            group: None,
+            member: None,
+            membership_change: None,
+            reason: None,
+            expires_at: None,
+            digest: None,
+            typecode: None,
+            control_typecode: None,
         };
 
         match value {
@@ -584,9 +783,14 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 sender,
                 nonconfidential_data,
                 message,
+                content_type: _,
+                segments: _,
+                in_reply_to: _,
+                digest,
                 message_type,
             } => {
                 this.sender = Some(sender);
+                this.digest = Some(digest.to_vec());
                 this.nonconfidential_data = Some(nonconfidential_data);
                 this.message = Some(message);
                 this.crypto_type = match message_type.crypto_type {
@@ -595,6 +799,7 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                     tsp::cesr::CryptoType::HpkeEssr => Some(CryptoType::HpkeEssr),
                     tsp::cesr::CryptoType::NaclAuth => Some(CryptoType::NaclAuth),
                     tsp::cesr::CryptoType::NaclEssr => Some(CryptoType::NaclEssr),
+                    tsp::cesr::CryptoType::Session => Some(CryptoType::Session),
                 };
                 this.signature_type = match message_type.signature_type {
                     tsp::cesr::SignatureType::NoSignature => Some(SignatureType::NoSignature),
@@ -616,7 +821,33 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 this.sender = Some(sender);
                 this.nested_vid = Some(nested_vid);
             }
-            tsp::ReceivedTspMessage::CancelRelationship { sender } => {
+            tsp::ReceivedTspMessage::CancelRelationship { sender, nested_vid } => {
+                this.sender = Some(sender);
+                this.nested_vid = Some(nested_vid);
+            }
+            tsp::ReceivedTspMessage::RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            } => {
+                this.sender = Some(sender);
+                this.thread_id = Some(thread_id.to_vec());
+                this.reason = Some(reason);
+            }
+            tsp::ReceivedTspMessage::RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            } => {
+                this.sender = Some(sender);
+                this.thread_id = Some(thread_id.to_vec());
+                this.expires_at = Some(expires_at);
+            }
+            tsp::ReceivedTspMessage::Acknowledgement { sender, digest } => {
+                this.sender = Some(sender);
+                this.digest = Some(digest.to_vec());
+            }
+            tsp::ReceivedTspMessage::SessionEstablished { sender } => {
                 this.sender = Some(sender);
             }
             tsp::ReceivedTspMessage::NewIdentifier { sender, new_vid } => {
@@ -634,6 +865,9 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 sender,
                 next_hop,
                 route,
+                annotation: _,
+                route_annotations: _,
+                nonconfidential_data: _,
                 opaque_payload,
             } => {
                 this.sender = Some(sender);
@@ -641,6 +875,47 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 this.route = Some(Some(route));
                 this.opaque_payload = Some(opaque_payload);
             }
+            tsp::ReceivedTspMessage::GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                message_type: _,
+            } => {
+                this.sender = Some(sender);
+                this.group = Some(group);
+                this.nonconfidential_data = Some(nonconfidential_data);
+                this.message = Some(message);
+            }
+            tsp::ReceivedTspMessage::GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            } => {
+                this.sender = Some(sender);
+                this.group = Some(group);
+                this.member = Some(member);
+                this.membership_change = Some(format!("{change:?}"));
+            }
+            tsp::ReceivedTspMessage::Extension {
+                sender,
+                typecode,
+                message,
+            } => {
+                this.sender = Some(sender);
+                this.typecode = Some(typecode);
+                this.message = Some(message.to_string().into_bytes());
+            }
+            tsp::ReceivedTspMessage::UnknownControl {
+                sender,
+                typecode,
+                raw,
+            } => {
+                this.sender = Some(sender);
+                this.control_typecode = Some(typecode);
+                this.message = Some(raw);
+            }
             #[cfg(not(target_arch = "wasm32"))]
             tsp::ReceivedTspMessage::PendingMessage { .. } => {
                 unreachable!()
@@ -7,6 +7,7 @@ const CryptoType = {
     HpkeEssr: 2,
     NaclAuth: 3,
     NaclEssr: 4,
+    Session: 5,
 };
 
 const SignatureType = {
@@ -66,6 +67,10 @@ class Store {
         return this.inner.make_relationship_cancel(...args);
     }
 
+    make_nested_relationship_cancel(...args) {
+        return this.inner.make_nested_relationship_cancel(...args);
+    }
+
     make_nested_relationship_accept(...args) {
         return this.inner.make_nested_relationship_accept(...args);
     }
@@ -78,6 +83,18 @@ class Store {
         return this.inner.forward_routed_message(...args);
     }
 
+    get_metadata(...args) {
+        return this.inner.get_metadata(...args);
+    }
+
+    set_metadata(...args) {
+        return this.inner.set_metadata(...args);
+    }
+
+    find_vids_by_metadata(...args) {
+        return this.inner.find_vids_by_metadata(...args);
+    }
+
     open_message(...args) {
         const flatMessage = this.inner.open_message(...args);
         return ReceivedTspMessage.fromFlat(flatMessage);
@@ -17,37 +17,143 @@ fn py_exception<E: std::fmt::Debug>(e: E) -> PyErr {
     PyException::new_err(format!("{e:?}"))
 }
 
+/// A wallet the VIDs of a [Store] are loaded from and written to
+struct Wallet {
+    wallet: tsp::Wallet,
+    runtime: tokio::runtime::Runtime,
+}
+
 #[pyclass]
-struct Store(tsp::Store);
+struct Store {
+    inner: tsp::Store,
+    wallet: Option<Wallet>,
+}
+
+impl Store {
+    /// Write the VIDs that changed to the wallet, if the store has one that is open
+    fn persist(&mut self) -> Result<(), tsp::Error> {
+        let Some(Wallet { wallet, runtime }) = &mut self.wallet else {
+            return Ok(());
+        };
+
+        runtime.block_on(wallet.save())
+    }
+
+    /// Write the VIDs to the wallet and close it; does nothing if it is closed already
+    fn close_wallet(&mut self) -> Result<(), tsp::Error> {
+        match self.wallet.take() {
+            Some(Wallet { wallet, runtime }) => runtime.block_on(wallet.close()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A store that is garbage collected without being closed still writes its wallet
+impl Drop for Store {
+    fn drop(&mut self) {
+        if let Err(e) = self.close_wallet() {
+            eprintln!("could not write wallet: {e}");
+        }
+    }
+}
 
 #[pymethods]
 impl Store {
+    /// Create a store; with a wallet `name` and `password`, the VIDs are loaded from
+    /// the SQLite wallet `name`, which is created if it does not exist
     #[new]
-    fn new() -> Self {
-        Self(tsp::Store::default())
+    #[pyo3(signature = (name=None, password=None))]
+    fn new(name: Option<String>, password: Option<String>) -> PyResult<Self> {
+        let Some(name) = name else {
+            return Ok(Self {
+                inner: tsp::Store::default(),
+                wallet: None,
+            });
+        };
+
+        let password = password.unwrap_or_default();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(py_exception)?;
+
+        let wallet = runtime
+            .block_on(tsp::Wallet::open(&name, password.as_bytes()))
+            .map_err(py_exception)?;
+
+        Ok(Self {
+            inner: wallet.store().as_store().clone(),
+            wallet: Some(Wallet { wallet, runtime }),
+        })
+    }
+
+    /// Write the VIDs to the wallet
+    fn write_wallet(&mut self) -> PyResult<()> {
+        self.persist().map_err(py_exception)
+    }
+
+    /// Write the VIDs to the wallet and close it; the store can still be used, but
+    /// changes are no longer written
+    fn close(&mut self) -> PyResult<()> {
+        self.close_wallet().map_err(py_exception)
     }
 
     fn add_private_vid(&self, vid: OwnedVid) -> PyResult<()> {
-        self.0.add_private_vid(vid.0).map_err(py_exception)
+        self.inner.add_private_vid(vid.0).map_err(py_exception)
     }
 
     fn add_verified_vid(&self, vid: OwnedVid) -> PyResult<()> {
-        self.0.add_verified_vid(vid.0).map_err(py_exception)
+        self.inner.add_verified_vid(vid.0).map_err(py_exception)
     }
 
     fn set_relation_for_vid(&self, vid: String, relation_vid: Option<String>) -> PyResult<()> {
-        self.0
+        self.inner
             .set_relation_for_vid(&vid, relation_vid.as_deref())
             .map_err(py_exception)
     }
 
     fn set_route_for_vid(&self, vid: String, route: Vec<String>) -> PyResult<()> {
         let borrowed: Vec<_> = route.iter().map(|s| s.as_str()).collect();
-        self.0
+        self.inner
             .set_route_for_vid(&vid, &borrowed)
             .map_err(py_exception)
     }
 
+    fn get_metadata(&self, vid: String) -> PyResult<Option<String>> {
+        let metadata = self.inner.get_metadata(&vid).map_err(py_exception)?;
+
+        Ok(metadata.map(|metadata| metadata.to_string()))
+    }
+
+    #[pyo3(signature = (vid, metadata))]
+    fn set_metadata(&self, vid: String, metadata: Option<String>) -> PyResult<()> {
+        let metadata = metadata
+            .map(|metadata| serde_json::from_str(&metadata))
+            .transpose()
+            .map_err(py_exception)?;
+
+        self.inner
+            .set_metadata(&vid, metadata)
+            .map_err(py_exception)
+    }
+
+    fn get_vid_metadata(&self, vid: String) -> PyResult<Option<String>> {
+        let metadata = self.inner.get_vid_metadata(&vid).map_err(py_exception)?;
+
+        metadata
+            .map(|metadata| serde_json::to_string(&metadata))
+            .transpose()
+            .map_err(py_exception)
+    }
+
+    fn find_vids_by_metadata(&self, key: String, value: String) -> PyResult<Vec<String>> {
+        let value = serde_json::from_str(&value).map_err(py_exception)?;
+
+        self.inner
+            .find_vids_by_metadata(&key, &value)
+            .map_err(py_exception)
+    }
+
     #[pyo3(signature = (sender, receiver, nonconfidential_data, message))]
     fn seal_message(
         &self,
@@ -57,7 +163,7 @@ impl Store {
         message: Vec<u8>,
     ) -> PyResult<(String, Vec<u8>)> {
         let (url, bytes) = self
-            .0
+            .inner
             .seal_message(
                 &sender,
                 &receiver,
@@ -69,6 +175,21 @@ impl Store {
         Ok((url.to_string(), bytes))
     }
 
+    #[pyo3(signature = (sender, receiver, digest))]
+    fn make_ack(
+        &self,
+        sender: String,
+        receiver: String,
+        digest: [u8; 32],
+    ) -> PyResult<(String, Vec<u8>)> {
+        let (url, bytes) = self
+            .inner
+            .make_ack(&sender, &receiver, &digest)
+            .map_err(py_exception)?;
+
+        Ok((url.to_string(), bytes))
+    }
+
     #[pyo3(signature = (sender, receiver, route))]
     fn make_relationship_request(
         &self,
@@ -79,7 +200,7 @@ impl Store {
         let route_items: Vec<&str> = route.iter().flatten().map(|s| s.as_str()).collect();
 
         let (url, bytes) = self
-            .0
+            .inner
             .make_relationship_request(
                 &sender,
                 &receiver,
@@ -101,7 +222,7 @@ impl Store {
         let route_items: Vec<&str> = route.iter().flatten().map(|s| s.as_str()).collect();
 
         let (url, bytes) = self
-            .0
+            .inner
             .make_relationship_accept(
                 &sender,
                 &receiver,
@@ -113,6 +234,22 @@ impl Store {
         Ok((url.to_string(), bytes))
     }
 
+    #[pyo3(signature = (sender, receiver, thread_id, reason=None))]
+    fn make_relationship_reject(
+        &self,
+        sender: String,
+        receiver: String,
+        thread_id: [u8; 32],
+        reason: Option<String>,
+    ) -> PyResult<(String, Vec<u8>)> {
+        let (url, bytes) = self
+            .inner
+            .make_relationship_reject(&sender, &receiver, thread_id, reason.as_deref())
+            .map_err(py_exception)?;
+
+        Ok((url.to_string(), bytes))
+    }
+
     #[pyo3(signature = (sender, receiver))]
     fn make_relationship_cancel(
         &self,
@@ -120,13 +257,27 @@ impl Store {
         receiver: String,
     ) -> PyResult<(String, Vec<u8>)> {
         let (url, bytes) = self
-            .0
+            .inner
             .make_relationship_cancel(&sender, &receiver)
             .map_err(py_exception)?;
 
         Ok((url.to_string(), bytes))
     }
 
+    #[pyo3(signature = (nested_sender, nested_receiver))]
+    fn make_nested_relationship_cancel(
+        &self,
+        nested_sender: String,
+        nested_receiver: String,
+    ) -> PyResult<(String, Vec<u8>)> {
+        let (url, bytes) = self
+            .inner
+            .make_nested_relationship_cancel(&nested_sender, &nested_receiver)
+            .map_err(py_exception)?;
+
+        Ok((url.to_string(), bytes))
+    }
+
     #[pyo3(signature = (sender, receiver, sender_new_vid))]
     fn make_new_identifier_notice(
         &self,
@@ -135,7 +286,7 @@ impl Store {
         sender_new_vid: String,
     ) -> PyResult<(String, Vec<u8>)> {
         let (url, bytes) = self
-            .0
+            .inner
             .make_new_identifier_notice(&sender, &receiver, &sender_new_vid)
             .map_err(py_exception)?;
 
@@ -150,7 +301,7 @@ impl Store {
         referred_vid: String,
     ) -> PyResult<(String, Vec<u8>)> {
         let (url, bytes) = self
-            .0
+            .inner
             .make_relationship_referral(&sender, &receiver, &referred_vid)
             .map_err(py_exception)?;
 
@@ -163,7 +314,7 @@ impl Store {
         receiver: String,
     ) -> PyResult<((String, Vec<u8>), OwnedVid)> {
         let ((url, bytes), vid) = self
-            .0
+            .inner
             .make_nested_relationship_request(&parent_sender, &receiver)
             .map_err(py_exception)?;
 
@@ -177,7 +328,7 @@ impl Store {
         thread_id: [u8; 32],
     ) -> PyResult<((String, Vec<u8>), OwnedVid)> {
         let ((url, bytes), vid) = self
-            .0
+            .inner
             .make_nested_relationship_accept(&sender, &receiver, thread_id)
             .map_err(py_exception)?;
 
@@ -192,7 +343,7 @@ impl Store {
     ) -> PyResult<(String, Vec<u8>)> {
         let borrowed_route: Vec<_> = route.iter().map(|v| v.as_slice()).collect();
         let (url, bytes) = self
-            .0
+            .inner
             .forward_routed_message(&next_hop, borrowed_route, &opaque_payload)
             .map_err(py_exception)?;
 
@@ -200,9 +351,8 @@ impl Store {
     }
 
     fn open_message(&self, mut message: Vec<u8>) -> PyResult<FlatReceivedTspMessage> {
-        self.0
-            .open_message(&mut message)
-            .map(|msg| msg.into_owned())
+        self.inner
+            .open_message_owned(&mut message)
             .map(FlatReceivedTspMessage::from)
             .map_err(py_exception)
     }
@@ -219,6 +369,14 @@ enum ReceivedTspMessageVariant {
     PendingMessage,
     NewIdentifier,
     Referral,
+    GroupMessage,
+    GroupMembership,
+    RejectRelationship,
+    RenewRelationship,
+    Acknowledgement,
+    SessionEstablished,
+    Extension,
+    UnknownControl,
 }
 
 impl From<&tsp::ReceivedTspMessage> for ReceivedTspMessageVariant {
@@ -228,10 +386,18 @@ impl From<&tsp::ReceivedTspMessage> for ReceivedTspMessageVariant {
             tsp::ReceivedTspMessage::RequestRelationship { .. } => Self::RequestRelationship,
             tsp::ReceivedTspMessage::AcceptRelationship { .. } => Self::AcceptRelationship,
             tsp::ReceivedTspMessage::CancelRelationship { .. } => Self::CancelRelationship,
+            tsp::ReceivedTspMessage::RejectRelationship { .. } => Self::RejectRelationship,
+            tsp::ReceivedTspMessage::RenewRelationship { .. } => Self::RenewRelationship,
+            tsp::ReceivedTspMessage::Acknowledgement { .. } => Self::Acknowledgement,
+            tsp::ReceivedTspMessage::SessionEstablished { .. } => Self::SessionEstablished,
             tsp::ReceivedTspMessage::ForwardRequest { .. } => Self::ForwardRequest,
             tsp::ReceivedTspMessage::PendingMessage { .. } => Self::PendingMessage,
             tsp::ReceivedTspMessage::NewIdentifier { .. } => Self::NewIdentifier,
             tsp::ReceivedTspMessage::Referral { .. } => Self::Referral,
+            tsp::ReceivedTspMessage::GroupMessage { .. } => Self::GroupMessage,
+            tsp::ReceivedTspMessage::GroupMembership { .. } => Self::GroupMembership,
+            tsp::ReceivedTspMessage::Extension { .. } => Self::Extension,
+            tsp::ReceivedTspMessage::UnknownControl { .. } => Self::UnknownControl,
         }
     }
 }
@@ -244,6 +410,7 @@ pub enum CryptoType {
     HpkeEssr = 2,
     NaclAuth = 3,
     NaclEssr = 4,
+    Session = 5,
 }
 
 #[pyclass]
@@ -286,6 +453,22 @@ struct FlatReceivedTspMessage {
     new_vid: Option<String>,
     #[pyo3(get, set)]
     referred_vid: Option<String>,
+    #[pyo3(get, set)]
+    group: Option<String>,
+    #[pyo3(get, set)]
+    member: Option<String>,
+    #[pyo3(get, set)]
+    membership_change: Option<String>,
+    #[pyo3(get, set)]
+    reason: Option<Option<String>>,
+    #[pyo3(get, set)]
+    expires_at: Option<u64>,
+    #[pyo3(get, set)]
+    digest: Option<[u8; 32]>,
+    #[pyo3(get, set)]
+    typecode: Option<u8>,
+    #[pyo3(get, set)]
+    control_typecode: Option<[u8; 2]>,
 }
 
 #[pymethods]
@@ -315,6 +498,14 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
             unknown_vid: None,
             new_vid: None,
             referred_vid: None,
+            group: None,
+            member: None,
+            membership_change: None,
+            reason: None,
+            expires_at: None,
+            digest: None,
+            typecode: None,
+            control_typecode: None,
         };
 
         match value {
@@ -322,9 +513,14 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 sender,
                 nonconfidential_data,
                 message,
+                content_type: _,
+                segments: _,
+                in_reply_to: _,
+                digest,
                 message_type,
             } => {
                 this.sender = Some(sender);
+                this.digest = Some(digest);
                 this.nonconfidential_data = Some(nonconfidential_data);
                 this.message = Some(message);
                 this.crypto_type = match message_type.crypto_type {
@@ -333,6 +529,7 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                     tsp::cesr::CryptoType::HpkeEssr => Some(CryptoType::HpkeEssr),
                     tsp::cesr::CryptoType::NaclAuth => Some(CryptoType::NaclAuth),
                     tsp::cesr::CryptoType::NaclEssr => Some(CryptoType::NaclEssr),
+                    tsp::cesr::CryptoType::Session => Some(CryptoType::Session),
                 };
                 this.signature_type = match message_type.signature_type {
                     tsp::cesr::SignatureType::NoSignature => Some(SignatureType::NoSignature),
@@ -354,7 +551,33 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 this.sender = Some(sender);
                 this.nested_vid = Some(nested_vid);
             }
-            tsp::ReceivedTspMessage::CancelRelationship { sender } => {
+            tsp::ReceivedTspMessage::CancelRelationship { sender, nested_vid } => {
+                this.sender = Some(sender);
+                this.nested_vid = Some(nested_vid);
+            }
+            tsp::ReceivedTspMessage::RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            } => {
+                this.sender = Some(sender);
+                this.thread_id = Some(thread_id);
+                this.reason = Some(reason);
+            }
+            tsp::ReceivedTspMessage::RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            } => {
+                this.sender = Some(sender);
+                this.thread_id = Some(thread_id);
+                this.expires_at = Some(expires_at);
+            }
+            tsp::ReceivedTspMessage::Acknowledgement { sender, digest } => {
+                this.sender = Some(sender);
+                this.digest = Some(digest);
+            }
+            tsp::ReceivedTspMessage::SessionEstablished { sender } => {
                 this.sender = Some(sender);
             }
             tsp::ReceivedTspMessage::NewIdentifier { sender, new_vid } => {
@@ -372,6 +595,9 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 sender,
                 next_hop,
                 route,
+                annotation: _,
+                route_annotations: _,
+                nonconfidential_data: _,
                 opaque_payload,
             } => {
                 this.sender = Some(sender);
@@ -379,6 +605,47 @@ impl From<tsp::ReceivedTspMessage> for FlatReceivedTspMessage {
                 this.route = Some(Some(route));
                 this.opaque_payload = Some(opaque_payload);
             }
+            tsp::ReceivedTspMessage::GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                message_type: _,
+            } => {
+                this.sender = Some(sender);
+                this.group = Some(group);
+                this.nonconfidential_data = Some(nonconfidential_data);
+                this.message = Some(message);
+            }
+            tsp::ReceivedTspMessage::GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            } => {
+                this.sender = Some(sender);
+                this.group = Some(group);
+                this.member = Some(member);
+                this.membership_change = Some(format!("{change:?}"));
+            }
+            tsp::ReceivedTspMessage::Extension {
+                sender,
+                typecode,
+                message,
+            } => {
+                this.sender = Some(sender);
+                this.typecode = Some(typecode);
+                this.message = Some(message.to_string().into_bytes());
+            }
+            tsp::ReceivedTspMessage::UnknownControl {
+                sender,
+                typecode,
+                raw,
+            } => {
+                this.sender = Some(sender);
+                this.control_typecode = Some(typecode);
+                this.message = Some(raw);
+            }
             tsp::ReceivedTspMessage::PendingMessage {
                 unknown_vid,
                 payload,
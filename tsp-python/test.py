@@ -1,3 +1,5 @@
+import os
+import tempfile
 import unittest
 from tsp import *
 
@@ -265,5 +267,28 @@ class AliceBob(unittest.TestCase):
             case other:
                 self.fail(f"unexpected message type {other}")
 
+class Wallet(unittest.TestCase):
+    def test_context_manager(self):
+        with tempfile.TemporaryDirectory() as directory:
+            name = os.path.join(directory, "wallet")
+            alice = new_vid()
+            bob = new_vid()
+
+            with Store(name, "unsafe") as store:
+                store.add_private_vid(alice)
+                store.add_private_vid(bob)
+
+            # the VIDs were written when the store was closed
+            with Store(name, "unsafe") as store:
+                _url, sealed = store.seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+
+                match store.open_message(sealed):
+                    case GenericMessage(sender, _, message, _, _):
+                        self.assertEqual(sender, alice.identifier())
+                        self.assertEqual(message, b"hello world")
+
+                    case other:
+                        self.fail(f"unexpected message type {other}")
+
 if __name__ == '__main__':
     unittest.main()
@@ -1,3 +1,4 @@
+import json
 from dataclasses import dataclass
 
 import tsp_python
@@ -6,8 +7,22 @@ from tsp_python import OwnedVid, ReceivedTspMessageVariant, FlatReceivedTspMessa
 class Store:
     inner: tsp_python.Store
 
-    def __init__(self):
-        self.inner = tsp_python.Store()
+    def __init__(self, name=None, password=None):
+        """With a wallet name and password, VIDs are loaded from that wallet and written back on close()"""
+        self.inner = tsp_python.Store(name, password)
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exc_type, exc_value, traceback):
+        self.close()
+        return False
+
+    def write_wallet(self):
+        return self.inner.write_wallet()
+
+    def close(self):
+        return self.inner.close()
 
     def add_private_vid(self, *args, **kwargs):
         return self.inner.add_private_vid(*args, **kwargs)
@@ -28,15 +43,24 @@ class Store:
         flat_message = self.inner.open_message(*args, **kwargs)
         return ReceivedTspMessage.from_flat(flat_message)
 
+    def make_ack(self, *args, **kwargs):
+        return self.inner.make_ack(*args, **kwargs)
+
     def make_relationship_request(self, *args, **kwargs):
         return self.inner.make_relationship_request(*args, **kwargs)
 
     def make_relationship_accept(self, *args, **kwargs):
         return self.inner.make_relationship_accept(*args, **kwargs)
 
+    def make_relationship_reject(self, *args, **kwargs):
+        return self.inner.make_relationship_reject(*args, **kwargs)
+
     def make_relationship_cancel(self, *args, **kwargs):
         return self.inner.make_relationship_cancel(*args, **kwargs)
 
+    def make_nested_relationship_cancel(self, *args, **kwargs):
+        return self.inner.make_nested_relationship_cancel(*args, **kwargs)
+
     def make_nested_relationship_request(self, *args, **kwargs):
         return self.inner.make_nested_relationship_request(*args, **kwargs)
 
@@ -46,6 +70,20 @@ class Store:
     def forward_routed_message(self, *args, **kwargs):
         return self.inner.forward_routed_message(*args, **kwargs)
 
+    def get_metadata(self, vid):
+        metadata = self.inner.get_metadata(vid)
+        return None if metadata is None else json.loads(metadata)
+
+    def get_vid_metadata(self, vid):
+        metadata = self.inner.get_vid_metadata(vid)
+        return None if metadata is None else json.loads(metadata)
+
+    def set_metadata(self, vid, metadata):
+        return self.inner.set_metadata(vid, None if metadata is None else json.dumps(metadata))
+
+    def find_vids_by_metadata(self, key, value):
+        return self.inner.find_vids_by_metadata(key, json.dumps(value))
+
 class ReceivedTspMessage:
     @staticmethod
     def from_flat(msg: FlatReceivedTspMessage):
@@ -62,6 +100,18 @@ class ReceivedTspMessage:
             case ReceivedTspMessageVariant.CancelRelationship:
                 return CancelRelationship(msg.sender)
 
+            case ReceivedTspMessageVariant.RejectRelationship:
+                return RejectRelationship(msg.sender, msg.thread_id, msg.reason)
+
+            case ReceivedTspMessageVariant.RenewRelationship:
+                return RenewRelationship(msg.sender, msg.thread_id, msg.expires_at)
+
+            case ReceivedTspMessageVariant.Acknowledgement:
+                return Acknowledgement(msg.sender, msg.digest)
+
+            case ReceivedTspMessageVariant.SessionEstablished:
+                return SessionEstablished(msg.sender)
+
             case ReceivedTspMessageVariant.ForwardRequest:
                 return ForwardRequest(msg.sender, msg.next_hop, msg.route, msg.opaque_payload)
 
@@ -88,6 +138,27 @@ class AcceptRelationship(ReceivedTspMessage):
 class CancelRelationship(ReceivedTspMessage):
     sender: str
 
+@dataclass
+class RejectRelationship(ReceivedTspMessage):
+    sender: str
+    thread_id: str
+    reason: str
+
+@dataclass
+class RenewRelationship(ReceivedTspMessage):
+    sender: str
+    thread_id: str
+    expires_at: int
+
+@dataclass
+class Acknowledgement(ReceivedTspMessage):
+    sender: str
+    digest: str
+
+@dataclass
+class SessionEstablished(ReceivedTspMessage):
+    sender: str
+
 @dataclass
 class RequestRelationship(ReceivedTspMessage):
     sender: str
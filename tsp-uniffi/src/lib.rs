@@ -0,0 +1,582 @@
+use futures::StreamExt;
+use std::sync::Arc;
+
+uniffi::setup_scaffolding!();
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum TspError {
+    Tsp(tsp::Error),
+    InvalidArgument(String),
+}
+
+impl std::fmt::Display for TspError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TspError::Tsp(e) => write!(f, "{e}"),
+            TspError::InvalidArgument(e) => write!(f, "invalid argument: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TspError {}
+
+impl From<tsp::Error> for TspError {
+    fn from(e: tsp::Error) -> Self {
+        TspError::Tsp(e)
+    }
+}
+
+impl From<serde_json::Error> for TspError {
+    fn from(e: serde_json::Error) -> Self {
+        TspError::InvalidArgument(e.to_string())
+    }
+}
+
+fn to_digest(bytes: Vec<u8>) -> Result<tsp::definitions::Digest, TspError> {
+    bytes
+        .try_into()
+        .map_err(|_| TspError::InvalidArgument("a digest is 32 bytes".to_string()))
+}
+
+fn borrow_route(route: &Option<Vec<String>>) -> Option<Vec<&str>> {
+    route
+        .as_ref()
+        .map(|route| route.iter().map(String::as_str).collect())
+}
+
+/// Receives the messages of [AsyncStore::receive], implemented in Kotlin or Swift
+#[uniffi::export(with_foreign)]
+pub trait MessageHandler: Send + Sync {
+    fn on_message(&self, message: ReceivedTspMessage);
+
+    fn on_error(&self, error: String);
+}
+
+#[derive(Default, uniffi::Object)]
+pub struct AsyncStore(tsp::AsyncStore);
+
+#[uniffi::export(async_runtime = "tokio")]
+impl AsyncStore {
+    #[uniffi::constructor]
+    pub fn new() -> Self {
+        Self(tsp::AsyncStore::new())
+    }
+
+    pub fn add_private_vid(&self, vid: Arc<OwnedVid>) -> Result<(), TspError> {
+        Ok(self.0.add_private_vid(vid.0.clone())?)
+    }
+
+    pub fn add_verified_vid(&self, vid: Arc<OwnedVid>) -> Result<(), TspError> {
+        Ok(self.0.add_verified_vid(vid.0.clone())?)
+    }
+
+    pub fn forget_vid(&self, vid: String) -> Result<(), TspError> {
+        Ok(self.0.forget_vid(&vid)?)
+    }
+
+    pub fn list_vids(&self) -> Result<Vec<String>, TspError> {
+        Ok(self.0.list_vids()?)
+    }
+
+    pub async fn verify_vid(&self, vid: String) -> Result<(), TspError> {
+        // the clone shares the VID database, so the verified VID ends up in this store
+        let mut db = self.0.clone();
+
+        Ok(db.verify_vid(&vid).await?)
+    }
+
+    pub fn set_relation_for_vid(
+        &self,
+        vid: String,
+        relation_vid: Option<String>,
+    ) -> Result<(), TspError> {
+        Ok(self.0.set_relation_for_vid(&vid, relation_vid.as_deref())?)
+    }
+
+    pub fn set_route_for_vid(&self, vid: String, route: Vec<String>) -> Result<(), TspError> {
+        let borrowed: Vec<_> = route.iter().map(|s| s.as_str()).collect();
+
+        Ok(self.0.set_route_for_vid(&vid, &borrowed)?)
+    }
+
+    pub fn get_metadata(&self, vid: String) -> Result<Option<String>, TspError> {
+        let metadata = self.0.get_metadata(&vid)?;
+
+        Ok(metadata.map(|metadata| metadata.to_string()))
+    }
+
+    pub fn set_metadata(&self, vid: String, metadata: Option<String>) -> Result<(), TspError> {
+        let metadata = metadata
+            .map(|metadata| serde_json::from_str(&metadata))
+            .transpose()?;
+
+        Ok(self.0.set_metadata(&vid, metadata)?)
+    }
+
+    pub fn get_vid_metadata(&self, vid: String) -> Result<Option<String>, TspError> {
+        let metadata = self.0.get_vid_metadata(&vid)?;
+
+        Ok(metadata
+            .map(|metadata| serde_json::to_string(&metadata))
+            .transpose()?)
+    }
+
+    pub fn find_vids_by_metadata(
+        &self,
+        key: String,
+        value: String,
+    ) -> Result<Vec<String>, TspError> {
+        let value = serde_json::from_str(&value)?;
+
+        Ok(self.0.find_vids_by_metadata(&key, &value)?)
+    }
+
+    pub async fn send(
+        &self,
+        sender: String,
+        receiver: String,
+        nonconfidential_data: Option<Vec<u8>>,
+        message: Vec<u8>,
+    ) -> Result<(), TspError> {
+        Ok(self
+            .0
+            .send(
+                &sender,
+                &receiver,
+                nonconfidential_data.as_deref(),
+                &message,
+            )
+            .await?)
+    }
+
+    pub async fn send_and_hash(
+        &self,
+        sender: String,
+        receiver: String,
+        nonconfidential_data: Option<Vec<u8>>,
+        message: Vec<u8>,
+    ) -> Result<Vec<u8>, TspError> {
+        let digest = self
+            .0
+            .send_and_hash(
+                &sender,
+                &receiver,
+                nonconfidential_data.as_deref(),
+                &message,
+            )
+            .await?;
+
+        Ok(digest.to_vec())
+    }
+
+    pub async fn send_ack(
+        &self,
+        sender: String,
+        receiver: String,
+        digest: Vec<u8>,
+    ) -> Result<(), TspError> {
+        Ok(self
+            .0
+            .send_ack(&sender, &receiver, &to_digest(digest)?)
+            .await?)
+    }
+
+    pub async fn send_relationship_request(
+        &self,
+        sender: String,
+        receiver: String,
+        route: Option<Vec<String>>,
+    ) -> Result<(), TspError> {
+        let route = borrow_route(&route);
+
+        Ok(self
+            .0
+            .send_relationship_request(&sender, &receiver, route.as_deref())
+            .await?)
+    }
+
+    pub async fn send_relationship_accept(
+        &self,
+        sender: String,
+        receiver: String,
+        thread_id: Vec<u8>,
+        route: Option<Vec<String>>,
+    ) -> Result<(), TspError> {
+        let route = borrow_route(&route);
+
+        Ok(self
+            .0
+            .send_relationship_accept(&sender, &receiver, to_digest(thread_id)?, route.as_deref())
+            .await?)
+    }
+
+    pub async fn send_relationship_reject(
+        &self,
+        sender: String,
+        receiver: String,
+        thread_id: Vec<u8>,
+        reason: Option<String>,
+    ) -> Result<(), TspError> {
+        Ok(self
+            .0
+            .send_relationship_reject(&sender, &receiver, to_digest(thread_id)?, reason.as_deref())
+            .await?)
+    }
+
+    pub async fn send_relationship_cancel(
+        &self,
+        sender: String,
+        receiver: String,
+    ) -> Result<(), TspError> {
+        Ok(self.0.send_relationship_cancel(&sender, &receiver).await?)
+    }
+
+    pub async fn send_new_identifier_notice(
+        &self,
+        sender: String,
+        receiver: String,
+        sender_new_vid: String,
+    ) -> Result<(), TspError> {
+        Ok(self
+            .0
+            .send_new_identifier_notice(&sender, &receiver, &sender_new_vid)
+            .await?)
+    }
+
+    pub async fn send_relationship_referral(
+        &self,
+        sender: String,
+        receiver: String,
+        referred_vid: String,
+    ) -> Result<(), TspError> {
+        Ok(self
+            .0
+            .send_relationship_referral(&sender, &receiver, &referred_vid)
+            .await?)
+    }
+
+    pub async fn send_nested_relationship_request(
+        &self,
+        parent_sender: String,
+        receiver: String,
+    ) -> Result<Arc<OwnedVid>, TspError> {
+        let vid = self
+            .0
+            .send_nested_relationship_request(&parent_sender, &receiver)
+            .await?;
+
+        Ok(Arc::new(OwnedVid(vid)))
+    }
+
+    pub async fn send_nested_relationship_accept(
+        &self,
+        parent_sender: String,
+        nested_receiver: String,
+        thread_id: Vec<u8>,
+    ) -> Result<Arc<OwnedVid>, TspError> {
+        let vid = self
+            .0
+            .send_nested_relationship_accept(
+                &parent_sender,
+                &nested_receiver,
+                to_digest(thread_id)?,
+            )
+            .await?;
+
+        Ok(Arc::new(OwnedVid(vid)))
+    }
+
+    /// Listen for messages for the private VID `vid` and pass each one to `handler`;
+    /// returns when the connection closes, or when the calling coroutine is cancelled
+    pub async fn receive(
+        &self,
+        vid: String,
+        handler: Arc<dyn MessageHandler>,
+    ) -> Result<(), TspError> {
+        let mut messages = self.0.receive(&vid).await?;
+
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(message) => handler.on_message(message.into()),
+                Err(e) => handler.on_error(e.to_string()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(uniffi::Object)]
+pub struct OwnedVid(tsp::OwnedVid);
+
+#[uniffi::export]
+impl OwnedVid {
+    #[uniffi::constructor]
+    pub fn new_did_peer(url: String) -> Result<Self, TspError> {
+        let url = url
+            .parse()
+            .map_err(|_| TspError::InvalidArgument(format!("invalid url {url}")))?;
+
+        Ok(OwnedVid(tsp::OwnedVid::new_did_peer(url)))
+    }
+
+    pub fn identifier(&self) -> String {
+        use tsp::VerifiedVid;
+        self.0.identifier().to_string()
+    }
+
+    pub fn endpoint(&self) -> String {
+        use tsp::VerifiedVid;
+        self.0.endpoint().to_string()
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct MessageSegment {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(uniffi::Enum)]
+pub enum ReceivedTspMessage {
+    GenericMessage {
+        sender: String,
+        nonconfidential_data: Option<Vec<u8>>,
+        message: Vec<u8>,
+        content_type: Option<String>,
+        segments: Vec<MessageSegment>,
+        in_reply_to: Option<Vec<u8>>,
+        digest: Vec<u8>,
+        crypto_type: String,
+        signature_type: String,
+    },
+    RequestRelationship {
+        sender: String,
+        route: Option<Vec<Vec<u8>>>,
+        nested_vid: Option<String>,
+        thread_id: Vec<u8>,
+    },
+    AcceptRelationship {
+        sender: String,
+        nested_vid: Option<String>,
+    },
+    CancelRelationship {
+        sender: String,
+        nested_vid: Option<String>,
+    },
+    RejectRelationship {
+        sender: String,
+        thread_id: Vec<u8>,
+        reason: Option<String>,
+    },
+    RenewRelationship {
+        sender: String,
+        thread_id: Vec<u8>,
+        expires_at: u64,
+    },
+    SessionEstablished {
+        sender: String,
+    },
+    Acknowledgement {
+        sender: String,
+        digest: Vec<u8>,
+    },
+    ForwardRequest {
+        sender: String,
+        next_hop: String,
+        route: Vec<Vec<u8>>,
+        annotation: Option<Vec<u8>>,
+        route_annotations: Vec<Vec<u8>>,
+        nonconfidential_data: Option<Vec<u8>>,
+        opaque_payload: Vec<u8>,
+    },
+    NewIdentifier {
+        sender: String,
+        new_vid: String,
+    },
+    Referral {
+        sender: String,
+        referred_vid: String,
+    },
+    GroupMessage {
+        sender: String,
+        group: String,
+        nonconfidential_data: Option<Vec<u8>>,
+        message: Vec<u8>,
+        crypto_type: String,
+        signature_type: String,
+    },
+    GroupMembership {
+        sender: String,
+        group: String,
+        member: String,
+        change: String,
+    },
+    Extension {
+        sender: String,
+        typecode: u8,
+        /// The decoded message, as JSON
+        message: String,
+    },
+    UnknownControl {
+        sender: String,
+        typecode: Vec<u8>,
+        raw: Vec<u8>,
+    },
+    PendingMessage {
+        unknown_vid: String,
+        payload: Vec<u8>,
+    },
+}
+
+impl From<tsp::ReceivedTspMessage> for ReceivedTspMessage {
+    fn from(value: tsp::ReceivedTspMessage) -> Self {
+        match value {
+            tsp::ReceivedTspMessage::GenericMessage {
+                sender,
+                nonconfidential_data,
+                message,
+                content_type,
+                segments,
+                in_reply_to,
+                digest,
+                message_type,
+            } => ReceivedTspMessage::GenericMessage {
+                sender,
+                nonconfidential_data,
+                message,
+                content_type,
+                segments: segments
+                    .into_iter()
+                    .map(|(content_type, data)| MessageSegment { content_type, data })
+                    .collect(),
+                in_reply_to: in_reply_to.map(|digest| digest.to_vec()),
+                digest: digest.to_vec(),
+                crypto_type: format!("{:?}", message_type.crypto_type),
+                signature_type: format!("{:?}", message_type.signature_type),
+            },
+            tsp::ReceivedTspMessage::RequestRelationship {
+                sender,
+                route,
+                nested_vid,
+                thread_id,
+            } => ReceivedTspMessage::RequestRelationship {
+                sender,
+                route,
+                nested_vid,
+                thread_id: thread_id.to_vec(),
+            },
+            tsp::ReceivedTspMessage::AcceptRelationship { sender, nested_vid } => {
+                ReceivedTspMessage::AcceptRelationship { sender, nested_vid }
+            }
+            tsp::ReceivedTspMessage::CancelRelationship { sender, nested_vid } => {
+                ReceivedTspMessage::CancelRelationship { sender, nested_vid }
+            }
+            tsp::ReceivedTspMessage::RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            } => ReceivedTspMessage::RejectRelationship {
+                sender,
+                thread_id: thread_id.to_vec(),
+                reason,
+            },
+            tsp::ReceivedTspMessage::RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            } => ReceivedTspMessage::RenewRelationship {
+                sender,
+                thread_id: thread_id.to_vec(),
+                expires_at,
+            },
+            tsp::ReceivedTspMessage::SessionEstablished { sender } => {
+                ReceivedTspMessage::SessionEstablished { sender }
+            }
+            tsp::ReceivedTspMessage::Acknowledgement { sender, digest } => {
+                ReceivedTspMessage::Acknowledgement {
+                    sender,
+                    digest: digest.to_vec(),
+                }
+            }
+            tsp::ReceivedTspMessage::ForwardRequest {
+                sender,
+                next_hop,
+                route,
+                annotation,
+                route_annotations,
+                nonconfidential_data,
+                opaque_payload,
+            } => ReceivedTspMessage::ForwardRequest {
+                sender,
+                next_hop,
+                route,
+                annotation,
+                route_annotations,
+                nonconfidential_data,
+                opaque_payload,
+            },
+            tsp::ReceivedTspMessage::NewIdentifier { sender, new_vid } => {
+                ReceivedTspMessage::NewIdentifier { sender, new_vid }
+            }
+            tsp::ReceivedTspMessage::Referral {
+                sender,
+                referred_vid,
+            } => ReceivedTspMessage::Referral {
+                sender,
+                referred_vid,
+            },
+            tsp::ReceivedTspMessage::GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                message_type,
+            } => ReceivedTspMessage::GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                crypto_type: format!("{:?}", message_type.crypto_type),
+                signature_type: format!("{:?}", message_type.signature_type),
+            },
+            tsp::ReceivedTspMessage::GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            } => ReceivedTspMessage::GroupMembership {
+                sender,
+                group,
+                member,
+                change: format!("{change:?}"),
+            },
+            tsp::ReceivedTspMessage::Extension {
+                sender,
+                typecode,
+                message,
+            } => ReceivedTspMessage::Extension {
+                sender,
+                typecode,
+                message: message.to_string(),
+            },
+            tsp::ReceivedTspMessage::UnknownControl {
+                sender,
+                typecode,
+                raw,
+            } => ReceivedTspMessage::UnknownControl {
+                sender,
+                typecode: typecode.to_vec(),
+                raw,
+            },
+            tsp::ReceivedTspMessage::PendingMessage {
+                unknown_vid,
+                payload,
+            } => ReceivedTspMessage::PendingMessage {
+                unknown_vid,
+                payload,
+            },
+        }
+    }
+}
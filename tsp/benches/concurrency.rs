@@ -0,0 +1,61 @@
+//! Measures the throughput of sealing and opening messages on a shared store
+//! from an increasing number of threads
+//!
+//! Run with `cargo bench -p tsp --bench concurrency`
+
+use std::time::{Duration, Instant};
+use tsp::{OwnedVid, Store, VerifiedVid};
+
+const MESSAGES_PER_THREAD: usize = 2_000;
+const VIDS: usize = 64;
+
+fn new_vid() -> OwnedVid {
+    OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap())
+}
+
+/// Every thread seals and opens messages between its own pair of VIDs
+fn run(store: &Store, pairs: &[(OwnedVid, OwnedVid)], threads: usize) -> Duration {
+    let start = Instant::now();
+
+    std::thread::scope(|scope| {
+        for thread in 0..threads {
+            let (sender, receiver) = &pairs[thread % pairs.len()];
+
+            scope.spawn(move || {
+                for _ in 0..MESSAGES_PER_THREAD {
+                    let (_, mut message) = store
+                        .seal_message(sender.identifier(), receiver.identifier(), None, b"hello")
+                        .unwrap();
+                    store.open_message(&mut message).unwrap();
+                }
+            });
+        }
+    });
+
+    start.elapsed()
+}
+
+fn main() {
+    let store = Store::new();
+    let pairs: Vec<_> = (0..VIDS).map(|_| (new_vid(), new_vid())).collect();
+
+    for (sender, receiver) in &pairs {
+        store.add_private_vid(sender.clone()).unwrap();
+        store.add_private_vid(receiver.clone()).unwrap();
+    }
+
+    let max_threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+    let mut threads = 1;
+    while threads <= max_threads {
+        let elapsed = run(&store, &pairs, threads);
+        let messages = (threads * MESSAGES_PER_THREAD) as f64;
+
+        println!(
+            "{threads:>3} threads: {:>10.0} messages/s",
+            messages / elapsed.as_secs_f64()
+        );
+
+        threads *= 2;
+    }
+}
@@ -0,0 +1,16 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/transport.proto");
+
+    // the gRPC transport is part of the async feature
+    #[cfg(feature = "async")]
+    {
+        // use the bundled protoc, so building does not depend on a system installation
+        if std::env::var_os("PROTOC").is_none() {
+            std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        }
+
+        tonic_build::compile_protos("proto/transport.proto")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,298 @@
+//! A store of the generic messages a [Store](crate::Store) sealed and opened, by digest
+//!
+//! Every message is recorded under the digest of its plaintext, the `digest` of
+//! [ReceivedTspMessage::GenericMessage](crate::ReceivedTspMessage::GenericMessage) and
+//! the `in_reply_to` of replies to it. This lets an application show the original of a
+//! reply, or process a received message only once: with
+//! [ArchiveConfig::refuse_duplicates], opening a message that was already received fails
+//! with [Error::DuplicateMessage](crate::Error::DuplicateMessage).
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::definitions::{Digest, Payload, ReceivedTspMessage};
+
+/// Whether a message was sealed or opened by the store
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A generic message recorded in the archive
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedMessage {
+    /// The digest of the plaintext of the message
+    pub digest: Digest,
+    pub direction: Direction,
+    pub sender: String,
+    /// The receiver in the envelope, if any; for a nested message, that is the receiver
+    /// of the outer message
+    pub receiver: Option<String>,
+    /// When the message was sealed or opened, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub nonconfidential_data: Option<Vec<u8>>,
+    /// The content of the message, still compressed if `compressed` is set; empty for a
+    /// multipart message
+    pub message: Vec<u8>,
+    /// The (content type, data) segments of a multipart message
+    pub segments: Vec<(String, Vec<u8>)>,
+    pub in_reply_to: Option<Digest>,
+    pub compressed: bool,
+}
+
+impl ArchivedMessage {
+    /// The entry of a generic message that is about to be sealed, without its digest;
+    /// `None` for other payloads
+    pub(crate) fn sent(
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        payload: &Payload<&[u8]>,
+    ) -> Option<Self> {
+        let (message, segments, in_reply_to, compressed) = match payload {
+            Payload::Content(message) => (message.to_vec(), Vec::new(), None, false),
+            Payload::Reply {
+                message,
+                in_reply_to,
+            } => (message.to_vec(), Vec::new(), Some(*in_reply_to), false),
+            Payload::Compressed {
+                message,
+                in_reply_to,
+            } => (message.to_vec(), Vec::new(), *in_reply_to, true),
+            Payload::Multipart(segments) => (
+                Vec::new(),
+                segments
+                    .iter()
+                    .map(|(content_type, data)| {
+                        (
+                            String::from_utf8_lossy(content_type).into_owned(),
+                            data.to_vec(),
+                        )
+                    })
+                    .collect(),
+                None,
+                false,
+            ),
+            _ => return None,
+        };
+
+        Some(Self {
+            digest: Default::default(),
+            direction: Direction::Sent,
+            sender: sender.to_string(),
+            receiver: Some(receiver.to_string()),
+            timestamp: now(),
+            nonconfidential_data: nonconfidential_data.map(<[u8]>::to_vec),
+            message,
+            segments,
+            in_reply_to,
+            compressed,
+        })
+    }
+
+    /// The entry of an opened message that was addressed to `receiver`; `None` if it is
+    /// not a generic message
+    pub(crate) fn received(
+        receiver: Option<String>,
+        received: &ReceivedTspMessage<&[u8]>,
+    ) -> Option<Self> {
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            nonconfidential_data,
+            message,
+            segments,
+            in_reply_to,
+            digest,
+            message_type,
+            ..
+        } = received
+        else {
+            return None;
+        };
+
+        Some(Self {
+            digest: *digest,
+            direction: Direction::Received,
+            sender: sender.clone(),
+            receiver,
+            timestamp: now(),
+            nonconfidential_data: nonconfidential_data.map(<[u8]>::to_vec),
+            message: message.to_vec(),
+            segments: segments
+                .iter()
+                .map(|(content_type, data)| (content_type.clone(), data.to_vec()))
+                .collect(),
+            in_reply_to: *in_reply_to,
+            compressed: message_type.compressed,
+        })
+    }
+}
+
+/// How long messages are kept, and whether received messages are opened only once
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveConfig {
+    /// Forget messages that were recorded longer ago than this
+    pub max_age: Option<Duration>,
+    /// Forget the oldest messages when more than this many are recorded
+    pub max_entries: Option<usize>,
+    /// Refuse to open a generic message that is still recorded as received; a message
+    /// that was forgotten by the retention policy is accepted again
+    pub refuse_duplicates: bool,
+}
+
+#[derive(Default)]
+struct Entries {
+    messages: HashMap<(Digest, Direction), ArchivedMessage>,
+    /// The keys of `messages`, oldest first
+    order: VecDeque<(Digest, Direction)>,
+}
+
+/// A store of sent and received generic messages by digest; set it on a store with
+/// [Store::set_message_archive](crate::Store::set_message_archive)
+#[derive(Default)]
+pub struct MessageArchive {
+    config: ArchiveConfig,
+    entries: Mutex<Entries>,
+}
+
+impl MessageArchive {
+    /// Create an empty archive that keeps messages as long as `config` allows
+    pub fn new(config: ArchiveConfig) -> Self {
+        Self {
+            config,
+            entries: Default::default(),
+        }
+    }
+
+    /// How long this archive keeps messages
+    pub fn config(&self) -> &ArchiveConfig {
+        &self.config
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .expect("message archive lock is poisoned")
+    }
+
+    /// Record `message`, unless a message with the same digest and direction is recorded
+    /// already; returns whether it was recorded
+    pub fn record(&self, message: ArchivedMessage) -> bool {
+        let mut entries = self.entries();
+        let key = (message.digest, message.direction);
+
+        if entries.messages.contains_key(&key) {
+            return false;
+        }
+
+        entries.messages.insert(key, message);
+        entries.order.push_back(key);
+        self.prune_entries(&mut entries);
+
+        true
+    }
+
+    /// The message with `digest`; if it was both sent and received by this store, the
+    /// one that was sent
+    pub fn get_by_digest(&self, digest: &Digest) -> Option<ArchivedMessage> {
+        let entries = self.entries();
+
+        [Direction::Sent, Direction::Received]
+            .into_iter()
+            .find_map(|direction| entries.messages.get(&(*digest, direction)).cloned())
+    }
+
+    /// Whether a message with `digest` was received, e.g. to process it only once
+    pub fn was_received(&self, digest: &Digest) -> bool {
+        self.entries()
+            .messages
+            .contains_key(&(*digest, Direction::Received))
+    }
+
+    /// All recorded messages, oldest first
+    pub fn messages(&self) -> Vec<ArchivedMessage> {
+        let entries = self.entries();
+
+        entries
+            .order
+            .iter()
+            .filter_map(|key| entries.messages.get(key).cloned())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget the messages that are older than the maximum age; messages are also
+    /// forgotten whenever a new one is recorded, so this only needs to be called to free
+    /// memory while no messages are exchanged. Returns the number of forgotten messages
+    pub fn prune(&self) -> usize {
+        let mut entries = self.entries();
+        let before = entries.messages.len();
+        self.prune_entries(&mut entries);
+
+        before - entries.messages.len()
+    }
+
+    /// Forget all messages
+    pub fn clear(&self) {
+        *self.entries() = Default::default();
+    }
+
+    fn prune_entries(&self, entries: &mut Entries) {
+        let oldest = self
+            .config
+            .max_age
+            .map(|max_age| now().saturating_sub(max_age.as_secs()));
+
+        while let Some(key) = entries.order.front().copied() {
+            let expired = oldest.is_some_and(|oldest| {
+                entries
+                    .messages
+                    .get(&key)
+                    .is_some_and(|message| message.timestamp < oldest)
+            });
+            let excess = self
+                .config
+                .max_entries
+                .is_some_and(|max_entries| entries.order.len() > max_entries);
+
+            if !expired && !excess {
+                break;
+            }
+
+            entries.order.pop_front();
+            entries.messages.remove(&key);
+        }
+    }
+}
+
+/// The current time in seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
@@ -1,16 +1,151 @@
 use crate::{
-    definitions::{Digest, ReceivedTspMessage, TSPStream, VerifiedVid},
+    archive::MessageArchive,
+    contacts::{Contact, ContactImport},
+    definitions::{
+        content_type, Digest, MessageHeaders, Payload, ReceivedTspMessage, RelationshipStatus,
+        TSPStream, VerifiedVid,
+    },
     error::Error,
-    store::Store,
-    ExportVid, OwnedVid, PrivateVid,
+    graph::RelationshipGraph,
+    store::{RelationshipCleanup, RelationshipRequirement, Store, StoreChanges, StoreConfig},
+    transport::{
+        Circuits, DeliveryConfig, EndpointProbe, Priority, SendQueue, TransportConfig,
+        TransportError,
+    },
+    vid::{
+        resolver, DidMethodRegistry, DidMethodResolver, Provenance, VerificationPolicy,
+        VidMetadata, VidOrigin, VidRefresh, VidResolver, VidStats,
+    },
+    ControlExtension, ExportVid, ForwardGuard, OwnedVid, PrivateVid, Vault, Vid,
 };
-use futures::StreamExt;
+use dashmap::DashMap;
+use futures::{channel::oneshot, FutureExt, StreamExt};
+use rand::RngCore;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
+/// A request sent by [`AsyncStore::call`] awaiting its reply: the VID the reply
+/// is expected from, and where to deliver the reply
+type PendingReply = (String, oneshot::Sender<Vec<u8>>);
+
+/// The answer to [`AsyncStore::ping`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pong {
+    /// The time between sending the ping and receiving the answer
+    pub round_trip: Duration,
+    /// When the receiver answered, in milliseconds since the Unix epoch by its clock
+    pub answered_at: u64,
+}
+
+const PING_NONCE_SIZE: usize = 16;
+const PING_SIZE: usize = PING_NONCE_SIZE + 8;
+
+/// The current time in milliseconds since the Unix epoch, for ping timestamps
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// What [`AsyncStore::receive`] does with a message from a sender that is not in the store
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FirstContactPolicy {
+    /// Yield a [ReceivedTspMessage::PendingMessage], to be accepted with
+    /// [`AsyncStore::resolve_pending`] or rejected with [`AsyncStore::reject_pending`]
+    #[default]
+    Off,
+    /// Resolve and verify the sender, add it to the store and open the message
+    ResolveAndVerify,
+    /// Like [`FirstContactPolicy::ResolveAndVerify`], for senders of these DID methods
+    /// only, e.g. "web" for did:web; other senders are handled like [`FirstContactPolicy::Off`]
+    ResolveMethods(Vec<String>),
+}
+
+impl FirstContactPolicy {
+    fn is_off(&self) -> bool {
+        *self == FirstContactPolicy::Off
+    }
+
+    /// Whether an unknown sender identified by `vid` is resolved on first contact
+    fn allows(&self, vid: &str) -> bool {
+        match self {
+            FirstContactPolicy::Off => false,
+            FirstContactPolicy::ResolveAndVerify => true,
+            FirstContactPolicy::ResolveMethods(methods) => {
+                let mut parts = vid.split(':');
+
+                match (parts.next(), parts.next()) {
+                    (Some(crate::vid::did::SCHEME), Some(method)) => {
+                        methods.iter().any(|allowed| allowed == method)
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Progress of the relationship [`AsyncStore::send`] sets up before the first message to
+/// an unrelated VID; see [`AsyncStore::set_relationship_bootstrap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapStatus {
+    /// A relationship request was sent, and the accept is awaited
+    Requested,
+    /// The receiver accepted the relationship; the message is sent next
+    Accepted,
+    /// The receiver rejected or cancelled the relationship request
+    Rejected,
+    /// The receiver did not accept the relationship in time
+    TimedOut,
+}
+
+/// Called with the sender, the receiver and the progress of a relationship bootstrap
+type BootstrapCallback = Arc<dyn Fn(&str, &str, BootstrapStatus) + Send + Sync>;
+
+/// How [`AsyncStore::send`] sets up a relationship with an unrelated VID
+#[derive(Clone)]
+struct RelationshipBootstrap {
+    timeout: Duration,
+    on_status: BootstrapCallback,
+}
+
+/// Stops a stream returned by [`AsyncStore::receive_with_handle`]
+///
+/// After [`ReceiveHandle::close`], the stream stops waiting for new messages, but still
+/// yields the messages the transport already received, and then ends.
+#[derive(Debug, Clone)]
+pub struct ReceiveHandle {
+    token: CancellationToken,
+}
+
+impl ReceiveHandle {
+    /// Drain the messages that already arrived, then end the stream
+    pub fn close(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether [`ReceiveHandle::close`] was called
+    pub fn is_closed(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
 /// Holds private ands verified VIDs
 /// A Store contains verified VIDs, our relationship status to them,
 /// as well as the private VIDs that this application has control over.
 ///
+/// Cloning an `AsyncStore` is cheap: clones share the same VIDs and settings, so a clone
+/// can be moved into every task that sends or receives messages. See [`Store`] for which
+/// operations may run in parallel.
+///
 /// # Example
 ///
 /// ```rust
@@ -33,9 +168,32 @@ use url::Url;
 ///     ).await;
 /// }
 /// ```
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct AsyncStore {
     inner: Store,
+    /// Shared with clones and tenants, so a later change applies to them as well
+    settings: Arc<RwLock<AsyncSettings>>,
+    pending_replies: Arc<DashMap<Digest, PendingReply>>,
+    relationship_changed: Arc<Notify>,
+    circuits: Arc<Circuits>,
+    send_queue: Arc<SendQueue>,
+}
+
+/// The settings of an [`AsyncStore`], on top of those of its [`Store`]
+#[derive(Default, Clone)]
+struct AsyncSettings {
+    transport_config: TransportConfig,
+    transport_preference: Vec<String>,
+    resolver: Option<Arc<VidResolver>>,
+    did_methods: Arc<DidMethodRegistry>,
+    auto_ack: bool,
+    answer_pings: bool,
+    relationship_notice: bool,
+    first_contact: FirstContactPolicy,
+    bootstrap: Option<RelationshipBootstrap>,
+    delivery_config: DeliveryConfig,
+    vault: Option<Arc<Vault>>,
+    decryption_workers: usize,
 }
 
 impl AsyncStore {
@@ -44,21 +202,255 @@ impl AsyncStore {
         Default::default()
     }
 
+    /// The settings of this store; a setting is only ever replaced as a whole, so they are
+    /// consistent even if a panic poisoned the lock
+    fn settings(&self) -> RwLockReadGuard<'_, AsyncSettings> {
+        self.settings.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Change the settings of this store and its clones
+    fn change_settings(&self, change: impl FnOnce(&mut AsyncSettings)) {
+        change(
+            &mut self
+                .settings
+                .write()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+
+    /// Set the resource limits enforced when sealing and opening messages
+    pub fn set_store_config(&self, config: StoreConfig) {
+        self.inner.set_config(config);
+    }
+
+    /// Set the bounds on buffered messages used by [`AsyncStore::receive`]
+    pub fn set_transport_config(&self, config: TransportConfig) {
+        self.change_settings(|settings| settings.transport_config = config);
+    }
+
+    /// Prefer endpoints with these URL schemes, in this order, when a VID can be reached at
+    /// several endpoints, e.g. `["quic", "https"]`; the others are tried if sending fails
+    pub fn set_transport_preference(&self, schemes: impl IntoIterator<Item = impl Into<String>>) {
+        let schemes = schemes.into_iter().map(Into::into).collect();
+        self.change_settings(|settings| settings.transport_preference = schemes);
+    }
+
+    /// Set the timeout, retries and circuit breaker applied when sending messages;
+    /// a failed delivery is reported as [`Error::Delivery`]
+    pub fn set_delivery_config(&self, config: DeliveryConfig) {
+        self.change_settings(|settings| settings.delivery_config = config);
+    }
+
+    /// Acknowledge every generic message received through [`AsyncStore::receive`], so its
+    /// sender learns it was delivered; see [`AsyncStore::send_ack`]
+    pub fn set_auto_ack(&self, enabled: bool) {
+        self.change_settings(|settings| settings.auto_ack = enabled);
+    }
+
+    /// Answer every [ping](AsyncStore::ping) received through [`AsyncStore::receive`]; pings
+    /// are answered automatically and not passed on to the stream
+    pub fn set_answer_pings(&self, enabled: bool) {
+        self.change_settings(|settings| settings.answer_pings = enabled);
+    }
+
+    /// Refuse generic messages from senders we do not have `requirement` with; see
+    /// [`Store::set_relationship_requirement`]
+    pub fn set_relationship_requirement(
+        &self,
+        requirement: RelationshipRequirement,
+    ) -> Result<(), Error> {
+        self.inner.set_relationship_requirement(requirement)
+    }
+
+    /// Tell the senders of messages that [`AsyncStore::receive`] refuses for lack of a
+    /// relationship, with an empty message of the
+    /// [`RELATIONSHIP_REQUIRED`](content_type::RELATIONSHIP_REQUIRED) content type; the
+    /// refusal is still passed on to the stream as [`Error::NoRelationship`]
+    pub fn set_relationship_notice(&self, enabled: bool) {
+        self.change_settings(|settings| settings.relationship_notice = enabled);
+    }
+
+    /// Open up to `workers` messages received through [`AsyncStore::receive`] at the same
+    /// time on blocking threads, so a burst of large messages does not stall the stream.
+    /// Messages from the same sender are still opened one after the other, and the stream
+    /// yields all messages in the order they arrived. With 0 workers, the default, messages
+    /// are opened one by one on the task that polls the stream
+    pub fn set_decryption_workers(&self, workers: usize) {
+        self.change_settings(|settings| settings.decryption_workers = workers);
+    }
+
+    /// Decide what [`AsyncStore::receive`] does with messages from unknown senders; the
+    /// senders it resolves are subject to the [verification
+    /// policy](AsyncStore::set_verification_policy) like any other VID
+    pub fn set_first_contact_policy(&self, policy: FirstContactPolicy) {
+        self.change_settings(|settings| settings.first_contact = policy);
+    }
+
+    /// Set up a relationship before [`AsyncStore::send`] sends the first message to a VID we
+    /// are unrelated to: a relationship request is sent, and the message follows once the
+    /// receiver accepted it, or fails with [`Error::ReplyTimeout`] after `timeout`.
+    /// `on_status` is called with the sender, the receiver and every [`BootstrapStatus`].
+    ///
+    /// Like for [`AsyncStore::call`], the stream returned by [`AsyncStore::receive`] for the
+    /// sender must be polled while waiting for the accept.
+    pub fn set_relationship_bootstrap(
+        &self,
+        timeout: Duration,
+        on_status: impl Fn(&str, &str, BootstrapStatus) + Send + Sync + 'static,
+    ) {
+        let bootstrap = RelationshipBootstrap {
+            timeout,
+            on_status: Arc::new(on_status),
+        };
+        self.change_settings(|settings| settings.bootstrap = Some(bootstrap));
+    }
+
+    /// Consult `policy` before resolved VIDs, and VIDs learned from received messages,
+    /// are added to the store
+    pub fn set_verification_policy(&self, policy: impl VerificationPolicy + 'static) {
+        self.inner.set_verification_policy(policy);
+    }
+
+    /// Consult `guard` before routed messages are forwarded
+    pub fn set_forward_guard(&self, guard: impl ForwardGuard + 'static) {
+        self.inner.set_forward_guard(guard);
+    }
+
+    /// Refuse to send messages that expose a nested VID as their sender on the wire; see
+    /// [`Store::set_hide_identities`]
+    pub fn set_hide_identities(&self, enabled: bool) {
+        self.inner.set_hide_identities(enabled);
+    }
+
+    /// Record the generic messages that are sent and received in `archive`, e.g. to skip
+    /// messages that were received before; see [`Store::set_message_archive`]
+    pub fn set_message_archive(&self, archive: MessageArchive) {
+        self.inner.set_message_archive(archive);
+    }
+
+    /// Seal and open the control messages of type `typecode` with `extension`; see
+    /// [`AsyncStore::send_extension_message`]
+    pub fn register_control_extension(
+        &self,
+        typecode: u8,
+        extension: impl ControlExtension + 'static,
+    ) {
+        self.inner.register_control_extension(typecode, extension);
+    }
+
+    /// Resolve VIDs through a caching [`VidResolver`] instead of fetching
+    /// their DID documents on every call to [`AsyncStore::verify_vid`]
+    pub fn set_resolver(&self, resolver: VidResolver) {
+        self.change_settings(|settings| settings.resolver = Some(Arc::new(resolver)));
+    }
+
+    /// Resolve VIDs of the DID method `method`, e.g. "jwk" for did:jwk, with `resolver`
+    /// in [`AsyncStore::verify_vid`]; this takes precedence over the built-in methods
+    pub fn register_did_method(
+        &self,
+        method: impl Into<String>,
+        resolver: impl DidMethodResolver + 'static,
+    ) {
+        self.change_settings(|settings| {
+            Arc::make_mut(&mut settings.did_methods).register(method, resolver);
+        });
+    }
+
+    /// Replace all application provided DID method resolvers with `registry`
+    pub fn set_did_methods(&self, registry: DidMethodRegistry) {
+        self.change_settings(|settings| settings.did_methods = Arc::new(registry));
+    }
+
+    /// Write changes to nested relationships to `vault` as soon as they are made: before a
+    /// nested relationship request or accept is sent, and before an accept received through
+    /// [`AsyncStore::receive`] is yielded. The pending requests are then known again after
+    /// a restart, and can still be matched with their accept; see also
+    /// [`AsyncStore::expire_nested_requests`]
+    pub fn set_vault(&self, vault: Arc<Vault>) {
+        self.change_settings(|settings| settings.vault = Some(vault));
+    }
+
+    /// Write the VIDs `vids` to the vault set with [`AsyncStore::set_vault`], if any;
+    /// all of them are written in a single transaction
+    async fn persist_vids(&self, vids: &[&str]) -> Result<(), Error> {
+        let vault = self.settings().vault.clone();
+
+        match vault {
+            Some(vault) => vault.persist(self.inner.export_vids(vids)?, None).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Write the VIDs that changed when the nested VID `nested_vid` of `sender` accepted
+    /// our nested relationship request
+    async fn persist_nested_accept(&self, sender: &str, nested_vid: &str) -> Result<(), Error> {
+        let context = self.inner.get_vid(nested_vid)?;
+        let mut vids = vec![sender, nested_vid];
+        vids.extend(context.get_relation_vid());
+
+        self.persist_vids(&vids).await
+    }
+
     /// Export the database to serializable default types
     pub fn export(&self) -> Result<Vec<ExportVid>, Error> {
         self.inner.export()
     }
 
+    /// Take the VIDs that changed since the previous call; see [`Store::take_changes`]
+    pub fn take_changes(&self) -> StoreChanges {
+        self.inner.take_changes()
+    }
+
+    /// Export the database as a password protected backup
+    pub fn export_encrypted(&self, password: &str) -> Result<Vec<u8>, Error> {
+        self.inner.export_encrypted(password)
+    }
+
+    /// Import a backup created by [`AsyncStore::export_encrypted`]
+    pub fn import_encrypted(&self, backup: &[u8], password: &str) -> Result<(), Error> {
+        self.inner.import_encrypted(backup, password)
+    }
+
+    /// Encode the VID identified by `vid` as a compact string, e.g. for a QR code
+    pub fn compact_vid(&self, vid: &str) -> Result<String, Error> {
+        self.inner.compact_vid(vid)
+    }
+
     /// Expose the inner non-async database
     pub fn as_store(&self) -> &Store {
         &self.inner
     }
 
+    /// Get the store of the tenant `name`, with its own VIDs; see [`Store::tenant`]
+    ///
+    /// The tenant store sends and receives messages with the transport settings of this store.
+    pub fn tenant(&self, name: &str) -> AsyncStore {
+        AsyncStore {
+            inner: self.inner.tenant(name),
+            ..self.clone()
+        }
+    }
+
+    /// List the names of the tenants of this store
+    pub fn tenants(&self) -> Vec<String> {
+        self.inner.tenants()
+    }
+
+    /// Remove the tenant `name` and all of its VIDs; returns whether the tenant existed
+    pub fn remove_tenant(&self, name: &str) -> bool {
+        self.inner.remove_tenant(name)
+    }
+
     /// Import the database from serializable default types
     pub fn import(&self, vids: Vec<ExportVid>) -> Result<(), Error> {
         self.inner.import(vids)
     }
 
+    /// List the ancestors of a nested VID, starting with its parent
+    pub fn parent_chain(&self, vid: &str) -> Result<Vec<String>, Error> {
+        self.inner.parent_chain(vid)
+    }
+
     /// Adds a relation to an already existing vid, making it a nested Vid
     pub fn set_relation_for_vid(&self, vid: &str, relation_vid: Option<&str>) -> Result<(), Error> {
         self.inner.set_relation_for_vid(vid, relation_vid)
@@ -92,11 +484,105 @@ impl AsyncStore {
         self.inner.forget_vid(vid)
     }
 
+    /// Remove the nested VIDs, routes and relation VIDs set up for the relationship with
+    /// `peer`; with `dry_run`, only report what would be removed.
+    /// See [`Store::cleanup_relationship`]
+    pub fn cleanup_relationship(
+        &self,
+        peer: &str,
+        dry_run: bool,
+    ) -> Result<RelationshipCleanup, Error> {
+        self.inner.cleanup_relationship(peer, dry_run)
+    }
+
     /// Add the already resolved `verified_vid` to the database as a relationship
     pub fn add_verified_vid(&self, verified_vid: impl VerifiedVid + 'static) -> Result<(), Error> {
         self.inner.add_verified_vid(verified_vid)
     }
 
+    /// Get the application defined metadata of the VID identified by `vid`
+    pub fn get_metadata(&self, vid: &str) -> Result<Option<serde_json::Value>, Error> {
+        self.inner.get_metadata(vid)
+    }
+
+    /// Replace the application defined metadata of the VID identified by `vid`
+    pub fn set_metadata(
+        &self,
+        vid: &str,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        self.inner.set_metadata(vid, metadata)
+    }
+
+    /// Modify the application defined metadata of the VID identified by `vid` in place
+    pub fn update_metadata<T>(
+        &self,
+        vid: &str,
+        change: impl FnOnce(&mut Option<serde_json::Value>) -> T,
+    ) -> Result<T, Error> {
+        self.inner.update_metadata(vid, change)
+    }
+
+    /// Get what was learned about the VID identified by `vid` when it was resolved,
+    /// e.g. the services and other identifiers listed in its DID document
+    pub fn get_vid_metadata(&self, vid: &str) -> Result<Option<VidMetadata>, Error> {
+        self.inner.get_vid_metadata(vid)
+    }
+
+    /// Send the messages for `vid` to `transport` instead of the endpoint of the VID;
+    /// `None` restores the endpoint of the VID. See [`Store::set_transport_override`]
+    pub fn set_transport_override(&self, vid: &str, transport: Option<Url>) -> Result<(), Error> {
+        self.inner.set_transport_override(vid, transport)
+    }
+
+    /// Get the status of our relationship with the VID identified by `vid`
+    pub fn get_relation_status(&self, vid: &str) -> Result<RelationshipStatus, Error> {
+        self.inner.get_relation_status(vid)
+    }
+
+    /// Get how the relationship with the VID identified by `vid` was used: the number of
+    /// messages sent and received, when the last message arrived and the last send error
+    pub fn get_vid_stats(&self, vid: &str) -> Result<VidStats, Error> {
+        self.inner.get_vid_stats(vid)
+    }
+
+    /// Summarize how the relationship with every VID in the database was used, by VID;
+    /// see [`AsyncStore::get_vid_stats`]
+    pub fn wallet_summary(&self) -> BTreeMap<String, VidStats> {
+        self.inner.wallet_summary()
+    }
+
+    /// The VIDs in the database and how they relate, see [`Store::relationship_graph`]
+    pub fn relationship_graph(&self) -> RelationshipGraph {
+        self.inner.relationship_graph()
+    }
+
+    /// Get how the VID identified by `vid` entered the database, see [`Store::provenance`]
+    pub fn provenance(&self, vid: &str) -> Result<Option<Provenance>, Error> {
+        self.inner.provenance(vid)
+    }
+
+    /// The chain of VIDs that introduced the VID identified by `vid`, nearest first; see
+    /// [`Store::introducers`]
+    pub fn introducers(&self, vid: &str) -> Result<Vec<String>, Error> {
+        self.inner.introducers(vid)
+    }
+
+    /// All VIDs that were introduced by `vid`, directly or transitively; see
+    /// [`Store::introduced_by`]
+    pub fn introduced_by(&self, vid: &str) -> Vec<String> {
+        self.inner.introduced_by(vid)
+    }
+
+    /// List the VIDs whose metadata is a JSON object with `key` set to `value`
+    pub fn find_vids_by_metadata(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<Vec<String>, Error> {
+        self.inner.find_vids_by_metadata(key, value)
+    }
+
     /// Check whether the [PrivateVid] identified by `vid` exists inthe database
     pub fn has_private_vid(&self, vid: &str) -> Result<bool, Error> {
         self.inner.has_private_vid(vid)
@@ -104,13 +590,149 @@ impl AsyncStore {
 
     /// Resolve and verify public key material for a VID identified by `vid` and add it to the database as a relationship
     pub async fn verify_vid(&mut self, vid: &str) -> Result<(), Error> {
-        let verified_vid = crate::vid::verify_vid(vid).await?;
+        self.resolve_and_add(vid, VidOrigin::Resolved).await
+    }
+
+    /// Resolve and verify many VIDs concurrently, e.g. when importing a contact list,
+    /// with at most `max_concurrent` resolutions in flight.
+    /// Returns the result for every VID, in the same order as `vids`
+    pub async fn verify_vids(
+        &self,
+        vids: &[&str],
+        max_concurrent: usize,
+    ) -> Vec<Result<(), Error>> {
+        futures::stream::iter(vids)
+            .map(|vid| self.resolve_and_add(vid, VidOrigin::Resolved))
+            .buffered(max_concurrent.max(1))
+            .collect()
+            .await
+    }
+
+    /// Import the counterparties listed in a trust registry, read from `registry`; see
+    /// [the registry format](crate::contacts). Every DID is resolved and verified, its keys
+    /// are checked against the expected fingerprints, and only then it is added to the
+    /// store with the metadata of its entry. Aliases are left to the application.
+    ///
+    /// Returns the result for every entry, in the order of the registry; fails only if the
+    /// registry cannot be read
+    pub async fn import_contacts(
+        &self,
+        mut registry: impl std::io::Read,
+    ) -> Result<Vec<ContactImport>, Error> {
+        let mut data = String::new();
+        registry.read_to_string(&mut data)?;
+
+        let imports = futures::stream::iter(crate::contacts::parse_registry(&data)?)
+            .map(|contact| async move {
+                let result = self.import_contact(&contact).await;
+
+                ContactImport { contact, result }
+            })
+            .buffered(8)
+            .collect()
+            .await;
+
+        Ok(imports)
+    }
+
+    async fn import_contact(&self, contact: &Contact) -> Result<(), Error> {
+        let (verified_vid, metadata) = self.resolve(&contact.did, VidOrigin::Resolved).await?;
+        crate::contacts::check_fingerprints(&verified_vid, &contact.fingerprints)?;
 
         self.inner.add_verified_vid(verified_vid)?;
+        self.inner.set_vid_metadata(&contact.did, metadata)?;
+        self.inner
+            .set_provenance(&contact.did, Provenance::Imported)?;
+
+        if let Some(metadata) = &contact.metadata {
+            self.inner
+                .set_metadata(&contact.did, Some(metadata.clone()))?;
+        }
 
         Ok(())
     }
 
+    async fn resolve_and_add(&self, vid: &str, origin: VidOrigin<'_>) -> Result<(), Error> {
+        let (verified_vid, metadata) = self.resolve(vid, origin).await?;
+
+        self.inner.add_verified_vid(verified_vid)?;
+        self.inner.set_vid_metadata(vid, metadata)?;
+        self.inner.set_provenance(vid, origin.into())?;
+
+        Ok(())
+    }
+
+    /// Resolve and verify `vid`, if the verification policy accepts it
+    async fn resolve(&self, vid: &str, origin: VidOrigin<'_>) -> Result<(Vid, VidMetadata), Error> {
+        self.inner.check_policy(vid, origin)?;
+
+        let (did_methods, resolver) = {
+            let settings = self.settings();
+            (settings.did_methods.clone(), settings.resolver.clone())
+        };
+
+        let (verified_vid, mut metadata) = match did_methods.resolve(vid).await {
+            Some(result) => (result?, VidMetadata::default()),
+            None => match resolver {
+                Some(resolver) => resolver.resolve_with_metadata(vid).await?,
+                None => crate::vid::verify_vid_with_metadata(vid).await?,
+            },
+        };
+
+        metadata.resolved_at = metadata.resolved_at.or_else(|| Some(resolver::now()));
+        if let VidOrigin::Referral { sender } = origin {
+            metadata.referred_by = Some(sender.to_string());
+        }
+
+        Ok((verified_vid, metadata))
+    }
+
+    /// Resolve `vid` again, bypassing any cache, and report whether its keys differ from
+    /// the version in this store. Changed keys are not trusted until the caller adds the
+    /// new VID with [`AsyncStore::add_verified_vid`]
+    pub async fn refresh_vid(&self, vid: &str) -> Result<VidRefresh, Error> {
+        let (did_methods, resolver) = {
+            let settings = self.settings();
+            (settings.did_methods.clone(), settings.resolver.clone())
+        };
+
+        let current = match did_methods.resolve(vid).await {
+            Some(result) => result?,
+            None => match resolver {
+                Some(resolver) => resolver.refresh_vid(vid).await?.vid().clone(),
+                None => crate::vid::verify_vid(vid).await?,
+            },
+        };
+
+        let previous = self.inner.get_verified_vid(vid).ok();
+
+        Ok(VidRefresh::compare(previous.as_deref(), current))
+    }
+
+    /// Check whether the endpoints of `vid`, including its alternative endpoints, can be
+    /// reached, without sending a message; every probe is bounded by the timeout of the
+    /// [delivery configuration](AsyncStore::set_delivery_config)
+    pub async fn probe_endpoint(&self, vid: &str) -> Result<Vec<EndpointProbe>, Error> {
+        let vid = self.inner.get_verified_vid(vid)?;
+        let timeout = self.settings().delivery_config.timeout;
+
+        let probes = std::iter::once(vid.endpoint())
+            .chain(vid.alternative_endpoints())
+            .map(|endpoint| crate::transport::probe_endpoint(endpoint, timeout));
+
+        Ok(futures::future::join_all(probes).await)
+    }
+
+    /// Probe the endpoints of every VID in the store at once, see
+    /// [`AsyncStore::probe_endpoint`]
+    pub async fn probe_all(&self) -> Result<BTreeMap<String, Vec<EndpointProbe>>, Error> {
+        let vids = self.inner.list_vids()?;
+        let probes = vids.iter().map(|vid| self.probe_endpoint(vid));
+        let probes = futures::future::try_join_all(probes).await?;
+
+        Ok(vids.into_iter().zip(probes).collect())
+    }
+
     /// Send a TSP message given earlier resolved VIDs
     /// Encodes, encrypts, signs and sends a TSP message
     ///
@@ -144,15 +766,517 @@ impl AsyncStore {
         sender: &str,
         receiver: &str,
         nonconfidential_data: Option<&[u8]>,
-        message: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error> {
+        self.send_with_priority(
+            sender,
+            receiver,
+            nonconfidential_data,
+            message,
+            Priority::Interactive,
+        )
+        .await
+    }
+
+    /// Send a TSP message like [`AsyncStore::send`], with the `priority` it gets when the
+    /// endpoint of the receiver is busy; see [`DeliveryConfig::max_in_flight`]
+    ///
+    /// Control messages, e.g. relationship accepts and cancels, are sent with
+    /// [`Priority::Control`], so they go ahead of [`Priority::Bulk`] transfers.
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+        priority = ?priority,
+    ))]
+    pub async fn send_with_priority(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+        priority: Priority,
+    ) -> Result<(), Error> {
+        self.bootstrap_relationship(sender, receiver).await?;
+        self.renew_session(sender, receiver).await?;
+
+        let (endpoint, message) =
+            self.inner
+                .seal_message(sender, receiver, nonconfidential_data, message)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, priority).await?;
+
+        Ok(())
+    }
+
+    /// Sign and send an unencrypted TSP message to `receiver`; see [`Store::sign_message`]
+    ///
+    /// The receiver opens the message like any other, the [`MessageType`](crate::definitions::MessageType)
+    /// of the received message tells it was only signed.
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn send_signed(
+        &self,
+        sender: &str,
+        receiver: &str,
+        message: &[u8],
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self.inner.sign_message(sender, receiver, message)?;
+
+        tracing::info!("sending signed message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Interactive)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Send a TSP message over the route to `receiver`, with a nonconfidential annotation
+    /// for each intermediary; see [`Store::seal_message_with_annotations`]
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn send_with_annotations(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        annotations: &[&[u8]],
+        message: &[u8],
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self.inner.seal_message_with_annotations(
+            sender,
+            receiver,
+            nonconfidential_data,
+            annotations,
+            message,
+        )?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Interactive)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Send a TSP message that replies to the earlier message with digest `in_reply_to`
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn send_reply(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        in_reply_to: &Digest,
+        message: &[u8],
+    ) -> Result<(), Error> {
+        self.renew_session(sender, receiver).await?;
+
+        let (endpoint, message) =
+            self.inner
+                .seal_reply(sender, receiver, nonconfidential_data, in_reply_to, message)?;
+
+        tracing::info!("sending reply to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Interactive)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Send a TSP message like [`AsyncStore::send`], and return its digest; the
+    /// [`ReceivedTspMessage::Acknowledgement`] for this message carries the same digest
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn send_and_hash(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Digest, Error> {
+        self.renew_session(sender, receiver).await?;
+
+        let mut digest = Default::default();
+        let (endpoint, message) = self.inner.seal_message_payload_and_hash(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::Content(message),
+            Some(&mut digest),
+        )?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Interactive)
+            .await?;
+
+        Ok(digest)
+    }
+
+    /// Tell `receiver` that the message with `digest` arrived at `sender`.
+    /// Encodes the acknowledgement, encrypts, signs and sends a TSP message
+    pub async fn send_ack(
+        &self,
+        sender: &str,
+        receiver: &str,
+        digest: &Digest,
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self.inner.make_ack(sender, receiver, digest)?;
+
+        tracing::info!("sending acknowledgement to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Acknowledge a received message in the background; failures are only logged
+    fn spawn_ack(&self, sender: &str, receiver: &str, digest: Digest) {
+        let db = self.clone();
+        let (sender, receiver) = (sender.to_string(), receiver.to_string());
+
+        tokio::spawn(async move {
+            if let Err(e) = db.send_ack(&sender, &receiver, &digest).await {
+                tracing::warn!("could not acknowledge a message from {receiver}: {e}");
+            }
+        });
+    }
+
+    /// Send a TSP message and wait for the reply to it, returning the reply's payload
+    ///
+    /// The reply is recognized by its `in_reply_to` digest (see [`AsyncStore::send_reply`])
+    /// and taken out of the stream returned by [`AsyncStore::receive`] for `sender`; that
+    /// stream must be polled while waiting, otherwise the call fails after `timeout`.
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn call(
+        &self,
+        sender: &str,
+        receiver: &str,
+        message: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Error> {
+        self.call_with_data(sender, receiver, None, message, timeout)
+            .await
+    }
+
+    /// Send a TSP message with `nonconfidential_data` and wait for the reply to it
+    async fn call_with_data(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Error> {
+        let mut digest = Default::default();
+        let (endpoint, message) = self.inner.seal_message_payload_and_hash(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::Content(message),
+            Some(&mut digest),
+        )?;
+
+        let (reply_sender, reply) = oneshot::channel();
+        self.pending_replies
+            .insert(digest, (receiver.to_string(), reply_sender));
+
+        tracing::info!("sending request to {endpoint}");
+
+        let result = match self
+            .send_to(&endpoint, &message, Priority::Interactive)
+            .await
+        {
+            Ok(()) => match tokio::time::timeout(timeout, reply).await {
+                Ok(Ok(reply)) => Ok(reply),
+                _ => Err(Error::ReplyTimeout(receiver.to_string(), timeout)),
+            },
+            Err(e) => Err(e),
+        };
+
+        self.pending_replies.remove(&digest);
+
+        result
+    }
+
+    /// Request a relationship with `receiver` and wait until it is accepted, if a
+    /// [relationship bootstrap](AsyncStore::set_relationship_bootstrap) is set and the
+    /// receiver is unrelated; a request that is already outstanding is awaited as well
+    async fn bootstrap_relationship(&self, sender: &str, receiver: &str) -> Result<(), Error> {
+        let Some(bootstrap) = self.settings().bootstrap.clone() else {
+            return Ok(());
+        };
+
+        let report = |status| (bootstrap.on_status)(sender, receiver, status);
+        let deadline = tokio::time::Instant::now() + bootstrap.timeout;
+        let mut waiting = false;
+
+        loop {
+            // listen before checking, so an accept that arrives in between is not missed
+            let changed = self.relationship_changed.notified();
+
+            match self.inner.get_relation_status(receiver)? {
+                RelationshipStatus::Unrelated if waiting => {
+                    report(BootstrapStatus::Rejected);
+
+                    return Err(Error::Relationship(format!(
+                        "{receiver} did not accept the relationship request"
+                    )));
+                }
+                RelationshipStatus::Unrelated => {
+                    self.send_relationship_request(sender, receiver, None)
+                        .await?;
+                    report(BootstrapStatus::Requested);
+                }
+                RelationshipStatus::Unidirectional { .. } => {}
+                _ => {
+                    if waiting {
+                        report(BootstrapStatus::Accepted);
+                    }
+
+                    return Ok(());
+                }
+            }
+
+            waiting = true;
+
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                report(BootstrapStatus::TimedOut);
+
+                return Err(Error::ReplyTimeout(receiver.to_string(), bootstrap.timeout));
+            }
+        }
+    }
+
+    /// Measure the round trip time to `receiver`, through its route if it has one
+    ///
+    /// The ping is a generic message with the [`PING`](content_type::PING) content type,
+    /// which a store answers if [`AsyncStore::set_answer_pings`] is enabled. Like for
+    /// [`AsyncStore::call`], the stream returned by [`AsyncStore::receive`] for `sender`
+    /// must be polled while waiting for the answer.
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn ping(
+        &self,
+        sender: &str,
+        receiver: &str,
+        timeout: Duration,
+    ) -> Result<Pong, Error> {
+        let mut ping = [0; PING_SIZE];
+        rand::thread_rng().fill_bytes(&mut ping[..PING_NONCE_SIZE]);
+        ping[PING_NONCE_SIZE..].copy_from_slice(&now_millis().to_be_bytes());
+
+        let headers = MessageHeaders::new()
+            .with_content_type(content_type::PING)
+            .to_bytes()?;
+
+        let started = Instant::now();
+        let pong = self
+            .call_with_data(sender, receiver, Some(&headers), &ping, timeout)
+            .await?;
+        let round_trip = started.elapsed();
+
+        // the answer repeats the ping, followed by the time it was answered
+        match pong.strip_prefix(ping.as_slice()) {
+            Some(answered_at) => Ok(Pong {
+                round_trip,
+                answered_at: u64::from_be_bytes(
+                    answered_at
+                        .try_into()
+                        .map_err(|_| Error::InvalidPong(receiver.to_string()))?,
+                ),
+            }),
+            None => Err(Error::InvalidPong(receiver.to_string())),
+        }
+    }
+
+    /// Answer a ping in the background; failures are only logged
+    fn spawn_pong(&self, sender: &str, receiver: &str, digest: Digest, ping: &[u8]) {
+        if ping.len() != PING_SIZE {
+            tracing::debug!("ignoring a malformed ping from {receiver}");
+            return;
+        }
+
+        let db = self.clone();
+        let (sender, receiver) = (sender.to_string(), receiver.to_string());
+        let mut pong = ping.to_vec();
+        pong.extend(now_millis().to_be_bytes());
+
+        tokio::spawn(async move {
+            let headers = match MessageHeaders::new()
+                .with_content_type(content_type::PONG)
+                .to_bytes()
+            {
+                Ok(headers) => headers,
+                Err(e) => {
+                    tracing::warn!("could not answer a ping from {receiver}: {e}");
+                    return;
+                }
+            };
+
+            if let Err(e) = db
+                .send_reply(&sender, &receiver, Some(&headers), &digest, &pong)
+                .await
+            {
+                tracing::warn!("could not answer a ping from {receiver}: {e}");
+            }
+        });
+    }
+
+    /// Tell `receiver` in the background that its message was refused for lack of a
+    /// relationship; failures are only logged
+    fn spawn_relationship_notice(&self, sender: &str, receiver: &str) {
+        let db = self.clone();
+        let (sender, receiver) = (sender.to_string(), receiver.to_string());
+
+        tokio::spawn(async move {
+            let notice = MessageHeaders::new()
+                .with_content_type(content_type::RELATIONSHIP_REQUIRED)
+                .to_bytes()
+                .map_err(Error::from)
+                .and_then(|headers| {
+                    db.inner
+                        .seal_message(&sender, &receiver, Some(&headers), &[])
+                });
+
+            let result = match notice {
+                Ok((endpoint, notice)) => db.send_to(&endpoint, &notice, Priority::Control).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                tracing::warn!("could not tell {receiver} a relationship is required: {e}");
+            }
+        });
+    }
+
+    /// Send a TSP message consisting of multiple (content type, data) segments,
+    /// e.g. a JSON control object plus a binary attachment
+    #[tracing::instrument(skip_all, fields(
+        sender = %crate::telemetry::fingerprint(sender),
+        receiver = %crate::telemetry::fingerprint(receiver),
+    ))]
+    pub async fn send_multipart(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        segments: &[(&str, &[u8])],
     ) -> Result<(), Error> {
         let (endpoint, message) =
             self.inner
-                .seal_message(sender, receiver, nonconfidential_data, message)?;
+                .seal_message_multipart(sender, receiver, nonconfidential_data, segments)?;
 
-        tracing::info!("sending message to {endpoint}");
+        tracing::info!("sending multipart message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Interactive)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Create a group named `name`, whose messages are sent by the private VID `owner`
+    pub fn create_group(&self, name: &str, owner: &str) -> Result<(), Error> {
+        self.inner.create_group(name, owner)
+    }
+
+    /// Add `member` to a group and notify all members of the change
+    pub async fn add_group_member(&self, name: &str, member: &str) -> Result<(), Error> {
+        let notices = self.inner.add_group_member(name, member)?;
+
+        self.send_all(notices, Priority::Control).await
+    }
+
+    /// Remove `member` from a group and notify the remaining and the removed member
+    pub async fn remove_group_member(&self, name: &str, member: &str) -> Result<(), Error> {
+        let notices = self.inner.remove_group_member(name, member)?;
+
+        self.send_all(notices, Priority::Control).await
+    }
+
+    /// Send a TSP message to every member of a group
+    pub async fn send_to_group(
+        &self,
+        name: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<(), Error> {
+        let messages = self
+            .inner
+            .seal_group_message(name, nonconfidential_data, message)?;
+
+        tracing::info!("sending group message to {} members", messages.len());
+
+        self.send_all(messages, Priority::Interactive).await
+    }
+
+    async fn send_all(
+        &self,
+        messages: Vec<(Url, Vec<u8>)>,
+        priority: Priority,
+    ) -> Result<(), Error> {
+        for (endpoint, message) in messages {
+            self.send_to(&endpoint, &message, priority).await?;
+        }
+
+        Ok(())
+    }
 
-        crate::transport::send_message(&endpoint, &message).await?;
+    /// Send a message to `endpoint`, falling back to the alternative endpoints of its VID;
+    /// on a busy endpoint, messages with a higher `priority` are sent first
+    async fn send_to(
+        &self,
+        endpoint: &Url,
+        message: &[u8],
+        priority: Priority,
+    ) -> Result<(), Error> {
+        let alternatives = self.inner.alternative_endpoints(endpoint);
+        let (delivery_config, transport_preference) = {
+            let settings = self.settings();
+            (
+                settings.delivery_config.clone(),
+                settings.transport_preference.clone(),
+            )
+        };
+
+        let _slot = self
+            .send_queue
+            .acquire(endpoint, priority, delivery_config.max_in_flight)
+            .await;
+
+        let result = self
+            .circuits
+            .deliver(endpoint, &delivery_config, || {
+                crate::transport::send_message_with_fallback(
+                    endpoint,
+                    &alternatives,
+                    &transport_preference,
+                    message,
+                )
+            })
+            .await;
+
+        if let Err(e) = result {
+            let e: Error = e.into();
+            self.inner.record_send_error(endpoint, &e);
+
+            return Err(e);
+        }
 
         Ok(())
     }
@@ -195,7 +1319,27 @@ impl AsyncStore {
 
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Request a relationship like [`AsyncStore::send_relationship_request`], for a relationship
+    /// that ends by itself `ttl` from now unless it is renewed with [`AsyncStore::send_relationship_renew`]
+    pub async fn send_relationship_request_with_ttl(
+        &self,
+        sender: &str,
+        receiver: &str,
+        route: Option<&[&str]>,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self
+            .inner
+            .make_relationship_request_with_ttl(sender, receiver, route, ttl)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
 
         Ok(())
     }
@@ -216,7 +1360,97 @@ impl AsyncStore {
 
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Reject a direct relationship between the resolved VIDs identifier by `sender` and `receiver`.
+    /// `thread_id` must be the same as the one that was present in the relationship request.
+    /// Encodes the control message, encrypts, signs and sends a TSP message
+    pub async fn send_relationship_reject(
+        &self,
+        sender: &str,
+        receiver: &str,
+        thread_id: Digest,
+        reason: Option<&str>,
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self
+            .inner
+            .make_relationship_reject(sender, receiver, thread_id, reason)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Extend the direct relationship between the resolved `sender` and `receiver` VIDs
+    /// until `ttl` from now; the receiver applies the same expiry to its side.
+    /// Encodes the control message, encrypts, signs and sends a TSP message
+    pub async fn send_relationship_renew(
+        &self,
+        sender: &str,
+        receiver: &str,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self.inner.make_relationship_renew(sender, receiver, ttl)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// End all relationships that have expired; returns the VIDs whose relationship ended
+    pub fn expire_relationships(&self) -> Result<Vec<String>, Error> {
+        self.inner.expire_relationships()
+    }
+
+    /// Forget the one-shot VIDs that expired, see [`OwnedVid::new_ephemeral`]; returns the
+    /// forgotten VIDs
+    pub fn forget_expired_vids(&self) -> Result<Vec<String>, Error> {
+        self.inner.forget_expired_vids()
+    }
+
+    /// Forget the nested relationship requests that were sent more than `max_age` seconds
+    /// ago and were not accepted, see [`Store::expire_nested_requests`]; the changed VIDs
+    /// are written to the vault, if one is set
+    pub async fn expire_nested_requests(
+        &self,
+        max_age: u64,
+    ) -> Result<Vec<(String, Vec<Digest>)>, Error> {
+        let expired = self.inner.expire_nested_requests(max_age);
+        let vids = expired
+            .iter()
+            .map(|(vid, _)| vid.as_str())
+            .collect::<Vec<_>>();
+        self.persist_vids(&vids).await?;
+
+        Ok(expired)
+    }
+
+    /// Establish a session for the messages from `sender` to `receiver`, see
+    /// [`Store::make_session_key`]; once the session runs out, [`AsyncStore::send`] and
+    /// the other methods that send generic messages establish a new one automatically
+    pub async fn send_session_key(&self, sender: &str, receiver: &str) -> Result<(), Error> {
+        let (endpoint, message) = self.inner.make_session_key(sender, receiver)?;
+
+        tracing::info!("sending session key to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Replace the session from `sender` to `receiver` if it has run out
+    async fn renew_session(&self, sender: &str, receiver: &str) -> Result<(), Error> {
+        if self.inner.needs_new_session(sender, receiver) {
+            tracing::debug!("renewing the session with {receiver}");
+            self.send_session_key(sender, receiver).await?;
+        }
 
         Ok(())
     }
@@ -232,7 +1466,26 @@ impl AsyncStore {
 
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Cancels a nested relationship between the `nested_sender` and `nested_receiver` VIDs.
+    /// The control message is sent between their parents, after which both nested VIDs are
+    /// removed from the store.
+    pub async fn send_nested_relationship_cancel(
+        &self,
+        nested_sender: &str,
+        nested_receiver: &str,
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self
+            .inner
+            .make_nested_relationship_cancel(nested_sender, nested_receiver)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
 
         Ok(())
     }
@@ -250,7 +1503,7 @@ impl AsyncStore {
 
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
 
         Ok(())
     }
@@ -268,11 +1521,72 @@ impl AsyncStore {
 
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Send a control message of the application defined type `typecode` to `receiver`,
+    /// encoded by the extension registered with [`AsyncStore::register_control_extension`]
+    pub async fn send_extension_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        typecode: u8,
+        message: &serde_json::Value,
+    ) -> Result<(), Error> {
+        let (endpoint, message) = self
+            .inner
+            .make_extension_message(sender, receiver, typecode, message)?;
+
+        tracing::info!("sending message to {endpoint}");
+
+        self.send_to(&endpoint, &message, Priority::Control).await?;
+
+        Ok(())
+    }
+
+    /// Accept a [ReceivedTspMessage::Referral]: resolve and verify the referred VID, and add it
+    /// to the database, recording who referred it in its [VidMetadata::referred_by]
+    ///
+    /// With `request_from`, a relationship with the referred VID is requested from that
+    /// private VID; see [`AsyncStore::send_relationship_request`]
+    pub async fn accept_referral(
+        &self,
+        referral: &ReceivedTspMessage,
+        request_from: Option<&str>,
+    ) -> Result<(), Error> {
+        let ReceivedTspMessage::Referral {
+            sender,
+            referred_vid,
+        } = referral
+        else {
+            return Err(Error::Relationship("not a referral".into()));
+        };
+
+        self.resolve_and_add(referred_vid, VidOrigin::Referral { sender })
+            .await?;
+
+        if let Some(request_from) = request_from {
+            self.send_relationship_request(request_from, referred_vid, None)
+                .await?;
+        }
 
         Ok(())
     }
 
+    /// Accept a [ReceivedTspMessage::NewIdentifier]: resolve and verify the new VID, and add
+    /// it to the database, recording who announced it in its [`Provenance`]. The
+    /// relationship with the sender is not moved to the new VID
+    pub async fn accept_new_identifier(&self, message: &ReceivedTspMessage) -> Result<(), Error> {
+        let ReceivedTspMessage::NewIdentifier { sender, new_vid } = message else {
+            return Err(Error::Relationship("not a new identifier".into()));
+        };
+
+        self.resolve_and_add(new_vid, VidOrigin::NewIdentifier { sender })
+            .await
+    }
+
     /// Send a nested relationship request to `receiver`, creating a new nested vid with `outer_sender` as a parent.
     pub async fn send_nested_relationship_request(
         &self,
@@ -283,9 +1597,11 @@ impl AsyncStore {
             .inner
             .make_nested_relationship_request(parent_sender, receiver)?;
 
+        self.persist_vids(&[receiver, vid.identifier()]).await?;
+
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
 
         Ok(vid)
     }
@@ -306,9 +1622,12 @@ impl AsyncStore {
             thread_id,
         )?;
 
+        self.persist_vids(&[nested_receiver, vid.identifier()])
+            .await?;
+
         tracing::info!("sending message to {endpoint}");
 
-        crate::transport::send_message(&endpoint, &message).await?;
+        self.send_to(&endpoint, &message, Priority::Control).await?;
 
         Ok(vid)
     }
@@ -324,7 +1643,8 @@ impl AsyncStore {
     ) -> Result<Url, Error> {
         let (transport, message) = self.inner.route_message(sender, receiver, message)?;
 
-        crate::transport::send_message(&transport, &message).await?;
+        self.send_to(&transport, &message, Priority::Interactive)
+            .await?;
 
         Ok(transport)
     }
@@ -343,7 +1663,30 @@ impl AsyncStore {
             opaque_message,
         )?;
 
-        crate::transport::send_message(&transport, &message).await?;
+        self.send_to(&transport, &message, Priority::Interactive)
+            .await?;
+
+        Ok(transport)
+    }
+
+    /// Like [`AsyncStore::forward_routed_message`], passing along the `annotations` for the
+    /// remaining hops, as received in [`ReceivedTspMessage::ForwardRequest`]
+    pub async fn forward_annotated_routed_message(
+        &self,
+        next_hop: &str,
+        path: Vec<impl AsRef<[u8]>>,
+        annotations: Vec<impl AsRef<[u8]>>,
+        opaque_message: &[u8],
+    ) -> Result<Url, Error> {
+        let (transport, message) = self.inner.forward_annotated_routed_message(
+            next_hop,
+            path.iter().map(|x| x.as_ref()).collect(),
+            annotations.iter().map(|x| x.as_ref()).collect(),
+            opaque_message,
+        )?;
+
+        self.send_to(&transport, &message, Priority::Interactive)
+            .await?;
 
         Ok(transport)
     }
@@ -353,35 +1696,376 @@ impl AsyncStore {
     pub fn open_message<'a>(
         &self,
         message: &'a mut [u8],
-    ) -> Result<ReceivedTspMessage<&'a [u8]>, Error> {
+    ) -> Result<ReceivedTspMessage<Cow<'a, [u8]>>, Error> {
         self.inner.open_message(message)
     }
 
     /// Receive TSP messages for the private VID identified by `vid`, using the appropriate transport mechanism for it.
     /// Messages will be queued in a channel
     /// The returned channel contains a maximum of 16 messages
+    ///
+    /// A nested VID with a `tsp://` placeholder endpoint receives on the endpoints of the
+    /// nearest parent that has actual ones, so the stream also yields the messages for
+    /// that parent, and the parent cannot be received on at the same time
     pub async fn receive(&self, vid: &str) -> Result<TSPStream<ReceivedTspMessage, Error>, Error> {
-        let receiver = self.inner.get_private_vid(vid)?;
-        let messages = crate::transport::receive_messages(receiver.endpoint()).await?;
+        self.receive_until(vid, CancellationToken::new()).await
+    }
+
+    /// Receive TSP messages like [`AsyncStore::receive`], with a [`ReceiveHandle`] to
+    /// end the stream without losing messages that already arrived
+    pub async fn receive_with_handle(
+        &self,
+        vid: &str,
+    ) -> Result<(TSPStream<ReceivedTspMessage, Error>, ReceiveHandle), Error> {
+        let token = CancellationToken::new();
+        let messages = self.receive_until(vid, token.clone()).await?;
 
+        Ok((messages, ReceiveHandle { token }))
+    }
+
+    /// Receive TSP messages like [`AsyncStore::receive`] until `token` is cancelled
+    ///
+    /// Once cancelled, the messages the transport already received are still opened and
+    /// yielded, after which the stream ends.
+    #[tracing::instrument(skip_all, fields(vid = %crate::telemetry::fingerprint(vid)))]
+    pub async fn receive_until(
+        &self,
+        vid: &str,
+        token: CancellationToken,
+    ) -> Result<TSPStream<ReceivedTspMessage, Error>, Error> {
+        self.inner.get_private_vid(vid)?;
+
+        // listen on every endpoint the VID can be reached at; a nested VID is reached at
+        // the endpoints of its parent
+        let receiver = self
+            .inner
+            .get_verified_vid(&self.inner.transport_vid(vid)?)?;
+
+        let config = self.settings().transport_config.clone();
+        let mut streams = Vec::new();
+        for endpoint in std::iter::once(receiver.endpoint()).chain(receiver.alternative_endpoints())
+        {
+            streams.push(crate::transport::receive_messages_with_config(endpoint, &config).await?);
+        }
+        let messages = Self::drain_until(futures::stream::select_all(streams), token);
+
+        Ok(self.open_received(vid, messages))
+    }
+
+    /// Receive TSP messages for several private VIDs, which may share endpoints, with a
+    /// stream per VID; each endpoint is listened on once, see
+    /// [`receive_demultiplexed`](crate::transport::receive_demultiplexed). Use this
+    /// instead of [`AsyncStore::receive`] for VIDs at the same endpoint, e.g. a broker
+    pub async fn receive_many(
+        &self,
+        vids: &[&str],
+    ) -> Result<BTreeMap<String, TSPStream<ReceivedTspMessage, Error>>, Error> {
+        let mut endpoints = Vec::new();
+        for vid in vids {
+            let receiver = self.inner.get_private_vid(vid)?;
+            let vid_endpoints = std::iter::once(receiver.endpoint())
+                .chain(receiver.alternative_endpoints())
+                .cloned()
+                .collect();
+
+            endpoints.push((vid.to_string(), vid_endpoints));
+        }
+
+        let config = self.settings().transport_config.clone();
+        let streams = crate::transport::receive_demultiplexed(&endpoints, &config).await?;
+
+        Ok(streams
+            .into_iter()
+            .map(|(vid, messages)| {
+                let messages = self.open_received(&vid, messages);
+                (vid, messages)
+            })
+            .collect())
+    }
+
+    /// An [axum::Router] that accepts the messages POSTed to the `http` and `https`
+    /// endpoints of the private VIDs `vids`, to embed in a web service; receiving for
+    /// these VIDs then yields the POSTed messages, see [`crate::transport::router`]
+    #[cfg(feature = "server")]
+    pub fn router(&self, vids: &[&str]) -> Result<axum::Router, Error> {
+        let mut endpoints = Vec::new();
+        for vid in vids {
+            self.inner.get_private_vid(vid)?;
+
+            let receiver = self
+                .inner
+                .get_verified_vid(&self.inner.transport_vid(vid)?)?;
+            endpoints.extend(
+                std::iter::once(receiver.endpoint())
+                    .chain(receiver.alternative_endpoints())
+                    .cloned(),
+            );
+        }
+
+        Ok(crate::transport::router(
+            endpoints,
+            &self.settings().transport_config,
+        ))
+    }
+
+    /// Open the messages received for `vid`, resolving unknown senders, acknowledging
+    /// messages and handing replies to [`AsyncStore::call`] as configured
+    fn open_received(
+        &self,
+        vid: &str,
+        messages: impl futures::Stream<Item = Result<Vec<u8>, TransportError>> + Send + 'static,
+    ) -> TSPStream<ReceivedTspMessage, Error> {
+        let settings = self.settings().clone();
         let db = self.inner.clone();
-        Ok(Box::pin(messages.then(move |message| {
-            let db_inner = db.clone();
+        let resolver = (!settings.first_contact.is_off()).then(|| self.clone());
+        let messages: TSPStream<ReceivedTspMessage, Error> = match settings.decryption_workers {
+            0 => Box::pin(messages.then(move |message| {
+                let (db, resolver) = (db.clone(), resolver.clone());
+
+                async move {
+                    let message = Self::open_or_pending(&db, message?)?;
+
+                    Self::open_pending(resolver, message).await
+                }
+            })),
+            workers => Box::pin(Self::open_in_parallel(db, resolver, messages, workers)),
+        };
+
+        let pending_replies = self.pending_replies.clone();
+        let relationship_changed = self.relationship_changed.clone();
+        let acknowledger = settings.auto_ack.then(|| (self.clone(), vid.to_string()));
+        let ponger = settings
+            .answer_pings
+            .then(|| (self.clone(), vid.to_string()));
+        let notifier = settings
+            .relationship_notice
+            .then(|| (self.clone(), vid.to_string()));
+        let persister = settings.vault.is_some().then(|| self.clone());
+        Box::pin(messages.filter_map(move |message| {
+            // wake up the sends waiting for a relationship to be accepted
+            if let Ok(
+                ReceivedTspMessage::AcceptRelationship { .. }
+                | ReceivedTspMessage::RejectRelationship { .. }
+                | ReceivedTspMessage::CancelRelationship { .. },
+            ) = &message
+            {
+                relationship_changed.notify_waiters();
+            }
+
+            if let (
+                Some((acknowledger, vid)),
+                Ok(ReceivedTspMessage::GenericMessage { sender, digest, .. }),
+            ) = (&acknowledger, &message)
+            {
+                acknowledger.spawn_ack(vid, sender, *digest);
+            }
+
+            if let (Some((notifier, vid)), Err(Error::NoRelationship(sender))) =
+                (&notifier, &message)
+            {
+                notifier.spawn_relationship_notice(vid, sender);
+            }
+
+            // pings are answered here, so they do not reach the application
+            let message = match (&ponger, message) {
+                (
+                    Some((ponger, vid)),
+                    Ok(ReceivedTspMessage::GenericMessage {
+                        sender,
+                        message: ping,
+                        content_type: Some(media_type),
+                        in_reply_to: None,
+                        digest,
+                        ..
+                    }),
+                ) if media_type == content_type::PING => {
+                    ponger.spawn_pong(vid, &sender, digest, &ping);
+                    None
+                }
+                (_, Ok(message)) => Self::deliver_reply(&pending_replies, message).map(Ok),
+                (_, Err(e)) => Some(Err(e)),
+            };
+
+            let persister = persister.clone();
             async move {
-                match message {
-                    Ok(mut m) => match db_inner.open_message(&mut m) {
-                        Err(Error::UnverifiedSource(unknown_vid, opaque_data)) => {
-                            Ok(ReceivedTspMessage::PendingMessage {
-                                unknown_vid,
-                                payload: opaque_data.unwrap_or(m),
-                            })
-                        }
-                        maybe_message => maybe_message.map(|msg| msg.into_owned()),
-                    },
-                    Err(e) => Err(e.into()),
+                if let (
+                    Some(persister),
+                    Some(Ok(ReceivedTspMessage::AcceptRelationship {
+                        sender,
+                        nested_vid: Some(nested_vid),
+                    })),
+                ) = (&persister, &message)
+                {
+                    if let Err(e) = persister.persist_nested_accept(sender, nested_vid).await {
+                        return Some(Err(e));
+                    }
+                }
+
+                message
+            }
+        }))
+    }
+
+    /// Open the messages of different senders on up to `workers` blocking threads at the
+    /// same time, see [`AsyncStore::set_decryption_workers`]
+    fn open_in_parallel(
+        db: Store,
+        resolver: Option<AsyncStore>,
+        messages: impl futures::Stream<Item = Result<Vec<u8>, TransportError>> + Send + 'static,
+        workers: usize,
+    ) -> impl futures::Stream<Item = Result<ReceivedTspMessage, Error>> + Send {
+        // signals that the last message of a sender is opened, by dropping the sender half
+        let mut opening: HashMap<Vec<u8>, oneshot::Receiver<()>> = HashMap::new();
+
+        messages
+            .map(move |message| {
+                let sender = message
+                    .as_deref()
+                    .ok()
+                    .and_then(|message| crate::cesr::get_sender_receiver(message).ok())
+                    .map(|(sender, _)| sender.to_vec())
+                    .unwrap_or_default();
+
+                opening.retain(|_, opened| matches!(opened.try_recv(), Ok(None)));
+                let (opened, next) = oneshot::channel::<()>();
+                let previous = opening.insert(sender, next);
+                let (db, resolver) = (db.clone(), resolver.clone());
+
+                async move {
+                    if let Some(previous) = previous {
+                        let _ = previous.await;
+                    }
+
+                    let message = message?;
+                    let message = match tokio::task::spawn_blocking(move || {
+                        Self::open_or_pending(&db, message)
+                    })
+                    .await
+                    {
+                        Ok(message) => message?,
+                        Err(e) => std::panic::resume_unwind(e.into_panic()),
+                    };
+                    let message = Self::open_pending(resolver, message).await;
+
+                    drop(opened);
+
+                    message
+                }
+            })
+            .buffered(workers)
+    }
+
+    /// Resolve the sender of `message` if it is pending and `resolver` is set, see
+    /// [`AsyncStore::open_first_contact`]; other messages are returned as they are
+    async fn open_pending(
+        resolver: Option<AsyncStore>,
+        message: ReceivedTspMessage,
+    ) -> Result<ReceivedTspMessage, Error> {
+        match (resolver, message) {
+            (
+                Some(resolver),
+                ReceivedTspMessage::PendingMessage {
+                    unknown_vid,
+                    payload,
+                },
+            ) => resolver.open_first_contact(unknown_vid, payload).await,
+            (_, message) => Ok(message),
+        }
+    }
+
+    /// Resolve the unknown sender of a pending message as allowed by the first contact
+    /// policy, and open the message. Every nested layer may reveal another unknown
+    /// sender; the message stays pending once a sender is not allowed or cannot be resolved
+    async fn open_first_contact(
+        &self,
+        mut unknown_vid: String,
+        mut payload: Vec<u8>,
+    ) -> Result<ReceivedTspMessage, Error> {
+        while self.settings().first_contact.allows(&unknown_vid) {
+            if let Err(e) = self
+                .resolve_and_add(&unknown_vid, VidOrigin::FirstContact)
+                .await
+            {
+                tracing::warn!("could not resolve first contact {unknown_vid}: {e}");
+                break;
+            }
+
+            match Self::open_or_pending(&self.inner, payload)? {
+                ReceivedTspMessage::PendingMessage {
+                    unknown_vid: next_vid,
+                    payload: next_payload,
+                } if next_vid != unknown_vid => {
+                    (unknown_vid, payload) = (next_vid, next_payload);
+                }
+                message => return Ok(message),
+            }
+        }
+
+        Ok(ReceivedTspMessage::PendingMessage {
+            unknown_vid,
+            payload,
+        })
+    }
+
+    /// Yield `messages` until `token` is cancelled, then only the ones that are ready
+    /// without waiting
+    fn drain_until<S: futures::Stream + Unpin>(
+        messages: S,
+        token: CancellationToken,
+    ) -> impl futures::Stream<Item = S::Item> {
+        futures::stream::unfold((messages, token), |(mut messages, token)| async move {
+            let message = if token.is_cancelled() {
+                messages.next().now_or_never().flatten()
+            } else {
+                tokio::select! {
+                    biased;
+                    message = messages.next() => message,
+                    () = token.cancelled() => messages.next().now_or_never().flatten(),
                 }
+            };
+
+            message.map(|message| (message, (messages, token)))
+        })
+    }
+
+    /// Hand a reply to the [`AsyncStore::call`] waiting for it; other messages are returned
+    fn deliver_reply(
+        pending_replies: &DashMap<Digest, PendingReply>,
+        message: ReceivedTspMessage,
+    ) -> Option<ReceivedTspMessage> {
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            message: payload,
+            in_reply_to: Some(in_reply_to),
+            ..
+        } = &message
+        else {
+            return Some(message);
+        };
+
+        // only the VID the request was sent to may answer it
+        let (_, (_, reply)) =
+            pending_replies.remove_if(in_reply_to, |_, (expected, _)| expected == sender)?;
+
+        match reply.send(payload.clone()) {
+            Ok(()) => None,
+            // the caller stopped waiting, so pass the reply on to the stream
+            Err(_) => Some(message),
+        }
+    }
+
+    /// Open a received message; a message from an unknown sender is returned as a
+    /// [ReceivedTspMessage::PendingMessage]
+    fn open_or_pending(db: &Store, mut message: Vec<u8>) -> Result<ReceivedTspMessage, Error> {
+        match db.open_message_owned(&mut message) {
+            Err(Error::UnverifiedSource(unknown_vid, opaque_data)) => {
+                Ok(ReceivedTspMessage::PendingMessage {
+                    unknown_vid,
+                    payload: opaque_data.unwrap_or(message),
+                })
             }
-        })))
+            maybe_message => maybe_message,
+        }
     }
 
     /// Send TSP broadcast message to the specified VIDs
@@ -396,12 +2080,65 @@ impl AsyncStore {
         for vid in receivers {
             let receiver = self.inner.get_verified_vid(vid.as_ref())?;
 
-            crate::transport::send_message(receiver.endpoint(), &message).await?;
+            self.send_to(receiver.endpoint(), &message, Priority::Interactive)
+                .await?;
         }
 
         Ok(())
     }
 
+    /// Send a signed, but not encrypted, message to every VID in `recipients` at the same
+    /// time, e.g. a public announcement; the message is signed once and the same copy is
+    /// delivered to all of them. Returns the delivery result for every recipient, in the
+    /// same order as `recipients`
+    pub async fn broadcast(
+        &self,
+        sender: &str,
+        recipients: &[&str],
+        message: &[u8],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let message = self.inner.sign_anycast(sender, message)?;
+
+        tracing::info!("broadcasting message to {} recipients", recipients.len());
+
+        let deliveries = recipients.iter().map(|vid| {
+            let message = &message;
+
+            async move {
+                let receiver = self.inner.get_verified_vid(vid)?;
+
+                self.send_to(receiver.endpoint(), message, Priority::Interactive)
+                    .await
+            }
+        });
+
+        Ok(futures::future::join_all(deliveries).await)
+    }
+
+    /// Accept a [ReceivedTspMessage::PendingMessage]: resolve and verify the unknown VID, add it to
+    /// the database and open the pending payload again.
+    ///
+    /// If the payload contains a nested message from yet another unknown VID, a new
+    /// [ReceivedTspMessage::PendingMessage] is returned.
+    pub async fn resolve_pending(
+        &mut self,
+        unknown_vid: &str,
+        payload: Vec<u8>,
+    ) -> Result<ReceivedTspMessage, Error> {
+        self.verify_vid(unknown_vid).await?;
+
+        Self::open_or_pending(&self.inner, payload)
+    }
+
+    /// Reject a [ReceivedTspMessage::PendingMessage]: the payload is discarded and the unknown VID
+    /// is not added to the database
+    pub fn reject_pending(&self, unknown_vid: &str, payload: Vec<u8>) {
+        tracing::info!(
+            "rejected pending message of {} bytes from {unknown_vid}",
+            payload.len()
+        );
+    }
+
     /// Process the payload from a  'PendingMessage' by resolving the unknown vid and retrying
     /// This takes a Vec as a payload; for a borrowing version the `as_inner()` version can be used; usually after
     /// unpacking a TSP message you can't or need to do anything with it anyway.
@@ -412,6 +2149,6 @@ impl AsyncStore {
     ) -> Result<ReceivedTspMessage, Error> {
         self.verify_vid(vid).await?;
 
-        Ok(self.inner.open_message(&mut payload)?.into_owned())
+        self.inner.open_message_owned(&mut payload)
     }
 }
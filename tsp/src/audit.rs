@@ -0,0 +1,352 @@
+//! A tamper-evident log of the security-relevant events of a [Store](crate::Store)
+//!
+//! Every entry contains the hash of the entry before it, and is signed by a designated
+//! local VID. Removing, reordering or altering entries breaks the chain, which
+//! [verify] detects given the exported entries and the public keys of the signer.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ed25519_dalek::{Signer, Verifier};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::definitions::{Digest, PrivateVid, RelationshipStatus, VerifiedVid};
+
+/// The state of a relationship, as recorded in the audit log
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelationshipState {
+    Unrelated,
+    Requested,
+    Established,
+    Controlled,
+}
+
+impl From<&RelationshipStatus> for RelationshipState {
+    fn from(status: &RelationshipStatus) -> Self {
+        match status {
+            RelationshipStatus::Unrelated => Self::Unrelated,
+            RelationshipStatus::Unidirectional { .. } => Self::Requested,
+            RelationshipStatus::Bidirectional { .. } => Self::Established,
+            RelationshipStatus::_Controlled => Self::Controlled,
+        }
+    }
+}
+
+/// A security-relevant event
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditEvent {
+    /// A VID was added to the store
+    VidAdded { vid: String },
+    /// A VID was removed from the store
+    VidRemoved { vid: String },
+    /// A VID was added again with a different verification key
+    KeyRotated { vid: String },
+    /// The relationship with `vid` changed
+    RelationshipChanged {
+        vid: String,
+        from: RelationshipState,
+        to: RelationshipState,
+    },
+    /// A message was sealed; `digest` is the SHA-256 hash of the sealed message
+    MessageSent {
+        sender: String,
+        receiver: String,
+        digest: Digest,
+    },
+    /// A message was opened; `digest` is the SHA-256 hash of the message as received
+    MessageReceived {
+        sender: String,
+        receiver: Option<String>,
+        digest: Digest,
+    },
+}
+
+impl AuditEvent {
+    /// The unambiguous encoding of this event that is hashed into its entry
+    fn encode(&self, out: &mut Vec<u8>) {
+        fn field(value: &[u8], out: &mut Vec<u8>) {
+            out.extend((value.len() as u64).to_be_bytes());
+            out.extend(value);
+        }
+
+        match self {
+            AuditEvent::VidAdded { vid } => {
+                out.push(0);
+                field(vid.as_bytes(), out);
+            }
+            AuditEvent::VidRemoved { vid } => {
+                out.push(1);
+                field(vid.as_bytes(), out);
+            }
+            AuditEvent::KeyRotated { vid } => {
+                out.push(2);
+                field(vid.as_bytes(), out);
+            }
+            AuditEvent::RelationshipChanged { vid, from, to } => {
+                out.push(3);
+                field(vid.as_bytes(), out);
+                out.extend([*from as u8, *to as u8]);
+            }
+            AuditEvent::MessageSent {
+                sender,
+                receiver,
+                digest,
+            } => {
+                out.push(4);
+                field(sender.as_bytes(), out);
+                field(receiver.as_bytes(), out);
+                out.extend(digest);
+            }
+            AuditEvent::MessageReceived {
+                sender,
+                receiver,
+                digest,
+            } => {
+                out.push(5);
+                field(sender.as_bytes(), out);
+                match receiver {
+                    Some(receiver) => {
+                        out.push(1);
+                        field(receiver.as_bytes(), out);
+                    }
+                    None => out.push(0),
+                }
+                out.extend(digest);
+            }
+        }
+    }
+}
+
+/// An entry of the audit log
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The position of this entry in the log, starting at zero
+    pub sequence: u64,
+    /// When the event happened, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub event: AuditEvent,
+    /// The hash of the previous entry, all zeroes for the first entry
+    pub previous: Digest,
+    /// The hash of this entry, which the next entry refers to
+    pub hash: Digest,
+    /// The Ed25519 signature of the signer of the log over `hash`
+    pub signature: Vec<u8>,
+}
+
+impl AuditEntry {
+    fn compute_hash(
+        sequence: u64,
+        timestamp: u64,
+        event: &AuditEvent,
+        previous: &Digest,
+    ) -> Digest {
+        let mut data = Vec::new();
+        data.extend(previous);
+        data.extend(sequence.to_be_bytes());
+        data.extend(timestamp.to_be_bytes());
+        event.encode(&mut data);
+
+        crate::crypto::sha256(&data)
+    }
+}
+
+/// Why exported audit log entries could not be verified
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum AuditError {
+    #[error("entry {0} is out of sequence")]
+    Sequence(u64),
+    #[error("entry {0} does not follow the previous entry")]
+    BrokenChain(u64),
+    #[error("entry {0} was altered")]
+    Hash(u64),
+    #[error("entry {0} has an invalid signature")]
+    Signature(u64),
+    #[error("invalid verification key of the signer")]
+    VerificationKey,
+}
+
+/// A hash-chained audit log, signed by a local VID; set it on a store with
+/// [Store::set_audit_log](crate::Store::set_audit_log)
+pub struct AuditLog {
+    signer: Arc<dyn PrivateVid>,
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Create an empty audit log whose entries are signed by `signer`
+    pub fn new(signer: impl PrivateVid + 'static) -> Self {
+        Self {
+            signer: Arc::new(signer),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The identifier of the VID that signs the entries
+    pub fn signer(&self) -> &str {
+        self.signer.identifier()
+    }
+
+    /// Append `event` to the log
+    pub fn record(&self, event: AuditEvent) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+
+        let mut entries = self.entries.lock().expect("audit log lock is poisoned");
+        let sequence = entries.len() as u64;
+        let previous = entries.last().map(|entry| entry.hash).unwrap_or_default();
+        let hash = AuditEntry::compute_hash(sequence, timestamp, &event, &previous);
+
+        let sign_key =
+            ed25519_dalek::SigningKey::from_bytes(self.signer.signing_key().expose_secret());
+        let signature = sign_key.sign(&hash).to_bytes().to_vec();
+
+        entries.push(AuditEntry {
+            sequence,
+            timestamp,
+            event,
+            previous,
+            hash,
+            signature,
+        });
+    }
+
+    /// Export the entries of the log, e.g. for a compliance review
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries
+            .lock()
+            .expect("audit log lock is poisoned")
+            .clone()
+    }
+
+    /// Export the entries starting at `sequence`; verify them with [verify_from] and the
+    /// hash of the last entry exported before
+    pub fn entries_since(&self, sequence: u64) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().expect("audit log lock is poisoned");
+
+        entries
+            .get(sequence as usize..)
+            .map(<[AuditEntry]>::to_vec)
+            .unwrap_or_default()
+    }
+}
+
+/// Verify that `entries` form a complete log, signed by `signer`
+pub fn verify(entries: &[AuditEntry], signer: &dyn VerifiedVid) -> Result<(), AuditError> {
+    verify_from(entries, 0, &Digest::default(), signer)
+}
+
+/// Verify that `entries` continue a log at `sequence`, after the entry with hash `previous`
+pub fn verify_from(
+    entries: &[AuditEntry],
+    sequence: u64,
+    previous: &Digest,
+    signer: &dyn VerifiedVid,
+) -> Result<(), AuditError> {
+    let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(signer.verifying_key())
+        .map_err(|_| AuditError::VerificationKey)?;
+
+    let mut previous = *previous;
+    for (expected, entry) in (sequence..).zip(entries) {
+        if entry.sequence != expected {
+            return Err(AuditError::Sequence(entry.sequence));
+        }
+
+        if entry.previous != previous {
+            return Err(AuditError::BrokenChain(entry.sequence));
+        }
+
+        let hash = AuditEntry::compute_hash(
+            entry.sequence,
+            entry.timestamp,
+            &entry.event,
+            &entry.previous,
+        );
+        if hash != entry.hash {
+            return Err(AuditError::Hash(entry.sequence));
+        }
+
+        let signature = ed25519_dalek::Signature::from_slice(&entry.signature)
+            .map_err(|_| AuditError::Signature(entry.sequence))?;
+        verifying_key
+            .verify(&entry.hash, &signature)
+            .map_err(|_| AuditError::Signature(entry.sequence))?;
+
+        previous = entry.hash;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OwnedVid;
+
+    fn event(vid: &str) -> AuditEvent {
+        AuditEvent::VidAdded {
+            vid: vid.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let signer = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        let log = AuditLog::new(signer.clone());
+
+        log.record(event("did:example:alice"));
+        log.record(event("did:example:bob"));
+        log.record(AuditEvent::VidRemoved {
+            vid: "did:example:alice".to_string(),
+        });
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].previous, entries[0].hash);
+        verify(&entries, signer.vid()).unwrap();
+
+        // a later export continues where the previous one ended
+        let later = log.entries_since(2);
+        assert_eq!(later.len(), 1);
+        verify_from(&later, 2, &entries[1].hash, signer.vid()).unwrap();
+
+        // altered, removed and reordered entries are detected
+        let mut altered = entries.clone();
+        altered[1].event = event("did:example:mallory");
+        assert_eq!(verify(&altered, signer.vid()), Err(AuditError::Hash(1)));
+
+        let mut removed = entries.clone();
+        removed.remove(1);
+        assert_eq!(verify(&removed, signer.vid()), Err(AuditError::Sequence(2)));
+
+        let mut reordered = entries.clone();
+        reordered.swap(0, 1);
+        assert_eq!(
+            verify(&reordered, signer.vid()),
+            Err(AuditError::Sequence(1))
+        );
+
+        // entries signed by another VID are rejected
+        let other = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        assert_eq!(verify(&entries, other.vid()), Err(AuditError::Signature(0)));
+    }
+}
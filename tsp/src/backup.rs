@@ -0,0 +1,120 @@
+//! Password protected, portable wallet backups
+//!
+//! A backup consists of a fixed header followed by the encrypted JSON of the exported VIDs:
+//!
+//! | bytes | content                                        |
+//! |-------|------------------------------------------------|
+//! | 4     | magic `TSPW`                                   |
+//! | 1     | format version                                 |
+//! | 12    | Argon2id memory cost, time cost and lanes (BE) |
+//! | 16    | salt                                           |
+//! | 12    | ChaCha20Poly1305 nonce                         |
+//! | rest  | ciphertext                                     |
+//!
+//! The complete header is authenticated as associated data.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, Key, KeyInit,
+};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::{secret::Secret, Error, ExportVid};
+
+const MAGIC: &[u8; 4] = b"TSPW";
+const VERSION: u8 = 1;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 3 * 4 + SALT_SIZE + NONCE_SIZE;
+
+fn derive_key(password: &str, salt: &[u8], params: Params) -> Result<Secret<[u8; 32]>, Error> {
+    let mut key = Secret::new([0; 32]);
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, key.expose_secret_mut())
+        .map_err(|_| Error::InvalidBackup("could not derive a key from the password"))?;
+
+    Ok(key)
+}
+
+/// Serialize and encrypt `vids` with a key derived from `password`
+pub(crate) fn seal(vids: &[ExportVid], password: &str) -> Result<Vec<u8>, Error> {
+    let params = Params::default();
+
+    let mut salt = [0; SALT_SIZE];
+    let mut nonce = [0; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut backup = Vec::with_capacity(HEADER_SIZE);
+    backup.extend_from_slice(MAGIC);
+    backup.push(VERSION);
+    backup.extend_from_slice(&params.m_cost().to_be_bytes());
+    backup.extend_from_slice(&params.t_cost().to_be_bytes());
+    backup.extend_from_slice(&params.p_cost().to_be_bytes());
+    backup.extend_from_slice(&salt);
+    backup.extend_from_slice(&nonce);
+
+    let key = derive_key(password, &salt, params)?;
+    let plaintext = Zeroizing::new(
+        serde_json::to_vec(vids)
+            .map_err(|_| Error::InvalidBackup("could not serialize the VIDs"))?,
+    );
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key.expose_secret()))
+        .encrypt(
+            &nonce.into(),
+            Payload {
+                msg: &plaintext,
+                aad: &backup,
+            },
+        )
+        .map_err(|_| Error::InvalidBackup("could not encrypt the VIDs"))?;
+
+    backup.extend_from_slice(&ciphertext);
+
+    Ok(backup)
+}
+
+/// Decrypt and deserialize a backup created by [seal]
+pub(crate) fn open(backup: &[u8], password: &str) -> Result<Vec<ExportVid>, Error> {
+    if backup.len() < HEADER_SIZE || &backup[..MAGIC.len()] != MAGIC {
+        return Err(Error::InvalidBackup("not a TSP wallet backup"));
+    }
+
+    let (header, ciphertext) = backup.split_at(HEADER_SIZE);
+
+    if header[MAGIC.len()] != VERSION {
+        return Err(Error::InvalidBackup("unsupported backup version"));
+    }
+
+    let (costs, rest) = header[MAGIC.len() + 1..].split_at(3 * 4);
+    let (salt, nonce) = rest.split_at(SALT_SIZE);
+
+    let cost = |i: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&costs[i * 4..(i + 1) * 4]);
+
+        u32::from_be_bytes(bytes)
+    };
+
+    let params = Params::new(cost(0), cost(1), cost(2), None)
+        .map_err(|_| Error::InvalidBackup("invalid key derivation parameters"))?;
+    let key = derive_key(password, salt, params)?;
+
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(key.expose_secret()))
+        .decrypt(
+            nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| Error::InvalidBackup("wrong password or corrupted backup"))?;
+    let plaintext = Zeroizing::new(plaintext);
+
+    serde_json::from_slice(&plaintext)
+        .map_err(|_| Error::InvalidBackup("could not deserialize the VIDs"))
+}
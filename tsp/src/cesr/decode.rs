@@ -94,6 +94,32 @@ pub fn decode_variable_data_index(
     }
 }
 
+/// The size of the variable size data with a known identifier at the start of `stream`,
+/// including its header; unlike [decode_variable_data_index], the data itself does not have
+/// to be present yet. Returns `None` if the header is incomplete, and `Some(None)` if
+/// `stream` starts with something else
+pub fn variable_data_size(identifier: u32, stream: &[u8]) -> Option<Option<usize>> {
+    let input = extract_triplet(stream.get(0..=2)?.try_into().unwrap());
+    let selector = input >> 18;
+
+    let (found_id, size) = match selector {
+        D4 | D5 | D6 => (input >> 12 & mask(6), input & mask(12)),
+        D7 | D8 | D9 => (
+            input & mask(18),
+            extract_triplet(stream.get(3..6)?.try_into().unwrap()),
+        ),
+        _ => return Some(None),
+    };
+
+    if found_id == identifier {
+        let offset = (selector - D4) as usize;
+
+        Some(Some((offset + 1).next_multiple_of(3) + 3 * size as usize))
+    } else {
+        Some(None)
+    }
+}
+
 pub fn decode_variable_data<'a>(identifier: u32, stream: &mut &'a [u8]) -> Option<&'a [u8]> {
     let range = decode_variable_data_index(identifier, stream, &mut 0)?;
     let slice = &stream[range.start..range.end];
@@ -216,6 +242,28 @@ pub fn decode_large_blob_index(stream: &[u8]) -> Option<std::ops::Range<usize>>
     Some(range)
 }
 
+/// The size of the "big data" at the start of `stream`, including its header, like
+/// [variable_data_size]
+pub fn large_blob_size(stream: &[u8]) -> Option<Option<usize>> {
+    let selector = b'N' - b'A';
+    if super::selector_char(stream) != Some('N') {
+        return Some(None);
+    }
+
+    let header = stream.get(0..9)?;
+    let Some(size) = decode_fixed_data::<8>(selector as u32, &mut &header[..]) else {
+        return Some(None);
+    };
+
+    // a size that does not fit in memory is reported as the largest possible size
+    let size = usize::try_from(u64::from_be_bytes(*size))
+        .ok()
+        .and_then(|size| size.checked_next_multiple_of(3))
+        .map_or(usize::MAX, |size| size.saturating_add(9));
+
+    Some(Some(size))
+}
+
 #[cfg(test)]
 pub fn decode_large_blob<'a>(stream: &mut &'a [u8]) -> Option<&'a [u8]> {
     let range = decode_large_blob_index(stream)?;
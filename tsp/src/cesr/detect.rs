@@ -1,12 +1,13 @@
 use base64ct::{Base64UrlUnpadded, Encoding};
 
-pub fn to_binary(data: &mut [u8]) -> Option<&[u8]> {
-    let first_byte = data.first()?;
+use super::Domain;
 
-    match first_byte >> 5 {
-        0b001 => Base64UrlUnpadded::decode_in_place(data).ok(), // CESR in the T domain
-        0b111 => Some(data),                                    // CESR in the B domain
-        _ => None,
+/// Convert a complete TSP message to the binary domain in place; see
+/// [FrameDecoder](super::FrameDecoder) for messages that arrive in chunks
+pub fn to_binary(data: &mut [u8]) -> Option<&[u8]> {
+    match Domain::detect(*data.first()?)? {
+        Domain::Text => Base64UrlUnpadded::decode_in_place(data).ok(),
+        Domain::Binary => Some(data),
     }
 }
 
@@ -48,8 +48,18 @@ pub fn encode_variable_data(
     payload: &[u8],
     stream: &mut impl for<'a> Extend<&'a u8>,
 ) {
-    let padded_size = payload.len().next_multiple_of(3);
-    let lead_bytes = padded_size - payload.len();
+    encode_variable_data_header(identifier, payload.len(), stream);
+    stream.extend(payload);
+}
+
+/// Encode the code and padding that precede variable size data of length `len`
+pub fn encode_variable_data_header(
+    identifier: u32,
+    len: usize,
+    stream: &mut impl for<'a> Extend<&'a u8>,
+) {
+    let padded_size = len.next_multiple_of(3);
+    let lead_bytes = padded_size - len;
 
     let selector = D4 + lead_bytes as u32;
     let size = (padded_size / 3) as u32;
@@ -64,7 +74,6 @@ pub fn encode_variable_data(
     }
 
     stream.extend(&<[u8; 2]>::default()[0..lead_bytes]);
-    stream.extend(payload);
 }
 
 /// Encode a frame with known identifier and count code
@@ -96,12 +105,17 @@ pub fn encode_genus(
 /// followed by the raw data padded in the usual sense for CESR (pre-padding)
 /// This is a temporary encoding, depending on how CESR will address this in the future.
 pub fn encode_large_blob(payload: &[u8], stream: &mut impl for<'a> Extend<&'a u8>) {
-    let size = payload.len() as u64;
+    encode_large_blob_header(payload.len(), stream);
+    stream.extend(payload);
+}
+
+/// Encode the size and padding that precede a large blob of length `len`
+pub fn encode_large_blob_header(len: usize, stream: &mut impl for<'a> Extend<&'a u8>) {
+    let size = len as u64;
     let padded_size = size.next_multiple_of(3);
     let lead_bytes = padded_size - size;
 
     let selector = (b'N' - b'A') as u32;
     encode_fixed_data(selector, &u64::to_be_bytes(size), stream);
     stream.extend(&<[u8; 2]>::default()[0..lead_bytes as usize]);
-    stream.extend(payload);
 }
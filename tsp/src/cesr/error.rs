@@ -6,16 +6,48 @@ pub enum EncodeError {
 }
 
 /// An error type to indicate something went wrong with decoding
-#[derive(Clone, Copy, Debug)]
+///
+/// Offsets are in bytes, relative to the start of the buffer that was being decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DecodeError {
-    UnexpectedData,
-    UnexpectedMsgType,
-    TrailingGarbage,
+    /// The data at `offset` is not the `expected` CESR field; `found` is the
+    /// selector (the first character of the CESR code) at that position
+    UnexpectedData {
+        offset: usize,
+        expected: &'static str,
+        found: Option<char>,
+    },
+    UnexpectedMsgType {
+        offset: usize,
+    },
+    TrailingGarbage {
+        offset: usize,
+    },
     SignatureError,
     VidError,
-    VersionMismatch,
+    VersionMismatch {
+        offset: usize,
+    },
     InvalidCryptoType,
     InvalidSignatureType,
+    /// A message of `size` bytes is larger than the `max_size` that is accepted
+    MessageTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+}
+
+impl DecodeError {
+    /// The byte offset at which decoding failed, if known
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            DecodeError::UnexpectedData { offset, .. }
+            | DecodeError::UnexpectedMsgType { offset }
+            | DecodeError::TrailingGarbage { offset }
+            | DecodeError::VersionMismatch { offset } => Some(*offset),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for EncodeError {
@@ -26,7 +58,37 @@ impl std::fmt::Display for EncodeError {
 
 impl std::fmt::Display for DecodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        write!(f, "{:?}", self)
+        match self {
+            DecodeError::UnexpectedData {
+                offset,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "UnexpectedData at byte {offset}: expected {expected}, found selector '{found}'"
+            ),
+            DecodeError::UnexpectedData {
+                offset,
+                expected,
+                found: None,
+            } => write!(f, "UnexpectedData at byte {offset}: expected {expected}"),
+            DecodeError::UnexpectedMsgType { offset } => {
+                write!(f, "UnexpectedMsgType at byte {offset}")
+            }
+            DecodeError::TrailingGarbage { offset } => {
+                write!(f, "TrailingGarbage at byte {offset}")
+            }
+            DecodeError::VersionMismatch { offset } => {
+                write!(f, "VersionMismatch at byte {offset}")
+            }
+            DecodeError::MessageTooLarge { size, max_size } => {
+                write!(
+                    f,
+                    "message of {size} bytes exceeds the maximum of {max_size} bytes"
+                )
+            }
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -4,7 +4,9 @@ mod detect;
 mod encode;
 pub mod error;
 mod packet;
+mod stream;
 pub use packet::*;
+pub use stream::{Domain, FrameDecoder};
 
 #[cfg(feature = "cesr-t")]
 pub use detect::to_binary;
@@ -41,6 +43,15 @@ fn header_match(input: &[u8], target: &[u8]) -> bool {
     }
 }
 
+/// The selector (i.e. the first character of the CESR code) at the start of `stream`
+fn selector_char(stream: &[u8]) -> Option<char> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    stream
+        .first()
+        .map(|byte| ALPHABET[(byte >> 2) as usize] as char)
+}
+
 /// Constants for CESR selectors
 mod selector {
     pub const D0: u32 = 52;
@@ -2,6 +2,7 @@
 const TSP_PLAINTEXT: u32 = (b'B' - b'A') as u32;
 const TSP_CIPHERTEXT: u32 = (b'C' - b'A') as u32;
 const TSP_DEVELOPMENT_VID: u32 = (21 << 6 | 8) << 6 | 3; // "VID"
+const TSP_PADDING: u32 = (b'P' - b'A') as u32;
 
 /// Constants that determine the specific CESR types for "fixed length data"
 const TSP_TYPECODE: u32 = (b'X' - b'A') as u32;
@@ -11,40 +12,66 @@ const TSP_NONCE: u32 = (b'A' - b'A') as u32;
 const TSP_SHA256: u32 = (b'I' - b'A') as u32;
 #[allow(dead_code)]
 const TSP_BLAKE2B256: u32 = (b'F' - b'A') as u32;
+const TSP_NUMBER: u32 = (b'N' - b'A') as u32; // 64-bit big-endian integer
 
 /// Constants that determine the specific CESR types for the framing codes
 const TSP_ETS_WRAPPER: u16 = (b'E' - b'A') as u16;
 const TSP_S_WRAPPER: u16 = (b'S' - b'A') as u16;
 const TSP_HOP_LIST: u16 = (b'I' - b'A') as u16;
 const TSP_PAYLOAD: u16 = (b'Z' - b'A') as u16;
+const TSP_HEADER_MAP: u16 = (b'M' - b'A') as u16;
+const TSP_SEGMENT_LIST: u16 = (b'L' - b'A') as u16;
+const TSP_ANNOTATION_LIST: u16 = (b'N' - b'A') as u16;
 
 /// Constants to encode message types
 mod msgtype {
     pub(super) const GEN_MSG: [u8; 2] = [0, 0];
     pub(super) const NEST_MSG: [u8; 2] = [0, 1];
+    pub(super) const MULTIPART_MSG: [u8; 2] = [0, 2];
+    pub(super) const GROUP_MSG: [u8; 2] = [0, 3];
+    pub(super) const ACK_MSG: [u8; 2] = [0, 4];
     pub(super) const NEW_REL: [u8; 2] = [1, 0];
     pub(super) const NEW_REL_REPLY: [u8; 2] = [1, 1];
     pub(super) const NEW_NEST_REL: [u8; 2] = [1, 2];
     pub(super) const NEW_NEST_REL_REPLY: [u8; 2] = [1, 3];
     pub(super) const NEW_REFER_REL: [u8; 2] = [1, 4];
     pub(super) const THIRDP_REFER_REL: [u8; 2] = [1, 5];
+    pub(super) const SESSION_KEY: [u8; 2] = [1, 6];
+    pub(super) const REL_RENEW: [u8; 2] = [1, 252];
+    pub(super) const REL_REJECT: [u8; 2] = [1, 253];
+    pub(super) const NEST_REL_CANCEL: [u8; 2] = [1, 254];
     pub(super) const REL_CANCEL: [u8; 2] = [1, 255];
+    pub(super) const GROUP_MEMBER_ADD: [u8; 2] = [2, 0];
+    pub(super) const GROUP_MEMBER_REMOVE: [u8; 2] = [2, 1];
+
+    /// Message types starting with this byte are reserved for control messages defined by
+    /// applications, see [Payload::ExtensionMessage](super::Payload::ExtensionMessage)
+    pub(super) const EXTENSION: u8 = 0x7f;
+
+    /// Flag in the message type marking a deflate-compressed message
+    pub(super) const COMPRESSED: u8 = 0x80;
+    pub(super) const GEN_MSG_COMPRESSED: [u8; 2] = [GEN_MSG[0] | COMPRESSED, GEN_MSG[1]];
 }
 
+use base64ct::{Base64UrlUnpadded, Encoding};
+#[cfg(feature = "serialize")]
+use serde::Serialize;
+
 use super::{
     decode::{
         decode_count, decode_count_mut, decode_fixed_data, decode_fixed_data_mut,
         decode_variable_data, decode_variable_data_index, decode_variable_data_mut,
+        large_blob_size, variable_data_size,
     },
-    encode::{encode_count, encode_fixed_data},
+    encode::{encode_count, encode_fixed_data, encode_variable_data_header},
     error::{DecodeError, EncodeError},
 };
 
 /// A type to enforce that a random nonce contains enough bits of security
 /// (128bits via a birthday attack -> 256bits needed)
 /// This explicitly does not implement Clone or Copy to make sure nonces are not reused
-#[derive(Debug)]
-#[cfg_attr(any(test, feature = "fuzzing"), derive(PartialEq, Eq, Clone))]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Clone))]
 pub struct Nonce([u8; 32]);
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
@@ -78,10 +105,25 @@ impl Nonce {
 pub enum Payload<'a, Bytes, Vid> {
     /// A TSP message which consists only of a message which will be protected using HPKE
     GenericMessage(Bytes),
+    /// A generic TSP message that replies to an earlier message with digest `reply`
+    ReplyMessage { reply: Digest<'a>, message: Bytes },
+    /// A generic TSP message, optionally replying to `reply`, whose message is compressed
+    /// with deflate
+    CompressedMessage {
+        reply: Option<Digest<'a>>,
+        message: Bytes,
+    },
     /// A payload that consists of a TSP Envelope+Message
     NestedMessage(Bytes),
-    /// A routed payload; same as above but with routing information attached
-    RoutedMessage(Vec<Vid>, Bytes),
+    /// A routed payload; same as above but with routing information attached, and
+    /// optional nonconfidential annotations for the receiver and the following hops
+    RoutedMessage(Vec<Vid>, Vec<Vid>, Bytes),
+    /// A TSP message consisting of an ordered list of (content type, data) segments
+    MultipartMessage(Vec<(Vid, Bytes)>),
+    /// A TSP message addressed to all members of a group
+    GroupMessage { group: Vid, message: Bytes },
+    /// A TSP message acknowledging the receipt of the message with digest `reply`
+    Acknowledgement { reply: Digest<'a> },
     /// A TSP message requesting a relationship
     DirectRelationProposal { nonce: Nonce, hops: Vec<Vid> },
     /// A TSP message confirming a relationship
@@ -94,8 +136,32 @@ pub enum Payload<'a, Bytes, Vid> {
     NewIdentifierProposal { thread_id: Digest<'a>, new_vid: Vid },
     /// A TSP Message revealing a third party
     RelationshipReferral { referred_vid: Vid },
+    /// A TSP message refusing the relationship proposal with digest `reply`
+    RelationshipReject { reply: Digest<'a>, reason: Bytes },
+    /// A TSP message extending the relationship with digest `reply` until `expires_at`,
+    /// in seconds since the Unix epoch
+    RelationshipRenew { reply: Digest<'a>, expires_at: u64 },
+    /// A TSP message establishing a symmetric session for the relationship with thread id
+    /// `reply`, derived from `seed`
+    SessionKey {
+        reply: Digest<'a>,
+        seed: &'a [u8; 32],
+    },
     /// A TSP cancellation message
     RelationshipCancel { reply: Digest<'a> },
+    /// A TSP message cancelling the nested relationship of the sender's `nested_vid`
+    NestedRelationCancel { nested_vid: Vid, reply: Digest<'a> },
+    /// A TSP message announcing a new member of a group
+    GroupMemberAdd { group: Vid, member: Vid },
+    /// A TSP message announcing the removal of a member from a group
+    GroupMemberRemove { group: Vid, member: Vid },
+    /// An experimental control message defined by an application; its message type is
+    /// `typecode` in the range that is reserved for extensions, and its contents are opaque
+    ExtensionMessage { typecode: u8, data: Bytes },
+    /// A message of a type this implementation does not know, e.g. a control message
+    /// introduced by a newer version of the protocol; `raw` holds the encoded fields after
+    /// the message type as is, including any padding
+    UnknownControl { typecode: [u8; 2], raw: Bytes },
 }
 
 impl<'a, Bytes: AsRef<[u8]>, Vid: AsRef<[u8]>> Payload<'a, Bytes, Vid> {
@@ -112,10 +178,41 @@ impl<'a, Bytes: AsRef<[u8]>, Vid: AsRef<[u8]>> Payload<'a, Bytes, Vid> {
 
         count.0
     }
+
+    /// The digest of an earlier message this payload refers to, if any
+    pub fn digest(&self) -> Option<&Digest<'a>> {
+        match self {
+            Payload::ReplyMessage { reply, .. }
+            | Payload::Acknowledgement { reply }
+            | Payload::DirectRelationAffirm { reply }
+            | Payload::NestedRelationAffirm { reply, .. }
+            | Payload::RelationshipReject { reply, .. }
+            | Payload::RelationshipRenew { reply, .. }
+            | Payload::SessionKey { reply, .. }
+            | Payload::RelationshipCancel { reply }
+            | Payload::NestedRelationCancel { reply, .. } => Some(reply),
+            Payload::NewIdentifierProposal { thread_id, .. } => Some(thread_id),
+            Payload::CompressedMessage { reply, .. } => reply.as_ref(),
+            Payload::GenericMessage(_)
+            | Payload::NestedMessage(_)
+            | Payload::RoutedMessage(..)
+            | Payload::MultipartMessage(_)
+            | Payload::GroupMessage { .. }
+            | Payload::DirectRelationProposal { .. }
+            | Payload::NestedRelationProposal { .. }
+            | Payload::RelationshipReferral { .. }
+            | Payload::GroupMemberAdd { .. }
+            | Payload::GroupMemberRemove { .. }
+            | Payload::ExtensionMessage { .. }
+            | Payload::UnknownControl { .. } => None,
+        }
+    }
 }
 
-// helpers for generating and comparing arbitrary `Payload`s
-#[cfg(feature = "fuzzing")]
+pub mod payload;
+
+// helpers for generating and comparing arbitrary `Payload`s and `Envelope`s
+#[cfg(any(test, feature = "fuzzing"))]
 pub mod fuzzing;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -126,6 +223,8 @@ pub enum CryptoType {
     HpkeEssr = 2,
     NaclAuth = 3,
     NaclEssr = 4,
+    /// Encrypted with a key of a symmetric session, see [crate::Store::make_session_key]
+    Session = 5,
 }
 
 impl TryFrom<u8> for CryptoType {
@@ -138,6 +237,7 @@ impl TryFrom<u8> for CryptoType {
             2 => Ok(CryptoType::HpkeEssr),
             3 => Ok(CryptoType::NaclAuth),
             4 => Ok(CryptoType::NaclEssr),
+            5 => Ok(CryptoType::Session),
             _ => Err(DecodeError::InvalidCryptoType),
         }
     }
@@ -209,6 +309,43 @@ fn checked_encode_variable_data(
     Ok(())
 }
 
+/// Safely encode the header of variable data of length `len`, using a blob if needed
+fn checked_encode_variable_data_header(
+    identifier: u32,
+    len: usize,
+    stream: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    const DATA_LIMIT: usize = 3 * (1 << 24);
+
+    if len >= DATA_LIMIT {
+        // since blobs have no identifier, that information is lost on large payloads and a "blob" can only be used
+        // for TSP_PLAINTEXT or TSP_CIPHERTEXT.
+        if identifier == TSP_PLAINTEXT || identifier == TSP_CIPHERTEXT {
+            super::encode::encode_large_blob_header(len, stream);
+        } else {
+            return Err(EncodeError::ExcessiveFieldSize);
+        }
+    } else {
+        super::encode::encode_variable_data_header(identifier, len, stream);
+    }
+
+    Ok(())
+}
+
+/// The offset of `stream` in the buffer starting at address `start`
+fn offset(start: usize, stream: &[u8]) -> usize {
+    stream.as_ptr() as usize - start
+}
+
+/// Report that the data at the beginning of `stream` is not the `expected` field
+fn unexpected(start: usize, stream: &[u8], expected: &'static str) -> DecodeError {
+    DecodeError::UnexpectedData {
+        offset: offset(start, stream),
+        expected,
+        found: super::selector_char(stream),
+    }
+}
+
 /// Safely decode variable data, detecting blobs
 fn checked_decode_variable_data_mut(
     identifier: u32,
@@ -263,18 +400,44 @@ pub fn encode_payload(
             encode_fixed_data(TSP_TYPECODE, &msgtype::GEN_MSG, output);
             checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
         }
+        Payload::ReplyMessage { reply, message } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::GEN_MSG, output);
+            encode_digest(reply, output);
+            checked_encode_variable_data(TSP_PLAINTEXT, message.as_ref(), output)?;
+        }
+        Payload::CompressedMessage { reply, message } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::GEN_MSG_COMPRESSED, output);
+            if let Some(reply) = reply {
+                encode_digest(reply, output);
+            }
+            checked_encode_variable_data(TSP_PLAINTEXT, message.as_ref(), output)?;
+        }
         Payload::NestedMessage(data) => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::NEST_MSG, output);
             checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
         }
-        Payload::RoutedMessage(hops, data) => {
+        Payload::RoutedMessage(hops, annotations, data) => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::GEN_MSG, output);
             if hops.is_empty() {
                 return Err(EncodeError::MissingHops);
             }
             encode_hops(hops, output)?;
+            encode_annotations(annotations, output)?;
             checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
         }
+        Payload::MultipartMessage(segments) => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::MULTIPART_MSG, output);
+            encode_segments(segments, output)?;
+        }
+        Payload::GroupMessage { group, message } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::GROUP_MSG, output);
+            checked_encode_variable_data(TSP_DEVELOPMENT_VID, group.as_ref(), output)?;
+            checked_encode_variable_data(TSP_PLAINTEXT, message.as_ref(), output)?;
+        }
+        Payload::Acknowledgement { reply } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::ACK_MSG, output);
+            encode_digest(reply, output);
+        }
         Payload::DirectRelationProposal { nonce, hops } => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::NEW_REL, output);
             encode_hops(hops, output)?;
@@ -309,15 +472,68 @@ pub fn encode_payload(
             encode_fixed_data(TSP_TYPECODE, &msgtype::THIRDP_REFER_REL, output);
             checked_encode_variable_data(TSP_DEVELOPMENT_VID, referred_vid.as_ref(), output)?;
         }
+        Payload::RelationshipReject { reply, reason } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::REL_REJECT, output);
+            encode_digest(reply, output);
+            checked_encode_variable_data(TSP_PLAINTEXT, reason.as_ref(), output)?;
+        }
+        Payload::RelationshipRenew { reply, expires_at } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::REL_RENEW, output);
+            encode_digest(reply, output);
+            encode_fixed_data(TSP_NUMBER, &expires_at.to_be_bytes(), output);
+        }
+        Payload::SessionKey { reply, seed } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::SESSION_KEY, output);
+            encode_digest(reply, output);
+            encode_fixed_data(TSP_NONCE, *seed, output);
+        }
         Payload::RelationshipCancel { reply } => {
             encode_fixed_data(TSP_TYPECODE, &msgtype::REL_CANCEL, output);
             encode_digest(reply, output);
         }
+        Payload::NestedRelationCancel { nested_vid, reply } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::NEST_REL_CANCEL, output);
+            checked_encode_variable_data(TSP_DEVELOPMENT_VID, nested_vid.as_ref(), output)?;
+            encode_digest(reply, output);
+        }
+        Payload::GroupMemberAdd { group, member } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::GROUP_MEMBER_ADD, output);
+            checked_encode_variable_data(TSP_DEVELOPMENT_VID, group.as_ref(), output)?;
+            checked_encode_variable_data(TSP_DEVELOPMENT_VID, member.as_ref(), output)?;
+        }
+        Payload::GroupMemberRemove { group, member } => {
+            encode_fixed_data(TSP_TYPECODE, &msgtype::GROUP_MEMBER_REMOVE, output);
+            checked_encode_variable_data(TSP_DEVELOPMENT_VID, group.as_ref(), output)?;
+            checked_encode_variable_data(TSP_DEVELOPMENT_VID, member.as_ref(), output)?;
+        }
+        Payload::ExtensionMessage { typecode, data } => {
+            encode_fixed_data(TSP_TYPECODE, &[msgtype::EXTENSION, *typecode], output);
+            checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+        }
+        Payload::UnknownControl { typecode, raw } => {
+            encode_fixed_data(TSP_TYPECODE, typecode, output);
+            output.extend(raw.as_ref());
+        }
     }
 
     Ok(())
 }
 
+/// Encode `size` bytes of padding to follow an encoded payload, which hide its length and
+/// are skipped by [decode_payload]; like every encoded field, `size` is a multiple of 3
+pub fn encode_padding(mut size: usize, output: &mut impl for<'a> Extend<&'a u8>) {
+    // the most a field with a short code can hold, so larger padding is split in fields
+    const MAX_FIELD_SIZE: usize = 3 + 3 * 4095;
+
+    while size >= 3 {
+        let field_size = size.min(MAX_FIELD_SIZE);
+        encode_variable_data_header(TSP_PADDING, field_size - 3, output);
+        output.extend(core::iter::repeat(&0).take(field_size - 3));
+
+        size -= field_size;
+    }
+}
+
 /// Encode a hops list
 pub fn encode_hops(
     hops: &[impl AsRef<[u8]>],
@@ -333,8 +549,77 @@ pub fn encode_hops(
     Ok(())
 }
 
+/// Encode a list of per-hop annotations, if there are any
+fn encode_annotations(
+    annotations: &[impl AsRef<[u8]>],
+    output: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    if annotations.is_empty() {
+        return Ok(());
+    }
+
+    // the count code has room for 12 bits
+    if annotations.len() >= 1 << 12 {
+        return Err(EncodeError::ExcessiveFieldSize);
+    }
+
+    encode_count(TSP_ANNOTATION_LIST, annotations.len() as u16, output);
+    for annotation in annotations {
+        checked_encode_variable_data(TSP_PLAINTEXT, annotation.as_ref(), output)?;
+    }
+
+    Ok(())
+}
+
+/// Encode a list of (content type, data) segments
+fn encode_segments(
+    segments: &[(impl AsRef<[u8]>, impl AsRef<[u8]>)],
+    output: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    // the count code has room for 12 bits
+    if segments.len() >= 1 << 12 {
+        return Err(EncodeError::ExcessiveFieldSize);
+    }
+
+    encode_count(TSP_SEGMENT_LIST, segments.len() as u16, output);
+    for (content_type, data) in segments {
+        checked_encode_variable_data(TSP_PLAINTEXT, content_type.as_ref(), output)?;
+        checked_encode_variable_data(TSP_PLAINTEXT, data.as_ref(), output)?;
+    }
+
+    Ok(())
+}
+
+/// A decoded (content type, data) segment
+type Segment<'a> = (&'a [u8], &'a mut [u8]);
+
+/// Decode a list of (content type, data) segments
+fn decode_segments(
+    start: usize,
+    stream: &mut [u8],
+) -> Result<(Vec<Segment>, &mut [u8]), DecodeError> {
+    let err = unexpected(start, stream, "segment list");
+    let (count, mut stream) = decode_count_mut(TSP_SEGMENT_LIST, stream).ok_or(err)?;
+
+    let mut segments = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let content_type: &[u8];
+        let data;
+        let err = unexpected(start, stream, "segment content type");
+        (content_type, stream) =
+            checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+        let err = unexpected(start, stream, "segment data");
+        (data, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+
+        segments.push((content_type, data));
+    }
+
+    Ok((segments, stream))
+}
+
 /// Decode a hops list
 fn decode_hops<'a, Vid: TryFrom<&'a [u8]>>(
+    start: usize,
     stream: &'a mut [u8],
 ) -> Result<(Vec<Vid>, &'a mut [u8]), DecodeError> {
     // a rare case of Rust's borrow checker not being able to figure out
@@ -349,8 +634,8 @@ fn decode_hops<'a, Vid: TryFrom<&'a [u8]>>(
     let mut hop_list = Vec::with_capacity(hop_length as usize);
     for _ in 0..hop_length {
         let hop: &[u8];
-        (hop, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream)
-            .ok_or(DecodeError::UnexpectedData)?;
+        let err = unexpected(start, stream, "hop VID");
+        (hop, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream).ok_or(err)?;
 
         hop_list.push(hop.try_into().map_err(|_| DecodeError::VidError)?);
     }
@@ -358,6 +643,77 @@ fn decode_hops<'a, Vid: TryFrom<&'a [u8]>>(
     Ok((hop_list, stream))
 }
 
+/// Decode a list of per-hop annotations; the list is optional
+fn decode_annotations(
+    start: usize,
+    stream: &mut [u8],
+) -> Result<(Vec<&[u8]>, &mut [u8]), DecodeError> {
+    // see decode_hops
+    if decode_count_mut(TSP_ANNOTATION_LIST, stream).is_none() {
+        return Ok((Vec::new(), stream));
+    }
+
+    let (count, mut stream) = decode_count_mut(TSP_ANNOTATION_LIST, stream).unwrap();
+
+    let mut annotations = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let annotation: &[u8];
+        let err = unexpected(start, stream, "hop annotation");
+        (annotation, stream) =
+            checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+
+        annotations.push(annotation);
+    }
+
+    Ok((annotations, stream))
+}
+
+/// Encode a map of structured header fields (for use as nonconfidential data)
+pub fn encode_headers(
+    fields: &[(impl AsRef<[u8]>, impl AsRef<[u8]>)],
+    output: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    if fields.len() >= 1 << 12 {
+        return Err(EncodeError::ExcessiveFieldSize);
+    }
+
+    encode_count(TSP_HEADER_MAP, fields.len() as u16, output);
+    for (key, value) in fields {
+        checked_encode_variable_data(TSP_PLAINTEXT, key.as_ref(), output)?;
+        checked_encode_variable_data(TSP_PLAINTEXT, value.as_ref(), output)?;
+    }
+
+    Ok(())
+}
+
+/// A list of (key, value) pairs in a header map
+pub type HeaderFields<'a> = Vec<(&'a [u8], &'a [u8])>;
+
+/// Decode a map of structured header fields
+pub fn decode_headers(mut stream: &[u8]) -> Result<HeaderFields<'_>, DecodeError> {
+    let start = stream.as_ptr() as usize;
+    let count = decode_count(TSP_HEADER_MAP, &mut stream)
+        .ok_or_else(|| unexpected(start, stream, "header map"))?;
+
+    let mut fields = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let key = decode_variable_data(TSP_PLAINTEXT, &mut stream)
+            .ok_or_else(|| unexpected(start, stream, "header key"))?;
+        let value = decode_variable_data(TSP_PLAINTEXT, &mut stream)
+            .ok_or_else(|| unexpected(start, stream, "header value"))?;
+
+        fields.push((key, value));
+    }
+
+    if !stream.is_empty() {
+        return Err(DecodeError::TrailingGarbage {
+            offset: offset(start, stream),
+        });
+    }
+
+    Ok(fields)
+}
+
 // "NestedBytes" to support both mutable and non-mutable data
 /// A decoded payload + optional ESSR data
 pub struct DecodedPayload<'a> {
@@ -366,7 +722,14 @@ pub struct DecodedPayload<'a> {
 }
 
 /// Decode a TSP Digest
-fn decode_digest(stream: &mut [u8]) -> Result<(Digest, &mut [u8]), DecodeError> {
+/// Check whether the stream starts with a TSP Digest
+fn starts_with_digest(stream: &[u8]) -> bool {
+    decode_fixed_data::<32>(TSP_SHA256, &mut &stream[..]).is_some()
+        || decode_fixed_data::<32>(TSP_BLAKE2B256, &mut &stream[..]).is_some()
+}
+
+fn decode_digest(start: usize, stream: &mut [u8]) -> Result<(Digest, &mut [u8]), DecodeError> {
+    let err = unexpected(start, stream, "digest");
     let result = if decode_fixed_data::<32>(TSP_SHA256, &mut (stream as &[u8])).is_some() {
         decode_fixed_data_mut(TSP_SHA256, stream)
             .map(|(digest, stream)| (Digest::Sha2_256(digest), stream))
@@ -377,7 +740,7 @@ fn decode_digest(stream: &mut [u8]) -> Result<(Digest, &mut [u8]), DecodeError>
         None
     };
 
-    result.ok_or(DecodeError::UnexpectedData)
+    result.ok_or(err)
 }
 
 /// Encode a TSP Digest
@@ -390,11 +753,13 @@ pub fn encode_digest(digest: &Digest, output: &mut impl for<'a> Extend<&'a u8>)
 
 /// Decode a TSP Payload
 pub fn decode_payload(mut stream: &mut [u8]) -> Result<DecodedPayload, DecodeError> {
+    let start = stream.as_ptr() as usize;
     let sender_identity = match decode_count_mut(TSP_PAYLOAD, stream) {
         Some((2, upd_stream)) => {
             let essr_prefix: &[u8];
-            (essr_prefix, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, upd_stream)
-                .ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, upd_stream, "sender VID");
+            (essr_prefix, stream) =
+                decode_variable_data_mut(TSP_DEVELOPMENT_VID, upd_stream).ok_or(err)?;
 
             Some(essr_prefix)
         }
@@ -403,34 +768,67 @@ pub fn decode_payload(mut stream: &mut [u8]) -> Result<DecodedPayload, DecodeErr
 
             None
         }
-        _ => return Err(DecodeError::VersionMismatch),
+        _ => return Err(DecodeError::VersionMismatch { offset: 0 }),
     };
 
-    let (&mut msgtype, mut stream) =
-        decode_fixed_data_mut(TSP_TYPECODE, stream).ok_or(DecodeError::UnexpectedData)?;
+    let err = unexpected(start, stream, "message type");
+    let (&mut msgtype, mut stream) = decode_fixed_data_mut(TSP_TYPECODE, stream).ok_or(err)?;
 
     let payload = match msgtype {
         msgtype::GEN_MSG => {
-            let (hop_list, upd_stream) = decode_hops(stream)?;
+            let (hop_list, upd_stream) = decode_hops(start, stream)?;
             let msg;
-            if hop_list.is_empty() {
-                (msg, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, upd_stream)
-                    .ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, upd_stream, "plaintext");
+            if hop_list.is_empty() && starts_with_digest(upd_stream) {
+                let reply;
+                (reply, stream) = decode_digest(start, upd_stream)?;
+                let err = unexpected(start, stream, "plaintext");
+                (msg, stream) =
+                    checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+
+                Payload::ReplyMessage {
+                    reply,
+                    message: msg,
+                }
+            } else if hop_list.is_empty() {
+                (msg, stream) =
+                    checked_decode_variable_data_mut(TSP_PLAINTEXT, upd_stream).ok_or(err)?;
 
                 Payload::GenericMessage(msg)
             } else {
-                (msg, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, upd_stream)
-                    .ok_or(DecodeError::UnexpectedData)?;
+                let (annotations, upd_stream) = decode_annotations(start, upd_stream)?;
+                let err = unexpected(start, upd_stream, "plaintext");
+                (msg, stream) =
+                    checked_decode_variable_data_mut(TSP_PLAINTEXT, upd_stream).ok_or(err)?;
+
+                Payload::RoutedMessage(hop_list, annotations, msg)
+            }
+        }
+        msgtype::GEN_MSG_COMPRESSED => {
+            let reply = if starts_with_digest(stream) {
+                let reply;
+                (reply, stream) = decode_digest(start, stream)?;
+
+                Some(reply)
+            } else {
+                None
+            };
+
+            let msg;
+            let err = unexpected(start, stream, "compressed plaintext");
+            (msg, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
 
-                Payload::RoutedMessage(hop_list, msg)
+            Payload::CompressedMessage {
+                reply,
+                message: msg,
             }
         }
         msgtype::NEW_REL => {
-            let (hop_list, upd_stream) = decode_hops(stream)?;
+            let (hop_list, upd_stream) = decode_hops(start, stream)?;
 
             let nonce;
-            (nonce, stream) =
-                decode_fixed_data_mut(TSP_NONCE, upd_stream).ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, upd_stream, "nonce");
+            (nonce, stream) = decode_fixed_data_mut(TSP_NONCE, upd_stream).ok_or(err)?;
 
             Payload::DirectRelationProposal {
                 nonce: Nonce(*nonce),
@@ -439,25 +837,50 @@ pub fn decode_payload(mut stream: &mut [u8]) -> Result<DecodedPayload, DecodeErr
         }
         msgtype::NEST_MSG => {
             let msg;
-            (msg, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, stream)
-                .ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, stream, "nested message");
+            (msg, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
 
             Payload::NestedMessage(msg)
         }
+        msgtype::MULTIPART_MSG => {
+            let segments;
+            (segments, stream) = decode_segments(start, stream)?;
+
+            Payload::MultipartMessage(segments)
+        }
+        msgtype::GROUP_MSG => {
+            let group: &[u8];
+            let msg;
+            let err = unexpected(start, stream, "group");
+            (group, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream).ok_or(err)?;
+            let err = unexpected(start, stream, "group message");
+            (msg, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+
+            Payload::GroupMessage {
+                group,
+                message: msg,
+            }
+        }
+        msgtype::ACK_MSG => {
+            let reply;
+            (reply, stream) = decode_digest(start, stream)?;
+
+            Payload::Acknowledgement { reply }
+        }
         msgtype::NEW_REL_REPLY => {
             let reply;
-            (reply, stream) = decode_digest(stream)?;
+            (reply, stream) = decode_digest(start, stream)?;
 
             Payload::DirectRelationAffirm { reply }
         }
         msgtype::NEW_NEST_REL => {
             let data: &mut [u8];
-            (data, stream) = decode_variable_data_mut(TSP_PLAINTEXT, stream)
-                .ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, stream, "nested message");
+            (data, stream) = decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
 
             let nonce;
-            (nonce, stream) =
-                decode_fixed_data_mut(TSP_NONCE, stream).ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, stream, "nonce");
+            (nonce, stream) = decode_fixed_data_mut(TSP_NONCE, stream).ok_or(err)?;
 
             Payload::NestedRelationProposal {
                 message: data,
@@ -467,9 +890,9 @@ pub fn decode_payload(mut stream: &mut [u8]) -> Result<DecodedPayload, DecodeErr
         msgtype::NEW_NEST_REL_REPLY => {
             let data: &mut [u8];
             let reply;
-            (data, stream) = decode_variable_data_mut(TSP_PLAINTEXT, stream)
-                .ok_or(DecodeError::UnexpectedData)?;
-            (reply, stream) = decode_digest(stream)?;
+            let err = unexpected(start, stream, "nested message");
+            (data, stream) = decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+            (reply, stream) = decode_digest(start, stream)?;
 
             Payload::NestedRelationAffirm {
                 message: data,
@@ -477,31 +900,102 @@ pub fn decode_payload(mut stream: &mut [u8]) -> Result<DecodedPayload, DecodeErr
             }
         }
         msgtype::NEW_REFER_REL => {
-            let (thread_id, upd_stream) = decode_digest(stream)?;
+            let (thread_id, upd_stream) = decode_digest(start, stream)?;
             let new_vid: &[u8];
-            (new_vid, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, upd_stream)
-                .ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, upd_stream, "new VID");
+            (new_vid, stream) =
+                decode_variable_data_mut(TSP_DEVELOPMENT_VID, upd_stream).ok_or(err)?;
 
             Payload::NewIdentifierProposal { thread_id, new_vid }
         }
         msgtype::THIRDP_REFER_REL => {
             let referred_vid: &[u8];
-            (referred_vid, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream)
-                .ok_or(DecodeError::UnexpectedData)?;
+            let err = unexpected(start, stream, "referred VID");
+            (referred_vid, stream) =
+                decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream).ok_or(err)?;
 
             Payload::RelationshipReferral { referred_vid }
         }
+        msgtype::REL_REJECT => {
+            let (reply, upd_stream) = decode_digest(start, stream)?;
+            let reason;
+            let err = unexpected(start, upd_stream, "rejection reason");
+            (reason, stream) =
+                checked_decode_variable_data_mut(TSP_PLAINTEXT, upd_stream).ok_or(err)?;
+
+            Payload::RelationshipReject { reply, reason }
+        }
+        msgtype::REL_RENEW => {
+            let (reply, upd_stream) = decode_digest(start, stream)?;
+            let expires_at: &mut [u8; 8];
+            let err = unexpected(start, upd_stream, "expiry");
+            (expires_at, stream) = decode_fixed_data_mut(TSP_NUMBER, upd_stream).ok_or(err)?;
+
+            Payload::RelationshipRenew {
+                reply,
+                expires_at: u64::from_be_bytes(*expires_at),
+            }
+        }
+        msgtype::SESSION_KEY => {
+            let (reply, upd_stream) = decode_digest(start, stream)?;
+            let seed: &mut [u8; 32];
+            let err = unexpected(start, upd_stream, "session seed");
+            (seed, stream) = decode_fixed_data_mut(TSP_NONCE, upd_stream).ok_or(err)?;
+
+            Payload::SessionKey { reply, seed }
+        }
         msgtype::REL_CANCEL => {
             let reply;
-            (reply, stream) = decode_digest(stream)?;
+            (reply, stream) = decode_digest(start, stream)?;
 
             Payload::RelationshipCancel { reply }
         }
-        _ => return Err(DecodeError::UnexpectedMsgType),
+        msgtype::NEST_REL_CANCEL => {
+            let nested_vid: &[u8];
+            let err = unexpected(start, stream, "nested VID");
+            (nested_vid, stream) =
+                decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream).ok_or(err)?;
+            let reply;
+            (reply, stream) = decode_digest(start, stream)?;
+
+            Payload::NestedRelationCancel { nested_vid, reply }
+        }
+        msgtype::GROUP_MEMBER_ADD | msgtype::GROUP_MEMBER_REMOVE => {
+            let group: &[u8];
+            let member: &[u8];
+            let err = unexpected(start, stream, "group");
+            (group, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream).ok_or(err)?;
+            let err = unexpected(start, stream, "group member");
+            (member, stream) = decode_variable_data_mut(TSP_DEVELOPMENT_VID, stream).ok_or(err)?;
+
+            if msgtype == msgtype::GROUP_MEMBER_ADD {
+                Payload::GroupMemberAdd { group, member }
+            } else {
+                Payload::GroupMemberRemove { group, member }
+            }
+        }
+        [msgtype::EXTENSION, typecode] => {
+            let data;
+            let err = unexpected(start, stream, "extension data");
+            (data, stream) = checked_decode_variable_data_mut(TSP_PLAINTEXT, stream).ok_or(err)?;
+
+            Payload::ExtensionMessage { typecode, data }
+        }
+        typecode => {
+            // the fields of an unknown message type cannot be told apart from padding
+            let raw = std::mem::take(&mut stream);
+
+            Payload::UnknownControl { typecode, raw }
+        }
     };
 
-    if !stream.is_empty() {
-        Err(DecodeError::TrailingGarbage)
+    let mut padding_end = 0;
+    while decode_variable_data_index(TSP_PADDING, stream, &mut padding_end).is_some() {}
+
+    if padding_end < stream.len() {
+        Err(DecodeError::TrailingGarbage {
+            offset: offset(start, &stream[padding_end..]),
+        })
     } else {
         Ok(DecodedPayload {
             payload,
@@ -568,23 +1062,36 @@ pub fn encode_ciphertext(
     checked_encode_variable_data(TSP_CIPHERTEXT, ciphertext, output)
 }
 
+/// Encode the CESR code that precedes a ciphertext of length `len`; the ciphertext
+/// itself can then be written directly after it
+pub fn encode_ciphertext_header(
+    len: usize,
+    output: &mut impl for<'a> Extend<&'a u8>,
+) -> Result<(), EncodeError> {
+    checked_encode_variable_data_header(TSP_CIPHERTEXT, len, output)
+}
+
 /// Checks whether the expected TSP header is present and returns its size and whether it
 /// is a "ETS" or "S" envelope
 pub(super) fn detected_tsp_header_size_and_confidentiality(
     stream: &mut &[u8],
 ) -> Result<(usize, CryptoType, SignatureType), DecodeError> {
     let origin = stream as &[u8];
+    let mismatch = |stream: &[u8]| DecodeError::VersionMismatch {
+        offset: origin.len() - stream.len(),
+    };
+
     let encrypted = if let Some(1) = decode_count(TSP_ETS_WRAPPER, stream) {
         true
     } else if let Some(1) = decode_count(TSP_S_WRAPPER, stream) {
         false
     } else {
-        return Err(DecodeError::VersionMismatch);
+        return Err(mismatch(stream));
     };
 
     match decode_fixed_data(TSP_TYPECODE, stream) {
         Some([0, 0]) => {}
-        _ => return Err(DecodeError::VersionMismatch),
+        _ => return Err(mismatch(stream)),
     }
 
     let (crypto_type, signature_type) = match decode_fixed_data(TSP_TYPECODE, stream) {
@@ -592,12 +1099,12 @@ pub(super) fn detected_tsp_header_size_and_confidentiality(
             let crypto_type = CryptoType::try_from(*crypto)?;
 
             if crypto_type.is_encrypted() != encrypted {
-                return Err(DecodeError::VersionMismatch);
+                return Err(mismatch(stream));
             }
 
             (crypto_type, SignatureType::try_from(*signature)?)
         }
-        _ => return Err(DecodeError::VersionMismatch),
+        _ => return Err(mismatch(stream)),
     };
 
     debug_assert_eq!(origin.len() - stream.len(), 9);
@@ -618,10 +1125,11 @@ pub struct VerificationChallenge<'a> {
 pub fn decode_sender_receiver<'a, Vid: TryFrom<&'a [u8]>>(
     stream: &mut &'a [u8],
 ) -> Result<(Vid, Option<Vid>, CryptoType, SignatureType), DecodeError> {
+    let start = stream.as_ptr() as usize;
     let (_, crypto_type, signature_type) = detected_tsp_header_size_and_confidentiality(stream)?;
 
     let sender = decode_variable_data(TSP_DEVELOPMENT_VID, stream)
-        .ok_or(DecodeError::UnexpectedData)?
+        .ok_or_else(|| unexpected(start, stream, "sender VID"))?
         .try_into()
         .map_err(|_| DecodeError::VidError)?;
 
@@ -714,8 +1222,9 @@ pub fn decode_envelope<'a>(stream: &'a mut [u8]) -> Result<CipherView<'a>, Decod
     let (mut pos, crypto_type, signature_type) =
         detected_tsp_header_size_and_confidentiality(&mut (stream as &[u8]))?;
 
+    let start = stream.as_ptr() as usize;
     let sender = decode_variable_data_index(TSP_DEVELOPMENT_VID, stream, &mut pos)
-        .ok_or(DecodeError::UnexpectedData)?;
+        .ok_or_else(|| unexpected(start, &stream[pos..], "sender VID"))?;
 
     let receiver = decode_variable_data_index(TSP_DEVELOPMENT_VID, stream, &mut pos);
 
@@ -726,7 +1235,7 @@ pub fn decode_envelope<'a>(stream: &'a mut [u8]) -> Result<CipherView<'a>, Decod
     let ciphertext = if crypto_type.is_encrypted() {
         Some(
             checked_decode_variable_data_index(TSP_CIPHERTEXT, stream, &mut pos)
-                .ok_or(DecodeError::UnexpectedData)?,
+                .ok_or_else(|| unexpected(start, &stream[pos..], "ciphertext"))?,
         )
     } else {
         None
@@ -738,11 +1247,13 @@ pub fn decode_envelope<'a>(stream: &'a mut [u8]) -> Result<CipherView<'a>, Decod
     let mut sigdata: &[u8];
     (data, sigdata) = stream.split_at_mut(signed_data.end);
 
-    let signature =
-        decode_fixed_data(ED25519_SIGNATURE, &mut sigdata).ok_or(DecodeError::UnexpectedData)?;
+    let signature = decode_fixed_data(ED25519_SIGNATURE, &mut sigdata)
+        .ok_or_else(|| unexpected(start, sigdata, "signature"))?;
 
     if !sigdata.is_empty() {
-        return Err(DecodeError::TrailingGarbage);
+        return Err(DecodeError::TrailingGarbage {
+            offset: offset(start, sigdata),
+        });
     }
 
     Ok(CipherView {
@@ -763,6 +1274,54 @@ pub fn decode_envelope<'a>(stream: &'a mut [u8]) -> Result<CipherView<'a>, Decod
     })
 }
 
+/// The size in bytes of the encoded Ed25519 signature that ends a TSP message
+const SIGNATURE_SIZE: usize = 66;
+
+/// The length of the TSP message in the binary domain at the start of `stream`, which may
+/// extend beyond the end of `stream`; `None` if more data is needed to tell. Only the
+/// envelope is checked, the message is decoded with [decode_envelope] once it is complete
+pub fn message_length(stream: &[u8]) -> Result<Option<usize>, DecodeError> {
+    let Some(header) = stream.get(0..9) else {
+        return Ok(None);
+    };
+
+    let start = stream.as_ptr() as usize;
+    let (mut pos, crypto_type, _) = detected_tsp_header_size_and_confidentiality(&mut &header[..])?;
+
+    // the sender VID, the optional receiver VID and nonconfidential data, and the
+    // ciphertext of an encrypted message, which may be a blob
+    let fields: [(u32, bool, &str); 4] = [
+        (TSP_DEVELOPMENT_VID, true, "sender VID"),
+        (TSP_DEVELOPMENT_VID, false, "receiver VID"),
+        (TSP_PLAINTEXT, false, "nonconfidential data"),
+        (TSP_CIPHERTEXT, true, "ciphertext"),
+    ];
+
+    for (identifier, required, name) in fields {
+        if identifier == TSP_CIPHERTEXT && !crypto_type.is_encrypted() {
+            continue;
+        }
+
+        let Some(rest) = stream.get(pos..) else {
+            return Ok(None);
+        };
+
+        let size = match variable_data_size(identifier, rest) {
+            Some(None) if identifier == TSP_CIPHERTEXT => large_blob_size(rest),
+            size => size,
+        };
+
+        match size {
+            None => return Ok(None),
+            Some(Some(size)) => pos = pos.saturating_add(size),
+            Some(None) if required => return Err(unexpected(start, rest, name)),
+            Some(None) => {}
+        }
+    }
+
+    Ok(Some(pos.saturating_add(SIGNATURE_SIZE)))
+}
+
 /// Allocating variant of [encode_payload]
 #[cfg(test)]
 pub fn encode_payload_vec(
@@ -869,6 +1428,231 @@ pub fn open_message_into_parts(data: &[u8]) -> Result<MessageParts, DecodeError>
     })
 }
 
+/// A field found in a CESR-encoded message, or a problem found while decoding it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Byte offset of the field in the message
+    pub offset: usize,
+    /// Size in bytes of the field, including its CESR code
+    pub len: usize,
+    /// Description of the field
+    pub description: String,
+    /// The reason decoding stopped at `offset`, if it failed
+    pub error: Option<DecodeError>,
+}
+
+impl Diagnostic {
+    fn field(range: Range<usize>, description: String) -> Self {
+        Diagnostic {
+            offset: range.start,
+            len: range.len(),
+            description,
+            error: None,
+        }
+    }
+
+    fn error(error: DecodeError) -> Self {
+        Diagnostic {
+            offset: error.offset().unwrap_or(0),
+            len: 0,
+            description: error.to_string(),
+            error: Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.error {
+            Some(_) => write!(f, "{:>6}: error: {}", self.offset, self.description),
+            None => write!(
+                f,
+                "{:>6}: {} ({} bytes)",
+                self.offset, self.description, self.len
+            ),
+        }
+    }
+}
+
+/// Describe the fields of a CESR-encoded message, up to and including the first
+/// problem that prevents decoding it
+pub fn diagnose(data: &[u8]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let start = data.as_ptr() as usize;
+
+    let (mut pos, crypto_type, signature_type) =
+        match detected_tsp_header_size_and_confidentiality(&mut (data as &[u8])) {
+            Ok(header) => header,
+            Err(error) => {
+                diagnostics.push(Diagnostic::error(error));
+                return diagnostics;
+            }
+        };
+
+    diagnostics.push(Diagnostic::field(
+        0..pos,
+        format!("envelope header ({crypto_type:?}, {signature_type:?})"),
+    ));
+
+    let encrypted = crypto_type.is_encrypted();
+    let fields = [
+        (TSP_DEVELOPMENT_VID, "sender VID", true),
+        (TSP_DEVELOPMENT_VID, "receiver VID", encrypted),
+        (TSP_PLAINTEXT, "nonconfidential data", false),
+        (TSP_CIPHERTEXT, "ciphertext", encrypted),
+    ];
+
+    for (identifier, name, required) in fields {
+        if identifier == TSP_CIPHERTEXT && !encrypted {
+            continue;
+        }
+
+        let begin = pos;
+        match checked_decode_variable_data_index(identifier, data, &mut pos) {
+            Some(range) if identifier == TSP_DEVELOPMENT_VID => {
+                diagnostics.push(Diagnostic::field(
+                    begin..pos,
+                    format!("{name}: {}", String::from_utf8_lossy(&data[range])),
+                ))
+            }
+            Some(range) => diagnostics.push(Diagnostic::field(
+                begin..pos,
+                format!("{name}: {} bytes", range.len()),
+            )),
+            None if required => {
+                diagnostics.push(Diagnostic::error(unexpected(start, &data[pos..], name)));
+                return diagnostics;
+            }
+            None => {}
+        }
+    }
+
+    let mut rest = &data[pos..];
+    if decode_fixed_data::<64>(ED25519_SIGNATURE, &mut rest).is_none() {
+        diagnostics.push(Diagnostic::error(unexpected(start, rest, "signature")));
+        return diagnostics;
+    }
+
+    diagnostics.push(Diagnostic::field(
+        pos..data.len() - rest.len(),
+        "signature".to_string(),
+    ));
+
+    if !rest.is_empty() {
+        diagnostics.push(Diagnostic::error(DecodeError::TrailingGarbage {
+            offset: offset(start, rest),
+        }));
+    }
+
+    diagnostics
+}
+
+/// The kind of a part of a CESR-encoded message
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartType {
+    Header,
+    Sender,
+    Receiver,
+    NonconfidentialData,
+    Ciphertext,
+    Signature,
+}
+
+impl PartType {
+    /// Human readable name of the part
+    pub fn label(self) -> &'static str {
+        match self {
+            PartType::Header => "Envelope header",
+            PartType::Sender => "Sender VID",
+            PartType::Receiver => "Receiver VID",
+            PartType::NonconfidentialData => "Non-confidential data",
+            PartType::Ciphertext => "Ciphertext",
+            PartType::Signature => "Signature",
+        }
+    }
+}
+
+/// A part of a CESR-encoded message, described for display
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnotatedPart {
+    /// Byte offset of the part in the message
+    pub offset: usize,
+    /// Size in bytes of the part, including its CESR code
+    pub len: usize,
+    /// Size in bytes of the CESR code at the start of the part
+    pub code_len: usize,
+    pub part_type: PartType,
+    /// Human readable name of the part, see [PartType::label]
+    pub label: &'static str,
+    /// The decoded contents of the part, or their size if they cannot be shown as text
+    pub preview: String,
+}
+
+/// Describe the parts of a CESR-encoded message in order, e.g. to highlight them in a
+/// user interface; unlike [diagnose], this fails if the message cannot be decoded
+pub fn annotate(data: &[u8]) -> Result<Vec<AnnotatedPart>, DecodeError> {
+    let parts = open_message_into_parts(data)?;
+    let start = data.as_ptr() as usize;
+
+    let describe = |part_type: PartType, part: &Part, preview: String| AnnotatedPart {
+        offset: offset(start, part.prefix),
+        len: part.prefix.len() + part.data.len(),
+        code_len: part.prefix.len(),
+        part_type,
+        label: part_type.label(),
+        preview,
+    };
+    let text = |data: &[u8]| String::from_utf8_lossy(data).into_owned();
+    let size = |data: &[u8]| format!("{} bytes", data.len());
+
+    let mut annotated = vec![
+        describe(
+            PartType::Header,
+            &parts.prefix,
+            format!("{:?}, {:?}", parts.crypto_type, parts.signature_type),
+        ),
+        describe(PartType::Sender, &parts.sender, text(parts.sender.data)),
+    ];
+
+    if let Some(receiver) = &parts.receiver {
+        annotated.push(describe(PartType::Receiver, receiver, text(receiver.data)));
+    }
+
+    if let Some(part) = &parts.nonconfidential_data {
+        let preview = match std::str::from_utf8(part.data) {
+            Ok(data) => data.to_string(),
+            Err(_) => size(part.data),
+        };
+        annotated.push(describe(PartType::NonconfidentialData, part, preview));
+    }
+
+    if let Some(ciphertext) = &parts.ciphertext {
+        annotated.push(describe(
+            PartType::Ciphertext,
+            ciphertext,
+            size(ciphertext.data),
+        ));
+    }
+
+    annotated.push(describe(
+        PartType::Signature,
+        &parts.signature,
+        Base64UrlUnpadded::encode_string(parts.signature.data),
+    ));
+
+    Ok(annotated)
+}
+
 /// Convenience interface: this struct is isomorphic to [Envelope] but represents
 /// a "opened" envelope, i.e. message.
 #[cfg(all(feature = "demo", test))]
@@ -1153,6 +1937,104 @@ mod test {
         assert!(decode_envelope(&mut outer).is_err());
     }
 
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_diagnose() {
+        let fixed_sig = [1; 64];
+
+        let mut outer = encode_ets_envelope_vec(Envelope {
+            crypto_type: CryptoType::HpkeAuth,
+            signature_type: SignatureType::Ed25519,
+            sender: &b"Alister"[..],
+            receiver: Some(&b"Bobbi"[..]),
+            nonconfidential_data: Some(b"treasure"),
+        })
+        .unwrap();
+        let header_len = outer.len();
+        encode_ciphertext(b"secret", &mut outer).unwrap();
+        encode_signature(&fixed_sig, &mut outer);
+
+        let diagnostics = diagnose(&outer);
+        assert_eq!(diagnostics.len(), 6);
+        assert!(diagnostics.iter().all(|d| d.error.is_none()));
+        assert_eq!(diagnostics[1].description, "sender VID: Alister");
+        assert_eq!(
+            diagnostics.iter().map(|d| d.len).sum::<usize>(),
+            outer.len()
+        );
+
+        let len = outer.len();
+        outer.push(b'-');
+        assert_eq!(
+            diagnose(&outer).last().unwrap().error,
+            Some(DecodeError::TrailingGarbage { offset: len })
+        );
+
+        // the signature is found where the ciphertext was expected
+        outer.truncate(header_len);
+        encode_signature(&fixed_sig, &mut outer);
+        let error = DecodeError::UnexpectedData {
+            offset: header_len,
+            expected: "ciphertext",
+            found: Some('0'),
+        };
+        assert_eq!(diagnose(&outer).last().unwrap().error, Some(error));
+        assert_eq!(decode_envelope(&mut outer).unwrap_err(), error);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_annotate() {
+        let mut outer = encode_ets_envelope_vec(Envelope {
+            crypto_type: CryptoType::HpkeAuth,
+            signature_type: SignatureType::Ed25519,
+            sender: &b"Alister"[..],
+            receiver: Some(&b"Bobbi"[..]),
+            nonconfidential_data: Some(b"treasure"),
+        })
+        .unwrap();
+        encode_ciphertext(b"secret", &mut outer).unwrap();
+        encode_signature(&[1; 64], &mut outer);
+
+        let parts = annotate(&outer).unwrap();
+        assert_eq!(
+            parts.iter().map(|part| part.part_type).collect::<Vec<_>>(),
+            [
+                PartType::Header,
+                PartType::Sender,
+                PartType::Receiver,
+                PartType::NonconfidentialData,
+                PartType::Ciphertext,
+                PartType::Signature,
+            ]
+        );
+        assert_eq!(
+            parts
+                .iter()
+                .map(|part| part.preview.as_str())
+                .collect::<Vec<_>>()[..5],
+            [
+                "HpkeAuth, Ed25519",
+                "Alister",
+                "Bobbi",
+                "treasure",
+                "6 bytes"
+            ]
+        );
+
+        // the parts cover the message without gaps
+        let mut offset = 0;
+        for part in &parts {
+            assert_eq!(part.offset, offset);
+            assert!(part.code_len <= part.len);
+            offset += part.len;
+        }
+        assert_eq!(offset, outer.len());
+
+        outer.truncate(outer.len() - 1);
+        assert!(annotate(&outer).is_err());
+    }
+
     #[cfg(all(feature = "demo", test))]
     #[test]
     fn convenience() {
@@ -1202,10 +2084,72 @@ mod test {
     fn test_routed_msg() {
         test_turn_around(Payload::RoutedMessage(
             vec![b"foo", b"bar"],
+            vec![],
+            &mut b"Hello TSP!".to_owned(),
+        ));
+        test_turn_around(Payload::RoutedMessage(
+            vec![b"foo", b"bar"],
+            vec![b"priority=high", b""],
             &mut b"Hello TSP!".to_owned(),
         ));
     }
 
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_multipart_msg() {
+        test_turn_around(Payload::MultipartMessage(vec![
+            (
+                &b"application/json"[..],
+                &mut br#"{"hello":"TSP"}"#.to_owned()[..],
+            ),
+            (&b"application/octet-stream"[..], &mut [0, 1, 2, 3][..]),
+        ]));
+        test_turn_around(Payload::MultipartMessage(vec![]));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_reply_msg() {
+        test_turn_around(Payload::ReplyMessage {
+            reply: Digest::Sha2_256(&[1; 32]),
+            message: &mut b"Hello TSP!".to_owned(),
+        });
+        test_turn_around(Payload::ReplyMessage {
+            reply: Digest::Blake2b256(&[2; 32]),
+            message: &mut [],
+        });
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_compressed_msg() {
+        test_turn_around(Payload::CompressedMessage {
+            reply: None,
+            message: &mut b"Hello TSP!".to_owned(),
+        });
+        test_turn_around(Payload::CompressedMessage {
+            reply: Some(Digest::Sha2_256(&[1; 32])),
+            message: &mut b"Hello TSP!".to_owned(),
+        });
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_group_msgs() {
+        test_turn_around(Payload::GroupMessage {
+            group: b"book club",
+            message: &mut b"Hello TSP!".to_owned(),
+        });
+        test_turn_around(Payload::GroupMemberAdd {
+            group: b"book club",
+            member: b"Charlie",
+        });
+        test_turn_around(Payload::GroupMemberRemove {
+            group: b"book club",
+            member: b"Charlie",
+        });
+    }
+
     #[test]
     #[wasm_bindgen_test]
     fn test_par_refer_rel() {
@@ -1294,12 +2238,40 @@ mod test {
             reply: Digest::Blake2b256(nonce),
         });
 
+        test_turn_around(Payload::RelationshipReject {
+            reply: Digest::Sha2_256(nonce),
+            reason: &mut b"not interested".to_owned(),
+        });
+
+        test_turn_around(Payload::RelationshipRenew {
+            reply: Digest::Blake2b256(nonce),
+            expires_at: 1_700_000_000,
+        });
+
+        test_turn_around(Payload::Acknowledgement {
+            reply: Digest::Sha2_256(nonce),
+        });
+
+        test_turn_around(Payload::SessionKey {
+            reply: Digest::Sha2_256(nonce),
+            seed: nonce,
+        });
+
         test_turn_around(Payload::RelationshipCancel {
             reply: Digest::Sha2_256(nonce),
         });
         test_turn_around(Payload::RelationshipCancel {
             reply: Digest::Blake2b256(nonce),
         });
+
+        test_turn_around(Payload::NestedRelationCancel {
+            nested_vid: b"Alice",
+            reply: Digest::Sha2_256(nonce),
+        });
+        test_turn_around(Payload::NestedRelationCancel {
+            nested_vid: b"Alice",
+            reply: Digest::Blake2b256(nonce),
+        });
     }
 
     #[test]
@@ -1335,4 +2307,22 @@ mod test {
         let (source, _) = checked_decode_variable_data_mut(TSP_PLAINTEXT, input).unwrap();
         assert!(source.len() == 60_000_000);
     }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_headers() {
+        let fields: [(&[u8], &[u8]); 2] = [(b"ct", b"application/json"), (b"ts", &[0, 0, 0, 42])];
+        let mut data = vec![];
+        encode_headers(&fields, &mut data).unwrap();
+
+        assert_eq!(decode_headers(&data).unwrap(), fields);
+
+        let len = data.len();
+        data.push(0);
+        assert_eq!(
+            decode_headers(&data),
+            Err(DecodeError::TrailingGarbage { offset: len })
+        );
+        assert!(decode_headers(b"not a header map").is_err());
+    }
 }
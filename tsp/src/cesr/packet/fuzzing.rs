@@ -1,48 +1,81 @@
 use super::*;
+use arbitrary::{Arbitrary, Unstructured};
+
+/// The digests an arbitrary payload can refer to; a `Payload<'static, ..>`
+/// can only borrow digests that live forever
+static DIGESTS: [[u8; 32]; 3] = [
+    [0; 32],
+    {
+        let mut buf = [0; 32];
+        let mut i = 0;
+        while i < buf.len() {
+            buf[i] = i as u8;
+            i += 1;
+        }
+
+        buf
+    },
+    [0xff; 32],
+];
 
 #[derive(Debug)]
 pub struct Wrapper(pub Payload<'static, Vec<u8>, Vec<u8>>);
 
 impl<'a> arbitrary::Arbitrary<'a> for Wrapper {
     fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
-        const DIGEST: [u8; 32] = {
-            let mut buf = [0; 32];
-            let mut i = 0;
-            while i < buf.len() {
-                buf[i] = i as u8;
-                i += 1;
-            }
-
-            buf
-        };
-
         #[derive(arbitrary::Arbitrary)]
         enum Variants {
             GenericMessage,
+            ReplyMessage,
+            CompressedMessage,
             NestedMessage,
             RoutedMessage,
+            MultipartMessage,
+            GroupMessage,
+            Acknowledgement,
             DirectRelationProposal,
             DirectRelationAffirm,
             NestedRelationProposal,
             NestedRelationAffirm,
             NewIdentifierProposal,
             RelationshipReferral,
+            RelationshipReject,
+            RelationshipRenew,
+            SessionKey,
             RelationshipCancel,
+            NestedRelationCancel,
+            GroupMemberAdd,
+            GroupMemberRemove,
+            ExtensionMessage,
+            UnknownControl,
         }
 
         #[allow(dead_code)]
         fn check_exhaustive(payload: Payload<Vec<u8>, Vec<u8>>) -> Variants {
             match payload {
                 Payload::GenericMessage(_) => Variants::GenericMessage,
+                Payload::ReplyMessage { .. } => Variants::ReplyMessage,
+                Payload::CompressedMessage { .. } => Variants::CompressedMessage,
                 Payload::NestedMessage(_) => Variants::NestedMessage,
-                Payload::RoutedMessage(_, _) => Variants::RoutedMessage,
+                Payload::RoutedMessage(..) => Variants::RoutedMessage,
+                Payload::MultipartMessage(_) => Variants::MultipartMessage,
+                Payload::GroupMessage { .. } => Variants::GroupMessage,
+                Payload::Acknowledgement { .. } => Variants::Acknowledgement,
                 Payload::DirectRelationProposal { .. } => Variants::DirectRelationProposal,
                 Payload::DirectRelationAffirm { .. } => Variants::DirectRelationAffirm,
                 Payload::NestedRelationProposal { .. } => Variants::NestedRelationProposal,
                 Payload::NestedRelationAffirm { .. } => Variants::NestedRelationAffirm,
                 Payload::NewIdentifierProposal { .. } => Variants::NewIdentifierProposal,
                 Payload::RelationshipReferral { .. } => Variants::RelationshipReferral,
+                Payload::RelationshipReject { .. } => Variants::RelationshipReject,
+                Payload::RelationshipRenew { .. } => Variants::RelationshipRenew,
+                Payload::SessionKey { .. } => Variants::SessionKey,
                 Payload::RelationshipCancel { .. } => Variants::RelationshipCancel,
+                Payload::NestedRelationCancel { .. } => Variants::NestedRelationCancel,
+                Payload::GroupMemberAdd { .. } => Variants::GroupMemberAdd,
+                Payload::GroupMemberRemove { .. } => Variants::GroupMemberRemove,
+                Payload::ExtensionMessage { .. } => Variants::ExtensionMessage,
+                Payload::UnknownControl { .. } => Variants::UnknownControl,
             }
         }
 
@@ -54,37 +87,91 @@ impl<'a> arbitrary::Arbitrary<'a> for Wrapper {
             Digest::Blake2b256
         };
 
-        use arbitrary::Arbitrary;
         let payload = match variant {
             Variants::GenericMessage => Payload::GenericMessage(Arbitrary::arbitrary(u)?),
+            Variants::ReplyMessage => Payload::ReplyMessage {
+                reply: digest(u.choose(&DIGESTS)?),
+                message: Arbitrary::arbitrary(u)?,
+            },
+            Variants::CompressedMessage => Payload::CompressedMessage {
+                reply: if Arbitrary::arbitrary(u)? {
+                    Some(digest(u.choose(&DIGESTS)?))
+                } else {
+                    None
+                },
+                message: Arbitrary::arbitrary(u)?,
+            },
             Variants::NestedMessage => Payload::NestedMessage(Arbitrary::arbitrary(u)?),
-            Variants::RoutedMessage => {
-                Payload::RoutedMessage(Arbitrary::arbitrary(u)?, Arbitrary::arbitrary(u)?)
-            }
+            Variants::RoutedMessage => Payload::RoutedMessage(
+                Arbitrary::arbitrary(u)?,
+                Arbitrary::arbitrary(u)?,
+                Arbitrary::arbitrary(u)?,
+            ),
+            Variants::MultipartMessage => Payload::MultipartMessage(Arbitrary::arbitrary(u)?),
+            Variants::GroupMessage => Payload::GroupMessage {
+                group: Arbitrary::arbitrary(u)?,
+                message: Arbitrary::arbitrary(u)?,
+            },
+            Variants::Acknowledgement => Payload::Acknowledgement {
+                reply: digest(u.choose(&DIGESTS)?),
+            },
             Variants::DirectRelationProposal => Payload::DirectRelationProposal {
                 nonce: Nonce(Arbitrary::arbitrary(u)?),
                 hops: Arbitrary::arbitrary(u)?,
             },
             Variants::DirectRelationAffirm => Payload::DirectRelationAffirm {
-                reply: digest(&DIGEST),
+                reply: digest(u.choose(&DIGESTS)?),
             },
             Variants::NestedRelationProposal => Payload::NestedRelationProposal {
                 nonce: Nonce(Arbitrary::arbitrary(u)?),
                 message: Arbitrary::arbitrary(u)?,
             },
             Variants::NestedRelationAffirm => Payload::NestedRelationAffirm {
-                reply: digest(&DIGEST),
+                reply: digest(u.choose(&DIGESTS)?),
                 message: Arbitrary::arbitrary(u)?,
             },
             Variants::NewIdentifierProposal => Payload::NewIdentifierProposal {
-                thread_id: digest(&DIGEST),
+                thread_id: digest(u.choose(&DIGESTS)?),
                 new_vid: Arbitrary::arbitrary(u)?,
             },
             Variants::RelationshipReferral => Payload::RelationshipReferral {
                 referred_vid: Arbitrary::arbitrary(u)?,
             },
+            Variants::RelationshipReject => Payload::RelationshipReject {
+                reply: digest(u.choose(&DIGESTS)?),
+                reason: Arbitrary::arbitrary(u)?,
+            },
+            Variants::RelationshipRenew => Payload::RelationshipRenew {
+                reply: digest(u.choose(&DIGESTS)?),
+                expires_at: Arbitrary::arbitrary(u)?,
+            },
+            Variants::SessionKey => Payload::SessionKey {
+                reply: digest(u.choose(&DIGESTS)?),
+                seed: u.choose(&DIGESTS)?,
+            },
             Variants::RelationshipCancel => Payload::RelationshipCancel {
-                reply: digest(&DIGEST),
+                reply: digest(u.choose(&DIGESTS)?),
+            },
+            Variants::NestedRelationCancel => Payload::NestedRelationCancel {
+                nested_vid: Arbitrary::arbitrary(u)?,
+                reply: digest(u.choose(&DIGESTS)?),
+            },
+            Variants::GroupMemberAdd => Payload::GroupMemberAdd {
+                group: Arbitrary::arbitrary(u)?,
+                member: Arbitrary::arbitrary(u)?,
+            },
+            Variants::GroupMemberRemove => Payload::GroupMemberRemove {
+                group: Arbitrary::arbitrary(u)?,
+                member: Arbitrary::arbitrary(u)?,
+            },
+            Variants::ExtensionMessage => Payload::ExtensionMessage {
+                typecode: Arbitrary::arbitrary(u)?,
+                data: Arbitrary::arbitrary(u)?,
+            },
+            // no message type starts with 0x7e
+            Variants::UnknownControl => Payload::UnknownControl {
+                typecode: [0x7e, Arbitrary::arbitrary(u)?],
+                raw: Arbitrary::arbitrary(u)?,
             },
         };
 
@@ -96,9 +183,38 @@ impl<'a> PartialEq<Payload<'a, &'a mut [u8], &'a [u8]>> for Wrapper {
     fn eq(&self, other: &Payload<'a, &'a mut [u8], &'a [u8]>) -> bool {
         match (&self.0, other) {
             (Payload::GenericMessage(l0), Payload::GenericMessage(r0)) => l0 == r0,
+            (
+                Payload::ReplyMessage {
+                    reply: l_reply,
+                    message: l_msg,
+                },
+                Payload::ReplyMessage {
+                    reply: r_reply,
+                    message: r_msg,
+                },
+            ) => l_reply == r_reply && l_msg == r_msg,
+            (
+                Payload::CompressedMessage {
+                    reply: l_reply,
+                    message: l_msg,
+                },
+                Payload::CompressedMessage {
+                    reply: r_reply,
+                    message: r_msg,
+                },
+            ) => l_reply == r_reply && l_msg == r_msg,
             (Payload::NestedMessage(l0), Payload::NestedMessage(r0)) => l0 == r0,
-            (Payload::RoutedMessage(l0, l1), Payload::RoutedMessage(r0, r1)) => {
-                l0 == r0 && l1 == r1
+            (Payload::RoutedMessage(l0, l1, l2), Payload::RoutedMessage(r0, r1, r2)) => {
+                l0 == r0 && l1 == r1 && l2 == r2
+            }
+            (Payload::MultipartMessage(l0), Payload::MultipartMessage(r0)) => {
+                l0.len() == r0.len()
+                    && l0
+                        .iter()
+                        .zip(r0)
+                        .all(|((l_type, l_data), (r_type, r_data))| {
+                            l_type == r_type && l_data == r_data
+                        })
             }
             (
                 Payload::DirectRelationProposal {
@@ -152,11 +268,257 @@ impl<'a> PartialEq<Payload<'a, &'a mut [u8], &'a [u8]>> for Wrapper {
                     referred_vid: r_vid,
                 },
             ) => l_vid == r_vid,
+            (
+                Payload::RelationshipReject {
+                    reply: l_reply,
+                    reason: l_reason,
+                },
+                Payload::RelationshipReject {
+                    reply: r_reply,
+                    reason: r_reason,
+                },
+            ) => l_reply == r_reply && l_reason == r_reason,
+            (
+                Payload::RelationshipRenew {
+                    reply: l_reply,
+                    expires_at: l_expires_at,
+                },
+                Payload::RelationshipRenew {
+                    reply: r_reply,
+                    expires_at: r_expires_at,
+                },
+            ) => l_reply == r_reply && l_expires_at == r_expires_at,
+            (
+                Payload::SessionKey {
+                    reply: l_reply,
+                    seed: l_seed,
+                },
+                Payload::SessionKey {
+                    reply: r_reply,
+                    seed: r_seed,
+                },
+            ) => l_reply == r_reply && l_seed == r_seed,
             (
                 Payload::RelationshipCancel { reply: l_reply },
                 Payload::RelationshipCancel { reply: r_reply },
             ) => l_reply == r_reply,
+            (
+                Payload::NestedRelationCancel {
+                    nested_vid: l_vid,
+                    reply: l_reply,
+                },
+                Payload::NestedRelationCancel {
+                    nested_vid: r_vid,
+                    reply: r_reply,
+                },
+            ) => l_vid == r_vid && l_reply == r_reply,
+            (
+                Payload::GroupMessage {
+                    group: l_group,
+                    message: l_msg,
+                },
+                Payload::GroupMessage {
+                    group: r_group,
+                    message: r_msg,
+                },
+            ) => l_group == r_group && l_msg == r_msg,
+            (
+                Payload::Acknowledgement { reply: l_reply },
+                Payload::Acknowledgement { reply: r_reply },
+            ) => l_reply == r_reply,
+            (
+                Payload::GroupMemberAdd {
+                    group: l_group,
+                    member: l_member,
+                },
+                Payload::GroupMemberAdd {
+                    group: r_group,
+                    member: r_member,
+                },
+            )
+            | (
+                Payload::GroupMemberRemove {
+                    group: l_group,
+                    member: l_member,
+                },
+                Payload::GroupMemberRemove {
+                    group: r_group,
+                    member: r_member,
+                },
+            ) => l_group == r_group && l_member == r_member,
+            (
+                Payload::ExtensionMessage {
+                    typecode: l_typecode,
+                    data: l_data,
+                },
+                Payload::ExtensionMessage {
+                    typecode: r_typecode,
+                    data: r_data,
+                },
+            ) => l_typecode == r_typecode && l_data == r_data,
+            (
+                Payload::UnknownControl {
+                    typecode: l_typecode,
+                    raw: l_raw,
+                },
+                Payload::UnknownControl {
+                    typecode: r_typecode,
+                    raw: r_raw,
+                },
+            ) => l_typecode == r_typecode && l_raw == r_raw,
             _ => false,
         }
     }
 }
+
+/// An arbitrary envelope, covering every combination of crypto and signature type
+#[derive(Debug)]
+pub struct EnvelopeWrapper {
+    pub crypto_type: CryptoType,
+    pub signature_type: SignatureType,
+    pub sender: Vec<u8>,
+    pub receiver: Option<Vec<u8>>,
+    pub nonconfidential_data: Option<Vec<u8>>,
+}
+
+impl<'a> Arbitrary<'a> for EnvelopeWrapper {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let crypto_type = CryptoType::try_from(u.int_in_range(0..=5)?)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        let signature_type = SignatureType::try_from(u.int_in_range(0..=1)?)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+
+        Ok(EnvelopeWrapper {
+            crypto_type,
+            signature_type,
+            sender: Arbitrary::arbitrary(u)?,
+            receiver: Arbitrary::arbitrary(u)?,
+            nonconfidential_data: Arbitrary::arbitrary(u)?,
+        })
+    }
+}
+
+impl EnvelopeWrapper {
+    pub fn envelope(&self) -> Envelope<'_, &[u8]> {
+        Envelope {
+            crypto_type: self.crypto_type.clone(),
+            signature_type: self.signature_type.clone(),
+            sender: &self.sender,
+            receiver: self.receiver.as_deref(),
+            nonconfidential_data: self.nonconfidential_data.as_deref(),
+        }
+    }
+
+    /// Encode a complete message: an ETS envelope with `ciphertext` if the crypto type
+    /// encrypts, an S envelope otherwise, followed by a dummy signature
+    pub fn encode(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        let mut data = Vec::new();
+
+        if self.crypto_type.is_encrypted() {
+            encode_ets_envelope(self.envelope(), &mut data)?;
+            encode_ciphertext(ciphertext, &mut data)?;
+        } else {
+            encode_s_envelope(self.envelope(), &mut data)?;
+        }
+
+        encode_signature(&[0x42; 64], &mut data);
+
+        Ok(data)
+    }
+}
+
+impl<'a> PartialEq<Envelope<'a, &'a [u8]>> for EnvelopeWrapper {
+    fn eq(&self, other: &Envelope<'a, &'a [u8]>) -> bool {
+        self.crypto_type == other.crypto_type
+            && self.signature_type == other.signature_type
+            && self.sender == other.sender
+            && self.receiver.as_deref() == other.receiver
+            && self.nonconfidential_data.as_deref() == other.nonconfidential_data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    const ITERATIONS: u64 = 2000;
+
+    /// Run `property` on values generated from random bytes, seeded by the iteration
+    /// so failures can be reproduced
+    fn check<T: for<'a> Arbitrary<'a>>(property: impl Fn(u64, T)) {
+        let mut data = vec![0; 512];
+
+        for seed in 0..ITERATIONS {
+            StdRng::seed_from_u64(seed).fill_bytes(&mut data);
+
+            if let Ok(value) = T::arbitrary(&mut Unstructured::new(&data)) {
+                property(seed, value);
+            }
+        }
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        check(
+            |seed, (Wrapper(payload), sender_identity): (Wrapper, Option<Vec<u8>>)| {
+                let mut encoded = Vec::new();
+                match encode_payload(&payload, sender_identity.as_deref(), &mut encoded) {
+                    Ok(()) => {}
+                    Err(EncodeError::MissingHops) => {
+                        assert!(
+                            matches!(&payload, Payload::RoutedMessage(hops, _, _) if hops.is_empty())
+                        );
+                        return;
+                    }
+                    Err(e) => panic!("seed {seed}: could not encode {payload:?}: {e:?}"),
+                }
+
+                let mut buffer = encoded.clone();
+                let decoded = decode_payload(&mut buffer)
+                    .unwrap_or_else(|e| panic!("seed {seed}: could not decode {payload:?}: {e:?}"));
+
+                assert!(
+                    Wrapper(payload.clone()) == decoded.payload,
+                    "seed {seed}: {payload:?} decoded as {:?}",
+                    decoded.payload
+                );
+                assert_eq!(decoded.sender_identity, sender_identity.as_deref());
+
+                let mut reencoded = Vec::new();
+                encode_payload(&decoded.payload, decoded.sender_identity, &mut reencoded).unwrap();
+                assert_eq!(encoded, reencoded, "seed {seed}: {payload:?}");
+            },
+        );
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        check(|seed, (wrapper, ciphertext): (EnvelopeWrapper, Vec<u8>)| {
+            let mut encoded = wrapper.encode(&ciphertext).unwrap();
+            let expected = encoded.clone();
+
+            let view = decode_envelope(&mut encoded)
+                .unwrap_or_else(|e| panic!("seed {seed}: could not decode {wrapper:?}: {e:?}"));
+            assert_eq!(view.as_challenge().signature, &[0x42; 64]);
+
+            let opened = view.into_opened::<&[u8]>().unwrap();
+            assert!(wrapper == opened.envelope, "seed {seed}: {wrapper:?}");
+
+            let decoded_ciphertext = opened.ciphertext.map(|data| data.to_vec());
+            let expected_ciphertext = wrapper.crypto_type.is_encrypted().then_some(ciphertext);
+            assert_eq!(decoded_ciphertext, expected_ciphertext, "seed {seed}");
+
+            let reencoded = EnvelopeWrapper {
+                crypto_type: opened.envelope.crypto_type,
+                signature_type: opened.envelope.signature_type,
+                sender: opened.envelope.sender.to_vec(),
+                receiver: opened.envelope.receiver.map(<[u8]>::to_vec),
+                nonconfidential_data: opened.envelope.nonconfidential_data.map(<[u8]>::to_vec),
+            }
+            .encode(decoded_ciphertext.as_deref().unwrap_or_default())
+            .unwrap();
+            assert_eq!(expected, reencoded, "seed {seed}");
+        });
+    }
+}
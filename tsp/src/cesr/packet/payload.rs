@@ -0,0 +1,427 @@
+//! Encoding and decoding of TSP payloads on their own, for applications that frame
+//! or sign messages themselves instead of using a [Store](crate::Store)
+//!
+//! ```
+//! use tsp::cesr::payload::{self, OwnedPayload};
+//!
+//! let encoded = payload::encode(&OwnedPayload::GenericMessage(b"hello".to_vec()), None).unwrap();
+//! let decoded = payload::decode(&encoded).unwrap();
+//!
+//! assert_eq!(decoded.payload, OwnedPayload::GenericMessage(b"hello".to_vec()));
+//! assert_eq!(decoded.sender_identity, None);
+//! ```
+
+use super::{decode_payload, encode_payload, Digest, Nonce, Payload};
+use crate::cesr::error::{DecodeError, EncodeError};
+
+/// A digest referred to by a payload, with the hash function that produced it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OwnedDigest {
+    Sha2_256([u8; 32]),
+    Blake2b256([u8; 32]),
+}
+
+impl OwnedDigest {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        match self {
+            OwnedDigest::Sha2_256(bytes) => bytes,
+            OwnedDigest::Blake2b256(bytes) => bytes,
+        }
+    }
+
+    fn as_digest(&self) -> Digest<'_> {
+        match self {
+            OwnedDigest::Sha2_256(bytes) => Digest::Sha2_256(bytes),
+            OwnedDigest::Blake2b256(bytes) => Digest::Blake2b256(bytes),
+        }
+    }
+}
+
+impl From<Digest<'_>> for OwnedDigest {
+    fn from(digest: Digest<'_>) -> Self {
+        match digest {
+            Digest::Sha2_256(bytes) => OwnedDigest::Sha2_256(*bytes),
+            Digest::Blake2b256(bytes) => OwnedDigest::Blake2b256(*bytes),
+        }
+    }
+}
+
+/// A TSP payload that owns its data; see [Payload] for the meaning of the variants
+#[derive(Debug, PartialEq, Eq)]
+pub enum OwnedPayload {
+    GenericMessage(Vec<u8>),
+    ReplyMessage {
+        reply: OwnedDigest,
+        message: Vec<u8>,
+    },
+    CompressedMessage {
+        reply: Option<OwnedDigest>,
+        message: Vec<u8>,
+    },
+    NestedMessage(Vec<u8>),
+    RoutedMessage(Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<u8>),
+    MultipartMessage(Vec<(Vec<u8>, Vec<u8>)>),
+    GroupMessage {
+        group: Vec<u8>,
+        message: Vec<u8>,
+    },
+    Acknowledgement {
+        reply: OwnedDigest,
+    },
+    DirectRelationProposal {
+        nonce: Nonce,
+        hops: Vec<Vec<u8>>,
+    },
+    DirectRelationAffirm {
+        reply: OwnedDigest,
+    },
+    NestedRelationProposal {
+        nonce: Nonce,
+        message: Vec<u8>,
+    },
+    NestedRelationAffirm {
+        message: Vec<u8>,
+        reply: OwnedDigest,
+    },
+    NewIdentifierProposal {
+        thread_id: OwnedDigest,
+        new_vid: Vec<u8>,
+    },
+    RelationshipReferral {
+        referred_vid: Vec<u8>,
+    },
+    RelationshipReject {
+        reply: OwnedDigest,
+        reason: Vec<u8>,
+    },
+    RelationshipRenew {
+        reply: OwnedDigest,
+        expires_at: u64,
+    },
+    SessionKey {
+        reply: OwnedDigest,
+        seed: [u8; 32],
+    },
+    RelationshipCancel {
+        reply: OwnedDigest,
+    },
+    NestedRelationCancel {
+        nested_vid: Vec<u8>,
+        reply: OwnedDigest,
+    },
+    GroupMemberAdd {
+        group: Vec<u8>,
+        member: Vec<u8>,
+    },
+    GroupMemberRemove {
+        group: Vec<u8>,
+        member: Vec<u8>,
+    },
+    ExtensionMessage {
+        typecode: u8,
+        data: Vec<u8>,
+    },
+    UnknownControl {
+        typecode: [u8; 2],
+        raw: Vec<u8>,
+    },
+}
+
+impl OwnedPayload {
+    /// Borrow this payload in the form the encoder takes
+    fn as_payload(&self) -> Payload<'_, &[u8], &[u8]> {
+        fn vids(vids: &[Vec<u8>]) -> Vec<&[u8]> {
+            vids.iter().map(Vec::as_slice).collect()
+        }
+
+        match self {
+            OwnedPayload::GenericMessage(message) => Payload::GenericMessage(message.as_slice()),
+            OwnedPayload::ReplyMessage { reply, message } => Payload::ReplyMessage {
+                reply: reply.as_digest(),
+                message: message.as_slice(),
+            },
+            OwnedPayload::CompressedMessage { reply, message } => Payload::CompressedMessage {
+                reply: reply.as_ref().map(OwnedDigest::as_digest),
+                message: message.as_slice(),
+            },
+            OwnedPayload::NestedMessage(message) => Payload::NestedMessage(message.as_slice()),
+            OwnedPayload::RoutedMessage(hops, annotations, message) => {
+                Payload::RoutedMessage(vids(hops), vids(annotations), message.as_slice())
+            }
+            OwnedPayload::MultipartMessage(segments) => Payload::MultipartMessage(
+                segments
+                    .iter()
+                    .map(|(content_type, data)| (content_type.as_slice(), data.as_slice()))
+                    .collect(),
+            ),
+            OwnedPayload::GroupMessage { group, message } => Payload::GroupMessage {
+                group: group.as_slice(),
+                message: message.as_slice(),
+            },
+            OwnedPayload::Acknowledgement { reply } => Payload::Acknowledgement {
+                reply: reply.as_digest(),
+            },
+            // the nonce is encoded as it was given, not reused for another message
+            OwnedPayload::DirectRelationProposal { nonce, hops } => {
+                Payload::DirectRelationProposal {
+                    nonce: Nonce(nonce.0),
+                    hops: vids(hops),
+                }
+            }
+            OwnedPayload::DirectRelationAffirm { reply } => Payload::DirectRelationAffirm {
+                reply: reply.as_digest(),
+            },
+            OwnedPayload::NestedRelationProposal { nonce, message } => {
+                Payload::NestedRelationProposal {
+                    nonce: Nonce(nonce.0),
+                    message: message.as_slice(),
+                }
+            }
+            OwnedPayload::NestedRelationAffirm { message, reply } => {
+                Payload::NestedRelationAffirm {
+                    message: message.as_slice(),
+                    reply: reply.as_digest(),
+                }
+            }
+            OwnedPayload::NewIdentifierProposal { thread_id, new_vid } => {
+                Payload::NewIdentifierProposal {
+                    thread_id: thread_id.as_digest(),
+                    new_vid: new_vid.as_slice(),
+                }
+            }
+            OwnedPayload::RelationshipReferral { referred_vid } => Payload::RelationshipReferral {
+                referred_vid: referred_vid.as_slice(),
+            },
+            OwnedPayload::RelationshipReject { reply, reason } => Payload::RelationshipReject {
+                reply: reply.as_digest(),
+                reason: reason.as_slice(),
+            },
+            OwnedPayload::RelationshipRenew { reply, expires_at } => Payload::RelationshipRenew {
+                reply: reply.as_digest(),
+                expires_at: *expires_at,
+            },
+            OwnedPayload::SessionKey { reply, seed } => Payload::SessionKey {
+                reply: reply.as_digest(),
+                seed,
+            },
+            OwnedPayload::RelationshipCancel { reply } => Payload::RelationshipCancel {
+                reply: reply.as_digest(),
+            },
+            OwnedPayload::NestedRelationCancel { nested_vid, reply } => {
+                Payload::NestedRelationCancel {
+                    nested_vid: nested_vid.as_slice(),
+                    reply: reply.as_digest(),
+                }
+            }
+            OwnedPayload::GroupMemberAdd { group, member } => Payload::GroupMemberAdd {
+                group: group.as_slice(),
+                member: member.as_slice(),
+            },
+            OwnedPayload::GroupMemberRemove { group, member } => Payload::GroupMemberRemove {
+                group: group.as_slice(),
+                member: member.as_slice(),
+            },
+            OwnedPayload::ExtensionMessage { typecode, data } => Payload::ExtensionMessage {
+                typecode: *typecode,
+                data: data.as_slice(),
+            },
+            OwnedPayload::UnknownControl { typecode, raw } => Payload::UnknownControl {
+                typecode: *typecode,
+                raw: raw.as_slice(),
+            },
+        }
+    }
+}
+
+impl<Bytes: AsRef<[u8]>, Vid: AsRef<[u8]>> From<Payload<'_, Bytes, Vid>> for OwnedPayload {
+    fn from(payload: Payload<'_, Bytes, Vid>) -> Self {
+        let bytes = |bytes: Bytes| bytes.as_ref().to_vec();
+        let vid = |vid: Vid| vid.as_ref().to_vec();
+        let vids = |vids: Vec<Vid>| -> Vec<Vec<u8>> { vids.into_iter().map(vid).collect() };
+
+        match payload {
+            Payload::GenericMessage(message) => OwnedPayload::GenericMessage(bytes(message)),
+            Payload::ReplyMessage { reply, message } => OwnedPayload::ReplyMessage {
+                reply: reply.into(),
+                message: bytes(message),
+            },
+            Payload::CompressedMessage { reply, message } => OwnedPayload::CompressedMessage {
+                reply: reply.map(Into::into),
+                message: bytes(message),
+            },
+            Payload::NestedMessage(message) => OwnedPayload::NestedMessage(bytes(message)),
+            Payload::RoutedMessage(hops, annotations, message) => {
+                OwnedPayload::RoutedMessage(vids(hops), vids(annotations), bytes(message))
+            }
+            Payload::MultipartMessage(segments) => OwnedPayload::MultipartMessage(
+                segments
+                    .into_iter()
+                    .map(|(content_type, data)| (vid(content_type), bytes(data)))
+                    .collect(),
+            ),
+            Payload::GroupMessage { group, message } => OwnedPayload::GroupMessage {
+                group: vid(group),
+                message: bytes(message),
+            },
+            Payload::Acknowledgement { reply } => OwnedPayload::Acknowledgement {
+                reply: reply.into(),
+            },
+            Payload::DirectRelationProposal { nonce, hops } => {
+                OwnedPayload::DirectRelationProposal {
+                    nonce,
+                    hops: vids(hops),
+                }
+            }
+            Payload::DirectRelationAffirm { reply } => OwnedPayload::DirectRelationAffirm {
+                reply: reply.into(),
+            },
+            Payload::NestedRelationProposal { nonce, message } => {
+                OwnedPayload::NestedRelationProposal {
+                    nonce,
+                    message: bytes(message),
+                }
+            }
+            Payload::NestedRelationAffirm { message, reply } => {
+                OwnedPayload::NestedRelationAffirm {
+                    message: bytes(message),
+                    reply: reply.into(),
+                }
+            }
+            Payload::NewIdentifierProposal { thread_id, new_vid } => {
+                OwnedPayload::NewIdentifierProposal {
+                    thread_id: thread_id.into(),
+                    new_vid: vid(new_vid),
+                }
+            }
+            Payload::RelationshipReferral { referred_vid } => OwnedPayload::RelationshipReferral {
+                referred_vid: vid(referred_vid),
+            },
+            Payload::RelationshipReject { reply, reason } => OwnedPayload::RelationshipReject {
+                reply: reply.into(),
+                reason: bytes(reason),
+            },
+            Payload::RelationshipRenew { reply, expires_at } => OwnedPayload::RelationshipRenew {
+                reply: reply.into(),
+                expires_at,
+            },
+            Payload::SessionKey { reply, seed } => OwnedPayload::SessionKey {
+                reply: reply.into(),
+                seed: *seed,
+            },
+            Payload::RelationshipCancel { reply } => OwnedPayload::RelationshipCancel {
+                reply: reply.into(),
+            },
+            Payload::NestedRelationCancel { nested_vid, reply } => {
+                OwnedPayload::NestedRelationCancel {
+                    nested_vid: vid(nested_vid),
+                    reply: reply.into(),
+                }
+            }
+            Payload::GroupMemberAdd { group, member } => OwnedPayload::GroupMemberAdd {
+                group: vid(group),
+                member: vid(member),
+            },
+            Payload::GroupMemberRemove { group, member } => OwnedPayload::GroupMemberRemove {
+                group: vid(group),
+                member: vid(member),
+            },
+            Payload::ExtensionMessage { typecode, data } => OwnedPayload::ExtensionMessage {
+                typecode,
+                data: bytes(data),
+            },
+            Payload::UnknownControl { typecode, raw } => OwnedPayload::UnknownControl {
+                typecode,
+                raw: bytes(raw),
+            },
+        }
+    }
+}
+
+/// A payload decoded by [decode]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Decoded {
+    pub payload: OwnedPayload,
+    /// The sender VID included in the payload, for messages sealed in ESSR mode
+    pub sender_identity: Option<Vec<u8>>,
+}
+
+/// Encode `payload`, including `sender_identity` as is done for messages sealed in ESSR mode
+pub fn encode(
+    payload: &OwnedPayload,
+    sender_identity: Option<&[u8]>,
+) -> Result<Vec<u8>, EncodeError> {
+    let payload = payload.as_payload();
+
+    let mut data = Vec::with_capacity(payload.calculate_size(sender_identity));
+    encode_payload(&payload, sender_identity, &mut data)?;
+
+    Ok(data)
+}
+
+/// Decode a payload encoded by [encode], or taken from an opened TSP message
+pub fn decode(data: &[u8]) -> Result<Decoded, DecodeError> {
+    let mut data = data.to_vec();
+    let decoded = decode_payload(&mut data)?;
+
+    Ok(Decoded {
+        payload: decoded.payload.into(),
+        sender_identity: decoded.sender_identity.map(<[u8]>::to_vec),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn turn_around() {
+        let payloads = [
+            OwnedPayload::ReplyMessage {
+                reply: OwnedDigest::Sha2_256([1; 32]),
+                message: b"pong".to_vec(),
+            },
+            OwnedPayload::CompressedMessage {
+                reply: None,
+                message: b"deflated".to_vec(),
+            },
+            OwnedPayload::RoutedMessage(
+                vec![b"did:example:bob".to_vec()],
+                vec![b"note".to_vec()],
+                b"hello".to_vec(),
+            ),
+            OwnedPayload::MultipartMessage(vec![(b"text/plain".to_vec(), b"hello".to_vec())]),
+            OwnedPayload::DirectRelationProposal {
+                nonce: Nonce::generate(|bytes| *bytes = [2; 32]),
+                hops: Vec::new(),
+            },
+            OwnedPayload::NestedRelationCancel {
+                nested_vid: b"did:example:alice".to_vec(),
+                reply: OwnedDigest::Blake2b256([3; 32]),
+            },
+            OwnedPayload::ExtensionMessage {
+                typecode: 7,
+                data: b"consent receipt".to_vec(),
+            },
+            OwnedPayload::UnknownControl {
+                typecode: [9, 1],
+                raw: b"from a newer peer".to_vec(),
+            },
+        ];
+
+        for payload in payloads {
+            let encoded = encode(&payload, Some(b"did:example:alice")).unwrap();
+            let decoded = decode(&encoded).unwrap();
+
+            assert_eq!(decoded.payload, payload);
+            assert_eq!(
+                decoded.sender_identity.as_deref(),
+                Some(&b"did:example:alice"[..])
+            );
+        }
+    }
+
+    #[test]
+    fn reject_garbage() {
+        assert!(decode(b"not a payload").is_err());
+    }
+}
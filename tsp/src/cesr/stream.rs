@@ -0,0 +1,290 @@
+use super::{error::DecodeError, message_length};
+
+/// The domain a CESR encoded TSP message is in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Domain {
+    /// Base64 characters, e.g. for text based transports
+    Text,
+    /// Raw bytes
+    Binary,
+}
+
+impl Domain {
+    /// The domain of the message starting with `byte`, or `None` if no TSP message starts
+    /// with it; every message starts with a count code, which is "-" in the text domain
+    pub fn detect(byte: u8) -> Option<Domain> {
+        match byte >> 5 {
+            0b001 => Some(Domain::Text),
+            0b111 => Some(Domain::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Splits CESR data that arrives in chunks, e.g. from a TCP connection, into complete TSP
+/// messages in the binary domain
+///
+/// Every message can be in either domain; messages in the text domain are converted as
+/// their data arrives. Data that does not start like a TSP message is passed on as is, for
+/// the decoder to reject it.
+///
+/// ```
+/// # use tsp::cesr::FrameDecoder;
+/// let mut frames = FrameDecoder::new(1024 * 1024);
+///
+/// for chunk in [&b"not a "[..], &b"TSP message"[..]] {
+///     frames.push(chunk);
+///
+///     while let Some(message) = frames.next_message().unwrap() {
+///         println!("received {} bytes", message.len());
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FrameDecoder {
+    /// Received data that is not converted or split off yet
+    pending: Vec<u8>,
+    /// The start of a message in the text domain, converted to the binary domain
+    converted: Vec<u8>,
+    max_size: usize,
+}
+
+impl FrameDecoder {
+    /// Split messages of at most `max_size` bytes in the binary domain; larger messages are
+    /// refused as soon as their envelope arrived
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            pending: Vec::new(),
+            converted: Vec::new(),
+            max_size,
+        }
+    }
+
+    /// Add the next chunk of received data
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+    }
+
+    /// Whether no part of a message is buffered, e.g. to tell whether a stream ended
+    /// halfway a message
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.converted.is_empty()
+    }
+
+    /// Take the next complete message, in the binary domain, or `None` if more data is
+    /// needed; after an error, the stream cannot be split any further
+    pub fn next_message(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        let domain = self.pending.first().copied().map(Domain::detect);
+
+        #[cfg(feature = "cesr-t")]
+        if !self.converted.is_empty() || matches!(domain, Some(Some(Domain::Text))) {
+            return self.next_text_message();
+        }
+
+        match domain {
+            None => Ok(None),
+            Some(Some(Domain::Binary)) => {
+                let len = complete_length(&self.pending, self.max_size)?;
+
+                Ok(len.map(|len| self.pending.drain(..len).collect()))
+            }
+            // not a TSP message, or in an unsupported domain
+            Some(_) => Ok(Some(std::mem::take(&mut self.pending))),
+        }
+    }
+
+    /// Take the next complete message in the text domain, converting as much of the pending
+    /// data as needed
+    #[cfg(feature = "cesr-t")]
+    fn next_text_message(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        loop {
+            if !self.converted.is_empty() {
+                if let Some(len) = complete_length(&self.converted, self.max_size)? {
+                    return Ok(Some(self.converted.drain(..len).collect()));
+                }
+            }
+
+            if !self.convert()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Convert the complete quadlets of Base64 characters at the start of the pending data
+    /// to the binary domain; returns whether anything was converted
+    #[cfg(feature = "cesr-t")]
+    fn convert(&mut self) -> Result<bool, DecodeError> {
+        use base64ct::{Base64UrlUnpadded, Encoding};
+
+        let text = self
+            .pending
+            .iter()
+            .take_while(|byte| byte.is_ascii_alphanumeric() || **byte == b'-' || **byte == b'_')
+            .count();
+        let text = text - text % 4;
+
+        if text == 0 {
+            // anything other than Base64 characters ends a message in the text domain
+            return match self.pending.len() {
+                0..=3 => Ok(false),
+                _ => Err(DecodeError::UnexpectedData {
+                    offset: self.converted.len(),
+                    expected: "text domain CESR",
+                    found: None,
+                }),
+            };
+        }
+
+        let start = self.converted.len();
+        self.converted.resize(start + text / 4 * 3, 0);
+        Base64UrlUnpadded::decode(&self.pending[..text], &mut self.converted[start..]).map_err(
+            |_| DecodeError::UnexpectedData {
+                offset: start,
+                expected: "text domain CESR",
+                found: None,
+            },
+        )?;
+        self.pending.drain(..text);
+
+        Ok(true)
+    }
+}
+
+/// The length of the message at the start of `data`, if all of it arrived
+fn complete_length(data: &[u8], max_size: usize) -> Result<Option<usize>, DecodeError> {
+    match message_length(data)? {
+        Some(len) if len > max_size => Err(DecodeError::MessageTooLarge {
+            size: len,
+            max_size,
+        }),
+        Some(len) if len <= data.len() => Ok(Some(len)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{OwnedVid, Store, VerifiedVid};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn sealed_messages() -> (Store, Vec<Vec<u8>>) {
+        let store = Store::new();
+        let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        let bob = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let messages = [&b"hello"[..], &[7; 10_000][..], &b""[..]]
+            .into_iter()
+            .map(|message| {
+                store
+                    .seal_message(
+                        alice.identifier(),
+                        bob.identifier(),
+                        Some(&b"extra"[..]),
+                        message,
+                    )
+                    .unwrap()
+                    .1
+            })
+            .chain([store
+                .sign_message(alice.identifier(), bob.identifier(), b"signed")
+                .unwrap()
+                .1])
+            .collect();
+
+        (store, messages)
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_split_binary() {
+        let (store, messages) = sealed_messages();
+        let stream = messages.concat();
+
+        for chunk_size in [1, 7, 1000, stream.len()] {
+            let mut frames = FrameDecoder::new(1024 * 1024);
+            let mut received = Vec::new();
+
+            for chunk in stream.chunks(chunk_size) {
+                frames.push(chunk);
+
+                while let Some(message) = frames.next_message().unwrap() {
+                    received.push(message);
+                }
+            }
+
+            assert_eq!(received, messages);
+            assert!(frames.is_empty());
+        }
+
+        let mut message = messages[0].clone();
+        assert!(store.open_message(&mut message).is_ok());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_incomplete_and_oversized() {
+        let (_, messages) = sealed_messages();
+
+        let mut frames = FrameDecoder::new(1024 * 1024);
+        frames.push(&messages[0][..messages[0].len() - 1]);
+        assert_eq!(frames.next_message(), Ok(None));
+        assert!(!frames.is_empty());
+
+        let mut frames = FrameDecoder::new(1000);
+        frames.push(&messages[1][..messages[1].len() - 1]);
+        assert!(matches!(
+            frames.next_message(),
+            Err(DecodeError::MessageTooLarge { max_size: 1000, .. })
+        ));
+
+        // other data is passed on as is
+        let mut frames = FrameDecoder::new(1000);
+        frames.push(b"Hello, world!");
+        assert_eq!(frames.next_message(), Ok(Some(b"Hello, world!".to_vec())));
+        assert!(frames.is_empty());
+    }
+
+    #[cfg(feature = "cesr-t")]
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_split_mixed_domains() {
+        use base64ct::{Base64UrlUnpadded, Encoding};
+
+        let (_, messages) = sealed_messages();
+        let text = |message: &[u8]| Base64UrlUnpadded::encode_string(message).into_bytes();
+        assert_eq!(Domain::detect(text(&messages[0])[0]), Some(Domain::Text));
+        assert_eq!(Domain::detect(messages[0][0]), Some(Domain::Binary));
+
+        let stream = [
+            text(&messages[0]),
+            messages[1].clone(),
+            text(&messages[2]),
+            text(&messages[3]),
+            messages[0].clone(),
+        ]
+        .concat();
+
+        for chunk_size in [1, 5, 4096, stream.len()] {
+            let mut frames = FrameDecoder::new(1024 * 1024);
+            let mut received = Vec::new();
+
+            for chunk in stream.chunks(chunk_size) {
+                frames.push(chunk);
+
+                while let Some(message) = frames.next_message().unwrap() {
+                    received.push(message);
+                }
+            }
+
+            assert_eq!(
+                received,
+                [&messages[..], &messages[..1]].concat(),
+                "chunks of {chunk_size} bytes"
+            );
+            assert!(frames.is_empty());
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Canonical test vectors, so other TSP implementations can check interoperability
+//! with this crate without running it
+//!
+//! A test vector contains the sender and receiver VIDs including their private keys,
+//! the plaintext and the sealed CESR message. Use [generate] to emit vectors for the
+//! cryptographic suite compiled into this build, and [replay] to check vectors
+//! produced by any implementation.
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cesr::{CryptoType, SignatureType},
+    crypto::{self, CryptoError},
+    definitions::Payload,
+    OwnedVid,
+};
+
+/// Error originating from generating or replaying test vectors
+#[derive(thiserror::Error, Debug)]
+pub enum ConformanceError {
+    #[error("invalid test vector JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid base64 in field {0}")]
+    Base64(&'static str),
+    #[error("unknown {0} {1}")]
+    UnknownType(&'static str, String),
+    #[error("crypto error: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("test vector '{0}' mismatch: {1}")]
+    Mismatch(String, &'static str),
+}
+
+/// A single test vector; binary fields are base64url encoded without padding
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestVector {
+    pub description: String,
+    pub crypto_type: String,
+    pub signature_type: String,
+    /// The sender VID including private keys, in the format of [OwnedVid]
+    pub sender: serde_json::Value,
+    /// The receiver VID including private keys, in the format of [OwnedVid]
+    pub receiver: serde_json::Value,
+    pub nonconfidential_data: Option<String>,
+    pub plaintext: String,
+    /// The sealed CESR message
+    pub message: String,
+}
+
+/// The outcome of replaying a single test vector
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Replay {
+    /// The message was opened and matched the expected contents
+    Passed,
+    /// The vector uses a cryptographic suite that is not compiled into this build
+    Skipped(String),
+}
+
+fn crypto_type_name(crypto_type: &CryptoType) -> String {
+    format!("{crypto_type:?}")
+}
+
+fn parse_crypto_type(name: &str) -> Result<CryptoType, ConformanceError> {
+    Ok(match name {
+        "Plaintext" => CryptoType::Plaintext,
+        "HpkeAuth" => CryptoType::HpkeAuth,
+        "HpkeEssr" => CryptoType::HpkeEssr,
+        "NaclAuth" => CryptoType::NaclAuth,
+        "NaclEssr" => CryptoType::NaclEssr,
+        "Session" => CryptoType::Session,
+        _ => {
+            return Err(ConformanceError::UnknownType(
+                "crypto type",
+                name.to_string(),
+            ))
+        }
+    })
+}
+
+fn parse_signature_type(name: &str) -> Result<SignatureType, ConformanceError> {
+    Ok(match name {
+        "NoSignature" => SignatureType::NoSignature,
+        "Ed25519" => SignatureType::Ed25519,
+        _ => {
+            return Err(ConformanceError::UnknownType(
+                "signature type",
+                name.to_string(),
+            ))
+        }
+    })
+}
+
+fn decode(field: &'static str, value: &str) -> Result<Vec<u8>, ConformanceError> {
+    Base64UrlUnpadded::decode_vec(value).map_err(|_| ConformanceError::Base64(field))
+}
+
+fn test_vid(name: &str) -> OwnedVid {
+    OwnedVid::bind(
+        format!("did:example:{name}"),
+        format!("tcp://127.0.0.1:1337/{name}").parse().unwrap(),
+    )
+}
+
+/// Generate test vectors with fresh keys for every message type supported by this build
+pub fn generate() -> Result<Vec<TestVector>, ConformanceError> {
+    let alice = test_vid("alice");
+    let bob = test_vid("bob");
+
+    let sender = serde_json::to_value(&alice)?;
+    let receiver = serde_json::to_value(&bob)?;
+
+    let vector = |description: &str,
+                  crypto_type: CryptoType,
+                  signature_type: SignatureType,
+                  nonconfidential_data: Option<&[u8]>,
+                  plaintext: &[u8],
+                  message: &[u8]| TestVector {
+        description: description.to_string(),
+        crypto_type: crypto_type_name(&crypto_type),
+        signature_type: format!("{signature_type:?}"),
+        sender: sender.clone(),
+        receiver: receiver.clone(),
+        nonconfidential_data: nonconfidential_data.map(Base64UrlUnpadded::encode_string),
+        plaintext: Base64UrlUnpadded::encode_string(plaintext),
+        message: Base64UrlUnpadded::encode_string(message),
+    };
+
+    let mut vectors = Vec::new();
+
+    let plaintext = b"hello world";
+    let signed = crypto::sign(&alice, Some(&bob), plaintext)?;
+    vectors.push(vector(
+        "signed non-confidential message",
+        CryptoType::Plaintext,
+        SignatureType::Ed25519,
+        None,
+        plaintext,
+        &signed,
+    ));
+
+    type Case<'a> = (&'a str, Option<&'a [u8]>, &'a [u8]);
+    let cases: [Case; 3] = [
+        ("confidential message", None, b"hello world"),
+        (
+            "confidential message with non-confidential data",
+            Some(b"extra header data"),
+            b"hello world",
+        ),
+        ("empty confidential message", None, b""),
+    ];
+
+    for (description, nonconfidential_data, plaintext) in cases {
+        let mut message = crypto::seal(
+            &alice,
+            &bob,
+            nonconfidential_data,
+            Payload::Content(plaintext),
+        )?;
+        let sealed = message.clone();
+        let (_, _, crypto_type, signature_type) = crypto::open(&bob, &alice, &mut message)?;
+
+        vectors.push(vector(
+            description,
+            crypto_type,
+            signature_type,
+            nonconfidential_data,
+            plaintext,
+            &sealed,
+        ));
+    }
+
+    Ok(vectors)
+}
+
+/// Check that the sealed message of `vector` opens to the expected contents
+pub fn replay(vector: &TestVector) -> Result<Replay, ConformanceError> {
+    let expected_crypto_type = parse_crypto_type(&vector.crypto_type)?;
+    let expected_signature_type = parse_signature_type(&vector.signature_type)?;
+    let mismatch = |reason| ConformanceError::Mismatch(vector.description.clone(), reason);
+
+    // key sizes differ between cryptographic suites; keys are decoded from
+    // borrowed strings, so the VIDs cannot be deserialized from a `Value` directly
+    let (Ok(sender), Ok(receiver)) = (
+        serde_json::from_str::<OwnedVid>(&vector.sender.to_string()),
+        serde_json::from_str::<OwnedVid>(&vector.receiver.to_string()),
+    ) else {
+        return Ok(Replay::Skipped(format!(
+            "keys of {} are not supported by this build",
+            vector.crypto_type
+        )));
+    };
+
+    let nonconfidential_data = vector
+        .nonconfidential_data
+        .as_deref()
+        .map(|data| decode("nonconfidentialData", data))
+        .transpose()?;
+    let plaintext = decode("plaintext", &vector.plaintext)?;
+    let mut message = decode("message", &vector.message)?;
+
+    if expected_crypto_type == CryptoType::Plaintext {
+        let (payload, _) = crypto::verify(&sender, &mut message)?;
+
+        if payload != plaintext {
+            return Err(mismatch("payload"));
+        }
+
+        return Ok(Replay::Passed);
+    }
+
+    let (received_data, payload, crypto_type, signature_type) =
+        crypto::open(&receiver, &sender, &mut message)?;
+
+    if crypto_type != expected_crypto_type {
+        return Err(mismatch("crypto type"));
+    }
+    if signature_type != expected_signature_type {
+        return Err(mismatch("signature type"));
+    }
+    if received_data != nonconfidential_data.as_deref() {
+        return Err(mismatch("non-confidential data"));
+    }
+    if payload.as_bytes() != plaintext.as_slice() {
+        return Err(mismatch("plaintext"));
+    }
+
+    Ok(Replay::Passed)
+}
+
+/// Serialize test vectors to pretty printed JSON
+pub fn to_json(vectors: &[TestVector]) -> Result<String, ConformanceError> {
+    Ok(serde_json::to_string_pretty(vectors)?)
+}
+
+/// Replay all test vectors in a JSON document as produced by [to_json]
+pub fn replay_json(json: &str) -> Result<Vec<Replay>, ConformanceError> {
+    let vectors: Vec<TestVector> = serde_json::from_str(json)?;
+
+    vectors.iter().map(replay).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_conformance_vectors() {
+        let vectors = generate().unwrap();
+        let json = to_json(&vectors).unwrap();
+
+        let results = replay_json(&json).unwrap();
+        assert_eq!(results.len(), vectors.len());
+        assert!(results.iter().all(|result| *result == Replay::Passed));
+
+        // a vector with the wrong plaintext must be rejected
+        let mut tampered = vectors[1].clone();
+        tampered.plaintext = Base64UrlUnpadded::encode_string(b"goodbye world");
+        assert!(matches!(
+            replay(&tampered),
+            Err(ConformanceError::Mismatch(_, "plaintext"))
+        ));
+
+        // a vector with an unknown crypto type is an error
+        tampered.crypto_type = "Rot13".to_string();
+        assert!(matches!(
+            replay(&tampered),
+            Err(ConformanceError::UnknownType(..))
+        ));
+    }
+}
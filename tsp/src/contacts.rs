@@ -0,0 +1,257 @@
+//! Onboarding the counterparties listed in a trust registry, see
+//! [AsyncStore::import_contacts](crate::AsyncStore::import_contacts)
+//!
+//! A registry is either a JSON array of [Contact]s, or CSV with a header row naming the
+//! columns `did`, `alias`, `fingerprints` and `metadata`. In CSV, the fingerprints are
+//! separated by spaces or semicolons and the metadata is a JSON object; other columns are
+//! added to the metadata as strings:
+//!
+//! ```csv
+//! did,alias,fingerprints,organization
+//! did:web:did.tsp-test.org:user:bob,bob,3f1a...c2 9b07...e4,ACME
+//! ```
+
+use serde::Deserialize;
+use sha2::Digest;
+
+use crate::{definitions::VerifiedVid, Error};
+
+/// A counterparty listed in a trust registry
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Contact {
+    pub did: String,
+    /// The name the application knows the counterparty by
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// The [fingerprints](key_fingerprint) of keys the counterparty publishes; each of
+    /// them has to match its verification or encryption key
+    #[serde(default)]
+    pub fingerprints: Vec<String>,
+    /// Stored as the application defined metadata of the VID
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// The outcome of importing one [Contact]
+#[derive(Debug)]
+pub struct ContactImport {
+    pub contact: Contact,
+    /// Whether the VID was verified and added to the store, or why not
+    pub result: Result<(), Error>,
+}
+
+/// The fingerprint of a public key: the lowercase hex encoded SHA-256 hash of the key
+pub fn key_fingerprint(key: &[u8]) -> String {
+    sha2::Sha256::digest(key)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Check that every fingerprint in `expected` matches a public key of `vid`; fingerprints
+/// are compared ignoring case and `:` separators
+pub fn check_fingerprints(vid: &dyn VerifiedVid, expected: &[String]) -> Result<(), Error> {
+    let published = [
+        key_fingerprint(vid.verifying_key().as_ref()),
+        key_fingerprint(vid.encryption_key().as_ref()),
+    ];
+
+    for fingerprint in expected {
+        let normalized = fingerprint.replace(':', "").to_ascii_lowercase();
+
+        if !published.contains(&normalized) {
+            return Err(Error::FingerprintMismatch(
+                vid.identifier().to_string(),
+                fingerprint.clone(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the contacts of a registry, in JSON if it starts with `[` and otherwise in CSV
+pub fn parse_registry(data: &str) -> Result<Vec<Contact>, Error> {
+    if data.trim_start().starts_with('[') {
+        return serde_json::from_str(data).map_err(|e| Error::InvalidRegistry(e.to_string()));
+    }
+
+    let mut records = csv_records(data)?.into_iter();
+    let Some(header) = records.next() else {
+        return Ok(Vec::new());
+    };
+
+    let header = header
+        .iter()
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if !header.iter().any(|column| column == "did") {
+        return Err(Error::InvalidRegistry("missing did column".to_string()));
+    }
+
+    records
+        .enumerate()
+        // skip empty lines
+        .filter(|(_, record)| record.iter().any(|field| !field.trim().is_empty()))
+        .map(|(index, record)| {
+            let mut contact = Contact::default();
+            let mut extra = serde_json::Map::new();
+
+            for (column, field) in header.iter().zip(record) {
+                let field = field.trim();
+
+                match column.as_str() {
+                    "did" => contact.did = field.to_string(),
+                    "alias" => {
+                        contact.alias = Some(field).filter(|a| !a.is_empty()).map(Into::into)
+                    }
+                    "fingerprints" => {
+                        contact.fingerprints = field
+                            .split([' ', ';'])
+                            .filter(|fingerprint| !fingerprint.is_empty())
+                            .map(Into::into)
+                            .collect()
+                    }
+                    "metadata" if !field.is_empty() => {
+                        contact.metadata = Some(serde_json::from_str(field).map_err(|e| {
+                            // the header is the first line
+                            Error::InvalidRegistry(format!("record {}: {e}", index + 2))
+                        })?)
+                    }
+                    "metadata" => {}
+                    _ => {
+                        extra.insert(column.clone(), field.into());
+                    }
+                }
+            }
+
+            if contact.did.is_empty() {
+                return Err(Error::InvalidRegistry(format!(
+                    "record {}: missing did",
+                    index + 2
+                )));
+            }
+
+            if !extra.is_empty() {
+                match &mut contact.metadata {
+                    Some(serde_json::Value::Object(metadata)) => metadata.extend(extra),
+                    Some(_) => {
+                        return Err(Error::InvalidRegistry(format!(
+                            "record {}: metadata is not an object",
+                            index + 2
+                        )))
+                    }
+                    None => contact.metadata = Some(extra.into()),
+                }
+            }
+
+            Ok(contact)
+        })
+        .collect()
+}
+
+/// Split CSV into records of fields; fields may be quoted with `"`, with `""` for a quote
+fn csv_records(data: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, '\r') => {}
+            (false, c) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(Error::InvalidRegistry(
+            "unterminated quoted field".to_string(),
+        ));
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OwnedVid;
+
+    #[test]
+    fn test_parse_registry() {
+        let csv = "DID,alias,fingerprints,metadata,organization\n\
+            did:web:example.com:user:alice,alice,aa:BB;cc,\"{\"\"tier\"\": 1}\",ACME\n\
+            \n\
+            did:web:example.com:user:bob,,,,\"Bob, Inc.\"\n";
+
+        let contacts = parse_registry(csv).unwrap();
+        assert_eq!(
+            contacts,
+            [
+                Contact {
+                    did: "did:web:example.com:user:alice".to_string(),
+                    alias: Some("alice".to_string()),
+                    fingerprints: vec!["aa:BB".to_string(), "cc".to_string()],
+                    metadata: Some(serde_json::json!({ "tier": 1, "organization": "ACME" })),
+                },
+                Contact {
+                    did: "did:web:example.com:user:bob".to_string(),
+                    metadata: Some(serde_json::json!({ "organization": "Bob, Inc." })),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let json = r#"[{ "did": "did:web:example.com:user:alice", "alias": "alice" }]"#;
+        assert_eq!(
+            parse_registry(json).unwrap()[0].alias.as_deref(),
+            Some("alice")
+        );
+
+        assert!(matches!(
+            parse_registry("alias\nalice\n"),
+            Err(Error::InvalidRegistry(_))
+        ));
+        assert!(matches!(
+            parse_registry("did,alias\n\"did:web:example.com,alice\n"),
+            Err(Error::InvalidRegistry(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_fingerprints() {
+        let vid = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        let fingerprint = key_fingerprint(vid.verifying_key().as_ref());
+
+        assert!(check_fingerprints(&vid, &[]).is_ok());
+        assert!(check_fingerprints(&vid, &[fingerprint.to_ascii_uppercase()]).is_ok());
+        assert!(matches!(
+            check_fingerprints(&vid, &[key_fingerprint(b"another key")]),
+            Err(Error::FingerprintMismatch(_, _))
+        ));
+    }
+}
@@ -21,4 +21,14 @@ pub enum CryptoError {
     UnexpectedSender,
     #[error("no sender identity found in encrypted message")]
     MissingSender,
+    #[error("could not decompress message, or it exceeds the maximum size")]
+    Decompress,
+    #[error("message was sealed with a session key, but no session was established")]
+    MissingSession,
+    #[error("no session key for the message: {0}")]
+    SessionKey(&'static str),
+    #[error("only generic messages can be sealed with a session key")]
+    SessionPayload,
+    #[error("the {0:?} cipher suite is not available in this build")]
+    UnsupportedCipherSuite(crate::definitions::CipherSuite),
 }
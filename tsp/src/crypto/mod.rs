@@ -1,16 +1,21 @@
 use crate::definitions::MessageType;
 use crate::definitions::{
-    Digest, NonConfidentialData, Payload, PrivateKeyData, PrivateSigningKeyData, PrivateVid,
-    PublicKeyData, PublicVerificationKeyData, TSPMessage, VerifiedVid,
+    CipherSuite, Digest, DigestAlgorithm, NonConfidentialData, Padding, Payload, PrivateKeyData,
+    PrivateSigningKeyData, PrivateVid, PublicKeyData, PublicVerificationKeyData, SealOptions,
+    TSPMessage, VerifiedVid,
 };
 
 pub use digest::blake2b256;
 pub use digest::sha256;
-use rand::rngs::OsRng;
+use rand::{
+    rngs::{OsRng, StdRng},
+    CryptoRng, RngCore, SeedableRng,
+};
 
 mod digest;
 pub mod error;
 mod nonconfidential;
+pub(crate) mod session;
 
 mod tsp_hpke;
 #[cfg(not(feature = "pq"))]
@@ -18,7 +23,6 @@ mod tsp_nacl;
 
 pub use error::CryptoError;
 
-#[cfg(not(feature = "pq"))]
 use crate::cesr::CryptoType;
 
 #[cfg(not(feature = "pq"))]
@@ -49,6 +53,29 @@ pub fn seal(
     seal_and_hash(sender, receiver, nonconfidential_data, payload, None)
 }
 
+/// Same as [seal], but draws all randomness from `rng`; with a seeded RNG the
+/// sealed message is reproducible, which is useful for test vectors
+pub fn seal_with_rng(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<TSPMessage, CryptoError> {
+    let mut msg = Vec::with_capacity(64);
+    seal_and_hash_into_with_rng(
+        sender,
+        receiver,
+        nonconfidential_data,
+        payload,
+        None,
+        &mut msg,
+        rng,
+    )?;
+
+    Ok(msg)
+}
+
 /// Encrypt, authenticate and sign and CESR encode a TSP message; also returns the hash value of the plaintext parts before encryption
 pub fn seal_and_hash(
     sender: &dyn PrivateVid,
@@ -57,16 +84,142 @@ pub fn seal_and_hash(
     payload: Payload<&[u8]>,
     digest: Option<&mut Digest>,
 ) -> Result<TSPMessage, CryptoError> {
-    #[cfg(not(feature = "nacl"))]
-    let msg =
-        tsp_hpke::seal::<Aead, Kdf, Kem>(sender, receiver, nonconfidential_data, payload, digest)?;
+    seal_and_hash_with_options(
+        sender,
+        receiver,
+        nonconfidential_data,
+        payload,
+        digest,
+        SealOptions::default(),
+    )
+}
 
-    #[cfg(feature = "nacl")]
-    let msg = tsp_nacl::seal(sender, receiver, nonconfidential_data, payload, digest)?;
+/// Same as [seal_and_hash], but with per-message `options`, e.g. whether to use ESSR
+pub fn seal_and_hash_with_options(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+    options: SealOptions,
+) -> Result<TSPMessage, CryptoError> {
+    let mut msg = Vec::with_capacity(64);
+    seal_and_hash_into_with_options(
+        sender,
+        receiver,
+        nonconfidential_data,
+        payload,
+        digest,
+        &mut msg,
+        options,
+    )?;
 
     Ok(msg)
 }
 
+/// Encrypt, authenticate and sign and CESR encode a TSP message, appending it to `output`;
+/// the payload is encrypted in place, so no allocations are needed if `output` has enough capacity
+pub fn seal_and_hash_into(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+    output: &mut Vec<u8>,
+) -> Result<(), CryptoError> {
+    seal_and_hash_into_with_options(
+        sender,
+        receiver,
+        nonconfidential_data,
+        payload,
+        digest,
+        output,
+        SealOptions::default(),
+    )
+}
+
+/// Same as [seal_and_hash_into], but with per-message `options`, e.g. whether to use ESSR
+pub fn seal_and_hash_into_with_options(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+    output: &mut Vec<u8>,
+    options: SealOptions,
+) -> Result<(), CryptoError> {
+    seal_into(
+        sender,
+        receiver,
+        nonconfidential_data,
+        payload,
+        digest,
+        output,
+        options,
+        &mut StdRng::from_entropy(),
+    )
+}
+
+/// Same as [seal_and_hash_into], but draws all randomness from `rng`
+pub fn seal_and_hash_into_with_rng(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+    output: &mut Vec<u8>,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<(), CryptoError> {
+    seal_into(
+        sender,
+        receiver,
+        nonconfidential_data,
+        payload,
+        digest,
+        output,
+        SealOptions::default(),
+        rng,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn seal_into(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+    output: &mut Vec<u8>,
+    options: SealOptions,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<(), CryptoError> {
+    match options.cipher_suite.unwrap_or_default() {
+        CipherSuite::Hpke => tsp_hpke::seal_into::<Aead, Kdf, Kem>(
+            sender,
+            receiver,
+            nonconfidential_data,
+            payload,
+            digest,
+            output,
+            options,
+            rng,
+        ),
+        #[cfg(not(feature = "pq"))]
+        CipherSuite::Nacl => tsp_nacl::seal_into(
+            sender,
+            receiver,
+            nonconfidential_data,
+            payload,
+            digest,
+            output,
+            options,
+            rng,
+        ),
+        #[cfg(feature = "pq")]
+        CipherSuite::Nacl => Err(CryptoError::UnsupportedCipherSuite(CipherSuite::Nacl)),
+    }
+}
+
 pub type MessageContents<'a> = (
     Option<NonConfidentialData<'a>>,
     Payload<'a, &'a [u8], &'a mut [u8]>,
@@ -79,6 +232,52 @@ pub fn open<'a>(
     receiver: &dyn PrivateVid,
     sender: &dyn VerifiedVid,
     tsp_message: &'a mut [u8],
+) -> Result<MessageContents<'a>, CryptoError> {
+    open_and_hash(receiver, sender, tsp_message, None)
+}
+
+/// Same as [open], but also compute the digest of the payload, which is the same digest
+/// [seal_and_hash] computed when the message was sealed
+pub fn open_and_hash<'a>(
+    receiver: &dyn PrivateVid,
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a mut [u8],
+    digest: Option<&mut Digest>,
+) -> Result<MessageContents<'a>, CryptoError> {
+    open_and_hash_with_algorithm(receiver, sender, tsp_message, digest, &mut None)
+}
+
+/// Same as [open_and_hash], but compute the digest with the `algorithm` agreed for the
+/// relationship with `sender`; without one, the algorithm that goes with the cryptographic
+/// suite of the message is used. If the payload refers to an earlier message, the digest
+/// algorithm of that reference is used instead. On return, `algorithm` holds the algorithm
+/// that was used, so the sender's choice can be recorded.
+pub fn open_and_hash_with_algorithm<'a>(
+    receiver: &dyn PrivateVid,
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a mut [u8],
+    digest: Option<&mut Digest>,
+    algorithm: &mut Option<DigestAlgorithm>,
+) -> Result<MessageContents<'a>, CryptoError> {
+    open_with_session(
+        receiver,
+        sender,
+        tsp_message,
+        digest,
+        algorithm,
+        &mut |_| Err(CryptoError::MissingSession),
+    )
+}
+
+/// Same as [open_and_hash_with_algorithm], but a message sealed with a session key is opened
+/// with the key that `session_key` returns for its counter
+pub(crate) fn open_with_session<'a>(
+    receiver: &dyn PrivateVid,
+    sender: &dyn VerifiedVid,
+    tsp_message: &'a mut [u8],
+    digest: Option<&mut Digest>,
+    algorithm: &mut Option<DigestAlgorithm>,
+    session_key: &mut dyn FnMut(u64) -> Result<session::MessageKey, CryptoError>,
 ) -> Result<MessageContents<'a>, CryptoError> {
     let view = crate::cesr::decode_envelope(tsp_message)?;
 
@@ -105,21 +304,131 @@ pub fn open<'a>(
         return Err(CryptoError::UnexpectedRecipient);
     }
 
+    let mut used = algorithm.unwrap_or_else(|| {
+        CipherSuite::of(&envelope.crypto_type)
+            .map(CipherSuite::digest_algorithm)
+            .unwrap_or_default()
+    });
+
     #[cfg(feature = "pq")]
-    return tsp_hpke::open::<Aead, Kdf, Kem>(receiver, sender, raw_header, envelope, ciphertext);
+    let contents = match envelope.crypto_type {
+        CryptoType::Session => session::open(
+            raw_header,
+            envelope,
+            ciphertext,
+            digest,
+            &mut used,
+            session_key,
+        ),
+        _ => tsp_hpke::open::<Aead, Kdf, Kem>(
+            receiver, sender, raw_header, envelope, ciphertext, digest, &mut used,
+        ),
+    };
 
     #[cfg(not(feature = "pq"))]
-    match envelope.crypto_type {
-        CryptoType::HpkeAuth | CryptoType::HpkeEssr => {
-            tsp_hpke::open::<Aead, Kdf, Kem>(receiver, sender, raw_header, envelope, ciphertext)
-        }
-        CryptoType::NaclAuth | CryptoType::NaclEssr => {
-            tsp_nacl::open(receiver, sender, raw_header, envelope, ciphertext)
-        }
+    let contents = match envelope.crypto_type {
+        CryptoType::HpkeAuth | CryptoType::HpkeEssr => tsp_hpke::open::<Aead, Kdf, Kem>(
+            receiver, sender, raw_header, envelope, ciphertext, digest, &mut used,
+        ),
+        CryptoType::NaclAuth | CryptoType::NaclEssr => tsp_nacl::open(
+            receiver, sender, raw_header, envelope, ciphertext, digest, &mut used,
+        ),
+        CryptoType::Session => session::open(
+            raw_header,
+            envelope,
+            ciphertext,
+            digest,
+            &mut used,
+            session_key,
+        ),
         CryptoType::Plaintext => Err(CryptoError::MissingCiphertext),
+    };
+
+    *algorithm = Some(used);
+
+    contents
+}
+
+/// Adopt the digest algorithm of an earlier message the decrypted `plaintext` refers to,
+/// and tell whether it proposes a relationship, so its thread id must be computed
+fn inspect_payload(
+    plaintext: &mut [u8],
+    algorithm: &mut DigestAlgorithm,
+) -> Result<bool, CryptoError> {
+    let payload = crate::cesr::decode_payload(plaintext)?.payload;
+
+    if let Some(digest) = payload.digest() {
+        *algorithm = digest.into();
+    }
+
+    Ok(matches!(
+        payload,
+        crate::cesr::Payload::DirectRelationProposal { .. }
+            | crate::cesr::Payload::NestedRelationProposal { .. }
+    ))
+}
+
+/// Compress the content of `payload` into `buffer` if it is larger than `threshold` bytes,
+/// see [SealOptions::compress_above]; other payloads are returned unchanged
+pub(crate) fn compress<'a>(
+    payload: Payload<'a, &'a [u8]>,
+    threshold: Option<usize>,
+    buffer: &'a mut Vec<u8>,
+) -> Payload<'a, &'a [u8]> {
+    let Some(threshold) = threshold else {
+        return payload;
+    };
+
+    let (message, in_reply_to) = match payload {
+        Payload::Content(message) => (message, None),
+        Payload::Reply {
+            message,
+            in_reply_to,
+        } => (message, Some(in_reply_to)),
+        _ => return payload,
+    };
+
+    if message.len() <= threshold {
+        return payload;
+    }
+
+    *buffer = miniz_oxide::deflate::compress_to_vec(message, COMPRESSION_LEVEL);
+    let compressed: &'a [u8] = buffer;
+
+    // data that is compressed already, e.g. images, only grows
+    if compressed.len() >= message.len() {
+        return payload;
+    }
+
+    Payload::Compressed {
+        message: compressed,
+        in_reply_to,
     }
 }
 
+/// A balance between speed and size, the default level of zlib
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// The number of bytes of padding that bring an encoded payload of `size` bytes to a length
+/// chosen by `padding`, see [SealOptions::padding]
+pub(crate) fn padding_size(size: usize, padding: Option<Padding>) -> usize {
+    let target = match padding {
+        None => return 0,
+        Some(Padding::Block(block)) => size.next_multiple_of(block.max(1)),
+        Some(Padding::PowerOfTwo) => size.next_power_of_two(),
+    };
+
+    // encoded fields are a multiple of 3 bytes long
+    target.next_multiple_of(3) - size
+}
+
+/// Decompress a message compressed with [SealOptions::compress_above], if it decompresses
+/// to at most `limit` bytes
+pub fn decompress(message: &[u8], limit: usize) -> Result<Vec<u8>, CryptoError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(message, limit)
+        .map_err(|_| CryptoError::Decompress)
+}
+
 /// Construct and sign a non-confidential TSP message
 pub fn sign(
     sender: &dyn PrivateVid,
@@ -137,12 +446,19 @@ pub fn verify<'a>(
     nonconfidential::verify(sender, tsp_message)
 }
 
-#[cfg(all(not(feature = "essr"), not(feature = "pq")))]
 /// Generate a new encryption / decryption key pair
 pub fn gen_encrypt_keypair() -> (PrivateKeyData, PublicKeyData) {
+    gen_encrypt_keypair_with_rng(&mut OsRng)
+}
+
+#[cfg(all(not(feature = "nacl"), not(feature = "pq")))]
+/// Generate a new encryption / decryption key pair from `rng`
+pub fn gen_encrypt_keypair_with_rng(
+    rng: &mut (impl RngCore + CryptoRng),
+) -> (PrivateKeyData, PublicKeyData) {
     use hpke::Serializable;
 
-    let (private, public) = <Kem as hpke::Kem>::gen_keypair(&mut OsRng);
+    let (private, public) = <Kem as hpke::Kem>::gen_keypair(rng);
 
     (
         Into::<[u8; 32]>::into(private.to_bytes()).into(),
@@ -151,12 +467,14 @@ pub fn gen_encrypt_keypair() -> (PrivateKeyData, PublicKeyData) {
 }
 
 #[cfg(feature = "pq")]
-/// Generate a new encryption / decryption key pair
-pub fn gen_encrypt_keypair() -> (PrivateKeyData, PublicKeyData) {
+/// Generate a new encryption / decryption key pair from `rng`
+pub fn gen_encrypt_keypair_with_rng(
+    rng: &mut (impl RngCore + CryptoRng),
+) -> (PrivateKeyData, PublicKeyData) {
     use crate::definitions::{PRIVATE_KEY_SIZE, PUBLIC_KEY_SIZE};
     use hpke_pq::Serializable;
 
-    let (private, public) = <Kem as hpke_pq::Kem>::gen_keypair(&mut OsRng);
+    let (private, public) = <Kem as hpke_pq::Kem>::gen_keypair(rng);
 
     let private = private.to_bytes();
     let public = public.to_bytes();
@@ -172,9 +490,11 @@ pub fn gen_encrypt_keypair() -> (PrivateKeyData, PublicKeyData) {
 }
 
 #[cfg(feature = "nacl")]
-/// Generate a new encryption / decryption key pair
-pub fn gen_encrypt_keypair() -> (PrivateKeyData, PublicKeyData) {
-    let private_key = crypto_box::SecretKey::generate(&mut OsRng);
+/// Generate a new encryption / decryption key pair from `rng`
+pub fn gen_encrypt_keypair_with_rng(
+    rng: &mut (impl RngCore + CryptoRng),
+) -> (PrivateKeyData, PublicKeyData) {
+    let private_key = crypto_box::SecretKey::generate(rng);
 
     (
         private_key.to_bytes().into(),
@@ -184,7 +504,14 @@ pub fn gen_encrypt_keypair() -> (PrivateKeyData, PublicKeyData) {
 
 /// Generate a new signing / verificationkey pair
 pub fn gen_sign_keypair() -> (PrivateSigningKeyData, PublicVerificationKeyData) {
-    let sigkey = ed25519_dalek::SigningKey::generate(&mut OsRng);
+    gen_sign_keypair_with_rng(&mut OsRng)
+}
+
+/// Generate a new signing / verification key pair from `rng`
+pub fn gen_sign_keypair_with_rng(
+    rng: &mut (impl RngCore + CryptoRng),
+) -> (PrivateSigningKeyData, PublicVerificationKeyData) {
+    let sigkey = ed25519_dalek::SigningKey::generate(rng);
 
     (
         sigkey.to_bytes().into(),
@@ -227,4 +554,209 @@ mod tests {
         assert_eq!(received_nonconfidential_data.unwrap(), nonconfidential_data);
         assert_eq!(received_secret_message, Payload::Content(secret_message));
     }
+    #[test]
+    fn seal_with_seeded_rng() {
+        use super::{gen_encrypt_keypair_with_rng, gen_sign_keypair_with_rng, seal_with_rng};
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let rng = || StdRng::seed_from_u64(1337);
+
+        assert_eq!(
+            gen_sign_keypair_with_rng(&mut rng()).1,
+            gen_sign_keypair_with_rng(&mut rng()).1
+        );
+        assert_eq!(
+            gen_encrypt_keypair_with_rng(&mut rng()).1,
+            gen_encrypt_keypair_with_rng(&mut rng()).1
+        );
+
+        let alice = OwnedVid::bind(
+            "did:test:alice",
+            Url::parse("tcp:://127.0.0.1:13371").unwrap(),
+        );
+        let bob = OwnedVid::bind(
+            "did:test:bob",
+            Url::parse("tcp:://127.0.0.1:13372").unwrap(),
+        );
+
+        let seal = |rng: &mut StdRng| {
+            seal_with_rng(
+                &bob,
+                &alice,
+                None,
+                Payload::RequestRelationship {
+                    route: None,
+                    thread_id: Default::default(),
+                },
+                rng,
+            )
+            .unwrap()
+        };
+
+        let mut message = seal(&mut rng());
+        assert_eq!(message, seal(&mut rng()));
+        assert_ne!(message, seal(&mut StdRng::seed_from_u64(1338)));
+
+        let (_, payload, _, _) = open(&alice, &bob, &mut message).unwrap();
+        assert!(matches!(payload, Payload::RequestRelationship { .. }));
+    }
+
+    #[test]
+    fn seal_open_essr_per_message() {
+        use super::seal_and_hash_with_options;
+        use crate::{cesr::CryptoType, definitions::SealOptions};
+
+        let alice = OwnedVid::bind(
+            "did:test:alice",
+            Url::parse("tcp:://127.0.0.1:13371").unwrap(),
+        );
+        let bob = OwnedVid::bind(
+            "did:test:bob",
+            Url::parse("tcp:://127.0.0.1:13372").unwrap(),
+        );
+
+        let secret_message: &[u8] = b"hello world";
+
+        for essr in [false, true] {
+            let mut message = seal_and_hash_with_options(
+                &bob,
+                &alice,
+                None,
+                Payload::Content(secret_message),
+                None,
+                SealOptions {
+                    essr,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let (_, received_secret_message, crypto_type, _) =
+                open(&alice, &bob, &mut message).unwrap();
+
+            assert_eq!(received_secret_message, Payload::Content(secret_message));
+
+            if cfg!(feature = "nacl") {
+                let expected = if essr {
+                    CryptoType::NaclEssr
+                } else {
+                    CryptoType::NaclAuth
+                };
+                assert_eq!(crypto_type, expected);
+            } else if !cfg!(feature = "pq") {
+                let expected = if essr {
+                    CryptoType::HpkeEssr
+                } else {
+                    CryptoType::HpkeAuth
+                };
+                assert_eq!(crypto_type, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn seal_open_cipher_suites() {
+        use super::{open_and_hash_with_algorithm, seal_and_hash_with_options, CryptoError};
+        use crate::definitions::{CipherSuite, SealOptions};
+
+        let alice = OwnedVid::bind(
+            "did:test:alice",
+            Url::parse("tcp:://127.0.0.1:13371").unwrap(),
+        );
+        let bob = OwnedVid::bind(
+            "did:test:bob",
+            Url::parse("tcp:://127.0.0.1:13372").unwrap(),
+        );
+
+        let secret_message: &[u8] = b"hello world";
+
+        for cipher_suite in [CipherSuite::Hpke, CipherSuite::Nacl] {
+            let mut sent_digest = Default::default();
+            let sealed = seal_and_hash_with_options(
+                &bob,
+                &alice,
+                None,
+                Payload::Content(secret_message),
+                Some(&mut sent_digest),
+                SealOptions {
+                    cipher_suite: Some(cipher_suite),
+                    ..Default::default()
+                },
+            );
+
+            if cfg!(feature = "pq") && cipher_suite == CipherSuite::Nacl {
+                assert!(matches!(
+                    sealed,
+                    Err(CryptoError::UnsupportedCipherSuite(CipherSuite::Nacl))
+                ));
+                continue;
+            }
+
+            let mut message = sealed.unwrap();
+            let mut received_digest = Default::default();
+            let mut algorithm = None;
+            let (_, received_secret_message, crypto_type, _) = open_and_hash_with_algorithm(
+                &alice,
+                &bob,
+                &mut message,
+                Some(&mut received_digest),
+                &mut algorithm,
+            )
+            .unwrap();
+
+            assert_eq!(received_secret_message, Payload::Content(secret_message));
+            assert_eq!(CipherSuite::of(&crypto_type), Some(cipher_suite));
+            assert_eq!(algorithm, Some(cipher_suite.digest_algorithm()));
+            assert_eq!(sent_digest, received_digest);
+        }
+    }
+
+    #[test]
+    fn seal_open_padded() {
+        use super::seal_and_hash_with_options;
+        use crate::definitions::{Padding, SealOptions};
+
+        let alice = OwnedVid::bind(
+            "did:test:alice",
+            Url::parse("tcp:://127.0.0.1:13371").unwrap(),
+        );
+        let bob = OwnedVid::bind(
+            "did:test:bob",
+            Url::parse("tcp:://127.0.0.1:13372").unwrap(),
+        );
+
+        let seal = |secret_message: &[u8], padding| {
+            seal_and_hash_with_options(
+                &bob,
+                &alice,
+                None,
+                Payload::Content(secret_message),
+                None,
+                SealOptions {
+                    padding,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        for padding in [Padding::Block(256), Padding::PowerOfTwo] {
+            // both fit in the same block, and below the same power of two
+            let mut short = seal(&[b'x'; 70], Some(padding));
+            let long = seal(&[b'x'; 100], Some(padding));
+            assert_eq!(short.len(), long.len());
+            assert!(short.len() > seal(&[b'x'; 70], None).len());
+
+            let (_, received_secret_message, _, _) = open(&alice, &bob, &mut short).unwrap();
+            assert_eq!(
+                received_secret_message,
+                Payload::Content(&[b'x'; 70] as &[u8])
+            );
+        }
+
+        // padding larger than a single field
+        let mut message = seal(b"hi", Some(Padding::Block(20_000)));
+        let (_, received_secret_message, _, _) = open(&alice, &bob, &mut message).unwrap();
+        assert_eq!(received_secret_message, Payload::Content(b"hi" as &[u8]));
+    }
 }
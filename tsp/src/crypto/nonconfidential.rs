@@ -1,6 +1,6 @@
 use crate::{
     cesr::{CryptoType, DecodedEnvelope, Envelope, SignatureType},
-    definitions::{MessageType, PrivateVid, TSPMessage, VerifiedVid},
+    definitions::{DigestAlgorithm, MessageType, PrivateVid, TSPMessage, VerifiedVid},
 };
 use ed25519_dalek::ed25519::signature::Signer;
 
@@ -26,7 +26,7 @@ pub fn sign(
     )?;
 
     // create and append signature
-    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
+    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key().expose_secret());
     let signature = sign_key.sign(&data).to_bytes();
     crate::cesr::encode_signature(&signature, &mut data);
 
@@ -70,6 +70,9 @@ pub fn verify<'a>(
         MessageType {
             crypto_type,
             signature_type,
+            // signed messages are identified by the SHA2-256 digest of their content
+            digest_algorithm: DigestAlgorithm::Sha2_256,
+            compressed: false,
         },
     ))
 }
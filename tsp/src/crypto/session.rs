@@ -0,0 +1,363 @@
+//! Symmetric session keys, to seal many generic messages on a relationship without an
+//! asymmetric operation per message
+//!
+//! A session is established by sending a random seed in a [Payload::SessionKey] control
+//! message, which is sealed like any other message. Both sides derive a chain key from the
+//! seed, the thread id of the relationship and the VIDs of the sender and receiver. Every
+//! message is encrypted with ChaCha20Poly1305 under the next key of the chain (a symmetric
+//! ratchet), so the current state does not reveal the keys of earlier messages. A session
+//! only protects the messages of its sender; the receiver establishes its own to reply.
+//!
+//! The ciphertext of a message is followed by the authentication tag and the position of
+//! its key in the chain, as an 8 byte big-endian counter. The outer signature is unchanged.
+
+use std::collections::BTreeMap;
+
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, Key, KeyInit};
+use ed25519_dalek::Signer;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::{CryptoError, MessageContents};
+use crate::{
+    cesr::{CryptoType, DecodedPayload, Envelope, SignatureType},
+    definitions::{
+        Digest, DigestAlgorithm, NonConfidentialData, Payload, PrivateVid, SealOptions, VerifiedVid,
+    },
+    secret::Secret,
+};
+
+/// A session is replaced after its sender sealed this many messages with it
+pub const MAX_MESSAGES: u64 = 1 << 20;
+
+/// A session is replaced once it is this old, in seconds
+pub const MAX_AGE: u64 = 24 * 60 * 60;
+
+/// The number of keys kept for messages that arrive out of order
+const MAX_SKIP: u64 = 1024;
+
+const TAG_SIZE: usize = 16;
+const COUNTER_SIZE: usize = 8;
+
+/// Separates the session keys from other uses of the seed
+const LABEL: &[u8] = b"TSP session key";
+
+/// The key of a single message
+pub(crate) type MessageKey = Zeroizing<[u8; 32]>;
+
+fn hmac(key: &[u8; 32], parts: &[&[u8]]) -> Zeroizing<[u8; 32]> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+
+    Zeroizing::new(mac.finalize().into_bytes().into())
+}
+
+/// The first chain key of the session from `sender` to `receiver`
+fn chain_key(
+    seed: &[u8; 32],
+    thread_id: &Digest,
+    sender: &str,
+    receiver: &str,
+) -> Secret<[u8; 32]> {
+    let key = hmac(
+        seed,
+        &[
+            LABEL,
+            thread_id,
+            sender.as_bytes(),
+            &[0],
+            receiver.as_bytes(),
+        ],
+    );
+
+    Secret::new(*key)
+}
+
+/// Take the next message key from `chain` and advance it
+fn ratchet(chain: &mut Secret<[u8; 32]>) -> MessageKey {
+    let message_key = hmac(chain.expose_secret(), &[&[1]]);
+    let next_chain = hmac(chain.expose_secret(), &[&[2]]);
+    *chain.expose_secret_mut() = *next_chain;
+
+    message_key
+}
+
+fn nonce(counter: u64) -> chacha20poly1305::Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+
+    nonce.into()
+}
+
+/// The sending side of a session
+pub(crate) struct SendingSession {
+    thread_id: Digest,
+    chain: Secret<[u8; 32]>,
+    counter: u64,
+    established_at: u64,
+}
+
+impl SendingSession {
+    pub(crate) fn new(
+        seed: &[u8; 32],
+        thread_id: Digest,
+        sender: &str,
+        receiver: &str,
+        now: u64,
+    ) -> Self {
+        Self {
+            thread_id,
+            chain: chain_key(seed, &thread_id, sender, receiver),
+            counter: 0,
+            established_at: now,
+        }
+    }
+
+    /// The thread id of the relationship this session was established for
+    pub(crate) fn thread_id(&self) -> &Digest {
+        &self.thread_id
+    }
+
+    /// Whether this session has to be replaced at `now`, in seconds since the Unix epoch
+    pub(crate) fn is_exhausted(&self, now: u64) -> bool {
+        self.counter >= MAX_MESSAGES || now >= self.established_at.saturating_add(MAX_AGE)
+    }
+
+    /// The key for the next message, with its counter
+    pub(crate) fn next_key(&mut self) -> (u64, MessageKey) {
+        let counter = self.counter;
+        self.counter += 1;
+
+        (counter, ratchet(&mut self.chain))
+    }
+}
+
+/// The receiving side of a session
+pub(crate) struct ReceivingSession {
+    thread_id: Digest,
+    chain: Secret<[u8; 32]>,
+    next: u64,
+    skipped: BTreeMap<u64, MessageKey>,
+}
+
+impl ReceivingSession {
+    pub(crate) fn new(seed: &[u8; 32], thread_id: Digest, sender: &str, receiver: &str) -> Self {
+        Self {
+            thread_id,
+            chain: chain_key(seed, &thread_id, sender, receiver),
+            next: 0,
+            skipped: BTreeMap::new(),
+        }
+    }
+
+    /// The thread id of the relationship this session was established for
+    pub(crate) fn thread_id(&self) -> &Digest {
+        &self.thread_id
+    }
+
+    /// The key of the message with `counter`; every key is handed out once, so a replayed
+    /// message cannot be opened. Keys of messages that have not arrived yet are kept,
+    /// up to a limit, in case they arrive out of order
+    pub(crate) fn key(&mut self, counter: u64) -> Result<MessageKey, CryptoError> {
+        if counter < self.next {
+            return self.skipped.remove(&counter).ok_or(CryptoError::SessionKey(
+                "message was replayed or arrived too late",
+            ));
+        }
+
+        if counter - self.next > MAX_SKIP {
+            return Err(CryptoError::SessionKey("too many messages were skipped"));
+        }
+
+        while self.next < counter {
+            self.skipped.insert(self.next, ratchet(&mut self.chain));
+            self.next += 1;
+        }
+
+        while self.skipped.len() as u64 > MAX_SKIP {
+            self.skipped.pop_first();
+        }
+
+        self.next += 1;
+
+        Ok(ratchet(&mut self.chain))
+    }
+}
+
+/// Seal a generic message with the message `key` of a session, and append it to `data`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn seal_into(
+    sender: &dyn PrivateVid,
+    receiver: &dyn VerifiedVid,
+    nonconfidential_data: Option<NonConfidentialData>,
+    secret_payload: Payload<&[u8]>,
+    digest: Option<&mut Digest>,
+    data: &mut Vec<u8>,
+    options: SealOptions,
+    (counter, key): (u64, MessageKey),
+) -> Result<(), CryptoError> {
+    let algorithm = options.digest.unwrap_or_default();
+
+    let envelope_start = data.len();
+    crate::cesr::encode_ets_envelope(
+        crate::cesr::Envelope {
+            crypto_type: CryptoType::Session,
+            signature_type: SignatureType::Ed25519,
+            sender: sender.identifier(),
+            receiver: Some(receiver.identifier()),
+            nonconfidential_data,
+        },
+        data,
+    )?;
+    let envelope_end = data.len();
+
+    let mut compressed = Vec::new();
+    let secret_payload = super::compress(secret_payload, options.compress_above, &mut compressed);
+
+    let secret_payload: crate::cesr::Payload<_, &[u8]> = match secret_payload {
+        Payload::Content(data) => crate::cesr::Payload::GenericMessage(data),
+        Payload::Reply {
+            message,
+            ref in_reply_to,
+        } => crate::cesr::Payload::ReplyMessage {
+            reply: algorithm.tag(in_reply_to),
+            message,
+        },
+        Payload::Compressed {
+            message,
+            ref in_reply_to,
+        } => crate::cesr::Payload::CompressedMessage {
+            reply: in_reply_to.as_ref().map(|digest| algorithm.tag(digest)),
+            message,
+        },
+        _ => return Err(CryptoError::SessionPayload),
+    };
+
+    let payload_size = secret_payload.calculate_size(None);
+    let padding = super::padding_size(payload_size, options.padding);
+    let ciphertext_size = payload_size + padding + TAG_SIZE + COUNTER_SIZE;
+
+    // prepare CESR-encoded ciphertext, which is encrypted in place after the envelope
+    crate::cesr::encode_ciphertext_header(ciphertext_size, data)?;
+    let plaintext_start = data.len();
+    data.reserve(ciphertext_size + 2 + 64);
+
+    crate::cesr::encode_payload(&secret_payload, None, data)?;
+    crate::cesr::encode_padding(padding, data);
+
+    // hash the raw bytes of the plaintext before encryption
+    if let Some(digest) = digest {
+        *digest = algorithm.hash(&data[plaintext_start..])
+    }
+
+    let (header, plaintext) = data.split_at_mut(plaintext_start);
+    let tag = ChaCha20Poly1305::new(Key::from_slice(&*key)).encrypt_in_place_detached(
+        &nonce(counter),
+        &header[envelope_start..envelope_end],
+        plaintext,
+    )?;
+
+    // append the authentication tag and counter to the end of the ciphertext
+    data.extend(tag);
+    data.extend(counter.to_be_bytes());
+
+    // create and append outer signature
+    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key().expose_secret());
+    let signature = sign_key.sign(&data[envelope_start..]).to_bytes();
+    crate::cesr::encode_signature(&signature, data);
+
+    Ok(())
+}
+
+/// Open a message sealed with a session key; `session_key` looks up the key for its counter
+pub(crate) fn open<'a>(
+    raw_header: &'a [u8],
+    envelope: Envelope<'a, &[u8]>,
+    ciphertext: &'a mut [u8],
+    digest: Option<&mut Digest>,
+    algorithm: &mut DigestAlgorithm,
+    session_key: &mut dyn FnMut(u64) -> Result<MessageKey, CryptoError>,
+) -> Result<MessageContents<'a>, CryptoError> {
+    if ciphertext.len() < TAG_SIZE + COUNTER_SIZE {
+        return Err(CryptoError::MissingCiphertext);
+    }
+
+    // split authentication tag and counter
+    let (ciphertext, footer) = ciphertext.split_at_mut(ciphertext.len() - TAG_SIZE - COUNTER_SIZE);
+    let (tag, counter) = footer.split_at(TAG_SIZE);
+    let counter = u64::from_be_bytes(counter.try_into().expect("counter has a fixed size"));
+
+    let key = session_key(counter)?;
+
+    // decrypt the ciphertext
+    ChaCha20Poly1305::new(Key::from_slice(&*key)).decrypt_in_place_detached(
+        &nonce(counter),
+        raw_header,
+        ciphertext,
+        tag.into(),
+    )?;
+
+    super::inspect_payload(ciphertext, algorithm)?;
+
+    if let Some(digest) = digest {
+        *digest = algorithm.hash(ciphertext);
+    }
+
+    let DecodedPayload { payload, .. } = crate::cesr::decode_payload(ciphertext)?;
+
+    let secret_payload = match payload {
+        crate::cesr::Payload::GenericMessage(data) => Payload::Content(data as _),
+        crate::cesr::Payload::ReplyMessage { reply, message } => Payload::Reply {
+            message: message as _,
+            in_reply_to: *reply.as_bytes(),
+        },
+        crate::cesr::Payload::CompressedMessage { reply, message } => Payload::Compressed {
+            message: message as _,
+            in_reply_to: reply.map(|reply| *reply.as_bytes()),
+        },
+        _ => return Err(CryptoError::SessionPayload),
+    };
+
+    Ok((
+        envelope.nonconfidential_data,
+        secret_payload,
+        envelope.crypto_type,
+        envelope.signature_type,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ratchet() {
+        let thread_id = [7; 32];
+        let mut sending = SendingSession::new(&[1; 32], thread_id, "alice", "bob", 0);
+        let mut receiving = ReceivingSession::new(&[1; 32], thread_id, "alice", "bob");
+
+        let keys = (0..4).map(|_| sending.next_key()).collect::<Vec<_>>();
+        assert_ne!(keys[0].1, keys[1].1);
+
+        // out of order delivery
+        assert_eq!(receiving.key(2).unwrap(), keys[2].1);
+        assert_eq!(receiving.key(0).unwrap(), keys[0].1);
+        assert_eq!(receiving.key(3).unwrap(), keys[3].1);
+        assert_eq!(receiving.key(1).unwrap(), keys[1].1);
+
+        // every key is handed out once
+        assert!(receiving.key(2).is_err());
+        assert!(receiving.key(4 + MAX_SKIP + 1).is_err());
+
+        // the direction of the session is part of the key
+        let mut reverse = ReceivingSession::new(&[1; 32], thread_id, "bob", "alice");
+        assert_ne!(reverse.key(0).unwrap(), keys[0].1);
+
+        assert!(!sending.is_exhausted(MAX_AGE - 1));
+        assert!(sending.is_exhausted(MAX_AGE));
+    }
+}
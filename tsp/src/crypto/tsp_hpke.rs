@@ -1,27 +1,19 @@
 use crate::{
-    cesr::{CryptoType, DecodedPayload, Envelope},
-    definitions::{Payload, PrivateVid, VerifiedVid},
+    cesr::{CryptoType, DecodedPayload, Envelope, SignatureType},
+    definitions::{
+        CipherSuite, DigestAlgorithm, MembershipChange, NonConfidentialData, Payload, PrivateVid,
+        SealOptions, VerifiedVid,
+    },
 };
 
-#[cfg(not(feature = "nacl"))]
-use crate::{
-    cesr::SignatureType,
-    definitions::{NonConfidentialData, TSPMessage},
-};
-
-#[cfg(not(feature = "nacl"))]
 use ed25519_dalek::Signer;
-#[cfg(not(feature = "nacl"))]
-use rand::{rngs::StdRng, SeedableRng};
 
 #[cfg(not(feature = "pq"))]
 use hpke::{
-    aead, kdf, kem, single_shot_open_in_place_detached, Deserializable, OpModeR, Serializable,
+    aead, kdf, kem, single_shot_open_in_place_detached, single_shot_seal_in_place_detached,
+    Deserializable, OpModeR, OpModeS, Serializable,
 };
 
-#[cfg(all(not(feature = "nacl"), not(feature = "pq")))]
-use hpke::{single_shot_seal_in_place_detached, OpModeS};
-
 #[cfg(feature = "pq")]
 use hpke_pq::{
     aead, kdf, kem, single_shot_open_in_place_detached, single_shot_seal_in_place_detached,
@@ -30,71 +22,154 @@ use hpke_pq::{
 
 use super::{CryptoError, MessageContents};
 
-#[cfg(not(feature = "nacl"))]
-pub(crate) fn seal<A, Kdf, Kem>(
+/// Seal a message and append it to `data`; the payload is encrypted in place in `data`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn seal_into<A, Kdf, Kem>(
     sender: &dyn PrivateVid,
     receiver: &dyn VerifiedVid,
     nonconfidential_data: Option<NonConfidentialData>,
     secret_payload: Payload<&[u8]>,
     digest: Option<&mut super::Digest>,
-) -> Result<TSPMessage, CryptoError>
+    data: &mut Vec<u8>,
+    options: SealOptions,
+    csprng: &mut (impl rand::RngCore + rand::CryptoRng),
+) -> Result<(), CryptoError>
 where
     A: aead::Aead,
     Kdf: kdf::Kdf,
     Kem: kem::Kem,
 {
-    let mut csprng = StdRng::from_entropy();
+    // the post-quantum KEM does not support the "Auth" mode, so the sender is always encrypted
+    let essr = options.essr || cfg!(feature = "pq");
+    let algorithm = options
+        .digest
+        .unwrap_or(CipherSuite::Hpke.digest_algorithm());
 
-    let mut data = Vec::with_capacity(64);
+    let envelope_start = data.len();
     crate::cesr::encode_ets_envelope(
         crate::cesr::Envelope {
-            crypto_type: CryptoType::HpkeAuth,
+            crypto_type: if essr {
+                CryptoType::HpkeEssr
+            } else {
+                CryptoType::HpkeAuth
+            },
             signature_type: SignatureType::Ed25519,
             sender: sender.identifier(),
             receiver: Some(receiver.identifier()),
             nonconfidential_data,
         },
-        &mut data,
+        data,
     )?;
+    let envelope_end = data.len();
+
+    let mut compressed = Vec::new();
+    let secret_payload = super::compress(secret_payload, options.compress_above, &mut compressed);
 
     let secret_payload = match secret_payload {
         Payload::Content(data) => crate::cesr::Payload::GenericMessage(data),
+        Payload::Reply {
+            message,
+            ref in_reply_to,
+        } => crate::cesr::Payload::ReplyMessage {
+            reply: algorithm.tag(in_reply_to),
+            message,
+        },
+        Payload::Compressed {
+            message,
+            ref in_reply_to,
+        } => crate::cesr::Payload::CompressedMessage {
+            reply: in_reply_to.as_ref().map(|digest| algorithm.tag(digest)),
+            message,
+        },
         Payload::RequestRelationship {
             route,
             thread_id: _ignored,
         } => crate::cesr::Payload::DirectRelationProposal {
-            nonce: fresh_nonce(&mut csprng),
+            nonce: fresh_nonce(csprng),
             hops: route.unwrap_or_else(Vec::new),
         },
         Payload::AcceptRelationship { ref thread_id } => {
             crate::cesr::Payload::DirectRelationAffirm {
-                reply: crate::cesr::Digest::Sha2_256(thread_id),
+                reply: algorithm.tag(thread_id),
             }
         }
         Payload::RequestNestedRelationship {
             inner,
             thread_id: _ignored,
         } => crate::cesr::Payload::NestedRelationProposal {
-            nonce: fresh_nonce(&mut csprng),
+            nonce: fresh_nonce(csprng),
             message: inner,
         },
         Payload::AcceptNestedRelationship {
             ref thread_id,
             inner,
         } => crate::cesr::Payload::NestedRelationAffirm {
-            reply: crate::cesr::Digest::Sha2_256(thread_id),
+            reply: algorithm.tag(thread_id),
             message: inner,
         },
+        Payload::RejectRelationship {
+            ref thread_id,
+            reason,
+        } => crate::cesr::Payload::RelationshipReject {
+            reply: algorithm.tag(thread_id),
+            reason,
+        },
+        Payload::RenewRelationship {
+            ref thread_id,
+            expires_at,
+        } => crate::cesr::Payload::RelationshipRenew {
+            reply: algorithm.tag(thread_id),
+            expires_at,
+        },
+        Payload::SessionKey {
+            ref thread_id,
+            seed,
+        } => crate::cesr::Payload::SessionKey {
+            reply: algorithm.tag(thread_id),
+            seed,
+        },
         Payload::CancelRelationship { ref thread_id } => crate::cesr::Payload::RelationshipCancel {
-            reply: crate::cesr::Digest::Sha2_256(thread_id),
+            reply: algorithm.tag(thread_id),
+        },
+        Payload::CancelNestedRelationship {
+            nested_vid,
+            ref thread_id,
+        } => crate::cesr::Payload::NestedRelationCancel {
+            nested_vid,
+            reply: algorithm.tag(thread_id),
         },
         Payload::NestedMessage(data) => crate::cesr::Payload::NestedMessage(data),
-        Payload::RoutedMessage(hops, data) => crate::cesr::Payload::RoutedMessage(hops, data),
+        Payload::RoutedMessage(hops, annotations, data) => {
+            crate::cesr::Payload::RoutedMessage(hops, annotations, data)
+        }
+        Payload::Multipart(segments) => crate::cesr::Payload::MultipartMessage(segments),
+        Payload::GroupMessage { group, message } => {
+            crate::cesr::Payload::GroupMessage { group, message }
+        }
+        Payload::Acknowledgement { ref digest } => crate::cesr::Payload::Acknowledgement {
+            reply: algorithm.tag(digest),
+        },
+        Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Added,
+        } => crate::cesr::Payload::GroupMemberAdd { group, member },
+        Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Removed,
+        } => crate::cesr::Payload::GroupMemberRemove { group, member },
+        Payload::Extension { typecode, data } => {
+            crate::cesr::Payload::ExtensionMessage { typecode, data }
+        }
+        Payload::UnknownControl { typecode, raw } => {
+            crate::cesr::Payload::UnknownControl { typecode, raw }
+        }
         Payload::NewIdentifier {
             ref thread_id,
             new_vid,
         } => crate::cesr::Payload::NewIdentifierProposal {
-            thread_id: crate::cesr::Digest::Sha2_256(thread_id),
+            thread_id: algorithm.tag(thread_id),
             new_vid,
         },
         Payload::Referral { referred_vid } => {
@@ -102,33 +177,39 @@ where
         }
     };
 
-    #[cfg(feature = "essr")]
-    let sender_in_payload = Some(sender.identifier().as_bytes());
-    #[cfg(not(feature = "essr"))]
-    let sender_in_payload = None;
+    let sender_in_payload = essr.then_some(sender.identifier().as_bytes());
+    let payload_size = secret_payload.calculate_size(sender_in_payload);
+    let padding = super::padding_size(payload_size, options.padding);
 
-    // prepare CESR-encoded ciphertext
-    let mut cesr_message = Vec::with_capacity(
+    let ciphertext_size =
         // plaintext size
-        secret_payload.calculate_size(sender_in_payload)
+        payload_size + padding
         // authenticated encryption tag length
         + aead::AeadTag::<A>::size()
         // encapsulated key length
-        + Kem::EncappedKey::size(),
-    );
+        + Kem::EncappedKey::size();
+
+    // prepare CESR-encoded ciphertext, which is encrypted in place after the envelope
+    crate::cesr::encode_ciphertext_header(ciphertext_size, data)?;
+    let plaintext_start = data.len();
+    data.reserve(ciphertext_size + 2 + 64);
 
-    crate::cesr::encode_payload(&secret_payload, sender_in_payload, &mut cesr_message)?;
+    crate::cesr::encode_payload(&secret_payload, sender_in_payload, data)?;
+    crate::cesr::encode_padding(padding, data);
 
-    // HPKE sender mode: "Auth" for ESSR and PQ features
-    #[cfg(all(not(feature = "essr"), not(feature = "pq")))]
-    let mode = {
-        let sender_decryption_key = Kem::PrivateKey::from_bytes(sender.decryption_key().as_ref())?;
+    // HPKE sender mode: "Base" for ESSR, since the sender is part of the payload
+    #[cfg(not(feature = "pq"))]
+    let mode = if essr {
+        OpModeS::Base
+    } else {
+        let sender_decryption_key =
+            Kem::PrivateKey::from_bytes(sender.decryption_key().expose_secret())?;
         let sender_encryption_key = Kem::PublicKey::from_bytes(sender.encryption_key().as_ref())?;
 
         OpModeS::Auth((sender_decryption_key, sender_encryption_key))
     };
 
-    #[cfg(any(feature = "essr", feature = "pq"))]
+    #[cfg(feature = "pq")]
     let mode = OpModeS::Base;
 
     // recipient public key
@@ -136,32 +217,30 @@ where
 
     // hash the raw bytes of the plaintext before encryption
     if let Some(digest) = digest {
-        *digest = crate::crypto::sha256(&cesr_message)
+        *digest = algorithm.hash(&data[plaintext_start..])
     }
 
     // perform encryption
-    let (encapped_key, tag) = single_shot_seal_in_place_detached::<A, Kdf, Kem, StdRng>(
+    let (header, plaintext) = data.split_at_mut(plaintext_start);
+    let (encapped_key, tag) = single_shot_seal_in_place_detached::<A, Kdf, Kem, _>(
         &mode,
         &message_receiver,
-        &data,
-        &mut cesr_message,
+        &header[envelope_start..envelope_end],
+        plaintext,
         &[],
-        &mut csprng,
+        csprng,
     )?;
 
     // append the authentication tag and encapsulated key to the end of the ciphertext
-    cesr_message.extend(tag.to_bytes());
-    cesr_message.extend(encapped_key.to_bytes());
-
-    // encode and append the ciphertext to the envelope data
-    crate::cesr::encode_ciphertext(&cesr_message, &mut data)?;
+    data.extend(tag.to_bytes());
+    data.extend(encapped_key.to_bytes());
 
     // create and append outer signature
-    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
-    let signature = sign_key.sign(&data).to_bytes();
-    crate::cesr::encode_signature(&signature, &mut data);
+    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key().expose_secret());
+    let signature = sign_key.sign(&data[envelope_start..]).to_bytes();
+    crate::cesr::encode_signature(&signature, data);
 
-    Ok(data)
+    Ok(())
 }
 
 pub(crate) fn open<'a, A, Kdf, Kem>(
@@ -170,6 +249,8 @@ pub(crate) fn open<'a, A, Kdf, Kem>(
     raw_header: &'a [u8],
     envelope: Envelope<'a, &[u8]>,
     ciphertext: &'a mut [u8],
+    digest: Option<&mut super::Digest>,
+    algorithm: &mut DigestAlgorithm,
 ) -> Result<MessageContents<'a>, CryptoError>
 where
     A: aead::Aead,
@@ -182,7 +263,8 @@ where
     let (tag, encapped_key) = footer.split_at(footer.len() - Kem::EncappedKey::size());
 
     // construct correct key types
-    let receiver_decryption_key = Kem::PrivateKey::from_bytes(receiver.decryption_key().as_ref())?;
+    let receiver_decryption_key =
+        Kem::PrivateKey::from_bytes(receiver.decryption_key().expose_secret())?;
     let encapped_key = Kem::EncappedKey::from_bytes(encapped_key)?;
     let tag = aead::AeadTag::from_bytes(tag)?;
 
@@ -210,12 +292,16 @@ where
 
     // micro-optimization: only compute the thread_id digest if we really need it; we cannot do this
     // later since after constructing the resulting Payload, we are giving out mutable borrows
-    let thread_id = match crate::cesr::decode_payload(ciphertext)?.payload {
-        crate::cesr::Payload::DirectRelationProposal { .. }
-        | crate::cesr::Payload::NestedRelationProposal { .. } => crate::crypto::sha256(ciphertext),
-        _ => Default::default(),
+    let thread_id = if super::inspect_payload(ciphertext, algorithm)? {
+        algorithm.hash(ciphertext)
+    } else {
+        Default::default()
     };
 
+    if let Some(digest) = digest {
+        *digest = algorithm.hash(ciphertext);
+    }
+
     #[allow(unused_variables)]
     let DecodedPayload {
         payload,
@@ -235,6 +321,14 @@ where
 
     let secret_payload = match payload {
         crate::cesr::Payload::GenericMessage(data) => Payload::Content(data as _),
+        crate::cesr::Payload::ReplyMessage { reply, message } => Payload::Reply {
+            message: message as _,
+            in_reply_to: *reply.as_bytes(),
+        },
+        crate::cesr::Payload::CompressedMessage { reply, message } => Payload::Compressed {
+            message: message as _,
+            in_reply_to: reply.map(|reply| *reply.as_bytes()),
+        },
         crate::cesr::Payload::DirectRelationProposal { hops, .. } => Payload::RequestRelationship {
             route: if hops.is_empty() { None } else { Some(hops) },
             thread_id,
@@ -251,11 +345,39 @@ where
                 thread_id: *reply.as_bytes(),
             }
         }
+        crate::cesr::Payload::RelationshipReject { reply, reason } => Payload::RejectRelationship {
+            thread_id: *reply.as_bytes(),
+            reason: reason as _,
+        },
+        crate::cesr::Payload::RelationshipRenew { reply, expires_at } => {
+            Payload::RenewRelationship {
+                thread_id: *reply.as_bytes(),
+                expires_at,
+            }
+        }
+        crate::cesr::Payload::SessionKey { reply, seed } => Payload::SessionKey {
+            thread_id: *reply.as_bytes(),
+            seed,
+        },
         crate::cesr::Payload::RelationshipCancel { reply, .. } => Payload::CancelRelationship {
             thread_id: *reply.as_bytes(),
         },
+        crate::cesr::Payload::NestedRelationCancel { nested_vid, reply } => {
+            Payload::CancelNestedRelationship {
+                nested_vid,
+                thread_id: *reply.as_bytes(),
+            }
+        }
         crate::cesr::Payload::NestedMessage(data) => Payload::NestedMessage(data),
-        crate::cesr::Payload::RoutedMessage(hops, data) => Payload::RoutedMessage(hops, data as _),
+        crate::cesr::Payload::RoutedMessage(hops, annotations, data) => {
+            Payload::RoutedMessage(hops, annotations, data as _)
+        }
+        crate::cesr::Payload::MultipartMessage(segments) => Payload::Multipart(
+            segments
+                .into_iter()
+                .map(|(content_type, data)| (content_type, data as _))
+                .collect(),
+        ),
         crate::cesr::Payload::NewIdentifierProposal { thread_id, new_vid } => {
             Payload::NewIdentifier {
                 thread_id: *thread_id.as_bytes(),
@@ -265,6 +387,31 @@ where
         crate::cesr::Payload::RelationshipReferral { referred_vid } => {
             Payload::Referral { referred_vid }
         }
+        crate::cesr::Payload::GroupMessage { group, message } => Payload::GroupMessage {
+            group,
+            message: message as _,
+        },
+        crate::cesr::Payload::Acknowledgement { reply } => Payload::Acknowledgement {
+            digest: *reply.as_bytes(),
+        },
+        crate::cesr::Payload::GroupMemberAdd { group, member } => Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Added,
+        },
+        crate::cesr::Payload::GroupMemberRemove { group, member } => Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Removed,
+        },
+        crate::cesr::Payload::ExtensionMessage { typecode, data } => Payload::Extension {
+            typecode,
+            data: data as _,
+        },
+        crate::cesr::Payload::UnknownControl { typecode, raw } => Payload::UnknownControl {
+            typecode,
+            raw: raw as _,
+        },
     };
 
     Ok((
@@ -276,7 +423,6 @@ where
 }
 
 /// Generate N random bytes using the provided RNG
-#[cfg(not(feature = "nacl"))]
 fn fresh_nonce(csprng: &mut (impl rand::RngCore + rand::CryptoRng)) -> crate::cesr::Nonce {
     crate::cesr::Nonce::generate(|dst| csprng.fill_bytes(dst))
 }
@@ -1,131 +1,206 @@
 use crate::{
-    cesr::{CryptoType, DecodedPayload, Envelope},
-    definitions::{Payload, PrivateVid, VerifiedVid},
+    cesr::{CryptoType, DecodedPayload, Envelope, SignatureType},
+    definitions::{
+        CipherSuite, DigestAlgorithm, MembershipChange, NonConfidentialData, Payload, PrivateVid,
+        SealOptions, VerifiedVid,
+    },
 };
-use crypto_box::{aead::AeadInPlace, ChaChaBox, PublicKey, SecretKey};
-
-#[cfg(feature = "nacl")]
-use crate::{
-    cesr::SignatureType,
-    definitions::{NonConfidentialData, TSPMessage},
+use crypto_box::{
+    aead::{AeadCore, AeadInPlace},
+    ChaChaBox, PublicKey, SecretKey,
 };
-#[cfg(feature = "nacl")]
-use crypto_box::aead::{AeadCore, OsRng};
-#[cfg(feature = "nacl")]
 use ed25519_dalek::Signer;
-#[cfg(feature = "nacl")]
-use rand::{rngs::StdRng, SeedableRng};
 
 use super::{CryptoError, MessageContents};
 
-#[cfg(feature = "nacl")]
-pub(crate) fn seal(
+/// Seal a message and append it to `data`; the payload is encrypted in place in `data`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn seal_into(
     sender: &dyn PrivateVid,
     receiver: &dyn VerifiedVid,
     nonconfidential_data: Option<NonConfidentialData>,
     secret_payload: Payload<&[u8]>,
     digest: Option<&mut super::Digest>,
-) -> Result<TSPMessage, CryptoError> {
-    let mut csprng = StdRng::from_entropy();
+    data: &mut Vec<u8>,
+    options: SealOptions,
+    csprng: &mut (impl rand::RngCore + rand::CryptoRng),
+) -> Result<(), CryptoError> {
+    let algorithm = options
+        .digest
+        .unwrap_or(CipherSuite::Nacl.digest_algorithm());
 
-    let mut data = Vec::with_capacity(64);
+    let envelope_start = data.len();
     crate::cesr::encode_ets_envelope(
         crate::cesr::Envelope {
-            crypto_type: CryptoType::NaclAuth,
+            crypto_type: if options.essr {
+                CryptoType::NaclEssr
+            } else {
+                CryptoType::NaclAuth
+            },
             signature_type: SignatureType::Ed25519,
             sender: sender.identifier(),
             receiver: Some(receiver.identifier()),
             nonconfidential_data,
         },
-        &mut data,
+        data,
     )?;
 
+    let mut compressed = Vec::new();
+    let secret_payload = super::compress(secret_payload, options.compress_above, &mut compressed);
+
     let secret_payload = match secret_payload {
         Payload::Content(data) => crate::cesr::Payload::GenericMessage(data),
+        Payload::Reply {
+            message,
+            ref in_reply_to,
+        } => crate::cesr::Payload::ReplyMessage {
+            reply: algorithm.tag(in_reply_to),
+            message,
+        },
+        Payload::Compressed {
+            message,
+            ref in_reply_to,
+        } => crate::cesr::Payload::CompressedMessage {
+            reply: in_reply_to.as_ref().map(|digest| algorithm.tag(digest)),
+            message,
+        },
         Payload::RequestRelationship {
             route,
             thread_id: _ignored,
         } => crate::cesr::Payload::DirectRelationProposal {
-            nonce: fresh_nonce(&mut csprng),
+            nonce: fresh_nonce(csprng),
             hops: route.unwrap_or_else(Vec::new),
         },
         Payload::AcceptRelationship { ref thread_id } => {
             crate::cesr::Payload::DirectRelationAffirm {
-                reply: crate::cesr::Digest::Blake2b256(thread_id),
+                reply: algorithm.tag(thread_id),
             }
         }
         Payload::RequestNestedRelationship {
             inner,
             thread_id: _ignored,
         } => crate::cesr::Payload::NestedRelationProposal {
-            nonce: fresh_nonce(&mut csprng),
+            nonce: fresh_nonce(csprng),
             message: inner,
         },
         Payload::AcceptNestedRelationship {
             ref thread_id,
             inner,
         } => crate::cesr::Payload::NestedRelationAffirm {
-            reply: crate::cesr::Digest::Blake2b256(thread_id),
+            reply: algorithm.tag(thread_id),
             message: inner,
         },
         Payload::NewIdentifier {
             ref thread_id,
             new_vid,
         } => crate::cesr::Payload::NewIdentifierProposal {
-            thread_id: crate::cesr::Digest::Blake2b256(thread_id),
+            thread_id: algorithm.tag(thread_id),
             new_vid,
         },
         Payload::Referral { referred_vid } => {
             crate::cesr::Payload::RelationshipReferral { referred_vid }
         }
+        Payload::RejectRelationship {
+            ref thread_id,
+            reason,
+        } => crate::cesr::Payload::RelationshipReject {
+            reply: algorithm.tag(thread_id),
+            reason,
+        },
+        Payload::RenewRelationship {
+            ref thread_id,
+            expires_at,
+        } => crate::cesr::Payload::RelationshipRenew {
+            reply: algorithm.tag(thread_id),
+            expires_at,
+        },
+        Payload::SessionKey {
+            ref thread_id,
+            seed,
+        } => crate::cesr::Payload::SessionKey {
+            reply: algorithm.tag(thread_id),
+            seed,
+        },
         Payload::CancelRelationship { ref thread_id } => crate::cesr::Payload::RelationshipCancel {
-            reply: crate::cesr::Digest::Blake2b256(thread_id),
+            reply: algorithm.tag(thread_id),
+        },
+        Payload::CancelNestedRelationship {
+            nested_vid,
+            ref thread_id,
+        } => crate::cesr::Payload::NestedRelationCancel {
+            nested_vid,
+            reply: algorithm.tag(thread_id),
         },
         Payload::NestedMessage(data) => crate::cesr::Payload::NestedMessage(data),
-        Payload::RoutedMessage(hops, data) => crate::cesr::Payload::RoutedMessage(hops, data),
+        Payload::RoutedMessage(hops, annotations, data) => {
+            crate::cesr::Payload::RoutedMessage(hops, annotations, data)
+        }
+        Payload::Multipart(segments) => crate::cesr::Payload::MultipartMessage(segments),
+        Payload::GroupMessage { group, message } => {
+            crate::cesr::Payload::GroupMessage { group, message }
+        }
+        Payload::Acknowledgement { ref digest } => crate::cesr::Payload::Acknowledgement {
+            reply: algorithm.tag(digest),
+        },
+        Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Added,
+        } => crate::cesr::Payload::GroupMemberAdd { group, member },
+        Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Removed,
+        } => crate::cesr::Payload::GroupMemberRemove { group, member },
+        Payload::Extension { typecode, data } => {
+            crate::cesr::Payload::ExtensionMessage { typecode, data }
+        }
+        Payload::UnknownControl { typecode, raw } => {
+            crate::cesr::Payload::UnknownControl { typecode, raw }
+        }
     };
 
-    // prepare CESR-encoded ciphertext
-    let mut cesr_message = Vec::new();
+    let sender_in_payload = options.essr.then_some(sender.identifier().as_bytes());
 
-    #[cfg(feature = "essr")]
-    crate::cesr::encode_payload(
-        &secret_payload,
-        Some(sender.identifier().as_bytes()),
-        &mut cesr_message,
-    )?;
+    let payload_size = secret_payload.calculate_size(sender_in_payload);
+    let padding = super::padding_size(payload_size, options.padding);
+
+    // plaintext, authentication tag and nonce
+    let ciphertext_size = payload_size + padding + 16 + 24;
 
-    #[cfg(not(feature = "essr"))]
-    crate::cesr::encode_payload(&secret_payload, None, &mut cesr_message)?;
+    // prepare CESR-encoded ciphertext, which is encrypted in place after the envelope
+    crate::cesr::encode_ciphertext_header(ciphertext_size, data)?;
+    let plaintext_start = data.len();
+    data.reserve(ciphertext_size + 2 + 64);
+
+    crate::cesr::encode_payload(&secret_payload, sender_in_payload, data)?;
+    crate::cesr::encode_padding(padding, data);
 
     // hash the raw bytes of the plaintext before encryption
     if let Some(digest) = digest {
-        *digest = crate::crypto::blake2b256(&cesr_message)
+        *digest = algorithm.hash(&data[plaintext_start..])
     }
 
-    let sender_secret_key = SecretKey::from_bytes(**sender.decryption_key());
+    let sender_secret_key = SecretKey::from_bytes(*sender.decryption_key().expose_secret());
     let receiver_public_key = PublicKey::from(**receiver.encryption_key());
 
     let sender_box = ChaChaBox::new(&receiver_public_key, &sender_secret_key);
 
     // Get a random nonce to encrypt the message under
-    let nonce = ChaChaBox::generate_nonce(&mut OsRng);
+    let nonce = ChaChaBox::generate_nonce(&mut *csprng);
 
     // aad not yet supported: https://github.com/RustCrypto/nacl-compat/blob/78b59261458923740724c84937459f0a6017a592/crypto_box/src/lib.rs#L227
-    let tag = sender_box.encrypt_in_place_detached(&nonce, &[], &mut cesr_message);
-
-    cesr_message.extend(tag.unwrap());
-    cesr_message.extend(nonce);
+    let tag = sender_box.encrypt_in_place_detached(&nonce, &[], &mut data[plaintext_start..]);
 
-    // encode and append the ciphertext to the envelope data
-    crate::cesr::encode_ciphertext(&cesr_message, &mut data)?;
+    data.extend(tag.unwrap());
+    data.extend(nonce);
 
     // create and append outer signature
-    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key());
-    let signature = sign_key.sign(&data).to_bytes();
-    crate::cesr::encode_signature(&signature, &mut data);
+    let sign_key = ed25519_dalek::SigningKey::from_bytes(sender.signing_key().expose_secret());
+    let signature = sign_key.sign(&data[envelope_start..]).to_bytes();
+    crate::cesr::encode_signature(&signature, data);
 
-    Ok(data)
+    Ok(())
 }
 
 pub(crate) fn open<'a>(
@@ -134,17 +209,24 @@ pub(crate) fn open<'a>(
     _raw_header: &'a [u8],
     envelope: Envelope<'a, &[u8]>,
     ciphertext: &'a mut [u8],
+    digest: Option<&mut super::Digest>,
+    algorithm: &mut DigestAlgorithm,
 ) -> Result<MessageContents<'a>, CryptoError> {
     let (ciphertext, footer) = ciphertext.split_at_mut(ciphertext.len() - 16 - 24);
     let (tag, nonce) = footer.split_at(16);
 
-    let receiver_secret_key = SecretKey::from_bytes(**receiver.decryption_key());
+    let receiver_secret_key = SecretKey::from_bytes(*receiver.decryption_key().expose_secret());
     let sender_public_key = PublicKey::from(**sender.encryption_key());
     let receiver_box = ChaChaBox::new(&sender_public_key, &receiver_secret_key);
 
     receiver_box.decrypt_in_place_detached(nonce.into(), &[], ciphertext, tag.into())?;
 
-    let thread_id = crate::crypto::blake2b256(ciphertext);
+    super::inspect_payload(ciphertext, algorithm)?;
+    let thread_id = algorithm.hash(ciphertext);
+
+    if let Some(digest) = digest {
+        *digest = thread_id;
+    }
 
     #[allow(unused_variables)]
     let DecodedPayload {
@@ -165,6 +247,14 @@ pub(crate) fn open<'a>(
 
     let secret_payload = match payload {
         crate::cesr::Payload::GenericMessage(data) => Payload::Content(data as _),
+        crate::cesr::Payload::ReplyMessage { reply, message } => Payload::Reply {
+            message: message as _,
+            in_reply_to: *reply.as_bytes(),
+        },
+        crate::cesr::Payload::CompressedMessage { reply, message } => Payload::Compressed {
+            message: message as _,
+            in_reply_to: reply.map(|reply| *reply.as_bytes()),
+        },
         crate::cesr::Payload::DirectRelationProposal { hops, .. } => Payload::RequestRelationship {
             route: if hops.is_empty() { None } else { Some(hops) },
             thread_id,
@@ -194,11 +284,64 @@ pub(crate) fn open<'a>(
         crate::cesr::Payload::RelationshipReferral { referred_vid } => {
             Payload::Referral { referred_vid }
         }
+        crate::cesr::Payload::GroupMessage { group, message } => Payload::GroupMessage {
+            group,
+            message: message as _,
+        },
+        crate::cesr::Payload::Acknowledgement { reply } => Payload::Acknowledgement {
+            digest: *reply.as_bytes(),
+        },
+        crate::cesr::Payload::GroupMemberAdd { group, member } => Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Added,
+        },
+        crate::cesr::Payload::GroupMemberRemove { group, member } => Payload::GroupMembership {
+            group,
+            member,
+            change: MembershipChange::Removed,
+        },
+        crate::cesr::Payload::ExtensionMessage { typecode, data } => Payload::Extension {
+            typecode,
+            data: data as _,
+        },
+        crate::cesr::Payload::UnknownControl { typecode, raw } => Payload::UnknownControl {
+            typecode,
+            raw: raw as _,
+        },
+        crate::cesr::Payload::RelationshipReject { reply, reason } => Payload::RejectRelationship {
+            thread_id: *reply.as_bytes(),
+            reason: reason as _,
+        },
+        crate::cesr::Payload::RelationshipRenew { reply, expires_at } => {
+            Payload::RenewRelationship {
+                thread_id: *reply.as_bytes(),
+                expires_at,
+            }
+        }
+        crate::cesr::Payload::SessionKey { reply, seed } => Payload::SessionKey {
+            thread_id: *reply.as_bytes(),
+            seed,
+        },
         crate::cesr::Payload::RelationshipCancel { reply, .. } => Payload::CancelRelationship {
             thread_id: *reply.as_bytes(),
         },
+        crate::cesr::Payload::NestedRelationCancel { nested_vid, reply } => {
+            Payload::CancelNestedRelationship {
+                nested_vid,
+                thread_id: *reply.as_bytes(),
+            }
+        }
         crate::cesr::Payload::NestedMessage(data) => Payload::NestedMessage(data),
-        crate::cesr::Payload::RoutedMessage(hops, data) => Payload::RoutedMessage(hops, data as _),
+        crate::cesr::Payload::RoutedMessage(hops, annotations, data) => {
+            Payload::RoutedMessage(hops, annotations, data as _)
+        }
+        crate::cesr::Payload::MultipartMessage(segments) => Payload::Multipart(
+            segments
+                .into_iter()
+                .map(|(content_type, data)| (content_type, data as _))
+                .collect(),
+        ),
     };
 
     Ok((
@@ -210,7 +353,6 @@ pub(crate) fn open<'a>(
 }
 
 /// Generate N random bytes using the provided RNG
-#[cfg(feature = "nacl")]
 fn fresh_nonce(csprng: &mut (impl rand::RngCore + rand::CryptoRng)) -> crate::cesr::Nonce {
     crate::cesr::Nonce::generate(|dst| csprng.fill_bytes(dst))
 }
@@ -21,11 +21,22 @@ impl<T: AsRef<[u8]>> ReceivedTspMessage<T> {
                 sender,
                 nonconfidential_data,
                 message,
+                content_type,
+                segments,
+                in_reply_to,
+                digest,
                 message_type,
             } => GenericMessage {
                 sender,
                 nonconfidential_data: nonconfidential_data.map(&f),
                 message: f(message),
+                content_type,
+                segments: segments
+                    .into_iter()
+                    .map(|(content_type, data)| (content_type, f(data)))
+                    .collect(),
+                in_reply_to,
+                digest,
                 message_type,
             },
             RequestRelationship {
@@ -35,22 +46,48 @@ impl<T: AsRef<[u8]>> ReceivedTspMessage<T> {
                 thread_id,
             } => RequestRelationship {
                 sender,
-                route,
+                route: route.map(|route| route.into_iter().map(&f).collect()),
                 nested_vid,
                 thread_id,
             },
             AcceptRelationship { sender, nested_vid } => AcceptRelationship { sender, nested_vid },
-            CancelRelationship { sender } => CancelRelationship { sender },
+            CancelRelationship { sender, nested_vid } => CancelRelationship { sender, nested_vid },
+            RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            } => RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            },
+            RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            } => RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            },
+            SessionEstablished { sender } => SessionEstablished { sender },
+            Acknowledgement { sender, digest } => Acknowledgement { sender, digest },
             ForwardRequest {
                 sender,
                 next_hop,
                 route,
+                annotation,
+                route_annotations,
+                nonconfidential_data,
                 opaque_payload,
             } => ForwardRequest {
                 sender,
                 next_hop,
-                route,
-                opaque_payload,
+                route: route.into_iter().map(&f).collect(),
+                annotation: annotation.map(&f),
+                route_annotations: route_annotations.into_iter().map(&f).collect(),
+                nonconfidential_data: nonconfidential_data.map(&f),
+                opaque_payload: f(opaque_payload),
             },
             NewIdentifier { sender, new_vid } => NewIdentifier { sender, new_vid },
             Referral {
@@ -60,6 +97,48 @@ impl<T: AsRef<[u8]>> ReceivedTspMessage<T> {
                 sender,
                 referred_vid,
             },
+            GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                message_type,
+            } => GroupMessage {
+                sender,
+                group,
+                nonconfidential_data: nonconfidential_data.map(&f),
+                message: f(message),
+                message_type,
+            },
+            GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            } => GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            },
+            Extension {
+                sender,
+                typecode,
+                message,
+            } => Extension {
+                sender,
+                typecode,
+                message,
+            },
+            UnknownControl {
+                sender,
+                typecode,
+                raw,
+            } => UnknownControl {
+                sender,
+                typecode,
+                raw: f(raw),
+            },
             #[cfg(feature = "async")]
             PendingMessage {
                 unknown_vid,
@@ -0,0 +1,152 @@
+use super::Digest;
+use crate::cesr::error::{DecodeError, EncodeError};
+
+/// Well-known header field names
+pub const CONTENT_TYPE: &str = "ct";
+pub const THREAD_REF: &str = "thr";
+pub const TIMESTAMP: &str = "ts";
+
+/// Common values of the [CONTENT_TYPE] header
+pub mod content_type {
+    pub const JSON: &str = "application/json";
+    pub const TEXT: &str = "text/plain";
+    pub const OCTET_STREAM: &str = "application/octet-stream";
+    /// A connectivity test: a 16 byte nonce followed by the time it was sent, in
+    /// milliseconds since the UNIX epoch, as a big-endian u64
+    pub const PING: &str = "application/tsp-ping";
+    /// The reply to a [PING]: its payload, followed by the time it was answered
+    pub const PONG: &str = "application/tsp-pong";
+    /// A notice that a message was refused because there is no relationship with its
+    /// sender; the payload is empty
+    pub const RELATIONSHIP_REQUIRED: &str = "application/tsp-relationship-required";
+}
+
+/// Structured headers that can be carried in the nonconfidential data of a TSP message
+///
+/// The headers are encoded as a CESR map of (key, value) pairs; apart from the well-known
+/// fields, applications can add their own fields using [`MessageHeaders::with_field`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageHeaders {
+    fields: Vec<(String, Vec<u8>)>,
+}
+
+impl MessageHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the media type of the confidential message, e.g. "application/json"
+    pub fn with_content_type(self, content_type: impl Into<String>) -> Self {
+        self.with_field(CONTENT_TYPE, content_type.into().into_bytes())
+    }
+
+    /// Refer to the thread (digest of an earlier message) this message belongs to
+    pub fn with_thread_ref(self, thread_ref: Digest) -> Self {
+        self.with_field(THREAD_REF, thread_ref.to_vec())
+    }
+
+    /// Set the creation time of the message, in seconds since the UNIX epoch
+    pub fn with_timestamp(self, timestamp: u64) -> Self {
+        self.with_field(TIMESTAMP, timestamp.to_be_bytes().to_vec())
+    }
+
+    /// Set an arbitrary header field, replacing any previous value
+    pub fn with_field(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        let key = key.into();
+        let value = value.into();
+
+        match self.fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((key, value)),
+        }
+
+        self
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        std::str::from_utf8(self.get(CONTENT_TYPE)?).ok()
+    }
+
+    pub fn thread_ref(&self) -> Option<Digest> {
+        self.get(THREAD_REF)?.try_into().ok()
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.get(TIMESTAMP)?.try_into().ok()?))
+    }
+
+    /// Get the raw value of a header field
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    /// Encode the headers, for use as nonconfidential data
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut data = Vec::new();
+        crate::cesr::encode_headers(&self.fields, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// Decode headers from nonconfidential data
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DecodeError> {
+        let fields = crate::cesr::decode_headers(data)?
+            .into_iter()
+            .map(|(k, v)| {
+                let key = std::str::from_utf8(k).map_err(|_| DecodeError::UnexpectedData {
+                    offset: k.as_ptr() as usize - data.as_ptr() as usize,
+                    expected: "UTF-8 header key",
+                    found: None,
+                })?;
+
+                Ok((key.to_string(), v.to_vec()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { fields })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn headers_round_trip() {
+        let headers = MessageHeaders::new()
+            .with_content_type("text/plain")
+            .with_thread_ref([7; 32])
+            .with_timestamp(1_700_000_000)
+            .with_field("x-app", b"custom".to_vec());
+
+        let data = headers.to_bytes().unwrap();
+        let decoded = MessageHeaders::from_bytes(&data).unwrap();
+
+        assert_eq!(decoded, headers);
+        assert_eq!(decoded.content_type(), Some("text/plain"));
+        assert_eq!(decoded.thread_ref(), Some([7; 32]));
+        assert_eq!(decoded.timestamp(), Some(1_700_000_000));
+        assert_eq!(decoded.get("x-app"), Some(&b"custom"[..]));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn headers_replace_and_reject() {
+        let headers = MessageHeaders::new()
+            .with_content_type("text/plain")
+            .with_content_type("application/json");
+        assert_eq!(headers.fields().count(), 1);
+        assert_eq!(headers.content_type(), Some("application/json"));
+
+        assert!(MessageHeaders::from_bytes(b"ad-hoc data").is_err());
+    }
+}
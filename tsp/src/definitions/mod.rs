@@ -2,6 +2,8 @@ use core::fmt;
 use std::{fmt::Debug, ops::Deref};
 use zeroize::Zeroize;
 
+use crate::secret::Secret;
+
 #[cfg(feature = "async")]
 use futures::Stream;
 
@@ -22,8 +24,8 @@ pub const PRIVATE_KEY_SIZE: usize = 32;
 #[cfg(not(feature = "pq"))]
 pub const PUBLIC_KEY_SIZE: usize = 32;
 
-#[derive(Clone, Zeroize)]
-pub struct PrivateKeyData([u8; PRIVATE_KEY_SIZE]);
+#[derive(Clone)]
+pub struct PrivateKeyData(Secret<[u8; PRIVATE_KEY_SIZE]>);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PublicKeyData([u8; PUBLIC_KEY_SIZE]);
@@ -32,8 +34,8 @@ pub const PRIVATE_SIGNING_KEY_SIZE: usize = 32;
 
 pub const PUBLIC_VERIFICATION_KEY_SIZE: usize = 32;
 
-#[derive(Clone, Zeroize)]
-pub struct PrivateSigningKeyData([u8; PRIVATE_SIGNING_KEY_SIZE]);
+#[derive(Clone)]
+pub struct PrivateSigningKeyData(Secret<[u8; PRIVATE_SIGNING_KEY_SIZE]>);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PublicVerificationKeyData([u8; PUBLIC_VERIFICATION_KEY_SIZE]);
@@ -49,6 +51,180 @@ pub type TSPStream<D, E> = std::pin::Pin<Box<dyn Stream<Item = Result<D, E>> + S
 pub struct MessageType {
     pub crypto_type: crate::cesr::CryptoType,
     pub signature_type: crate::cesr::SignatureType,
+    /// The hash function that computed the digest of the message
+    pub digest_algorithm: DigestAlgorithm,
+    /// Whether the message is still compressed with deflate, see [SealOptions::compress_above];
+    /// [Store::open_message](crate::Store::open_message) decompresses the content and clears it
+    pub compressed: bool,
+}
+
+/// The hash function used for message digests and thread ids
+///
+/// Both algorithms are accepted when receiving messages; the one used when sending
+/// can be chosen per relationship, see [SealOptions::digest].
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    Sha2_256,
+    Blake2b256,
+}
+
+impl Default for DigestAlgorithm {
+    /// The algorithm that goes with the default [CipherSuite]
+    fn default() -> Self {
+        CipherSuite::default().digest_algorithm()
+    }
+}
+
+impl DigestAlgorithm {
+    /// Compute the digest of `content`
+    pub fn hash(self, content: &[u8]) -> Digest {
+        match self {
+            Self::Sha2_256 => crate::crypto::sha256(content),
+            Self::Blake2b256 => crate::crypto::blake2b256(content),
+        }
+    }
+
+    /// Tag `digest` with this algorithm for encoding
+    pub(crate) fn tag(self, digest: &Digest) -> crate::cesr::Digest<'_> {
+        match self {
+            Self::Sha2_256 => crate::cesr::Digest::Sha2_256(digest),
+            Self::Blake2b256 => crate::cesr::Digest::Blake2b256(digest),
+        }
+    }
+}
+
+impl From<&crate::cesr::Digest<'_>> for DigestAlgorithm {
+    fn from(digest: &crate::cesr::Digest<'_>) -> Self {
+        match digest {
+            crate::cesr::Digest::Sha2_256(_) => Self::Sha2_256,
+            crate::cesr::Digest::Blake2b256(_) => Self::Blake2b256,
+        }
+    }
+}
+
+/// The cryptography that encrypts and authenticates a message
+///
+/// Messages in either suite are accepted when receiving; the one used when sending can be
+/// chosen per relationship or per message, see [SealOptions::cipher_suite]. NaCl is not
+/// available with the `pq` feature, as the keys are then post-quantum HPKE keys.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    Hpke,
+    Nacl,
+}
+
+impl Default for CipherSuite {
+    /// NaCl if this crate was built with the `nacl` feature, HPKE otherwise
+    fn default() -> Self {
+        if cfg!(feature = "nacl") {
+            Self::Nacl
+        } else {
+            Self::Hpke
+        }
+    }
+}
+
+impl CipherSuite {
+    /// The digest algorithm that goes with this suite, used unless another one is chosen
+    pub fn digest_algorithm(self) -> DigestAlgorithm {
+        match self {
+            Self::Hpke => DigestAlgorithm::Sha2_256,
+            Self::Nacl => DigestAlgorithm::Blake2b256,
+        }
+    }
+
+    /// The suite a message was sealed with; `None` for plaintext and session messages
+    pub fn of(crypto_type: &crate::cesr::CryptoType) -> Option<Self> {
+        match crypto_type {
+            crate::cesr::CryptoType::HpkeAuth | crate::cesr::CryptoType::HpkeEssr => {
+                Some(Self::Hpke)
+            }
+            crate::cesr::CryptoType::NaclAuth | crate::cesr::CryptoType::NaclEssr => {
+                Some(Self::Nacl)
+            }
+            crate::cesr::CryptoType::Plaintext | crate::cesr::CryptoType::Session => None,
+        }
+    }
+}
+
+/// Whether messages are sealed with ESSR unless their [SealOptions] say otherwise
+const ESSR_BY_DEFAULT: bool = cfg!(feature = "essr");
+
+/// Options that control how a single message is sealed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SealOptions {
+    /// Encrypt the sender VID together with the payload (ESSR) instead of authenticating
+    /// the sender through the key exchange; post-quantum HPKE always uses ESSR
+    pub essr: bool,
+    /// The hash function for the digest of the message and the digests it refers to;
+    /// without one, the algorithm of the relationship or else that of the cipher suite is used
+    pub digest: Option<DigestAlgorithm>,
+    /// The cryptography to seal the message with; without one, the suite of the relationship
+    /// or else [CipherSuite::default] is used
+    pub cipher_suite: Option<CipherSuite>,
+    /// Compress the content of messages larger than this many bytes with deflate before
+    /// encryption; content that does not get smaller is sent as is
+    pub compress_above: Option<usize>,
+    /// Which envelopes carry the nonconfidential data when the message is wrapped for routed
+    /// or nested mode; by default, a routed message carries it on the inner message for the
+    /// receiver and a nested message on its outer envelope
+    pub nonconfidential_placement: Option<NonConfidentialPlacement>,
+    /// Pad the encrypted payload to hide its exact length from everyone but the receiver;
+    /// the padding is removed when the message is opened
+    pub padding: Option<Padding>,
+    /// Refuse to seal a message that puts a nested VID, one with a parent, as sender on an
+    /// envelope that others than the receiver can read: that of a direct message, or the
+    /// inner envelope of a routed message, which the intermediaries see. Nested VIDs are
+    /// then only ever sent from wrapped in a message of their parent. Implies `essr`
+    pub hide_identity: bool,
+}
+
+impl Default for SealOptions {
+    fn default() -> Self {
+        Self {
+            essr: ESSR_BY_DEFAULT,
+            digest: None,
+            cipher_suite: None,
+            compress_above: None,
+            nonconfidential_placement: None,
+            padding: None,
+            hide_identity: false,
+        }
+    }
+}
+
+/// The lengths an encrypted payload is padded to, see [SealOptions::padding]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    /// Round the length up to a multiple of this many bytes
+    Block(usize),
+    /// Round the length up to the next power of two, which hides more of the length of
+    /// larger messages at the cost of more padding
+    PowerOfTwo,
+}
+
+/// Where the nonconfidential data of a wrapped message is placed, which decides
+/// whether the intermediaries that handle the outer envelope can read it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonConfidentialPlacement {
+    /// On the inner message only, which is opened by the receiver
+    Inner,
+    /// On the outer envelope only, which is read by the next hop or parent
+    Outer,
+    /// On both the inner message and the outer envelope
+    Both,
+}
+
+impl NonConfidentialPlacement {
+    pub(crate) fn inner(self) -> bool {
+        matches!(self, Self::Inner | Self::Both)
+    }
+
+    pub(crate) fn outer(self) -> bool {
+        matches!(self, Self::Outer | Self::Both)
+    }
 }
 
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
@@ -57,25 +233,112 @@ pub enum RelationshipStatus {
     _Controlled,
     Bidirectional {
         thread_id: Digest,
-        outstanding_nested_thread_ids: Vec<Digest>,
+        outstanding_nested_thread_ids: Vec<OutstandingThreadId>,
+        /// When the relationship ends by itself, in seconds since the Unix epoch
+        #[cfg_attr(
+            feature = "serialize",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        expires_at: Option<u64>,
     },
     Unidirectional {
         thread_id: Digest,
+        /// When the request lapses by itself, in seconds since the Unix epoch
+        #[cfg_attr(
+            feature = "serialize",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        expires_at: Option<u64>,
     },
     Unrelated,
 }
 
+/// The thread id of a nested relationship request that was sent and not yet accepted
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(from = "StoredThreadId")
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutstandingThreadId {
+    pub thread_id: Digest,
+    /// When the request was sent, in seconds since the Unix epoch
+    pub requested_at: u64,
+}
+
+/// Wallets written before requests were timed only kept the thread id; such requests
+/// count as sent when they are loaded
+#[cfg(feature = "serialize")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredThreadId {
+    Timed {
+        thread_id: Digest,
+        requested_at: u64,
+    },
+    Untimed(Digest),
+}
+
+#[cfg(feature = "serialize")]
+impl From<StoredThreadId> for OutstandingThreadId {
+    fn from(stored: StoredThreadId) -> Self {
+        match stored {
+            StoredThreadId::Timed {
+                thread_id,
+                requested_at,
+            } => OutstandingThreadId {
+                thread_id,
+                requested_at,
+            },
+            StoredThreadId::Untimed(thread_id) => OutstandingThreadId {
+                thread_id,
+                requested_at: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs()),
+            },
+        }
+    }
+}
+
+impl RelationshipStatus {
+    /// When this relationship expires, in seconds since the Unix epoch
+    pub fn expires_at(&self) -> Option<u64> {
+        match self {
+            RelationshipStatus::Bidirectional { expires_at, .. }
+            | RelationshipStatus::Unidirectional { expires_at, .. } => *expires_at,
+            RelationshipStatus::_Controlled | RelationshipStatus::Unrelated => None,
+        }
+    }
+
+    /// Whether this relationship has expired at `now`, in seconds since the Unix epoch
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
 #[derive(Debug)]
 pub enum ReceivedTspMessage<Data: AsRef<[u8]> = Vec<u8>> {
     GenericMessage {
         sender: String,
         nonconfidential_data: Option<Data>,
         message: Data,
+        /// The media type of `message`, from the [CONTENT_TYPE] header of the
+        /// nonconfidential data, see [content_type] for common values
+        content_type: Option<String>,
+        /// The (content type, data) segments of a multipart message; for a
+        /// multipart message `message` is empty
+        segments: Vec<(String, Data)>,
+        /// The digest of the earlier message this message replies to, as returned by
+        /// [seal_and_hash](crate::crypto::seal_and_hash) when that message was sealed
+        in_reply_to: Option<Digest>,
+        /// The digest of this message, to pass as `in_reply_to` when replying to it
+        digest: Digest,
         message_type: MessageType,
     },
     RequestRelationship {
         sender: String,
-        route: Option<Vec<Vec<u8>>>,
+        route: Option<Vec<Data>>,
         nested_vid: Option<String>,
         thread_id: Digest,
     },
@@ -85,12 +348,42 @@ pub enum ReceivedTspMessage<Data: AsRef<[u8]> = Vec<u8>> {
     },
     CancelRelationship {
         sender: String,
+        nested_vid: Option<String>,
+    },
+    /// The relationship we requested with `thread_id` was refused
+    RejectRelationship {
+        sender: String,
+        thread_id: Digest,
+        reason: Option<String>,
+    },
+    /// The relationship with `thread_id` was extended until `expires_at`
+    RenewRelationship {
+        sender: String,
+        thread_id: Digest,
+        expires_at: u64,
+    },
+    /// `sender` established a session key for the messages it sends us, see
+    /// [Store::make_session_key](crate::Store::make_session_key)
+    SessionEstablished {
+        sender: String,
+    },
+    /// `sender` received the message with `digest`, see [ReceivedTspMessage::GenericMessage]
+    Acknowledgement {
+        sender: String,
+        digest: Digest,
     },
     ForwardRequest {
         sender: String,
         next_hop: String,
-        route: Vec<Vec<u8>>,
-        opaque_payload: Vec<u8>,
+        route: Vec<Data>,
+        /// The nonconfidential annotation addressed to us, e.g. a priority or TTL hint
+        annotation: Option<Data>,
+        /// The annotations for the remaining hops, to pass along with `route`
+        route_annotations: Vec<Data>,
+        /// The nonconfidential data the sender placed on the envelope for us, see
+        /// [SealOptions::nonconfidential_placement]
+        nonconfidential_data: Option<Data>,
+        opaque_payload: Data,
     },
     NewIdentifier {
         sender: String,
@@ -100,6 +393,34 @@ pub enum ReceivedTspMessage<Data: AsRef<[u8]> = Vec<u8>> {
         sender: String,
         referred_vid: String,
     },
+    GroupMessage {
+        sender: String,
+        group: String,
+        nonconfidential_data: Option<Data>,
+        message: Data,
+        message_type: MessageType,
+    },
+    GroupMembership {
+        sender: String,
+        group: String,
+        member: String,
+        change: MembershipChange,
+    },
+    /// A control message of an application defined type, decoded by the
+    /// [ControlExtension](crate::ControlExtension) registered for `typecode`
+    Extension {
+        sender: String,
+        typecode: u8,
+        message: serde_json::Value,
+    },
+    /// A message of a type this version of the library does not know, e.g. a control
+    /// message introduced by a newer version of the protocol; `raw` holds its encoded
+    /// fields as is, for the application to interpret or ignore
+    UnknownControl {
+        sender: String,
+        typecode: [u8; 2],
+        raw: Data,
+    },
     #[cfg(feature = "async")]
     PendingMessage {
         unknown_vid: String,
@@ -107,16 +428,72 @@ pub enum ReceivedTspMessage<Data: AsRef<[u8]> = Vec<u8>> {
     },
 }
 
+impl<Data: AsRef<[u8]>> ReceivedTspMessage<Data> {
+    /// The structured headers carried in the nonconfidential data of a generic message,
+    /// if present and well-formed
+    pub fn headers(&self) -> Option<MessageHeaders> {
+        match self {
+            ReceivedTspMessage::GenericMessage {
+                nonconfidential_data: Some(data),
+                ..
+            } => MessageHeaders::from_bytes(data.as_ref()).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A change in the membership of a group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MembershipChange {
+    Added,
+    Removed,
+}
+
 mod conversions;
+mod headers;
+pub use headers::*;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Payload<'a, Bytes: AsRef<[u8]>, MaybeMutBytes: AsRef<[u8]> = Bytes> {
     Content(Bytes),
+    /// Content replying to the earlier message with digest `in_reply_to`
+    Reply {
+        message: Bytes,
+        in_reply_to: Digest,
+    },
+    /// Content compressed with deflate, optionally replying to `in_reply_to`
+    Compressed {
+        message: Bytes,
+        in_reply_to: Option<Digest>,
+    },
     NestedMessage(MaybeMutBytes),
-    RoutedMessage(Vec<VidData<'a>>, Bytes),
+    /// Routed content with the remaining hops, and optional nonconfidential annotations
+    /// for the receiver followed by those for the remaining hops
+    RoutedMessage(Vec<VidData<'a>>, Vec<&'a [u8]>, Bytes),
+    /// Ordered (content type, data) segments
+    Multipart(Vec<(&'a [u8], Bytes)>),
+    /// A message for all members of `group`
+    GroupMessage {
+        group: &'a [u8],
+        message: Bytes,
+    },
+    /// A notice that `member` joined or left `group`
+    GroupMembership {
+        group: &'a [u8],
+        member: VidData<'a>,
+        change: MembershipChange,
+    },
+    /// Confirm the receipt of the message with `digest`
+    Acknowledgement {
+        digest: Digest,
+    },
     CancelRelationship {
         thread_id: Digest,
     },
+    CancelNestedRelationship {
+        nested_vid: VidData<'a>,
+        thread_id: Digest,
+    },
     RequestRelationship {
         route: Option<Vec<VidData<'a>>>,
         thread_id: Digest,
@@ -124,6 +501,21 @@ pub enum Payload<'a, Bytes: AsRef<[u8]>, MaybeMutBytes: AsRef<[u8]> = Bytes> {
     AcceptRelationship {
         thread_id: Digest,
     },
+    /// Refuse the relationship requested with `thread_id`, with an optional human readable reason
+    RejectRelationship {
+        thread_id: Digest,
+        reason: Bytes,
+    },
+    /// Extend the relationship with `thread_id` until `expires_at`, in seconds since the Unix epoch
+    RenewRelationship {
+        thread_id: Digest,
+        expires_at: u64,
+    },
+    /// Establish a session key for the relationship with `thread_id`, derived from `seed`
+    SessionKey {
+        thread_id: Digest,
+        seed: &'a [u8; 32],
+    },
     RequestNestedRelationship {
         inner: MaybeMutBytes,
         thread_id: Digest,
@@ -139,21 +531,44 @@ pub enum Payload<'a, Bytes: AsRef<[u8]>, MaybeMutBytes: AsRef<[u8]> = Bytes> {
     Referral {
         referred_vid: VidData<'a>,
     },
+    /// A control message defined by an application, see [ControlExtension](crate::ControlExtension)
+    Extension {
+        typecode: u8,
+        data: Bytes,
+    },
+    /// A message of a type we do not know, e.g. a control message of a newer version of
+    /// the protocol, with its encoded fields as is
+    UnknownControl {
+        typecode: [u8; 2],
+        raw: Bytes,
+    },
 }
 
 impl<'a, Bytes: AsRef<[u8]>, MaybeMutBytes: AsRef<[u8]>> Payload<'a, Bytes, MaybeMutBytes> {
     pub fn as_bytes(&self) -> &[u8] {
         match self {
             Payload::Content(bytes) => bytes.as_ref(),
+            Payload::Reply { message, .. } => message.as_ref(),
+            Payload::Compressed { message, .. } => message.as_ref(),
             Payload::NestedMessage(bytes) => bytes.as_ref(),
-            Payload::RoutedMessage(_, bytes) => bytes.as_ref(),
+            Payload::RoutedMessage(_, _, bytes) => bytes.as_ref(),
+            Payload::Multipart(_) => &[],
+            Payload::GroupMessage { message, .. } => message.as_ref(),
+            Payload::GroupMembership { .. } => &[],
+            Payload::Acknowledgement { .. } => &[],
             Payload::CancelRelationship { .. } => &[],
+            Payload::CancelNestedRelationship { .. } => &[],
             Payload::RequestRelationship { .. } => &[],
             Payload::AcceptRelationship { .. } => &[],
+            Payload::RejectRelationship { .. } => &[],
+            Payload::RenewRelationship { .. } => &[],
+            Payload::SessionKey { .. } => &[],
             Payload::RequestNestedRelationship { .. } => &[],
             Payload::AcceptNestedRelationship { .. } => &[],
             Payload::NewIdentifier { .. } => &[],
             Payload::Referral { .. } => &[],
+            Payload::Extension { data, .. } => data.as_ref(),
+            Payload::UnknownControl { raw, .. } => raw.as_ref(),
         }
     }
 }
@@ -164,12 +579,18 @@ impl<'a, Bytes: AsRef<[u8]>> fmt::Display for Payload<'a, Bytes> {
             Payload::Content(bytes) => {
                 write!(f, "Content: {}", String::from_utf8_lossy(bytes.as_ref()))
             }
+            Payload::Reply { message, .. } => {
+                write!(f, "Reply: {}", String::from_utf8_lossy(message.as_ref()))
+            }
+            Payload::Compressed { message, .. } => {
+                write!(f, "Compressed Content: {} bytes", message.as_ref().len())
+            }
             Payload::NestedMessage(bytes) => write!(
                 f,
                 "Nested Message: {}",
                 String::from_utf8_lossy(bytes.as_ref())
             ),
-            Payload::RoutedMessage(hops, bytes) => {
+            Payload::RoutedMessage(hops, _, bytes) => {
                 write!(
                     f,
                     "Routed Message: {}, route: [",
@@ -180,13 +601,45 @@ impl<'a, Bytes: AsRef<[u8]>> fmt::Display for Payload<'a, Bytes> {
                 }
                 write!(f, "]")
             }
+            Payload::Multipart(segments) => {
+                write!(f, "Multipart Message: [")?;
+                for (content_type, bytes) in segments {
+                    write!(
+                        f,
+                        "{}: {}, ",
+                        String::from_utf8_lossy(content_type),
+                        String::from_utf8_lossy(bytes.as_ref())
+                    )?
+                }
+                write!(f, "]")
+            }
+            Payload::GroupMessage { group, message } => write!(
+                f,
+                "Group Message to {}: {}",
+                String::from_utf8_lossy(group),
+                String::from_utf8_lossy(message.as_ref())
+            ),
+            Payload::GroupMembership { group, change, .. } => write!(
+                f,
+                "Group Membership {change:?} in {}",
+                String::from_utf8_lossy(group)
+            ),
+            Payload::Acknowledgement { .. } => write!(f, "Acknowledgement"),
             Payload::CancelRelationship { .. } => write!(f, "Cancel Relationship"),
+            Payload::CancelNestedRelationship { .. } => write!(f, "Cancel Nested Relationship"),
             Payload::RequestRelationship { .. } => write!(f, "Request Relationship"),
             Payload::AcceptRelationship { .. } => write!(f, "Accept Relationship"),
+            Payload::RejectRelationship { .. } => write!(f, "Reject Relationship"),
+            Payload::RenewRelationship { .. } => write!(f, "Renew Relationship"),
+            Payload::SessionKey { .. } => write!(f, "Session Key"),
             Payload::RequestNestedRelationship { .. } => write!(f, "Request Nested Relationship"),
             Payload::AcceptNestedRelationship { .. } => write!(f, "Accept Nested Relationship"),
             Payload::NewIdentifier { .. } => write!(f, "Request Identifier Change"),
             Payload::Referral { .. } => write!(f, "Relationship Referral"),
+            Payload::Extension { typecode, .. } => write!(f, "Extension {typecode}"),
+            Payload::UnknownControl { typecode, .. } => {
+                write!(f, "Unknown Control {:02x}{:02x}", typecode[0], typecode[1])
+            }
         }
     }
 }
@@ -198,11 +651,22 @@ pub trait VerifiedVid: Send + Sync {
     /// The transport layer endpoint in the transport layer associated with this Vid
     fn endpoint(&self) -> &url::Url;
 
+    /// Other endpoints this Vid can be reached at, in the order they were published
+    fn alternative_endpoints(&self) -> &[url::Url] {
+        &[]
+    }
+
     /// The verification key that can check signatures made by this Vid
     fn verifying_key(&self) -> &PublicVerificationKeyData;
 
     /// The encryption key associated with this Vid
     fn encryption_key(&self) -> &PublicKeyData;
+
+    /// The fields of a Vid type defined outside this library that are persisted along with
+    /// its keys, see [VidCodec](crate::vid::VidCodec)
+    fn custom_fields(&self) -> Option<crate::vid::CustomFields> {
+        None
+    }
 }
 
 pub trait PrivateVid: VerifiedVid + Send + Sync {
@@ -211,6 +675,26 @@ pub trait PrivateVid: VerifiedVid + Send + Sync {
 
     /// The PRIVATE key used to sign data
     fn signing_key(&self) -> &PrivateSigningKeyData;
+
+    /// When this VID expires, in seconds since the Unix epoch, if it is a one-shot VID: a
+    /// store forgets it after opening a message sent to it, or once it expired
+    fn expires_at(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl PrivateKeyData {
+    /// Access the key material, which is otherwise redacted
+    pub fn expose_secret(&self) -> &[u8; PRIVATE_KEY_SIZE] {
+        self.0.expose_secret()
+    }
+}
+
+impl PrivateSigningKeyData {
+    /// Access the key material, which is otherwise redacted
+    pub fn expose_secret(&self) -> &[u8; PRIVATE_SIGNING_KEY_SIZE] {
+        self.0.expose_secret()
+    }
 }
 
 impl Debug for PrivateKeyData {
@@ -219,9 +703,9 @@ impl Debug for PrivateKeyData {
     }
 }
 
-impl AsRef<[u8]> for PrivateKeyData {
-    fn as_ref(&self) -> &[u8] {
-        &self.0
+impl Debug for PrivateSigningKeyData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrivateSigningKeyData([redacted])")
     }
 }
 
@@ -231,12 +715,6 @@ impl AsRef<[u8]> for PublicKeyData {
     }
 }
 
-impl AsRef<[u8]> for PrivateSigningKeyData {
-    fn as_ref(&self) -> &[u8] {
-        &self.0
-    }
-}
-
 impl AsRef<[u8]> for PublicVerificationKeyData {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -244,8 +722,11 @@ impl AsRef<[u8]> for PublicVerificationKeyData {
 }
 
 impl From<[u8; PRIVATE_SIGNING_KEY_SIZE]> for PrivateSigningKeyData {
-    fn from(data: [u8; PRIVATE_SIGNING_KEY_SIZE]) -> PrivateSigningKeyData {
-        PrivateSigningKeyData(data)
+    fn from(mut data: [u8; PRIVATE_SIGNING_KEY_SIZE]) -> PrivateSigningKeyData {
+        let key = PrivateSigningKeyData(Secret::new(data));
+        data.zeroize();
+
+        key
     }
 }
 
@@ -256,8 +737,11 @@ impl From<[u8; PUBLIC_VERIFICATION_KEY_SIZE]> for PublicVerificationKeyData {
 }
 
 impl From<[u8; PRIVATE_KEY_SIZE]> for PrivateKeyData {
-    fn from(data: [u8; PRIVATE_KEY_SIZE]) -> PrivateKeyData {
-        PrivateKeyData(data)
+    fn from(mut data: [u8; PRIVATE_KEY_SIZE]) -> PrivateKeyData {
+        let key = PrivateKeyData(Secret::new(data));
+        data.zeroize();
+
+        key
     }
 }
 
@@ -275,14 +759,6 @@ impl Deref for PublicKeyData {
     }
 }
 
-impl Deref for PrivateKeyData {
-    type Target = [u8; PRIVATE_KEY_SIZE];
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
 impl Deref for PublicVerificationKeyData {
     type Target = [u8; PUBLIC_VERIFICATION_KEY_SIZE];
 
@@ -290,11 +766,3 @@ impl Deref for PublicVerificationKeyData {
         &self.0
     }
 }
-
-impl Deref for PrivateSigningKeyData {
-    type Target = [u8; PRIVATE_SIGNING_KEY_SIZE];
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
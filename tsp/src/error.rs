@@ -8,6 +8,9 @@ pub enum Error {
     #[cfg(feature = "async")]
     #[error("Error: {0}")]
     Transport(#[from] crate::transport::TransportError),
+    #[cfg(feature = "async")]
+    #[error("Error: {0}")]
+    Delivery(Box<crate::transport::DeliveryError>),
     #[error("Error: {0}")]
     Crypto(#[from] crate::crypto::CryptoError),
     #[error("Error: {0}")]
@@ -19,12 +22,19 @@ pub enum Error {
     #[error("Error: {0}")]
     #[cfg(feature = "async")]
     Storage(#[from] aries_askar::Error),
+    #[error("Error: {0}")]
+    #[cfg(feature = "async")]
+    Io(#[from] std::io::Error),
     #[error("Error decoding persisted state: {0}")]
     DecodeState(&'static str),
+    #[error("Error: invalid wallet backup: {0}")]
+    InvalidBackup(&'static str),
     #[error("Error: {0}")]
     InvalidRoute(String),
     #[error("Error: {0}")]
     Relationship(String),
+    #[error("Error: group {0}: {1}")]
+    Group(String, &'static str),
     #[error("Error: missing private vid {0}")]
     MissingPrivateVid(String),
     #[error("Error: missing vid {0}")]
@@ -43,10 +53,48 @@ pub enum Error {
     InvalidNextHop(String),
     #[error("Error: no relation established for {0}")]
     MissingDropOff(String),
+    #[error("Error: no relationship with sender {0}, its message was refused")]
+    NoRelationship(String),
+    #[error("Error: payload of {0} bytes exceeds the maximum of {1} bytes")]
+    PayloadTooLarge(usize, usize),
+    #[error("Error: route of {0} hops exceeds the maximum of {1} hops")]
+    TooManyHops(usize, usize),
+    #[error("Error: message nesting exceeds the maximum depth of {0}")]
+    NestingTooDeep(usize),
+    #[error("Error: vid {0} rejected by the verification policy: {1}")]
+    PolicyRejected(String, String),
+    #[error("Error: forwarding to {0} refused: {1}")]
+    ForwardRefused(String, String),
+    #[error("Error: no extension registered for control message type {0}")]
+    UnknownExtension(u8),
+    #[error("Error: invalid control message of type {0}: {1}")]
+    InvalidExtension(u8, String),
+    #[error("Error: vid {0} does not publish a key with fingerprint {1}")]
+    FingerprintMismatch(String, String),
+    #[error("Error: invalid trust registry: {0}")]
+    InvalidRegistry(String),
+    #[error("Error: sealing would expose the nested vid {0}")]
+    IdentityLeak(String),
+    #[error("Error: message from {0} was already received")]
+    DuplicateMessage(String),
+    #[error("Error: no reply from {0} within {1:?}")]
+    #[cfg(feature = "async")]
+    ReplyTimeout(String, std::time::Duration),
+    #[error("Error: invalid answer to a ping from {0}")]
+    #[cfg(feature = "async")]
+    InvalidPong(String),
     #[error("Internal error")]
     Internal,
 }
 
+/// Delivery errors are boxed, as they are much larger than the other errors
+#[cfg(feature = "async")]
+impl From<crate::transport::DeliveryError> for Error {
+    fn from(e: crate::transport::DeliveryError) -> Self {
+        Self::Delivery(Box::new(e))
+    }
+}
+
 impl<T> From<std::sync::PoisonError<T>> for Error {
     fn from(_: std::sync::PoisonError<T>) -> Self {
         Self::Internal
@@ -0,0 +1,15 @@
+/// Encodes and decodes the control messages of an application defined type, to prototype
+/// control flows that TSP does not define yet, e.g. consent receipts or billing
+/// acknowledgements
+///
+/// Register it for a `typecode` with
+/// [`Store::register_control_extension`](crate::Store::register_control_extension). The
+/// messages are sealed like any other control message, with a message type in the range
+/// that is reserved for experiments, so peers without the extension reject them.
+pub trait ControlExtension: Send + Sync {
+    /// Encode `message` into the contents of a control message, or refuse it with a reason
+    fn encode(&self, message: &serde_json::Value) -> Result<Vec<u8>, String>;
+
+    /// Decode the contents of a received control message, or reject it with a reason
+    fn decode(&self, data: &[u8]) -> Result<serde_json::Value, String>;
+}
@@ -0,0 +1,118 @@
+//! The VIDs of a [Store](crate::Store) and how they relate, to visualize and debug a
+//! topology with many intermediaries
+//!
+//! [Store::relationship_graph](crate::Store::relationship_graph) returns a [RelationshipGraph],
+//! which can be rendered with [Graphviz](https://graphviz.org/) using [RelationshipGraph::to_dot],
+//! or serialized to JSON.
+
+use std::fmt::Write;
+
+#[cfg(feature = "serialize")]
+use serde::Serialize;
+
+use crate::audit::RelationshipState;
+
+/// A VID in the store
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub vid: String,
+    /// Whether we hold the private keys of the VID
+    pub private: bool,
+    /// Whether the VID is nested in a parent VID
+    pub nested: bool,
+    /// Our relationship with the VID; there is a [EdgeKind::Relationship] edge as well if
+    /// it is known which of our VIDs the relationship is with
+    pub relationship: RelationshipState,
+}
+
+/// How one VID relates to another
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize),
+    serde(tag = "kind", rename_all = "camelCase")
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// A relationship from our VID to another one
+    Relationship { state: RelationshipState },
+    /// A nested VID and its parent
+    Parent,
+    /// A VID and the intermediary at position `hop` of the route to it
+    Route { hop: usize },
+}
+
+/// A directed edge from `from` to `to`
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    #[cfg_attr(feature = "serialize", serde(flatten))]
+    pub kind: EdgeKind,
+}
+
+/// The VIDs of a store and their relationships, parents and routes, ordered by VID
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RelationshipGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl RelationshipGraph {
+    /// Render the graph in the DOT language of Graphviz: private VIDs are boxes, nested
+    /// VIDs are dashed, VIDs are labeled with our relationship with them, and parent links
+    /// and routes are dotted edges
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph tsp {\n".to_string();
+
+        for node in &self.nodes {
+            let shape = if node.private { "box" } else { "ellipse" };
+            let style = if node.nested { ", style=dashed" } else { "" };
+            let label = match node.relationship {
+                RelationshipState::Unrelated => quote(&node.vid),
+                state => quote(&format!("{}\n{}", node.vid, label(state))),
+            };
+            let _ = writeln!(
+                dot,
+                "    {} [label={label}, shape={shape}{style}];",
+                quote(&node.vid)
+            );
+        }
+
+        for edge in &self.edges {
+            let attributes = match edge.kind {
+                EdgeKind::Relationship { state } => format!("label=\"{}\"", label(state)),
+                EdgeKind::Parent => "label=\"parent\", style=dotted".to_string(),
+                EdgeKind::Route { hop } => format!("label=\"hop {hop}\", style=dotted"),
+            };
+
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [{attributes}];",
+                quote(&edge.from),
+                quote(&edge.to)
+            );
+        }
+
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+/// The label of a relationship edge
+fn label(state: RelationshipState) -> &'static str {
+    match state {
+        RelationshipState::Unrelated => "unrelated",
+        RelationshipState::Requested => "requested",
+        RelationshipState::Established => "established",
+        RelationshipState::Controlled => "controlled",
+    }
+}
+
+/// A DOT string literal
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
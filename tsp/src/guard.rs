@@ -0,0 +1,17 @@
+/// Decides whether a routed message may be forwarded; consulted by
+/// [`Store::forward_routed_message`](crate::Store::forward_routed_message) before
+/// the message is sealed, e.g. to apply rate limits or deny-lists
+pub trait ForwardGuard: Send + Sync {
+    /// Allow forwarding `payload_size` bytes from our VID `sender` to `next_hop`,
+    /// or refuse it with a reason
+    fn check(&self, sender: &str, next_hop: &str, payload_size: usize) -> Result<(), String>;
+}
+
+impl<F> ForwardGuard for F
+where
+    F: Fn(&str, &str, usize) -> Result<(), String> + Send + Sync,
+{
+    fn check(&self, sender: &str, next_hop: &str, payload_size: usize) -> Result<(), String> {
+        self(sender, next_hop, payload_size)
+    }
+}
@@ -21,32 +21,35 @@
 //! TSP messages (low level API), it does not require an async runtime.
 //! ## Example
 //!
-//! The following example demonstrates how to send a message from Alice to Bob
+//! The following example demonstrates how to send a message from Alice to Bob. Their
+//! identifiers have `mem://` endpoints, which deliver messages within the same process
+//! instead of over the network; real identifiers use e.g. `https://` or `tcp://`.
 //!
 //! ```rust
-//! use tsp::{AsyncStore, OwnedVid, Error, ReceivedTspMessage};
+//! use tsp::{AsyncStore, OwnedVid, Error, ReceivedTspMessage, VerifiedVid};
 //! use futures::StreamExt;
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Error> {
+//!     let alice = OwnedVid::new_did_peer("mem://alice".parse().unwrap());
+//!     let bob = OwnedVid::new_did_peer("mem://bob".parse().unwrap());
+//!
 //!     // bob database
-//!     let mut bob_db = AsyncStore::new();
-//!     let bob_vid = OwnedVid::from_file("../examples/test/bob.json").await?;
-//!     bob_db.add_private_vid(bob_vid)?;
-//!     bob_db.verify_vid("did:web:did.tsp-test.org:user:alice").await?;
+//!     let bob_db = AsyncStore::new();
+//!     bob_db.add_private_vid(bob.clone())?;
+//!     bob_db.add_verified_vid(alice.vid().clone())?;
 //!
-//!     let mut bobs_messages = bob_db.receive("did:web:did.tsp-test.org:user:bob").await?;
+//!     let mut bobs_messages = bob_db.receive(bob.identifier()).await?;
 //!
 //!     // alice database
-//!     let mut alice_db = AsyncStore::new();
-//!     let alice_vid = OwnedVid::from_file("../examples/test/alice.json").await?;
-//!     alice_db.add_private_vid(alice_vid)?;
-//!     alice_db.verify_vid("did:web:did.tsp-test.org:user:bob").await?;
+//!     let alice_db = AsyncStore::new();
+//!     alice_db.add_private_vid(alice.clone())?;
+//!     alice_db.add_verified_vid(bob.vid().clone())?;
 //!
 //!     // send a message
 //!     alice_db.send(
-//!         "did:web:did.tsp-test.org:user:alice",
-//!         "did:web:did.tsp-test.org:user:bob",
+//!         alice.identifier(),
+//!         bob.identifier(),
 //!         Some(b"extra non-confidential data"),
 //!         b"hello world",
 //!     ).await?;
@@ -81,10 +84,35 @@ pub mod crypto;
 /// Defines several common data structures, traits and error types that are used throughout the project.
 pub mod definitions;
 mod error;
+mod extension;
+mod guard;
 mod store;
 
+/// Wraps key material so it is wiped from memory and redacted from logs and serialization
+pub mod secret;
+
+#[cfg(feature = "serialize")]
+mod backup;
+
+/// Canonical test vectors for checking interoperability with other TSP implementations
+#[cfg(feature = "serialize")]
+pub mod conformance;
+
+/// A signed, hash-chained log of security-relevant events, for compliance reviews
+pub mod audit;
+
+/// The messages a store sealed and opened by digest, for deduplication and retrieval
+pub mod archive;
+
+/// The VIDs of a store and how they relate, e.g. to visualize a test topology
+pub mod graph;
+
+/// Hooks for observability: a metrics facade and helpers for structured logging
+pub mod telemetry;
+
 /// Contains code for handling *verified identifiers* and identities.
-/// Currently only an extended form of `did:web` and `did:peer` are supported.
+/// Currently only an extended form of `did:web` and `did:peer` are supported;
+/// other DID methods can be added with a [DidMethodRegistry](vid::DidMethodRegistry).
 pub mod vid;
 
 /// Code (built using [tokio](https://tokio.rs/) foundations) for actually
@@ -95,21 +123,53 @@ pub mod transport;
 #[cfg(feature = "async")]
 mod async_store;
 
+/// Onboarding counterparties in bulk from a trust registry
+#[cfg(feature = "async")]
+pub mod contacts;
+
 #[cfg(feature = "async")]
 mod vault;
 
+#[cfg(feature = "async")]
+mod wallet;
+
+/// A drop-off point that keeps routed messages for offline receivers
+#[cfg(feature = "mailbox")]
+pub mod mailbox;
+
+/// Deliver received messages to a webhook, for backends that do not embed this library
+#[cfg(feature = "async")]
+pub mod webhook;
+
+/// Stores in the same process connected by links with simulated latency, loss and
+/// partitions, for end-to-end tests of routed and nested flows
+#[cfg(any(test, feature = "test-support"))]
+#[cfg(feature = "async")]
+pub mod test_support;
+
 #[cfg(not(feature = "pq"))]
 #[cfg(feature = "async")]
 #[cfg(test)]
 mod test;
 
 #[cfg(feature = "async")]
-pub use async_store::AsyncStore;
+pub use async_store::{AsyncStore, BootstrapStatus, FirstContactPolicy, Pong, ReceiveHandle};
+
+#[cfg(feature = "async")]
+pub use vault::{Vault, WALLET_VERSION};
 
 #[cfg(feature = "async")]
-pub use vault::Vault;
+pub use wallet::Wallet;
 
-pub use definitions::{Payload, PrivateVid, ReceivedTspMessage, RelationshipStatus, VerifiedVid};
+pub use definitions::{
+    CipherSuite, DigestAlgorithm, MembershipChange, MessageHeaders, NonConfidentialPlacement,
+    OutstandingThreadId, Padding, Payload, PrivateVid, ReceivedTspMessage, RelationshipStatus,
+    SealOptions, VerifiedVid,
+};
 pub use error::Error;
-pub use store::Store;
+pub use extension::ControlExtension;
+pub use guard::ForwardGuard;
+pub use store::{
+    Group, RelationshipCleanup, RelationshipRequirement, Store, StoreChanges, StoreConfig,
+};
 pub use vid::{ExportVid, OwnedVid, Vid};
@@ -0,0 +1,366 @@
+use crate::{
+    definitions::{ReceivedTspMessage, VerifiedVid},
+    error::Error,
+    AsyncStore,
+};
+use futures::{Stream, StreamExt};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Keeps the messages for receivers that could not be reached, in the order they arrived
+pub trait MailboxStorage: Send + Sync {
+    /// Keep `message` for `receiver`
+    fn push(&self, receiver: &str, message: Vec<u8>) -> Result<(), Error>;
+
+    /// Remove and return the messages kept for `receiver`
+    fn take(&self, receiver: &str) -> Result<Vec<Vec<u8>>, Error>;
+
+    /// The receivers messages are kept for
+    fn receivers(&self) -> Result<Vec<String>, Error>;
+}
+
+/// Keeps messages in memory; they are lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    messages: Mutex<HashMap<String, Vec<Vec<u8>>>>,
+}
+
+impl MailboxStorage for MemoryStorage {
+    fn push(&self, receiver: &str, message: Vec<u8>) -> Result<(), Error> {
+        self.messages
+            .lock()
+            .map_err(|_| Error::Internal)?
+            .entry(receiver.to_string())
+            .or_default()
+            .push(message);
+
+        Ok(())
+    }
+
+    fn take(&self, receiver: &str) -> Result<Vec<Vec<u8>>, Error> {
+        let mut messages = self.messages.lock().map_err(|_| Error::Internal)?;
+
+        Ok(messages.remove(receiver).unwrap_or_default())
+    }
+
+    fn receivers(&self) -> Result<Vec<String>, Error> {
+        let messages = self.messages.lock().map_err(|_| Error::Internal)?;
+
+        Ok(messages.keys().cloned().collect())
+    }
+}
+
+/// Keeps messages as files in a directory, with a subdirectory per receiver
+#[derive(Debug)]
+pub struct DirectoryStorage {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DirectoryStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn receiver_dir(&self, receiver: &str) -> PathBuf {
+        let digest = crate::crypto::sha256(receiver.as_bytes());
+        let name = digest
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        self.path.join(name)
+    }
+
+    /// The message files for a receiver, oldest first
+    fn message_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "tsp") {
+                files.push(path);
+            }
+        }
+
+        // file names are zero-padded sequence numbers
+        files.sort();
+
+        Ok(files)
+    }
+}
+
+impl MailboxStorage for DirectoryStorage {
+    fn push(&self, receiver: &str, message: Vec<u8>) -> Result<(), Error> {
+        let _guard = self.lock.lock().map_err(|_| Error::Internal)?;
+
+        let dir = self.receiver_dir(receiver);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("receiver"), receiver)?;
+
+        let next = Self::message_files(&dir)?
+            .last()
+            .and_then(|path| path.file_stem()?.to_str()?.parse::<u64>().ok())
+            .map_or(0, |last| last + 1);
+
+        std::fs::write(dir.join(format!("{next:020}.tsp")), message)?;
+
+        Ok(())
+    }
+
+    fn take(&self, receiver: &str) -> Result<Vec<Vec<u8>>, Error> {
+        let _guard = self.lock.lock().map_err(|_| Error::Internal)?;
+
+        let dir = self.receiver_dir(receiver);
+        let messages = Self::message_files(&dir)?
+            .iter()
+            .map(std::fs::read)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+
+        Ok(messages)
+    }
+
+    fn receivers(&self) -> Result<Vec<String>, Error> {
+        let _guard = self.lock.lock().map_err(|_| Error::Internal)?;
+
+        let entries = match std::fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut receivers = Vec::new();
+        for entry in entries {
+            if let Ok(receiver) = std::fs::read_to_string(entry?.path().join("receiver")) {
+                receivers.push(receiver);
+            }
+        }
+
+        Ok(receivers)
+    }
+}
+
+/// A drop-off point that keeps routed messages for receivers that are offline
+///
+/// When a routed message arrives whose final hop is one of our VIDs, it is passed on to
+/// the drop-off relation of that VID (see [`AsyncStore::set_relation_for_vid`]). If that
+/// receiver cannot be reached, the sealed message is kept in the [`MailboxStorage`] and
+/// delivered later, as soon as the receiver listens again (see
+/// [`receive_messages`](crate::transport::receive_messages)).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use tsp::{mailbox::{DirectoryStorage, Mailbox}, AsyncStore};
+///
+/// # async fn example(db: AsyncStore) -> Result<(), tsp::Error> {
+/// let messages = db.receive("did:web:did.tsp-test.org:user:mailbox").await?;
+///
+/// Mailbox::new(db, DirectoryStorage::new("mailbox"))
+///     .run(messages, Duration::from_secs(30))
+///     .await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Mailbox {
+    db: AsyncStore,
+    storage: Arc<dyn MailboxStorage>,
+}
+
+impl Mailbox {
+    pub fn new(db: AsyncStore, storage: impl MailboxStorage + 'static) -> Self {
+        Self {
+            db,
+            storage: Arc::new(storage),
+        }
+    }
+
+    /// Handle every message from `messages` until the stream ends, and try to deliver
+    /// the kept messages every `retry_interval`; errors are logged and skipped
+    pub async fn run(
+        &self,
+        messages: impl Stream<Item = Result<ReceivedTspMessage, Error>>,
+        retry_interval: Duration,
+    ) {
+        let mut messages = std::pin::pin!(messages);
+        let mut retry = tokio::time::interval(retry_interval);
+
+        loop {
+            tokio::select! {
+                message = messages.next() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+
+                    let result = match message {
+                        Ok(message) => self.handle(message).await,
+                        Err(e) => Err(e),
+                    };
+
+                    if let Err(e) = result {
+                        tracing::error!("mailbox could not handle a message: {e}");
+                    }
+                }
+                _ = retry.tick() => {
+                    if let Err(e) = self.deliver_all().await {
+                        tracing::error!("mailbox could not deliver kept messages: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pass on a routed message, keeping it if its final receiver cannot be reached;
+    /// messages other than forward requests are ignored
+    pub async fn handle(&self, message: ReceivedTspMessage) -> Result<(), Error> {
+        let ReceivedTspMessage::ForwardRequest {
+            next_hop,
+            route,
+            route_annotations,
+            opaque_payload,
+            ..
+        } = message
+        else {
+            return Ok(());
+        };
+
+        // not the final hop, pass it on without keeping it
+        if !route.is_empty() {
+            self.db
+                .forward_annotated_routed_message(
+                    &next_hop,
+                    route,
+                    route_annotations,
+                    &opaque_payload,
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        let (_, message) =
+            self.db
+                .as_store()
+                .forward_routed_message(&next_hop, Vec::new(), &opaque_payload)?;
+
+        let (_, Some(receiver)) = crate::cesr::get_sender_receiver(&message)? else {
+            return Err(Error::MissingDropOff(next_hop));
+        };
+        let receiver = std::str::from_utf8(receiver)?.to_string();
+
+        if let Err(e) = self.send(&receiver, &message).await {
+            tracing::info!("{receiver} is unreachable, keeping the message: {e}");
+            self.storage.push(&receiver, message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Try to deliver the messages kept for `receiver`; returns the number of
+    /// delivered messages, the others are kept
+    pub async fn deliver(&self, receiver: &str) -> Result<usize, Error> {
+        let mut messages = self.storage.take(receiver)?.into_iter();
+        let mut delivered = 0;
+
+        for message in messages.by_ref() {
+            if let Err(e) = self.send(receiver, &message).await {
+                tracing::debug!("{receiver} is still unreachable: {e}");
+                self.storage.push(receiver, message)?;
+                break;
+            }
+
+            delivered += 1;
+        }
+
+        // keep the remaining messages, in order
+        for message in messages {
+            self.storage.push(receiver, message)?;
+        }
+
+        Ok(delivered)
+    }
+
+    /// Try to deliver the messages kept for all receivers
+    pub async fn deliver_all(&self) -> Result<usize, Error> {
+        let mut delivered = 0;
+
+        for receiver in self.storage.receivers()? {
+            delivered += self.deliver(&receiver).await?;
+        }
+
+        Ok(delivered)
+    }
+
+    async fn send(&self, receiver: &str, message: &[u8]) -> Result<(), Error> {
+        let vid = self.db.as_store().get_verified_vid(receiver)?;
+
+        crate::transport::send_message_with_fallback(
+            vid.endpoint(),
+            vid.alternative_endpoints(),
+            &[],
+            message,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check_storage(storage: &dyn MailboxStorage) {
+        assert!(storage.receivers().unwrap().is_empty());
+
+        storage.push("did:example:bob", b"first".to_vec()).unwrap();
+        storage.push("did:example:bob", b"second".to_vec()).unwrap();
+        storage
+            .push("did:example:carol", b"third".to_vec())
+            .unwrap();
+
+        let mut receivers = storage.receivers().unwrap();
+        receivers.sort();
+        assert_eq!(receivers, ["did:example:bob", "did:example:carol"]);
+
+        assert_eq!(
+            storage.take("did:example:bob").unwrap(),
+            [b"first".to_vec(), b"second".to_vec()]
+        );
+        assert!(storage.take("did:example:bob").unwrap().is_empty());
+        assert_eq!(storage.receivers().unwrap(), ["did:example:carol"]);
+    }
+
+    #[test]
+    fn memory_storage() {
+        check_storage(&MemoryStorage::default());
+    }
+
+    #[test]
+    fn directory_storage() {
+        let path = std::env::temp_dir().join(format!("tsp-mailbox-{}", std::process::id()));
+        check_storage(&DirectoryStorage::new(&path));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+#[cfg(feature = "serialize")]
+use serde::{Serialize, Serializer};
+
+/// What [Debug](fmt::Debug) and serialization show instead of secret data
+pub const REDACTED: &str = "[redacted]";
+
+/// Holds secret data, like private key material, which is wiped from memory on drop
+///
+/// The data is kept on the heap, so moving a [Secret] around leaves no copies behind.
+/// With the `mlock` feature, the memory pages that hold the data are locked, so it is
+/// never written to swap. Secrets that share a page keep it locked until the last of them
+/// is dropped. Locking is best effort: the OS limits how much memory a process may lock
+/// (`RLIMIT_MEMLOCK`, often only 64 KiB), and every secret locks at least one whole page,
+/// so only the first secrets that fit within the limit are locked; failures are logged
+/// at debug level.
+///
+/// Formatting and serializing a [Secret] only shows [REDACTED], reading the data
+/// always goes through an explicit call to [Secret::expose_secret].
+pub struct Secret<T: Zeroize>(Box<T>);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        let secret = Self(Box::new(value));
+        secret.lock();
+
+        secret
+    }
+
+    /// Access the secret data
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+
+    /// Access the secret data to fill it in place, e.g. when deriving a key
+    pub fn expose_secret_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    #[cfg(all(feature = "mlock", unix))]
+    fn lock(&self) {
+        let data: *const T = &*self.0;
+
+        pages::lock(data as usize, std::mem::size_of::<T>());
+    }
+
+    #[cfg(not(all(feature = "mlock", unix)))]
+    fn lock(&self) {}
+
+    #[cfg(all(feature = "mlock", unix))]
+    fn unlock(&self) {
+        let data: *const T = &*self.0;
+
+        pages::unlock(data as usize, std::mem::size_of::<T>());
+    }
+
+    #[cfg(not(all(feature = "mlock", unix)))]
+    fn unlock(&self) {}
+}
+
+/// Memory locks are not nested: a single `munlock` unlocks a page, however many secrets
+/// it holds. The pages are therefore locked and unlocked here, counting the secrets on
+/// every page.
+#[cfg(all(feature = "mlock", unix))]
+mod pages {
+    use std::{
+        collections::BTreeMap,
+        sync::{Mutex, OnceLock, PoisonError},
+    };
+
+    /// The number of secrets on every page that holds any, by page address
+    static SECRETS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+    pub(super) fn page_size() -> usize {
+        static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+
+        *PAGE_SIZE.get_or_init(|| {
+            // SAFETY: sysconf has no preconditions
+            let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+            usize::try_from(size)
+                .ok()
+                .filter(|size| *size > 0)
+                .unwrap_or(4096)
+        })
+    }
+
+    /// The addresses of the pages that `len` bytes at address `data` span
+    fn pages(data: usize, len: usize) -> impl Iterator<Item = usize> {
+        let page_size = page_size();
+        let start = data - data % page_size;
+        let end = if len == 0 { start } else { data + len };
+
+        (start..end).step_by(page_size)
+    }
+
+    /// Lock the pages of a secret of `len` bytes at address `data`, unless another
+    /// secret locked them already
+    pub(super) fn lock(data: usize, len: usize) {
+        let mut secrets = SECRETS.lock().unwrap_or_else(PoisonError::into_inner);
+
+        for page in pages(data, len) {
+            let count = secrets.entry(page).or_default();
+
+            // SAFETY: the page is mapped, as it holds (part of) the secret
+            if *count == 0 && unsafe { libc::mlock(page as *const _, page_size()) } != 0 {
+                tracing::debug!("could not lock secret data in memory");
+            }
+
+            // a page that could not be locked is counted as well, to keep the counts balanced
+            *count += 1;
+        }
+    }
+
+    /// Unlock the pages of a secret of `len` bytes at address `data` that no other
+    /// secret is on
+    pub(super) fn unlock(data: usize, len: usize) {
+        let mut secrets = SECRETS.lock().unwrap_or_else(PoisonError::into_inner);
+
+        for page in pages(data, len) {
+            let Some(count) = secrets.get_mut(&page) else {
+                continue;
+            };
+
+            *count -= 1;
+            if *count == 0 {
+                secrets.remove(&page);
+
+                // SAFETY: see `lock`; unlocking a page that was not locked is harmless
+                unsafe { libc::munlock(page as *const _, page_size()) };
+            }
+        }
+    }
+
+    /// The number of secrets on the page that holds address `data`
+    #[cfg(test)]
+    pub(super) fn secrets_on_page(data: usize) -> usize {
+        let secrets = SECRETS.lock().unwrap_or_else(PoisonError::into_inner);
+
+        pages(data, 1)
+            .next()
+            .and_then(|page| secrets.get(&page).copied())
+            .unwrap_or_default()
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        self.unlock();
+    }
+}
+
+impl<T: Zeroize> ZeroizeOnDrop for Secret<T> {}
+
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.expose_secret().clone())
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<T: Zeroize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let secret = Secret::new([42u8; 32]);
+
+        assert_eq!(format!("{secret:?}"), "Secret([redacted])");
+        assert_eq!(secret.expose_secret(), &[42u8; 32]);
+        assert_eq!(secret.clone().expose_secret(), &[42u8; 32]);
+
+        #[cfg(feature = "serialize")]
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"[redacted]\"");
+    }
+
+    #[cfg(all(feature = "mlock", unix))]
+    #[test]
+    fn test_shared_pages() {
+        use super::pages::{lock, page_size, secrets_on_page, unlock};
+
+        // pages of a buffer no secret is on
+        let page_size = page_size();
+        let buffer = vec![0u8; 3 * page_size];
+        let page = (buffer.as_ptr() as usize).next_multiple_of(page_size);
+
+        lock(page, 32);
+        lock(page + 64, 32);
+        assert_eq!(secrets_on_page(page), 2);
+
+        unlock(page, 32);
+        assert_eq!(secrets_on_page(page), 1);
+
+        // a secret across two pages is on both
+        lock(page + page_size - 16, 32);
+        assert_eq!(secrets_on_page(page), 2);
+        assert_eq!(secrets_on_page(page + page_size), 1);
+
+        unlock(page + 64, 32);
+        unlock(page + page_size - 16, 32);
+        assert_eq!(secrets_on_page(page), 0);
+        assert_eq!(secrets_on_page(page + page_size), 0);
+    }
+}
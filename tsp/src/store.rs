@@ -1,17 +1,31 @@
 use crate::{
-    cesr::EnvelopeType,
-    crypto::CryptoError,
+    archive::{ArchivedMessage, MessageArchive},
+    audit::{AuditEvent, AuditLog, RelationshipState},
+    cesr::{CryptoType, EnvelopeType},
+    crypto::{
+        session::{MessageKey, ReceivingSession, SendingSession},
+        CryptoError,
+    },
     definitions::{
-        Digest, MessageType, Payload, PrivateVid, ReceivedTspMessage, RelationshipStatus,
-        VerifiedVid,
+        CipherSuite, Digest, DigestAlgorithm, MembershipChange, MessageHeaders, MessageType,
+        NonConfidentialPlacement, OutstandingThreadId, Payload, PrivateVid, ReceivedTspMessage,
+        RelationshipStatus, SealOptions, VerifiedVid,
     },
     error::Error,
-    vid::{resolve::verify_vid_offline, VidError},
-    ExportVid, OwnedVid,
+    graph::{Edge, EdgeKind, Node, RelationshipGraph},
+    telemetry,
+    vid::{
+        codec::PrivateAsVerified, resolve::verify_vid_offline, CustomFields, Provenance,
+        VerificationPolicy, VidCodec, VidError, VidMetadata, VidOrigin, VidStats,
+    },
+    ControlExtension, ExportVid, ForwardGuard, OwnedVid,
 };
+use dashmap::{mapref::one::RefMut, DashMap, DashSet};
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use url::Url;
 
@@ -23,6 +37,13 @@ pub(crate) struct VidContext {
     relation_vid: Option<String>,
     parent_vid: Option<String>,
     tunnel: Option<Box<[String]>>,
+    metadata: Option<serde_json::Value>,
+    vid_metadata: Option<VidMetadata>,
+    stats: VidStats,
+    digest_algorithm: Option<DigestAlgorithm>,
+    cipher_suite: Option<CipherSuite>,
+    provenance: Option<Provenance>,
+    transport_override: Option<Url>,
 }
 
 impl VidContext {
@@ -55,6 +76,13 @@ impl VidContext {
         }
     }
 
+    /// When this VID expires, if it is one of our one-shot VIDs
+    fn expires_at(&self) -> Option<u64> {
+        self.private
+            .as_ref()
+            .and_then(|private| private.expires_at())
+    }
+
     /// Get the parent VID for this VID
     pub(crate) fn get_parent_vid(&self) -> Option<&str> {
         self.parent_vid.as_deref()
@@ -69,17 +97,240 @@ impl VidContext {
     pub(crate) fn get_route(&self) -> Option<&[String]> {
         self.tunnel.as_deref()
     }
+
+    /// The endpoint messages for this VID are sent to: the transport override if it has
+    /// one, otherwise the endpoint of the VID itself
+    fn endpoint(&self) -> &Url {
+        self.transport_override
+            .as_ref()
+            .unwrap_or_else(|| self.vid.endpoint())
+    }
+
+    /// This VID, its keys and our relationship with it in serializable form
+    fn export(&self) -> ExportVid {
+        ExportVid {
+            id: self.vid.identifier().to_string(),
+            transport: self.vid.endpoint().clone(),
+            alternative_transports: self.vid.alternative_endpoints().to_vec(),
+            public_sigkey: self.vid.verifying_key().clone(),
+            public_enckey: self.vid.encryption_key().clone(),
+            sigkey: self.private.as_ref().map(|x| x.signing_key().clone()),
+            enckey: self.private.as_ref().map(|x| x.decryption_key().clone()),
+            relation_status: self.relation_status.clone(),
+            relation_vid: self.relation_vid.clone(),
+            parent_vid: self.parent_vid.clone(),
+            tunnel: self.tunnel.clone(),
+            metadata: self.metadata.clone(),
+            vid_metadata: self.vid_metadata.clone(),
+            stats: self.stats.clone(),
+            digest_algorithm: self.digest_algorithm,
+            cipher_suite: self.cipher_suite,
+            provenance: self.provenance.clone(),
+            transport_override: self.transport_override.clone(),
+            custom: self.vid.custom_fields(),
+        }
+    }
+
+    /// Use the digest algorithm and cipher suite of the relationship, unless `options`
+    /// choose them
+    fn seal_options(&self, options: SealOptions) -> SealOptions {
+        SealOptions {
+            digest: options.digest.or(self.digest_algorithm),
+            cipher_suite: options.cipher_suite.or(self.cipher_suite),
+            ..options
+        }
+    }
+}
+
+/// A named set of VIDs that all receive the messages sent to the group
+#[derive(Clone, Debug)]
+pub struct Group {
+    owner: String,
+    members: Vec<String>,
+}
+
+impl Group {
+    /// The private VID used to send messages to the members
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// The VIDs of the members, in the order they were added
+    pub fn members(&self) -> &[String] {
+        &self.members
+    }
+}
+
+/// What is removed from a store when a relationship ends, see [`Store::cleanup_relationship`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RelationshipCleanup {
+    /// Nested VIDs of the peer, and our own nested VIDs created for them, that are removed
+    pub nested_vids: Vec<String>,
+    /// VIDs whose route passes through the peer or a removed VID; the route is cleared
+    pub routes: Vec<String>,
+    /// VIDs whose relation VID is removed; the relation VID is cleared
+    pub relations: Vec<String>,
+}
+
+impl RelationshipCleanup {
+    /// Whether nothing is removed
+    pub fn is_empty(&self) -> bool {
+        self.nested_vids.is_empty() && self.routes.is_empty() && self.relations.is_empty()
+    }
 }
 
+/// A VID restored from an export, and its private part if it has one
+type ImportedVid = (Arc<dyn VerifiedVid>, Option<Arc<dyn PrivateVid>>);
+
 /// Holds private ands verified VIDs
 /// A Store contains verified vid's, our relationship status to them,
 /// as well as the private vid's that this application has control over.
 ///
 /// The struct is the primary interface to the VID database, in a synchronous
 /// context (when no async runtime is available).
+///
+/// # Concurrency
+///
+/// Clones of a store share the same VIDs, groups and settings. The VIDs are kept in a sharded
+/// map, so sealing, opening and forwarding messages can run in parallel on many
+/// threads: these operations only briefly lock a shard to copy a VID out of the map,
+/// and never hold a lock during cryptographic operations. Changes to a VID, such as
+/// relationship updates, lock only the shard that holds it. Changes to groups are
+/// serialized by a single lock.
+///
+/// # Sessions
+///
+/// Many messages to the same VID can be sealed with a symmetric session key instead of
+/// HPKE, see [Store::make_session_key]. Sessions are kept in memory only; they are not
+/// part of an [export](Store::export), and are established again after a restart.
+///
+/// # Tenants
+///
+/// A server that hosts VIDs for many users can keep them in one store, with a
+/// [tenant](Store::tenant) per user: every tenant has its own VIDs and groups, which
+/// the store and its other tenants cannot see.
 #[derive(Default, Clone)]
 pub struct Store {
-    pub(crate) vids: Arc<RwLock<HashMap<String, VidContext>>>,
+    pub(crate) vids: Arc<DashMap<String, VidContext>>,
+    groups: Arc<RwLock<HashMap<String, Group>>>,
+    tenants: Arc<DashMap<String, Store>>,
+    /// Sessions for the messages we send, by (sender, receiver)
+    sending_sessions: Arc<DashMap<(String, String), SendingSession>>,
+    /// Sessions for the messages we receive, by (sender, receiver)
+    receiving_sessions: Arc<DashMap<(String, String), ReceivingSession>>,
+    /// Shared with clones, so a later change applies to them as well
+    settings: Arc<RwLock<StoreSettings>>,
+    /// Shared with clones and tenants, so a later change applies to them as well
+    relationship_requirement: Arc<RwLock<RelationshipRequirement>>,
+    /// The VIDs that were added, changed or removed since the last [Store::take_changes]
+    changed: Arc<DashSet<String>>,
+}
+
+/// The settings of a [Store]
+#[derive(Default, Clone)]
+struct StoreSettings {
+    config: StoreConfig,
+    /// Seal every message as if [SealOptions::hide_identity] was set
+    hide_identities: bool,
+    policy: Option<Arc<dyn VerificationPolicy>>,
+    forward_guard: Option<Arc<dyn ForwardGuard>>,
+    audit: Option<Arc<AuditLog>>,
+    archive: Option<Arc<MessageArchive>>,
+    /// Codecs of custom VID types, by name
+    vid_codecs: HashMap<String, Arc<dyn VidCodec>>,
+    /// Application defined control messages, by type code
+    extensions: HashMap<u8, Arc<dyn ControlExtension>>,
+}
+
+/// The VIDs that changed since the previous call to [Store::take_changes], to write them to
+/// a vault with `persist_changes` instead of rewriting the whole wallet
+#[derive(Clone, Default)]
+pub struct StoreChanges {
+    /// The VIDs that were added or changed
+    pub vids: Vec<ExportVid>,
+    /// The identifiers of the VIDs that were removed
+    pub removed: Vec<String>,
+}
+
+impl StoreChanges {
+    /// Whether no VID changed
+    pub fn is_empty(&self) -> bool {
+        self.vids.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Resource limits enforced when sealing and opening messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreConfig {
+    /// The maximum size in bytes of a message payload
+    pub max_payload_size: usize,
+    /// The maximum number of hops in the route of a routed message
+    pub max_hops: usize,
+    /// The maximum number of nested messages wrapped inside each other
+    pub max_nested_depth: usize,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            max_payload_size: 8 * 1024 * 1024,
+            max_hops: 16,
+            max_nested_depth: 8,
+        }
+    }
+}
+
+/// The relationship [Store::open_message] requires with the sender of a generic message,
+/// see [Store::set_relationship_requirement]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RelationshipRequirement {
+    /// Open generic messages from every verified sender
+    #[default]
+    None,
+    /// Refuse generic messages from unrelated senders; a relationship we requested, that
+    /// was not accepted yet, is enough
+    Related,
+    /// Refuse generic messages from senders we do not have an accepted, bidirectional
+    /// relationship with
+    Bidirectional,
+}
+
+impl RelationshipRequirement {
+    /// Whether a generic message from a sender we have `status` with is opened
+    fn allows(self, status: &RelationshipStatus) -> bool {
+        match self {
+            RelationshipRequirement::None => true,
+            RelationshipRequirement::Related => !matches!(status, RelationshipStatus::Unrelated),
+            RelationshipRequirement::Bidirectional => {
+                matches!(status, RelationshipStatus::Bidirectional { .. })
+            }
+        }
+    }
+}
+
+/// The scheme of the endpoint of nested VIDs, which are reached through their parent
+const PLACEHOLDER_SCHEME: &str = "tsp";
+
+/// The current time in seconds since the Unix epoch, for relationship expiry
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// The audit log event of a received `message`, read before it is opened
+fn received_event(message: &[u8]) -> Option<AuditEvent> {
+    let (sender, receiver) = crate::cesr::get_sender_receiver(message).ok()?;
+
+    Some(AuditEvent::MessageReceived {
+        sender: std::str::from_utf8(sender).ok()?.to_string(),
+        receiver: match receiver {
+            Some(receiver) => Some(std::str::from_utf8(receiver).ok()?.to_string()),
+            None => None,
+        },
+        digest: crate::crypto::sha256(message),
+    })
 }
 
 /// This database is used to store and resolve VIDs
@@ -89,43 +340,454 @@ impl Store {
         Default::default()
     }
 
-    /// Export the database to serializable default types
-    pub fn export(&self) -> Result<Vec<ExportVid>, Error> {
+    /// Create a new, empty VID database with the specified resource limits
+    pub fn with_config(config: StoreConfig) -> Self {
+        let store = Self::default();
+        store.set_config(config);
+
+        store
+    }
+
+    /// The settings of this database; a setting is only ever replaced as a whole, so they
+    /// are consistent even if a panic poisoned the lock
+    fn settings(&self) -> RwLockReadGuard<'_, StoreSettings> {
+        self.settings.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Change the settings of this database and its clones
+    fn change_settings(&self, change: impl FnOnce(&mut StoreSettings)) {
+        change(
+            &mut self
+                .settings
+                .write()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+
+    /// Get the resource limits of this database
+    pub fn config(&self) -> StoreConfig {
+        self.settings().config
+    }
+
+    /// Replace the resource limits of this database and its clones
+    pub fn set_config(&self, config: StoreConfig) {
+        self.change_settings(|settings| settings.config = config);
+    }
+
+    /// Refuse to open generic messages from senders we do not have `requirement` with, with
+    /// [Error::NoRelationship], so applications only see messages within a relationship.
+    /// Messages with the [RELATIONSHIP_REQUIRED](crate::definitions::content_type::RELATIONSHIP_REQUIRED)
+    /// content type are always opened, and [Store::open_message_from] is not affected.
+    ///
+    /// The requirement applies to all clones and tenants of this store
+    pub fn set_relationship_requirement(
+        &self,
+        requirement: RelationshipRequirement,
+    ) -> Result<(), Error> {
+        *self.relationship_requirement.write()? = requirement;
+
+        Ok(())
+    }
+
+    /// Check the relationship with the sender of a generic message against the
+    /// relationship requirement
+    fn check_relationship(&self, sender: &str, content_type: Option<&str>) -> Result<(), Error> {
+        let requirement = *self.relationship_requirement.read()?;
+
+        if requirement == RelationshipRequirement::None
+            || content_type == Some(crate::definitions::content_type::RELATIONSHIP_REQUIRED)
+        {
+            return Ok(());
+        }
+
+        let allowed = self
+            .vids
+            .get(sender)
+            .is_some_and(|context| requirement.allows(&context.relation_status));
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::NoRelationship(sender.to_string()))
+        }
+    }
+
+    /// Consult `policy` before VIDs learned from received messages are added or reported
+    pub fn set_verification_policy(&self, policy: impl VerificationPolicy + 'static) {
+        self.change_settings(|settings| settings.policy = Some(Arc::new(policy)));
+    }
+
+    /// Check a VID against the verification policy, if one is set
+    pub(crate) fn check_policy(&self, vid: &str, origin: VidOrigin) -> Result<(), Error> {
+        let policy = self.settings().policy.clone();
+
+        match policy {
+            Some(policy) => policy
+                .check(vid, origin)
+                .map_err(|reason| Error::PolicyRejected(vid.to_string(), reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Seal every message as if [SealOptions::hide_identity] was set: sealing or signing a
+    /// message that would expose a nested VID as its sender fails with
+    /// [Error::IdentityLeak]. Otherwise such messages are only logged as a warning
+    pub fn set_hide_identities(&self, enabled: bool) {
+        self.change_settings(|settings| settings.hide_identities = enabled);
+    }
+
+    /// Check that `sender` is not a nested VID, before it is put on an envelope that others
+    /// than the receiver can read; refused if `hide` is set, otherwise logged
+    fn check_identity_exposure(&self, sender: &str, hide: bool) -> Result<(), Error> {
+        let nested = self
+            .vids
+            .get(sender)
+            .is_some_and(|context| context.get_parent_vid().is_some());
+
+        if !nested {
+            return Ok(());
+        }
+
+        if hide {
+            return Err(Error::IdentityLeak(sender.to_string()));
+        }
+
+        #[cfg(feature = "async")]
+        tracing::warn!(
+            sender = %telemetry::fingerprint(sender),
+            "the nested sender VID is exposed on the envelope"
+        );
+
+        Ok(())
+    }
+
+    /// Consult `guard` before routed messages are forwarded
+    pub fn set_forward_guard(&self, guard: impl ForwardGuard + 'static) {
+        self.change_settings(|settings| settings.forward_guard = Some(Arc::new(guard)));
+    }
+
+    /// Check a forwarded message against the forward guard, if one is set
+    fn check_forward(
+        &self,
+        sender: &str,
+        next_hop: &str,
+        payload_size: usize,
+    ) -> Result<(), Error> {
+        let guard = self.settings().forward_guard.clone();
+
+        match guard {
+            Some(guard) => guard
+                .check(sender, next_hop, payload_size)
+                .map_err(|reason| Error::ForwardRefused(next_hop.to_string(), reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Restore imported VIDs whose [custom fields](VerifiedVid::custom_fields) name `name`
+    /// as their codec with `codec`
+    pub fn register_vid_codec(&self, name: impl Into<String>, codec: impl VidCodec + 'static) {
+        self.change_settings(|settings| {
+            settings.vid_codecs.insert(name.into(), Arc::new(codec));
+        });
+    }
+
+    /// Seal and open the control messages of type `typecode` with `extension`; see
+    /// [Store::make_extension_message]
+    pub fn register_control_extension(
+        &self,
+        typecode: u8,
+        extension: impl ControlExtension + 'static,
+    ) {
+        self.change_settings(|settings| {
+            settings.extensions.insert(typecode, Arc::new(extension));
+        });
+    }
+
+    /// The extension registered for control messages of type `typecode`
+    fn extension(&self, typecode: u8) -> Result<Arc<dyn ControlExtension>, Error> {
+        self.settings()
+            .extensions
+            .get(&typecode)
+            .cloned()
+            .ok_or(Error::UnknownExtension(typecode))
+    }
+
+    /// Restore a VID of a custom type with the codec named in its `custom` fields
+    fn decode_custom(
+        &self,
+        vid: &mut ExportVid,
+        custom: CustomFields,
+    ) -> Result<ImportedVid, Error> {
+        let codec = self
+            .settings()
+            .vid_codecs
+            .get(&custom.codec)
+            .cloned()
+            .ok_or_else(|| VidError::UnknownCodec(custom.codec.clone()))?;
+
+        Ok(match vid.take_private_vid() {
+            Some(private) => {
+                let private = codec.decode_private(private, &custom.data)?;
+
+                (Arc::new(PrivateAsVerified(private.clone())), Some(private))
+            }
+            None => (
+                codec.decode_verified(vid.verified_vid(), &custom.data)?,
+                None,
+            ),
+        })
+    }
+
+    /// Record security-relevant events in `log`: VIDs that are added or removed, changed
+    /// keys and relationships, and the digests of the messages that are sealed and opened
+    pub fn set_audit_log(&self, log: AuditLog) {
+        self.change_settings(|settings| settings.audit = Some(Arc::new(log)));
+    }
+
+    /// The audit log of this database, if one is set
+    pub fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.settings().audit.clone()
+    }
+
+    /// Record the generic messages that are sealed and opened in `archive`, to look them
+    /// up by digest; see [MessageArchive]
+    pub fn set_message_archive(&self, archive: MessageArchive) {
+        self.change_settings(|settings| settings.archive = Some(Arc::new(archive)));
+    }
+
+    /// The message archive of this database, if one is set
+    pub fn message_archive(&self) -> Option<Arc<MessageArchive>> {
+        self.settings().archive.clone()
+    }
+
+    /// Seal a message with `seal`, which is passed the payload and where to write its
+    /// digest; a generic message is recorded in the message archive, if one is set
+    fn seal_archived<'a, T>(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        payload: Payload<'a, &'a [u8]>,
+        digest: Option<&mut Digest>,
+        seal: impl FnOnce(Payload<'a, &'a [u8]>, Option<&mut Digest>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let archive = self.message_archive();
+        let entry = archive.as_ref().and_then(|archive| {
+            ArchivedMessage::sent(sender, receiver, nonconfidential_data, &payload)
+                .map(|entry| (archive, entry))
+        });
+
+        let Some((archive, mut entry)) = entry else {
+            return seal(payload, digest);
+        };
+
+        let mut own_digest = Digest::default();
+        let digest = digest.unwrap_or(&mut own_digest);
+        *digest = Digest::default();
+        let result = seal(payload, Some(&mut *digest))?;
+
+        // content that is signed instead of sealed, in nested mode, has no digest
+        if *digest != Digest::default() {
+            entry.digest = *digest;
+            archive.record(entry);
+        }
+
+        Ok(result)
+    }
+
+    /// Record a generic message opened from `message`, which was addressed to `receiver`,
+    /// in the message archive, if one is set; fails if the archive refuses duplicates and
+    /// the message was received before
+    fn archive_received(
+        &self,
+        receiver: Option<String>,
+        received: &ReceivedTspMessage<&[u8]>,
+    ) -> Result<(), Error> {
+        let Some(archive) = self.message_archive() else {
+            return Ok(());
+        };
+
+        if let Some(entry) = ArchivedMessage::received(receiver, received) {
+            let sender = entry.sender.clone();
+
+            if !archive.record(entry) && archive.config().refuse_duplicates {
+                return Err(Error::DuplicateMessage(sender));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the event created by `event` in the audit log, if one is set
+    fn audit(&self, event: impl FnOnce() -> AuditEvent) {
+        if let Some(log) = self.audit_log() {
+            log.record(event());
+        }
+    }
+
+    /// Record a change of the relationship with `vid` in the audit log
+    fn audit_relationship(&self, vid: &str, from: RelationshipState, to: RelationshipState) {
+        if from != to {
+            self.audit(|| AuditEvent::RelationshipChanged {
+                vid: vid.to_string(),
+                from,
+                to,
+            });
+        }
+    }
+
+    /// Record a message sealed for `receiver` in the audit log
+    fn audit_sent(&self, sender: &str, receiver: &str, message: &[u8]) {
+        self.audit(|| AuditEvent::MessageSent {
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            digest: crate::crypto::sha256(message),
+        });
+    }
+
+    /// Record a VID that was added with [Store::add_verified_vid] or [Store::add_private_vid];
+    /// `previous` is the entry it replaced
+    fn audit_added(&self, vid: &dyn VerifiedVid, previous: Option<VidContext>) {
+        self.audit(|| match previous {
+            Some(previous) if previous.vid.verifying_key() != vid.verifying_key() => {
+                AuditEvent::KeyRotated {
+                    vid: vid.identifier().to_string(),
+                }
+            }
+            _ => AuditEvent::VidAdded {
+                vid: vid.identifier().to_string(),
+            },
+        });
+    }
+
+    /// Get the store of the tenant `name`, which is created on first use
+    ///
+    /// The tenant store holds its own VIDs and groups, isolated from this store and its
+    /// other tenants; clones of it share them, as do later calls with the same `name`.
+    /// A new tenant store starts out with the resource limits, verification policy, forward
+    /// guard, VID codecs and control extensions of this store, and always follows its
+    /// relationship requirement.
+    pub fn tenant(&self, name: &str) -> Store {
+        self.tenants
+            .entry(name.to_string())
+            .or_insert_with(|| Store {
+                settings: Arc::new(RwLock::new(StoreSettings {
+                    audit: None,
+                    archive: None,
+                    ..self.settings().clone()
+                })),
+                relationship_requirement: self.relationship_requirement.clone(),
+                ..Default::default()
+            })
+            .clone()
+    }
+
+    /// List the names of the tenants of this store
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants
+            .iter()
+            .map(|tenant| tenant.key().clone())
+            .collect()
+    }
+
+    /// Remove the tenant `name` and all of its VIDs; returns whether the tenant existed
+    pub fn remove_tenant(&self, name: &str) -> bool {
+        self.tenants.remove(name).is_some()
+    }
+
+    /// The alternative endpoints of the VID that is reached at `endpoint`
+    #[cfg(feature = "async")]
+    pub(crate) fn alternative_endpoints(&self, endpoint: &Url) -> Vec<Url> {
         self.vids
-            .read()?
-            .values()
-            .map(|context| {
-                Ok(ExportVid {
-                    id: context.vid.identifier().to_string(),
-                    transport: context.vid.endpoint().clone(),
-                    public_sigkey: context.vid.verifying_key().clone(),
-                    public_enckey: context.vid.encryption_key().clone(),
-                    sigkey: context.private.as_ref().map(|x| x.signing_key().clone()),
-                    enckey: context.private.as_ref().map(|x| x.decryption_key().clone()),
-                    relation_status: context.relation_status.clone(),
-                    relation_vid: context.relation_vid.clone(),
-                    parent_vid: context.parent_vid.clone(),
-                    tunnel: context.tunnel.clone(),
-                })
+            .iter()
+            .find(|context| {
+                context.vid.endpoint() == endpoint
+                    && !context.vid.alternative_endpoints().is_empty()
             })
+            .map(|context| context.vid.alternative_endpoints().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Export the database to serializable default types; one-shot VIDs, see
+    /// [OwnedVid::new_ephemeral], are left out
+    pub fn export(&self) -> Result<Vec<ExportVid>, Error> {
+        Ok(self
+            .vids
+            .iter()
+            .filter(|context| context.expires_at().is_none())
+            .map(|context| context.export())
+            .collect())
+    }
+
+    /// Take the VIDs that were added, changed or removed since the previous call, e.g. to
+    /// persist only those; VIDs that are [imported](Store::import) count as unchanged, as
+    /// they usually come from the wallet they would be written to
+    pub fn take_changes(&self) -> StoreChanges {
+        let changed = self
+            .changed
+            .iter()
+            .map(|vid| vid.key().clone())
+            .collect::<Vec<_>>();
+
+        let mut changes = StoreChanges::default();
+        for vid in changed {
+            // a VID that changes in the meantime is marked again, and taken next time
+            self.changed.remove(&vid);
+
+            match self.vids.get(&vid) {
+                Some(context) if context.expires_at().is_some() => {}
+                Some(context) => changes.vids.push(context.export()),
+                None => changes.removed.push(vid),
+            }
+        }
+
+        changes
+    }
+
+    /// Get a VID to change it, and mark it as changed
+    fn vid_mut(&self, vid: &str) -> Option<RefMut<'_, String, VidContext>> {
+        let context = self.vids.get_mut(vid)?;
+        self.changed.insert(vid.to_string());
+
+        Some(context)
+    }
+
+    /// Export only the VIDs `vids`, e.g. to persist them as soon as they changed
+    pub fn export_vids(&self, vids: &[&str]) -> Result<Vec<ExportVid>, Error> {
+        vids.iter()
+            .map(|vid| Ok(self.get_vid(vid)?.export()))
             .collect()
     }
 
-    /// Import the database from serializable default types
+    /// Import the database from serializable default types; VIDs of custom types are
+    /// restored by the codec registered with [Store::register_vid_codec]
     pub fn import(&self, vids: Vec<ExportVid>) -> Result<(), Error> {
-        vids.into_iter().try_for_each(|vid| {
-            self.vids.write()?.insert(
+        vids.into_iter().try_for_each(|mut vid| {
+            let (verified, private) = match vid.custom.take() {
+                Some(custom) => self.decode_custom(&mut vid, custom)?,
+                None => (
+                    Arc::new(vid.verified_vid()) as Arc<dyn VerifiedVid>,
+                    vid.take_private_vid()
+                        .map(|private| Arc::new(private) as Arc<dyn PrivateVid>),
+                ),
+            };
+
+            self.vids.insert(
                 vid.id.to_string(),
                 VidContext {
-                    vid: Arc::new(vid.verified_vid()),
-                    private: match vid.private_vid() {
-                        Some(private) => Some(Arc::new(private)),
-                        None => None,
-                    },
+                    vid: verified,
+                    private,
                     relation_status: vid.relation_status,
                     relation_vid: vid.relation_vid,
                     parent_vid: vid.parent_vid,
                     tunnel: vid.tunnel,
+                    metadata: vid.metadata,
+                    vid_metadata: vid.vid_metadata,
+                    stats: vid.stats,
+                    digest_algorithm: vid.digest_algorithm,
+                    cipher_suite: vid.cipher_suite,
+                    provenance: vid.provenance,
+                    transport_override: vid.transport_override,
                 },
             );
 
@@ -133,20 +795,54 @@ impl Store {
         })
     }
 
+    /// Export the database as a password protected backup that can be imported
+    /// with [`Store::import_encrypted`], e.g. on another machine
+    #[cfg(feature = "serialize")]
+    pub fn export_encrypted(&self, password: &str) -> Result<Vec<u8>, Error> {
+        crate::backup::seal(&self.export()?, password)
+    }
+
+    /// Import a backup created by [`Store::export_encrypted`]; unlike [Store::import], the
+    /// VIDs of the backup count as changed
+    #[cfg(feature = "serialize")]
+    pub fn import_encrypted(&self, backup: &[u8], password: &str) -> Result<(), Error> {
+        let vids = crate::backup::open(backup, password)?;
+        let ids = vids.iter().map(|vid| vid.id.clone()).collect::<Vec<_>>();
+
+        self.import(vids)?;
+        ids.into_iter().for_each(|id| {
+            self.changed.insert(id);
+        });
+
+        Ok(())
+    }
+
     /// Add the already resolved `verified_vid` to the database as a relationship
     pub fn add_verified_vid(&self, verified_vid: impl VerifiedVid + 'static) -> Result<(), Error> {
-        self.vids.write()?.insert(
-            verified_vid.identifier().to_string(),
+        let vid = Arc::new(verified_vid);
+
+        let previous = self.vids.insert(
+            vid.identifier().to_string(),
             VidContext {
-                vid: Arc::new(verified_vid),
+                vid: vid.clone(),
                 private: None,
                 relation_status: RelationshipStatus::Unrelated,
                 relation_vid: None,
                 parent_vid: None,
                 tunnel: None,
+                metadata: None,
+                vid_metadata: None,
+                stats: VidStats::default(),
+                digest_algorithm: None,
+                cipher_suite: None,
+                provenance: Some(Provenance::Manual),
+                transport_override: None,
             },
         );
 
+        self.changed.insert(vid.identifier().to_string());
+        self.audit_added(&*vid, previous);
+
         Ok(())
     }
 
@@ -154,28 +850,148 @@ impl Store {
     pub fn add_private_vid(&self, private_vid: impl PrivateVid + 'static) -> Result<(), Error> {
         let vid = Arc::new(private_vid);
 
-        self.vids.write()?.insert(
+        let previous = self.vids.insert(
             vid.identifier().to_string(),
             VidContext {
                 vid: vid.clone(),
-                private: Some(vid),
+                private: Some(vid.clone()),
                 relation_status: RelationshipStatus::Unrelated,
                 relation_vid: None,
                 parent_vid: None,
                 tunnel: None,
+                metadata: None,
+                vid_metadata: None,
+                stats: VidStats::default(),
+                digest_algorithm: None,
+                cipher_suite: None,
+                provenance: None,
+                transport_override: None,
             },
         );
 
+        // one-shot VIDs are never written to a wallet
+        if vid.expires_at().is_none() {
+            self.changed.insert(vid.identifier().to_string());
+        }
+        self.audit_added(&*vid, previous);
+
         Ok(())
     }
 
     /// Remove a VID from the database
     pub fn forget_vid(&self, vid: &str) -> Result<(), Error> {
-        self.vids.write()?.remove(vid);
+        if self.vids.remove(vid).is_some() {
+            self.changed.insert(vid.to_string());
+            self.audit(|| AuditEvent::VidRemoved {
+                vid: vid.to_string(),
+            });
+        }
 
         Ok(())
     }
 
+    /// Remove what was set up for the relationship with `peer`, which has ended: the nested
+    /// VIDs of the peer (and theirs, recursively), our own nested VIDs related to those,
+    /// the routes through any of these VIDs or the peer, and the relation VIDs that point
+    /// to a removed VID. The peer itself is kept.
+    ///
+    /// With `dry_run`, nothing is changed and the report lists what would be removed.
+    /// This happens automatically when a relationship is cancelled.
+    pub fn cleanup_relationship(
+        &self,
+        peer: &str,
+        dry_run: bool,
+    ) -> Result<RelationshipCleanup, Error> {
+        self.get_vid(peer)?;
+
+        // breadth-first walk from the peer; the peer itself is the first entry
+        let mut removed = vec![peer.to_string()];
+        let mut next = 0;
+
+        while let Some(current) = removed.get(next).cloned() {
+            let found = self
+                .vids
+                .iter()
+                .filter(|context| !removed.contains(context.key()))
+                .filter(|context| {
+                    let nested = context.get_parent_vid() == Some(current.as_str());
+                    // our own nested VID, created for a nested VID of the peer
+                    let own = next > 0
+                        && context.private.is_some()
+                        && context.get_parent_vid().is_some()
+                        && context.get_relation_vid() == Some(current.as_str());
+
+                    nested || own
+                })
+                .map(|context| context.key().clone())
+                .collect::<Vec<_>>();
+
+            removed.extend(found);
+            next += 1;
+        }
+
+        let nested_vids = removed.split_off(1);
+        let mut cleanup = RelationshipCleanup {
+            nested_vids,
+            ..Default::default()
+        };
+
+        for context in self.vids.iter() {
+            if cleanup.nested_vids.contains(context.key()) {
+                continue;
+            }
+
+            if context.get_route().is_some_and(|route| {
+                route
+                    .iter()
+                    .any(|hop| hop == peer || cleanup.nested_vids.contains(hop))
+            }) {
+                cleanup.routes.push(context.key().clone());
+            }
+
+            if context
+                .get_relation_vid()
+                .is_some_and(|relation| cleanup.nested_vids.iter().any(|vid| vid == relation))
+            {
+                cleanup.relations.push(context.key().clone());
+            }
+        }
+
+        cleanup.nested_vids.sort();
+        cleanup.routes.sort();
+        cleanup.relations.sort();
+
+        if dry_run || cleanup.is_empty() {
+            return Ok(cleanup);
+        }
+
+        #[cfg(feature = "async")]
+        tracing::info!(
+            "relationship with {peer} ended: removing {} nested VIDs, {} routes and {} relations",
+            cleanup.nested_vids.len(),
+            cleanup.routes.len(),
+            cleanup.relations.len()
+        );
+
+        for vid in &cleanup.nested_vids {
+            self.forget_vid(vid)?;
+        }
+
+        for vid in &cleanup.routes {
+            self.modify_vid(vid, |context| {
+                context.set_route(Vec::new());
+
+                Ok(())
+            })?;
+        }
+
+        for vid in &cleanup.relations {
+            self.set_relation_for_vid(vid, None)?;
+        }
+
+        Ok(cleanup)
+    }
+
     /// Sets the parent for a VID, thus making it a nested VID
     pub fn set_parent_for_vid(&self, vid: &str, parent_vid: Option<&str>) -> Result<(), Error> {
         self.modify_vid(vid, |resolved| {
@@ -185,18 +1001,44 @@ impl Store {
         })
     }
 
-    /// Adds a relation to an already existing vid
-    pub fn set_relation_for_vid(&self, vid: &str, relation_vid: Option<&str>) -> Result<(), Error> {
-        self.modify_vid(vid, |resolved| {
-            resolved.set_relation_vid(relation_vid);
+    /// List the ancestors of a nested VID, starting with its parent and ending with
+    /// the outermost VID. The list is empty if the VID is not nested.
+    pub fn parent_chain(&self, vid: &str) -> Result<Vec<String>, Error> {
+        let mut chain: Vec<String> = Vec::new();
+        let mut current = vid.to_string();
 
-            Ok(())
-        })
-    }
+        while let Some(parent) = self
+            .vids
+            .get(&current)
+            .ok_or_else(|| Error::MissingVid(current.clone()))?
+            .get_parent_vid()
+            .map(str::to_string)
+        {
+            if parent == vid || chain.contains(&parent) {
+                return Err(Error::Relationship(format!(
+                    "cycle in the parent chain of {vid}"
+                )));
+            }
+
+            chain.push(parent.clone());
+            current = parent;
+        }
+
+        Ok(chain)
+    }
+
+    /// Adds a relation to an already existing vid
+    pub fn set_relation_for_vid(&self, vid: &str, relation_vid: Option<&str>) -> Result<(), Error> {
+        self.modify_vid(vid, |resolved| {
+            resolved.set_relation_vid(relation_vid);
+
+            Ok(())
+        })
+    }
 
     /// List all VIDs in the database
     pub fn list_vids(&self) -> Result<Vec<String>, Error> {
-        Ok(self.vids.read()?.keys().cloned().collect())
+        Ok(self.vids.iter().map(|entry| entry.key().clone()).collect())
     }
 
     /// Sets the relationship status and relation for a VID.
@@ -262,33 +1104,435 @@ impl Store {
         vid: &str,
         change: impl FnOnce(&mut VidContext) -> Result<T, Error>,
     ) -> Result<T, Error> {
-        match self.vids.write()?.get_mut(vid) {
-            Some(resolved) => change(resolved),
+        match self.vid_mut(vid) {
+            Some(mut resolved) => {
+                let from = RelationshipState::from(&resolved.relation_status);
+                let result = change(&mut resolved);
+                self.audit_relationship(vid, from, (&resolved.relation_status).into());
+
+                result
+            }
+            None => Err(Error::UnverifiedVid(vid.to_string())),
+        }
+    }
+
+    /// Get the application defined metadata of the VID identified by `vid`
+    pub fn get_metadata(&self, vid: &str) -> Result<Option<serde_json::Value>, Error> {
+        Ok(self.get_vid(vid)?.metadata)
+    }
+
+    /// Replace the application defined metadata of the VID identified by `vid`
+    pub fn set_metadata(
+        &self,
+        vid: &str,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        self.update_metadata(vid, |current| *current = metadata)
+    }
+
+    /// Modify the application defined metadata of the VID identified by `vid` in place
+    pub fn update_metadata<T>(
+        &self,
+        vid: &str,
+        change: impl FnOnce(&mut Option<serde_json::Value>) -> T,
+    ) -> Result<T, Error> {
+        self.modify_vid(vid, |context| Ok(change(&mut context.metadata)))
+    }
+
+    /// Get what was learned about the VID identified by `vid` when it was resolved,
+    /// e.g. the services and other identifiers listed in its DID document
+    pub fn get_vid_metadata(&self, vid: &str) -> Result<Option<VidMetadata>, Error> {
+        Ok(self.get_vid(vid)?.vid_metadata)
+    }
+
+    /// Replace the resolution metadata of the VID identified by `vid`
+    #[cfg(feature = "async")]
+    pub(crate) fn set_vid_metadata(&self, vid: &str, metadata: VidMetadata) -> Result<(), Error> {
+        self.modify_vid(vid, |context| {
+            context.vid_metadata = Some(metadata);
+
+            Ok(())
+        })
+    }
+
+    /// Get how the VID identified by `vid` entered the database; `None` for private VIDs
+    /// and VIDs that were added before provenance was recorded
+    pub fn provenance(&self, vid: &str) -> Result<Option<Provenance>, Error> {
+        Ok(self.get_vid(vid)?.provenance)
+    }
+
+    /// Record how the VID identified by `vid` entered the database
+    pub(crate) fn set_provenance(&self, vid: &str, provenance: Provenance) -> Result<(), Error> {
+        self.modify_vid(vid, |context| {
+            context.provenance = Some(provenance);
+
+            Ok(())
+        })
+    }
+
+    /// The VIDs that introduced the VID identified by `vid`, see
+    /// [Provenance::introduced_by]: the one that introduced it first, then the one that
+    /// introduced that one, and so on, up to a VID that was not introduced by another
+    pub fn introducers(&self, vid: &str) -> Result<Vec<String>, Error> {
+        let mut introducers = Vec::new();
+        let mut provenance = self.get_vid(vid)?.provenance;
+
+        while let Some(introducer) = provenance
+            .as_ref()
+            .and_then(Provenance::introduced_by)
+            .map(String::from)
+        {
+            // a VID may be re-introduced by one it introduced itself
+            if introducer == vid || introducers.contains(&introducer) {
+                break;
+            }
+
+            provenance = self
+                .vids
+                .get(&introducer)
+                .and_then(|context| context.provenance.clone());
+            introducers.push(introducer);
+        }
+
+        Ok(introducers)
+    }
+
+    /// All VIDs in the database that were introduced by `vid`, directly or through VIDs
+    /// it introduced, e.g. to review them when `vid` turns out to be untrustworthy; VIDs
+    /// that were introduced directly come first
+    pub fn introduced_by(&self, vid: &str) -> Vec<String> {
+        let mut introduced = BTreeMap::<String, BTreeSet<String>>::new();
+
+        for context in self.vids.iter() {
+            if let Some(introducer) = context
+                .provenance
+                .as_ref()
+                .and_then(Provenance::introduced_by)
+            {
+                introduced
+                    .entry(introducer.to_string())
+                    .or_default()
+                    .insert(context.key().clone());
+            }
+        }
+
+        let mut found = Vec::new();
+        let mut queue = VecDeque::from([vid.to_string()]);
+
+        // every VID is introduced by at most one other, so each is found only once
+        while let Some(introducer) = queue.pop_front() {
+            for introduced_vid in introduced.remove(&introducer).unwrap_or_default() {
+                if introduced_vid != vid {
+                    found.push(introduced_vid.clone());
+                    queue.push_back(introduced_vid);
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Get the status of our relationship with the VID identified by `vid`
+    pub fn get_relation_status(&self, vid: &str) -> Result<RelationshipStatus, Error> {
+        Ok(self.get_vid(vid)?.relation_status)
+    }
+
+    /// Get how the relationship with the VID identified by `vid` was used
+    pub fn get_vid_stats(&self, vid: &str) -> Result<VidStats, Error> {
+        Ok(self.get_vid(vid)?.stats)
+    }
+
+    /// The VIDs in the database as nodes, and their relationships, parents and routes as
+    /// edges, e.g. to render them with [RelationshipGraph::to_dot]
+    pub fn relationship_graph(&self) -> RelationshipGraph {
+        let mut graph = RelationshipGraph::default();
+
+        for context in self.vids.iter() {
+            let vid = context.key();
+
+            graph.nodes.push(Node {
+                vid: vid.clone(),
+                private: context.private.is_some(),
+                nested: context.parent_vid.is_some(),
+                relationship: (&context.relation_status).into(),
+            });
+
+            if let Some(relation_vid) = &context.relation_vid {
+                if !matches!(context.relation_status, RelationshipStatus::Unrelated) {
+                    graph.edges.push(Edge {
+                        from: relation_vid.clone(),
+                        to: vid.clone(),
+                        kind: EdgeKind::Relationship {
+                            state: (&context.relation_status).into(),
+                        },
+                    });
+                }
+            }
+
+            if let Some(parent_vid) = &context.parent_vid {
+                graph.edges.push(Edge {
+                    from: vid.clone(),
+                    to: parent_vid.clone(),
+                    kind: EdgeKind::Parent,
+                });
+            }
+
+            for (hop, intermediary) in context.get_route().unwrap_or_default().iter().enumerate() {
+                graph.edges.push(Edge {
+                    from: vid.clone(),
+                    to: intermediary.clone(),
+                    kind: EdgeKind::Route { hop },
+                });
+            }
+        }
+
+        graph.nodes.sort_by(|a, b| a.vid.cmp(&b.vid));
+        graph
+            .edges
+            .sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+
+        graph
+    }
+
+    /// Summarize how the relationship with every VID in the database was used, by VID
+    pub fn wallet_summary(&self) -> BTreeMap<String, VidStats> {
+        self.vids
+            .iter()
+            .map(|context| (context.key().clone(), context.stats.clone()))
+            .collect()
+    }
+
+    /// Get the hash function for digests and thread ids of messages exchanged with `vid`
+    pub fn get_digest_algorithm(&self, vid: &str) -> Result<DigestAlgorithm, Error> {
+        match self.vids.get(vid) {
+            Some(context) => Ok(context.digest_algorithm.unwrap_or_default()),
+            None => Err(Error::UnverifiedVid(vid.to_string())),
+        }
+    }
+
+    /// Choose the hash function for digests and thread ids of messages sent to `vid`;
+    /// `None` restores [DigestAlgorithm::default]
+    ///
+    /// Both ends of a relationship should agree on the algorithm before the relationship
+    /// is formed, as the thread id is computed by each end. Afterwards, the algorithm of
+    /// the digests in received messages is recorded, so the relationship follows its peer.
+    pub fn set_digest_algorithm_for_vid(
+        &self,
+        vid: &str,
+        algorithm: Option<DigestAlgorithm>,
+    ) -> Result<(), Error> {
+        self.modify_vid(vid, |context| {
+            context.digest_algorithm = algorithm;
+
+            Ok(())
+        })
+    }
+
+    /// Get the cryptography that messages sent to `vid` are sealed with
+    pub fn get_cipher_suite(&self, vid: &str) -> Result<CipherSuite, Error> {
+        match self.vids.get(vid) {
+            Some(context) => Ok(context.cipher_suite.unwrap_or_default()),
             None => Err(Error::UnverifiedVid(vid.to_string())),
         }
     }
 
+    /// Choose the cryptography to seal messages sent to `vid` with; `None` restores
+    /// [CipherSuite::default]
+    ///
+    /// Messages in any suite are opened regardless of this choice. The suite of messages
+    /// received from `vid` is recorded, so replies use the suite its peer sent with.
+    pub fn set_cipher_suite_for_vid(
+        &self,
+        vid: &str,
+        cipher_suite: Option<CipherSuite>,
+    ) -> Result<(), Error> {
+        self.modify_vid(vid, |context| {
+            context.cipher_suite = cipher_suite;
+
+            Ok(())
+        })
+    }
+
+    /// Send the messages for `vid` to `transport` instead of the endpoint of the VID, e.g.
+    /// a local tunnel to its endpoint; `None` restores the endpoint of the VID. The
+    /// override is kept with the VID when the store is exported.
+    pub fn set_transport_override(&self, vid: &str, transport: Option<Url>) -> Result<(), Error> {
+        self.modify_vid(vid, |context| {
+            context.transport_override = transport;
+
+            Ok(())
+        })
+    }
+
+    /// Where the messages for `vid` are sent, see [Store::set_transport_override]
+    fn endpoint_for(&self, vid: &dyn VerifiedVid) -> Url {
+        let transport_vid = self
+            .transport_vid(vid.identifier())
+            .unwrap_or_else(|_| vid.identifier().to_string());
+
+        match self.vids.get(&transport_vid) {
+            Some(context) => context.endpoint().clone(),
+            None => vid.endpoint().clone(),
+        }
+    }
+
+    /// The VID whose endpoints the messages for `vid` arrive at: for a nested VID with a
+    /// `tsp://` placeholder endpoint, the nearest parent with an actual endpoint, otherwise
+    /// `vid` itself
+    pub(crate) fn transport_vid(&self, vid: &str) -> Result<String, Error> {
+        let is_placeholder = |vid: &str| {
+            self.vids
+                .get(vid)
+                .is_some_and(|context| context.endpoint().scheme() == PLACEHOLDER_SCHEME)
+        };
+
+        if !is_placeholder(vid) {
+            return Ok(vid.to_string());
+        }
+
+        Ok(self
+            .parent_chain(vid)?
+            .into_iter()
+            .find(|parent| !is_placeholder(parent))
+            .unwrap_or_else(|| vid.to_string()))
+    }
+
+    /// Switch to the digest algorithm `vid` used in a received message
+    fn record_digest_algorithm(&self, vid: &str, algorithm: DigestAlgorithm) {
+        if let Some(mut context) = self.vid_mut(vid) {
+            if context.digest_algorithm.unwrap_or_default() != algorithm {
+                context.digest_algorithm = Some(algorithm);
+            }
+        }
+    }
+
+    /// Switch to the cipher suite `vid` used in a received message, if it used one
+    fn record_cipher_suite(&self, vid: &str, crypto_type: &CryptoType) {
+        let Some(cipher_suite) = CipherSuite::of(crypto_type) else {
+            return;
+        };
+
+        if let Some(mut context) = self.vid_mut(vid) {
+            if context.cipher_suite.unwrap_or_default() != cipher_suite {
+                context.cipher_suite = Some(cipher_suite);
+            }
+        }
+    }
+
+    /// Count a message sealed for `vid`
+    fn record_sent(&self, vid: &str) {
+        if let Some(mut context) = self.vid_mut(vid) {
+            context.stats.messages_sent += 1;
+        }
+    }
+
+    /// Count a message opened from `vid`
+    fn record_received(&self, vid: &str) {
+        if let Some(mut context) = self.vid_mut(vid) {
+            context.stats.messages_received += 1;
+            context.stats.last_received = Some(now());
+        }
+    }
+
+    /// Remember why a message to the VIDs reached at `endpoint` could not be sent
+    #[cfg(feature = "async")]
+    pub(crate) fn record_send_error(&self, endpoint: &Url, error: &Error) {
+        for mut context in self.vids.iter_mut() {
+            if context.endpoint() == endpoint
+                || context.vid.alternative_endpoints().contains(endpoint)
+            {
+                context.stats.last_error = Some(error.to_string());
+                self.changed.insert(context.key().clone());
+            }
+        }
+    }
+
+    /// List the VIDs whose metadata is a JSON object with `key` set to `value`
+    pub fn find_vids_by_metadata(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<Vec<String>, Error> {
+        Ok(self
+            .vids
+            .iter()
+            .filter(|context| {
+                context
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.get(key))
+                    .is_some_and(|found| found == value)
+            })
+            .map(|context| context.key().clone())
+            .collect())
+    }
+
     /// Check whether the [PrivateVid] identified by `vid` exists inthe database
     pub fn has_private_vid(&self, vid: &str) -> Result<bool, Error> {
         Ok(self.get_private_vid(vid).is_ok())
     }
 
     /// Retrieve the [PrivateVid] identified by `vid` from the database, if it exists.
+    /// One-shot VIDs that expired are forgotten instead
     pub(crate) fn get_private_vid(&self, vid: &str) -> Result<Arc<dyn PrivateVid>, Error> {
         match self.get_vid(vid)?.private {
+            Some(private) if private.expires_at().is_some_and(|expiry| expiry <= now()) => {
+                self.forget_vid(vid)?;
+
+                Err(Error::MissingPrivateVid(vid.to_string()))
+            }
             Some(private) => Ok(private),
             None => Err(Error::MissingPrivateVid(vid.to_string())),
         }
     }
 
+    /// Forget `vid` if it is a one-shot VID, now that a message sent to it was opened
+    fn use_vid(&self, vid: &str) -> Result<(), Error> {
+        if self
+            .vids
+            .get(vid)
+            .is_some_and(|context| context.expires_at().is_some())
+        {
+            self.forget_vid(vid)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget the one-shot VIDs that expired, see [OwnedVid::new_ephemeral], e.g.
+    /// periodically in a long-lived store; returns the forgotten VIDs. Such a VID is also
+    /// forgotten when it is used after its expiry
+    pub fn forget_expired_vids(&self) -> Result<Vec<String>, Error> {
+        let now = now();
+        let expired = self
+            .vids
+            .iter()
+            .filter(|context| context.expires_at().is_some_and(|expiry| expiry <= now))
+            .map(|context| context.key().clone())
+            .collect::<Vec<_>>();
+
+        for vid in &expired {
+            self.forget_vid(vid)?;
+        }
+
+        Ok(expired)
+    }
+
     /// Retrieve the [Vid] identified by `vid` from the database, if it exists.
     pub(crate) fn get_verified_vid(&self, vid: &str) -> Result<Arc<dyn VerifiedVid>, Error> {
         Ok(self.get_vid(vid)?.vid)
     }
 
+    /// Encode the VID identified by `vid` with [Vid::to_compact_string](crate::Vid::to_compact_string)
+    pub fn compact_vid(&self, vid: &str) -> Result<String, Error> {
+        let vid = self.get_verified_vid(vid)?;
+
+        Ok(crate::vid::compact::encode_compact(vid.as_ref()))
+    }
+
     /// Retrieve the [VidContext] identified by `vid` from the database, if it exists.
     pub(super) fn get_vid(&self, vid: &str) -> Result<VidContext, Error> {
-        match self.vids.read()?.get(vid) {
+        match self.vids.get(vid) {
             Some(resolved) => Ok(resolved.clone()),
             None => Err(Error::UnverifiedVid(vid.to_string())),
         }
@@ -315,123 +1559,531 @@ impl Store {
         )
     }
 
-    /// Seal a TSP message.
-    pub(crate) fn seal_message_payload(
+    /// Seal a TSP message with per-message `options`, e.g. to select whether the sender
+    /// VID is encrypted along with the payload (ESSR) regardless of the `essr` feature
+    pub fn seal_message_with_options(
         &self,
         sender: &str,
         receiver: &str,
         nonconfidential_data: Option<&[u8]>,
-        payload: Payload<&[u8]>,
+        message: &[u8],
+        options: SealOptions,
     ) -> Result<(url::Url, Vec<u8>), Error> {
-        self.seal_message_payload_and_hash(sender, receiver, nonconfidential_data, payload, None)
+        let mut tsp_message = Vec::new();
+        let url = self.seal_archived(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::Content(message),
+            None,
+            |payload, digest| {
+                self.seal_layers(
+                    sender,
+                    receiver,
+                    nonconfidential_data,
+                    payload,
+                    &[],
+                    digest,
+                    &mut tsp_message,
+                    options,
+                )
+            },
+        )?;
+        self.audit_sent(sender, receiver, &tsp_message);
+
+        Ok((url, tsp_message))
     }
 
-    /// Seal a TSP message and return the digest of the payload
-    pub(crate) fn seal_message_payload_and_hash(
+    /// Seal a TSP message that replies to an earlier message, identified by the digest
+    /// returned by [seal_and_hash](crate::crypto::seal_and_hash) when it was sealed
+    pub fn seal_reply(
         &self,
         sender: &str,
         receiver: &str,
         nonconfidential_data: Option<&[u8]>,
-        payload: Payload<&[u8]>,
-        digest: Option<&mut Digest>,
+        in_reply_to: &Digest,
+        message: &[u8],
     ) -> Result<(url::Url, Vec<u8>), Error> {
-        let sender = self.get_private_vid(sender)?;
-        let receiver_context = self.get_vid(receiver)?;
+        self.seal_message_payload(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::Reply {
+                message,
+                in_reply_to: *in_reply_to,
+            },
+        )
+    }
 
-        // send routed mode
-        if let Some(intermediaries) = receiver_context.get_route() {
-            let first_hop = self.get_vid(&intermediaries[0])?;
+    /// Seal an acknowledgement telling `receiver` that the message with `digest` arrived,
+    /// see the `digest` of [ReceivedTspMessage::GenericMessage]
+    pub fn make_ack(
+        &self,
+        sender: &str,
+        receiver: &str,
+        digest: &Digest,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::Acknowledgement { digest: *digest },
+        )
+    }
 
-            let (sender, inner_message) = match first_hop.get_relation_vid() {
-                Some(first_sender) => {
-                    let inner_sender = receiver_context
-                        .get_relation_vid()
-                        .unwrap_or(sender.identifier());
-                    let inner_sender = self.get_private_vid(inner_sender)?;
+    /// Seal a TSP message for a receiver with a route, attaching a nonconfidential
+    /// annotation for each intermediary, e.g. priority or TTL hints.
+    ///
+    /// `annotations[i]` is only visible to the `i`-th intermediary of the route and the
+    /// intermediaries before it; there may be fewer annotations than intermediaries
+    pub fn seal_message_with_annotations(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        annotations: &[&[u8]],
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        if self.get_vid(receiver)?.get_route().is_none() {
+            return Err(Error::InvalidRoute(format!("no route to {receiver}")));
+        }
 
-                    let tsp_message: Vec<u8> = crate::crypto::seal_and_hash(
-                        &*inner_sender,
-                        &*receiver_context.vid,
-                        nonconfidential_data,
-                        payload,
-                        digest,
-                    )?;
+        let mut tsp_message = Vec::new();
+        let url = self.seal_archived(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::Content(message),
+            None,
+            |payload, digest| {
+                self.seal_layers(
+                    sender,
+                    receiver,
+                    nonconfidential_data,
+                    payload,
+                    annotations,
+                    digest,
+                    &mut tsp_message,
+                    SealOptions::default(),
+                )
+            },
+        )?;
+        self.audit_sent(sender, receiver, &tsp_message);
 
-                    let first_sender = self.get_private_vid(first_sender)?;
+        Ok((url, tsp_message))
+    }
 
-                    (first_sender, tsp_message)
-                }
-                None => return Err(VidError::ResolveVid("missing sender VID for first hop").into()),
-            };
+    /// Seal a TSP message for `receiver`, a VID that was resolved for a single exchange and
+    /// is not added to this store. The message is always sealed in direct mode, since
+    /// there is no route, parent or session configured for the receiver
+    pub fn seal_message_to(
+        &self,
+        sender: &str,
+        receiver: &dyn VerifiedVid,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let payload = Payload::Content(message);
+        self.check_payload(&payload)?;
+        self.check_identity_exposure(sender, self.settings().hide_identities)?;
 
-            let hops = intermediaries[1..]
-                .iter()
-                .map(|x| x.as_ref())
+        let sender_vid = self.get_private_vid(sender)?;
+        let tsp_message = self.seal_archived(
+            sender,
+            receiver.identifier(),
+            nonconfidential_data,
+            payload,
+            None,
+            |payload, digest| {
+                Ok(crate::crypto::seal_and_hash(
+                    &*sender_vid,
+                    receiver,
+                    nonconfidential_data,
+                    payload,
+                    digest,
+                )?)
+            },
+        )?;
+        self.audit_sent(sender, receiver.identifier(), &tsp_message);
+
+        Ok((receiver.endpoint().clone(), tsp_message))
+    }
+
+    /// Seal a TSP message consisting of an ordered list of (content type, data) segments,
+    /// e.g. a JSON control object plus a binary attachment
+    pub fn seal_message_multipart(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        segments: &[(&str, &[u8])],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let segments = segments
+            .iter()
+            .map(|(content_type, data)| (content_type.as_bytes(), *data))
+            .collect();
+
+        self.seal_message_payload(
+            sender,
+            receiver,
+            nonconfidential_data,
+            Payload::Multipart(segments),
+        )
+    }
+
+    /// Seal a TSP message.
+    pub(crate) fn seal_message_payload(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        payload: Payload<&[u8]>,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        self.seal_message_payload_and_hash(sender, receiver, nonconfidential_data, payload, None)
+    }
+
+    /// Seal a TSP message and return the digest of the payload
+    pub(crate) fn seal_message_payload_and_hash(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        payload: Payload<&[u8]>,
+        digest: Option<&mut Digest>,
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        #[cfg(feature = "async")]
+        let _span = tracing::info_span!(
+            "seal",
+            sender = %telemetry::fingerprint(sender),
+            receiver = %telemetry::fingerprint(receiver),
+        )
+        .entered();
+
+        let mut tsp_message = Vec::new();
+        let url = telemetry::timed(telemetry::SEAL_DURATION, || {
+            self.seal_archived(
+                sender,
+                receiver,
+                nonconfidential_data,
+                payload,
+                digest,
+                |payload, digest| {
+                    self.seal_layers(
+                        sender,
+                        receiver,
+                        nonconfidential_data,
+                        payload,
+                        &[],
+                        digest,
+                        &mut tsp_message,
+                        SealOptions::default(),
+                    )
+                },
+            )
+        })?;
+        self.audit_sent(sender, receiver, &tsp_message);
+
+        Ok((url, tsp_message))
+    }
+
+    /// Seal a TSP message and append it to `out`, so a caller can reuse the same
+    /// buffer for many messages. On error, `out` is left unchanged.
+    pub fn seal_message_into(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<url::Url, Error> {
+        #[cfg(feature = "async")]
+        let _span = tracing::info_span!(
+            "seal",
+            sender = %telemetry::fingerprint(sender),
+            receiver = %telemetry::fingerprint(receiver),
+        )
+        .entered();
+
+        let start = out.len();
+        let result = telemetry::timed(telemetry::SEAL_DURATION, || {
+            self.seal_archived(
+                sender,
+                receiver,
+                nonconfidential_data,
+                Payload::Content(message),
+                None,
+                |payload, digest| {
+                    self.seal_layers(
+                        sender,
+                        receiver,
+                        nonconfidential_data,
+                        payload,
+                        &[],
+                        digest,
+                        out,
+                        SealOptions::default(),
+                    )
+                },
+            )
+        });
+
+        match result {
+            Ok(_) => self.audit_sent(sender, receiver, &out[start..]),
+            Err(_) => out.truncate(start),
+        }
+
+        result
+    }
+
+    /// Seal a TSP message into `out`, wrapping it in a routed or nested message if needed
+    #[allow(clippy::too_many_arguments)]
+    fn seal_layers(
+        &self,
+        sender: &str,
+        receiver: &str,
+        nonconfidential_data: Option<&[u8]>,
+        payload: Payload<&[u8]>,
+        annotations: &[&[u8]],
+        digest: Option<&mut Digest>,
+        out: &mut Vec<u8>,
+        options: SealOptions,
+    ) -> Result<url::Url, Error> {
+        self.check_payload(&payload)?;
+
+        let options = if options.hide_identity || self.settings().hide_identities {
+            SealOptions {
+                essr: true,
+                hide_identity: true,
+                ..options
+            }
+        } else {
+            options
+        };
+
+        let sender = self.get_private_vid(sender)?;
+        let receiver_context = self.get_vid(receiver)?;
+
+        let route_len = receiver_context.get_route().map_or(0, |route| route.len());
+        if annotations.len() > route_len {
+            return Err(Error::InvalidRoute(format!(
+                "{} annotations for a route of {route_len} hops",
+                annotations.len()
+            )));
+        }
+
+        // the wrapping layers below choose where the nonconfidential data goes, the
+        // messages they wrap are sealed with the default placement
+        let placement = options.nonconfidential_placement;
+        let wrapper_options = SealOptions {
+            nonconfidential_placement: None,
+            ..options
+        };
+
+        // send routed mode
+        if let Some(intermediaries) = receiver_context.get_route() {
+            self.check_hops(intermediaries.len())?;
+
+            let placement = placement.unwrap_or(NonConfidentialPlacement::Inner);
+
+            let first_hop = self.get_vid(&intermediaries[0])?;
+
+            let (sender, inner_message) = match first_hop.get_relation_vid() {
+                Some(first_sender) => {
+                    let inner_sender = receiver_context
+                        .get_relation_vid()
+                        .unwrap_or(sender.identifier());
+                    let inner_sender = self.get_private_vid(inner_sender)?;
+
+                    // the intermediaries see the envelope of the inner message
+                    self.check_identity_exposure(inner_sender.identifier(), options.hide_identity)?;
+
+                    let tsp_message: Vec<u8> = crate::crypto::seal_and_hash_with_options(
+                        &*inner_sender,
+                        &*receiver_context.vid,
+                        nonconfidential_data.filter(|_| placement.inner()),
+                        payload,
+                        digest,
+                        receiver_context.seal_options(options),
+                    )?;
+
+                    let first_sender = self.get_private_vid(first_sender)?;
+
+                    (first_sender, tsp_message)
+                }
+                None => return Err(VidError::ResolveVid("missing sender VID for first hop").into()),
+            };
+
+            let hops = intermediaries[1..]
+                .iter()
+                .map(|x| x.as_ref())
                 .collect::<Vec<_>>();
 
-            return self.seal_message_payload(
+            self.record_sent(receiver);
+
+            return self.seal_layers(
                 sender.identifier(),
                 first_hop.vid.identifier(),
+                nonconfidential_data.filter(|_| placement.outer()),
+                Payload::RoutedMessage(hops, annotations.to_vec(), &inner_message),
+                &[],
                 None,
-                Payload::RoutedMessage(hops, &inner_message),
+                out,
+                wrapper_options,
             );
         }
 
         // send nested mode
-        if let Some(parent_receiver) = receiver_context.get_parent_vid() {
+        if receiver_context.get_parent_vid().is_some() {
             let Some(inner_sender) = receiver_context.get_relation_vid() else {
                 return Err(VidError::ResolveVid("missing sender VID for receiver").into());
             };
 
-            let sender_context = self.get_vid(inner_sender)?;
+            let receiver_chain = self.parent_chain(receiver)?;
+            let max_depth = self.config().max_nested_depth;
+            if receiver_chain.len() > max_depth {
+                return Err(Error::NestingTooDeep(max_depth));
+            }
 
-            let Some(parent_sender) = sender_context.get_parent_vid() else {
+            let sender_chain = self.parent_chain(inner_sender)?;
+            if sender_chain.is_empty() {
                 return Err(VidError::ResolveVid("missing parent for inner VID").into());
-            };
+            }
+
+            if sender_chain.len() != receiver_chain.len() {
+                return Err(
+                    VidError::ResolveVid("sender and receiver nested at different depths").into(),
+                );
+            }
 
-            if parent_sender != sender.identifier() && inner_sender != sender.identifier() {
+            if inner_sender != sender.identifier()
+                && !sender_chain.iter().any(|vid| vid == sender.identifier())
+            {
                 return Err(VidError::ResolveVid("incorrect sender VID").into());
             }
 
             let inner_sender = self.get_private_vid(inner_sender)?;
 
-            let inner_message = if let Payload::Content(_) = payload {
+            let placement = placement.unwrap_or(NonConfidentialPlacement::Outer);
+            let inner_data = nonconfidential_data.filter(|_| placement.inner());
+
+            // a signed message has no room for nonconfidential data, so content that
+            // comes with it is sealed instead
+            let mut inner_message = if let (Payload::Content(_), None) = (&payload, inner_data) {
                 crate::crypto::sign(
                     &*inner_sender,
                     Some(&*receiver_context.vid),
                     payload.as_bytes(),
                 )?
             } else {
-                crate::crypto::seal_and_hash(
+                crate::crypto::seal_and_hash_with_options(
                     &*inner_sender,
                     &*receiver_context.vid,
-                    None,
+                    inner_data,
                     payload,
                     digest,
+                    receiver_context.seal_options(options),
                 )?
             };
 
-            let parent_sender = self.get_private_vid(parent_sender)?;
-            let parent_receiver = self.get_verified_vid(parent_receiver)?;
+            // wrap the message in every level of the chains except the outermost one
+            let outermost = sender_chain.len() - 1;
+            for (level_sender, level_receiver) in sender_chain[..outermost]
+                .iter()
+                .zip(&receiver_chain[..outermost])
+            {
+                let level_sender = self.get_private_vid(level_sender)?;
+                let level_receiver = self.get_verified_vid(level_receiver)?;
+
+                inner_message = crate::crypto::seal_and_hash_with_options(
+                    &*level_sender,
+                    &*level_receiver,
+                    None,
+                    Payload::NestedMessage(&inner_message),
+                    None,
+                    wrapper_options,
+                )?;
+            }
+
+            let parent_sender = self.get_private_vid(&sender_chain[outermost])?;
+            let parent_receiver = self.get_verified_vid(&receiver_chain[outermost])?;
 
-            return self.seal_message_payload(
+            self.record_sent(receiver);
+
+            return self.seal_layers(
                 parent_sender.identifier(),
                 parent_receiver.identifier(),
-                nonconfidential_data,
+                nonconfidential_data.filter(|_| placement.outer()),
                 Payload::NestedMessage(&inner_message),
+                &[],
+                None,
+                out,
+                wrapper_options,
             );
         }
 
-        // send direct mode
-        let tsp_message = crate::crypto::seal_and_hash(
-            &*sender,
-            &*receiver_context.vid,
-            nonconfidential_data,
-            payload,
-            digest,
-        )?;
+        // send direct mode, with the session key for the receiver if there is one
+        self.check_identity_exposure(sender.identifier(), options.hide_identity)?;
+
+        match self.next_session_key(sender.identifier(), receiver, &payload) {
+            Some(key) => crate::crypto::session::seal_into(
+                &*sender,
+                &*receiver_context.vid,
+                nonconfidential_data,
+                payload,
+                digest,
+                out,
+                receiver_context.seal_options(options),
+                key,
+            )?,
+            None => crate::crypto::seal_and_hash_into_with_options(
+                &*sender,
+                &*receiver_context.vid,
+                nonconfidential_data,
+                payload,
+                digest,
+                out,
+                receiver_context.seal_options(options),
+            )?,
+        }
+
+        self.record_sent(receiver);
+
+        Ok(self.endpoint_for(&*receiver_context.vid))
+    }
+
+    /// Check a payload against the configured size and route limits
+    fn check_payload<Bytes: AsRef<[u8]>, MaybeMutBytes: AsRef<[u8]>>(
+        &self,
+        payload: &Payload<Bytes, MaybeMutBytes>,
+    ) -> Result<(), Error> {
+        let size = match payload {
+            Payload::Multipart(segments) => {
+                segments.iter().map(|(_, data)| data.as_ref().len()).sum()
+            }
+            _ => payload.as_bytes().len(),
+        };
+        let max_size = self.config().max_payload_size;
+        if size > max_size {
+            return Err(Error::PayloadTooLarge(size, max_size));
+        }
+
+        match payload {
+            Payload::RoutedMessage(hops, ..) => self.check_hops(hops.len()),
+            Payload::RequestRelationship {
+                route: Some(route), ..
+            } => self.check_hops(route.len()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the length of a route against the configured maximum number of hops
+    fn check_hops(&self, hops: usize) -> Result<(), Error> {
+        let max_hops = self.config().max_hops;
+        if hops > max_hops {
+            return Err(Error::TooManyHops(hops, max_hops));
+        }
 
-        Ok((receiver_context.vid.endpoint().clone(), tsp_message))
+        Ok(())
     }
 
     /// Sign a unencrypted message, without a specified recipient
@@ -445,12 +2097,39 @@ impl Store {
         sender: &str,
         payload: Payload<&[u8]>,
     ) -> Result<Vec<u8>, Error> {
+        self.check_identity_exposure(sender, self.settings().hide_identities)?;
+
         let sender = self.get_private_vid(sender)?;
         let message = crate::crypto::sign(&*sender, None, payload.as_bytes())?;
 
         Ok(message)
     }
 
+    /// Sign an unencrypted message for `receiver`, returning the endpoint of the receiver
+    /// and the signed message; only the integrity and origin of the message are protected
+    pub fn sign_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        message: &[u8],
+    ) -> Result<(url::Url, Vec<u8>), Error> {
+        let max_size = self.config().max_payload_size;
+        if message.len() > max_size {
+            return Err(Error::PayloadTooLarge(message.len(), max_size));
+        }
+
+        self.check_identity_exposure(sender, self.settings().hide_identities)?;
+
+        let sender = self.get_private_vid(sender)?;
+        let receiver_vid = self.get_verified_vid(receiver)?;
+        let message = crate::crypto::sign(&*sender, Some(&*receiver_vid), message)?;
+
+        self.record_sent(receiver);
+        self.audit_sent(sender.identifier(), receiver, &message);
+
+        Ok((self.endpoint_for(&*receiver_vid), message))
+    }
+
     /// Resolve a route, extract the next hop and verify the route
     fn resolve_route<'a>(&'a self, hop_list: &'a [&str]) -> Result<(String, Vec<&'a [u8]>), Error> {
         let Some(next_hop) = hop_list.first() else {
@@ -482,11 +2161,12 @@ impl Store {
 
         let (_, payload, _, _) = crate::crypto::open(&*receiver, &*sender, message)?;
 
-        let (next_hop, path, inner_message) = match payload {
-            Payload::RoutedMessage(hops, inner_message) => {
+        let (next_hop, path, annotations, inner_message) = match payload {
+            Payload::RoutedMessage(hops, annotations, inner_message) => {
                 let next_hop = std::str::from_utf8(hops[0])?;
+                let annotations = annotations.get(1..).unwrap_or_default().to_vec();
 
-                (next_hop, hops[1..].into(), inner_message)
+                (next_hop, hops[1..].into(), annotations, inner_message)
             }
             _ => {
                 return Err(Error::InvalidRoute(format!(
@@ -496,7 +2176,7 @@ impl Store {
             }
         };
 
-        self.forward_routed_message(next_hop, path, inner_message)
+        self.forward_annotated_routed_message(next_hop, path, annotations, inner_message)
     }
 
     /// Pass along a in-transit routed TSP `opaque_message` that is not meant for us, given earlier resolved VIDs.
@@ -507,7 +2187,23 @@ impl Store {
         route: Vec<&[u8]>,
         opaque_payload: &[u8],
     ) -> Result<(Url, Vec<u8>), Error> {
-        if route.is_empty() {
+        self.forward_annotated_routed_message(next_hop, route, Vec::new(), opaque_payload)
+    }
+
+    /// Like [`Store::forward_routed_message`], passing along the `annotations` for the
+    /// remaining hops, as received in [`ReceivedTspMessage::ForwardRequest`]
+    pub fn forward_annotated_routed_message(
+        &self,
+        next_hop: &str,
+        route: Vec<&[u8]>,
+        annotations: Vec<&[u8]>,
+        opaque_payload: &[u8],
+    ) -> Result<(Url, Vec<u8>), Error> {
+        #[cfg(feature = "async")]
+        let _span =
+            tracing::info_span!("forward", next_hop = %telemetry::fingerprint(next_hop)).entered();
+
+        let forwarded = if route.is_empty() {
             // we are the final delivery point, we should be the 'next_hop'
             let sender = self.get_vid(next_hop)?;
 
@@ -520,6 +2216,12 @@ impl Store {
                 None => return Err(Error::MissingDropOff(sender.vid.identifier().to_string())),
             };
 
+            self.check_forward(
+                sender_private.identifier(),
+                recipient.identifier(),
+                opaque_payload.len(),
+            )?;
+
             self.seal_message_payload(
                 sender_private.identifier(),
                 recipient.identifier(),
@@ -537,13 +2239,25 @@ impl Store {
                 None => return Err(Error::InvalidNextHop(next_hop.to_string())),
             };
 
+            self.check_forward(
+                sender.identifier(),
+                next_hop_context.vid.identifier(),
+                opaque_payload.len(),
+            )?;
+
             self.seal_message_payload(
                 sender.identifier(),
                 next_hop_context.vid.identifier(),
                 None,
-                Payload::RoutedMessage(route, opaque_payload),
+                Payload::RoutedMessage(route, annotations, opaque_payload),
             )
+        };
+
+        if forwarded.is_ok() {
+            telemetry::increment_counter(telemetry::MESSAGES_FORWARDED);
         }
+
+        forwarded
     }
 
     /// Get the sender from a CESR message
@@ -556,10 +2270,184 @@ impl Store {
 
     /// Decode an encrypted `message``, which has to be addressed to one of the VIDs in `receivers`, and has to have
     /// `verified_vids` as one of the senders.
+    ///
+    /// The data is borrowed from `message`, except for compressed content, which is
+    /// decompressed up to the maximum payload size.
     pub fn open_message<'a>(
         &self,
         message: &'a mut [u8],
+    ) -> Result<ReceivedTspMessage<Cow<'a, [u8]>>, Error> {
+        #[cfg(feature = "async")]
+        let _span = tracing::info_span!("open", len = message.len()).entered();
+
+        // the message is opened in place, so it is described for the audit log beforehand
+        let event = self.audit_log().and_then(|_| received_event(message));
+        let receiver = self
+            .message_archive()
+            .and_then(|_| crate::cesr::get_sender_receiver(message).ok())
+            .and_then(|(_, receiver)| receiver)
+            .and_then(|receiver| std::str::from_utf8(receiver).ok())
+            .map(String::from);
+
+        let received = telemetry::timed(telemetry::OPEN_DURATION, || {
+            self.open_message_at_depth(message, 0)
+        })?;
+        self.archive_received(receiver, &received)?;
+
+        // a pending message is recorded once its sender is resolved and it is opened again
+        if let Some(event) = event {
+            #[cfg(feature = "async")]
+            if matches!(received, ReceivedTspMessage::PendingMessage { .. }) {
+                return Ok(received.map(Cow::Borrowed));
+            }
+
+            self.audit(|| event);
+        }
+
+        self.decompress_content(received)
+    }
+
+    /// Decode a generic message from `sender`, a VID that was resolved for a single exchange
+    /// and is not added to this store; see [Store::seal_message_to]. Other messages change
+    /// a relationship, so their sender has to be in the store to open them with
+    /// [Store::open_message]
+    pub fn open_message_from<'a>(
+        &self,
+        sender: &dyn VerifiedVid,
+        message: &'a mut [u8],
+    ) -> Result<ReceivedTspMessage<Cow<'a, [u8]>>, Error> {
+        let (expected_sender, intended_receiver) = match crate::cesr::probe(message)? {
+            EnvelopeType::EncryptedMessage {
+                sender: envelope_sender,
+                receiver,
+                ..
+            } => (
+                envelope_sender == sender.identifier().as_bytes(),
+                std::str::from_utf8(receiver)?.to_string(),
+            ),
+            EnvelopeType::SignedMessage { .. } => return Err(CryptoError::MissingCiphertext.into()),
+        };
+
+        if !expected_sender {
+            return Err(CryptoError::UnexpectedSender.into());
+        }
+
+        let Ok(intended_receiver) = self.get_private_vid(&intended_receiver) else {
+            return Err(CryptoError::UnexpectedRecipient.into());
+        };
+
+        let mut digest = Default::default();
+        let mut digest_algorithm = None;
+        let (nonconfidential_data, payload, crypto_type, signature_type) =
+            crate::crypto::open_and_hash_with_algorithm(
+                &*intended_receiver,
+                sender,
+                message,
+                Some(&mut digest),
+                &mut digest_algorithm,
+            )?;
+
+        self.check_payload(&payload)?;
+        self.use_vid(intended_receiver.identifier())?;
+
+        let (message, segments, in_reply_to, compressed) = match payload {
+            Payload::Content(message) => (message, Vec::new(), None, false),
+            Payload::Reply {
+                message,
+                in_reply_to,
+            } => (message, Vec::new(), Some(in_reply_to), false),
+            Payload::Compressed {
+                message,
+                in_reply_to,
+            } => (message, Vec::new(), in_reply_to, true),
+            Payload::Multipart(segments) => (
+                &[][..],
+                segments
+                    .into_iter()
+                    .map(|(content_type, data)| {
+                        Ok((std::str::from_utf8(content_type)?.to_string(), data))
+                    })
+                    .collect::<Result<_, Error>>()?,
+                None,
+                false,
+            ),
+            _ => {
+                return Err(Error::UnverifiedSource(
+                    sender.identifier().to_string(),
+                    #[cfg(feature = "async")]
+                    None,
+                ))
+            }
+        };
+
+        let content_type = nonconfidential_data
+            .and_then(|data| MessageHeaders::from_bytes(data).ok())
+            .and_then(|headers| headers.content_type().map(String::from));
+
+        let received = ReceivedTspMessage::GenericMessage {
+            sender: sender.identifier().to_string(),
+            nonconfidential_data,
+            message,
+            content_type,
+            segments,
+            in_reply_to,
+            digest,
+            message_type: MessageType {
+                crypto_type,
+                signature_type,
+                // the algorithm is always set after opening the message
+                digest_algorithm: digest_algorithm.unwrap_or_default(),
+                compressed,
+            },
+        };
+        self.archive_received(Some(intended_receiver.identifier().to_string()), &received)?;
+
+        self.decompress_content(received)
+    }
+
+    /// Decode a message like [Store::open_message], into a freestanding version
+    pub fn open_message_owned(&self, message: &mut [u8]) -> Result<ReceivedTspMessage, Error> {
+        Ok(self.open_message(message)?.into_owned())
+    }
+
+    /// Decompress the content of an opened message, if it is compressed; the archive keeps
+    /// the content as it was received, so this happens after archiving
+    fn decompress_content<'a>(
+        &self,
+        received: ReceivedTspMessage<&'a [u8]>,
+    ) -> Result<ReceivedTspMessage<Cow<'a, [u8]>>, Error> {
+        let mut received = received.map(Cow::Borrowed);
+
+        if let ReceivedTspMessage::GenericMessage {
+            message,
+            message_type:
+                message_type @ MessageType {
+                    compressed: true, ..
+                },
+            ..
+        } = &mut received
+        {
+            *message = Cow::Owned(crate::crypto::decompress(
+                message,
+                self.config().max_payload_size,
+            )?);
+            message_type.compressed = false;
+        }
+
+        Ok(received)
+    }
+
+    /// Decode a message that is nested `depth` levels deep inside other messages
+    fn open_message_at_depth<'a>(
+        &self,
+        message: &'a mut [u8],
+        depth: usize,
     ) -> Result<ReceivedTspMessage<&'a [u8]>, Error> {
+        let max_depth = self.config().max_nested_depth;
+        if depth > max_depth {
+            return Err(Error::NestingTooDeep(max_depth));
+        }
+
         let probed_message = crate::cesr::probe(message)?;
 
         match probed_message {
@@ -583,17 +2471,122 @@ impl Store {
                     return Err(Error::UnverifiedSource(sender));
                 };
 
-                let (nonconfidential_data, payload, crypto_type, signature_type) =
-                    crate::crypto::open(&*intended_receiver, &*sender_vid, message)?;
+                self.expire_relationship(&sender)?;
 
-                match payload {
-                    Payload::Content(message) => Ok(ReceivedTspMessage::GenericMessage {
+                let mut digest = Default::default();
+                let mut digest_algorithm = self
+                    .vids
+                    .get(&sender)
+                    .and_then(|context| context.digest_algorithm);
+                let (nonconfidential_data, payload, crypto_type, signature_type) =
+                    crate::crypto::open_with_session(
+                        &*intended_receiver,
+                        &*sender_vid,
+                        message,
+                        Some(&mut digest),
+                        &mut digest_algorithm,
+                        &mut |counter| {
+                            self.receiving_session_key(
+                                &sender,
+                                intended_receiver.identifier(),
+                                counter,
+                            )
+                        },
+                    )?;
+
+                // the algorithm is always set after opening the message
+                let digest_algorithm = digest_algorithm.unwrap_or_default();
+                self.record_digest_algorithm(&sender, digest_algorithm);
+                self.record_cipher_suite(&sender, &crypto_type);
+                self.record_received(&sender);
+                self.use_vid(intended_receiver.identifier())?;
+
+                self.check_payload(&payload)?;
+
+                let content_type = nonconfidential_data
+                    .and_then(|data| MessageHeaders::from_bytes(data).ok())
+                    .and_then(|headers| headers.content_type().map(String::from));
+
+                if matches!(
+                    payload,
+                    Payload::Content(_)
+                        | Payload::Reply { .. }
+                        | Payload::Compressed { .. }
+                        | Payload::Multipart(_)
+                ) {
+                    self.check_relationship(&sender, content_type.as_deref())?;
+                }
+
+                match payload {
+                    Payload::Content(message) => Ok(ReceivedTspMessage::GenericMessage {
+                        sender,
+                        nonconfidential_data,
+                        message,
+                        content_type,
+                        segments: Vec::new(),
+                        in_reply_to: None,
+                        digest,
+                        message_type: MessageType {
+                            crypto_type,
+                            signature_type,
+                            digest_algorithm,
+                            compressed: false,
+                        },
+                    }),
+                    Payload::Reply {
+                        message,
+                        in_reply_to,
+                    } => Ok(ReceivedTspMessage::GenericMessage {
+                        sender,
+                        nonconfidential_data,
+                        message,
+                        content_type,
+                        segments: Vec::new(),
+                        in_reply_to: Some(in_reply_to),
+                        digest,
+                        message_type: MessageType {
+                            crypto_type,
+                            signature_type,
+                            digest_algorithm,
+                            compressed: false,
+                        },
+                    }),
+                    Payload::Compressed {
+                        message,
+                        in_reply_to,
+                    } => Ok(ReceivedTspMessage::GenericMessage {
                         sender,
                         nonconfidential_data,
                         message,
+                        content_type,
+                        segments: Vec::new(),
+                        in_reply_to,
+                        digest,
+                        message_type: MessageType {
+                            crypto_type,
+                            signature_type,
+                            digest_algorithm,
+                            compressed: true,
+                        },
+                    }),
+                    Payload::Multipart(segments) => Ok(ReceivedTspMessage::GenericMessage {
+                        sender,
+                        nonconfidential_data,
+                        message: &[],
+                        content_type,
+                        segments: segments
+                            .into_iter()
+                            .map(|(content_type, data)| {
+                                Ok((std::str::from_utf8(content_type)?.to_string(), data))
+                            })
+                            .collect::<Result<_, Error>>()?,
+                        in_reply_to: None,
+                        digest,
                         message_type: MessageType {
                             crypto_type,
                             signature_type,
+                            digest_algorithm,
+                            compressed: false,
                         },
                     }),
                     Payload::NestedMessage(inner) => {
@@ -610,7 +2603,7 @@ impl Store {
                             ));
                         }
 
-                        let mut received_message = self.open_message(inner)?;
+                        let mut received_message = self.open_message_at_depth(inner, depth + 1)?;
 
                         // if inner message was not encrypted, but outer message was encrypted by the same sender,
                         // then inner message was also sufficiently encrypted
@@ -618,7 +2611,7 @@ impl Store {
                             message_type:
                                 ref mut message_type @ MessageType {
                                     crypto_type: crate::cesr::CryptoType::Plaintext,
-                                    signature_type: _,
+                                    ..
                                 },
                             sender: ref inner_sender,
                             ..
@@ -631,20 +2624,23 @@ impl Store {
 
                         Ok(received_message)
                     }
-                    Payload::RoutedMessage(hops, message) => {
+                    Payload::RoutedMessage(hops, annotations, message) => {
                         let next_hop = std::str::from_utf8(hops[0])?;
 
                         Ok(ReceivedTspMessage::ForwardRequest {
                             sender,
                             next_hop: next_hop.to_string(),
-                            route: hops[1..].iter().map(|x| x.to_vec()).collect(),
-                            opaque_payload: message.to_owned(),
+                            route: hops[1..].to_vec(),
+                            annotation: annotations.first().copied(),
+                            route_annotations: annotations.get(1..).unwrap_or_default().to_vec(),
+                            nonconfidential_data,
+                            opaque_payload: message,
                         })
                     }
                     Payload::RequestRelationship { route, thread_id } => {
                         Ok(ReceivedTspMessage::RequestRelationship {
                             sender,
-                            route: route.map(|vec| vec.iter().map(|vid| vid.to_vec()).collect()),
+                            route,
                             thread_id,
                             nested_vid: None,
                         })
@@ -657,19 +2653,87 @@ impl Store {
                             nested_vid: None,
                         })
                     }
+                    Payload::RejectRelationship { thread_id, reason } => {
+                        let Some(mut context) = self.vid_mut(&sender) else {
+                            return Err(Error::MissingVid(sender));
+                        };
+
+                        // only a relationship we requested, and that was not accepted yet, can be rejected
+                        match context.relation_status {
+                            RelationshipStatus::Unidirectional {
+                                thread_id: digest, ..
+                            } if digest == thread_id => {
+                                context.relation_status = RelationshipStatus::Unrelated;
+                                self.audit_relationship(
+                                    &sender,
+                                    RelationshipState::Requested,
+                                    RelationshipState::Unrelated,
+                                );
+                            }
+                            _ => {
+                                return Err(Error::Relationship(
+                                    "invalid attempt to reject the relationship".into(),
+                                ))
+                            }
+                        }
+
+                        let reason = std::str::from_utf8(reason)?;
+
+                        Ok(ReceivedTspMessage::RejectRelationship {
+                            sender,
+                            thread_id,
+                            reason: (!reason.is_empty()).then(|| reason.to_string()),
+                        })
+                    }
+                    Payload::RenewRelationship {
+                        thread_id,
+                        expires_at: new_expiry,
+                    } => {
+                        self.modify_vid(&sender, |context| match context.relation_status {
+                            RelationshipStatus::Bidirectional {
+                                thread_id: digest,
+                                ref mut expires_at,
+                                ..
+                            } if digest == thread_id => {
+                                *expires_at = Some(new_expiry);
+
+                                Ok(())
+                            }
+                            _ => Err(Error::Relationship(
+                                "invalid attempt to renew the relationship".into(),
+                            )),
+                        })?;
+
+                        Ok(ReceivedTspMessage::RenewRelationship {
+                            sender,
+                            thread_id,
+                            expires_at: new_expiry,
+                        })
+                    }
                     Payload::CancelRelationship { thread_id } => {
-                        if let Some(context) = self.vids.write()?.get_mut(&sender) {
+                        let mut ended = false;
+
+                        if let Some(mut context) = self.vid_mut(&sender) {
                             match context.relation_status {
                                 RelationshipStatus::Bidirectional {
                                     thread_id: digest, ..
                                 }
-                                | RelationshipStatus::Unidirectional { thread_id: digest } => {
+                                | RelationshipStatus::Unidirectional {
+                                    thread_id: digest, ..
+                                } => {
                                     if thread_id != digest {
                                         return Err(Error::Relationship(
                                             "invalid attempt to end the relationship".into(),
                                         ));
                                     }
+                                    let from = RelationshipState::from(&context.relation_status);
                                     context.relation_status = RelationshipStatus::Unrelated;
+                                    self.audit_relationship(
+                                        &sender,
+                                        from,
+                                        RelationshipState::Unrelated,
+                                    );
+                                    ended = true;
                                 }
                                 RelationshipStatus::_Controlled => {
                                     return Err(Error::Relationship(
@@ -680,7 +2744,66 @@ impl Store {
                             }
                         }
 
-                        Ok(ReceivedTspMessage::CancelRelationship { sender })
+                        if ended {
+                            self.cleanup_relationship(&sender, false)?;
+                        }
+
+                        Ok(ReceivedTspMessage::CancelRelationship {
+                            sender,
+                            nested_vid: None,
+                        })
+                    }
+                    Payload::SessionKey { thread_id, seed } => {
+                        if self.session_thread_id(&sender) != Some(thread_id) {
+                            return Err(Error::Relationship(
+                                "invalid attempt to establish a session".into(),
+                            ));
+                        }
+
+                        let receiver = intended_receiver.identifier();
+                        self.receiving_sessions.insert(
+                            (sender.clone(), receiver.to_string()),
+                            ReceivingSession::new(seed, thread_id, &sender, receiver),
+                        );
+
+                        Ok(ReceivedTspMessage::SessionEstablished { sender })
+                    }
+                    Payload::CancelNestedRelationship {
+                        nested_vid,
+                        thread_id,
+                    } => {
+                        let nested_vid = std::str::from_utf8(nested_vid)?.to_string();
+                        let context = self.get_vid(&nested_vid)?;
+
+                        let related = match context.relation_status {
+                            RelationshipStatus::Bidirectional {
+                                thread_id: digest, ..
+                            }
+                            | RelationshipStatus::Unidirectional {
+                                thread_id: digest, ..
+                            } => digest == thread_id,
+                            _ => false,
+                        };
+
+                        if !related || context.get_parent_vid() != Some(sender.as_str()) {
+                            return Err(Error::Relationship(
+                                "invalid attempt to end the nested relationship".into(),
+                            ));
+                        }
+
+                        // remove the nested VIDs that were generated for this relationship
+                        self.cleanup_relationship(&nested_vid, false)?;
+                        if let Some(own_vid) = context.get_relation_vid() {
+                            if self.get_vid(own_vid)?.get_relation_vid() == Some(&nested_vid) {
+                                self.forget_vid(own_vid)?;
+                            }
+                        }
+                        self.forget_vid(&nested_vid)?;
+
+                        Ok(ReceivedTspMessage::CancelRelationship {
+                            sender,
+                            nested_vid: Some(nested_vid),
+                        })
                     }
                     Payload::RequestNestedRelationship { inner, thread_id } => {
                         let EnvelopeType::SignedMessage {
@@ -694,11 +2817,11 @@ impl Store {
 
                         let inner_vid = std::str::from_utf8(inner_vid)?.to_string();
 
-                        self.add_nested_vid(&inner_vid)?;
+                        self.add_nested_vid(&inner_vid, &sender)?;
 
                         // the act of opening this message is simply verifying the signature, because this SDK doesn't yet
                         // support sending data as part of control messages. This can easily change.
-                        let _ = self.open_message(inner)?;
+                        let _ = self.open_message_at_depth(inner, depth + 1)?;
 
                         self.set_parent_for_vid(&inner_vid, Some(&sender))?;
 
@@ -721,9 +2844,9 @@ impl Store {
 
                         let vid = std::str::from_utf8(vid)?.to_string();
                         let connect_to_vid = std::str::from_utf8(connect_to_vid)?.to_string();
-                        self.add_nested_vid(&vid)?;
+                        self.add_nested_vid(&vid, &sender)?;
 
-                        let _ = self.open_message(inner)?;
+                        let _ = self.open_message_at_depth(inner, depth + 1)?;
 
                         self.set_parent_for_vid(&vid, Some(&sender))?;
                         self.add_nested_relation(&sender, &vid, thread_id)?;
@@ -737,6 +2860,8 @@ impl Store {
                     }
                     Payload::NewIdentifier { thread_id, new_vid } => {
                         let vid = std::str::from_utf8(new_vid)?.to_string();
+                        self.check_policy(&vid, VidOrigin::NewIdentifier { sender: &sender })?;
+
                         match self.get_vid(&sender)?.relation_status {
                             RelationshipStatus::Bidirectional {
                                 thread_id: check_id,
@@ -748,15 +2873,78 @@ impl Store {
                             _ => Err(Error::Relationship(vid)),
                         }
                     }
+                    Payload::GroupMessage { group, message } => {
+                        Ok(ReceivedTspMessage::GroupMessage {
+                            sender,
+                            group: std::str::from_utf8(group)?.to_string(),
+                            nonconfidential_data,
+                            message,
+                            message_type: MessageType {
+                                crypto_type,
+                                signature_type,
+                                digest_algorithm,
+                                compressed: false,
+                            },
+                        })
+                    }
+                    Payload::GroupMembership {
+                        group,
+                        member,
+                        change,
+                    } => {
+                        let group = std::str::from_utf8(group)?;
+                        let member = std::str::from_utf8(member)?;
+
+                        if change == MembershipChange::Added {
+                            self.check_policy(
+                                member,
+                                VidOrigin::GroupMember {
+                                    group,
+                                    sender: &sender,
+                                },
+                            )?;
+                        }
+
+                        Ok(ReceivedTspMessage::GroupMembership {
+                            sender,
+                            group: group.to_string(),
+                            member: member.to_string(),
+                            change,
+                        })
+                    }
+                    Payload::Acknowledgement { digest } => {
+                        Ok(ReceivedTspMessage::Acknowledgement { sender, digest })
+                    }
                     Payload::Referral { referred_vid } => {
                         //NOTE: we could also check the relationship status here, but since a 3rd party introduction
                         //might be of interest to a user anyway regardless of existing status, we are less strict about it
                         let vid = std::str::from_utf8(referred_vid)?;
+                        self.check_policy(vid, VidOrigin::Referral { sender: &sender })?;
+
                         Ok(ReceivedTspMessage::Referral {
                             sender,
                             referred_vid: vid.to_string(),
                         })
                     }
+                    Payload::Extension { typecode, data } => {
+                        let message = self
+                            .extension(typecode)?
+                            .decode(data)
+                            .map_err(|reason| Error::InvalidExtension(typecode, reason))?;
+
+                        Ok(ReceivedTspMessage::Extension {
+                            sender,
+                            typecode,
+                            message,
+                        })
+                    }
+                    Payload::UnknownControl { typecode, raw } => {
+                        Ok(ReceivedTspMessage::UnknownControl {
+                            sender,
+                            typecode,
+                            raw,
+                        })
+                    }
                 }
             }
             EnvelopeType::SignedMessage {
@@ -764,9 +2952,13 @@ impl Store {
                 receiver: intended_receiver,
                 ..
             } => {
-                if let Some(intended_receiver) = intended_receiver {
-                    let intended_receiver = std::str::from_utf8(intended_receiver)?;
+                // copied, since the message is borrowed again to verify it
+                let intended_receiver = intended_receiver
+                    .map(std::str::from_utf8)
+                    .transpose()?
+                    .map(String::from);
 
+                if let Some(intended_receiver) = &intended_receiver {
                     if !self.has_private_vid(intended_receiver)? {
                         return Err(CryptoError::UnexpectedRecipient.into());
                     }
@@ -779,11 +2971,25 @@ impl Store {
                 };
 
                 let (message, message_type) = crate::crypto::verify(&*sender_vid, message)?;
+                self.record_received(&sender);
+                if let Some(intended_receiver) = &intended_receiver {
+                    self.use_vid(intended_receiver)?;
+                }
+                self.check_relationship(&sender, None)?;
+
+                let max_size = self.config().max_payload_size;
+                if message.len() > max_size {
+                    return Err(Error::PayloadTooLarge(message.len(), max_size));
+                }
 
                 Ok(ReceivedTspMessage::GenericMessage {
                     sender,
                     nonconfidential_data: None,
                     message,
+                    content_type: None,
+                    segments: Vec::new(),
+                    in_reply_to: None,
+                    digest: message_type.digest_algorithm.hash(message),
                     message_type,
                 })
             }
@@ -796,6 +3002,30 @@ impl Store {
         sender: &str,
         receiver: &str,
         route: Option<&[&str]>,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        self.relationship_request(sender, receiver, route, None)
+    }
+
+    /// Make a relationship request like [`Store::make_relationship_request`], for a relationship
+    /// that ends by itself `ttl` from now unless it is renewed with [`Store::make_relationship_renew`]
+    pub fn make_relationship_request_with_ttl(
+        &self,
+        sender: &str,
+        receiver: &str,
+        route: Option<&[&str]>,
+        ttl: Duration,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        let expires_at = now().saturating_add(ttl.as_secs());
+
+        self.relationship_request(sender, receiver, route, Some(expires_at))
+    }
+
+    fn relationship_request(
+        &self,
+        sender: &str,
+        receiver: &str,
+        route: Option<&[&str]>,
+        expires_at: Option<u64>,
     ) -> Result<(Url, Vec<u8>), Error> {
         let sender = self.get_private_vid(sender)?;
         let receiver = self.get_verified_vid(receiver)?;
@@ -819,12 +3049,16 @@ impl Store {
             self.set_route_for_vid(receiver.identifier(), hop_list)?;
             self.resolve_route_and_send(hop_list, &tsp_message)?
         } else {
-            (receiver.endpoint().clone(), tsp_message)
+            (self.endpoint_for(&*receiver), tsp_message)
         };
+        self.audit_sent(sender.identifier(), receiver.identifier(), &tsp_message);
 
         self.set_relation_status_for_vid(
             receiver.identifier(),
-            RelationshipStatus::Unidirectional { thread_id },
+            RelationshipStatus::Unidirectional {
+                thread_id,
+                expires_at,
+            },
         )?;
 
         Ok((transport, tsp_message.to_owned()))
@@ -859,6 +3093,7 @@ impl Store {
             RelationshipStatus::Bidirectional {
                 thread_id,
                 outstanding_nested_thread_ids: Default::default(),
+                expires_at: None,
             },
             sender,
         )?;
@@ -866,358 +3101,2489 @@ impl Store {
         Ok((transport, tsp_message))
     }
 
-    /// Cancels a direct relationship between the resolved `sender` and `receiver` VIDs.
+    /// Reject a direct relationship between the resolved VIDs identifier by `sender` and `receiver`.
+    /// `thread_id` must be the same as the one that was present in the relationship request.
     /// Encodes the control message, encrypts, signs and sends a TSP message
-    pub fn make_relationship_cancel(
+    pub fn make_relationship_reject(
         &self,
         sender: &str,
         receiver: &str,
+        thread_id: Digest,
+        reason: Option<&str>,
     ) -> Result<(Url, Vec<u8>), Error> {
-        let old_relationship =
-            self.replace_relation_status_for_vid(receiver, RelationshipStatus::Unrelated)?;
-
-        let thread_id = match old_relationship {
-            RelationshipStatus::Bidirectional { thread_id, .. } => thread_id,
-            RelationshipStatus::Unidirectional { thread_id } => thread_id,
-            RelationshipStatus::_Controlled | RelationshipStatus::Unrelated => {
-                return Err(Error::Relationship("no relationship to cancel".into()))
-            }
-        };
-
-        let (transport, message) = self.seal_message_payload(
+        self.seal_message_payload(
             sender,
             receiver,
             None,
-            Payload::CancelRelationship { thread_id },
-        )?;
-
-        Ok((transport, message))
+            Payload::RejectRelationship {
+                thread_id,
+                reason: reason.unwrap_or_default().as_bytes(),
+            },
+        )
     }
 
-    /// Send a nested relationship request to `receiver`, creating a new nested vid with `outer_sender` as a parent.
-    pub fn make_nested_relationship_request(
+    /// Extend the direct relationship between the resolved `sender` and `receiver` VIDs
+    /// until `ttl` from now; the receiver applies the same expiry to its side.
+    /// Encodes the control message, encrypts, signs and sends a TSP message
+    pub fn make_relationship_renew(
         &self,
-        parent_sender: &str,
+        sender: &str,
         receiver: &str,
-    ) -> Result<((Url, Vec<u8>), OwnedVid), Error> {
-        let sender = self.get_private_vid(parent_sender)?;
-        let receiver = self.get_verified_vid(receiver)?;
+        ttl: Duration,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        self.expire_relationship(receiver)?;
 
-        let nested_vid = self.make_propositioning_vid(sender.identifier())?;
+        let new_expiry = now().saturating_add(ttl.as_secs());
 
-        let inner_message = crate::crypto::sign(&nested_vid, None, &[])?;
+        let thread_id = self.modify_vid(receiver, |context| {
+            let RelationshipStatus::Bidirectional {
+                thread_id,
+                ref mut expires_at,
+                ..
+            } = context.relation_status
+            else {
+                return Err(Error::Relationship("no relationship to renew".into()));
+            };
 
-        let mut thread_id = Default::default();
-        let (endpoint, tsp_message) = self.seal_message_payload_and_hash(
-            sender.identifier(),
-            receiver.identifier(),
+            *expires_at = Some(new_expiry);
+
+            Ok(thread_id)
+        })?;
+
+        self.seal_message_payload(
+            sender,
+            receiver,
             None,
-            Payload::RequestNestedRelationship {
-                inner: &inner_message,
-                thread_id: Default::default(),
+            Payload::RenewRelationship {
+                thread_id,
+                expires_at: new_expiry,
             },
-            Some(&mut thread_id),
-        )?;
+        )
+    }
 
-        self.add_nested_thread_id(receiver.identifier(), thread_id)?;
+    /// End the relationship with `vid` if it has expired; returns whether it did
+    fn expire_relationship(&self, vid: &str) -> Result<bool, Error> {
+        self.modify_vid(vid, |context| {
+            // only look at the clock for relationships that can expire
+            if context.relation_status.expires_at().is_none()
+                || !context.relation_status.is_expired(now())
+            {
+                return Ok(false);
+            }
 
-        Ok(((endpoint, tsp_message), nested_vid))
+            context.relation_status = RelationshipStatus::Unrelated;
+
+            Ok(true)
+        })
     }
 
-    /// Accept a nested relationship with the (nested) VID identified by `nested_receiver`.
-    /// Generate a new nested VID that will have `parent_sender` as its parent.
-    /// `thread_id` must be the same as the one that was present in the relationship request.
-    /// Encodes the control message, encrypts, signs and sends a TSP message
-    pub fn make_nested_relationship_accept(
-        &self,
-        parent_sender: &str,
-        nested_receiver: &str,
-        thread_id: Digest,
-    ) -> Result<((Url, Vec<u8>), OwnedVid), Error> {
-        let nested_vid = self.make_propositioning_vid(parent_sender)?;
-        self.set_relation_for_vid(nested_vid.identifier(), Some(nested_receiver))?;
-        self.set_relation_for_vid(nested_receiver, Some(nested_vid.identifier()))?;
+    /// End all relationships that have expired, e.g. periodically in a long-lived wallet;
+    /// returns the VIDs whose relationship ended. Relationships with a single VID also end
+    /// when a message from that VID is opened after the expiry
+    pub fn expire_relationships(&self) -> Result<Vec<String>, Error> {
+        let mut expired = Vec::new();
 
-        let receiver_vid = self.get_vid(nested_receiver)?;
-        let parent_receiver = receiver_vid
-            .get_parent_vid()
-            .ok_or(Error::Relationship(format!(
-                "missing parent for {nested_receiver}"
-            )))?;
+        for vid in self.list_vids()? {
+            if self.expire_relationship(&vid)? {
+                expired.push(vid);
+            }
+        }
 
-        let inner_message = crate::crypto::sign(&nested_vid, Some(&*receiver_vid.vid), &[])?;
+        Ok(expired)
+    }
 
-        let (transport, tsp_message) = self.seal_message_payload(
-            parent_sender,
-            parent_receiver,
-            None,
-            Payload::AcceptNestedRelationship {
-                thread_id,
-                inner: &inner_message,
-            },
-        )?;
+    /// Forget the nested relationship requests that were sent more than `max_age` seconds
+    /// ago and were not accepted, e.g. after a restart; an accept for such a request is
+    /// refused. Returns the VIDs the requests were sent to, with the forgotten thread ids
+    pub fn expire_nested_requests(&self, max_age: u64) -> Vec<(String, Vec<Digest>)> {
+        let now = now();
+        let mut expired = Vec::new();
 
-        self.set_relation_status_for_vid(
-            nested_receiver,
-            RelationshipStatus::Bidirectional {
-                thread_id,
-                outstanding_nested_thread_ids: Default::default(),
-            },
-        )?;
+        for mut context in self.vids.iter_mut() {
+            let RelationshipStatus::Bidirectional {
+                ref mut outstanding_nested_thread_ids,
+                ..
+            } = context.relation_status
+            else {
+                continue;
+            };
 
-        Ok(((transport, tsp_message), nested_vid))
+            let mut thread_ids = Vec::new();
+            outstanding_nested_thread_ids.retain(|outstanding| {
+                let stale = outstanding.requested_at.saturating_add(max_age) <= now;
+                if stale {
+                    thread_ids.push(outstanding.thread_id);
+                }
+
+                !stale
+            });
+
+            if !thread_ids.is_empty() {
+                self.changed.insert(context.key().clone());
+                expired.push((context.key().clone(), thread_ids));
+            }
+        }
+
+        expired
     }
 
-    pub fn make_new_identifier_notice(
-        &self,
-        sender: &str,
-        receiver: &str,
-        new_vid: &str,
-    ) -> Result<(Url, Vec<u8>), Error> {
-        // check that the new vid is actually one of ours
-        let _new_vid = self.get_private_vid(new_vid)?;
+    /// Establish a session for the messages from `sender` to `receiver`, which need a
+    /// direct, bidirectional relationship. Returns the control message to send, which
+    /// is sealed as usual; once it is sent, generic messages to `receiver` are sealed with
+    /// [CryptoType::Session](crate::cesr::CryptoType::Session), using a new key from a
+    /// symmetric ratchet for every message.
+    ///
+    /// A session is used for at most [MAX_MESSAGES](crate::crypto::session::MAX_MESSAGES)
+    /// messages and [MAX_AGE](crate::crypto::session::MAX_AGE) seconds; after that, messages
+    /// are sealed with HPKE again until a new session is established, see
+    /// [Store::needs_new_session]. Calling this again replaces the session.
+    pub fn make_session_key(&self, sender: &str, receiver: &str) -> Result<(Url, Vec<u8>), Error> {
+        use rand::RngCore;
+
+        let context = self.get_vid(receiver)?;
+        if context.get_route().is_some() || context.get_parent_vid().is_some() {
+            return Err(Error::Relationship(
+                "sessions need a direct relationship".into(),
+            ));
+        }
 
-        let RelationshipStatus::Bidirectional { thread_id, .. } =
-            self.get_vid(receiver)?.relation_status
-        else {
-            return Err(Error::Relationship(receiver.to_string()));
+        let Some(thread_id) = self.session_thread_id(receiver) else {
+            return Err(Error::Relationship(format!(
+                "no relationship with {receiver} to establish a session for"
+            )));
         };
 
-        let (transport, tsp_message) = self.seal_message_payload(
+        let mut seed = crate::secret::Secret::new([0; 32]);
+        rand::rngs::OsRng.fill_bytes(seed.expose_secret_mut());
+
+        let message = self.seal_message_payload(
             sender,
             receiver,
             None,
-            Payload::NewIdentifier {
+            Payload::SessionKey {
                 thread_id,
-                new_vid: new_vid.as_ref(),
+                seed: seed.expose_secret(),
             },
         )?;
 
-        Ok((transport, tsp_message))
+        self.sending_sessions.insert(
+            (sender.to_string(), receiver.to_string()),
+            SendingSession::new(seed.expose_secret(), thread_id, sender, receiver, now()),
+        );
+
+        Ok(message)
     }
 
-    pub fn make_relationship_referral(
+    /// Whether the session for the messages from `sender` to `receiver` has run out, or
+    /// belongs to an earlier relationship, so a new one has to be established with
+    /// [Store::make_session_key]. Without a session or a relationship, this is `false`
+    pub fn needs_new_session(&self, sender: &str, receiver: &str) -> bool {
+        let Some(thread_id) = self.session_thread_id(receiver) else {
+            return false;
+        };
+
+        self.sending_sessions
+            .get(&(sender.to_string(), receiver.to_string()))
+            .is_some_and(|session| *session.thread_id() != thread_id || session.is_exhausted(now()))
+    }
+
+    /// The thread id of the bidirectional relationship with `vid`, which sessions belong to
+    fn session_thread_id(&self, vid: &str) -> Option<Digest> {
+        match self.vids.get(vid)?.relation_status {
+            RelationshipStatus::Bidirectional { thread_id, .. } => Some(thread_id),
+            _ => None,
+        }
+    }
+
+    /// Take the next key of the session from `sender` to `receiver` if `payload` is a
+    /// generic message, and the session is still valid for the relationship
+    fn next_session_key(
+        &self,
+        sender: &str,
+        receiver: &str,
+        payload: &Payload<&[u8]>,
+    ) -> Option<(u64, MessageKey)> {
+        if !matches!(payload, Payload::Content(_) | Payload::Reply { .. }) {
+            return None;
+        }
+
+        let thread_id = self.session_thread_id(receiver)?;
+        let mut session = self
+            .sending_sessions
+            .get_mut(&(sender.to_string(), receiver.to_string()))?;
+
+        if *session.thread_id() != thread_id || session.is_exhausted(now()) {
+            return None;
+        }
+
+        Some(session.next_key())
+    }
+
+    /// Look up the key of a message from `sender` to `receiver` sealed with a session key
+    fn receiving_session_key(
+        &self,
+        sender: &str,
+        receiver: &str,
+        counter: u64,
+    ) -> Result<MessageKey, CryptoError> {
+        let thread_id = self.session_thread_id(sender);
+        let key = (sender.to_string(), receiver.to_string());
+
+        let Some(mut session) = self.receiving_sessions.get_mut(&key) else {
+            return Err(CryptoError::MissingSession);
+        };
+
+        // a session ends with the relationship it was established for
+        if thread_id != Some(*session.thread_id()) {
+            drop(session);
+            self.receiving_sessions.remove(&key);
+
+            return Err(CryptoError::MissingSession);
+        }
+
+        session.value_mut().key(counter)
+    }
+
+    /// Cancels a direct relationship between the resolved `sender` and `receiver` VIDs.
+    /// Encodes the control message, encrypts, signs and sends a TSP message
+    pub fn make_relationship_cancel(
         &self,
         sender: &str,
         receiver: &str,
-        referred_vid: &str,
     ) -> Result<(Url, Vec<u8>), Error> {
-        // check that we actually know the referred vid
-        let _referred_vid = self.get_vid(referred_vid)?;
+        let old_relationship =
+            self.replace_relation_status_for_vid(receiver, RelationshipStatus::Unrelated)?;
 
-        let (transport, tsp_message) = self.seal_message_payload(
+        let thread_id = match old_relationship {
+            RelationshipStatus::Bidirectional { thread_id, .. } => thread_id,
+            RelationshipStatus::Unidirectional { thread_id, .. } => thread_id,
+            RelationshipStatus::_Controlled | RelationshipStatus::Unrelated => {
+                return Err(Error::Relationship("no relationship to cancel".into()))
+            }
+        };
+
+        let (transport, message) = self.seal_message_payload(
             sender,
             receiver,
             None,
-            Payload::Referral {
-                referred_vid: referred_vid.as_ref(),
-            },
+            Payload::CancelRelationship { thread_id },
         )?;
 
-        Ok((transport, tsp_message))
+        self.cleanup_relationship(receiver, false)?;
+
+        Ok((transport, message))
     }
 
-    fn make_propositioning_vid(&self, parent_vid: &str) -> Result<OwnedVid, Error> {
-        let transport = Url::parse("tsp://").expect("error generating a URL");
+    /// Cancels a nested relationship between the `nested_sender` and `nested_receiver` VIDs.
+    /// The control message is sent between their parents, after which both nested VIDs are
+    /// removed from the store.
+    pub fn make_nested_relationship_cancel(
+        &self,
+        nested_sender: &str,
+        nested_receiver: &str,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        let sender = self.get_vid(nested_sender)?;
+        let receiver = self.get_vid(nested_receiver)?;
+
+        let thread_id = match receiver.relation_status {
+            RelationshipStatus::Bidirectional { thread_id, .. }
+            | RelationshipStatus::Unidirectional { thread_id, .. }
+                if receiver.get_relation_vid() == Some(nested_sender) =>
+            {
+                thread_id
+            }
+            _ => {
+                return Err(Error::Relationship(
+                    "no nested relationship to cancel".into(),
+                ))
+            }
+        };
 
-        let vid = OwnedVid::new_did_peer(transport);
-        self.add_private_vid(vid.clone())?;
-        self.set_parent_for_vid(vid.identifier(), Some(parent_vid))?;
+        let (Some(parent_sender), Some(parent_receiver)) =
+            (sender.get_parent_vid(), receiver.get_parent_vid())
+        else {
+            return Err(Error::Relationship(
+                "no nested relationship to cancel".into(),
+            ));
+        };
 
-        Ok(vid)
+        let (transport, message) = self.seal_message_payload(
+            parent_sender,
+            parent_receiver,
+            None,
+            Payload::CancelNestedRelationship {
+                nested_vid: nested_sender.as_bytes(),
+                thread_id,
+            },
+        )?;
+
+        self.cleanup_relationship(nested_receiver, false)?;
+        self.forget_vid(nested_sender)?;
+        self.forget_vid(nested_receiver)?;
+
+        Ok((transport, message))
     }
 
-    /// Send a message given a route, extracting the next hop and verifying it in the process
-    fn resolve_route_and_send(
+    /// Send a nested relationship request to `receiver`, creating a new nested vid with `outer_sender` as a parent.
+    pub fn make_nested_relationship_request(
         &self,
-        hop_list: &[&str],
-        opaque_message: &[u8],
-    ) -> Result<(Url, Vec<u8>), Error> {
-        let (next_hop, path) = self.resolve_route(hop_list)?;
+        parent_sender: &str,
+        receiver: &str,
+    ) -> Result<((Url, Vec<u8>), OwnedVid), Error> {
+        let sender = self.get_private_vid(parent_sender)?;
+        let receiver = self.get_verified_vid(receiver)?;
 
-        self.forward_routed_message(&next_hop, path, opaque_message)
-    }
+        let nested_vid = self.make_propositioning_vid(sender.identifier())?;
 
-    fn add_nested_vid(&self, vid: &str) -> Result<(), Error> {
-        let nested_vid = verify_vid_offline(vid)?;
+        let inner_message = crate::crypto::sign(&nested_vid, None, &[])?;
 
-        self.add_verified_vid(nested_vid)
+        let mut thread_id = Default::default();
+        let (endpoint, tsp_message) = self.seal_message_payload_and_hash(
+            sender.identifier(),
+            receiver.identifier(),
+            None,
+            Payload::RequestNestedRelationship {
+                inner: &inner_message,
+                thread_id: Default::default(),
+            },
+            Some(&mut thread_id),
+        )?;
+
+        self.add_nested_thread_id(receiver.identifier(), thread_id)?;
+
+        Ok(((endpoint, tsp_message), nested_vid))
     }
 
-    fn upgrade_relation(
+    /// Accept a nested relationship with the (nested) VID identified by `nested_receiver`.
+    /// Generate a new nested VID that will have `parent_sender` as its parent.
+    /// `thread_id` must be the same as the one that was present in the relationship request.
+    /// Encodes the control message, encrypts, signs and sends a TSP message
+    pub fn make_nested_relationship_accept(
         &self,
-        my_vid: &str,
-        other_vid: &str,
+        parent_sender: &str,
+        nested_receiver: &str,
         thread_id: Digest,
-    ) -> Result<(), Error> {
-        let mut vids = self.vids.write()?;
-        let Some(context) = vids.get_mut(other_vid) else {
-            return Err(Error::Relationship(other_vid.into()));
-        };
+    ) -> Result<((Url, Vec<u8>), OwnedVid), Error> {
+        let nested_vid = self.make_propositioning_vid(parent_sender)?;
+        self.set_relation_for_vid(nested_vid.identifier(), Some(nested_receiver))?;
+        self.set_relation_for_vid(nested_receiver, Some(nested_vid.identifier()))?;
 
-        let RelationshipStatus::Unidirectional { thread_id: digest } = context.relation_status
-        else {
-            return Err(Error::Relationship(other_vid.into()));
-        };
+        let receiver_vid = self.get_vid(nested_receiver)?;
+        let parent_receiver = receiver_vid
+            .get_parent_vid()
+            .ok_or(Error::Relationship(format!(
+                "missing parent for {nested_receiver}"
+            )))?;
 
-        if thread_id != digest {
-            return Err(Error::Relationship(other_vid.into()));
-        }
+        let inner_message = crate::crypto::sign(&nested_vid, Some(&*receiver_vid.vid), &[])?;
 
-        context.relation_vid = Some(my_vid.to_string());
+        let (transport, tsp_message) = self.seal_message_payload(
+            parent_sender,
+            parent_receiver,
+            None,
+            Payload::AcceptNestedRelationship {
+                thread_id,
+                inner: &inner_message,
+            },
+        )?;
 
-        context.relation_status = RelationshipStatus::Bidirectional {
-            thread_id: digest,
-            outstanding_nested_thread_ids: Default::default(),
-        };
+        self.set_relation_status_for_vid(
+            nested_receiver,
+            RelationshipStatus::Bidirectional {
+                thread_id,
+                outstanding_nested_thread_ids: Default::default(),
+                expires_at: None,
+            },
+        )?;
 
-        Ok(())
+        Ok(((transport, tsp_message), nested_vid))
     }
 
-    fn add_nested_thread_id(&self, vid: &str, thread_id: Digest) -> Result<(), Error> {
-        let mut vids = self.vids.write()?;
-        let Some(context) = vids.get_mut(vid) else {
-            return Err(Error::MissingVid(vid.into()));
-        };
+    pub fn make_new_identifier_notice(
+        &self,
+        sender: &str,
+        receiver: &str,
+        new_vid: &str,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        // check that the new vid is actually one of ours
+        let _new_vid = self.get_private_vid(new_vid)?;
 
-        let RelationshipStatus::Bidirectional {
-            ref mut outstanding_nested_thread_ids,
-            ..
-        } = context.relation_status
+        let RelationshipStatus::Bidirectional { thread_id, .. } =
+            self.get_vid(receiver)?.relation_status
         else {
-            return Err(Error::Relationship(vid.into()));
+            return Err(Error::Relationship(receiver.to_string()));
         };
 
-        outstanding_nested_thread_ids.push(thread_id);
+        let (transport, tsp_message) = self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::NewIdentifier {
+                thread_id,
+                new_vid: new_vid.as_ref(),
+            },
+        )?;
+
+        Ok((transport, tsp_message))
+    }
+
+    /// Create a group named `name`, whose messages are sent by the private VID `owner`
+    pub fn create_group(&self, name: &str, owner: &str) -> Result<(), Error> {
+        let _owner = self.get_private_vid(owner)?;
+
+        let mut groups = self.groups.write()?;
+        if groups.contains_key(name) {
+            return Err(Error::Group(name.to_string(), "already exists"));
+        }
+
+        groups.insert(
+            name.to_string(),
+            Group {
+                owner: owner.to_string(),
+                members: Vec::new(),
+            },
+        );
 
         Ok(())
     }
 
-    fn add_nested_relation(
-        &self,
-        parent_vid: &str,
-        nested_vid: &str,
-        thread_id: Digest,
-    ) -> Result<(), Error> {
-        let mut vids = self.vids.write()?;
-        let Some(context) = vids.get_mut(parent_vid) else {
-            return Err(Error::Relationship(parent_vid.into()));
-        };
+    /// Remove the group named `name`; the members are not notified
+    pub fn remove_group(&self, name: &str) -> Result<(), Error> {
+        match self.groups.write()?.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(Error::Group(name.to_string(), "does not exist")),
+        }
+    }
 
-        let RelationshipStatus::Bidirectional {
-            ref mut outstanding_nested_thread_ids,
-            ..
-        } = context.relation_status
-        else {
-            return Err(Error::Relationship(parent_vid.into()));
-        };
+    /// Retrieve the group named `name`
+    pub fn get_group(&self, name: &str) -> Result<Group, Error> {
+        match self.groups.read()?.get(name) {
+            Some(group) => Ok(group.clone()),
+            None => Err(Error::Group(name.to_string(), "does not exist")),
+        }
+    }
 
-        // find the thread_id in the list of outstanding thread id's of the parent and remove it
-        let Some(index) = outstanding_nested_thread_ids
-            .iter()
-            .position(|&x| x == thread_id)
-        else {
-            return Err(Error::Relationship(nested_vid.into()));
-        };
-        outstanding_nested_thread_ids.remove(index);
+    /// Add the verified VID `member` to a group. Returns the membership notices to
+    /// deliver: the existing members learn about the new member, and the new member
+    /// learns about everyone in the group
+    pub fn add_group_member(&self, name: &str, member: &str) -> Result<Vec<(Url, Vec<u8>)>, Error> {
+        let _member = self.get_verified_vid(member)?;
 
-        let Some(context) = vids.get_mut(nested_vid) else {
-            return Err(Error::Relationship(nested_vid.into()));
+        let group = {
+            let mut groups = self.groups.write()?;
+            let Some(group) = groups.get_mut(name) else {
+                return Err(Error::Group(name.to_string(), "does not exist"));
+            };
+
+            if group.members.iter().any(|m| m == member) {
+                return Err(Error::Group(
+                    name.to_string(),
+                    "already contains this member",
+                ));
+            }
+
+            group.members.push(member.to_string());
+            group.clone()
         };
 
-        context.relation_status = RelationshipStatus::Bidirectional {
-            thread_id,
-            outstanding_nested_thread_ids: Default::default(),
+        let mut notices = Vec::with_capacity(2 * group.members.len());
+        for existing in &group.members {
+            notices.push(self.seal_membership_notice(
+                &group,
+                name,
+                existing,
+                member,
+                MembershipChange::Added,
+            )?);
+
+            if existing != member {
+                notices.push(self.seal_membership_notice(
+                    &group,
+                    name,
+                    member,
+                    existing,
+                    MembershipChange::Added,
+                )?);
+            }
+        }
+
+        Ok(notices)
+    }
+
+    /// Remove `member` from a group. Returns the membership notices to deliver
+    /// to the remaining members and to the removed member
+    pub fn remove_group_member(
+        &self,
+        name: &str,
+        member: &str,
+    ) -> Result<Vec<(Url, Vec<u8>)>, Error> {
+        let group = {
+            let mut groups = self.groups.write()?;
+            let Some(group) = groups.get_mut(name) else {
+                return Err(Error::Group(name.to_string(), "does not exist"));
+            };
+
+            let Some(index) = group.members.iter().position(|m| m == member) else {
+                return Err(Error::Group(
+                    name.to_string(),
+                    "does not contain this member",
+                ));
+            };
+
+            group.members.remove(index);
+            group.clone()
         };
 
-        Ok(())
+        group
+            .members
+            .iter()
+            .map(String::as_str)
+            .chain([member])
+            .map(|receiver| {
+                self.seal_membership_notice(
+                    &group,
+                    name,
+                    receiver,
+                    member,
+                    MembershipChange::Removed,
+                )
+            })
+            .collect()
     }
-}
 
-#[cfg(test)]
-mod test {
-    use wasm_bindgen_test::wasm_bindgen_test;
+    fn seal_membership_notice(
+        &self,
+        group: &Group,
+        name: &str,
+        receiver: &str,
+        member: &str,
+        change: MembershipChange,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        self.seal_message_payload(
+            &group.owner,
+            receiver,
+            None,
+            Payload::GroupMembership {
+                group: name.as_bytes(),
+                member: member.as_bytes(),
+                change,
+            },
+        )
+    }
+
+    /// Seal a message for every member of a group
+    pub fn seal_group_message(
+        &self,
+        name: &str,
+        nonconfidential_data: Option<&[u8]>,
+        message: &[u8],
+    ) -> Result<Vec<(Url, Vec<u8>)>, Error> {
+        let group = self.get_group(name)?;
+
+        group
+            .members
+            .iter()
+            .map(|member| {
+                self.seal_message_payload(
+                    &group.owner,
+                    member,
+                    nonconfidential_data,
+                    Payload::GroupMessage {
+                        group: name.as_bytes(),
+                        message,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn make_relationship_referral(
+        &self,
+        sender: &str,
+        receiver: &str,
+        referred_vid: &str,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        // check that we actually know the referred vid
+        let _referred_vid = self.get_vid(referred_vid)?;
+
+        let (transport, tsp_message) = self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::Referral {
+                referred_vid: referred_vid.as_ref(),
+            },
+        )?;
+
+        Ok((transport, tsp_message))
+    }
+
+    /// Make a control message of the application defined type `typecode`, encoded by the
+    /// [ControlExtension] registered with [Store::register_control_extension]
+    pub fn make_extension_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        typecode: u8,
+        message: &serde_json::Value,
+    ) -> Result<(Url, Vec<u8>), Error> {
+        let data = self
+            .extension(typecode)?
+            .encode(message)
+            .map_err(|reason| Error::InvalidExtension(typecode, reason))?;
+
+        self.seal_message_payload(
+            sender,
+            receiver,
+            None,
+            Payload::Extension {
+                typecode,
+                data: &data,
+            },
+        )
+    }
+
+    fn make_propositioning_vid(&self, parent_vid: &str) -> Result<OwnedVid, Error> {
+        let transport =
+            Url::parse(&format!("{PLACEHOLDER_SCHEME}://")).expect("error generating a URL");
+
+        let vid = OwnedVid::new_did_peer(transport);
+        self.add_private_vid(vid.clone())?;
+        self.set_parent_for_vid(vid.identifier(), Some(parent_vid))?;
+
+        Ok(vid)
+    }
+
+    /// Send a message given a route, extracting the next hop and verifying it in the process
+    fn resolve_route_and_send(
+        &self,
+        hop_list: &[&str],
+        opaque_message: &[u8],
+    ) -> Result<(Url, Vec<u8>), Error> {
+        let (next_hop, path) = self.resolve_route(hop_list)?;
+
+        self.forward_routed_message(&next_hop, path, opaque_message)
+    }
+
+    fn add_nested_vid(&self, vid: &str, parent: &str) -> Result<(), Error> {
+        self.check_policy(vid, VidOrigin::Nested { parent })?;
+
+        let nested_vid = verify_vid_offline(vid)?;
+
+        self.add_verified_vid(nested_vid)?;
+        self.set_provenance(
+            vid,
+            Provenance::Nested {
+                parent: parent.to_string(),
+            },
+        )
+    }
+
+    fn upgrade_relation(
+        &self,
+        my_vid: &str,
+        other_vid: &str,
+        thread_id: Digest,
+    ) -> Result<(), Error> {
+        let Some(mut context) = self.vid_mut(other_vid) else {
+            return Err(Error::Relationship(other_vid.into()));
+        };
+
+        let RelationshipStatus::Unidirectional {
+            thread_id: digest,
+            expires_at,
+        } = context.relation_status
+        else {
+            return Err(Error::Relationship(other_vid.into()));
+        };
+
+        if thread_id != digest {
+            return Err(Error::Relationship(other_vid.into()));
+        }
+
+        context.relation_vid = Some(my_vid.to_string());
+
+        // the relationship lasts as long as was asked for in the request
+        context.relation_status = RelationshipStatus::Bidirectional {
+            thread_id: digest,
+            outstanding_nested_thread_ids: Default::default(),
+            expires_at,
+        };
+        self.audit_relationship(
+            other_vid,
+            RelationshipState::Requested,
+            RelationshipState::Established,
+        );
+
+        Ok(())
+    }
+
+    fn add_nested_thread_id(&self, vid: &str, thread_id: Digest) -> Result<(), Error> {
+        let Some(mut context) = self.vid_mut(vid) else {
+            return Err(Error::MissingVid(vid.into()));
+        };
+
+        let RelationshipStatus::Bidirectional {
+            ref mut outstanding_nested_thread_ids,
+            ..
+        } = context.relation_status
+        else {
+            return Err(Error::Relationship(vid.into()));
+        };
+
+        outstanding_nested_thread_ids.push(OutstandingThreadId {
+            thread_id,
+            requested_at: now(),
+        });
+
+        Ok(())
+    }
+
+    fn add_nested_relation(
+        &self,
+        parent_vid: &str,
+        nested_vid: &str,
+        thread_id: Digest,
+    ) -> Result<(), Error> {
+        let Some(mut context) = self.vid_mut(parent_vid) else {
+            return Err(Error::Relationship(parent_vid.into()));
+        };
+
+        let RelationshipStatus::Bidirectional {
+            ref mut outstanding_nested_thread_ids,
+            ..
+        } = context.relation_status
+        else {
+            return Err(Error::Relationship(parent_vid.into()));
+        };
+
+        // find the thread_id in the list of outstanding thread id's of the parent and remove it
+        let Some(index) = outstanding_nested_thread_ids
+            .iter()
+            .position(|outstanding| outstanding.thread_id == thread_id)
+        else {
+            return Err(Error::Relationship(nested_vid.into()));
+        };
+        outstanding_nested_thread_ids.remove(index);
+
+        // release the parent entry, the nested VID may be stored in the same shard
+        drop(context);
+
+        let Some(mut context) = self.vid_mut(nested_vid) else {
+            return Err(Error::Relationship(nested_vid.into()));
+        };
+
+        let from = RelationshipState::from(&context.relation_status);
+        context.relation_status = RelationshipStatus::Bidirectional {
+            thread_id,
+            outstanding_nested_thread_ids: Default::default(),
+            expires_at: None,
+        };
+        self.audit_relationship(nested_vid, from, RelationshipState::Established);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::{RelationshipCleanup, RelationshipStatus, StoreConfig};
+    use crate::{
+        archive::{ArchiveConfig, Direction, MessageArchive},
+        audit::{AuditEvent, AuditLog, RelationshipState},
+        definitions::{NonConfidentialPlacement, Payload, SealOptions},
+        vid::VidOrigin,
+        ControlExtension, Error, MembershipChange, OwnedVid, ReceivedTspMessage, Store,
+        VerifiedVid,
+    };
+    #[cfg(feature = "serialize")]
+    use crate::{
+        vid::{CustomFields, VidCodec, VidError},
+        PrivateVid, Vid,
+    };
+    use std::borrow::Cow;
+    #[cfg(feature = "serialize")]
+    use std::sync::Arc;
+
+    fn new_vid() -> OwnedVid {
+        OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap())
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_add_private_vid() {
+        let store = Store::new();
+        let vid = new_vid();
+
+        store.add_private_vid(vid.clone()).unwrap();
+
+        assert!(store.has_private_vid(vid.identifier()).unwrap());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_add_verified_vid() {
+        let store = Store::new();
+        let owned_vid = new_vid();
+
+        store.add_verified_vid(owned_vid.vid().clone()).unwrap();
+
+        assert!(store.get_verified_vid(owned_vid.identifier()).is_ok());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_remove() {
+        let store = Store::new();
+        let vid = new_vid();
+
+        store.add_private_vid(vid.clone()).unwrap();
+
+        assert!(store.has_private_vid(vid.identifier()).unwrap());
+
+        store.forget_vid(vid.identifier()).unwrap();
+
+        assert!(!store.has_private_vid(vid.identifier()).unwrap());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_metadata() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.clone()).unwrap();
+
+        assert_eq!(store.get_metadata(bob.identifier()).unwrap(), None);
+        assert!(store.get_metadata("did:example:unknown").is_err());
+
+        store
+            .set_metadata(
+                bob.identifier(),
+                Some(serde_json::json!({ "name": "Bob", "tag": "work" })),
+            )
+            .unwrap();
+        store
+            .update_metadata(alice.identifier(), |metadata| {
+                *metadata = Some(serde_json::json!({ "tag": "work" }))
+            })
+            .unwrap();
+
+        let mut work = store
+            .find_vids_by_metadata("tag", &serde_json::json!("work"))
+            .unwrap();
+        work.sort();
+        let mut expected = vec![alice.identifier().to_string(), bob.identifier().to_string()];
+        expected.sort();
+        assert_eq!(work, expected);
+
+        assert_eq!(
+            store
+                .find_vids_by_metadata("name", &serde_json::json!("Bob"))
+                .unwrap(),
+            vec![bob.identifier().to_string()]
+        );
+
+        // metadata survives an export and import
+        let restored = Store::new();
+        restored.import(store.export().unwrap()).unwrap();
+        assert_eq!(
+            restored.get_metadata(bob.identifier()).unwrap(),
+            store.get_metadata(bob.identifier()).unwrap()
+        );
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_take_changes() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.clone()).unwrap();
+
+        let mut changed = store
+            .take_changes()
+            .vids
+            .into_iter()
+            .map(|vid| vid.id)
+            .collect::<Vec<_>>();
+        changed.sort();
+        let mut expected = vec![alice.identifier().to_string(), bob.identifier().to_string()];
+        expected.sort();
+        assert_eq!(changed, expected);
+        assert!(store.take_changes().is_empty());
+
+        // sealing a message only changes the statistics of its receiver
+        store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        let changes = store.take_changes();
+        assert_eq!(changes.vids.len(), 1);
+        assert_eq!(changes.vids[0].id, bob.identifier());
+
+        store.forget_vid(bob.identifier()).unwrap();
+        let changes = store.take_changes();
+        assert!(changes.vids.is_empty());
+        assert_eq!(changes.removed, vec![bob.identifier().to_string()]);
+
+        // imported VIDs are already persisted
+        let restored = Store::new();
+        restored.import(store.export().unwrap()).unwrap();
+        assert!(restored.take_changes().is_empty());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_open_seal() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let message = b"hello world";
+
+        let (url, mut sealed) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, message)
+            .unwrap();
+
+        assert_eq!(url.as_str(), "tcp://127.0.0.1:1337");
+
+        let received = store.open_message(&mut sealed).unwrap();
+
+        if let ReceivedTspMessage::GenericMessage {
+            sender,
+            message: received_message,
+            message_type,
+            ..
+        } = received
+        {
+            assert_eq!(sender, alice.identifier());
+            assert_eq!(&*received_message, message);
+            assert_ne!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
+            assert_ne!(
+                message_type.signature_type,
+                crate::cesr::SignatureType::NoSignature
+            );
+        } else {
+            panic!("unexpected message type");
+        }
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_open_seal_unstored_vid() {
+        let alice_store = Store::new();
+        let bob_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        alice_store.add_private_vid(alice.clone()).unwrap();
+        bob_store.add_private_vid(bob.clone()).unwrap();
+
+        let (url, sealed) = alice_store
+            .seal_message_to(alice.identifier(), bob.vid(), None, b"hello world")
+            .unwrap();
+        assert_eq!(url.as_str(), "tcp://127.0.0.1:1337");
+
+        let mut opened = sealed.clone();
+        let ReceivedTspMessage::GenericMessage {
+            sender, message, ..
+        } = bob_store
+            .open_message_from(alice.vid(), &mut opened)
+            .unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(&*message, b"hello world");
+
+        // neither store learned about the other VID
+        assert!(alice_store.get_verified_vid(bob.identifier()).is_err());
+        assert!(bob_store.get_verified_vid(alice.identifier()).is_err());
+
+        // the message has to come from the given sender
+        let mallory = new_vid();
+        assert!(matches!(
+            bob_store.open_message_from(mallory.vid(), &mut sealed.clone()),
+            Err(Error::Crypto(crate::crypto::CryptoError::UnexpectedSender))
+        ));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_audit_log() {
+        let store = Store::new();
+        let auditor = new_vid();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.set_audit_log(AuditLog::new(auditor.clone()));
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let (_, mut sealed) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+        let digest = crate::crypto::sha256(&sealed);
+        store.open_message(&mut sealed).unwrap();
+
+        store
+            .make_relationship_request(alice.identifier(), bob.identifier(), None)
+            .unwrap();
+
+        // bob is added again with new keys
+        let (signing_jwk, encryption_jwk) = new_vid().to_jwks();
+        let rotated = OwnedVid::from_jwks(
+            &signing_jwk,
+            &encryption_jwk,
+            bob.identifier(),
+            bob.endpoint().clone(),
+        )
+        .unwrap();
+        store.add_private_vid(rotated).unwrap();
+        store.forget_vid(bob.identifier()).unwrap();
+
+        let entries = store.audit_log().unwrap().entries();
+        let events = entries
+            .iter()
+            .map(|entry| entry.event.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 8);
+        assert_eq!(
+            events[..4],
+            [
+                AuditEvent::VidAdded {
+                    vid: alice.identifier().to_string()
+                },
+                AuditEvent::VidAdded {
+                    vid: bob.identifier().to_string()
+                },
+                AuditEvent::MessageSent {
+                    sender: alice.identifier().to_string(),
+                    receiver: bob.identifier().to_string(),
+                    digest,
+                },
+                AuditEvent::MessageReceived {
+                    sender: alice.identifier().to_string(),
+                    receiver: Some(bob.identifier().to_string()),
+                    digest,
+                },
+            ]
+        );
+        assert!(matches!(events[4], AuditEvent::MessageSent { .. }));
+        assert_eq!(
+            events[5..],
+            [
+                AuditEvent::RelationshipChanged {
+                    vid: bob.identifier().to_string(),
+                    from: RelationshipState::Unrelated,
+                    to: RelationshipState::Requested,
+                },
+                AuditEvent::KeyRotated {
+                    vid: bob.identifier().to_string()
+                },
+                AuditEvent::VidRemoved {
+                    vid: bob.identifier().to_string()
+                },
+            ]
+        );
+
+        crate::audit::verify(&entries, auditor.vid()).unwrap();
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_message_archive() {
+        let alice_store = Store::new();
+        let bob_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        alice_store.set_message_archive(MessageArchive::default());
+        bob_store.set_message_archive(MessageArchive::new(ArchiveConfig {
+            refuse_duplicates: true,
+            ..Default::default()
+        }));
+
+        alice_store.add_private_vid(alice.clone()).unwrap();
+        alice_store.add_verified_vid(bob.vid().clone()).unwrap();
+        bob_store.add_private_vid(bob.clone()).unwrap();
+        bob_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let (_, sealed) = alice_store
+            .seal_message(
+                alice.identifier(),
+                bob.identifier(),
+                Some(b"extra"),
+                b"hello",
+            )
+            .unwrap();
+        let ReceivedTspMessage::GenericMessage { digest, .. } =
+            bob_store.open_message(&mut sealed.clone()).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        let sent = alice_store
+            .message_archive()
+            .unwrap()
+            .get_by_digest(&digest)
+            .unwrap();
+        assert_eq!(sent.direction, Direction::Sent);
+        assert_eq!(sent.receiver.as_deref(), Some(bob.identifier()));
+        assert_eq!(sent.nonconfidential_data.as_deref(), Some(&b"extra"[..]));
+        assert_eq!(sent.message, b"hello");
+
+        let received = bob_store
+            .message_archive()
+            .unwrap()
+            .get_by_digest(&digest)
+            .unwrap();
+        assert_eq!(received.direction, Direction::Received);
+        assert_eq!(received.sender, alice.identifier());
+        assert_eq!(received.message, b"hello");
+
+        // the same message is only opened once
+        assert!(matches!(
+            bob_store.open_message(&mut sealed.clone()),
+            Err(Error::DuplicateMessage(sender)) if sender == alice.identifier()
+        ));
+
+        // a reply refers to the original message
+        let (_, mut reply) = bob_store
+            .seal_reply(bob.identifier(), alice.identifier(), None, &digest, b"hi")
+            .unwrap();
+        let ReceivedTspMessage::GenericMessage { in_reply_to, .. } =
+            alice_store.open_message(&mut reply).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        let original = alice_store
+            .message_archive()
+            .unwrap()
+            .get_by_digest(&in_reply_to.unwrap())
+            .unwrap();
+        assert_eq!(original.message, b"hello");
+        assert_eq!(alice_store.message_archive().unwrap().len(), 2);
+
+        // control messages are not recorded
+        alice_store
+            .make_relationship_request(alice.identifier(), bob.identifier(), None)
+            .unwrap();
+        assert_eq!(alice_store.message_archive().unwrap().len(), 2);
+
+        // the oldest messages are forgotten first
+        let archive = MessageArchive::new(ArchiveConfig {
+            max_entries: Some(1),
+            ..Default::default()
+        });
+        for message in alice_store.message_archive().unwrap().messages() {
+            assert!(archive.record(message));
+        }
+        assert_eq!(archive.len(), 1);
+        assert!(archive.get_by_digest(&digest).is_none());
+        assert_eq!(archive.messages()[0].message, b"hi");
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_tenants() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        let acme = store.tenant("acme");
+        acme.add_private_vid(alice.clone()).unwrap();
+        acme.add_verified_vid(bob.vid().clone()).unwrap();
+        store.tenant("globex").add_private_vid(bob.clone()).unwrap();
+
+        // every tenant only sees its own VIDs
+        assert!(store.list_vids().unwrap().is_empty());
+        assert!(store
+            .tenant("acme")
+            .has_private_vid(alice.identifier())
+            .unwrap());
+        assert!(!store
+            .tenant("acme")
+            .has_private_vid(bob.identifier())
+            .unwrap());
+        assert!(store
+            .tenant("globex")
+            .get_verified_vid(alice.identifier())
+            .is_err());
+        assert!(store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .is_err());
+
+        let (_, mut sealed) = store
+            .tenant("acme")
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+
+        // globex does not know alice yet
+        assert!(matches!(
+            store.tenant("globex").open_message(&mut sealed.clone()),
+            Err(Error::UnverifiedSource(..))
+        ));
+
+        store
+            .tenant("globex")
+            .add_verified_vid(alice.vid().clone())
+            .unwrap();
+        store.tenant("globex").open_message(&mut sealed).unwrap();
+
+        let mut tenants = store.tenants();
+        tenants.sort();
+        assert_eq!(tenants, ["acme", "globex"]);
+
+        assert!(store.remove_tenant("acme"));
+        assert!(!store.remove_tenant("acme"));
+        assert!(store.tenant("acme").list_vids().unwrap().is_empty());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_tenant_relationship_requirement() {
+        use super::RelationshipRequirement;
+
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        let acme = store.tenant("acme");
+        acme.add_private_vid(alice.clone()).unwrap();
+        acme.add_verified_vid(bob.vid().clone()).unwrap();
+
+        let globex = store.tenant("globex");
+        globex.add_private_vid(bob.clone()).unwrap();
+        globex.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let (_, sealed) = acme
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        globex.open_message(&mut sealed.clone()).unwrap();
+
+        // a tenant that already exists follows later changes of the store it belongs to
+        store
+            .set_relationship_requirement(RelationshipRequirement::Related)
+            .unwrap();
+        let mut opened = sealed.clone();
+        assert!(matches!(
+            globex.open_message(&mut opened),
+            Err(Error::NoRelationship(_))
+        ));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_relationship_requirement_allows() {
+        use super::RelationshipRequirement;
+
+        let bidirectional = RelationshipStatus::Bidirectional {
+            thread_id: Default::default(),
+            outstanding_nested_thread_ids: Vec::new(),
+            expires_at: None,
+        };
+        let unidirectional = RelationshipStatus::Unidirectional {
+            thread_id: Default::default(),
+            expires_at: None,
+        };
+
+        assert!(RelationshipRequirement::None.allows(&RelationshipStatus::Unrelated));
+        assert!(RelationshipRequirement::Related.allows(&unidirectional));
+        assert!(!RelationshipRequirement::Related.allows(&RelationshipStatus::Unrelated));
+
+        // only an accepted relationship in both directions will do
+        assert!(RelationshipRequirement::Bidirectional.allows(&bidirectional));
+        assert!(!RelationshipRequirement::Bidirectional.allows(&unidirectional));
+        assert!(!RelationshipRequirement::Bidirectional.allows(&RelationshipStatus::_Controlled));
+        assert!(!RelationshipRequirement::Bidirectional.allows(&RelationshipStatus::Unrelated));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_vid_stats() {
+        let alice_db = Store::new();
+        let bob_db = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        alice_db.add_private_vid(alice.clone()).unwrap();
+        alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+        bob_db.add_private_vid(bob.clone()).unwrap();
+        bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+
+        assert!(alice_db.get_vid_stats(bob.identifier()).unwrap().is_empty());
+
+        for _ in 0..2 {
+            let (_, mut sealed) = alice_db
+                .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+                .unwrap();
+            bob_db.open_message(&mut sealed).unwrap();
+        }
+
+        let sent = alice_db.get_vid_stats(bob.identifier()).unwrap();
+        assert_eq!(sent.messages_sent, 2);
+        assert_eq!(sent.messages_received, 0);
+        assert_eq!(sent.last_received, None);
+
+        let received = bob_db.get_vid_stats(alice.identifier()).unwrap();
+        assert_eq!(received.messages_sent, 0);
+        assert_eq!(received.messages_received, 2);
+        assert!(received.last_received.is_some());
+
+        // the statistics survive an export
+        let restored = Store::new();
+        restored.import(bob_db.export().unwrap()).unwrap();
+        assert_eq!(restored.wallet_summary(), bob_db.wallet_summary());
+        assert_eq!(restored.wallet_summary()[alice.identifier()], received);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_open_seal_with_headers() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let headers = crate::MessageHeaders::new()
+            .with_content_type(crate::definitions::content_type::TEXT)
+            .with_timestamp(1_700_000_000);
+        let nonconfidential_data = headers.to_bytes().unwrap();
+
+        let (_, mut sealed) = store
+            .seal_message(
+                alice.identifier(),
+                bob.identifier(),
+                Some(&nonconfidential_data),
+                b"hello world",
+            )
+            .unwrap();
+
+        let received = store.open_message(&mut sealed).unwrap();
+
+        assert_eq!(received.headers(), Some(headers));
+
+        let ReceivedTspMessage::GenericMessage { content_type, .. } = received else {
+            panic!("expected a generic message");
+        };
+        assert_eq!(content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_encrypted_export() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.vid().clone()).unwrap();
+
+        let backup = store.export_encrypted("correct horse").unwrap();
+
+        let restored = Store::new();
+        assert!(matches!(
+            restored.import_encrypted(&backup, "battery staple"),
+            Err(Error::InvalidBackup(_))
+        ));
+
+        let mut tampered = backup.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            restored.import_encrypted(&tampered, "correct horse"),
+            Err(Error::InvalidBackup(_))
+        ));
+
+        restored.import_encrypted(&backup, "correct horse").unwrap();
+        assert!(restored.has_private_vid(alice.identifier()).unwrap());
+        assert!(!restored.has_private_vid(bob.identifier()).unwrap());
+        assert_eq!(restored.list_vids().unwrap().len(), 2);
+    }
+
+    /// A VID type with a field the store does not know about
+    #[cfg(feature = "serialize")]
+    struct TaggedVid<V> {
+        vid: V,
+        tag: String,
+    }
+
+    #[cfg(feature = "serialize")]
+    impl<V: VerifiedVid> VerifiedVid for TaggedVid<V> {
+        fn identifier(&self) -> &str {
+            self.vid.identifier()
+        }
+
+        fn endpoint(&self) -> &url::Url {
+            self.vid.endpoint()
+        }
+
+        fn verifying_key(&self) -> &crate::definitions::PublicVerificationKeyData {
+            self.vid.verifying_key()
+        }
+
+        fn encryption_key(&self) -> &crate::definitions::PublicKeyData {
+            self.vid.encryption_key()
+        }
+
+        fn custom_fields(&self) -> Option<CustomFields> {
+            Some(CustomFields {
+                codec: "tagged".to_string(),
+                data: serde_json::json!({ "tag": self.tag }),
+            })
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    impl<V: PrivateVid> PrivateVid for TaggedVid<V> {
+        fn decryption_key(&self) -> &crate::definitions::PrivateKeyData {
+            self.vid.decryption_key()
+        }
+
+        fn signing_key(&self) -> &crate::definitions::PrivateSigningKeyData {
+            self.vid.signing_key()
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    struct TaggedCodec;
+
+    #[cfg(feature = "serialize")]
+    impl VidCodec for TaggedCodec {
+        fn decode_verified(
+            &self,
+            vid: Vid,
+            data: &serde_json::Value,
+        ) -> Result<Arc<dyn VerifiedVid>, VidError> {
+            Ok(Arc::new(TaggedVid {
+                vid,
+                tag: data["tag"].as_str().unwrap().to_string(),
+            }))
+        }
+
+        fn decode_private(
+            &self,
+            vid: OwnedVid,
+            data: &serde_json::Value,
+        ) -> Result<Arc<dyn PrivateVid>, VidError> {
+            Ok(Arc::new(TaggedVid {
+                vid,
+                tag: data["tag"].as_str().unwrap().to_string(),
+            }))
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_custom_vid_export() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store
+            .add_private_vid(TaggedVid {
+                vid: alice.clone(),
+                tag: "own".to_string(),
+            })
+            .unwrap();
+        store
+            .add_verified_vid(TaggedVid {
+                vid: bob.vid().clone(),
+                tag: "peer".to_string(),
+            })
+            .unwrap();
+
+        let backup = store.export_encrypted("correct horse").unwrap();
+
+        // the custom fields cannot be restored without the codec
+        assert!(matches!(
+            Store::new().import_encrypted(&backup, "correct horse"),
+            Err(Error::Vid(VidError::UnknownCodec(codec))) if codec == "tagged"
+        ));
+
+        let restored = Store::new();
+        restored.register_vid_codec("tagged", TaggedCodec);
+        restored.import_encrypted(&backup, "correct horse").unwrap();
+
+        let tag = |vid: Arc<dyn VerifiedVid>| vid.custom_fields().unwrap().data["tag"].clone();
+        assert_eq!(
+            tag(restored.get_verified_vid(alice.identifier()).unwrap()),
+            "own"
+        );
+        assert_eq!(
+            tag(restored.get_verified_vid(bob.identifier()).unwrap()),
+            "peer"
+        );
+        assert!(restored.has_private_vid(alice.identifier()).unwrap());
+        assert_eq!(
+            restored
+                .get_private_vid(alice.identifier())
+                .unwrap()
+                .custom_fields(),
+            store
+                .get_vid(alice.identifier())
+                .unwrap()
+                .vid
+                .custom_fields()
+        );
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_multipart_message() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let control = br#"{"action":"upload"}"#;
+        let blob = [0u8, 1, 2, 3, 255];
+
+        let (_, mut sealed) = a_store
+            .seal_message_multipart(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                &[("application/json", control), ("image/png", &blob)],
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            message,
+            segments,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+
+        assert_eq!(sender, alice.identifier());
+        assert!(message.is_empty());
+        assert_eq!(
+            segments,
+            vec![
+                ("application/json".to_string(), Cow::from(&control[..])),
+                ("image/png".to_string(), Cow::from(&blob[..])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_clones() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let store = store.clone();
+                let (alice, bob) = (&alice, &bob);
+
+                scope.spawn(move || {
+                    for _ in 0..8 {
+                        let (_, mut sealed) = store
+                            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+                            .unwrap();
+                        store.open_message(&mut sealed).unwrap();
+                    }
+                });
+            }
+
+            // a VID added through one clone is visible in all clones
+            store.clone().add_private_vid(new_vid()).unwrap();
+        });
+
+        assert_eq!(store.list_vids().unwrap().len(), 3);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_reply_message() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let mut digest = Default::default();
+        let mut sealed = crate::crypto::seal_and_hash(
+            &alice,
+            bob.vid(),
+            None,
+            crate::definitions::Payload::Content(b"ping"),
+            Some(&mut digest),
+        )
+        .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            in_reply_to,
+            digest: received_digest,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(in_reply_to, None);
+        assert_eq!(received_digest, digest);
+
+        let (_, mut reply) = b_store
+            .seal_reply(bob.identifier(), alice.identifier(), None, &digest, b"pong")
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            message,
+            in_reply_to,
+            ..
+        } = a_store.open_message(&mut reply).unwrap()
+        else {
+            panic!()
+        };
+
+        assert_eq!(sender, bob.identifier());
+        assert_eq!(&*message, b"pong");
+        assert_eq!(in_reply_to, Some(digest));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_digest_agility() {
+        use crate::definitions::DigestAlgorithm;
+
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let other = match DigestAlgorithm::default() {
+            DigestAlgorithm::Sha2_256 => DigestAlgorithm::Blake2b256,
+            DigestAlgorithm::Blake2b256 => DigestAlgorithm::Sha2_256,
+        };
+
+        // alice and bob agree on the other algorithm, so their digests match
+        a_store
+            .set_digest_algorithm_for_vid(bob.identifier(), Some(other))
+            .unwrap();
+        b_store
+            .set_digest_algorithm_for_vid(alice.identifier(), Some(other))
+            .unwrap();
+
+        let mut digest = Default::default();
+        let (_, mut sealed) = a_store
+            .seal_message_payload_and_hash(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                Payload::Content(b"ping"),
+                Some(&mut digest),
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            digest: received_digest,
+            message_type,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(received_digest, digest);
+        assert_eq!(message_type.digest_algorithm, other);
+
+        // a reply carries the algorithm of its digest, which bob adopts
+        b_store
+            .set_digest_algorithm_for_vid(alice.identifier(), None)
+            .unwrap();
+
+        let (_, mut reply) = a_store
+            .seal_reply(alice.identifier(), bob.identifier(), None, &digest, b"pong")
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            in_reply_to,
+            message_type,
+            ..
+        } = b_store.open_message(&mut reply).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(in_reply_to, Some(digest));
+        assert_eq!(message_type.digest_algorithm, other);
+        assert_eq!(
+            b_store.get_digest_algorithm(alice.identifier()).unwrap(),
+            other
+        );
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    #[cfg(not(feature = "pq"))]
+    fn test_cipher_suite_per_vid() {
+        use crate::{CipherSuite, SealOptions};
+
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let other = match CipherSuite::default() {
+            CipherSuite::Hpke => CipherSuite::Nacl,
+            CipherSuite::Nacl => CipherSuite::Hpke,
+        };
+
+        a_store
+            .set_cipher_suite_for_vid(bob.identifier(), Some(other))
+            .unwrap();
+        assert_eq!(a_store.get_cipher_suite(bob.identifier()).unwrap(), other);
+
+        let (_, mut sealed) = a_store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"ping")
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage { message_type, .. } =
+            b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(CipherSuite::of(&message_type.crypto_type), Some(other));
+        assert_eq!(message_type.digest_algorithm, other.digest_algorithm());
+
+        // bob follows the suite alice sent with, and keeps it when exported
+        assert_eq!(b_store.get_cipher_suite(alice.identifier()).unwrap(), other);
+
+        let restored = Store::new();
+        restored.import(b_store.export().unwrap()).unwrap();
+        assert_eq!(
+            restored.get_cipher_suite(alice.identifier()).unwrap(),
+            other
+        );
+
+        let (_, mut reply) = restored
+            .seal_message(bob.identifier(), alice.identifier(), None, b"pong")
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage { message_type, .. } =
+            a_store.open_message(&mut reply).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(CipherSuite::of(&message_type.crypto_type), Some(other));
+
+        // a per-message choice takes precedence over that of the relationship
+        let (_, mut sealed) = a_store
+            .seal_message_with_options(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                b"ping",
+                SealOptions {
+                    cipher_suite: Some(CipherSuite::default()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage { message_type, .. } =
+            b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(
+            CipherSuite::of(&message_type.crypto_type),
+            Some(CipherSuite::default())
+        );
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_provenance() {
+        use crate::vid::Provenance;
+
+        let store = Store::new();
+        let bob = new_vid();
+        let carol = new_vid();
+        let dave = new_vid();
+
+        for vid in [&bob, &carol, &dave] {
+            store.add_verified_vid(vid.vid().clone()).unwrap();
+        }
+
+        let referral = |sender: &OwnedVid| Provenance::Referral {
+            sender: sender.identifier().to_string(),
+        };
+        store
+            .set_provenance(carol.identifier(), referral(&bob))
+            .unwrap();
+        store
+            .set_provenance(dave.identifier(), referral(&carol))
+            .unwrap();
+
+        assert_eq!(
+            store.provenance(bob.identifier()).unwrap(),
+            Some(Provenance::Manual)
+        );
+        assert_eq!(
+            store.introducers(dave.identifier()).unwrap(),
+            [carol.identifier(), bob.identifier()]
+        );
+        assert_eq!(
+            store.introduced_by(bob.identifier()),
+            [carol.identifier(), dave.identifier()]
+        );
+        assert_eq!(store.introduced_by(carol.identifier()), [dave.identifier()]);
+        assert!(store.introduced_by(dave.identifier()).is_empty());
+
+        // introductions may go round in circles
+        store
+            .set_provenance(bob.identifier(), referral(&dave))
+            .unwrap();
+        assert_eq!(
+            store.introducers(bob.identifier()).unwrap(),
+            [dave.identifier(), carol.identifier()]
+        );
+        assert_eq!(
+            store.introduced_by(bob.identifier()),
+            [carol.identifier(), dave.identifier()]
+        );
+
+        // the provenance is kept with the VID
+        let restored = Store::new();
+        restored.import(store.export().unwrap()).unwrap();
+        assert_eq!(
+            restored.provenance(carol.identifier()).unwrap(),
+            Some(referral(&bob))
+        );
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_hide_identity() {
+        use crate::{cesr::CryptoType, SealOptions};
+
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let nested_alice = new_vid();
+        let nested_bob = new_vid();
+
+        for vid in [&alice, &bob, &nested_alice, &nested_bob] {
+            store.add_private_vid(vid.clone()).unwrap();
+        }
+        store
+            .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(nested_bob.identifier(), Some(nested_alice.identifier()))
+            .unwrap();
+
+        let hidden = SealOptions {
+            hide_identity: true,
+            ..Default::default()
+        };
+
+        // a direct message from a nested VID exposes it
+        assert!(store
+            .seal_message(nested_alice.identifier(), bob.identifier(), None, b"hello")
+            .is_ok());
+        assert!(matches!(
+            store.seal_message_with_options(
+                nested_alice.identifier(),
+                bob.identifier(),
+                None,
+                b"hello",
+                hidden,
+            ),
+            Err(Error::IdentityLeak(vid)) if vid == nested_alice.identifier()
+        ));
+
+        // wrapped in a message of its parent, it is hidden
+        assert!(store
+            .seal_message_with_options(
+                nested_alice.identifier(),
+                nested_bob.identifier(),
+                None,
+                b"hello",
+                hidden,
+            )
+            .is_ok());
+
+        store.set_hide_identities(true);
+        assert!(matches!(
+            store.seal_message(nested_alice.identifier(), bob.identifier(), None, b"hello"),
+            Err(Error::IdentityLeak(_))
+        ));
+        assert!(matches!(
+            store.sign_message(nested_alice.identifier(), bob.identifier(), b"hello"),
+            Err(Error::IdentityLeak(_))
+        ));
+
+        // the sender of other messages is encrypted along with the payload
+        let (_, mut sealed) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        let ReceivedTspMessage::GenericMessage { message_type, .. } =
+            store.open_message(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert!(matches!(
+            message_type.crypto_type,
+            CryptoType::HpkeEssr | CryptoType::NaclEssr
+        ));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_compression() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let message = br#"{"hello":"world"}"#.repeat(100);
+        let options = SealOptions {
+            compress_above: Some(1024),
+            ..Default::default()
+        };
+
+        let (_, mut compressed) = a_store
+            .seal_message_with_options(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                &message,
+                options,
+            )
+            .unwrap();
+        let (_, uncompressed) = a_store
+            .seal_message(alice.identifier(), bob.identifier(), None, &message)
+            .unwrap();
+        assert!(compressed.len() < uncompressed.len());
+
+        // the content is decompressed when the message is opened
+        let mut opened = compressed.clone();
+        let ReceivedTspMessage::GenericMessage {
+            message: received,
+            message_type,
+            ..
+        } = b_store.open_message(&mut opened).unwrap()
+        else {
+            panic!()
+        };
+        assert!(matches!(received, Cow::Owned(_)));
+        assert_eq!(received, message);
+        assert!(!message_type.compressed);
+
+        let ReceivedTspMessage::GenericMessage {
+            message: received,
+            message_type,
+            ..
+        } = b_store.open_message_owned(&mut compressed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(received, message);
+        assert!(!message_type.compressed);
+
+        // messages below the threshold are sent as is
+        let (_, mut sealed) = a_store
+            .seal_message_with_options(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                b"hello",
+                options,
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            message,
+            message_type,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert!(matches!(message, Cow::Borrowed(b"hello")));
+        assert!(!message_type.compressed);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_acknowledgement() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        let mut digest = Default::default();
+        let mut sealed = crate::crypto::seal_and_hash(
+            &alice,
+            bob.vid(),
+            None,
+            crate::definitions::Payload::Content(b"hello"),
+            Some(&mut digest),
+        )
+        .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            digest: received_digest,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+
+        let (_, mut ack) = b_store
+            .make_ack(bob.identifier(), alice.identifier(), &received_digest)
+            .unwrap();
+
+        let ReceivedTspMessage::Acknowledgement {
+            sender,
+            digest: acknowledged,
+        } = a_store.open_message(&mut ack).unwrap()
+        else {
+            panic!()
+        };
+
+        assert_eq!(sender, bob.identifier());
+        assert_eq!(acknowledged, digest);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_group_messages() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let carol = new_vid();
+
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        a_store.add_verified_vid(carol.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
+
+        a_store
+            .create_group("book club", alice.identifier())
+            .unwrap();
+        assert!(a_store
+            .create_group("book club", alice.identifier())
+            .is_err());
+
+        // bob learns that he joined
+        let mut notices = a_store
+            .add_group_member("book club", bob.identifier())
+            .unwrap();
+        assert_eq!(notices.len(), 1);
+        let ReceivedTspMessage::GroupMembership {
+            sender,
+            group,
+            member,
+            change,
+        } = b_store.open_message(&mut notices[0].1).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(group, "book club");
+        assert_eq!(member, bob.identifier());
+        assert_eq!(change, MembershipChange::Added);
+
+        // bob learns about carol, and carol about bob and herself
+        let notices = a_store
+            .add_group_member("book club", carol.identifier())
+            .unwrap();
+        assert_eq!(notices.len(), 3);
+        assert!(a_store
+            .add_group_member("book club", carol.identifier())
+            .is_err());
+
+        let group = a_store.get_group("book club").unwrap();
+        assert_eq!(group.owner(), alice.identifier());
+        assert_eq!(group.members(), [bob.identifier(), carol.identifier()]);
+
+        let mut messages = a_store
+            .seal_group_message("book club", None, b"hello everyone")
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        let ReceivedTspMessage::GroupMessage { group, message, .. } =
+            b_store.open_message(&mut messages[0].1).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(group, "book club");
+        assert_eq!(&*message, b"hello everyone");
+
+        // the remaining member and the removed member are notified
+        let mut notices = a_store
+            .remove_group_member("book club", bob.identifier())
+            .unwrap();
+        assert_eq!(notices.len(), 2);
+        assert!(matches!(
+            b_store.open_message(&mut notices[1].1).unwrap(),
+            ReceivedTspMessage::GroupMembership {
+                change: MembershipChange::Removed,
+                ..
+            }
+        ));
+        assert_eq!(
+            a_store.get_group("book club").unwrap().members(),
+            [carol.identifier()]
+        );
+
+        a_store.remove_group("book club").unwrap();
+        assert!(a_store
+            .seal_group_message("book club", None, b"anyone?")
+            .is_err());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_ephemeral_vid() {
+        let store = Store::new();
+        let alice = new_vid();
+        let inquiry = OwnedVid::new_ephemeral(
+            "tcp://127.0.0.1:1337".parse().unwrap(),
+            std::time::Duration::from_secs(60),
+        );
+        let expired = OwnedVid::new_ephemeral(
+            "tcp://127.0.0.1:1337".parse().unwrap(),
+            std::time::Duration::ZERO,
+        );
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(inquiry.clone()).unwrap();
+        store.add_private_vid(expired.clone()).unwrap();
+
+        // one-shot VIDs are never exported
+        assert_eq!(store.export().unwrap().len(), 1);
+        assert!(store
+            .take_changes()
+            .vids
+            .iter()
+            .all(|vid| vid.id == alice.identifier()));
+
+        assert_eq!(
+            store.forget_expired_vids().unwrap(),
+            vec![expired.identifier().to_string()]
+        );
+        assert!(!store.has_private_vid(expired.identifier()).unwrap());
+
+        // the reply to the inquiry is opened once
+        let (_, mut reply) = store
+            .seal_message(alice.identifier(), inquiry.identifier(), None, b"reply")
+            .unwrap();
+        let mut replayed = reply.clone();
 
-    use crate::{OwnedVid, ReceivedTspMessage, Store, VerifiedVid};
+        let ReceivedTspMessage::GenericMessage { message, .. } =
+            store.open_message(&mut reply).unwrap()
+        else {
+            panic!("expected a generic message");
+        };
+        assert_eq!(&*message, b"reply");
 
-    fn new_vid() -> OwnedVid {
-        OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap())
+        assert!(!store.has_private_vid(inquiry.identifier()).unwrap());
+        assert!(store.open_message(&mut replayed).is_err());
     }
 
     #[test]
     #[wasm_bindgen_test]
-    fn test_add_private_vid() {
+    fn test_relationship_graph() {
+        use crate::{
+            audit::RelationshipState,
+            graph::{EdgeKind, Node},
+        };
+
         let store = Store::new();
-        let vid = new_vid();
+        let alice = new_vid();
+        let bob = new_vid();
+        let carol = new_vid();
+        let nested = new_vid();
 
-        store.add_private_vid(vid.clone()).unwrap();
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.vid().clone()).unwrap();
+        store.add_verified_vid(carol.vid().clone()).unwrap();
+        store.add_verified_vid(nested.vid().clone()).unwrap();
 
-        assert!(store.has_private_vid(vid.identifier()).unwrap());
+        store
+            .make_relationship_request(alice.identifier(), bob.identifier(), None)
+            .unwrap();
+        store
+            .set_relation_for_vid(bob.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_parent_for_vid(nested.identifier(), Some(carol.identifier()))
+            .unwrap();
+        store
+            .set_route_for_vid(carol.identifier(), [bob.identifier(), carol.identifier()])
+            .unwrap();
+
+        let graph = store.relationship_graph();
+
+        let node = |vid: &OwnedVid| graph.nodes.iter().find(|node| node.vid == vid.identifier());
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(node(&alice).unwrap().private);
+        assert_eq!(
+            node(&bob),
+            Some(&Node {
+                vid: bob.identifier().to_string(),
+                private: false,
+                nested: false,
+                relationship: RelationshipState::Requested,
+            })
+        );
+        assert!(node(&nested).unwrap().nested);
+
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str(), edge.kind))
+            .collect();
+        assert_eq!(edges.len(), 4);
+        assert!(edges.contains(&(
+            alice.identifier(),
+            bob.identifier(),
+            EdgeKind::Relationship {
+                state: RelationshipState::Requested
+            }
+        )));
+        assert!(edges.contains(&(nested.identifier(), carol.identifier(), EdgeKind::Parent)));
+        assert!(edges.contains(&(
+            carol.identifier(),
+            bob.identifier(),
+            EdgeKind::Route { hop: 0 }
+        )));
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph tsp {"));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [label=\"parent\", style=dotted];",
+            nested.identifier(),
+            carol.identifier()
+        )));
     }
 
     #[test]
     #[wasm_bindgen_test]
-    fn test_add_verified_vid() {
-        let store = Store::new();
-        let owned_vid = new_vid();
+    fn test_transport_override() {
+        let a_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
 
-        store.add_verified_vid(owned_vid.vid().clone()).unwrap();
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
 
-        assert!(store.get_verified_vid(owned_vid.identifier()).is_ok());
+        let tunnel: url::Url = "tcp://127.0.0.1:4444".parse().unwrap();
+        a_store
+            .set_transport_override(bob.identifier(), Some(tunnel.clone()))
+            .unwrap();
+
+        let (url, _) = a_store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        assert_eq!(url, tunnel);
+
+        let (url, _) = a_store
+            .make_relationship_request(alice.identifier(), bob.identifier(), None)
+            .unwrap();
+        assert_eq!(url, tunnel);
+
+        // the override is kept in an export
+        let c_store = Store::new();
+        c_store.import(a_store.export().unwrap()).unwrap();
+        let (url, _) = c_store
+            .sign_message(alice.identifier(), bob.identifier(), b"hello")
+            .unwrap();
+        assert_eq!(url, tunnel);
+
+        a_store
+            .set_transport_override(bob.identifier(), None)
+            .unwrap();
+        let (url, _) = a_store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        assert_eq!(&url, bob.endpoint());
     }
 
     #[test]
     #[wasm_bindgen_test]
-    fn test_remove() {
-        let store = Store::new();
-        let vid = new_vid();
+    fn test_seal_message_into() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
 
-        store.add_private_vid(vid.clone()).unwrap();
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
 
-        assert!(store.has_private_vid(vid.identifier()).unwrap());
+        let mut buffer = Vec::new();
 
-        store.forget_vid(vid.identifier()).unwrap();
+        for message in [&b"hello world"[..], b"a second, somewhat longer message"] {
+            buffer.clear();
 
-        assert!(!store.has_private_vid(vid.identifier()).unwrap());
+            let url = a_store
+                .seal_message_into(
+                    alice.identifier(),
+                    bob.identifier(),
+                    None,
+                    message,
+                    &mut buffer,
+                )
+                .unwrap();
+            assert_eq!(&url, bob.endpoint());
+
+            let ReceivedTspMessage::GenericMessage {
+                sender,
+                message: received,
+                ..
+            } = b_store.open_message(&mut buffer).unwrap()
+            else {
+                panic!()
+            };
+
+            assert_eq!(sender, alice.identifier());
+            assert_eq!(received, message);
+        }
+
+        // a failed seal leaves the buffer untouched
+        buffer.clear();
+        buffer.extend_from_slice(b"prefix");
+        assert!(a_store
+            .seal_message_into(alice.identifier(), "did:unknown", None, b"hi", &mut buffer)
+            .is_err());
+        assert_eq!(buffer, b"prefix");
     }
 
     #[test]
     #[wasm_bindgen_test]
-    fn test_open_seal() {
-        let store = Store::new();
+    fn test_resource_limits() {
+        let config = StoreConfig {
+            max_payload_size: 16,
+            max_hops: 2,
+            max_nested_depth: 1,
+        };
+        let a_store = Store::new();
+        let b_store = Store::with_config(config);
         let alice = new_vid();
         let bob = new_vid();
 
-        store.add_private_vid(alice.clone()).unwrap();
-        store.add_private_vid(bob.clone()).unwrap();
+        a_store.add_private_vid(alice.clone()).unwrap();
+        a_store.add_verified_vid(bob.vid().clone()).unwrap();
+        b_store.add_private_vid(bob.clone()).unwrap();
+        b_store.add_verified_vid(alice.vid().clone()).unwrap();
 
-        let message = b"hello world";
+        let message = b"a message that is too large";
 
-        let (url, mut sealed) = store
+        assert!(matches!(
+            b_store.seal_message(bob.identifier(), alice.identifier(), None, message),
+            Err(Error::PayloadTooLarge(27, 16))
+        ));
+
+        let (_, mut sealed) = a_store
             .seal_message(alice.identifier(), bob.identifier(), None, message)
             .unwrap();
 
-        assert_eq!(url.as_str(), "tcp://127.0.0.1:1337");
+        assert!(matches!(
+            b_store.open_message(&mut sealed),
+            Err(Error::PayloadTooLarge(27, 16))
+        ));
 
-        let received = store.open_message(&mut sealed).unwrap();
+        let hops = [new_vid(), new_vid(), new_vid()];
+        let route = hops.iter().map(|vid| vid.identifier()).collect::<Vec<_>>();
+        b_store
+            .set_route_for_vid(alice.identifier(), &route)
+            .unwrap();
 
-        if let ReceivedTspMessage::GenericMessage {
-            sender,
-            message: received_message,
-            message_type,
-            ..
-        } = received
-        {
-            assert_eq!(sender, alice.identifier());
-            assert_eq!(received_message, message);
-            assert_ne!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
-            assert_ne!(
-                message_type.signature_type,
-                crate::cesr::SignatureType::NoSignature
-            );
-        } else {
-            panic!("unexpected message type");
-        }
+        assert!(matches!(
+            b_store.seal_message(bob.identifier(), alice.identifier(), None, b"hello"),
+            Err(Error::TooManyHops(3, 2))
+        ));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_clones_share_settings() {
+        let store = Store::new();
+        let clone = store.clone();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.vid().clone()).unwrap();
+
+        // settings changed after cloning apply to the clone as well
+        store.set_config(StoreConfig {
+            max_payload_size: 16,
+            ..Default::default()
+        });
+        assert_eq!(clone.config(), store.config());
+
+        assert!(matches!(
+            clone.seal_message(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                b"a message that is too large"
+            ),
+            Err(Error::PayloadTooLarge(27, 16))
+        ));
+
+        // a new tenant starts out with the settings of its store
+        let tenant = clone.tenant("tenant");
+        store.set_config(StoreConfig::default());
+        assert_eq!(tenant.config().max_payload_size, 16);
     }
 
     #[test]
@@ -1286,6 +5652,95 @@ mod test {
         assert_eq!(sender, bob.identifier());
     }
 
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_session_key() {
+        use crate::cesr::CryptoType;
+
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        // sessions need a relationship
+        assert!(store
+            .make_session_key(alice.identifier(), bob.identifier())
+            .is_err());
+
+        let (_, mut sealed) = store
+            .make_relationship_request(alice.identifier(), bob.identifier(), None)
+            .unwrap();
+        let ReceivedTspMessage::RequestRelationship { thread_id, .. } =
+            store.open_message(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        let (_, mut sealed) = store
+            .make_relationship_accept(bob.identifier(), alice.identifier(), thread_id, None)
+            .unwrap();
+        store.open_message(&mut sealed).unwrap();
+
+        let (_, mut sealed) = store
+            .make_session_key(alice.identifier(), bob.identifier())
+            .unwrap();
+        let ReceivedTspMessage::SessionEstablished { sender } =
+            store.open_message(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert!(!store.needs_new_session(alice.identifier(), bob.identifier()));
+
+        let crypto_type = |message: ReceivedTspMessage<Cow<[u8]>>| match message {
+            ReceivedTspMessage::GenericMessage {
+                message,
+                message_type,
+                ..
+            } => {
+                assert_eq!(&*message, b"hello world");
+                message_type.crypto_type
+            }
+            _ => panic!("unexpected message type"),
+        };
+
+        let (_, mut first) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+        let (_, mut second) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+
+        // messages that arrive out of order can be opened, but only once
+        let mut replayed = second.clone();
+        let received = store.open_message(&mut second).unwrap();
+        assert_eq!(crypto_type(received), CryptoType::Session);
+        let received = store.open_message(&mut first).unwrap();
+        assert_eq!(crypto_type(received), CryptoType::Session);
+        assert!(store.open_message(&mut replayed).is_err());
+
+        // the session only covers messages from alice to bob
+        let (_, mut sealed) = store
+            .seal_message(bob.identifier(), alice.identifier(), None, b"hello world")
+            .unwrap();
+        let received = store.open_message(&mut sealed).unwrap();
+        assert_ne!(crypto_type(received), CryptoType::Session);
+
+        // a session ends with the relationship
+        let (_, mut sealed) = store
+            .make_relationship_cancel(alice.identifier(), bob.identifier())
+            .unwrap();
+        store.open_message(&mut sealed).unwrap();
+
+        let (_, mut sealed) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+            .unwrap();
+        let received = store.open_message(&mut sealed).unwrap();
+        assert_ne!(crypto_type(received), CryptoType::Session);
+        assert!(!store.needs_new_session(alice.identifier(), bob.identifier()));
+    }
+
     #[test]
     #[wasm_bindgen_test]
     fn test_make_relationship_cancel() {
@@ -1333,10 +5788,248 @@ mod test {
         assert_eq!(url.as_str(), "tcp://127.0.0.1:1337");
         let received = store.open_message(&mut sealed).unwrap();
 
-        let ReceivedTspMessage::CancelRelationship { sender, .. } = received else {
+        let ReceivedTspMessage::CancelRelationship { sender, .. } = received else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, bob.identifier());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_cleanup_relationship() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let bob_nested = new_vid();
+        let alice_nested = new_vid();
+        let carol = new_vid();
+        let dave = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.vid().clone()).unwrap();
+        store
+            .set_relation_and_status_for_vid(
+                bob.identifier(),
+                RelationshipStatus::Bidirectional {
+                    thread_id: Default::default(),
+                    outstanding_nested_thread_ids: vec![],
+                    expires_at: None,
+                },
+                alice.identifier(),
+            )
+            .unwrap();
+
+        // a nested relationship inside the one with bob
+        store.add_verified_vid(bob_nested.vid().clone()).unwrap();
+        store
+            .set_parent_for_vid(bob_nested.identifier(), Some(bob.identifier()))
+            .unwrap();
+        store.add_private_vid(alice_nested.clone()).unwrap();
+        store
+            .set_parent_for_vid(alice_nested.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(alice_nested.identifier(), Some(bob_nested.identifier()))
+            .unwrap();
+
+        // carol is routed through bob, dave is sent to from the nested VID
+        store.add_verified_vid(carol.vid().clone()).unwrap();
+        store
+            .set_route_for_vid(carol.identifier(), [bob.identifier(), carol.identifier()])
+            .unwrap();
+        store.add_verified_vid(dave.vid().clone()).unwrap();
+        store
+            .set_relation_for_vid(dave.identifier(), Some(alice_nested.identifier()))
+            .unwrap();
+
+        let mut nested_vids = vec![
+            bob_nested.identifier().to_string(),
+            alice_nested.identifier().to_string(),
+        ];
+        nested_vids.sort();
+        let expected = RelationshipCleanup {
+            nested_vids,
+            routes: vec![carol.identifier().to_string()],
+            relations: vec![dave.identifier().to_string()],
+        };
+
+        // a dry run changes nothing
+        let cleanup = store.cleanup_relationship(bob.identifier(), true).unwrap();
+        assert_eq!(cleanup, expected);
+        assert!(store.get_vid(bob_nested.identifier()).is_ok());
+        assert!(store
+            .get_vid(carol.identifier())
+            .unwrap()
+            .get_route()
+            .is_some());
+
+        // cancelling the relationship removes everything that depends on it
+        store
+            .make_relationship_cancel(alice.identifier(), bob.identifier())
+            .unwrap();
+
+        assert!(store.get_vid(bob_nested.identifier()).is_err());
+        assert!(store.get_vid(alice_nested.identifier()).is_err());
+        assert!(store.get_vid(bob.identifier()).is_ok());
+        assert!(store
+            .get_vid(carol.identifier())
+            .unwrap()
+            .get_route()
+            .is_none());
+        assert_eq!(
+            store.get_vid(dave.identifier()).unwrap().get_relation_vid(),
+            None
+        );
+        assert!(store
+            .cleanup_relationship(bob.identifier(), true)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_relationship_expiry() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        let expires_at = |vid: &str| store.get_vid(vid).unwrap().relation_status.expires_at();
+
+        // alice asks for a relationship that lasts an hour
+        let (_, mut sealed) = store
+            .make_relationship_request_with_ttl(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                std::time::Duration::from_secs(3600),
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::RequestRelationship { thread_id, .. } =
+            store.open_message(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+
+        let (_, mut sealed) = store
+            .make_relationship_accept(bob.identifier(), alice.identifier(), thread_id, None)
+            .unwrap();
+        store.open_message(&mut sealed).unwrap();
+
+        let requested = expires_at(bob.identifier()).unwrap();
+        assert!(requested > super::now());
+
+        // alice extends the relationship, bob takes over the new expiry
+        let (_, mut sealed) = store
+            .make_relationship_renew(
+                alice.identifier(),
+                bob.identifier(),
+                std::time::Duration::from_secs(7200),
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::RenewRelationship {
+            sender,
+            thread_id: renewed,
+            expires_at: renewed_until,
+        } = store.open_message(&mut sealed).unwrap()
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(renewed, thread_id);
+        assert!(renewed_until > requested);
+        assert_eq!(expires_at(bob.identifier()), Some(renewed_until));
+        assert_eq!(expires_at(alice.identifier()), Some(renewed_until));
+
+        assert!(store.expire_relationships().unwrap().is_empty());
+
+        // a relationship without time left ends at the next sweep
+        store
+            .make_relationship_request_with_ttl(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                std::time::Duration::ZERO,
+            )
+            .unwrap();
+
+        assert_eq!(
+            store.expire_relationships().unwrap(),
+            [bob.identifier().to_string()]
+        );
+        assert!(matches!(
+            store.get_vid(bob.identifier()).unwrap().relation_status,
+            super::RelationshipStatus::Unrelated
+        ));
+        assert!(store
+            .make_relationship_renew(
+                alice.identifier(),
+                bob.identifier(),
+                std::time::Duration::from_secs(3600),
+            )
+            .is_err());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_make_relationship_reject() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        // alice wants to establish a relation
+        let (_, mut sealed) = store
+            .make_relationship_request(alice.identifier(), bob.identifier(), None)
+            .unwrap();
+
+        let received = store.open_message(&mut sealed).unwrap();
+
+        let ReceivedTspMessage::RequestRelationship { thread_id, .. } = received else {
+            panic!("unexpected message type");
+        };
+
+        // bob refuses the relation
+        let (url, mut sealed) = store
+            .make_relationship_reject(
+                bob.identifier(),
+                alice.identifier(),
+                thread_id,
+                Some("not interested"),
+            )
+            .unwrap();
+
+        assert_eq!(url.as_str(), "tcp://127.0.0.1:1337");
+        let mut replayed = sealed.clone();
+        let received = store.open_message(&mut sealed).unwrap();
+
+        let ReceivedTspMessage::RejectRelationship {
+            sender,
+            thread_id: rejected,
+            reason,
+        } = received
+        else {
             panic!("unexpected message type");
         };
         assert_eq!(sender, bob.identifier());
+        assert_eq!(rejected, thread_id);
+        assert_eq!(reason.as_deref(), Some("not interested"));
+
+        assert!(matches!(
+            store.get_vid(bob.identifier()).unwrap().relation_status,
+            super::RelationshipStatus::Unrelated
+        ));
+
+        // there is no pending request left to reject
+        assert!(matches!(
+            store.open_message(&mut replayed),
+            Err(Error::Relationship(_))
+        ));
     }
 
     #[test]
@@ -1358,6 +6051,7 @@ mod test {
         let status = super::RelationshipStatus::Bidirectional {
             thread_id: Default::default(),
             outstanding_nested_thread_ids: vec![],
+            expires_at: None,
         };
 
         a_store
@@ -1413,6 +6107,196 @@ mod test {
         assert_eq!(referred_vid, charles.identifier());
     }
 
+    struct ConsentReceipt;
+
+    impl ControlExtension for ConsentReceipt {
+        fn encode(&self, message: &serde_json::Value) -> Result<Vec<u8>, String> {
+            if message.get("purpose").is_none() {
+                return Err("missing purpose".to_string());
+            }
+
+            serde_json::to_vec(message).map_err(|e| e.to_string())
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<serde_json::Value, String> {
+            serde_json::from_slice(data).map_err(|e| e.to_string())
+        }
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_control_extension() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        // nothing is registered for this type code yet
+        let receipt = serde_json::json!({ "purpose": "newsletter", "granted": true });
+        assert!(matches!(
+            store.make_extension_message(alice.identifier(), bob.identifier(), 0x01, &receipt),
+            Err(Error::UnknownExtension(0x01))
+        ));
+
+        store.register_control_extension(0x01, ConsentReceipt);
+
+        assert!(matches!(
+            store.make_extension_message(
+                alice.identifier(),
+                bob.identifier(),
+                0x01,
+                &serde_json::json!({})
+            ),
+            Err(Error::InvalidExtension(0x01, _))
+        ));
+
+        let (url, sealed) = store
+            .make_extension_message(alice.identifier(), bob.identifier(), 0x01, &receipt)
+            .unwrap();
+        assert_eq!(url.as_str(), "tcp://127.0.0.1:1337");
+
+        let mut opened = sealed.clone();
+        let received = store.open_message(&mut opened).unwrap();
+        let ReceivedTspMessage::Extension {
+            sender,
+            typecode,
+            message,
+        } = received
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(typecode, 0x01);
+        assert_eq!(message, receipt);
+
+        // a receiver without the extension rejects the message
+        let other = Store::new();
+        other.add_verified_vid(alice.clone()).unwrap();
+        other.add_private_vid(bob.clone()).unwrap();
+        assert!(matches!(
+            other.open_message(&mut sealed.clone()),
+            Err(Error::UnknownExtension(0x01))
+        ));
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_unknown_control() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        // a control message of a newer version of the protocol
+        let (_, mut sealed) = store
+            .seal_message_payload(
+                alice.identifier(),
+                bob.identifier(),
+                None,
+                crate::definitions::Payload::UnknownControl {
+                    typecode: [9, 1],
+                    raw: &b"new fields"[..],
+                },
+            )
+            .unwrap();
+
+        let received = store.open_message(&mut sealed).unwrap();
+        let ReceivedTspMessage::UnknownControl {
+            sender,
+            typecode,
+            raw,
+        } = received
+        else {
+            panic!("unexpected message type");
+        };
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(typecode, [9, 1]);
+        assert_eq!(&*raw, b"new fields");
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_verification_policy() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let charles = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+        store.add_verified_vid(charles.clone()).unwrap();
+
+        store.set_verification_policy(|vid: &str, origin: VidOrigin| match origin {
+            VidOrigin::Referral { .. } if vid.starts_with("did:peer:") => {
+                Err("no did:peer referrals".to_string())
+            }
+            _ => Ok(()),
+        });
+
+        let (_, mut sealed) = store
+            .make_relationship_referral(alice.identifier(), bob.identifier(), charles.identifier())
+            .unwrap();
+
+        assert!(matches!(
+            store.open_message(&mut sealed),
+            Err(Error::PolicyRejected(vid, _)) if vid == charles.identifier()
+        ));
+
+        // other messages are not affected
+        let (_, mut sealed) = store
+            .seal_message(alice.identifier(), bob.identifier(), None, b"hello")
+            .unwrap();
+        assert!(store.open_message(&mut sealed).is_ok());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_forward_guard() {
+        let store = Store::new();
+        let alice = new_vid();
+        let bob = new_vid();
+        let charles = new_vid();
+
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_verified_vid(bob.clone()).unwrap();
+        store.add_verified_vid(charles.clone()).unwrap();
+        store
+            .set_relation_for_vid(bob.identifier(), Some(alice.identifier()))
+            .unwrap();
+        store
+            .set_relation_for_vid(charles.identifier(), Some(alice.identifier()))
+            .unwrap();
+
+        let denied = charles.identifier().to_string();
+        store.set_forward_guard(move |_: &str, next_hop: &str, size: usize| {
+            if next_hop == denied {
+                Err("denied".to_string())
+            } else if size > 16 {
+                Err("too large".to_string())
+            } else {
+                Ok(())
+            }
+        });
+
+        let route = vec![b"did:example:dave".as_slice()];
+
+        assert!(store
+            .forward_routed_message(bob.identifier(), route.clone(), b"hello")
+            .is_ok());
+        assert!(matches!(
+            store.forward_routed_message(bob.identifier(), route.clone(), &[0; 17]),
+            Err(Error::ForwardRefused(next_hop, reason)) if next_hop == bob.identifier() && reason == "too large"
+        ));
+        assert!(matches!(
+            store.forward_routed_message(charles.identifier(), route, b"hello"),
+            Err(Error::ForwardRefused(next_hop, _)) if next_hop == charles.identifier()
+        ));
+    }
+
     #[test]
     #[wasm_bindgen_test]
     fn test_routed() {
@@ -1493,6 +6377,7 @@ mod test {
             next_hop,
             route,
             opaque_payload,
+            ..
         } = received
         else {
             panic!()
@@ -1502,7 +6387,7 @@ mod test {
         let (_url, mut sealed) = b_store
             .forward_routed_message(
                 &next_hop,
-                route.iter().map(|s| s.as_slice()).collect(),
+                route.iter().map(|hop| &hop[..]).collect(),
                 &opaque_payload,
             )
             .unwrap();
@@ -1514,6 +6399,7 @@ mod test {
             next_hop,
             route,
             opaque_payload,
+            ..
         } = received
         else {
             panic!()
@@ -1523,7 +6409,7 @@ mod test {
         let (_url, mut sealed) = c_store
             .forward_routed_message(
                 &next_hop,
-                route.iter().map(|s| s.as_slice()).collect(),
+                route.iter().map(|hop| &hop[..]).collect(),
                 &opaque_payload,
             )
             .unwrap();
@@ -1535,6 +6421,7 @@ mod test {
             nonconfidential_data,
             message,
             message_type,
+            ..
         } = received
         else {
             panic!()
@@ -1542,12 +6429,144 @@ mod test {
 
         assert_eq!(sender, sneaky_a.identifier());
         assert!(nonconfidential_data.is_none());
-        assert_eq!(message, hello_world);
+        assert_eq!(&*message, hello_world);
         assert_ne!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
         assert_ne!(
             message_type.signature_type,
             crate::cesr::SignatureType::NoSignature
         );
+
+        // every intermediary only sees its own annotation and those of later hops
+        let (_url, mut sealed) = a_store
+            .seal_message_with_annotations(
+                sneaky_a.identifier(),
+                sneaky_d.identifier(),
+                None,
+                &[b"priority=high", b"ttl=60"],
+                hello_world,
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::ForwardRequest {
+            next_hop,
+            route,
+            annotation,
+            route_annotations,
+            opaque_payload,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(annotation.as_deref(), Some(&b"priority=high"[..]));
+        assert_eq!(route_annotations, vec![&b"ttl=60"[..]]);
+
+        let (_url, mut sealed) = b_store
+            .forward_annotated_routed_message(
+                &next_hop,
+                route.iter().map(|hop| &hop[..]).collect(),
+                route_annotations
+                    .iter()
+                    .map(|annotation| &annotation[..])
+                    .collect(),
+                &opaque_payload,
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::ForwardRequest {
+            annotation,
+            route_annotations,
+            ..
+        } = c_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(annotation.as_deref(), Some(&b"ttl=60"[..]));
+        assert!(route_annotations.is_empty());
+
+        assert!(matches!(
+            a_store.seal_message_with_annotations(
+                sneaky_a.identifier(),
+                sneaky_d.identifier(),
+                None,
+                &[b"1", b"2", b"3", b"4"],
+                hello_world,
+            ),
+            Err(Error::InvalidRoute(_))
+        ));
+
+        // the sender decides whether the first hop, the receiver, or both see the
+        // nonconfidential data
+        for (placement, first_hop_sees, receiver_sees) in [
+            (None, false, true),
+            (Some(NonConfidentialPlacement::Inner), false, true),
+            (Some(NonConfidentialPlacement::Outer), true, false),
+            (Some(NonConfidentialPlacement::Both), true, true),
+        ] {
+            let (_url, mut sealed) = a_store
+                .seal_message_with_options(
+                    sneaky_a.identifier(),
+                    sneaky_d.identifier(),
+                    Some(b"extra"),
+                    hello_world,
+                    SealOptions {
+                        nonconfidential_placement: placement,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+
+            let ReceivedTspMessage::ForwardRequest {
+                next_hop,
+                route,
+                nonconfidential_data,
+                opaque_payload,
+                ..
+            } = b_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!()
+            };
+            assert_eq!(nonconfidential_data.is_some(), first_hop_sees);
+
+            let (_url, mut sealed) = b_store
+                .forward_routed_message(
+                    &next_hop,
+                    route.iter().map(|hop| &hop[..]).collect(),
+                    &opaque_payload,
+                )
+                .unwrap();
+
+            let ReceivedTspMessage::ForwardRequest {
+                next_hop,
+                route,
+                nonconfidential_data,
+                opaque_payload,
+                ..
+            } = c_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!()
+            };
+            assert!(nonconfidential_data.is_none());
+
+            let (_url, mut sealed) = c_store
+                .forward_routed_message(
+                    &next_hop,
+                    route.iter().map(|hop| &hop[..]).collect(),
+                    &opaque_payload,
+                )
+                .unwrap();
+
+            let ReceivedTspMessage::GenericMessage {
+                nonconfidential_data,
+                message,
+                ..
+            } = d_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!()
+            };
+            assert_eq!(nonconfidential_data.is_some(), receiver_sees);
+            assert_eq!(&*message, hello_world);
+        }
     }
 
     #[test]
@@ -1608,6 +6627,7 @@ mod test {
             nonconfidential_data,
             message,
             message_type,
+            ..
         } = received
         else {
             panic!()
@@ -1615,12 +6635,120 @@ mod test {
 
         assert_eq!(sender, nested_a.identifier());
         assert!(nonconfidential_data.is_none());
-        assert_eq!(message, hello_world);
+        assert_eq!(&*message, hello_world);
         assert_ne!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
         assert_ne!(
             message_type.signature_type,
             crate::cesr::SignatureType::NoSignature
         );
+
+        // nonconfidential data on the inner message reaches the nested receiver
+        let (_url, mut sealed) = a_store
+            .seal_message_with_options(
+                nested_a.identifier(),
+                nested_b.identifier(),
+                Some(b"extra"),
+                hello_world,
+                SealOptions {
+                    nonconfidential_placement: Some(NonConfidentialPlacement::Inner),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let ReceivedTspMessage::GenericMessage {
+            sender,
+            nonconfidential_data,
+            message,
+            ..
+        } = b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+
+        assert_eq!(sender, nested_a.identifier());
+        assert_eq!(nonconfidential_data.as_deref(), Some(&b"extra"[..]));
+        assert_eq!(&*message, hello_world);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_nested_depth_three() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+
+        // a <- a1 <- a2 and b <- b1 <- b2
+        let a = [new_vid(), new_vid(), new_vid()];
+        let b = [new_vid(), new_vid(), new_vid()];
+
+        for vid in &a {
+            a_store.add_private_vid(vid.clone()).unwrap();
+            b_store.add_verified_vid(vid.clone()).unwrap();
+        }
+
+        for vid in &b {
+            b_store.add_private_vid(vid.clone()).unwrap();
+            a_store.add_verified_vid(vid.clone()).unwrap();
+        }
+
+        for store in [&a_store, &b_store] {
+            for chain in [&a, &b] {
+                store
+                    .set_parent_for_vid(chain[1].identifier(), Some(chain[0].identifier()))
+                    .unwrap();
+                store
+                    .set_parent_for_vid(chain[2].identifier(), Some(chain[1].identifier()))
+                    .unwrap();
+            }
+        }
+
+        a_store
+            .set_relation_for_vid(b[2].identifier(), Some(a[2].identifier()))
+            .unwrap();
+
+        assert_eq!(
+            a_store.parent_chain(a[2].identifier()).unwrap(),
+            vec![a[1].identifier(), a[0].identifier()]
+        );
+        assert!(a_store.parent_chain(a[0].identifier()).unwrap().is_empty());
+
+        let hello_world = b"hello world";
+
+        // any VID in the chain of the inner sender can be used as sender
+        for sender in &a {
+            let (_url, mut sealed) = a_store
+                .seal_message(sender.identifier(), b[2].identifier(), None, hello_world)
+                .unwrap();
+
+            let ReceivedTspMessage::GenericMessage {
+                sender,
+                message,
+                message_type,
+                ..
+            } = b_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!()
+            };
+
+            assert_eq!(sender, a[2].identifier());
+            assert_eq!(&*message, hello_world);
+            assert_ne!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
+        }
+
+        let unrelated = new_vid();
+        a_store.add_private_vid(unrelated.clone()).unwrap();
+        assert!(a_store
+            .seal_message(unrelated.identifier(), b[2].identifier(), None, hello_world)
+            .is_err());
+
+        // a cycle in the parent chain is reported instead of followed
+        a_store
+            .set_parent_for_vid(a[0].identifier(), Some(a[2].identifier()))
+            .unwrap();
+        assert!(matches!(
+            a_store.parent_chain(a[2].identifier()),
+            Err(Error::Relationship(_))
+        ));
     }
 
     #[cfg(not(feature = "pq"))]
@@ -1742,6 +6870,7 @@ mod test {
             nonconfidential_data,
             message,
             message_type,
+            ..
         } = received
         else {
             panic!()
@@ -1749,11 +6878,115 @@ mod test {
 
         assert_eq!(sender, nested_a.identifier());
         assert!(nonconfidential_data.is_none());
-        assert_eq!(message, hello_world);
+        assert_eq!(&*message, hello_world);
         assert_ne!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
         assert_ne!(
             message_type.signature_type,
             crate::cesr::SignatureType::NoSignature
         );
+
+        // now bob cancels the nested relationship
+        let (_url, mut sealed) = b_store
+            .make_nested_relationship_cancel(nested_b.identifier(), nested_a.identifier())
+            .unwrap();
+
+        let received = a_store.open_message(&mut sealed).unwrap();
+
+        let ReceivedTspMessage::CancelRelationship {
+            sender,
+            nested_vid: Some(nested_vid),
+        } = received
+        else {
+            panic!()
+        };
+
+        assert_eq!(sender, b.identifier());
+        assert_eq!(nested_vid, nested_b.identifier());
+
+        for (store, vid) in [
+            (&a_store, &nested_a),
+            (&a_store, &nested_b),
+            (&b_store, &nested_a),
+            (&b_store, &nested_b),
+        ] {
+            assert!(store.get_vid(vid.identifier()).is_err());
+        }
+
+        // the outer relationship is unaffected
+        assert!(a_store
+            .seal_message(a.identifier(), b.identifier(), None, hello_world)
+            .is_ok());
+    }
+
+    #[cfg(not(feature = "pq"))]
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_expire_nested_requests() {
+        let a_store = Store::new();
+        let b_store = Store::new();
+
+        let a = new_vid();
+        let b = new_vid();
+
+        a_store.add_private_vid(a.clone()).unwrap();
+        b_store.add_private_vid(b.clone()).unwrap();
+
+        a_store.add_verified_vid(b.clone()).unwrap();
+        b_store.add_verified_vid(a.clone()).unwrap();
+
+        let (_url, mut sealed) = a_store
+            .make_relationship_request(a.identifier(), b.identifier(), None)
+            .unwrap();
+        let ReceivedTspMessage::RequestRelationship { thread_id, .. } =
+            b_store.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+        let (_url, mut sealed) = b_store
+            .make_relationship_accept(b.identifier(), a.identifier(), thread_id, None)
+            .unwrap();
+        a_store.open_message(&mut sealed).unwrap();
+
+        let nested_accept = || {
+            let ((_url, mut sealed), _) = a_store
+                .make_nested_relationship_request(a.identifier(), b.identifier())
+                .unwrap();
+            let ReceivedTspMessage::RequestRelationship {
+                nested_vid: Some(nested_vid),
+                thread_id,
+                ..
+            } = b_store.open_message(&mut sealed).unwrap()
+            else {
+                panic!()
+            };
+            let ((_url, sealed), _) = b_store
+                .make_nested_relationship_accept(b.identifier(), &nested_vid, thread_id)
+                .unwrap();
+
+            (thread_id, sealed)
+        };
+
+        // the pending request survives a restart
+        let (first, mut sealed) = nested_accept();
+        let restored = Store::new();
+        restored.import(a_store.export().unwrap()).unwrap();
+
+        assert!(restored.expire_nested_requests(60).is_empty());
+        let ReceivedTspMessage::AcceptRelationship {
+            nested_vid: Some(_),
+            ..
+        } = restored.open_message(&mut sealed).unwrap()
+        else {
+            panic!()
+        };
+
+        // an accept for an expired request is refused; the first request is still
+        // outstanding in the store that was not restored
+        let (second, mut sealed) = nested_accept();
+        assert_eq!(
+            a_store.expire_nested_requests(0),
+            vec![(b.identifier().to_string(), vec![first, second])]
+        );
+        assert!(a_store.open_message(&mut sealed).is_err());
     }
 }
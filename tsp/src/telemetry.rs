@@ -0,0 +1,125 @@
+use once_cell::sync::Lazy;
+use sha2::Digest;
+use std::sync::{Arc, RwLock};
+
+/// Number of messages handed to a transport
+pub const MESSAGES_SENT: &str = "tsp_messages_sent_total";
+/// Number of messages received from a transport
+pub const MESSAGES_RECEIVED: &str = "tsp_messages_received_total";
+/// Number of routed messages forwarded to a next hop
+pub const MESSAGES_FORWARDED: &str = "tsp_messages_forwarded_total";
+/// Time spent sealing a message, in seconds
+pub const SEAL_DURATION: &str = "tsp_seal_duration_seconds";
+/// Time spent opening a message, in seconds
+pub const OPEN_DURATION: &str = "tsp_open_duration_seconds";
+
+/// Receives the metrics recorded by this crate; implement this trait to forward
+/// the metrics to e.g. Prometheus
+pub trait MetricsRecorder: Send + Sync {
+    /// Increase the counter `name` by `value`
+    fn increment_counter(&self, name: &'static str, value: u64);
+
+    /// Record an observation of `value` for the histogram `name`
+    fn record_histogram(&self, name: &'static str, value: f64);
+}
+
+static RECORDER: Lazy<RwLock<Option<Arc<dyn MetricsRecorder>>>> = Lazy::new(Default::default);
+
+/// Install the recorder that receives all metrics, replacing any previous recorder
+pub fn set_metrics_recorder(recorder: impl MetricsRecorder + 'static) {
+    if let Ok(mut current) = RECORDER.write() {
+        *current = Some(Arc::new(recorder));
+    }
+}
+
+/// Stop recording metrics
+pub fn clear_metrics_recorder() {
+    if let Ok(mut current) = RECORDER.write() {
+        *current = None;
+    }
+}
+
+fn recorder() -> Option<Arc<dyn MetricsRecorder>> {
+    RECORDER.read().ok()?.clone()
+}
+
+pub(crate) fn increment_counter(name: &'static str) {
+    if let Some(recorder) = recorder() {
+        recorder.increment_counter(name, 1);
+    }
+}
+
+/// Run `f` and record its duration in the histogram `name`
+pub(crate) fn timed<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    // `Instant` is not available on wasm32-unknown-unknown
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(recorder) = recorder() {
+        let start = std::time::Instant::now();
+        let result = f();
+        recorder.record_histogram(name, start.elapsed().as_secs_f64());
+
+        return result;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    let _ = name;
+
+    f()
+}
+
+/// A short, stable identifier for a VID, for use in logs without revealing the full DID
+pub fn fingerprint(vid: &str) -> String {
+    sha2::Sha256::digest(vid.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<&'static str>>);
+
+    impl MetricsRecorder for Arc<Recorder> {
+        fn increment_counter(&self, name: &'static str, _value: u64) {
+            self.0.lock().unwrap().push(name);
+        }
+
+        fn record_histogram(&self, name: &'static str, _value: f64) {
+            self.0.lock().unwrap().push(name);
+        }
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_fingerprint() {
+        let fingerprint = fingerprint("did:web:did.tsp-test.org:user:alice");
+
+        assert_eq!(fingerprint.len(), 16);
+        assert!(!fingerprint.contains("alice"));
+        assert_ne!(
+            fingerprint,
+            super::fingerprint("did:web:did.tsp-test.org:user:bob")
+        );
+    }
+
+    #[test]
+    fn test_metrics_recorder() {
+        let recorder = Arc::new(Recorder::default());
+        set_metrics_recorder(recorder.clone());
+
+        increment_counter(MESSAGES_SENT);
+        assert_eq!(timed(SEAL_DURATION, || 42), 42);
+
+        clear_metrics_recorder();
+        increment_counter(MESSAGES_SENT);
+
+        let recorded = recorder.0.lock().unwrap();
+        assert!(recorded.contains(&MESSAGES_SENT));
+        assert!(recorded.contains(&SEAL_DURATION));
+    }
+}
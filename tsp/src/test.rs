@@ -1,4 +1,4 @@
-use crate::{AsyncStore, OwnedVid, VerifiedVid};
+use crate::{AsyncStore, FirstContactPolicy, OwnedVid, VerifiedVid};
 use futures::StreamExt;
 
 #[tokio::test]
@@ -183,6 +183,119 @@ async fn test_anycast() {
     assert_eq!(message, b"hello world");
 }
 
+#[tokio::test]
+async fn test_broadcast() {
+    let alice = OwnedVid::new_did_peer("mem://broadcast-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://broadcast-bob".parse().unwrap());
+    let carol = OwnedVid::new_did_peer("mem://broadcast-carol".parse().unwrap());
+    let dave = OwnedVid::new_did_peer("mem://broadcast-dave".parse().unwrap());
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+    alice_db.add_verified_vid(carol.vid().clone()).unwrap();
+
+    let mut receivers = Vec::new();
+    for receiver in [&bob, &carol] {
+        let db = AsyncStore::new();
+        db.add_private_vid(receiver.clone()).unwrap();
+        db.add_verified_vid(alice.vid().clone()).unwrap();
+
+        receivers.push(db.receive(receiver.identifier()).await.unwrap());
+    }
+
+    // dave is not known to alice
+    let results = alice_db
+        .broadcast(
+            alice.identifier(),
+            &[bob.identifier(), dave.identifier(), carol.identifier()],
+            b"hello everyone",
+        )
+        .await
+        .unwrap();
+
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+
+    for messages in &mut receivers {
+        let crate::definitions::ReceivedTspMessage::GenericMessage {
+            sender,
+            message,
+            message_type,
+            ..
+        } = messages.next().await.unwrap().unwrap()
+        else {
+            panic!("did not receive a broadcast message")
+        };
+
+        assert_eq!(sender, alice.identifier());
+        assert_eq!(message, b"hello everyone");
+        assert_eq!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
+    }
+}
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn test_signed_message() {
+    // bob database
+    let mut bob_db = AsyncStore::new();
+    let bob_vid = OwnedVid::from_file("../examples/test/bob.json")
+        .await
+        .unwrap();
+    bob_db.add_private_vid(bob_vid.clone()).unwrap();
+    bob_db
+        .verify_vid("did:web:did.tsp-test.org:user:alice")
+        .await
+        .unwrap();
+
+    let mut bobs_messages = bob_db
+        .receive("did:web:did.tsp-test.org:user:bob")
+        .await
+        .unwrap();
+
+    // alice database
+    let mut alice_db = AsyncStore::new();
+    let alice_vid = OwnedVid::from_file("../examples/test/alice.json")
+        .await
+        .unwrap();
+    alice_db.add_private_vid(alice_vid.clone()).unwrap();
+    alice_db
+        .verify_vid("did:web:did.tsp-test.org:user:bob")
+        .await
+        .unwrap();
+
+    // send a signed message
+    alice_db
+        .send_signed(
+            "did:web:did.tsp-test.org:user:alice",
+            "did:web:did.tsp-test.org:user:bob",
+            b"hello world",
+        )
+        .await
+        .unwrap();
+
+    // receive a message
+    let crate::definitions::ReceivedTspMessage::GenericMessage {
+        sender,
+        message,
+        message_type,
+        ..
+    } = bobs_messages.next().await.unwrap().unwrap()
+    else {
+        panic!("bob did not receive a signed message")
+    };
+
+    assert_eq!(sender, "did:web:did.tsp-test.org:user:alice");
+    assert_eq!(message_type.crypto_type, crate::cesr::CryptoType::Plaintext);
+    assert_ne!(
+        message_type.signature_type,
+        crate::cesr::SignatureType::NoSignature
+    );
+
+    assert_eq!(message, b"hello world");
+}
+
 #[tokio::test]
 #[serial_test::serial(tcp)]
 async fn test_nested_mode() {
@@ -275,6 +388,106 @@ async fn test_nested_mode() {
     assert_eq!(&message, b"hello nested world");
 }
 
+#[tokio::test]
+async fn test_import_contacts() {
+    let alice = OwnedVid::new_did_peer("mem://import-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://import-bob".parse().unwrap());
+    let fingerprint =
+        |vid: &OwnedVid| crate::contacts::key_fingerprint(vid.encryption_key().as_ref());
+
+    let registry = format!(
+        "did,alias,fingerprints,department\n\
+        {},alice,{},sales\n\
+        {},bob,{},\n\
+        did:example:unknown,carol,,\n",
+        alice.identifier(),
+        fingerprint(&alice),
+        bob.identifier(),
+        fingerprint(&alice),
+    );
+
+    let store = AsyncStore::new();
+    let imports = store.import_contacts(registry.as_bytes()).await.unwrap();
+
+    assert_eq!(imports.len(), 3);
+    assert_eq!(imports[0].contact.alias.as_deref(), Some("alice"));
+    assert!(imports[0].result.is_ok());
+    assert!(matches!(
+        imports[1].result,
+        Err(crate::Error::FingerprintMismatch(_, _))
+    ));
+    assert!(imports[2].result.is_err());
+
+    assert!(store
+        .as_store()
+        .get_verified_vid(alice.identifier())
+        .is_ok());
+    assert!(store.as_store().get_verified_vid(bob.identifier()).is_err());
+    assert_eq!(
+        store.get_metadata(alice.identifier()).unwrap(),
+        Some(serde_json::json!({ "department": "sales" }))
+    );
+}
+
+#[tokio::test]
+async fn test_nested_placeholder_endpoint() {
+    let alice = OwnedVid::new_did_peer("mem://nested-placeholder-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://nested-placeholder-bob".parse().unwrap());
+    let nested_alice = OwnedVid::new_did_peer("tsp://".parse().unwrap());
+    let nested_bob = OwnedVid::new_did_peer("tsp://".parse().unwrap());
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_private_vid(nested_alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+    alice_db.add_verified_vid(nested_bob.vid().clone()).unwrap();
+    alice_db
+        .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+        .unwrap();
+    alice_db
+        .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+        .unwrap();
+    alice_db
+        .set_relation_for_vid(nested_bob.identifier(), Some(nested_alice.identifier()))
+        .unwrap();
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_private_vid(nested_bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+    bob_db.add_verified_vid(nested_alice.vid().clone()).unwrap();
+    bob_db
+        .set_parent_for_vid(nested_bob.identifier(), Some(bob.identifier()))
+        .unwrap();
+    bob_db
+        .set_parent_for_vid(nested_alice.identifier(), Some(alice.identifier()))
+        .unwrap();
+
+    // the nested VID receives on the endpoint of its parent
+    let mut bobs_inner_messages = bob_db.receive(nested_bob.identifier()).await.unwrap();
+    assert!(bob_db.receive(bob.identifier()).await.is_err());
+
+    alice_db
+        .send(
+            nested_alice.identifier(),
+            nested_bob.identifier(),
+            None,
+            b"hello nested world",
+        )
+        .await
+        .unwrap();
+
+    let crate::definitions::ReceivedTspMessage::GenericMessage {
+        sender, message, ..
+    } = bobs_inner_messages.next().await.unwrap().unwrap()
+    else {
+        panic!("bob did not receive a generic message inner")
+    };
+
+    assert_eq!(sender, nested_alice.identifier());
+    assert_eq!(message, b"hello nested world");
+}
+
 #[tokio::test]
 #[serial_test::serial(tcp)]
 async fn test_routed_mode() {
@@ -347,6 +560,7 @@ async fn test_routed_mode() {
         sender,
         next_hop,
         route,
+        ..
     } = bobs_messages.next().await.unwrap().unwrap()
     else {
         panic!("bob did not receive a forward request")
@@ -593,3 +807,669 @@ async fn test_relation_forming() {
 
     assert_eq!(sender, "did:web:did.tsp-test.org:user:bob");
 }
+
+async fn next_message(
+    messages: &mut crate::definitions::TSPStream<crate::ReceivedTspMessage, crate::Error>,
+) -> Vec<u8> {
+    let crate::ReceivedTspMessage::GenericMessage { message, .. } =
+        messages.next().await.unwrap().unwrap()
+    else {
+        panic!("did not receive a generic message")
+    };
+
+    message
+}
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn test_alternative_endpoints() {
+    let primary: url::Url = "tcp://127.0.0.1:1337".parse().unwrap();
+    let alternative: url::Url = "tcp://127.0.0.1:1338".parse().unwrap();
+    let unreachable: url::Url = "tcp://127.0.0.1:1339".parse().unwrap();
+
+    let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1336".parse().unwrap());
+    let bob =
+        OwnedVid::new_did_peer(primary.clone()).with_alternative_endpoints([alternative.clone()]);
+    // carol can only be reached at her alternative endpoint, which she shares with bob
+    let carol = OwnedVid::new_did_peer(unreachable).with_alternative_endpoints([primary]);
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_private_vid(carol.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+
+    let mut bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+    alice_db.add_verified_vid(carol.vid().clone()).unwrap();
+
+    // bob receives on his primary endpoint
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"primary")
+        .await
+        .unwrap();
+    assert_eq!(next_message(&mut bobs_messages).await, b"primary");
+
+    // and on his alternative endpoint
+    let (_, message) = alice_db
+        .as_store()
+        .seal_message(alice.identifier(), bob.identifier(), None, b"alternative")
+        .unwrap();
+    crate::transport::send_message(&alternative, &message)
+        .await
+        .unwrap();
+    assert_eq!(next_message(&mut bobs_messages).await, b"alternative");
+
+    // sending falls back to an alternative endpoint
+    alice_db
+        .send(alice.identifier(), carol.identifier(), None, b"fallback")
+        .await
+        .unwrap();
+    assert_eq!(next_message(&mut bobs_messages).await, b"fallback");
+}
+
+#[tokio::test]
+async fn test_resolve_pending() {
+    let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("tcp://127.0.0.1:1338".parse().unwrap());
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    // bob does not know alice yet
+    let mut bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+
+    let (_, message) = alice_db
+        .as_store()
+        .seal_message(alice.identifier(), bob.identifier(), None, b"hello world")
+        .unwrap();
+
+    let Err(crate::Error::UnverifiedSource(unknown_vid, _)) =
+        bob_db.open_message(&mut message.clone())
+    else {
+        panic!("expected an unverified source");
+    };
+    let payload = message;
+    assert_eq!(unknown_vid, alice.identifier());
+
+    // rejecting does not add the unknown vid
+    bob_db.reject_pending(&unknown_vid, payload.clone());
+    assert!(bob_db.as_store().get_verified_vid(&unknown_vid).is_err());
+
+    let crate::ReceivedTspMessage::GenericMessage {
+        sender, message, ..
+    } = bob_db.resolve_pending(&unknown_vid, payload).await.unwrap()
+    else {
+        panic!("expected a generic message");
+    };
+    assert_eq!(sender, alice.identifier());
+    assert_eq!(message, b"hello world");
+}
+
+#[tokio::test]
+async fn test_first_contact_policy() {
+    let alice = OwnedVid::new_did_peer("mem://first-contact-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://first-contact-bob".parse().unwrap());
+    let carol = OwnedVid::new_did_peer("mem://first-contact-carol".parse().unwrap());
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+    alice_db.add_verified_vid(carol.vid().clone()).unwrap();
+
+    // bob only resolves did:web senders, carol resolves every sender
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.set_first_contact_policy(FirstContactPolicy::ResolveMethods(vec!["web".into()]));
+
+    let carol_db = AsyncStore::new();
+    carol_db.add_private_vid(carol.clone()).unwrap();
+    carol_db.set_first_contact_policy(FirstContactPolicy::ResolveAndVerify);
+
+    let mut bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+    let mut carols_messages = carol_db.receive(carol.identifier()).await.unwrap();
+
+    for receiver in [&bob, &carol] {
+        alice_db
+            .send(alice.identifier(), receiver.identifier(), None, b"hello")
+            .await
+            .unwrap();
+    }
+
+    let crate::ReceivedTspMessage::PendingMessage { unknown_vid, .. } =
+        bobs_messages.next().await.unwrap().unwrap()
+    else {
+        panic!("bob did not receive a pending message");
+    };
+    assert_eq!(unknown_vid, alice.identifier());
+    assert!(bob_db
+        .as_store()
+        .get_verified_vid(alice.identifier())
+        .is_err());
+
+    let crate::ReceivedTspMessage::GenericMessage {
+        sender, message, ..
+    } = carols_messages.next().await.unwrap().unwrap()
+    else {
+        panic!("carol did not receive a generic message");
+    };
+    assert_eq!(sender, alice.identifier());
+    assert_eq!(message, b"hello");
+    assert!(carol_db
+        .get_vid_metadata(alice.identifier())
+        .unwrap()
+        .is_some_and(|metadata| metadata.resolved_at.is_some()));
+}
+
+#[tokio::test]
+async fn test_accept_referral() {
+    let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("tcp://127.0.0.1:1338".parse().unwrap());
+    let carol = OwnedVid::new_did_peer("tcp://127.0.0.1:1339".parse().unwrap());
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    // bob introduces carol to alice
+    let referral = crate::ReceivedTspMessage::Referral {
+        sender: bob.identifier().to_string(),
+        referred_vid: carol.identifier().to_string(),
+    };
+
+    let not_a_referral = crate::ReceivedTspMessage::NewIdentifier {
+        sender: bob.identifier().to_string(),
+        new_vid: carol.identifier().to_string(),
+    };
+    assert!(alice_db
+        .accept_referral(&not_a_referral, None)
+        .await
+        .is_err());
+
+    alice_db.accept_referral(&referral, None).await.unwrap();
+
+    let metadata = alice_db
+        .get_vid_metadata(carol.identifier())
+        .unwrap()
+        .unwrap();
+    assert_eq!(metadata.referred_by.as_deref(), Some(bob.identifier()));
+    assert_eq!(
+        alice_db.provenance(carol.identifier()).unwrap(),
+        Some(crate::vid::Provenance::Referral {
+            sender: bob.identifier().to_string()
+        })
+    );
+
+    // carol announces a new identifier, which bob introduced through carol
+    let dave = OwnedVid::new_did_peer("tcp://127.0.0.1:1340".parse().unwrap());
+    let new_identifier = crate::ReceivedTspMessage::NewIdentifier {
+        sender: carol.identifier().to_string(),
+        new_vid: dave.identifier().to_string(),
+    };
+    alice_db
+        .accept_new_identifier(&new_identifier)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        alice_db.introducers(dave.identifier()).unwrap(),
+        [carol.identifier(), bob.identifier()]
+    );
+    assert_eq!(
+        alice_db.introduced_by(bob.identifier()),
+        [carol.identifier(), dave.identifier()]
+    );
+    assert_eq!(
+        alice_db.provenance(bob.identifier()).unwrap(),
+        Some(crate::vid::Provenance::Manual)
+    );
+    assert_eq!(alice_db.provenance(alice.identifier()).unwrap(), None);
+
+    // the verification policy decides on referred VIDs
+    alice_db.forget_vid(carol.identifier()).unwrap();
+    alice_db.set_verification_policy(|_: &str, origin: crate::vid::VidOrigin| match origin {
+        crate::vid::VidOrigin::Referral { .. } => Err("no referrals".to_string()),
+        _ => Ok(()),
+    });
+
+    assert!(alice_db.accept_referral(&referral, None).await.is_err());
+    assert!(alice_db
+        .as_store()
+        .get_verified_vid(carol.identifier())
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_refresh_vid() {
+    let alice = "did:web:did.tsp-test.org:user:alice";
+
+    let mut db = AsyncStore::new();
+    db.set_resolver(crate::vid::VidResolver::default());
+    db.verify_vid(alice).await.unwrap();
+
+    assert!(matches!(
+        db.refresh_vid(alice).await.unwrap(),
+        crate::vid::VidRefresh::Unchanged(_)
+    ));
+
+    // pretend alice had different keys before
+    let stale = OwnedVid::bind(alice, "tcp://127.0.0.1:1337".parse().unwrap());
+    db.add_verified_vid(stale.vid().clone()).unwrap();
+
+    assert!(matches!(
+        db.refresh_vid(alice).await.unwrap(),
+        crate::vid::VidRefresh::KeysChanged(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_verify_vids() {
+    let alice = "did:web:did.tsp-test.org:user:alice";
+    let bob = "did:web:did.tsp-test.org:user:bob";
+    let peer = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+
+    let db = AsyncStore::new();
+    let results = db
+        .verify_vids(&[alice, "did:example:unknown", bob, peer.identifier()], 2)
+        .await;
+
+    assert_eq!(results.len(), 4);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+    assert!(results[3].is_ok());
+
+    let vids = db.list_vids().unwrap();
+    assert_eq!(vids.len(), 3);
+    assert!(vids.iter().any(|vid| vid == peer.identifier()));
+}
+
+#[tokio::test]
+async fn test_did_method_registry() {
+    let alice = OwnedVid::bind("did:example:alice", "tcp://127.0.0.1:1337".parse().unwrap());
+    let vid = alice.vid().clone();
+
+    let mut db = AsyncStore::new();
+    assert!(db.verify_vid(alice.identifier()).await.is_err());
+
+    db.register_did_method("example", move |id: String| {
+        let vid = vid.clone();
+        async move {
+            match id.as_str() {
+                "did:example:alice" => Ok(vid),
+                _ => Err(crate::vid::VidError::ResolveVid("unknown example DID")),
+            }
+        }
+    });
+
+    db.verify_vid(alice.identifier()).await.unwrap();
+    assert_eq!(
+        db.as_store()
+            .get_verified_vid(alice.identifier())
+            .unwrap()
+            .verifying_key(),
+        alice.verifying_key()
+    );
+
+    assert!(db.verify_vid("did:example:bob").await.is_err());
+}
+
+#[tokio::test]
+async fn test_call() {
+    let alice = OwnedVid::new_did_peer("mem://call-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://call-bob".parse().unwrap());
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    let mut bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+    let alices_messages = alice_db.receive(alice.identifier()).await.unwrap();
+    tokio::spawn(alices_messages.for_each(|_| async {}));
+
+    // bob answers the first request only
+    let (alice_id, bob_id) = (alice.identifier().to_string(), bob.identifier().to_string());
+    tokio::spawn(async move {
+        let crate::ReceivedTspMessage::GenericMessage {
+            message, digest, ..
+        } = bobs_messages.next().await.unwrap().unwrap()
+        else {
+            panic!("bob did not receive a generic message")
+        };
+        assert_eq!(message, b"ping");
+
+        bob_db
+            .send_reply(&bob_id, &alice_id, None, &digest, b"pong")
+            .await
+            .unwrap();
+
+        // keep receiving, without answering
+        bobs_messages.for_each(|_| async {}).await;
+    });
+
+    let timeout = std::time::Duration::from_secs(1);
+    let reply = alice_db
+        .call(alice.identifier(), bob.identifier(), b"ping", timeout)
+        .await
+        .unwrap();
+    assert_eq!(reply, b"pong");
+
+    assert!(matches!(
+        alice_db
+            .call(alice.identifier(), bob.identifier(), b"ping", timeout)
+            .await,
+        Err(crate::Error::ReplyTimeout(..))
+    ));
+}
+
+#[tokio::test]
+async fn test_relationship_bootstrap() {
+    let alice = OwnedVid::new_did_peer("mem://bootstrap-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://bootstrap-bob".parse().unwrap());
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    let statuses = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = statuses.clone();
+    alice_db.set_relationship_bootstrap(std::time::Duration::from_secs(1), move |_, _, status| {
+        recorded.lock().unwrap().push(status)
+    });
+
+    let alices_messages = alice_db.receive(alice.identifier()).await.unwrap();
+    tokio::spawn(alices_messages.for_each(|_| async {}));
+
+    // bob accepts the relationship, then receives the messages
+    let mut bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+    let (alice_id, bob_id) = (alice.identifier().to_string(), bob.identifier().to_string());
+    let received = tokio::spawn(async move {
+        let crate::ReceivedTspMessage::RequestRelationship { thread_id, .. } =
+            bobs_messages.next().await.unwrap().unwrap()
+        else {
+            panic!("bob did not receive a relationship request")
+        };
+
+        bob_db
+            .send_relationship_accept(&bob_id, &alice_id, thread_id, None)
+            .await
+            .unwrap();
+
+        bobs_messages.take(2).collect::<Vec<_>>().await
+    });
+
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello bob")
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        alice_db.get_relation_status(bob.identifier()).unwrap(),
+        crate::RelationshipStatus::Bidirectional { .. }
+    ));
+    assert_eq!(
+        *statuses.lock().unwrap(),
+        [
+            crate::BootstrapStatus::Requested,
+            crate::BootstrapStatus::Accepted
+        ]
+    );
+
+    // once related, messages are sent right away
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello again")
+        .await
+        .unwrap();
+    assert_eq!(statuses.lock().unwrap().len(), 2);
+
+    let received = received.await.unwrap();
+    for (message, expected) in received
+        .into_iter()
+        .zip([&b"hello bob"[..], b"hello again"])
+    {
+        let crate::ReceivedTspMessage::GenericMessage { message, .. } = message.unwrap() else {
+            panic!("bob did not receive a generic message")
+        };
+        assert_eq!(message, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_ping() {
+    let alice = OwnedVid::new_did_peer("mem://ping-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://ping-bob".parse().unwrap());
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    let alices_messages = alice_db.receive(alice.identifier()).await.unwrap();
+    tokio::spawn(alices_messages.for_each(|_| async {}));
+
+    bob_db.set_answer_pings(true);
+    let bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+    let received = tokio::spawn(bobs_messages.take(1).collect::<Vec<_>>());
+
+    let timeout = std::time::Duration::from_secs(1);
+    let pong = alice_db
+        .ping(alice.identifier(), bob.identifier(), timeout)
+        .await
+        .unwrap();
+    assert!(pong.round_trip < timeout);
+    assert!(pong.answered_at > 0);
+
+    // the ping was answered without reaching bob's stream
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello")
+        .await
+        .unwrap();
+    let received = received.await.unwrap();
+    assert!(matches!(
+        &received[..],
+        [Ok(crate::ReceivedTspMessage::GenericMessage { message, .. })] if message == b"hello"
+    ));
+}
+
+#[tokio::test]
+async fn test_relationship_requirement() {
+    let alice = OwnedVid::new_did_peer("mem://requirement-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://requirement-bob".parse().unwrap());
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+    bob_db
+        .set_relationship_requirement(crate::RelationshipRequirement::Bidirectional)
+        .unwrap();
+    bob_db.set_relationship_notice(true);
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    let mut alices_messages = alice_db.receive(alice.identifier()).await.unwrap();
+    let mut bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+
+    // bob refuses the message, and tells alice a relationship is required
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello")
+        .await
+        .unwrap();
+    assert!(matches!(
+        bobs_messages.next().await.unwrap(),
+        Err(crate::Error::NoRelationship(sender)) if sender == alice.identifier()
+    ));
+    assert!(matches!(
+        alices_messages.next().await.unwrap(),
+        Ok(crate::ReceivedTspMessage::GenericMessage { content_type: Some(media_type), .. })
+            if media_type == crate::definitions::content_type::RELATIONSHIP_REQUIRED
+    ));
+
+    bob_db
+        .as_store()
+        .set_relation_status_for_vid(
+            alice.identifier(),
+            crate::RelationshipStatus::Bidirectional {
+                thread_id: Default::default(),
+                outstanding_nested_thread_ids: Vec::new(),
+                expires_at: None,
+            },
+        )
+        .unwrap();
+
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello again")
+        .await
+        .unwrap();
+    assert!(matches!(
+        bobs_messages.next().await.unwrap(),
+        Ok(crate::ReceivedTspMessage::GenericMessage { message, .. }) if message == b"hello again"
+    ));
+}
+
+#[tokio::test]
+async fn test_decryption_workers() {
+    let alice = OwnedVid::new_did_peer("mem://workers-alice".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("mem://workers-bob".parse().unwrap());
+    let carol = OwnedVid::new_did_peer("mem://workers-carol".parse().unwrap());
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+    bob_db.add_verified_vid(carol.vid().clone()).unwrap();
+    bob_db.set_decryption_workers(4);
+
+    let senders_db = AsyncStore::new();
+    senders_db.add_private_vid(alice.clone()).unwrap();
+    senders_db.add_private_vid(carol.clone()).unwrap();
+    senders_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    let bobs_messages = bob_db.receive(bob.identifier()).await.unwrap();
+    let received = tokio::spawn(bobs_messages.take(20).collect::<Vec<_>>());
+
+    let mut sent = Vec::new();
+    for i in 0..10 {
+        for sender in [&alice, &carol] {
+            let message = format!("message {i} from {}", sender.identifier());
+            senders_db
+                .send(
+                    sender.identifier(),
+                    bob.identifier(),
+                    None,
+                    message.as_bytes(),
+                )
+                .await
+                .unwrap();
+            sent.push(message.into_bytes());
+        }
+    }
+
+    // opened in parallel, but yielded in the order they were sent
+    let received: Vec<_> = received
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|message| {
+            let crate::ReceivedTspMessage::GenericMessage { message, .. } = message.unwrap() else {
+                panic!("bob did not receive a generic message")
+            };
+            message
+        })
+        .collect();
+    assert_eq!(received, sent);
+}
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn test_close_receive() {
+    let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1336".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+
+    let bob_db = AsyncStore::new();
+    bob_db.add_private_vid(bob.clone()).unwrap();
+    bob_db.add_verified_vid(alice.vid().clone()).unwrap();
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+
+    let (mut bobs_messages, handle) = bob_db.receive_with_handle(bob.identifier()).await.unwrap();
+
+    alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello")
+        .await
+        .unwrap();
+
+    let crate::ReceivedTspMessage::GenericMessage { message, .. } =
+        bobs_messages.next().await.unwrap().unwrap()
+    else {
+        panic!("bob did not receive a generic message")
+    };
+    assert_eq!(message, b"hello");
+
+    // the stream ends instead of waiting for the next message
+    handle.close();
+    assert!(handle.is_closed());
+
+    let end = tokio::time::timeout(std::time::Duration::from_secs(1), bobs_messages.next())
+        .await
+        .unwrap();
+    assert!(end.is_none());
+}
+
+#[tokio::test]
+#[serial_test::serial(tcp)]
+async fn test_circuit_breaker() {
+    let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1336".parse().unwrap());
+    let bob = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+
+    let alice_db = AsyncStore::new();
+    alice_db.add_private_vid(alice.clone()).unwrap();
+    alice_db.add_verified_vid(bob.vid().clone()).unwrap();
+    alice_db.set_delivery_config(crate::transport::DeliveryConfig {
+        retries: 1,
+        backoff: std::time::Duration::from_millis(10),
+        failure_threshold: Some(1),
+        ..Default::default()
+    });
+
+    // bob is not listening, so the connection is refused
+    let Err(crate::Error::Delivery(error)) = alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello")
+        .await
+    else {
+        panic!("sending to bob did not fail")
+    };
+    assert_eq!(error.attempts, 2);
+    assert!(!error.maybe_delivered());
+
+    // the endpoint is not tried again until the circuit is reset
+    let Err(crate::Error::Delivery(error)) = alice_db
+        .send(alice.identifier(), bob.identifier(), None, b"hello")
+        .await
+    else {
+        panic!("sending to bob did not fail")
+    };
+    assert_eq!(error.attempts, 0);
+    assert!(matches!(
+        error.reason,
+        crate::transport::DeliveryFailure::CircuitOpen(1)
+    ));
+    assert!(!error.maybe_delivered());
+}
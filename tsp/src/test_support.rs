@@ -0,0 +1,492 @@
+use crate::{
+    transport::{self, TransportError},
+    AsyncStore, Error, OwnedVid, ReceivedTspMessage, RelationshipStatus, VerifiedVid,
+};
+use futures::StreamExt;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep_until, timeout, Instant},
+};
+use url::Url;
+
+/// The conditions of the links between the nodes of a [TestNetwork]
+#[derive(Clone, Debug, Default)]
+pub struct LinkConditions {
+    /// The time every message takes to arrive
+    pub latency: Duration,
+    /// A random extra delay of up to this long per message, so messages that are sent
+    /// shortly after each other can arrive in another order
+    pub jitter: Duration,
+    /// The chance, from 0 to 1, that a message is lost
+    pub drop_rate: f64,
+}
+
+/// The state of a network, shared with the interceptors of its endpoints
+struct Conditions {
+    default: LinkConditions,
+    /// The links from one node to another with other conditions than the default
+    links: HashMap<(String, String), LinkConditions>,
+    /// The group of the nodes of a partitioned network; the nodes that are not in a group
+    /// form a group together
+    groups: HashMap<String, usize>,
+    /// The node of every VID, to tell which node sent a message
+    owners: HashMap<String, String>,
+    rng: StdRng,
+}
+
+impl Conditions {
+    /// When `message` arrives at `receiver`, or `None` if it is lost
+    fn arrival(&mut self, message: &[u8], receiver: &str) -> Result<Option<Instant>, io::Error> {
+        let sender = crate::cesr::get_sender_receiver(message)
+            .ok()
+            .and_then(|(sender, _)| std::str::from_utf8(sender).ok())
+            .and_then(|sender| self.owners.get(sender))
+            .cloned();
+
+        let conditions = match sender {
+            Some(sender) if self.groups.get(&sender) != self.groups.get(receiver) => {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            Some(sender) => self.links.get(&(sender, receiver.to_string())),
+            None => None,
+        }
+        .unwrap_or(&self.default)
+        .clone();
+
+        if self.rng.gen_bool(conditions.drop_rate.clamp(0.0, 1.0)) {
+            return Ok(None);
+        }
+
+        let jitter = conditions.jitter.mul_f64(self.rng.gen());
+
+        Ok(Some(Instant::now() + conditions.latency + jitter))
+    }
+}
+
+/// A store in a [TestNetwork], with a `did:peer` VID at its own `mem://` endpoint
+pub struct Node {
+    pub store: AsyncStore,
+    pub vid: OwnedVid,
+    messages: mpsc::UnboundedReceiver<Result<ReceivedTspMessage, Error>>,
+    receiver: JoinHandle<()>,
+}
+
+impl Node {
+    /// The identifier of the VID of this node
+    pub fn identifier(&self) -> &str {
+        self.vid.identifier()
+    }
+}
+
+/// Stores in the same process that reach each other over the `mem://` transport, through
+/// links with configurable latency, jitter and loss that can be partitioned
+///
+/// Every node verifies the VIDs of the other nodes, and receives on its VID from the
+/// moment it is added. The sender of a message is told by its envelope, so messages of
+/// other VIDs of a node, e.g. nested VIDs, pass the links of the node whose VID sealed the
+/// outer message.
+///
+/// ```no_run
+/// # async fn test() -> Result<(), tsp::Error> {
+/// use std::time::Duration;
+/// use tsp::test_support::{LinkConditions, TestNetwork};
+///
+/// let mut network = TestNetwork::new("example");
+/// let alice = network.add_node("alice").await?.identifier().to_string();
+/// let bob = network.add_node("bob").await?.identifier().to_string();
+///
+/// network.set_conditions(LinkConditions {
+///     latency: Duration::from_millis(20),
+///     ..Default::default()
+/// });
+///
+/// network.node("alice").store.send(&alice, &bob, None, b"hello").await?;
+/// network.assert_delivered("bob", Duration::from_secs(1)).await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TestNetwork {
+    name: String,
+    conditions: Arc<Mutex<Conditions>>,
+    nodes: BTreeMap<String, Node>,
+}
+
+impl TestNetwork {
+    /// An empty network with perfect links; `name` keeps its endpoints apart from those
+    /// of other tests that run at the same time
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            conditions: Arc::new(Mutex::new(Conditions {
+                default: LinkConditions::default(),
+                links: HashMap::new(),
+                groups: HashMap::new(),
+                owners: HashMap::new(),
+                rng: StdRng::seed_from_u64(0),
+            })),
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    fn conditions(&self) -> MutexGuard<'_, Conditions> {
+        self.conditions.lock().expect("network lock is poisoned")
+    }
+
+    /// Add a node with a new store, which verifies the VIDs of the other nodes
+    pub async fn add_node(&mut self, name: &str) -> Result<&Node, Error> {
+        self.add_node_with(name, |_| {}).await
+    }
+
+    /// Add a node like [TestNetwork::add_node], configuring its store with `configure`
+    /// before it starts receiving
+    pub async fn add_node_with(
+        &mut self,
+        name: &str,
+        configure: impl FnOnce(&mut AsyncStore),
+    ) -> Result<&Node, Error> {
+        let endpoint: Url = format!("mem://{}-{name}", self.name)
+            .parse()
+            .expect("invalid node name");
+        let vid = OwnedVid::new_did_peer(endpoint.clone());
+
+        let mut store = AsyncStore::new();
+        store.add_private_vid(vid.clone())?;
+        configure(&mut store);
+
+        for node in self.nodes.values() {
+            node.store.add_verified_vid(vid.vid().clone())?;
+            store.add_verified_vid(node.vid.vid().clone())?;
+        }
+
+        self.conditions()
+            .owners
+            .insert(vid.identifier().to_string(), name.to_string());
+
+        let (wire, in_flight) = mpsc::unbounded_channel();
+        tokio::spawn(carry(endpoint.clone(), in_flight));
+
+        let conditions = self.conditions.clone();
+        let receiver = name.to_string();
+        let address = endpoint.to_string();
+        transport::set_interceptor(
+            &endpoint,
+            Some(Arc::new(move |message: Vec<u8>| {
+                let arrival = conditions
+                    .lock()
+                    .expect("network lock is poisoned")
+                    .arrival(&message, &receiver)
+                    .map_err(|e| TransportError::Connection(address.clone(), e))?;
+
+                if let Some(arrival) = arrival {
+                    let _ = wire.send((arrival, message));
+                }
+
+                Ok(())
+            })),
+        );
+
+        let mut received = store.receive(vid.identifier()).await?;
+        let (messages, rx) = mpsc::unbounded_channel();
+        let receiver = tokio::spawn(async move {
+            while let Some(message) = received.next().await {
+                if messages.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.nodes.insert(
+            name.to_string(),
+            Node {
+                store,
+                vid,
+                messages: rx,
+                receiver,
+            },
+        );
+
+        Ok(&self.nodes[name])
+    }
+
+    /// The node called `name`
+    pub fn node(&self, name: &str) -> &Node {
+        self.nodes
+            .get(name)
+            .unwrap_or_else(|| panic!("no node called {name}"))
+    }
+
+    /// Use `conditions` for the links that are not configured otherwise
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.conditions().default = conditions;
+    }
+
+    /// Use `conditions` for the messages from `sender` to `receiver`
+    pub fn set_link_conditions(&self, sender: &str, receiver: &str, conditions: LinkConditions) {
+        self.conditions()
+            .links
+            .insert((sender.to_string(), receiver.to_string()), conditions);
+    }
+
+    /// Seed the random delays and losses, to reproduce a run
+    pub fn set_seed(&self, seed: u64) {
+        self.conditions().rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Split the network in `groups` of nodes that only reach the nodes in their own group;
+    /// the nodes that are not listed form another group. Sending to a node in another
+    /// group fails like a connection that times out, but messages that are already under
+    /// way still arrive
+    pub fn partition(&self, groups: &[&[&str]]) {
+        self.conditions().groups = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, nodes)| nodes.iter().map(move |node| (node.to_string(), group)))
+            .collect();
+    }
+
+    /// Undo a [TestNetwork::partition]
+    pub fn heal(&self) {
+        self.conditions().groups.clear();
+    }
+
+    /// The next message `node` received, or `None` if nothing arrives `within` this time
+    pub async fn next_message(
+        &mut self,
+        node: &str,
+        within: Duration,
+    ) -> Option<Result<ReceivedTspMessage, Error>> {
+        let node = self
+            .nodes
+            .get_mut(node)
+            .unwrap_or_else(|| panic!("no node called {node}"));
+
+        timeout(within, node.messages.recv()).await.ok().flatten()
+    }
+
+    /// Assert that `node` receives a message `within` this time, and return it
+    pub async fn assert_delivered(&mut self, node: &str, within: Duration) -> ReceivedTspMessage {
+        match self.next_message(node, within).await {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => panic!("{node} could not open a message: {e}"),
+            None => panic!("{node} received nothing within {within:?}"),
+        }
+    }
+
+    /// Assert that `node` receives nothing `within` this time
+    pub async fn assert_not_delivered(&mut self, node: &str, within: Duration) {
+        if let Some(message) = self.next_message(node, within).await {
+            panic!("{node} received {message:?}");
+        }
+    }
+
+    /// The status of the relationship with `other`, as recorded in the store of `node`
+    pub fn relationship(&self, node: &str, other: &str) -> RelationshipStatus {
+        self.node(node)
+            .store
+            .get_relation_status(self.node(other).identifier())
+            .expect("nodes verify each other's VIDs")
+    }
+
+    /// Assert that `a` and `b` both have a bidirectional relationship with the other
+    pub fn assert_bidirectional(&self, a: &str, b: &str) {
+        for (node, other) in [(a, b), (b, a)] {
+            let status = self.relationship(node, other);
+            assert!(
+                matches!(status, RelationshipStatus::Bidirectional { .. }),
+                "{node} has no bidirectional relationship with {other}: {status:?}"
+            );
+        }
+    }
+
+    /// Assert that neither `a` nor `b` has a relationship with the other
+    pub fn assert_unrelated(&self, a: &str, b: &str) {
+        for (node, other) in [(a, b), (b, a)] {
+            let status = self.relationship(node, other);
+            assert!(
+                matches!(status, RelationshipStatus::Unrelated),
+                "{node} has a relationship with {other}: {status:?}"
+            );
+        }
+    }
+}
+
+impl Drop for TestNetwork {
+    fn drop(&mut self) {
+        for node in self.nodes.values() {
+            transport::set_interceptor(node.vid.endpoint(), None);
+            node.receiver.abort();
+        }
+    }
+}
+
+/// Deliver the messages under way to `endpoint` at the time they arrive, in the order
+/// they were sent if they arrive at the same time
+async fn carry(endpoint: Url, mut wire: mpsc::UnboundedReceiver<(Instant, Vec<u8>)>) {
+    let mut in_flight = BinaryHeap::new();
+    let mut sent = 0u64;
+    let mut open = true;
+
+    while open || !in_flight.is_empty() {
+        let next_arrival = in_flight
+            .peek()
+            .map(|Reverse((arrival, _, _)): &Reverse<(Instant, u64, Vec<u8>)>| *arrival);
+
+        tokio::select! {
+            message = wire.recv(), if open => match message {
+                Some((arrival, message)) => {
+                    in_flight.push(Reverse((arrival, sent, message)));
+                    sent += 1;
+                }
+                None => open = false,
+            },
+            _ = sleep_until(next_arrival.unwrap_or_else(Instant::now)), if next_arrival.is_some() => {
+                if let Some(Reverse((_, _, message))) = in_flight.pop() {
+                    if let Err(e) = transport::deliver_in_memory(message, &endpoint).await {
+                        tracing::debug!("message for {endpoint} was lost: {e}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    async fn message(network: &mut TestNetwork, node: &str) -> Vec<u8> {
+        let ReceivedTspMessage::GenericMessage { message, .. } =
+            network.assert_delivered(node, SECOND).await
+        else {
+            panic!("{node} did not receive a generic message");
+        };
+
+        message
+    }
+
+    #[tokio::test]
+    async fn test_latency_and_loss() {
+        let mut network = TestNetwork::new("test-support-latency");
+        let alice = network
+            .add_node("alice")
+            .await
+            .unwrap()
+            .identifier()
+            .to_string();
+        let bob = network
+            .add_node("bob")
+            .await
+            .unwrap()
+            .identifier()
+            .to_string();
+
+        network.set_conditions(LinkConditions {
+            latency: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        let sent = Instant::now();
+        let store = network.node("alice").store.clone();
+        store.send(&alice, &bob, None, b"one").await.unwrap();
+        store.send(&alice, &bob, None, b"two").await.unwrap();
+        assert_eq!(message(&mut network, "bob").await, b"one");
+        assert_eq!(message(&mut network, "bob").await, b"two");
+        assert!(sent.elapsed() >= Duration::from_millis(100));
+
+        // with jitter, messages arrive in another order
+        network.set_conditions(LinkConditions {
+            jitter: Duration::from_millis(50),
+            ..Default::default()
+        });
+        let messages = (0..20u8).map(|i| vec![i]).collect::<Vec<_>>();
+        for message in &messages {
+            store.send(&alice, &bob, None, message).await.unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in &messages {
+            received.push(message(&mut network, "bob").await);
+        }
+        assert_ne!(received, messages);
+        received.sort();
+        assert_eq!(received, messages);
+
+        // lost messages never arrive, but sending them succeeds
+        network.set_link_conditions(
+            "alice",
+            "bob",
+            LinkConditions {
+                drop_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        store.send(&alice, &bob, None, b"lost").await.unwrap();
+        network
+            .assert_not_delivered("bob", Duration::from_millis(100))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_partition() {
+        let mut network = TestNetwork::new("test-support-partition");
+        let alice = network
+            .add_node("alice")
+            .await
+            .unwrap()
+            .identifier()
+            .to_string();
+        let bob = network
+            .add_node("bob")
+            .await
+            .unwrap()
+            .identifier()
+            .to_string();
+        network.add_node("carol").await.unwrap();
+
+        network.assert_unrelated("alice", "bob");
+
+        network.partition(&[&["alice"]]);
+        let store = network.node("alice").store.clone();
+        assert!(store.send(&alice, &bob, None, b"hello").await.is_err());
+        network
+            .assert_not_delivered("bob", Duration::from_millis(100))
+            .await;
+
+        // bob and carol are still connected
+        let carol = network.node("carol").identifier().to_string();
+        let bob_store = network.node("bob").store.clone();
+        bob_store.send(&bob, &carol, None, b"hi").await.unwrap();
+        assert_eq!(message(&mut network, "carol").await, b"hi");
+
+        network.heal();
+        store
+            .send_relationship_request(&alice, &bob, None)
+            .await
+            .unwrap();
+        let ReceivedTspMessage::RequestRelationship { thread_id, .. } =
+            network.assert_delivered("bob", SECOND).await
+        else {
+            panic!("bob did not receive a relationship request");
+        };
+
+        bob_store
+            .send_relationship_accept(&bob, &alice, thread_id, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            network.assert_delivered("alice", SECOND).await,
+            ReceivedTspMessage::AcceptRelationship { .. }
+        ));
+        network.assert_bidirectional("alice", "bob");
+    }
+}
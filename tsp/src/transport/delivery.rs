@@ -0,0 +1,213 @@
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use super::TransportError;
+
+/// Timeouts, retries, circuit breaking and concurrency limits applied when sending a
+/// message to an endpoint
+#[derive(Clone, Debug)]
+pub struct DeliveryConfig {
+    /// Give up on an attempt that takes longer than this
+    pub timeout: Duration,
+    /// Number of times a failed attempt is repeated; attempts are not repeated
+    /// if the endpoint can never accept the message, e.g. for an invalid address
+    pub retries: u32,
+    /// Wait this long before the first retry; the delay doubles for every next retry
+    pub backoff: Duration,
+    /// Stop sending to an endpoint after this many consecutive failed deliveries,
+    /// or never if it is `None`
+    pub failure_threshold: Option<u32>,
+    /// Try a single delivery to a stopped endpoint again after this delay;
+    /// if it succeeds, sending to the endpoint resumes
+    pub reset_after: Duration,
+    /// Send at most this many messages to an endpoint at the same time, or any number
+    /// if it is `None`; other messages wait and are sent in order of their
+    /// [`Priority`](super::Priority)
+    pub max_in_flight: Option<usize>,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            retries: 0,
+            backoff: Duration::from_millis(200),
+            failure_threshold: None,
+            reset_after: Duration::from_secs(30),
+            max_in_flight: None,
+        }
+    }
+}
+
+/// Why delivering a message failed
+#[derive(thiserror::Error, Debug)]
+pub enum DeliveryFailure {
+    #[error("{0}")]
+    Transport(TransportError),
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("endpoint stopped after {0} consecutive failures")]
+    CircuitOpen(u32),
+}
+
+/// A message could not be delivered to an endpoint
+#[derive(thiserror::Error, Debug)]
+#[error("delivery to '{endpoint}' failed after {attempts} attempt(s): {reason}")]
+pub struct DeliveryError {
+    pub endpoint: Url,
+    /// Number of attempts made; zero if the endpoint was stopped by the circuit breaker
+    pub attempts: u32,
+    pub reason: DeliveryFailure,
+}
+
+impl DeliveryError {
+    /// Whether the receiving endpoint may have accepted the message anyway, e.g. because
+    /// the last attempt timed out or the connection broke after the message was written.
+    /// If this is `false`, the message was certainly not delivered and can safely be sent
+    /// again; otherwise, sending it again may deliver it twice
+    pub fn maybe_delivered(&self) -> bool {
+        match &self.reason {
+            DeliveryFailure::Timeout(_) => true,
+            DeliveryFailure::CircuitOpen(_) => false,
+            DeliveryFailure::Transport(error) => !not_sent(error),
+        }
+    }
+}
+
+/// Errors that occur before any part of the message is sent
+fn not_sent(error: &TransportError) -> bool {
+    use std::io::ErrorKind;
+
+    match error {
+        TransportError::Http(_, e) => e.is_connect() || e.is_builder() || e.status().is_some(),
+        TransportError::Connection(_, e) => matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused | ErrorKind::AddrNotAvailable | ErrorKind::NotFound
+        ),
+        TransportError::QuicConnection(..) | TransportError::ListenPort => true,
+        _ => is_permanent(error),
+    }
+}
+
+/// Errors that repeating the attempt will not fix
+fn is_permanent(error: &TransportError) -> bool {
+    matches!(
+        error,
+        TransportError::InvalidTransportAddress(_)
+            | TransportError::InvalidTransportScheme(_)
+            | TransportError::InvalidProxy(_)
+            | TransportError::TLSConfiguration
+            | TransportError::TLSMissingFile(_)
+            | TransportError::TLSKey(_)
+    )
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Consecutive delivery failures per endpoint
+#[derive(Debug, Default)]
+pub(crate) struct Circuits(DashMap<String, Circuit>);
+
+impl Circuits {
+    /// Refuse delivery if the circuit of `endpoint` is open; once `reset_after` has passed,
+    /// a single delivery is let through and the circuit is reopened if that fails
+    fn check(&self, endpoint: &Url, config: &DeliveryConfig) -> Result<(), u32> {
+        let Some(threshold) = config.failure_threshold else {
+            return Ok(());
+        };
+
+        let Some(mut circuit) = self.0.get_mut(endpoint.as_str()) else {
+            return Ok(());
+        };
+
+        match circuit.opened_at {
+            Some(opened_at) if opened_at.elapsed() < config.reset_after => Err(circuit.failures),
+            Some(_) => {
+                // half-open: a failure of this delivery reopens the circuit immediately
+                circuit.failures = threshold.saturating_sub(1);
+                circuit.opened_at = None;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record(&self, endpoint: &Url, config: &DeliveryConfig, success: bool) {
+        if success {
+            self.0.remove(endpoint.as_str());
+            return;
+        }
+
+        let mut circuit = self.0.entry(endpoint.to_string()).or_default();
+        circuit.failures = circuit.failures.saturating_add(1);
+
+        if config
+            .failure_threshold
+            .is_some_and(|threshold| circuit.failures >= threshold)
+        {
+            tracing::warn!(
+                "stopped sending to {endpoint} after {} consecutive failures",
+                circuit.failures
+            );
+            circuit.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// Deliver a message with `send`, applying the timeout, retries and circuit breaker of `config`
+    pub(crate) async fn deliver<F, Fut>(
+        &self,
+        endpoint: &Url,
+        config: &DeliveryConfig,
+        send: F,
+    ) -> Result<(), DeliveryError>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<(), TransportError>>,
+    {
+        if let Err(failures) = self.check(endpoint, config) {
+            return Err(DeliveryError {
+                endpoint: endpoint.clone(),
+                attempts: 0,
+                reason: DeliveryFailure::CircuitOpen(failures),
+            });
+        }
+
+        let mut attempts = 0;
+        let mut backoff = config.backoff;
+
+        let reason = loop {
+            attempts += 1;
+
+            let reason = match tokio::time::timeout(config.timeout, send()).await {
+                Ok(Ok(())) => {
+                    self.record(endpoint, config, true);
+                    return Ok(());
+                }
+                Ok(Err(e)) => DeliveryFailure::Transport(e),
+                Err(_) => DeliveryFailure::Timeout(config.timeout),
+            };
+
+            let permanent = matches!(&reason, DeliveryFailure::Transport(e) if is_permanent(e));
+            if permanent || attempts > config.retries {
+                break reason;
+            }
+
+            tracing::debug!("sending to {endpoint} failed, retrying in {backoff:?}: {reason}");
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        };
+
+        self.record(endpoint, config, false);
+
+        Err(DeliveryError {
+            endpoint: endpoint.clone(),
+            attempts,
+            reason,
+        })
+    }
+}
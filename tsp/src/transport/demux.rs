@@ -0,0 +1,139 @@
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use url::Url;
+
+use super::{
+    inbox::{Inbox, TransportConfig},
+    TSPStream, TransportError,
+};
+
+/// Receive messages for several VIDs that may share endpoints, e.g. a broker, over a
+/// single transport stream per endpoint
+///
+/// `vids` lists every VID with the endpoints it is reached at. Each received message is
+/// passed to the VID it is addressed to, according to its envelope; messages without a
+/// receiver go to every VID at the endpoint, and messages for other VIDs or that cannot
+/// be decoded are dropped. Every VID gets its own queue, bounded by `config`; with
+/// [`OverflowPolicy::Park`](super::OverflowPolicy::Park), a VID whose stream is not
+/// consumed holds up the other VIDs at the same endpoint.
+pub async fn receive_demultiplexed(
+    vids: &[(String, Vec<Url>)],
+    config: &TransportConfig,
+) -> Result<BTreeMap<String, TSPStream<Vec<u8>, TransportError>>, TransportError> {
+    let mut streams = BTreeMap::new();
+    let mut endpoints = HashMap::<Url, HashMap<String, Inbox>>::new();
+
+    for (vid, vid_endpoints) in vids {
+        let (inbox, stream) = Inbox::new(config);
+        streams.insert(vid.clone(), stream);
+
+        for endpoint in vid_endpoints {
+            endpoints
+                .entry(endpoint.clone())
+                .or_default()
+                .insert(vid.clone(), inbox.clone());
+        }
+    }
+
+    for (endpoint, mut inboxes) in endpoints {
+        let mut messages = super::receive_messages_with_config(&endpoint, config).await?;
+
+        tokio::spawn(async move {
+            while let Some(message) = messages.next().await {
+                let message = match message {
+                    Ok(message) => message,
+                    Err(e) => {
+                        tracing::warn!("receiving on {endpoint} failed: {e}");
+                        continue;
+                    }
+                };
+
+                let receivers = match crate::cesr::get_sender_receiver(&message) {
+                    Ok((_, Some(receiver))) => {
+                        let receiver = String::from_utf8_lossy(receiver);
+                        if !inboxes.contains_key(receiver.as_ref()) {
+                            tracing::debug!("dropped message on {endpoint} for {receiver}");
+                            continue;
+                        }
+
+                        vec![receiver.into_owned()]
+                    }
+                    Ok((_, None)) => inboxes.keys().cloned().collect(),
+                    Err(e) => {
+                        tracing::warn!("dropped undecodable message on {endpoint}: {e}");
+                        continue;
+                    }
+                };
+
+                for receiver in receivers {
+                    // stop queuing for a VID once its stream is dropped
+                    if inboxes[&receiver]
+                        .deliver(Ok(message.clone()))
+                        .await
+                        .is_err()
+                    {
+                        inboxes.remove(&receiver);
+                    }
+                }
+
+                if inboxes.is_empty() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok(streams)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cesr::{CryptoType, Envelope, SignatureType};
+
+    fn envelope(receiver: Option<&[u8]>) -> Vec<u8> {
+        let mut message = Vec::new();
+        crate::cesr::encode_ets_envelope(
+            Envelope {
+                crypto_type: CryptoType::HpkeAuth,
+                signature_type: SignatureType::Ed25519,
+                sender: &b"sender"[..],
+                receiver,
+                nonconfidential_data: None,
+            },
+            &mut message,
+        )
+        .unwrap();
+        crate::cesr::encode_ciphertext(b"secret", &mut message).unwrap();
+        crate::cesr::encode_signature(&[1; 64], &mut message);
+
+        message
+    }
+
+    #[tokio::test]
+    #[serial_test::serial(tcp)]
+    async fn test_receive_demultiplexed() {
+        let url = Url::parse("tcp://localhost:12346").unwrap();
+        let vids = [
+            ("alice".to_string(), vec![url.clone()]),
+            ("bob".to_string(), vec![url.clone()]),
+        ];
+
+        let mut streams = receive_demultiplexed(&vids, &TransportConfig::default())
+            .await
+            .unwrap();
+        let mut alice = streams.remove("alice").unwrap();
+        let mut bob = streams.remove("bob").unwrap();
+
+        let for_bob = envelope(Some(b"bob"));
+        let for_carol = envelope(Some(b"carol"));
+        let for_alice = envelope(Some(b"alice"));
+
+        for message in [&for_bob, &for_carol, &for_alice] {
+            super::super::send_message(&url, message).await.unwrap();
+        }
+
+        assert_eq!(alice.next().await.unwrap().unwrap(), for_alice);
+        assert_eq!(bob.next().await.unwrap().unwrap(), for_bob);
+    }
+}
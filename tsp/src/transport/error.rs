@@ -6,10 +6,18 @@ pub enum TransportError {
     Connection(String, std::io::Error),
     #[error("connection to '{0}' failed: {1}")]
     QuicConnection(String, quinn::ConnectError),
+    #[error("gRPC call to '{0}' failed: {1}")]
+    Grpc(String, tonic::transport::Error),
+    #[error("gRPC call to '{0}' returned status {1}")]
+    GrpcStatus(String, String),
     #[error("invalid address '{0}'")]
     InvalidTransportAddress(String),
     #[error("invalid transport scheme '{0}'")]
     InvalidTransportScheme(String),
+    #[error("invalid proxy '{0}'")]
+    InvalidProxy(String),
+    #[error("proxy '{0}' failed: {1}")]
+    Proxy(String, String),
     #[error("websocket '{0}' failed: {1}")]
     Websocket(String, tokio_tungstenite::tungstenite::Error),
     #[error("invalid message received '{0}'")]
@@ -20,8 +28,12 @@ pub enum TransportError {
     TLSMissingFile(String),
     #[error("invalid TLS key '{0}'")]
     TLSKey(String),
+    #[error("invalid TLS certificate: {0}")]
+    TLSCertificate(String),
     #[error("{0}")]
     TLS(#[from] rustls::Error),
+    #[error("overloaded: {0}")]
+    Overloaded(String),
     #[error("internel error")]
     Internal,
     #[error("could not listen on random UDP port")]
@@ -0,0 +1,324 @@
+//! The `grpc://` transport, a client and server of the `tsp.v1.Transport` service in
+//! `proto/transport.proto`
+//!
+//! A `grpc://` endpoint is a path on a server of the service, e.g.
+//! `grpc://relay.example.com:4243/user/alice`. Sending to it calls `Submit`, and receiving
+//! on it calls `Receive`, which streams the messages submitted to the endpoint for as long
+//! as the call is open. [`grpc_service`] serves some endpoints with [tonic]; like an HTTP
+//! endpoint hosted by `router`, a hosted endpoint can only be received on by one call at a
+//! time, and submitting to it fails while nothing receives on it.
+
+use futures::StreamExt;
+use hyper_util::rt::TokioIo;
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, sync::Mutex};
+use tonic::{
+    service::Routes,
+    transport::{Channel, Endpoint},
+    Request, Response, Status, Streaming,
+};
+use url::Url;
+
+use super::{
+    inbox::{check_size, Inbox, TransportConfig},
+    pool::Pool,
+    TransportError,
+};
+use crate::definitions::TSPStream;
+
+mod proto {
+    tonic::include_proto!("tsp.v1");
+}
+
+use proto::{
+    transport_client::TransportClient,
+    transport_server::{Transport, TransportServer},
+    Empty, ReceiveRequest, TspMessage,
+};
+
+pub(crate) const SCHEME: &str = "grpc";
+
+/// Room for the other fields of a `TspMessage`, on top of the maximum message size
+const FIELD_OVERHEAD: usize = 4 * 1024;
+
+/// Idle client connections, keyed by transport address
+static POOL: Lazy<Pool<TransportClient<Channel>>> = Lazy::new(Default::default);
+
+pub(super) fn clear_pool() {
+    POOL.clear();
+}
+
+/// Open a new HTTP/2 connection to a gRPC server, through the configured proxy
+async fn connect(url: &Url) -> Result<TransportClient<Channel>, TransportError> {
+    let (Some(host), Some(port)) = (url.host_str(), url.port()) else {
+        return Err(TransportError::InvalidTransportAddress(url.to_string()));
+    };
+
+    let endpoint = Endpoint::from_shared(format!("http://{host}:{port}"))
+        .map_err(|_| TransportError::InvalidTransportAddress(url.to_string()))?;
+
+    let target = url.clone();
+    let channel = endpoint
+        .connect_with_connector(tower::service_fn(move |_| {
+            let target = target.clone();
+            async move { super::proxy::connect(&target).await.map(TokioIo::new) }
+        }))
+        .await
+        .map_err(|e| TransportError::Grpc(url.to_string(), e))?;
+
+    Ok(TransportClient::new(channel))
+}
+
+fn status_error(url: &Url, status: Status) -> TransportError {
+    TransportError::GrpcStatus(
+        url.to_string(),
+        format!("{:?}: {}", status.code(), status.message()),
+    )
+}
+
+/// Complete an HTTP/2 handshake with a gRPC server, without calling it
+pub(crate) async fn probe(url: &Url) -> Result<(), TransportError> {
+    connect(url).await?;
+
+    Ok(())
+}
+
+/// Send a message over gRPC (HTTP/2 without TLS)
+/// Calls `tsp.v1.Transport/Submit` for the path of the specified transport address.
+/// Connections are kept open and reused for subsequent messages to the same address.
+pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
+    let mut client = match POOL.take(url.as_str()) {
+        Some(client) => client,
+        None => connect(url).await?,
+    };
+
+    client
+        .submit(TspMessage {
+            message: tsp_message.to_vec(),
+            endpoint: url.path().to_string(),
+        })
+        .await
+        .map_err(|status| status_error(url, status))?;
+
+    POOL.put(url.as_str(), client);
+
+    Ok(())
+}
+
+/// Receive (multiple) messages over gRPC
+/// Calls `tsp.v1.Transport/Receive` for the path of the specified transport address, and
+/// yields the messages as the server streams them; messages larger than the configured
+/// maximum end the stream.
+pub(crate) async fn receive_messages(
+    address: &Url,
+    config: &TransportConfig,
+) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
+    let mut client = connect(address)
+        .await?
+        .max_decoding_message_size(config.max_message_size + FIELD_OVERHEAD);
+
+    let messages = client
+        .receive(ReceiveRequest {
+            endpoint: address.path().to_string(),
+        })
+        .await
+        .map_err(|status| status_error(address, status))?
+        .into_inner();
+
+    let address = address.clone();
+    let config = config.clone();
+
+    Ok(Box::pin(messages.map(move |message| match message {
+        Ok(message) => check_size(message.message, &config),
+        Err(status) => Err(status_error(&address, status)),
+    })))
+}
+
+/// A [tonic] service that hosts `endpoints`, to serve with [`tonic::transport::Server`]
+/// or merge into an axum router with [`Routes::into_axum_router`]
+///
+/// Every endpoint is identified by its path only; the service has to be reachable at the
+/// host and port of the endpoints. Endpoints with another scheme than `grpc` are ignored.
+/// Messages larger than `config.max_message_size` are refused.
+///
+/// ```no_run
+/// # async fn serve(store: tsp::AsyncStore) -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = "grpc://relay.example.com:4243/user/alice".parse()?;
+/// let service = tsp::transport::grpc_service([endpoint], &Default::default());
+///
+/// let address = "0.0.0.0:4243".parse()?;
+/// tokio::spawn(tonic::transport::Server::builder().add_routes(service).serve(address));
+///
+/// let messages = store.receive("did:web:relay.example.com:user:alice").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn grpc_service(endpoints: impl IntoIterator<Item = Url>, config: &TransportConfig) -> Routes {
+    let hosted = endpoints
+        .into_iter()
+        .filter(|endpoint| endpoint.scheme() == SCHEME)
+        .map(|endpoint| (endpoint.path().to_string(), None))
+        .collect();
+
+    let service = TransportServer::new(HostedEndpoints {
+        hosted: Mutex::new(hosted),
+        config: config.clone(),
+    })
+    .max_decoding_message_size(config.max_message_size + FIELD_OVERHEAD);
+
+    Routes::new(service)
+}
+
+/// The endpoints of a [grpc_service], by path, with the inbox of the call that receives
+/// on them, if any
+struct HostedEndpoints {
+    hosted: Mutex<HashMap<String, Option<Inbox>>>,
+    config: TransportConfig,
+}
+
+impl HostedEndpoints {
+    /// Pass a submitted message to the call that receives on its endpoint
+    async fn deliver(&self, message: TspMessage) -> Result<(), Status> {
+        let endpoint = &message.endpoint;
+
+        let receiver = {
+            let hosted = self
+                .hosted
+                .lock()
+                .map_err(|_| Status::internal("hosted endpoints lock is poisoned"))?;
+
+            match hosted.get(endpoint) {
+                Some(receiver) => receiver.clone(),
+                None => return Err(Status::not_found(format!("{endpoint} is not hosted here"))),
+            }
+        };
+
+        let Some(inbox) = receiver.filter(|inbox| !inbox.is_closed()) else {
+            return Err(Status::unavailable(format!(
+                "nothing receives on {endpoint}"
+            )));
+        };
+
+        if message.message.len() > inbox.max_message_size() {
+            return Err(Status::resource_exhausted(format!(
+                "messages are at most {} bytes",
+                inbox.max_message_size()
+            )));
+        }
+
+        inbox
+            .deliver(Ok(message.message))
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for HostedEndpoints {
+    async fn submit(&self, request: Request<TspMessage>) -> Result<Response<Empty>, Status> {
+        self.deliver(request.into_inner()).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn submit_stream(
+        &self,
+        request: Request<Streaming<TspMessage>>,
+    ) -> Result<Response<Empty>, Status> {
+        let mut messages = request.into_inner();
+
+        while let Some(message) = messages.message().await? {
+            self.deliver(message).await?;
+        }
+
+        Ok(Response::new(Empty {}))
+    }
+
+    type ReceiveStream = TSPStream<TspMessage, Status>;
+
+    async fn receive(
+        &self,
+        request: Request<ReceiveRequest>,
+    ) -> Result<Response<Self::ReceiveStream>, Status> {
+        let endpoint = request.into_inner().endpoint;
+
+        let mut hosted = self
+            .hosted
+            .lock()
+            .map_err(|_| Status::internal("hosted endpoints lock is poisoned"))?;
+
+        let Some(receiver) = hosted.get_mut(&endpoint) else {
+            return Err(Status::not_found(format!("{endpoint} is not hosted here")));
+        };
+
+        if receiver.as_ref().is_some_and(|inbox| !inbox.is_closed()) {
+            return Err(Status::already_exists(format!(
+                "another call receives on {endpoint}"
+            )));
+        }
+
+        let (inbox, messages) = Inbox::new(&self.config);
+        *receiver = Some(inbox);
+
+        // an error status would end the call, so messages dropped by the overflow
+        // policy are not reported to the receiver
+        let messages = messages
+            .filter_map(move |message| {
+                let endpoint = endpoint.clone();
+
+                async move { message.ok().map(|message| TspMessage { message, endpoint }) }
+            })
+            .map(Ok);
+
+        Ok(Response::new(Box::pin(messages)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tonic::transport::{server::TcpIncoming, Server};
+
+    #[tokio::test]
+    async fn test_grpc_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::parse(&format!("grpc://127.0.0.1:{port}/user/alice")).unwrap();
+        let message = b"Hello, world!";
+
+        let service = grpc_service([url.clone()], &TransportConfig::default());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_routes(service)
+                .serve_with_incoming(incoming),
+        );
+
+        // nothing receives yet
+        assert!(send_message(message, &url).await.is_err());
+
+        let mut incoming_stream = receive_messages(&url, &TransportConfig::default())
+            .await
+            .unwrap();
+        assert!(receive_messages(&url, &TransportConfig::default())
+            .await
+            .is_err());
+
+        send_message(message, &url).await.unwrap();
+        let received_message = incoming_stream.next().await.unwrap().unwrap();
+
+        assert_eq!(message, received_message.as_slice());
+
+        // the second message reuses the pooled connection
+        send_message(message, &url).await.unwrap();
+        let received_message = incoming_stream.next().await.unwrap().unwrap();
+
+        assert_eq!(message, received_message.as_slice());
+
+        // other paths are not hosted
+        let mut other = url.clone();
+        other.set_path("/user/bob");
+        assert!(send_message(message, &other).await.is_err());
+    }
+}
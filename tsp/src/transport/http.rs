@@ -1,9 +1,12 @@
 use crate::definitions::TSPStream;
 use async_stream::stream;
 use futures::StreamExt;
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
 use url::Url;
 
-use super::TransportError;
+use super::{inbox::TransportConfig, TransportError};
 
 pub(crate) const SCHEME_HTTP: &str = "http";
 pub(crate) const SCHEME_HTTPS: &str = "https";
@@ -11,8 +14,41 @@ pub(crate) const SCHEME_HTTPS: &str = "https";
 pub(crate) const SCHEME_WS: &str = "ws";
 pub(crate) const SCHEME_WSS: &str = "wss";
 
+/// Shared client, which keeps a pool of idle connections per host
+static CLIENT: Lazy<RwLock<Option<reqwest::Client>>> = Lazy::new(Default::default);
+
+pub(super) fn client() -> Result<reqwest::Client, reqwest::Error> {
+    if let Some(client) = CLIENT.read().ok().and_then(|c| c.clone()) {
+        return Ok(client);
+    }
+
+    let config = super::pool::config();
+    let mut builder = reqwest::Client::builder()
+        .pool_idle_timeout(config.idle_timeout)
+        .pool_max_idle_per_host(config.max_connections);
+
+    if let Some(proxy) = super::proxy::config() {
+        builder = builder.proxy(proxy.to_reqwest()?);
+    }
+
+    let client = builder.build()?;
+
+    if let Ok(mut shared) = CLIENT.write() {
+        *shared = Some(client.clone());
+    }
+
+    Ok(client)
+}
+
+/// Drop the shared client, so the next message uses a client with the current pool configuration
+pub(super) fn reset_client() {
+    if let Ok(mut shared) = CLIENT.write() {
+        *shared = None;
+    }
+}
+
 pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
-    let client = reqwest::Client::new();
+    let client = client().map_err(|e| TransportError::Http(url.to_string(), e))?;
     let url = url.clone();
 
     client
@@ -25,8 +61,44 @@ pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), Tr
     Ok(())
 }
 
+/// Send a `HEAD` request; any response, including an error status, means the
+/// endpoint is reachable
+pub(crate) async fn probe(url: &Url) -> Result<(), TransportError> {
+    let client = client().map_err(|e| TransportError::Http(url.to_string(), e))?;
+
+    client
+        .head(url.clone())
+        .send()
+        .await
+        .map_err(|e| TransportError::Http(url.to_string(), e))?;
+
+    Ok(())
+}
+
+/// Receive messages from the HTTP(S) endpoint as Server-Sent Events if it serves
+/// an event stream, otherwise over a websocket connection; endpoints hosted by a
+/// `router` receive the messages POSTed to it instead
 pub(crate) async fn receive_messages(
     address: &Url,
+    config: &TransportConfig,
+) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
+    #[cfg(feature = "server")]
+    if let Some(messages) = super::server::receive_messages(address, config)? {
+        return Ok(messages);
+    }
+
+    if let Some(response) = super::sse::connect(address).await? {
+        return Ok(super::sse::receive_messages(address, response, config));
+    }
+
+    receive_websocket_messages(address, config).await
+}
+
+/// Receive messages over a websocket connection to the HTTP(S) endpoint
+/// Messages are read one at a time, so no messages are buffered.
+async fn receive_websocket_messages(
+    address: &Url,
+    config: &TransportConfig,
 ) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
     let mut ws_address = address.clone();
 
@@ -37,11 +109,20 @@ pub(crate) async fn receive_messages(
     }
     .map_err(|_| TransportError::InvalidTransportScheme(address.scheme().to_owned()))?;
 
-    let ws_stream = match tokio_tungstenite::connect_async(&ws_address).await {
-        Ok((stream, _)) => stream,
-        Err(e) => return Err(TransportError::Websocket(ws_address.to_string(), e)),
+    let ws_config = WebSocketConfig {
+        max_message_size: Some(config.max_message_size),
+        max_frame_size: Some(config.max_message_size),
+        ..Default::default()
     };
 
+    let ws_stream =
+        match tokio_tungstenite::connect_async_with_config(&ws_address, Some(ws_config), false)
+            .await
+        {
+            Ok((stream, _)) => stream,
+            Err(e) => return Err(TransportError::Websocket(ws_address.to_string(), e)),
+        };
+
     let (_, mut receiver) = ws_stream.split();
 
     Ok(Box::pin(stream! {
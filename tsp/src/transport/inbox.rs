@@ -0,0 +1,211 @@
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::{io::AsyncRead, sync::mpsc};
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use super::TransportError;
+use crate::{
+    cesr::{error::DecodeError, FrameDecoder},
+    definitions::TSPStream,
+};
+
+/// What to do with a received message if the queue of in-flight messages is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Stop reading from the network until the consumer catches up; this pushes back
+    /// on the sending side (TCP flow control, HTTP/2 and QUIC stream limits)
+    #[default]
+    Park,
+    /// Discard the message; the stream reports the number of dropped messages as
+    /// [`TransportError::Overloaded`] before the next message is yielded
+    Drop,
+}
+
+/// Bounds on the messages buffered by [`receive_messages_with_config`](super::receive_messages_with_config)
+#[derive(Clone, Debug)]
+pub struct TransportConfig {
+    /// The maximum number of received messages that are queued but not yet consumed
+    pub max_in_flight: usize,
+    /// The maximum size in bytes of a single received message; larger messages are
+    /// rejected with [`TransportError::Overloaded`]
+    pub max_message_size: usize,
+    /// What to do with received messages if `max_in_flight` is reached
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 16,
+            max_message_size: 8 * 1024 * 1024,
+            overflow: OverflowPolicy::Park,
+        }
+    }
+}
+
+/// Sending half of the bounded queue between the connection handlers and the receive stream
+#[derive(Clone)]
+pub(super) struct Inbox {
+    tx: mpsc::Sender<Result<Vec<u8>, TransportError>>,
+    dropped: Arc<AtomicUsize>,
+    config: TransportConfig,
+}
+
+impl Inbox {
+    /// Create a bounded queue and the stream that consumes it
+    pub(super) fn new(config: &TransportConfig) -> (Inbox, TSPStream<Vec<u8>, TransportError>) {
+        let (tx, mut rx) = mpsc::channel(config.max_in_flight.max(1));
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let inbox = Inbox {
+            tx,
+            dropped: dropped.clone(),
+            config: config.clone(),
+        };
+
+        let stream = Box::pin(stream! {
+            while let Some(item) = rx.recv().await {
+                let count = dropped.swap(0, Ordering::Relaxed);
+                if count > 0 {
+                    yield Err(TransportError::Overloaded(format!("dropped {count} messages")));
+                }
+
+                yield item;
+            }
+        });
+
+        (inbox, stream)
+    }
+
+    /// Whether the receive stream has been dropped
+    pub(super) fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    pub(super) fn max_message_size(&self) -> usize {
+        self.config.max_message_size
+    }
+
+    /// Queue a received message according to the overflow policy
+    /// Fails if the receive stream has been dropped
+    pub(super) async fn deliver(
+        &self,
+        message: Result<Vec<u8>, TransportError>,
+    ) -> Result<(), TransportError> {
+        let message = message.and_then(|m| check_size(m, &self.config));
+
+        match self.config.overflow {
+            OverflowPolicy::Park => self
+                .tx
+                .send(message)
+                .await
+                .map_err(|_| TransportError::Internal),
+            OverflowPolicy::Drop => match self.tx.try_send(message) {
+                Ok(()) => Ok(()),
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+
+                    Ok(())
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => Err(TransportError::Internal),
+            },
+        }
+    }
+}
+
+/// Reject messages that exceed the configured maximum size
+pub(super) fn check_size(
+    message: Vec<u8>,
+    config: &TransportConfig,
+) -> Result<Vec<u8>, TransportError> {
+    if message.len() > config.max_message_size {
+        Err(TransportError::Overloaded(format!(
+            "message of {} bytes exceeds the maximum of {} bytes",
+            message.len(),
+            config.max_message_size
+        )))
+    } else {
+        Ok(message)
+    }
+}
+
+/// Split the data read from a connection with `peer` into TSP messages, in the text or
+/// binary domain, see [FrameDecoder]; the stream ends after the first error
+pub(super) fn split_messages(
+    connection: impl AsyncRead + Unpin,
+    peer: String,
+    config: &TransportConfig,
+) -> impl Stream<Item = Result<Vec<u8>, TransportError>> {
+    let mut chunks = FramedRead::new(connection, BytesCodec::new());
+    let mut frames = FrameDecoder::new(config.max_message_size);
+
+    stream! {
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(chunk) => frames.push(&chunk),
+                Err(e) => {
+                    yield Err(TransportError::Connection(peer, e));
+                    return;
+                }
+            }
+
+            loop {
+                match frames.next_message() {
+                    Ok(Some(message)) => yield Ok(message),
+                    Ok(None) => break,
+                    Err(e @ DecodeError::MessageTooLarge { .. }) => {
+                        yield Err(TransportError::Overloaded(e.to_string()));
+                        return;
+                    }
+                    Err(e) => {
+                        yield Err(TransportError::InvalidMessageReceived(e.to_string()));
+                        return;
+                    }
+                }
+            }
+        }
+
+        if !frames.is_empty() {
+            yield Err(TransportError::InvalidMessageReceived(format!(
+                "connection with {peer} closed halfway a message"
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_inbox_drop_policy() {
+        let config = TransportConfig {
+            max_in_flight: 1,
+            max_message_size: 4,
+            overflow: OverflowPolicy::Drop,
+        };
+        let (inbox, mut stream) = Inbox::new(&config);
+
+        inbox.deliver(Ok(b"one".to_vec())).await.unwrap();
+        inbox.deliver(Ok(b"two".to_vec())).await.unwrap();
+
+        // the second message did not fit in the queue
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(TransportError::Overloaded(_)))
+        ));
+        assert!(matches!(stream.next().await, Some(Ok(m)) if m == b"one"));
+
+        // messages that are too large are rejected
+        inbox.deliver(Ok(b"three".to_vec())).await.unwrap();
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(TransportError::Overloaded(_)))
+        ));
+    }
+}
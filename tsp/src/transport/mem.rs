@@ -0,0 +1,155 @@
+//! Delivery between stores in the same process, e.g. in tests and examples
+//!
+//! A `mem://` endpoint, like `mem://alice`, exists while a receive stream for it is alive.
+//! Messages sent to it are queued in memory instead of going over the network. Like a
+//! port, an endpoint can only be received on by one stream at a time.
+
+use once_cell::sync::Lazy;
+#[cfg(any(test, feature = "test-support"))]
+use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Mutex, MutexGuard},
+};
+use url::Url;
+
+use super::{
+    inbox::{Inbox, TransportConfig},
+    TSPStream, TransportError,
+};
+
+pub(crate) const SCHEME: &str = "mem";
+
+/// The inboxes of the endpoints that are received on
+static ENDPOINTS: Lazy<Mutex<HashMap<String, Inbox>>> = Lazy::new(Default::default);
+
+fn endpoints() -> MutexGuard<'static, HashMap<String, Inbox>> {
+    ENDPOINTS.lock().expect("memory transport lock is poisoned")
+}
+
+/// Takes the messages sent to an endpoint instead of queueing them, to [deliver] them
+/// later or not at all, e.g. to simulate an unreliable network
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) type Interceptor = Arc<dyn Fn(Vec<u8>) -> Result<(), TransportError> + Send + Sync>;
+
+/// The interceptors of the endpoints that have one
+#[cfg(any(test, feature = "test-support"))]
+static INTERCEPTORS: Lazy<Mutex<HashMap<String, Interceptor>>> = Lazy::new(Default::default);
+
+/// Pass the messages sent to `url` to `interceptor`, or queue them directly again if it
+/// is `None`
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) fn set_interceptor(url: &Url, interceptor: Option<Interceptor>) {
+    let mut interceptors = INTERCEPTORS
+        .lock()
+        .expect("memory transport lock is poisoned");
+
+    match interceptor {
+        Some(interceptor) => interceptors.insert(url.to_string(), interceptor),
+        None => interceptors.remove(url.as_str()),
+    };
+}
+
+/// The inbox of the endpoint `url`; fails like a refused connection if nothing receives on it
+fn inbox(url: &Url) -> Result<Inbox, TransportError> {
+    let mut endpoints = endpoints();
+
+    match endpoints.get(url.as_str()) {
+        Some(inbox) if !inbox.is_closed() => Ok(inbox.clone()),
+        _ => {
+            endpoints.remove(url.as_str());
+
+            Err(TransportError::Connection(
+                url.to_string(),
+                io::ErrorKind::ConnectionRefused.into(),
+            ))
+        }
+    }
+}
+
+/// Queue a message for the receive stream of `url`
+pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
+    #[cfg(any(test, feature = "test-support"))]
+    {
+        let interceptor = INTERCEPTORS
+            .lock()
+            .expect("memory transport lock is poisoned")
+            .get(url.as_str())
+            .cloned();
+
+        if let Some(interceptor) = interceptor {
+            // like a connection, sending fails if nothing receives
+            inbox(url)?;
+
+            return interceptor(tsp_message.to_vec());
+        }
+    }
+
+    deliver(tsp_message.to_vec(), url).await
+}
+
+/// Queue a message for the receive stream of `url`, bypassing its interceptor
+pub(crate) async fn deliver(tsp_message: Vec<u8>, url: &Url) -> Result<(), TransportError> {
+    inbox(url)?.deliver(Ok(tsp_message)).await
+}
+
+/// Check whether something receives on `url`
+pub(crate) async fn probe(url: &Url) -> Result<(), TransportError> {
+    inbox(url)?;
+
+    Ok(())
+}
+
+/// Receive the messages sent to `address`, until the stream is dropped
+pub(crate) async fn receive_messages(
+    address: &Url,
+    config: &TransportConfig,
+) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
+    let mut endpoints = endpoints();
+
+    if endpoints
+        .get(address.as_str())
+        .is_some_and(|inbox| !inbox.is_closed())
+    {
+        return Err(TransportError::Connection(
+            address.to_string(),
+            io::ErrorKind::AddrInUse.into(),
+        ));
+    }
+
+    let (inbox, messages) = Inbox::new(config);
+    endpoints.insert(address.to_string(), inbox);
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_memory_transport() {
+        let url = Url::parse("mem://test-memory-transport").unwrap();
+        let message = b"Hello, world!";
+
+        // nothing receives yet
+        assert!(send_message(message, &url).await.is_err());
+
+        let mut incoming_stream = receive_messages(&url, &TransportConfig::default())
+            .await
+            .unwrap();
+        assert!(receive_messages(&url, &TransportConfig::default())
+            .await
+            .is_err());
+
+        send_message(message, &url).await.unwrap();
+        let received_message = incoming_stream.next().await.unwrap().unwrap();
+        assert_eq!(message, received_message.as_slice());
+
+        // the endpoint is gone with its stream
+        drop(incoming_stream);
+        assert!(probe(&url).await.is_err());
+    }
+}
@@ -1,39 +1,163 @@
-use crate::definitions::TSPStream;
+use crate::{definitions::TSPStream, telemetry};
+use futures::StreamExt;
 use url::Url;
 
 pub mod error;
 
+mod delivery;
+mod demux;
+mod grpc;
 mod http;
+mod inbox;
+mod mem;
+mod pool;
+mod priority;
+mod probe;
+mod proxy;
 mod quic;
+#[cfg(feature = "server")]
+mod server;
+mod sse;
 mod tcp;
 mod tls;
 
+pub use delivery::{DeliveryConfig, DeliveryError, DeliveryFailure};
+pub use demux::receive_demultiplexed;
 pub use error::TransportError;
+pub use grpc::grpc_service;
+pub use inbox::{OverflowPolicy, TransportConfig};
+pub use pool::PoolConfig;
+pub use priority::Priority;
+pub use probe::{probe_endpoint, EndpointProbe};
+pub use proxy::ProxyConfig;
+#[cfg(feature = "server")]
+pub use server::router;
+pub use tls::TlsConfig;
 
+pub(crate) use delivery::Circuits;
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) use mem::{deliver as deliver_in_memory, set_interceptor};
+pub(crate) use priority::SendQueue;
+
+/// Configure the pool of outgoing connections; this drops all currently idle connections
+pub fn set_pool_config(config: PoolConfig) {
+    pool::set_config(config);
+    http::reset_client();
+    quic::clear_pool();
+    grpc::clear_pool();
+}
+
+/// Send outgoing messages through `proxy`, or connect directly if it is `None`;
+/// this replaces the proxy read from the `TSP_PROXY` or `ALL_PROXY` environment
+/// variables and drops all currently idle connections
+pub fn set_proxy_config(proxy: Option<ProxyConfig>) {
+    proxy::set_config(proxy);
+    http::reset_client();
+    quic::clear_pool();
+    grpc::clear_pool();
+}
+
+/// Use `config` for `tls` and `quic` connections, except to hosts with their own
+/// configuration; this drops all currently idle connections
+pub fn set_tls_config(config: TlsConfig) -> Result<(), TransportError> {
+    tls::set_config(config)?;
+    quic::clear_pool();
+
+    Ok(())
+}
+
+/// Use `config` for `tls` and `quic` connections to `host`, e.g. the root certificate of
+/// a private CA and a client certificate for a partner's endpoint, or the default
+/// configuration again if it is `None`; this drops all currently idle connections
+pub fn set_host_tls_config(host: &str, config: Option<TlsConfig>) -> Result<(), TransportError> {
+    tls::set_host_config(host, config)?;
+    quic::clear_pool();
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(scheme = transport.scheme(), len = tsp_message.len()))]
 pub async fn send_message(transport: &Url, tsp_message: &[u8]) -> Result<(), TransportError> {
-    match transport.scheme() {
+    let sent = match transport.scheme() {
         tcp::SCHEME => tcp::send_message(tsp_message, transport).await,
         tls::SCHEME => tls::send_message(tsp_message, transport).await,
         quic::SCHEME => quic::send_message(tsp_message, transport).await,
+        grpc::SCHEME => grpc::send_message(tsp_message, transport).await,
+        mem::SCHEME => mem::send_message(tsp_message, transport).await,
         http::SCHEME_HTTP => http::send_message(tsp_message, transport).await,
         http::SCHEME_HTTPS => http::send_message(tsp_message, transport).await,
         _ => Err(TransportError::InvalidTransportScheme(
             transport.scheme().to_string(),
         )),
+    };
+
+    if sent.is_ok() {
+        telemetry::increment_counter(telemetry::MESSAGES_SENT);
     }
+
+    sent
+}
+
+/// Send a message to `transport` or one of its `alternatives`, trying the endpoints in
+/// the order of the URL schemes in `preference` until one accepts the message.
+/// Endpoints with a scheme that is not listed are tried last, in the order they are given
+pub async fn send_message_with_fallback(
+    transport: &Url,
+    alternatives: &[Url],
+    preference: &[String],
+    tsp_message: &[u8],
+) -> Result<(), TransportError> {
+    let mut endpoints = std::iter::once(transport)
+        .chain(alternatives)
+        .collect::<Vec<_>>();
+
+    endpoints.sort_by_key(|endpoint| {
+        preference
+            .iter()
+            .position(|scheme| scheme == endpoint.scheme())
+            .unwrap_or(preference.len())
+    });
+
+    let (last, rest) = endpoints.split_last().expect("at least one endpoint");
+
+    for endpoint in rest {
+        match send_message(endpoint, tsp_message).await {
+            Ok(()) => return Ok(()),
+            Err(e) => tracing::debug!("sending to {endpoint} failed, trying next endpoint: {e}"),
+        }
+    }
+
+    send_message(last, tsp_message).await
 }
 
 pub async fn receive_messages(
     transport: &Url,
 ) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
-    match transport.scheme() {
-        tcp::SCHEME => tcp::receive_messages(transport).await,
-        tls::SCHEME => tls::receive_messages(transport).await,
-        quic::SCHEME => quic::receive_messages(transport).await,
-        http::SCHEME_HTTP => http::receive_messages(transport).await,
-        http::SCHEME_HTTPS => http::receive_messages(transport).await,
+    receive_messages_with_config(transport, &TransportConfig::default()).await
+}
+
+/// Receive messages, bounding the number of buffered messages and their size
+#[tracing::instrument(skip_all, fields(scheme = transport.scheme()))]
+pub async fn receive_messages_with_config(
+    transport: &Url,
+    config: &TransportConfig,
+) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
+    let messages = match transport.scheme() {
+        tcp::SCHEME => tcp::receive_messages(transport, config).await,
+        tls::SCHEME => tls::receive_messages(transport, config).await,
+        quic::SCHEME => quic::receive_messages(transport, config).await,
+        grpc::SCHEME => grpc::receive_messages(transport, config).await,
+        mem::SCHEME => mem::receive_messages(transport, config).await,
+        http::SCHEME_HTTP => http::receive_messages(transport, config).await,
+        http::SCHEME_HTTPS => http::receive_messages(transport, config).await,
         _ => Err(TransportError::InvalidTransportScheme(
             transport.scheme().to_string(),
         )),
-    }
+    }?;
+
+    Ok(Box::pin(messages.inspect(|message| {
+        if message.is_ok() {
+            telemetry::increment_counter(telemetry::MESSAGES_RECEIVED);
+        }
+    })))
 }
@@ -0,0 +1,111 @@
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Configuration of the pool of outgoing connections that are reused across
+/// [`send_message`](super::send_message) calls
+///
+/// Connections are pooled for the `http(s)`, `quic` and `grpc` transports. The `tcp` and `tls`
+/// transports delimit messages by closing the connection and therefore are never reused.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// How long an unused connection is kept open
+    pub idle_timeout: Duration,
+    /// The maximum number of idle connections kept per endpoint
+    pub max_connections: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(90),
+            max_connections: 8,
+        }
+    }
+}
+
+static CONFIG: Lazy<RwLock<PoolConfig>> = Lazy::new(Default::default);
+
+pub(super) fn config() -> PoolConfig {
+    CONFIG.read().map(|c| c.clone()).unwrap_or_default()
+}
+
+pub(super) fn set_config(config: PoolConfig) {
+    if let Ok(mut current) = CONFIG.write() {
+        *current = config;
+    }
+}
+
+/// Idle connections, keyed by endpoint
+pub(super) struct Pool<C> {
+    idle: Mutex<HashMap<String, Vec<(C, Instant)>>>,
+}
+
+impl<C> Default for Pool<C> {
+    fn default() -> Self {
+        Self {
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<C> Pool<C> {
+    /// Take the most recently used connection to `endpoint` that has not expired
+    pub(super) fn take(&self, endpoint: &str) -> Option<C> {
+        let idle_timeout = config().idle_timeout;
+        let mut idle = self.idle.lock().ok()?;
+        let connections = idle.get_mut(endpoint)?;
+
+        connections.retain(|(_, last_used)| last_used.elapsed() < idle_timeout);
+
+        connections.pop().map(|(connection, _)| connection)
+    }
+
+    /// Return a connection to `endpoint` to the pool; it is dropped if the pool is full
+    pub(super) fn put(&self, endpoint: &str, connection: C) {
+        let max_connections = config().max_connections;
+        let Ok(mut idle) = self.idle.lock() else {
+            return;
+        };
+
+        let connections = idle.entry(endpoint.to_string()).or_default();
+        if connections.len() < max_connections {
+            connections.push((connection, Instant::now()));
+        }
+    }
+
+    /// Drop all idle connections
+    pub(super) fn clear(&self) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_reuse() {
+        let pool = Pool::default();
+
+        assert_eq!(pool.take("a"), None);
+
+        pool.put("a", 1);
+        pool.put("a", 2);
+        pool.put("b", 3);
+
+        assert_eq!(pool.take("a"), Some(2));
+        assert_eq!(pool.take("a"), Some(1));
+        assert_eq!(pool.take("a"), None);
+        assert_eq!(pool.take("b"), Some(3));
+
+        pool.put("a", 4);
+        pool.clear();
+        assert_eq!(pool.take("a"), None);
+    }
+}
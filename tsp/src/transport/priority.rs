@@ -0,0 +1,212 @@
+use std::{collections::HashMap, sync::Mutex};
+use tokio::sync::Notify;
+use url::Url;
+
+/// How urgently a message has to be delivered
+///
+/// When more messages are sent to an endpoint at the same time than
+/// [`DeliveryConfig::max_in_flight`](super::DeliveryConfig::max_in_flight) allows, the
+/// waiting messages with the highest priority are sent first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Large transfers that can wait, e.g. files
+    Bulk,
+    /// Messages someone is waiting for
+    #[default]
+    Interactive,
+    /// Relationship and session control messages, and acknowledgements
+    Control,
+}
+
+const PRIORITIES: usize = 3;
+
+#[derive(Debug, Default)]
+struct Lane {
+    in_flight: usize,
+    waiting: [usize; PRIORITIES],
+}
+
+impl Lane {
+    fn is_idle(&self) -> bool {
+        self.in_flight == 0 && self.waiting.iter().all(|waiting| *waiting == 0)
+    }
+}
+
+/// The messages that are being sent, and waiting to be sent, per endpoint
+#[derive(Debug, Default)]
+pub(crate) struct SendQueue {
+    lanes: Mutex<HashMap<String, Lane>>,
+    released: Notify,
+}
+
+/// Permission to send a message to an endpoint; the next message may go once it is dropped
+pub(crate) struct Slot<'a> {
+    queue: &'a SendQueue,
+    endpoint: Option<String>,
+}
+
+/// A message that waits for a slot
+struct Waiter<'a> {
+    queue: &'a SendQueue,
+    endpoint: &'a str,
+    priority: Priority,
+    registered: bool,
+}
+
+impl SendQueue {
+    fn lanes(&self) -> std::sync::MutexGuard<'_, HashMap<String, Lane>> {
+        self.lanes.lock().expect("send queue lock is poisoned")
+    }
+
+    /// Wait until a message with `priority` may be sent to `endpoint`: at most `limit`
+    /// messages are sent to an endpoint at the same time, and waiting messages with a
+    /// higher priority go first. Without a limit, messages are sent right away
+    pub(crate) async fn acquire(
+        &self,
+        endpoint: &Url,
+        priority: Priority,
+        limit: Option<usize>,
+    ) -> Slot<'_> {
+        let Some(limit) = limit else {
+            return Slot {
+                queue: self,
+                endpoint: None,
+            };
+        };
+
+        let mut waiter = Waiter {
+            queue: self,
+            endpoint: endpoint.as_str(),
+            priority,
+            registered: false,
+        };
+
+        loop {
+            // listen before checking, so a slot released in between is not missed
+            let released = self.released.notified();
+
+            if waiter.try_take(limit.max(1)) {
+                return Slot {
+                    queue: self,
+                    endpoint: Some(endpoint.to_string()),
+                };
+            }
+
+            released.await;
+        }
+    }
+}
+
+impl Waiter<'_> {
+    fn try_take(&mut self, limit: usize) -> bool {
+        let mut lanes = self.queue.lanes();
+        let lane = lanes.entry(self.endpoint.to_string()).or_default();
+        let index = self.priority as usize;
+        let ahead = lane.waiting[index + 1..].iter().sum::<usize>();
+
+        if lane.in_flight >= limit || ahead > 0 {
+            if !self.registered {
+                lane.waiting[index] += 1;
+                self.registered = true;
+            }
+
+            return false;
+        }
+
+        lane.in_flight += 1;
+
+        if self.registered {
+            lane.waiting[index] -= 1;
+            self.registered = false;
+
+            // waiters of a lower priority may use the slots that are still free
+            if lane.in_flight < limit {
+                self.queue.released.notify_waiters();
+            }
+        }
+
+        true
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+
+        let mut lanes = self.queue.lanes();
+        if let Some(lane) = lanes.get_mut(self.endpoint) {
+            lane.waiting[self.priority as usize] -= 1;
+
+            if lane.is_idle() {
+                lanes.remove(self.endpoint);
+            }
+        }
+
+        self.queue.released.notify_waiters();
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let Some(endpoint) = &self.endpoint else {
+            return;
+        };
+
+        let mut lanes = self.queue.lanes();
+        if let Some(lane) = lanes.get_mut(endpoint) {
+            lane.in_flight -= 1;
+
+            if lane.is_idle() {
+                lanes.remove(endpoint);
+            }
+        }
+
+        self.queue.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_priority_order() {
+        let queue = Arc::new(SendQueue::default());
+        let endpoint: Url = "tcp://127.0.0.1:1337".parse().unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // occupy the only slot, so every next message has to wait
+        let slot = queue.acquire(&endpoint, Priority::Bulk, Some(1)).await;
+
+        let mut tasks = Vec::new();
+        for priority in [Priority::Bulk, Priority::Interactive, Priority::Control] {
+            let (queue, endpoint, order) = (queue.clone(), endpoint.clone(), order.clone());
+
+            tasks.push(tokio::spawn(async move {
+                let _slot = queue.acquire(&endpoint, priority, Some(1)).await;
+                order.lock().unwrap().push(priority);
+            }));
+
+            // let the task register as waiting
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        drop(slot);
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::Control, Priority::Interactive, Priority::Bulk]
+        );
+        assert!(queue.lanes().is_empty());
+
+        // without a limit, nothing waits
+        let _first = queue.acquire(&endpoint, Priority::Bulk, None).await;
+        let _second = queue.acquire(&endpoint, Priority::Bulk, None).await;
+    }
+}
@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+use url::Url;
+
+use super::{grpc, http, mem, quic, tcp, tls, DeliveryFailure, TransportError};
+
+/// Whether an endpoint could be reached, see [probe_endpoint]
+#[derive(Debug)]
+pub struct EndpointProbe {
+    pub endpoint: Url,
+    /// Time until the endpoint answered, or until the probe failed
+    pub elapsed: Duration,
+    /// Why the endpoint could not be reached, if it could not
+    pub failure: Option<DeliveryFailure>,
+}
+
+impl EndpointProbe {
+    pub fn is_reachable(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    /// Time until the endpoint answered, if it was reached
+    pub fn latency(&self) -> Option<Duration> {
+        self.is_reachable().then_some(self.elapsed)
+    }
+}
+
+/// Check whether `endpoint` can be reached within `timeout`, without sending a message:
+/// TCP endpoints are dialed, TLS, QUIC and gRPC endpoints complete a handshake, and
+/// HTTP(S) endpoints are sent a `HEAD` request. In-memory endpoints are reachable while
+/// something receives on them
+pub async fn probe_endpoint(endpoint: &Url, timeout: Duration) -> EndpointProbe {
+    let start = Instant::now();
+
+    let probe = async {
+        match endpoint.scheme() {
+            tcp::SCHEME => tcp::probe(endpoint).await,
+            tls::SCHEME => tls::probe(endpoint).await,
+            quic::SCHEME => quic::probe(endpoint).await,
+            grpc::SCHEME => grpc::probe(endpoint).await,
+            mem::SCHEME => mem::probe(endpoint).await,
+            http::SCHEME_HTTP | http::SCHEME_HTTPS => http::probe(endpoint).await,
+            _ => Err(TransportError::InvalidTransportScheme(
+                endpoint.scheme().to_string(),
+            )),
+        }
+    };
+
+    let failure = match tokio::time::timeout(timeout, probe).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(DeliveryFailure::Transport(e)),
+        Err(_) => Some(DeliveryFailure::Timeout(timeout)),
+    };
+
+    EndpointProbe {
+        endpoint: endpoint.clone(),
+        elapsed: start.elapsed(),
+        failure,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_probe_tcp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url: Url = format!("tcp://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+
+        let probe = probe_endpoint(&url, Duration::from_secs(5)).await;
+        assert!(probe.is_reachable());
+        assert!(probe.latency().is_some());
+
+        drop(listener);
+        let probe = probe_endpoint(&url, Duration::from_secs(5)).await;
+        assert!(matches!(probe.failure, Some(DeliveryFailure::Transport(_))));
+        assert_eq!(probe.latency(), None);
+
+        let url = "ftp://127.0.0.1:21".parse().unwrap();
+        let probe = probe_endpoint(&url, Duration::from_secs(5)).await;
+        assert!(matches!(
+            probe.failure,
+            Some(DeliveryFailure::Transport(
+                TransportError::InvalidTransportScheme(_)
+            ))
+        ));
+    }
+}
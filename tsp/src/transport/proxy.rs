@@ -0,0 +1,534 @@
+use base64ct::{Base64, Encoding};
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use url::Url;
+
+use super::TransportError;
+
+const SCHEME_SOCKS5: &str = "socks5";
+const SCHEME_SOCKS5H: &str = "socks5h";
+const SCHEME_HTTP: &str = "http";
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_USERNAME_PASSWORD: u8 = 2;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+
+/// The maximum size of the response headers of an HTTP proxy
+const MAX_RESPONSE_SIZE: usize = 8 * 1024;
+
+/// A proxy that outgoing connections are made through, for networks without direct access
+///
+/// SOCKS5 proxies are supported by all transports but QUIC, HTTP proxies (using `CONNECT`)
+/// by the `http(s)`, `tcp`, `tls` and `grpc` transports. QUIC runs over UDP, which neither
+/// kind of proxy forwards here; sending over QUIC to a proxied host fails instead of
+/// silently bypassing the proxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// The `socks5://`, `socks5h://` or `http://` URL of the proxy; credentials are
+    /// taken from its user info. With `socks5` host names are resolved locally,
+    /// with `socks5h` by the proxy
+    pub url: Url,
+    /// Hosts that are connected to directly; an entry matches the host itself and
+    /// its subdomains, `*` matches all hosts
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn new(url: Url) -> Result<Self, TransportError> {
+        if ![SCHEME_SOCKS5, SCHEME_SOCKS5H, SCHEME_HTTP].contains(&url.scheme())
+            || url.host_str().is_none()
+        {
+            return Err(TransportError::InvalidProxy(url.to_string()));
+        }
+
+        Ok(Self {
+            url,
+            no_proxy: Vec::new(),
+        })
+    }
+
+    /// Authenticate with `username` and `password` to the proxy
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        // only fails for URLs without a host, which `new` rejects
+        let _ = self.url.set_username(username);
+        let _ = self.url.set_password(Some(password));
+
+        self
+    }
+
+    /// Connect to `hosts` directly
+    pub fn with_no_proxy(mut self, hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.no_proxy.extend(hosts.into_iter().map(Into::into));
+
+        self
+    }
+
+    /// Read the proxy from the `TSP_PROXY` environment variable, or `ALL_PROXY` if it
+    /// is not set, and the hosts to connect to directly from `NO_PROXY`
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .or_else(|_| std::env::var(name.to_lowercase()))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+
+        let url = var("TSP_PROXY").or_else(|| var("ALL_PROXY"))?;
+        let config = match Url::parse(&url) {
+            Ok(url) => Self::new(url),
+            Err(_) => Err(TransportError::InvalidProxy(url)),
+        };
+
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("ignoring proxy configuration: {e}");
+                return None;
+            }
+        };
+
+        let no_proxy = var("NO_PROXY").unwrap_or_default();
+
+        Some(
+            config.with_no_proxy(
+                no_proxy
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|host| !host.is_empty()),
+            ),
+        )
+    }
+
+    /// Whether connections to `host` bypass the proxy
+    pub fn bypasses(&self, host: &str) -> bool {
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.');
+
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+        })
+    }
+
+    /// The URL of the proxy without credentials, for error messages
+    fn label(&self) -> String {
+        let mut url = self.url.clone();
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
+        url.to_string()
+    }
+
+    /// The proxy to use for a connection to `host`, if any
+    fn for_host(&self, host: &str) -> Option<&Self> {
+        (!self.bypasses(host)).then_some(self)
+    }
+
+    fn credentials(&self) -> Option<(String, String)> {
+        if self.url.username().is_empty() {
+            return None;
+        }
+
+        let decode = |value: &str| {
+            percent_encoding::percent_decode_str(value)
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+
+        Some((
+            decode(self.url.username()),
+            decode(self.url.password().unwrap_or_default()),
+        ))
+    }
+
+    /// The proxy for the shared HTTP client
+    pub(super) fn to_reqwest(&self) -> Result<reqwest::Proxy, reqwest::Error> {
+        let no_proxy = reqwest::NoProxy::from_string(&self.no_proxy.join(","));
+
+        Ok(reqwest::Proxy::all(self.url.as_str())?.no_proxy(no_proxy))
+    }
+}
+
+/// The proxy configuration, initially read from the environment
+static CONFIG: Lazy<RwLock<Option<ProxyConfig>>> =
+    Lazy::new(|| RwLock::new(ProxyConfig::from_env()));
+
+pub(super) fn config() -> Option<ProxyConfig> {
+    CONFIG.read().ok().and_then(|config| config.clone())
+}
+
+pub(super) fn set_config(config: Option<ProxyConfig>) {
+    if let Ok(mut current) = CONFIG.write() {
+        *current = config;
+    }
+}
+
+/// Whether connections to the host of `url` go through a proxy
+pub(super) fn is_proxied(url: &Url) -> bool {
+    match (config(), url.host_str()) {
+        (Some(config), Some(host)) => !config.bypasses(host),
+        _ => false,
+    }
+}
+
+/// The host and port of `url`
+fn host_and_port(url: &Url) -> Result<(&str, u16), TransportError> {
+    let invalid = || TransportError::InvalidTransportAddress(url.to_string());
+    let host = url.host_str().ok_or_else(invalid)?;
+    let port = url.port_or_known_default().ok_or_else(invalid)?;
+
+    // IPv6 addresses are enclosed in brackets in URLs
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+/// Open a TCP connection to the host and port of `url`, through the configured proxy
+/// unless the host is excluded from it
+pub(super) async fn connect(url: &Url) -> Result<TcpStream, TransportError> {
+    let (host, port) = host_and_port(url)?;
+
+    match config().as_ref().and_then(|config| config.for_host(host)) {
+        Some(proxy) => connect_with_proxy(proxy, host, port).await,
+        None => connect_direct(url).await,
+    }
+}
+
+async fn connect_direct(url: &Url) -> Result<TcpStream, TransportError> {
+    let addresses = url
+        .socket_addrs(|| None)
+        .map_err(|_| TransportError::InvalidTransportAddress(url.to_string()))?;
+
+    let Some(address) = addresses.first() else {
+        return Err(TransportError::InvalidTransportAddress(url.to_string()));
+    };
+
+    TcpStream::connect(address)
+        .await
+        .map_err(|e| TransportError::Connection(address.to_string(), e))
+}
+
+/// Open a TCP connection to `host` and `port` through `proxy`
+async fn connect_with_proxy(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, TransportError> {
+    let mut proxy_address = proxy.url.clone();
+    if proxy_address.port().is_none() && proxy.url.scheme() != SCHEME_HTTP {
+        let _ = proxy_address.set_port(Some(1080));
+    }
+
+    let mut stream = connect_direct(&proxy_address).await?;
+    let io_error = |e| TransportError::Connection(proxy.label(), e);
+
+    match proxy.url.scheme() {
+        SCHEME_HTTP => http_connect(proxy, &mut stream, host, port).await,
+        SCHEME_SOCKS5 => {
+            let address = resolve(host, port).await?;
+
+            socks5_connect(proxy, &mut stream, Target::Address(address)).await
+        }
+        _ => socks5_connect(proxy, &mut stream, Target::Domain(host, port)).await,
+    }
+    .map_err(|e| match e {
+        ProxyError::Io(e) => io_error(e),
+        ProxyError::Refused(reason) => TransportError::Proxy(proxy.label(), reason),
+    })?;
+
+    Ok(stream)
+}
+
+enum ProxyError {
+    Io(std::io::Error),
+    Refused(String),
+}
+
+impl From<std::io::Error> for ProxyError {
+    fn from(e: std::io::Error) -> Self {
+        ProxyError::Io(e)
+    }
+}
+
+fn refused(reason: impl Into<String>) -> ProxyError {
+    ProxyError::Refused(reason.into())
+}
+
+/// Resolve `host` locally, for proxies that expect an address
+async fn resolve(host: &str, port: u16) -> Result<std::net::SocketAddr, TransportError> {
+    let invalid = || TransportError::InvalidTransportAddress(format!("{host}:{port}"));
+
+    tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| invalid())?
+        .next()
+        .ok_or_else(invalid)
+}
+
+/// Where a SOCKS5 proxy should connect to
+enum Target<'a> {
+    Address(std::net::SocketAddr),
+    Domain(&'a str, u16),
+}
+
+/// Establish a tunnel with the SOCKS5 protocol (RFC 1928), authenticating with a
+/// username and password (RFC 1929) if the proxy URL has credentials
+async fn socks5_connect(
+    proxy: &ProxyConfig,
+    stream: &mut TcpStream,
+    target: Target<'_>,
+) -> Result<(), ProxyError> {
+    let credentials = proxy.credentials();
+
+    let methods: &[u8] = match credentials {
+        Some(_) => &[SOCKS_NO_AUTH, SOCKS_USERNAME_PASSWORD],
+        None => &[SOCKS_NO_AUTH],
+    };
+
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+
+    match (choice, &credentials) {
+        ([SOCKS_VERSION, SOCKS_NO_AUTH], _) => {}
+        ([SOCKS_VERSION, SOCKS_USERNAME_PASSWORD], Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(refused("username or password too long for SOCKS5"));
+            }
+
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            let mut status = [0; 2];
+            stream.read_exact(&mut status).await?;
+
+            if status[1] != 0 {
+                return Err(refused("authentication failed"));
+            }
+        }
+        _ => return Err(refused("no acceptable authentication method")),
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+    let port = match target {
+        Target::Address(std::net::SocketAddr::V4(address)) => {
+            request.push(SOCKS_IPV4);
+            request.extend_from_slice(&address.ip().octets());
+            address.port()
+        }
+        Target::Address(std::net::SocketAddr::V6(address)) => {
+            request.push(SOCKS_IPV6);
+            request.extend_from_slice(&address.ip().octets());
+            address.port()
+        }
+        Target::Domain(host, port) => {
+            if host.len() > 255 {
+                return Err(refused("host name too long for SOCKS5"));
+            }
+
+            request.extend_from_slice(&[SOCKS_DOMAIN, host.len() as u8]);
+            request.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+
+    if reply[0] != SOCKS_VERSION {
+        return Err(refused("not a SOCKS5 proxy"));
+    }
+
+    if reply[1] != 0 {
+        let reason = match reply[1] {
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            _ => "general failure",
+        };
+
+        return Err(refused(reason));
+    }
+
+    // skip the address the proxy bound to, the tunnel is usable after it
+    let bound_address_size = match reply[3] {
+        SOCKS_IPV4 => 4,
+        SOCKS_IPV6 => 16,
+        SOCKS_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(refused("invalid address type in reply")),
+    };
+    let mut bound_address = vec![0; bound_address_size + 2];
+    stream.read_exact(&mut bound_address).await?;
+
+    Ok(())
+}
+
+/// Establish a tunnel with an HTTP `CONNECT` request, authenticating with basic
+/// authentication if the proxy URL has credentials
+async fn http_connect(
+    proxy: &ProxyConfig,
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+) -> Result<(), ProxyError> {
+    let authority = match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    };
+
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some((username, password)) = proxy.credentials() {
+        let token = Base64::encode_string(format!("{username}:{password}").as_bytes());
+        request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // read byte by byte, so no data from the tunnel is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_SIZE {
+            return Err(refused("response headers too large"));
+        }
+
+        response.push(stream.read_u8().await?);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status_line = status_line.lines().next().unwrap_or_default();
+
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(refused(format!("CONNECT failed: {status_line}"))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_no_proxy() {
+        let config = ProxyConfig::new("socks5://127.0.0.1:1080".parse().unwrap())
+            .unwrap()
+            .with_no_proxy(["localhost", ".internal.example.com"]);
+
+        assert!(config.bypasses("localhost"));
+        assert!(config.bypasses("did.internal.example.com"));
+        assert!(config.bypasses("Internal.Example.com"));
+        assert!(!config.bypasses("example.com"));
+        assert!(!config.bypasses("notinternal.example.com"));
+
+        assert!(ProxyConfig::new("ftp://127.0.0.1".parse().unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut tunneled = Vec::new();
+            stream.read_to_end(&mut tunneled).await.unwrap();
+
+            (String::from_utf8(request).unwrap(), tunneled)
+        });
+
+        let proxy = ProxyConfig::new(format!("http://{address}").parse().unwrap())
+            .unwrap()
+            .with_credentials("alice", "secret");
+
+        let mut stream = connect_with_proxy(&proxy, "example.com", 1337)
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        drop(stream);
+
+        let (request, tunneled) = server.await.unwrap();
+
+        assert!(request.starts_with("CONNECT example.com:1337 HTTP/1.1\r\n"));
+        assert!(request.contains("Proxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n"));
+        assert_eq!(tunneled, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // offers no authentication and username / password, the latter is chosen
+            let mut greeting = [0; 4];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = [0; 14];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0; 18];
+            stream.read_exact(&mut request).await.unwrap();
+            stream
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+
+            let mut tunneled = Vec::new();
+            stream.read_to_end(&mut tunneled).await.unwrap();
+
+            (greeting, auth, request, tunneled)
+        });
+
+        let proxy = ProxyConfig::new(format!("socks5h://{address}").parse().unwrap())
+            .unwrap()
+            .with_credentials("alice", "secret");
+
+        let mut stream = connect_with_proxy(&proxy, "example.com", 1337)
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        drop(stream);
+
+        let (greeting, auth, request, tunneled) = server.await.unwrap();
+
+        assert_eq!(greeting, [5, 2, 0, 2]);
+        assert_eq!(&auth, b"\x01\x05alice\x06secret");
+        assert_eq!(&request[..5], [5, 1, 0, 3, 11]);
+        assert_eq!(&request[5..16], b"example.com");
+        assert_eq!(request[16..], 1337u16.to_be_bytes());
+        assert_eq!(tunneled, b"hello");
+    }
+}
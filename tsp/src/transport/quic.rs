@@ -1,51 +1,93 @@
-use async_stream::stream;
 use once_cell::sync::Lazy;
 use quinn::{
     crypto::rustls::{QuicClientConfig, QuicServerConfig},
-    ClientConfig, Endpoint,
+    ClientConfig, Connection, Endpoint,
 };
 use std::{
     net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::Arc,
 };
-use tokio::sync::mpsc;
 use url::Url;
 
-use super::TransportError;
+use super::{
+    inbox::{Inbox, TransportConfig},
+    pool::Pool,
+    TransportError,
+};
 use crate::definitions::TSPStream;
 
 pub(crate) const SCHEME: &str = "quic";
 
 pub const ALPN_QUIC_HTTP: &[&[u8]] = &[b"hq-29"];
 
-static QUIC_CONFIG: Lazy<ClientConfig> = Lazy::new(|| {
-    let mut config = super::tls::create_tls_config();
-    config.alpn_protocols = ALPN_QUIC_HTTP.iter().map(|&x| x.into()).collect();
+/// Idle client connections (and the endpoint they belong to), keyed by transport address
+static POOL: Lazy<Pool<(Endpoint, Connection)>> = Lazy::new(Default::default);
 
-    quinn::ClientConfig::new(Arc::new(
-        QuicClientConfig::try_from(Arc::new(config))
-            .expect("could not convert TLS config to QUIC config"),
-    ))
-});
+pub(super) fn clear_pool() {
+    POOL.clear();
+}
 
 /// Send a message over QUIC
-/// Connects to the specified transport address and sends the message.
-/// Note that a new connection is opened for each message.
+/// Connects to the specified transport address and sends the message on a new stream.
+/// Connections are kept open and reused for subsequent messages to the same address.
 pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
+    let address = socket_address(url)?;
+
+    if let Some((endpoint, connection)) = POOL.take(url.as_str()) {
+        if connection.close_reason().is_none()
+            && send_on_connection(&connection, tsp_message, address)
+                .await
+                .is_ok()
+        {
+            POOL.put(url.as_str(), (endpoint, connection));
+
+            return Ok(());
+        }
+    }
+
+    let (endpoint, connection) = connect(url, address).await?;
+
+    send_on_connection(&connection, tsp_message, address).await?;
+
+    POOL.put(url.as_str(), (endpoint, connection));
+
+    Ok(())
+}
+
+/// Complete a QUIC handshake and close the connection, without sending a message
+pub(crate) async fn probe(url: &Url) -> Result<(), TransportError> {
+    let (_endpoint, connection) = connect(url, socket_address(url)?).await?;
+    connection.close(0u32.into(), b"probe");
+
+    Ok(())
+}
+
+/// The address to send QUIC packets for `url` to
+fn socket_address(url: &Url) -> Result<SocketAddr, TransportError> {
+    // QUIC runs over UDP, which is not tunneled through the proxy
+    if super::proxy::is_proxied(url) {
+        return Err(TransportError::Proxy(
+            url.to_string(),
+            "QUIC can not be sent through a proxy".to_string(),
+        ));
+    }
+
     let addresses = url
         .socket_addrs(|| None)
         .map_err(|_| TransportError::InvalidTransportAddress(url.to_string()))?;
 
-    let Some(address) = addresses.first().cloned() else {
-        return Err(TransportError::InvalidTransportAddress(url.to_string()));
-    };
+    addresses
+        .first()
+        .cloned()
+        .ok_or_else(|| TransportError::InvalidTransportAddress(url.to_string()))
+}
 
-    let domain = url
-        .domain()
-        .ok_or(TransportError::InvalidTransportAddress(format!(
-            "could not resolve {url} to a domain"
-        )))?
-        .to_owned();
+/// Open a new connection to `address`, from a random local port
+async fn connect(url: &Url, address: SocketAddr) -> Result<(Endpoint, Connection), TransportError> {
+    let (tls_config, server_name) = super::tls::client_config(url, ALPN_QUIC_HTTP)?;
+    let config = ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(tls_config).map_err(|_| TransportError::Internal)?,
+    ));
 
     // passing 0 as port number opens a random port
     let listen_address: SocketAddr = if address.is_ipv6() {
@@ -56,14 +98,23 @@ pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), Tr
 
     let mut endpoint = Endpoint::client(listen_address).map_err(|_| TransportError::ListenPort)?;
 
-    endpoint.set_default_client_config(QUIC_CONFIG.clone());
+    endpoint.set_default_client_config(config);
 
     let connection = endpoint
-        .connect(address, &domain)
+        .connect(address, &server_name.to_str())
         .map_err(|e| TransportError::QuicConnection(address.to_string(), e))?
         .await
         .map_err(|e| TransportError::Connection(address.to_string(), e.into()))?;
 
+    Ok((endpoint, connection))
+}
+
+/// Send a message on a new unidirectional stream of an existing connection
+async fn send_on_connection(
+    connection: &Connection,
+    tsp_message: &[u8],
+    address: SocketAddr,
+) -> Result<(), TransportError> {
     let mut send = connection
         .open_uni()
         .await
@@ -80,18 +131,20 @@ pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), Tr
         .await
         .map_err(|e| TransportError::Connection(address.to_string(), e.into()))?;
 
-    connection.close(0u32.into(), b"done");
-
     Ok(())
 }
 
 /// Receive (multiple) messages over QUIC
 /// Listens on the specified transport port and yields messages as they arrive
 /// This function handles multiple connections and messages and
-/// combines them in a single stream. It uses an internal queue of 16 messages.
+/// combines them in a single stream. It uses an internal queue bounded by the configuration.
 pub(crate) async fn receive_messages(
     address: &Url,
+    config: &TransportConfig,
 ) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
+    let mut server_crypto = super::tls::server_config(address)?;
+    server_crypto.alpn_protocols = ALPN_QUIC_HTTP.iter().map(|&x| x.into()).collect();
+
     let addresses = address
         .socket_addrs(|| None)
         .map_err(|_| TransportError::InvalidTransportAddress(address.to_string()))?;
@@ -100,16 +153,6 @@ pub(crate) async fn receive_messages(
         return Err(TransportError::InvalidTransportAddress(address.to_string()));
     };
 
-    let (cert, key) = super::tls::load_certificate()?;
-
-    let mut server_crypto =
-        rustls::ServerConfig::builder_with_provider(super::tls::CRYPTO_PROVIDER.clone())
-            .with_safe_default_protocol_versions()?
-            .with_no_client_auth()
-            .with_single_cert(cert, key)?;
-
-    server_crypto.alpn_protocols = ALPN_QUIC_HTTP.iter().map(|&x| x.into()).collect();
-
     let server_config = quinn::ServerConfig::with_crypto(Arc::new(
         QuicServerConfig::try_from(server_crypto).map_err(|_| TransportError::Internal)?,
     ));
@@ -117,49 +160,54 @@ pub(crate) async fn receive_messages(
     let endpoint = Endpoint::server(server_config, address)
         .map_err(|e| TransportError::Connection(address.to_string(), e))?;
 
-    let (tx, mut rx) = mpsc::channel::<Result<Vec<u8>, TransportError>>(16);
+    let (inbox, messages) = Inbox::new(config);
+    let max_message_size = config.max_message_size;
 
     tokio::spawn(async move {
         while let Some(incoming_conn) = endpoint.accept().await {
-            let tx = tx.clone();
+            let inbox = inbox.clone();
 
             tokio::spawn(async move {
                 let conn = incoming_conn
                     .await
                     .map_err(|e| TransportError::Connection(address.to_string(), e.into()))?;
 
-                let receive = conn.accept_uni().await;
-
-                let mut receive = match receive {
-                    Err(quinn::ConnectionError::ApplicationClosed { .. }) => {
-                        return Ok(());
-                    }
-                    Err(e) => {
-                        return Err(TransportError::Connection(address.to_string(), e.into()));
-                    }
-                    Ok(s) => s,
-                };
-
-                let message = receive.read_to_end(8 * 1024).await.map_err(|_| {
-                    TransportError::InvalidMessageReceived(format!(
-                        "message from {address} is too long",
-                    ))
-                });
-
-                tx.send(message)
-                    .await
-                    .map_err(|_| TransportError::Internal)?;
-
-                Ok(())
+                // a connection may be reused for multiple messages, each on its own stream
+                loop {
+                    let receive = conn.accept_uni().await;
+
+                    let mut receive = match receive {
+                        Err(
+                            quinn::ConnectionError::ApplicationClosed { .. }
+                            | quinn::ConnectionError::TimedOut,
+                        ) => {
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            return Err(TransportError::Connection(address.to_string(), e.into()));
+                        }
+                        Ok(s) => s,
+                    };
+
+                    let message = receive
+                        .read_to_end(max_message_size)
+                        .await
+                        .map_err(|e| match e {
+                            quinn::ReadToEndError::TooLong => TransportError::Overloaded(format!(
+                                "message from {address} exceeds the maximum of {max_message_size} bytes",
+                            )),
+                            quinn::ReadToEndError::Read(_) => TransportError::InvalidMessageReceived(
+                                format!("could not read message from {address}"),
+                            ),
+                        });
+
+                    inbox.deliver(message).await?;
+                }
             });
         }
     });
 
-    Ok(Box::pin(stream! {
-        while let Some(item) = rx.recv().await {
-            yield item;
-        }
-    }))
+    Ok(messages)
 }
 
 #[cfg(test)]
@@ -172,8 +220,17 @@ mod tests {
         let url = Url::parse("quic://localhost:3737").unwrap();
         let message = b"Hello, world!";
 
-        let mut incoming_stream = receive_messages(&url).await.unwrap();
+        let mut incoming_stream = receive_messages(&url, &TransportConfig::default())
+            .await
+            .unwrap();
+
+        send_message(message, &url).await.unwrap();
+
+        let received_message = incoming_stream.next().await.unwrap().unwrap();
+
+        assert_eq!(message, received_message.as_slice());
 
+        // the second message reuses the pooled connection
         send_message(message, &url).await.unwrap();
 
         let received_message = incoming_stream.next().await.unwrap().unwrap();
@@ -0,0 +1,172 @@
+//! Hosting the HTTP endpoints of local VIDs in an existing web service
+//!
+//! [`router`] accepts the messages that are POSTed to the paths of some `http://` or
+//! `https://` endpoints. From then on, receiving on such an endpoint, e.g. with
+//! [`AsyncStore::receive`](crate::AsyncStore::receive), yields the messages POSTed to it
+//! instead of connecting to a server. Like a `mem://` endpoint, a hosted endpoint can only
+//! be received on by one stream at a time, and POSTs fail while nothing receives on it.
+
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::StatusCode,
+    routing::post,
+    Router,
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{Mutex, MutexGuard},
+};
+use url::Url;
+
+use super::{
+    http::{SCHEME_HTTP, SCHEME_HTTPS},
+    inbox::{Inbox, TransportConfig},
+    TSPStream, TransportError,
+};
+
+/// The hosted endpoints, with the inbox of the stream that receives on them, if any
+static HOSTED: Lazy<Mutex<HashMap<String, Option<Inbox>>>> = Lazy::new(Default::default);
+
+fn hosted() -> MutexGuard<'static, HashMap<String, Option<Inbox>>> {
+    HOSTED.lock().expect("hosted endpoints lock is poisoned")
+}
+
+/// An [axum] router that accepts the TSP messages POSTed to `endpoints`, to nest or merge
+/// into the router of a web service
+///
+/// Every endpoint is routed by its path only; the service has to be reachable at the
+/// scheme, host and port of the endpoints. Endpoints with another scheme than `http` or
+/// `https`, or with the same path as an earlier endpoint, are ignored. Messages larger
+/// than `config.max_message_size` are refused.
+///
+/// ```no_run
+/// # async fn serve(store: tsp::AsyncStore) -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = "https://example.com/user/alice".parse()?;
+/// let app = tsp::transport::router([endpoint], &Default::default());
+///
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+/// tokio::spawn(async move { axum::serve(listener, app).await });
+///
+/// let messages = store.receive("did:web:example.com:user:alice").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn router(endpoints: impl IntoIterator<Item = Url>, config: &TransportConfig) -> Router {
+    let mut router = Router::new();
+    let mut paths = HashSet::new();
+
+    for endpoint in endpoints {
+        if ![SCHEME_HTTP, SCHEME_HTTPS].contains(&endpoint.scheme()) {
+            continue;
+        }
+
+        if !paths.insert(endpoint.path().to_string()) {
+            continue;
+        }
+
+        hosted().entry(endpoint.to_string()).or_default();
+
+        router = router.route(
+            endpoint.path(),
+            post(receive_message).with_state(endpoint.to_string()),
+        );
+    }
+
+    router.layer(DefaultBodyLimit::max(config.max_message_size))
+}
+
+/// Pass a POSTed message to the stream that receives on `endpoint`
+async fn receive_message(
+    State(endpoint): State<String>,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let inbox = hosted()
+        .get(&endpoint)
+        .and_then(|inbox| inbox.clone())
+        .filter(|inbox| !inbox.is_closed())
+        .ok_or_else(|| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("nothing receives on {endpoint}"),
+            )
+        })?;
+
+    if body.len() > inbox.max_message_size() {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("messages are at most {} bytes", inbox.max_message_size()),
+        ));
+    }
+
+    inbox
+        .deliver(Ok(body.to_vec()))
+        .await
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Receive the messages POSTed to `address` if it is hosted by a [router], until the
+/// stream is dropped; `None` if the endpoint is not hosted here
+pub(super) fn receive_messages(
+    address: &Url,
+    config: &TransportConfig,
+) -> Result<Option<TSPStream<Vec<u8>, TransportError>>, TransportError> {
+    let mut hosted = hosted();
+
+    let Some(receiver) = hosted.get_mut(address.as_str()) else {
+        return Ok(None);
+    };
+
+    if receiver.as_ref().is_some_and(|inbox| !inbox.is_closed()) {
+        return Err(TransportError::Connection(
+            address.to_string(),
+            io::ErrorKind::AddrInUse.into(),
+        ));
+    }
+
+    let (inbox, messages) = Inbox::new(config);
+    *receiver = Some(inbox);
+
+    Ok(Some(messages))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_hosted_endpoint() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::parse(&format!("http://127.0.0.1:{port}/user/alice")).unwrap();
+
+        let app = router([url.clone()], &TransportConfig::default());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let post = |message: &'static [u8]| client.post(url.clone()).body(message).send();
+
+        // nothing receives yet
+        let response = post(b"Hello, world!").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut messages = crate::transport::receive_messages(&url).await.unwrap();
+        assert!(crate::transport::receive_messages(&url).await.is_err());
+
+        let response = post(b"Hello, world!").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let received = messages.next().await.unwrap().unwrap();
+        assert_eq!(received, b"Hello, world!");
+
+        // other paths are not routed
+        let mut other = url.clone();
+        other.set_path("/user/bob");
+        let response = client.post(other).body("Hello").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,347 @@
+//! Receiving messages from an HTTP(S) endpoint as Server-Sent Events
+//!
+//! Every `message` event carries one TSP message, base64url encoded (unpadded) in its
+//! `data` field; events of other types are ignored. If the connection drops, the client
+//! reconnects and sends the id of the last received event in the `Last-Event-ID` header,
+//! so the server can resume where it left off.
+
+use async_stream::stream;
+use base64ct::{Base64UrlUnpadded, Encoding};
+use futures::StreamExt;
+use reqwest::{
+    header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE},
+    Response, StatusCode,
+};
+use std::time::Duration;
+use url::Url;
+
+use super::{
+    inbox::{check_size, TransportConfig},
+    TransportError,
+};
+use crate::definitions::TSPStream;
+
+const EVENT_STREAM: &str = "text/event-stream";
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+const MESSAGE_EVENT: &str = "message";
+
+/// Reconnection delay until the server sets one with a `retry` field
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+/// Number of consecutive failed reconnection attempts before the stream ends
+const MAX_RECONNECT_ATTEMPTS: usize = 5;
+
+/// Room for field names and ids on top of the encoded message
+const LINE_OVERHEAD: usize = 1024;
+
+/// A dispatched event
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Event {
+    pub(super) event_type: String,
+    pub(super) data: String,
+}
+
+/// Incremental parser for the `text/event-stream` format
+pub(super) struct EventParser {
+    buffer: Vec<u8>,
+    event_type: String,
+    data: String,
+    last_event_id: Option<String>,
+    retry: Duration,
+}
+
+impl Default for EventParser {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            event_type: String::new(),
+            data: String::new(),
+            last_event_id: None,
+            retry: DEFAULT_RETRY,
+        }
+    }
+}
+
+impl EventParser {
+    pub(super) fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// The number of bytes buffered for the event that is currently being received
+    pub(super) fn pending(&self) -> usize {
+        self.buffer.len() + self.data.len()
+    }
+
+    /// Discard a partially received event, e.g. after the connection dropped
+    pub(super) fn reset(&mut self) {
+        self.buffer.clear();
+        self.event_type.clear();
+        self.data.clear();
+    }
+
+    /// Return the next complete event, if one has been received
+    pub(super) fn next_event(&mut self) -> Option<Event> {
+        while let Some(line) = self.next_line() {
+            if line.is_empty() {
+                if self.data.is_empty() {
+                    self.event_type.clear();
+                    continue;
+                }
+
+                // the last data line does not end the data
+                self.data.pop();
+
+                let event_type = match std::mem::take(&mut self.event_type) {
+                    event_type if event_type.is_empty() => MESSAGE_EVENT.to_string(),
+                    event_type => event_type,
+                };
+
+                return Some(Event {
+                    event_type,
+                    data: std::mem::take(&mut self.data),
+                });
+            }
+
+            let line = String::from_utf8_lossy(&line);
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_ref(), ""),
+            };
+
+            match field {
+                "event" => self.event_type = value.to_string(),
+                "data" => {
+                    self.data.push_str(value);
+                    self.data.push('\n');
+                }
+                "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+                "retry" => {
+                    if let Ok(millis) = value.parse() {
+                        self.retry = Duration::from_millis(millis);
+                    }
+                }
+                // comments and unknown fields are ignored
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Lines end with CRLF, LF or CR
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        let end = self
+            .buffer
+            .iter()
+            .position(|&byte| byte == b'\n' || byte == b'\r')?;
+
+        // a trailing CR may be the first half of a CRLF that has not arrived yet
+        if self.buffer[end] == b'\r' && end + 1 == self.buffer.len() {
+            return None;
+        }
+
+        let skip = match &self.buffer[end..] {
+            [b'\r', b'\n', ..] => 2,
+            _ => 1,
+        };
+
+        let line = self.buffer[..end].to_vec();
+        self.buffer.drain(..end + skip);
+
+        Some(line)
+    }
+}
+
+/// Request an event stream from `address`, resuming after `last_event_id`
+async fn request(address: &Url, last_event_id: Option<&str>) -> Result<Response, TransportError> {
+    let client = super::http::client().map_err(|e| TransportError::Http(address.to_string(), e))?;
+
+    let mut request = client
+        .get(address.clone())
+        .header(ACCEPT, EVENT_STREAM)
+        .header(CACHE_CONTROL, "no-cache");
+
+    if let Some(id) = last_event_id {
+        request = request.header(LAST_EVENT_ID, id);
+    }
+
+    request
+        .send()
+        .await
+        .map_err(|e| TransportError::Http(address.to_string(), e))
+}
+
+fn is_event_stream(response: &Response) -> bool {
+    response.status().is_success()
+        && response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(EVENT_STREAM))
+}
+
+/// Open an event stream to `address`
+/// Returns `None` if the endpoint does not serve Server-Sent Events
+pub(super) async fn connect(address: &Url) -> Result<Option<Response>, TransportError> {
+    let response = request(address, None).await?;
+
+    Ok(is_event_stream(&response).then_some(response))
+}
+
+fn decode_message(data: &str, config: &TransportConfig) -> Result<Vec<u8>, TransportError> {
+    let message = Base64UrlUnpadded::decode_vec(data.trim()).map_err(|_| {
+        TransportError::InvalidMessageReceived("invalid base64 in event data".to_string())
+    })?;
+
+    check_size(message, config)
+}
+
+/// Convert an event stream into a stream of TSP messages, reconnecting if the connection drops
+pub(super) fn receive_messages(
+    address: &Url,
+    response: Response,
+    config: &TransportConfig,
+) -> TSPStream<Vec<u8>, TransportError> {
+    let address = address.clone();
+    let config = config.clone();
+    let max_pending = config.max_message_size.div_ceil(3) * 4 + LINE_OVERHEAD;
+
+    Box::pin(stream! {
+        let mut parser = EventParser::default();
+        let mut response = response;
+
+        loop {
+            let mut body = response.bytes_stream();
+
+            while let Some(chunk) = body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        tracing::debug!("event stream from {address} interrupted: {e}");
+                        break;
+                    }
+                };
+
+                parser.push(&chunk);
+
+                while let Some(event) = parser.next_event() {
+                    if event.event_type == MESSAGE_EVENT {
+                        yield decode_message(&event.data, &config);
+                    }
+                }
+
+                if parser.pending() > max_pending {
+                    yield Err(TransportError::Overloaded(format!(
+                        "event exceeds the maximum of {max_pending} bytes"
+                    )));
+                    return;
+                }
+            }
+
+            parser.reset();
+
+            let mut attempts = 0;
+            response = loop {
+                tokio::time::sleep(parser.retry).await;
+
+                let error = match request(&address, parser.last_event_id.as_deref()).await {
+                    Ok(response) if is_event_stream(&response) => break response,
+                    // the server asks the client to stop reconnecting
+                    Ok(response) if response.status() == StatusCode::NO_CONTENT => return,
+                    Ok(response) => TransportError::InvalidMessageReceived(format!(
+                        "'{address}' responded with {} instead of an event stream",
+                        response.status()
+                    )),
+                    Err(e) => e,
+                };
+
+                attempts += 1;
+                if attempts == MAX_RECONNECT_ATTEMPTS {
+                    yield Err(error);
+                    return;
+                }
+
+                tracing::debug!("reconnecting to {address} failed: {error}");
+            };
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_event_parser() {
+        let mut parser = EventParser::default();
+
+        parser.push(b": comment\r\nid: 7\r\ndata: first\r\ndata:second\r");
+        assert_eq!(parser.next_event(), None);
+
+        parser.push(b"\n\r\nevent: ping\ndata\n\nretry: 10\n");
+        assert_eq!(
+            parser.next_event(),
+            Some(Event {
+                event_type: "message".to_string(),
+                data: "first\nsecond".to_string(),
+            })
+        );
+        assert_eq!(
+            parser.next_event(),
+            Some(Event {
+                event_type: "ping".to_string(),
+                data: String::new(),
+            })
+        );
+        assert_eq!(parser.next_event(), None);
+
+        assert_eq!(parser.last_event_id.as_deref(), Some("7"));
+        assert_eq!(parser.retry, Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn test_sse_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+
+            for (id, message) in [(1, b"hello"), (2, b"world")] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+
+                let event = format!(
+                    "retry: 10\nid: {id}\ndata: {}\n\n",
+                    Base64UrlUnpadded::encode_string(message)
+                );
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n{event}"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+
+                requests.push(String::from_utf8(request).unwrap().to_lowercase());
+            }
+
+            requests
+        });
+
+        let url = Url::parse(&format!("http://{address}/transport/bob")).unwrap();
+        let mut messages = super::super::receive_messages(&url).await.unwrap();
+
+        assert_eq!(messages.next().await.unwrap().unwrap(), b"hello");
+        assert_eq!(messages.next().await.unwrap().unwrap(), b"world");
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("accept: text/event-stream"));
+        assert!(!requests[0].contains("last-event-id"));
+        assert!(requests[1].contains("last-event-id: 1"));
+    }
+}
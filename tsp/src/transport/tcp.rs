@@ -1,40 +1,42 @@
 use async_stream::stream;
 use futures::StreamExt;
 use tokio::{io::AsyncWriteExt, net::TcpListener};
-use tokio_util::codec::{BytesCodec, Framed};
 use url::Url;
 
-use super::{TSPStream, TransportError};
+use super::{
+    inbox::{check_size, split_messages, TransportConfig},
+    TSPStream, TransportError,
+};
 
 pub(crate) const SCHEME: &str = "tcp";
 
 /// Send a single message over TCP
 /// Note: this opens a new connection per message
 pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
-    let addresses = url
-        .socket_addrs(|| None)
-        .map_err(|_| TransportError::InvalidTransportAddress(url.to_string()))?;
-
-    let Some(address) = addresses.first() else {
-        return Err(TransportError::InvalidTransportAddress(url.to_string()));
-    };
-
-    let mut stream = tokio::net::TcpStream::connect(address)
-        .await
-        .map_err(|e| TransportError::Connection(address.to_string(), e))?;
+    let mut stream = super::proxy::connect(url).await?;
 
     stream
         .write_all(tsp_message)
         .await
-        .map_err(|e| TransportError::Connection(address.to_string(), e))?;
+        .map_err(|e| TransportError::Connection(url.to_string(), e))?;
+
+    Ok(())
+}
+
+/// Open and close a connection, without sending a message
+pub(crate) async fn probe(url: &Url) -> Result<(), TransportError> {
+    super::proxy::connect(url).await?;
 
     Ok(())
 }
 
 /// Receive (multiple) messages over TCP
 /// Listens on the specified transport port and yields messages as they arrive
+/// Connections are read one message at a time, so no messages are buffered. A connection
+/// can carry several messages, in the text or binary domain.
 pub(crate) async fn receive_messages(
     address: &Url,
+    config: &TransportConfig,
 ) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
     let addresses = address
         .socket_addrs(|| None)
@@ -48,12 +50,14 @@ pub(crate) async fn receive_messages(
         .await
         .map_err(|e| TransportError::Connection(address.to_string(), e))?;
 
+    let config = config.clone();
+
     Ok(Box::pin(stream! {
         while let Ok((stream, addr)) = listener.accept().await {
-            let mut messages = Framed::new(stream, BytesCodec::new());
+            let mut messages = std::pin::pin!(split_messages(stream, addr.to_string(), &config));
 
             while let Some(m) = messages.next().await {
-                yield m.map(|m| m.to_vec()).map_err(|e| TransportError::Connection(addr.to_string(), e));
+                yield m.and_then(|m| check_size(m, &config));
             }
         }
     }))
@@ -70,7 +74,9 @@ mod test {
         let url = Url::parse("tcp://localhost:12345").unwrap();
         let message = b"Hello, world!";
 
-        let mut incoming_stream = receive_messages(&url).await.unwrap();
+        let mut incoming_stream = receive_messages(&url, &TransportConfig::default())
+            .await
+            .unwrap();
 
         send_message(message, &url).await.unwrap();
         let received_message = incoming_stream.next().await.unwrap().unwrap();
@@ -1,15 +1,19 @@
-use async_stream::stream;
 use futures::StreamExt;
 use once_cell::sync::Lazy;
-use rustls::{crypto::CryptoProvider, ClientConfig, RootCertStore};
-use rustls_pki_types::ServerName;
-use std::sync::Arc;
-use tokio::{io::AsyncWriteExt, net::TcpListener, sync::mpsc};
+use rustls::{crypto::CryptoProvider, server::WebPkiClientVerifier, ClientConfig, RootCertStore};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use tokio::{io::AsyncWriteExt, net::TcpListener};
 use tokio_rustls::{TlsAcceptor, TlsConnector};
-use tokio_util::codec::{BytesCodec, Framed};
 use url::Url;
 
-use super::TransportError;
+use super::{
+    inbox::{check_size, split_messages, Inbox, TransportConfig},
+    TransportError,
+};
 use crate::definitions::TSPStream;
 
 pub(crate) const SCHEME: &str = "tls";
@@ -54,97 +58,371 @@ pub(super) fn load_certificate() -> Result<
     Ok((certs.unwrap(), key))
 }
 
-pub(super) fn create_tls_config() -> ClientConfig {
-    // Load native system certificates
-    let mut root_cert_store = RootCertStore::empty();
-    for cert in
-        rustls_native_certs::load_native_certs().expect("could not load native certificates")
-    {
-        root_cert_store
-            .add(cert)
-            .expect("could not add native certificate");
+/// The trust and client authentication settings of `tls` and `quic` connections
+///
+/// By default the native root certificates of the system are trusted, no client
+/// certificate is presented, and clients of receiving endpoints are not authenticated.
+/// Use it for all connections with [`set_tls_config`](super::set_tls_config), or for the
+/// connections to a single host with [`set_host_tls_config`](super::set_host_tls_config).
+#[derive(Debug)]
+pub struct TlsConfig {
+    native_roots: bool,
+    root_certificates: Vec<CertificateDer<'static>>,
+    client_certificate: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    client_roots: Vec<CertificateDer<'static>>,
+    server_name: Option<String>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            native_roots: true,
+            root_certificates: Vec::new(),
+            client_certificate: None,
+            client_roots: Vec::new(),
+            server_name: None,
+            alpn_protocols: Vec::new(),
+        }
     }
+}
 
-    // Add test CA certificate
-    #[cfg(test)]
-    {
-        let cert_path = "../examples/test/root-ca.pem";
-        let cert_file = std::fs::File::open(cert_path).expect("could not find test CA certificate");
-        let certs: std::io::Result<Vec<rustls_pki_types::CertificateDer<'static>>> =
-            rustls_pemfile::certs(&mut std::io::BufReader::new(cert_file)).collect();
-
-        for cert in certs.expect("could not read test CA certificate") {
-            root_cert_store
-                .add(cert)
-                .expect("could not add test CA certificate")
+impl Clone for TlsConfig {
+    fn clone(&self) -> Self {
+        Self {
+            native_roots: self.native_roots,
+            root_certificates: self.root_certificates.clone(),
+            client_certificate: self
+                .client_certificate
+                .as_ref()
+                .map(|(chain, key)| (chain.clone(), key.clone_key())),
+            client_roots: self.client_roots.clone(),
+            server_name: self.server_name.clone(),
+            alpn_protocols: self.alpn_protocols.clone(),
         }
     }
+}
+
+/// Read all certificates from `pem`
+fn certificates_from_pem(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, TransportError> {
+    let certificates = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TransportError::TLSCertificate(e.to_string()))?;
+
+    if certificates.is_empty() {
+        return Err(TransportError::TLSCertificate(
+            "no certificate found".to_string(),
+        ));
+    }
 
-    rustls::ClientConfig::builder_with_provider(CRYPTO_PROVIDER.clone())
-        .with_safe_default_protocol_versions()
-        .unwrap()
-        .with_root_certificates(root_cert_store)
-        .with_no_client_auth()
+    Ok(certificates)
 }
 
-pub(super) static CRYPTO_PROVIDER: Lazy<Arc<CryptoProvider>> =
-    Lazy::new(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
-pub(super) static TLS_CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| Arc::new(create_tls_config()));
+impl TlsConfig {
+    /// Whether to trust the native root certificates of the system, e.g. to only trust a
+    /// private CA
+    pub fn with_native_roots(mut self, enabled: bool) -> Self {
+        self.native_roots = enabled;
 
-/// Send a message over TLS
-/// Connects to the specified transport address and sends the message.
-/// Note that a new connection is opened for each message.
-pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
-    let addresses = url
-        .socket_addrs(|| None)
-        .map_err(|_| TransportError::InvalidTransportAddress(url.to_string()))?;
+        self
+    }
+
+    /// Also trust the PEM encoded root certificates in `pem`, e.g. of a private CA
+    pub fn with_root_certificates_pem(mut self, pem: &[u8]) -> Result<Self, TransportError> {
+        self.root_certificates.extend(certificates_from_pem(pem)?);
+
+        Ok(self)
+    }
+
+    /// Present the PEM encoded certificate chain `chain_pem` and private key `key_pem` to
+    /// servers that require client authentication (mutual TLS)
+    pub fn with_client_certificate_pem(
+        mut self,
+        chain_pem: &[u8],
+        key_pem: &[u8],
+    ) -> Result<Self, TransportError> {
+        let chain = certificates_from_pem(chain_pem)?;
+        let key = rustls_pemfile::private_key(&mut &key_pem[..])
+            .ok()
+            .flatten()
+            .ok_or(TransportError::TLSKey("client certificate".to_string()))?;
+
+        self.client_certificate = Some((chain, key));
+
+        Ok(self)
+    }
+
+    /// Require clients of receiving endpoints to present a certificate issued by one of
+    /// the PEM encoded root certificates in `pem`
+    pub fn with_client_roots_pem(mut self, pem: &[u8]) -> Result<Self, TransportError> {
+        self.client_roots.extend(certificates_from_pem(pem)?);
+
+        Ok(self)
+    }
+
+    /// Verify the certificate of the server for `name` instead of the host of the
+    /// endpoint, and send it in the SNI extension; required for endpoints with an IP address
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+
+        self
+    }
+
+    /// Offer these ALPN protocols, most preferred first; QUIC connections offer
+    /// [`ALPN_QUIC_HTTP`](super::quic::ALPN_QUIC_HTTP) if none are set
+    pub fn with_alpn_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<Vec<u8>>>,
+    ) -> Self {
+        self.alpn_protocols = protocols.into_iter().map(Into::into).collect();
+
+        self
+    }
+
+    fn root_store(&self) -> RootCertStore {
+        let mut root_cert_store = RootCertStore::empty();
+
+        // Load native system certificates
+        if self.native_roots {
+            for cert in rustls_native_certs::load_native_certs()
+                .expect("could not load native certificates")
+            {
+                root_cert_store
+                    .add(cert)
+                    .expect("could not add native certificate");
+            }
+        }
+
+        // Add test CA certificate
+        #[cfg(test)]
+        if self.native_roots {
+            let cert_path = "../examples/test/root-ca.pem";
+            let pem = std::fs::read(cert_path).expect("could not find test CA certificate");
+
+            for cert in certificates_from_pem(&pem).expect("could not read test CA certificate") {
+                root_cert_store
+                    .add(cert)
+                    .expect("could not add test CA certificate")
+            }
+        }
+
+        // invalid certificates are skipped, like rustls does for native certificates
+        root_cert_store.add_parsable_certificates(self.root_certificates.iter().cloned());
+
+        root_cert_store
+    }
+
+    /// The rustls configuration of outgoing connections
+    fn client_config(&self) -> Result<ClientConfig, TransportError> {
+        let builder = ClientConfig::builder_with_provider(CRYPTO_PROVIDER.clone())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(self.root_store());
+
+        let mut config = match &self.client_certificate {
+            Some((chain, key)) => builder.with_client_auth_cert(chain.clone(), key.clone_key())?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = self.alpn_protocols.clone();
+
+        Ok(config)
+    }
+
+    /// The rustls configuration of receiving endpoints, presenting `cert` and `key`
+    pub(super) fn server_config(
+        &self,
+        cert: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<rustls::ServerConfig, TransportError> {
+        let builder = rustls::ServerConfig::builder_with_provider(CRYPTO_PROVIDER.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if self.client_roots.is_empty() {
+            builder.with_no_client_auth()
+        } else {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(self.client_roots.iter().cloned());
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(roots),
+                CRYPTO_PROVIDER.clone(),
+            )
+            .build()
+            .map_err(|e| TransportError::TLSCertificate(e.to_string()))?;
+
+            builder.with_client_cert_verifier(verifier)
+        };
+
+        Ok(builder.with_single_cert(cert, key)?)
+    }
+}
+
+/// A [TlsConfig] with the rustls configuration of outgoing connections built from it
+struct Settings {
+    config: TlsConfig,
+    client: Arc<ClientConfig>,
+}
+
+impl Settings {
+    fn new(config: TlsConfig) -> Result<Self, TransportError> {
+        let client = Arc::new(config.client_config()?);
+
+        Ok(Self { config, client })
+    }
+}
+
+#[derive(Default)]
+struct Configs {
+    /// `None` until the default configuration is first used or set
+    default: Option<Arc<Settings>>,
+    hosts: HashMap<String, Arc<Settings>>,
+}
+
+static CONFIGS: Lazy<RwLock<Configs>> = Lazy::new(Default::default);
+
+pub(super) fn set_config(config: TlsConfig) -> Result<(), TransportError> {
+    let settings = Arc::new(Settings::new(config)?);
+    CONFIGS
+        .write()
+        .map_err(|_| TransportError::Internal)?
+        .default = Some(settings);
+
+    Ok(())
+}
+
+pub(super) fn set_host_config(host: &str, config: Option<TlsConfig>) -> Result<(), TransportError> {
+    let settings = config.map(Settings::new).transpose()?.map(Arc::new);
+    let mut configs = CONFIGS.write().map_err(|_| TransportError::Internal)?;
 
-    let Some(address) = addresses.first().cloned() else {
-        return Err(TransportError::InvalidTransportAddress(url.to_string()));
+    match settings {
+        Some(settings) => configs.hosts.insert(host.to_ascii_lowercase(), settings),
+        None => configs.hosts.remove(&host.to_ascii_lowercase()),
     };
 
-    let tcp_stream = tokio::net::TcpStream::connect(address)
-        .await
-        .map_err(|e| TransportError::Connection(address.to_string(), e))?;
+    Ok(())
+}
+
+/// The settings of connections to `host`, or the default ones
+fn settings(host: Option<&str>) -> Result<Arc<Settings>, TransportError> {
+    {
+        let configs = CONFIGS.read().map_err(|_| TransportError::Internal)?;
+
+        if let Some(settings) = host.and_then(|host| configs.hosts.get(&host.to_ascii_lowercase()))
+        {
+            return Ok(settings.clone());
+        }
 
-    let domain = url
-        .domain()
-        .ok_or(TransportError::InvalidTransportAddress(format!(
-            "could not resolve {url} to a domain"
-        )))?
-        .to_owned();
+        if let Some(settings) = &configs.default {
+            return Ok(settings.clone());
+        }
+    }
+
+    let mut configs = CONFIGS.write().map_err(|_| TransportError::Internal)?;
+    if configs.default.is_none() {
+        configs.default = Some(Arc::new(Settings::new(TlsConfig::default())?));
+    }
+
+    Ok(configs
+        .default
+        .clone()
+        .expect("default TLS settings are set"))
+}
+
+/// The rustls configuration and server name of a connection to `url`; `alpn` is offered
+/// if the configuration does not set any ALPN protocols
+pub(super) fn client_config(
+    url: &Url,
+    alpn: &[&[u8]],
+) -> Result<(Arc<ClientConfig>, ServerName<'static>), TransportError> {
+    let settings = settings(url.host_str())?;
+
+    let name = match &settings.config.server_name {
+        Some(name) => name.clone(),
+        None => url
+            .domain()
+            .ok_or(TransportError::InvalidTransportAddress(format!(
+                "could not resolve {url} to a domain"
+            )))?
+            .to_owned(),
+    };
 
-    let dns_name = ServerName::try_from(domain).map_err(|_| {
+    let server_name = ServerName::try_from(name).map_err(|_| {
         TransportError::InvalidTransportAddress(format!("could not resolve {url} to a server name"))
     })?;
 
-    let connector = TlsConnector::from(TLS_CONFIG.clone());
+    let client = if settings.client.alpn_protocols.is_empty() && !alpn.is_empty() {
+        let mut client = (*settings.client).clone();
+        client.alpn_protocols = alpn.iter().map(|&protocol| protocol.into()).collect();
 
-    let mut stream = connector
-        .connect(dns_name, tcp_stream)
-        .await
-        .map_err(|e| TransportError::Connection(address.to_string(), e))?;
+        Arc::new(client)
+    } else {
+        settings.client.clone()
+    };
+
+    Ok((client, server_name))
+}
+
+/// The rustls configuration of a receiving endpoint at `url`, presenting the certificate
+/// from [load_certificate]
+pub(super) fn server_config(url: &Url) -> Result<rustls::ServerConfig, TransportError> {
+    let (cert, key) = load_certificate()?;
+
+    settings(url.host_str())?.config.server_config(cert, key)
+}
+
+pub(super) static CRYPTO_PROVIDER: Lazy<Arc<CryptoProvider>> =
+    Lazy::new(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+
+/// Send a message over TLS
+/// Connects to the specified transport address and sends the message.
+/// Note that a new connection is opened for each message.
+pub(crate) async fn send_message(tsp_message: &[u8], url: &Url) -> Result<(), TransportError> {
+    let mut stream = connect(url).await?;
 
     stream
         .write_all(tsp_message)
         .await
-        .map_err(|e| TransportError::Connection(address.to_string(), e))?;
+        .map_err(|e| TransportError::Connection(url.to_string(), e))?;
 
     stream
         .shutdown()
         .await
-        .map_err(|e| TransportError::Connection(address.to_string(), e))?;
+        .map_err(|e| TransportError::Connection(url.to_string(), e))?;
 
     Ok(())
 }
 
+/// Complete a TLS handshake and close the connection, without sending a message
+pub(crate) async fn probe(url: &Url) -> Result<(), TransportError> {
+    connect(url)
+        .await?
+        .shutdown()
+        .await
+        .map_err(|e| TransportError::Connection(url.to_string(), e))
+}
+
+/// Open a TLS connection to `url`
+async fn connect(
+    url: &Url,
+) -> Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>, TransportError> {
+    let (config, server_name) = client_config(url, &[])?;
+    let tcp_stream = super::proxy::connect(url).await?;
+
+    TlsConnector::from(config)
+        .connect(server_name, tcp_stream)
+        .await
+        .map_err(|e| TransportError::Connection(url.to_string(), e))
+}
+
 /// Receive (multiple) messages over TLS
 /// Listens on the specified transport port and yields messages as they arrive
 /// This function handles multiple connections and messages and
-/// combines them in a single stream. It uses an internal queue of 16 messages.
+/// combines them in a single stream. It uses an internal queue bounded by the configuration.
 pub(crate) async fn receive_messages(
     address: &Url,
+    transport_config: &TransportConfig,
 ) -> Result<TSPStream<Vec<u8>, TransportError>, TransportError> {
+    let config = server_config(address)?;
+
     let addresses = address
         .socket_addrs(|| None)
         .map_err(|_| TransportError::InvalidTransportAddress(address.to_string()))?;
@@ -153,24 +431,19 @@ pub(crate) async fn receive_messages(
         return Err(TransportError::InvalidTransportAddress(address.to_string()));
     };
 
-    let (cert, key) = load_certificate()?;
-    let config = rustls::ServerConfig::builder_with_provider(CRYPTO_PROVIDER.clone())
-        .with_safe_default_protocol_versions()
-        .unwrap()
-        .with_no_client_auth()
-        .with_single_cert(cert, key)?;
-
     let acceptor = TlsAcceptor::from(Arc::new(config));
     let listener = TcpListener::bind(&address)
         .await
         .map_err(|e| TransportError::Connection(address.to_string(), e))?;
 
-    let (tx, mut rx) = mpsc::channel::<Result<Vec<u8>, TransportError>>(16);
+    let (inbox, messages) = Inbox::new(transport_config);
+    let transport_config = transport_config.clone();
 
     tokio::spawn(async move {
         while let Ok((stream, peer_addr)) = listener.accept().await {
             let acceptor = acceptor.clone();
-            let tx = tx.clone();
+            let inbox = inbox.clone();
+            let config = transport_config.clone();
 
             tokio::spawn(async move {
                 let stream = acceptor
@@ -178,15 +451,13 @@ pub(crate) async fn receive_messages(
                     .await
                     .map_err(|e| TransportError::Connection(peer_addr.to_string(), e))?;
 
-                let mut messages = Framed::new(stream, BytesCodec::new());
+                let mut messages =
+                    std::pin::pin!(split_messages(stream, peer_addr.to_string(), &config));
 
                 while let Some(m) = messages.next().await {
-                    tx.send(
-                        m.map(|m| m.to_vec())
-                            .map_err(|e| TransportError::Connection(peer_addr.to_string(), e)),
-                    )
-                    .await
-                    .map_err(|_| TransportError::Internal)?;
+                    inbox
+                        .deliver(m.and_then(|m| check_size(m, &config)))
+                        .await?;
                 }
 
                 Ok::<(), TransportError>(())
@@ -194,11 +465,7 @@ pub(crate) async fn receive_messages(
         }
     });
 
-    Ok(Box::pin(stream! {
-        while let Some(item) = rx.recv().await {
-            yield item;
-        }
-    }))
+    Ok(messages)
 }
 
 #[cfg(test)]
@@ -211,7 +478,9 @@ mod tests {
         let url = Url::parse("tls://localhost:4242").unwrap();
         let message = b"Hello, world!";
 
-        let mut incoming_stream = receive_messages(&url).await.unwrap();
+        let mut incoming_stream = receive_messages(&url, &TransportConfig::default())
+            .await
+            .unwrap();
 
         send_message(message, &url).await.unwrap();
 
@@ -219,4 +488,44 @@ mod tests {
 
         assert_eq!(message, received_message.as_slice());
     }
+
+    #[tokio::test]
+    async fn test_host_tls_config() {
+        let url = Url::parse("tls://127.0.0.1:4243").unwrap();
+        let message = b"Hello, world!";
+
+        let mut incoming_stream = receive_messages(&url, &TransportConfig::default())
+            .await
+            .unwrap();
+
+        // the certificate of an endpoint with an IP address needs a server name to verify
+        assert!(send_message(message, &url).await.is_err());
+
+        // trust only the private CA
+        let root_ca = std::fs::read("../examples/test/root-ca.pem").unwrap();
+        let config = TlsConfig::default()
+            .with_native_roots(false)
+            .with_root_certificates_pem(&root_ca)
+            .unwrap()
+            .with_server_name("localhost");
+        set_host_config("127.0.0.1", Some(config)).unwrap();
+
+        send_message(message, &url).await.unwrap();
+
+        let received_message = incoming_stream.next().await.unwrap().unwrap();
+        assert_eq!(message, received_message.as_slice());
+
+        // without any trusted root, the server is rejected
+        let config = TlsConfig::default()
+            .with_native_roots(false)
+            .with_server_name("localhost");
+        set_host_config("127.0.0.1", Some(config)).unwrap();
+
+        assert!(send_message(message, &url).await.is_err());
+
+        set_host_config("127.0.0.1", None).unwrap();
+        assert!(TlsConfig::default()
+            .with_root_certificates_pem(b"not a certificate")
+            .is_err());
+    }
 }
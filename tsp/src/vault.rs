@@ -1,8 +1,10 @@
 use crate::{
     definitions::{
-        PRIVATE_KEY_SIZE, PRIVATE_SIGNING_KEY_SIZE, PUBLIC_KEY_SIZE, PUBLIC_VERIFICATION_KEY_SIZE,
+        CipherSuite, DigestAlgorithm, PRIVATE_KEY_SIZE, PRIVATE_SIGNING_KEY_SIZE, PUBLIC_KEY_SIZE,
+        PUBLIC_VERIFICATION_KEY_SIZE,
     },
-    Error, ExportVid, RelationshipStatus,
+    vid::{CustomFields, Provenance, VidMetadata, VidStats},
+    Error, ExportVid, RelationshipStatus, StoreChanges,
 };
 use aries_askar::{
     entry::EntryOperation,
@@ -10,21 +12,73 @@ use aries_askar::{
     ErrorKind, StoreKeyMethod,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The version of the wallet format written by this release; wallets written
+/// before the format was versioned have version 0
+pub const WALLET_VERSION: u32 = 1;
+
+/// Upgrades a stored VID record from the version at its index to the next version
+type Migration = fn(&mut serde_json::Map<String, Value>);
+
+const MIGRATIONS: [Migration; WALLET_VERSION as usize] = [
+    // version 1 added alternative transports and application metadata
+    |vid| {
+        vid.entry("alternative_transports")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        vid.entry("metadata").or_insert(Value::Null);
+    },
+];
+
+/// Upgrade a stored VID record from `version` to [WALLET_VERSION]
+fn migrate_record(data: &[u8], version: u32) -> Result<Value, Error> {
+    let mut record: Value = serde_json::from_slice(data)
+        .map_err(|_| Error::DecodeState("could not decode vid metadata"))?;
+
+    let Value::Object(vid) = &mut record else {
+        return Err(Error::DecodeState("could not decode vid metadata"));
+    };
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(vid);
+    }
+
+    Ok(record)
+}
 
 #[allow(dead_code)]
 pub struct Vault {
     inner: aries_askar::Store,
     url: String,
+    profile: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Metadata {
     id: String,
     transport: String,
+    #[serde(default)]
+    alternative_transports: Vec<String>,
     relation_status: RelationshipStatus,
     relation_vid: Option<String>,
     parent_vid: Option<String>,
     tunnel: Option<Box<[String]>>,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    vid_metadata: Option<VidMetadata>,
+    #[serde(default)]
+    stats: VidStats,
+    #[serde(default)]
+    digest_algorithm: Option<DigestAlgorithm>,
+    #[serde(default)]
+    cipher_suite: Option<CipherSuite>,
+    #[serde(default)]
+    provenance: Option<Provenance>,
+    #[serde(default)]
+    transport_override: Option<String>,
+    #[serde(default)]
+    custom: Option<CustomFields>,
 }
 
 #[allow(dead_code)]
@@ -37,7 +91,16 @@ impl Vault {
             aries_askar::Store::provision(&url, StoreKeyMethod::RawKey, pass_key, None, true)
                 .await?;
 
-        Ok(Self { inner, url })
+        let vault = Self {
+            inner,
+            url,
+            profile: None,
+        };
+        let mut conn = vault.session().await?;
+        Self::set_version(&mut conn, WALLET_VERSION).await?;
+        conn.commit().await?;
+
+        Ok(vault)
     }
 
     pub async fn open_sqlite(name: &str, password: &[u8]) -> Result<Self, Error> {
@@ -47,7 +110,148 @@ impl Vault {
         let inner =
             aries_askar::Store::open(&url, Some(StoreKeyMethod::RawKey), pass_key, None).await?;
 
-        Ok(Self { inner, url })
+        Ok(Self {
+            inner,
+            url,
+            profile: None,
+        })
+    }
+
+    /// Get the vault of the tenant `name`, which keeps the VIDs and extra data, e.g. the
+    /// aliases, of a [tenant store](crate::Store::tenant) apart from the other tenants
+    ///
+    /// The tenant is stored in its own profile of the same wallet, which is created on first
+    /// use. Closing a tenant vault closes the whole wallet.
+    pub async fn tenant(&self, name: &str) -> Result<Self, Error> {
+        let vault = Self {
+            inner: self.inner.clone(),
+            url: self.url.clone(),
+            profile: Some(Self::tenant_profile(name)),
+        };
+
+        match self.inner.create_profile(vault.profile.clone()).await {
+            Ok(_) => {
+                let mut conn = vault.session().await?;
+                Self::set_version(&mut conn, WALLET_VERSION).await?;
+                conn.commit().await?;
+            }
+            Err(e) if e.kind() == ErrorKind::Duplicate => {}
+            Err(e) => Err(Error::from(e))?,
+        }
+
+        Ok(vault)
+    }
+
+    /// Remove the tenant `name` and everything stored for it; returns whether the tenant existed
+    pub async fn remove_tenant(&self, name: &str) -> Result<bool, Error> {
+        Ok(self
+            .inner
+            .remove_profile(Self::tenant_profile(name))
+            .await?)
+    }
+
+    /// The profile of the tenant `name`, distinct from the default profile of the wallet
+    fn tenant_profile(name: &str) -> String {
+        format!("tenant:{name}")
+    }
+
+    async fn session(&self) -> Result<aries_askar::Session, Error> {
+        Ok(self.inner.session(self.profile.clone()).await?)
+    }
+
+    /// The version of the format this wallet was written in
+    pub async fn version(&self) -> Result<u32, Error> {
+        let mut conn = self.session().await?;
+        let version = Self::get_version(&mut conn).await?;
+        conn.commit().await?;
+
+        Ok(version)
+    }
+
+    async fn get_version(conn: &mut aries_askar::Session) -> Result<u32, Error> {
+        let Some(entry) = conn.fetch("wallet", "version", false).await? else {
+            return Ok(0);
+        };
+
+        let version = std::str::from_utf8(&entry.value)
+            .ok()
+            .and_then(|version| version.parse().ok())
+            .ok_or(Error::DecodeState("could not decode the wallet version"))?;
+
+        if version > WALLET_VERSION {
+            return Err(Error::DecodeState(
+                "the wallet was written by a newer release",
+            ));
+        }
+
+        Ok(version)
+    }
+
+    async fn set_version(conn: &mut aries_askar::Session, version: u32) -> Result<(), Error> {
+        let version = version.to_string();
+
+        if let Err(e) = conn
+            .insert("wallet", "version", version.as_bytes(), None, None)
+            .await
+        {
+            if e.kind() == ErrorKind::Duplicate {
+                conn.update(
+                    EntryOperation::Replace,
+                    "wallet",
+                    "version",
+                    Some(version.as_bytes()),
+                    None,
+                    None,
+                )
+                .await?;
+            } else {
+                Err(Error::from(e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Upgrade the wallet to the current format, see [Vault::migrate_from];
+    /// returns the version the wallet was upgraded from
+    pub async fn migrate(&self) -> Result<u32, Error> {
+        let version = self.version().await?;
+        self.migrate_from(version).await?;
+
+        Ok(version)
+    }
+
+    /// Upgrade the stored VIDs from format `version` to [WALLET_VERSION]
+    ///
+    /// [Vault::load] reads wallets of older versions as well, but migrating
+    /// rewrites them, so older releases can no longer open the wallet.
+    pub async fn migrate_from(&self, version: u32) -> Result<(), Error> {
+        if version > WALLET_VERSION {
+            return Err(Error::DecodeState(
+                "the wallet was written by a newer release",
+            ));
+        }
+
+        let mut conn = self.session().await?;
+
+        for item in conn.fetch_all(Some("vid"), None, None, true).await? {
+            let record = migrate_record(&item.value, version)?.to_string();
+
+            conn.update(
+                EntryOperation::Replace,
+                "vid",
+                &item.name,
+                Some(record.as_bytes()),
+                None,
+                None,
+            )
+            .await?;
+        }
+
+        Self::set_version(&mut conn, WALLET_VERSION).await?;
+        conn.commit().await?;
+
+        Ok(())
     }
 
     pub async fn persist(
@@ -55,105 +259,178 @@ impl Vault {
         vids: Vec<ExportVid>,
         extra_data: Option<serde_json::Value>,
     ) -> Result<(), Error> {
-        let mut conn = self.inner.session(None).await?;
+        let mut conn = self.session().await?;
 
         for export in vids {
-            let id = export.id;
-
-            if let Some(private) = export.sigkey {
-                let signing_key = LocalKey::from_secret_bytes(KeyAlg::Ed25519, private.as_ref())?;
-                let signing_key_name = format!("{id}#signing-key");
-
-                if let Err(e) = conn
-                    .insert_key(&signing_key_name, &signing_key, None, None, None)
-                    .await
-                {
-                    if e.kind() != ErrorKind::Duplicate {
-                        Err(Error::from(e))?;
-                    }
-                }
+            Self::write_vid(&mut conn, export).await?;
+        }
+
+        if let Some(extra_data) = extra_data {
+            Self::write_extra_data(&mut conn, &extra_data).await?;
+        }
+
+        conn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Write only the VIDs that changed, as taken with
+    /// [Store::take_changes](crate::Store::take_changes), in a single transaction; the VIDs
+    /// that were removed from the store are removed from the wallet, with their keys. The
+    /// `extra_data` is only written if it is given
+    pub async fn persist_changes(
+        &self,
+        changes: StoreChanges,
+        extra_data: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        let mut conn = self.session().await?;
+
+        for export in changes.vids {
+            Self::write_vid(&mut conn, export).await?;
+        }
+
+        for id in &changes.removed {
+            Self::remove_vid(&mut conn, id).await?;
+        }
+
+        if let Some(extra_data) = extra_data {
+            Self::write_extra_data(&mut conn, &extra_data).await?;
+        }
+
+        conn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Remove what is left behind in the wallet: the keys of VIDs without a record, e.g. of
+    /// VIDs that were forgotten while the whole wallet was rewritten with [Vault::persist],
+    /// which never removes anything, and records of VIDs whose keys are missing, which
+    /// [Vault::load] skips. Returns the number of entries that were removed
+    pub async fn compact(&self) -> Result<usize, Error> {
+        let mut conn = self.session().await?;
+        let mut removed = 0;
+
+        let records = conn.fetch_all(Some("vid"), None, None, true).await?;
+        let keys = conn.fetch_all_keys(None, None, None, None, true).await?;
+        let key_names = keys
+            .iter()
+            .map(|key| key.name().to_string())
+            .collect::<std::collections::HashSet<_>>();
+
+        let mut ids = std::collections::HashSet::new();
+        for record in records.iter() {
+            let public_keys =
+                ["verification-key", "encryption-key"].map(|key| format!("{}#{key}", record.name));
+
+            if public_keys.iter().all(|name| key_names.contains(name)) {
+                ids.insert(record.name.as_str());
+            } else {
+                conn.remove("vid", &record.name).await?;
+                removed += 1;
             }
+        }
 
-            if let Some(private) = export.enckey {
-                let decryption_key = LocalKey::from_secret_bytes(KeyAlg::X25519, private.as_ref())?;
-                let decryption_key_name = format!("{id}#decryption-key");
-                if let Err(e) = conn
-                    .insert_key(&decryption_key_name, &decryption_key, None, None, None)
-                    .await
-                {
-                    if e.kind() != ErrorKind::Duplicate {
-                        Err(Error::from(e))?;
-                    }
-                }
+        for name in &key_names {
+            let owner = name.rsplit_once('#').map(|(id, _)| id);
+
+            if !owner.is_some_and(|id| ids.contains(id)) {
+                conn.remove_key(name).await?;
+                removed += 1;
             }
+        }
+
+        conn.commit().await?;
+
+        Ok(removed)
+    }
+
+    /// Insert or replace the record of a VID, and insert its keys if they are not stored yet
+    async fn write_vid(conn: &mut aries_askar::Session, export: ExportVid) -> Result<(), Error> {
+        let id = export.id;
+
+        if let Some(private) = export.sigkey {
+            let signing_key =
+                LocalKey::from_secret_bytes(KeyAlg::Ed25519, private.expose_secret())?;
+            let signing_key_name = format!("{id}#signing-key");
 
-            let verification_key =
-                LocalKey::from_public_bytes(KeyAlg::Ed25519, export.public_sigkey.as_ref())?;
-            let verification_key_name = format!("{id}#verification-key");
             if let Err(e) = conn
-                .insert_key(&verification_key_name, &verification_key, None, None, None)
+                .insert_key(&signing_key_name, &signing_key, None, None, None)
                 .await
             {
                 if e.kind() != ErrorKind::Duplicate {
                     Err(Error::from(e))?;
                 }
             }
+        }
 
-            let encryption_key =
-                LocalKey::from_public_bytes(KeyAlg::X25519, export.public_enckey.as_ref())?;
-            let encryption_key_name = format!("{id}#encryption-key");
+        if let Some(private) = export.enckey {
+            let decryption_key =
+                LocalKey::from_secret_bytes(KeyAlg::X25519, private.expose_secret())?;
+            let decryption_key_name = format!("{id}#decryption-key");
             if let Err(e) = conn
-                .insert_key(&encryption_key_name, &encryption_key, None, None, None)
+                .insert_key(&decryption_key_name, &decryption_key, None, None, None)
                 .await
             {
                 if e.kind() != ErrorKind::Duplicate {
                     Err(Error::from(e))?;
                 }
             }
+        }
 
-            if let Ok(data) = serde_json::to_string(&Metadata {
-                id: id.to_string(),
-                transport: export.transport.to_string(),
-                relation_status: export.relation_status,
-                relation_vid: export.relation_vid,
-                parent_vid: export.parent_vid,
-                tunnel: export.tunnel,
-            }) {
-                if let Err(e) = conn.insert("vid", &id, data.as_bytes(), None, None).await {
-                    if e.kind() == ErrorKind::Duplicate {
-                        conn.update(
-                            EntryOperation::Replace,
-                            "vid",
-                            &id,
-                            Some(data.as_bytes()),
-                            None,
-                            None,
-                        )
-                        .await?;
-                    } else {
-                        Err(Error::from(e))?;
-                    }
-                }
+        let verification_key =
+            LocalKey::from_public_bytes(KeyAlg::Ed25519, export.public_sigkey.as_ref())?;
+        let verification_key_name = format!("{id}#verification-key");
+        if let Err(e) = conn
+            .insert_key(&verification_key_name, &verification_key, None, None, None)
+            .await
+        {
+            if e.kind() != ErrorKind::Duplicate {
+                Err(Error::from(e))?;
             }
         }
 
-        if let Some(extra_data) = extra_data {
-            if let Err(e) = conn
-                .insert(
-                    "extra_data",
-                    "extra_data",
-                    extra_data.to_string().as_bytes(),
-                    None,
-                    None,
-                )
-                .await
-            {
+        let encryption_key =
+            LocalKey::from_public_bytes(KeyAlg::X25519, export.public_enckey.as_ref())?;
+        let encryption_key_name = format!("{id}#encryption-key");
+        if let Err(e) = conn
+            .insert_key(&encryption_key_name, &encryption_key, None, None, None)
+            .await
+        {
+            if e.kind() != ErrorKind::Duplicate {
+                Err(Error::from(e))?;
+            }
+        }
+
+        if let Ok(data) = serde_json::to_string(&Metadata {
+            id: id.to_string(),
+            transport: export.transport.to_string(),
+            alternative_transports: export
+                .alternative_transports
+                .iter()
+                .map(|transport| transport.to_string())
+                .collect(),
+            relation_status: export.relation_status,
+            relation_vid: export.relation_vid,
+            parent_vid: export.parent_vid,
+            tunnel: export.tunnel,
+            metadata: export.metadata,
+            vid_metadata: export.vid_metadata,
+            stats: export.stats,
+            digest_algorithm: export.digest_algorithm,
+            cipher_suite: export.cipher_suite,
+            provenance: export.provenance,
+            transport_override: export
+                .transport_override
+                .map(|transport| transport.to_string()),
+            custom: export.custom,
+        }) {
+            if let Err(e) = conn.insert("vid", &id, data.as_bytes(), None, None).await {
                 if e.kind() == ErrorKind::Duplicate {
                     conn.update(
                         EntryOperation::Replace,
-                        "extra_data",
-                        "extra_data",
-                        Some(extra_data.to_string().as_bytes()),
+                        "vid",
+                        &id,
+                        Some(data.as_bytes()),
                         None,
                         None,
                     )
@@ -164,7 +441,62 @@ impl Vault {
             }
         }
 
-        conn.commit().await?;
+        Ok(())
+    }
+
+    /// Remove the record and keys of the VID `id`, if they are stored
+    async fn remove_vid(conn: &mut aries_askar::Session, id: &str) -> Result<(), Error> {
+        fn ignore_missing(result: Result<(), aries_askar::Error>) -> Result<(), Error> {
+            match result {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::from(e)),
+                _ => Ok(()),
+            }
+        }
+
+        ignore_missing(conn.remove("vid", id).await)?;
+
+        for key in [
+            "signing-key",
+            "decryption-key",
+            "verification-key",
+            "encryption-key",
+        ] {
+            ignore_missing(conn.remove_key(&format!("{id}#{key}")).await)?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_extra_data(
+        conn: &mut aries_askar::Session,
+        extra_data: &serde_json::Value,
+    ) -> Result<(), Error> {
+        let extra_data = extra_data.to_string();
+
+        if let Err(e) = conn
+            .insert(
+                "extra_data",
+                "extra_data",
+                extra_data.as_bytes(),
+                None,
+                None,
+            )
+            .await
+        {
+            if e.kind() == ErrorKind::Duplicate {
+                conn.update(
+                    EntryOperation::Replace,
+                    "extra_data",
+                    "extra_data",
+                    Some(extra_data.as_bytes()),
+                    None,
+                    None,
+                )
+                .await?;
+            } else {
+                Err(Error::from(e))?;
+            }
+        }
 
         Ok(())
     }
@@ -172,11 +504,12 @@ impl Vault {
     pub async fn load(&self) -> Result<(Vec<ExportVid>, Option<serde_json::Value>), Error> {
         let mut vids = Vec::new();
 
-        let mut conn = self.inner.session(None).await?;
+        let mut conn = self.session().await?;
+        let version = Self::get_version(&mut conn).await?;
         let results = conn.fetch_all(Some("vid"), None, None, false).await?;
 
         for item in results.iter() {
-            let data: Metadata = serde_json::from_slice(&item.value)
+            let data: Metadata = serde_json::from_value(migrate_record(&item.value, version)?)
                 .map_err(|_| Error::DecodeState("could not decode vid metadata"))?;
 
             let id = data.id.clone();
@@ -215,6 +548,14 @@ impl Vault {
                 transport: data.transport.parse().map_err(|_| {
                     Error::DecodeState("could not parse transport URL from storage")
                 })?,
+                alternative_transports: data
+                    .alternative_transports
+                    .iter()
+                    .map(|transport| transport.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| {
+                        Error::DecodeState("could not parse transport URL from storage")
+                    })?,
                 public_sigkey: verification_bytes.into(),
                 public_enckey: encryption_bytes.into(),
                 sigkey: None,
@@ -223,6 +564,20 @@ impl Vault {
                 relation_vid: data.relation_vid,
                 parent_vid: data.parent_vid,
                 tunnel: data.tunnel,
+                metadata: data.metadata,
+                vid_metadata: data.vid_metadata,
+                stats: data.stats,
+                digest_algorithm: data.digest_algorithm,
+                cipher_suite: data.cipher_suite,
+                provenance: data.provenance,
+                transport_override: data
+                    .transport_override
+                    .map(|transport| transport.parse())
+                    .transpose()
+                    .map_err(|_| {
+                        Error::DecodeState("could not parse transport URL from storage")
+                    })?,
+                custom: data.custom,
             };
 
             let signing_key_name = format!("{id}#signing-key");
@@ -276,6 +631,14 @@ impl Vault {
         Ok(())
     }
 
+    /// Close the vault while it is shared, e.g. by a [Wallet](crate::Wallet); like closing a
+    /// tenant vault, this closes the whole wallet
+    pub(crate) async fn close_shared(&self) -> Result<(), Error> {
+        self.inner.clone().close().await?;
+
+        Ok(())
+    }
+
     pub async fn destroy(self) -> Result<(), Error> {
         self.inner.close().await?;
         aries_askar::Store::remove(&self.url).await?;
@@ -316,4 +679,149 @@ mod test {
             vault.destroy().await.unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn test_persist_changes() {
+        let vault = Vault::new_sqlite("test-changes", b"password")
+            .await
+            .unwrap();
+
+        let store = Store::new();
+        let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        let bob = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        store.add_private_vid(alice.clone()).unwrap();
+        store.add_private_vid(bob.clone()).unwrap();
+
+        vault
+            .persist_changes(store.take_changes(), None)
+            .await
+            .unwrap();
+        assert_eq!(vault.load().await.unwrap().0.len(), 2);
+
+        store
+            .set_metadata(alice.identifier(), Some(serde_json::json!("alice")))
+            .unwrap();
+        store.forget_vid(bob.identifier()).unwrap();
+        vault
+            .persist_changes(store.take_changes(), Some(serde_json::json!({})))
+            .await
+            .unwrap();
+
+        let (vids, extra_data) = vault.load().await.unwrap();
+        assert_eq!(vids.len(), 1);
+        assert_eq!(vids[0].metadata, Some(serde_json::json!("alice")));
+        assert_eq!(extra_data, Some(serde_json::json!({})));
+
+        // the keys of a VID that was forgotten while the whole wallet was rewritten are
+        // left behind, until the wallet is compacted
+        let carol = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        store.add_private_vid(carol.clone()).unwrap();
+        vault.persist(store.export().unwrap(), None).await.unwrap();
+
+        let mut conn = vault.inner.session(None).await.unwrap();
+        conn.remove("vid", carol.identifier()).await.unwrap();
+        conn.commit().await.unwrap();
+
+        assert_eq!(vault.compact().await.unwrap(), 4);
+        assert_eq!(vault.compact().await.unwrap(), 0);
+        assert_eq!(vault.load().await.unwrap().0.len(), 1);
+
+        vault.destroy().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tenants() {
+        let vault = Vault::new_sqlite("test-tenants", b"password")
+            .await
+            .unwrap();
+
+        let store = Store::new();
+        let vid = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        store.tenant("acme").add_private_vid(vid.clone()).unwrap();
+
+        let acme = vault.tenant("acme").await.unwrap();
+        acme.persist(store.tenant("acme").export().unwrap(), None)
+            .await
+            .unwrap();
+        assert_eq!(acme.version().await.unwrap(), WALLET_VERSION);
+
+        // the vids of a tenant are not visible to the wallet or its other tenants
+        assert!(vault.load().await.unwrap().0.is_empty());
+        let globex = vault.tenant("globex").await.unwrap();
+        assert!(globex.load().await.unwrap().0.is_empty());
+
+        let (vids, _) = vault.tenant("acme").await.unwrap().load().await.unwrap();
+        assert_eq!(vids.len(), 1);
+        assert_eq!(vids[0].id, vid.identifier());
+
+        assert!(vault.remove_tenant("acme").await.unwrap());
+        assert!(!vault.remove_tenant("acme").await.unwrap());
+
+        vault.destroy().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let vault = Vault::new_sqlite("test-migrate", b"password")
+            .await
+            .unwrap();
+        assert_eq!(vault.version().await.unwrap(), WALLET_VERSION);
+
+        let store = Store::new();
+        let vid = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        store.add_private_vid(vid.clone()).unwrap();
+        vault.persist(store.export().unwrap(), None).await.unwrap();
+
+        // rewrite the wallet in the unversioned format of earlier releases
+        let id = vid.identifier();
+        let fixture = format!(
+            r#"{{"id":"{id}","transport":"tcp://127.0.0.1:1337","relation_status":"_Controlled","relation_vid":null,"parent_vid":null,"tunnel":null}}"#
+        );
+        let mut conn = vault.inner.session(None).await.unwrap();
+        conn.update(
+            EntryOperation::Replace,
+            "vid",
+            id,
+            Some(fixture.as_bytes()),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        conn.remove("wallet", "version").await.unwrap();
+        conn.commit().await.unwrap();
+
+        assert_eq!(vault.version().await.unwrap(), 0);
+
+        // older wallets can be loaded without migrating
+        let (vids, _) = vault.load().await.unwrap();
+        assert_eq!(vids.len(), 1);
+        assert!(vids[0].alternative_transports.is_empty());
+
+        assert_eq!(vault.migrate().await.unwrap(), 0);
+        assert_eq!(vault.version().await.unwrap(), WALLET_VERSION);
+
+        let mut conn = vault.inner.session(None).await.unwrap();
+        let record = conn.fetch("vid", id, false).await.unwrap().unwrap();
+        let record: Value = serde_json::from_slice(&record.value).unwrap();
+        assert_eq!(record["alternative_transports"], serde_json::json!([]));
+        conn.commit().await.unwrap();
+
+        let store = Store::new();
+        store.import(vault.load().await.unwrap().0).unwrap();
+        assert!(store.has_private_vid(id).unwrap());
+
+        vault.destroy().await.unwrap();
+    }
+
+    #[test]
+    fn test_migrate_record() {
+        let record = migrate_record(br#"{"id":"did:example:alice"}"#, 0).unwrap();
+        assert_eq!(record["metadata"], Value::Null);
+
+        // current records are left as they are
+        let current = br#"{"alternative_transports":["tcp://127.0.0.1:1337"]}"#;
+        let record = migrate_record(current, WALLET_VERSION).unwrap();
+        assert_eq!(record, serde_json::from_slice::<Value>(current).unwrap());
+    }
 }
@@ -0,0 +1,70 @@
+//! Persistence of VID types defined outside this library
+//!
+//! The store exports every VID as an [ExportVid](crate::ExportVid), which only holds the
+//! identifier, endpoints and keys. A custom [VerifiedVid] type keeps its other fields by
+//! returning them from [VerifiedVid::custom_fields]; they are exported with the VID and
+//! handed to the [VidCodec] registered under the same name with
+//! [Store::register_vid_codec](crate::Store::register_vid_codec) when the VID is imported.
+
+use std::sync::Arc;
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use super::{OwnedVid, Vid, VidError};
+use crate::definitions::{PrivateVid, PublicKeyData, PublicVerificationKeyData, VerifiedVid};
+
+/// The fields of a custom VID type that are not part of an [ExportVid](crate::ExportVid)
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomFields {
+    /// The name of the [VidCodec] that restores the VID
+    pub codec: String,
+    pub data: serde_json::Value,
+}
+
+/// Restores VIDs of a custom type from their standard parts and their [CustomFields]
+pub trait VidCodec: Send + Sync {
+    /// Restore a VID we have a relationship with
+    fn decode_verified(
+        &self,
+        vid: Vid,
+        data: &serde_json::Value,
+    ) -> Result<Arc<dyn VerifiedVid>, VidError>;
+
+    /// Restore one of our own VIDs, including its private keys
+    fn decode_private(
+        &self,
+        vid: OwnedVid,
+        data: &serde_json::Value,
+    ) -> Result<Arc<dyn PrivateVid>, VidError>;
+}
+
+/// A restored private VID, used where the store expects a [VerifiedVid]
+pub(crate) struct PrivateAsVerified(pub(crate) Arc<dyn PrivateVid>);
+
+impl VerifiedVid for PrivateAsVerified {
+    fn identifier(&self) -> &str {
+        self.0.identifier()
+    }
+
+    fn endpoint(&self) -> &url::Url {
+        self.0.endpoint()
+    }
+
+    fn alternative_endpoints(&self) -> &[url::Url] {
+        self.0.alternative_endpoints()
+    }
+
+    fn verifying_key(&self) -> &PublicVerificationKeyData {
+        self.0.verifying_key()
+    }
+
+    fn encryption_key(&self) -> &PublicKeyData {
+        self.0.encryption_key()
+    }
+
+    fn custom_fields(&self) -> Option<CustomFields> {
+        self.0.custom_fields()
+    }
+}
@@ -0,0 +1,160 @@
+//! A compact single-line encoding of a verified VID, small enough to fit in a QR code
+//!
+//! The encoding is `tspvid:` followed by the base64url (unpadded) encoding of:
+//!
+//! | bytes | content                                                 |
+//! |-------|---------------------------------------------------------|
+//! | 1     | format version                                          |
+//! | 1     | signature key type (1 = Ed25519)                        |
+//! | 1     | encryption key type (1 = X25519, 2 = X25519Kyber768)    |
+//! | 2 + n | identifier                                              |
+//! | 2 + n | transport URL                                           |
+//! | 2 + n | verification key                                        |
+//! | 2 + n | encryption key                                          |
+//!
+//! Every variable length field is prefixed with its length as a big endian u16.
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use super::{error::VidError, resolve::verify_vid_offline, Vid};
+use crate::definitions::{VerifiedVid, PUBLIC_KEY_SIZE, PUBLIC_VERIFICATION_KEY_SIZE};
+
+const PREFIX: &str = "tspvid:";
+const VERSION: u8 = 1;
+const ED25519: u8 = 1;
+
+#[cfg(not(feature = "pq"))]
+const ENCRYPTION_KEY_TYPE: u8 = 1;
+
+#[cfg(feature = "pq")]
+const ENCRYPTION_KEY_TYPE: u8 = 2;
+
+fn push_field(out: &mut Vec<u8>, field: &[u8]) {
+    // fields are bounded by the key sizes and reasonable identifier lengths
+    let len = u16::try_from(field.len()).unwrap_or(u16::MAX);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(&field[..len as usize]);
+}
+
+fn take_field<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, rest) = input.split_first_chunk::<2>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+
+    if rest.len() < len {
+        return None;
+    }
+
+    let (field, rest) = rest.split_at(len);
+    *input = rest;
+
+    Some(field)
+}
+
+pub(crate) fn encode_compact(vid: &dyn VerifiedVid) -> String {
+    let mut data = vec![VERSION, ED25519, ENCRYPTION_KEY_TYPE];
+    push_field(&mut data, vid.identifier().as_bytes());
+    push_field(&mut data, vid.endpoint().as_str().as_bytes());
+    push_field(&mut data, vid.verifying_key().as_ref());
+    push_field(&mut data, vid.encryption_key().as_ref());
+
+    format!("{PREFIX}{}", Base64UrlUnpadded::encode_string(&data))
+}
+
+impl Vid {
+    /// Encode this VID, including its endpoint and public keys, as a compact string
+    /// suitable for out-of-band exchange, e.g. in a QR code
+    pub fn to_compact_string(&self) -> String {
+        encode_compact(self)
+    }
+
+    /// Decode a VID encoded with [Vid::to_compact_string]
+    ///
+    /// The keys of a `did:peer` VID are checked against its identifier; for other
+    /// VID types the keys are trusted as received out-of-band
+    pub fn from_compact_string(compact: &str) -> Result<Vid, VidError> {
+        let invalid = || VidError::InvalidVid(compact.to_string());
+
+        let data = compact
+            .trim()
+            .strip_prefix(PREFIX)
+            .and_then(|encoded| Base64UrlUnpadded::decode_vec(encoded).ok())
+            .ok_or_else(invalid)?;
+
+        let [VERSION, ED25519, ENCRYPTION_KEY_TYPE, rest @ ..] = data.as_slice() else {
+            return Err(VidError::ResolveVid(
+                "unsupported compact VID version or key types",
+            ));
+        };
+
+        let mut rest = rest;
+        let mut field = || take_field(&mut rest).ok_or_else(invalid);
+
+        let id = std::str::from_utf8(field()?).map_err(|_| invalid())?;
+        let transport = std::str::from_utf8(field()?)
+            .ok()
+            .and_then(|url| url.parse().ok())
+            .ok_or_else(invalid)?;
+        let public_sigkey: [u8; PUBLIC_VERIFICATION_KEY_SIZE] =
+            field()?.try_into().map_err(|_| invalid())?;
+        let public_enckey: [u8; PUBLIC_KEY_SIZE] = field()?.try_into().map_err(|_| invalid())?;
+
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+
+        let vid = Vid {
+            id: id.to_string(),
+            transport,
+            alternative_transports: Vec::new(),
+            public_sigkey: public_sigkey.into(),
+            public_enckey: public_enckey.into(),
+        };
+
+        if let Ok(peer) = verify_vid_offline(id) {
+            if peer.verifying_key() != vid.verifying_key()
+                || peer.encryption_key() != vid.encryption_key()
+            {
+                return Err(VidError::ResolveVid(
+                    "keys do not match the did:peer identifier",
+                ));
+            }
+        }
+
+        Ok(vid)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{OwnedVid, VerifiedVid, Vid};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_compact_string() {
+        let alice = OwnedVid::bind(
+            "did:web:did.tsp-test.org:user:alice",
+            "https://demo.teaspoon.world/user/alice".parse().unwrap(),
+        );
+
+        let compact = alice.vid().to_compact_string();
+        assert!(compact.starts_with("tspvid:"));
+
+        let decoded = Vid::from_compact_string(&compact).unwrap();
+        assert_eq!(decoded.identifier(), alice.identifier());
+        assert_eq!(decoded.endpoint(), alice.endpoint());
+        assert_eq!(decoded.verifying_key(), alice.verifying_key());
+        assert_eq!(decoded.encryption_key(), alice.encryption_key());
+
+        assert!(Vid::from_compact_string(&compact[..compact.len() - 4]).is_err());
+        assert!(Vid::from_compact_string("did:web:example.com").is_err());
+
+        // the keys of a did:peer must match its identifier
+        let peer = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+        assert!(Vid::from_compact_string(&peer.vid().to_compact_string()).is_ok());
+
+        let mut forged = peer.vid().clone();
+        forged.public_enckey = alice.encryption_key().clone();
+        assert!(Vid::from_compact_string(&forged.to_compact_string()).is_err());
+    }
+}
@@ -1,10 +1,12 @@
 use base64ct::{Base64UrlUnpadded, Encoding};
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
 use crate::definitions::{
     PrivateKeyData, PrivateSigningKeyData, PublicKeyData, PublicVerificationKeyData,
     PRIVATE_KEY_SIZE, PRIVATE_SIGNING_KEY_SIZE, PUBLIC_KEY_SIZE, PUBLIC_VERIFICATION_KEY_SIZE,
 };
+use crate::secret::REDACTED;
 
 #[cfg(feature = "async")]
 use super::{error::VidError, OwnedVid};
@@ -47,13 +49,13 @@ impl Serialize for PublicVerificationKeyData {
     }
 }
 
+/// Private keys are redacted when serialized, see [expose_secret] for the exception
 impl Serialize for PrivateKeyData {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let key = Base64UrlUnpadded::encode_string(self.as_ref());
-        serializer.serialize_str(&key)
+        serializer.serialize_str(REDACTED)
     }
 }
 
@@ -62,8 +64,50 @@ impl Serialize for PrivateSigningKeyData {
     where
         S: serde::Serializer,
     {
-        let key = Base64UrlUnpadded::encode_string(self.as_ref());
-        serializer.serialize_str(&key)
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// Key material that can be serialized in the clear by [expose_secret]
+pub(crate) trait ExposeSecret {
+    fn expose_bytes(&self) -> &[u8];
+}
+
+impl ExposeSecret for PrivateKeyData {
+    fn expose_bytes(&self) -> &[u8] {
+        self.expose_secret()
+    }
+}
+
+impl ExposeSecret for PrivateSigningKeyData {
+    fn expose_bytes(&self) -> &[u8] {
+        self.expose_secret()
+    }
+}
+
+/// Serialize private key material in the clear; only for formats that have to contain it,
+/// i.e. private VID files and (encrypted) exports, using `#[serde(serialize_with)]`
+pub(crate) fn expose_secret<K, S>(key: &K, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: ExposeSecret,
+    S: serde::Serializer,
+{
+    let key = Zeroizing::new(Base64UrlUnpadded::encode_string(key.expose_bytes()));
+    serializer.serialize_str(&key)
+}
+
+/// Like [expose_secret], for keys that may be absent
+pub(crate) fn expose_optional_secret<K, S>(
+    key: &Option<K>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ExposeSecret,
+    S: serde::Serializer,
+{
+    match key {
+        Some(key) => expose_secret(key, serializer),
+        None => serializer.serialize_none(),
     }
 }
 
@@ -72,8 +116,8 @@ impl<'de> Deserialize<'de> for PublicKeyData {
     where
         D: serde::Deserializer<'de>,
     {
-        let encoded: &str = Deserialize::deserialize(deserializer)?;
-        let key = Base64UrlUnpadded::decode_vec(encoded).map_err(serde::de::Error::custom)?;
+        let encoded: String = Deserialize::deserialize(deserializer)?;
+        let key = Base64UrlUnpadded::decode_vec(&encoded).map_err(serde::de::Error::custom)?;
         let key: [u8; PUBLIC_KEY_SIZE] = key
             .try_into()
             .map_err(|_| serde::de::Error::custom("key data has incorrect length"))?;
@@ -87,8 +131,8 @@ impl<'de> Deserialize<'de> for PublicVerificationKeyData {
     where
         D: serde::Deserializer<'de>,
     {
-        let encoded: &str = Deserialize::deserialize(deserializer)?;
-        let key = Base64UrlUnpadded::decode_vec(encoded).map_err(serde::de::Error::custom)?;
+        let encoded: String = Deserialize::deserialize(deserializer)?;
+        let key = Base64UrlUnpadded::decode_vec(&encoded).map_err(serde::de::Error::custom)?;
         let key: [u8; PUBLIC_VERIFICATION_KEY_SIZE] = key
             .try_into()
             .map_err(|_| serde::de::Error::custom("key data has incorrect length"))?;
@@ -102,9 +146,12 @@ impl<'de> Deserialize<'de> for PrivateKeyData {
     where
         D: serde::Deserializer<'de>,
     {
-        let encoded: &str = Deserialize::deserialize(deserializer)?;
-        let key = Base64UrlUnpadded::decode_vec(encoded).map_err(serde::de::Error::custom)?;
+        let encoded: Zeroizing<String> = Zeroizing::new(Deserialize::deserialize(deserializer)?);
+        let key = Zeroizing::new(
+            Base64UrlUnpadded::decode_vec(&encoded).map_err(serde::de::Error::custom)?,
+        );
         let key: [u8; PRIVATE_KEY_SIZE] = key
+            .as_slice()
             .try_into()
             .map_err(|_| serde::de::Error::custom("key data has incorrect length"))?;
 
@@ -117,9 +164,12 @@ impl<'de> Deserialize<'de> for PrivateSigningKeyData {
     where
         D: serde::Deserializer<'de>,
     {
-        let encoded: &str = Deserialize::deserialize(deserializer)?;
-        let key = Base64UrlUnpadded::decode_vec(encoded).map_err(serde::de::Error::custom)?;
+        let encoded: Zeroizing<String> = Zeroizing::new(Deserialize::deserialize(deserializer)?);
+        let key = Zeroizing::new(
+            Base64UrlUnpadded::decode_vec(&encoded).map_err(serde::de::Error::custom)?,
+        );
         let key: [u8; PRIVATE_SIGNING_KEY_SIZE] = key
+            .as_slice()
             .try_into()
             .map_err(|_| serde::de::Error::custom("key data has incorrect length"))?;
 
@@ -132,6 +182,7 @@ impl<'de> Deserialize<'de> for PrivateSigningKeyData {
 #[cfg(test)]
 mod test {
     use super::OwnedVid;
+    use crate::PrivateVid;
 
     #[tokio::test]
     async fn deserialize() {
@@ -142,4 +193,25 @@ mod test {
         assert_eq!(alice.vid().id, "did:web:did.tsp-test.org:user:alice");
         assert_eq!(alice.vid().transport.as_str(), "tcp://127.0.0.1:13371");
     }
+
+    #[tokio::test]
+    async fn redact_private_keys() {
+        let alice = OwnedVid::from_file("../examples/test/alice.json")
+            .await
+            .unwrap();
+
+        let key = serde_json::to_value(alice.decryption_key()).unwrap();
+        assert_eq!(key, crate::secret::REDACTED);
+
+        let exposed = serde_json::to_value(&alice).unwrap();
+        let restored: OwnedVid = serde_json::from_value(exposed).unwrap();
+        assert_eq!(
+            restored.decryption_key().expose_secret(),
+            alice.decryption_key().expose_secret()
+        );
+        assert_eq!(
+            restored.signing_key().expose_secret(),
+            alice.signing_key().expose_secret()
+        );
+    }
 }
@@ -1,10 +1,18 @@
-use crate::{definitions::VerifiedVid, vid::error::VidError, Vid};
+use crate::{
+    definitions::{VerifiedVid, PUBLIC_KEY_SIZE, PUBLIC_VERIFICATION_KEY_SIZE},
+    vid::error::VidError,
+    Vid,
+};
 use base64ct::{Base64UrlUnpadded, Encoding};
 use serde_json::json;
 use url::Url;
 
 pub(crate) const SCHEME: &str = "peer";
 
+/// Multicodec for ed25519-pub and x25519-pub
+const ED25519_PUB: u8 = 0xed;
+const X25519_PUB: u8 = 0xec;
+
 /// Encode VID as did:peer,include verification end encryption key
 /// The service definition has type `tsp`
 /// See <https://identity.foundation/peer-did-method-spec/>
@@ -45,8 +53,16 @@ pub fn encode_did_peer(vid: &Vid) -> String {
     format!("did:peer:2.Vz{verification_key}.Ez{encryption_key}.S{service}")
 }
 
+/// Verify a did:peer with numalgo 2, both in the form created by [encode_did_peer] and in
+/// the standard form, i.e. with varint multicodec keys, multiple keys and services, and
+/// service endpoint objects with `routingKeys`
+///
+/// The first supported verification (`V`) and key agreement (`E`) keys are used. The
+/// endpoint of the first `tsp` service becomes the transport and any other `tsp` services
+/// become alternative transports; without a `tsp` service, the first service endpoint that
+/// is a valid URL is used. Routing keys are accepted but not used.
 pub fn verify_did_peer(parts: &[&str]) -> Result<Vid, VidError> {
-    let mut peer_parts = parts[2].split('.');
+    let mut peer_parts = parts.get(2).unwrap_or(&"").split('.');
 
     // only numalgo 2 is supported
     if peer_parts.next() != Some("2") {
@@ -57,68 +73,48 @@ pub fn verify_did_peer(parts: &[&str]) -> Result<Vid, VidError> {
 
     let mut public_sigkey = None;
     let mut public_enckey = None;
-    let mut transport = None;
-
-    let mut buf = [0; 34];
+    let mut services = Vec::new();
 
     for part in peer_parts {
-        match &part[0..2] {
-            // Key Agreement (Encryption) + base58 multibase prefix
-            "Ez" => {
-                let count = bs58::decode(&part[2..])
-                    .with_alphabet(bs58::Alphabet::BITCOIN)
-                    .onto(&mut buf)
-                    .map_err(|_| {
-                        VidError::ResolveVid("invalid encoded encryption key in did:peer")
-                    })?;
-
-                debug_assert_eq!(count, 34);
-
-                // multicodec for x25519-pub + length 32 bytes
-                if let [0xec, 0x20, rest @ ..] = buf {
-                    public_enckey = rest.last_chunk().map(|c| (*c).into())
-                } else {
-                    return Err(VidError::ResolveVid(
-                        "invalid encryption key type in did:peer",
-                    ));
-                }
+        let (purpose, value) = match part.as_bytes() {
+            [purpose, ..] if purpose.is_ascii() => (*purpose, &part[1..]),
+            _ => return Err(VidError::ResolveVid("invalid part in did:peer")),
+        };
+
+        match purpose {
+            // Authentication (Verification)
+            b'V' if public_sigkey.is_none() => {
+                public_sigkey = decode_key::<PUBLIC_VERIFICATION_KEY_SIZE>(
+                    value,
+                    ED25519_PUB,
+                    "invalid verification key in did:peer",
+                )?
+                .map(Into::into);
             }
-            // Authentication (Verification) + base58 multibase prefix
-            "Vz" => {
-                let count = bs58::decode(&part[2..])
-                    .with_alphabet(bs58::Alphabet::BITCOIN)
-                    .onto(&mut buf)
-                    .map_err(|_| {
-                        VidError::ResolveVid("invalid encoded verification key in did:peer")
-                    })?;
-
-                debug_assert_eq!(count, 34);
-
-                // multicodec for ed25519-pub + length 32 bytes
-                if let [0xed, 0x20, rest @ ..] = buf {
-                    if let Some(sigkey_bytes) = rest.last_chunk::<32>() {
-                        public_sigkey = Some((*sigkey_bytes).into());
-                    }
-                } else {
-                    return Err(VidError::ResolveVid(
-                        "invalid verification key type in did:peer",
-                    ));
-                }
+            // Key Agreement (Encryption)
+            b'E' if public_enckey.is_none() => {
+                public_enckey = decode_key::<PUBLIC_KEY_SIZE>(
+                    value,
+                    X25519_PUB,
+                    "invalid encryption key in did:peer",
+                )?
+                .map(Into::into);
             }
-            // start of base64url encoded service definition
-            "Se" => {
-                let transport_bytes = Base64UrlUnpadded::decode_vec(&part[1..])
+            // further keys, or keys for purposes that are not used by TSP
+            b'V' | b'E' | b'A' | b'I' | b'D' => {}
+            // base64url encoded service definition, or list of service definitions
+            b'S' => {
+                let transport_bytes = Base64UrlUnpadded::decode_vec(value.trim_end_matches('='))
                     .map_err(|_| VidError::ResolveVid("invalid encoded transport in did:peer"))?;
 
                 let transport_json: serde_json::Value = serde_json::from_slice(&transport_bytes)
                     .map_err(|_| VidError::ResolveVid("invalid encoded transport in did:peer"))?;
 
-                if transport_json["t"] != "tsp" {
-                    return Err(VidError::ResolveVid("invalid transport type in did:peer"));
-                }
-
-                if let Some(transport_bytes) = &transport_json["s"]["uri"].as_str() {
-                    transport = Url::parse(transport_bytes).ok();
+                match transport_json {
+                    serde_json::Value::Array(list) => {
+                        services.extend(list.iter().filter_map(decode_service))
+                    }
+                    service => services.extend(decode_service(&service)),
                 }
             }
             _ => {
@@ -127,10 +123,20 @@ pub fn verify_did_peer(parts: &[&str]) -> Result<Vid, VidError> {
         }
     }
 
-    match (public_sigkey, public_enckey, transport) {
+    let (mut tsp_services, other_services): (Vec<_>, Vec<_>) =
+        services.into_iter().partition(|(is_tsp, _)| *is_tsp);
+
+    if tsp_services.is_empty() {
+        tsp_services.extend(other_services.into_iter().take(1));
+    }
+
+    let mut transports = tsp_services.into_iter().map(|(_, url)| url);
+
+    match (public_sigkey, public_enckey, transports.next()) {
         (Some(public_sigkey), Some(public_enckey), Some(transport)) => Ok(Vid {
             id: parts.join(":"),
             transport,
+            alternative_transports: transports.collect(),
             public_sigkey,
             public_enckey,
         }),
@@ -140,6 +146,47 @@ pub fn verify_did_peer(parts: &[&str]) -> Result<Vid, VidError> {
     }
 }
 
+/// Decode a base58 multibase encoded key, returns `None` for keys of another type than `codec`
+///
+/// The standard form encodes the multicodec as a varint (`0x01` continuation), the form
+/// created by [encode_did_peer] follows the multicodec by the key length (`0x20`)
+fn decode_key<const N: usize>(
+    value: &str,
+    codec: u8,
+    error: &'static str,
+) -> Result<Option<[u8; N]>, VidError> {
+    let encoded = value.strip_prefix('z').ok_or(VidError::ResolveVid(error))?;
+
+    let bytes = bs58::decode(encoded)
+        .with_alphabet(bs58::Alphabet::BITCOIN)
+        .into_vec()
+        .map_err(|_| VidError::ResolveVid(error))?;
+
+    match bytes.as_slice() {
+        [c, 0x01 | 0x20, key @ ..] if *c == codec => key
+            .try_into()
+            .map(Some)
+            .map_err(|_| VidError::ResolveVid(error)),
+        _ => Ok(None),
+    }
+}
+
+/// Decode a (possibly abbreviated) service definition into whether it is a TSP service and
+/// its endpoint; the endpoint is either a URI or an object with a `uri` and `routingKeys`
+fn decode_service(service: &serde_json::Value) -> Option<(bool, Url)> {
+    let service_type = service.get("t").or_else(|| service.get("type"))?;
+    let endpoint = service
+        .get("s")
+        .or_else(|| service.get("serviceEndpoint"))?;
+
+    let uri = match endpoint {
+        serde_json::Value::String(uri) => uri.as_str(),
+        endpoint => endpoint.get("uri")?.as_str()?,
+    };
+
+    Some((service_type == "tsp", Url::parse(uri).ok()?))
+}
+
 #[cfg(not(feature = "pq"))]
 #[cfg(test)]
 mod test {
@@ -150,6 +197,8 @@ mod test {
     use crate::Vid;
 
     use super::{encode_did_peer, verify_did_peer};
+    use base64ct::{Base64UrlUnpadded, Encoding};
+    use serde_json::json;
 
     #[test]
     #[wasm_bindgen_test]
@@ -160,6 +209,7 @@ mod test {
         let mut vid = Vid {
             id: Default::default(),
             transport: Url::parse("tcp://127.0.0.1:1337").unwrap(),
+            alternative_transports: Vec::new(),
             public_sigkey,
             public_enckey,
         };
@@ -174,4 +224,84 @@ mod test {
         assert_eq!(vid.encryption_key(), resolved_vid.encryption_key());
         assert_eq!(vid.endpoint(), resolved_vid.endpoint());
     }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn decode_standard_numalgo_2() {
+        let (_sigkey, public_sigkey) = crate::crypto::gen_sign_keypair();
+        let (_enckey, public_enckey) = crate::crypto::gen_encrypt_keypair();
+
+        // multibase (base58btc) encoded key with a varint multicodec
+        let key = |codec: &[u8], key: &[u8]| {
+            let bytes = [codec, key].concat();
+            format!("z{}", bs58::encode(bytes).into_string())
+        };
+        let service = |service: serde_json::Value| {
+            Base64UrlUnpadded::encode_string(service.to_string().as_bytes())
+        };
+
+        let id = format!(
+            "did:peer:2.V{}.V{}.E{}.A{}.S{}.S{}",
+            // a p256-pub key is skipped
+            key(&[0x80, 0x24], &[2; 33]),
+            key(&[0xed, 0x01], public_sigkey.as_ref()),
+            key(&[0xec, 0x01], public_enckey.as_ref()),
+            key(&[0xec, 0x01], &[0; 32]),
+            service(json!({
+                "t": "dm",
+                "s": {
+                    "uri": "https://example.com/didcomm",
+                    "a": ["didcomm/v2"],
+                    "r": ["did:example:mediator#key-1"]
+                }
+            })),
+            service(json!([
+                { "t": "tsp", "s": "https://example.com/tsp" },
+                { "t": "tsp", "s": { "uri": "tcp://127.0.0.1:1337", "r": [] } }
+            ])),
+        );
+
+        let parts = id.split(':').collect::<Vec<&str>>();
+        let vid = verify_did_peer(&parts).unwrap();
+
+        assert_eq!(vid.identifier(), id);
+        assert_eq!(vid.verifying_key(), &public_sigkey);
+        assert_eq!(vid.encryption_key(), &public_enckey);
+        assert_eq!(vid.endpoint().as_str(), "https://example.com/tsp");
+        assert_eq!(
+            vid.alternative_endpoints(),
+            &[Url::parse("tcp://127.0.0.1:1337").unwrap()]
+        );
+
+        // without a tsp service, the first service endpoint is used
+        let id = format!(
+            "did:peer:2.E{}.V{}.S{}",
+            key(&[0xec, 0x01], public_enckey.as_ref()),
+            key(&[0xed, 0x01], public_sigkey.as_ref()),
+            service(json!({
+                "t": "dm",
+                "s": { "uri": "https://example.com/didcomm", "r": ["did:example:mediator"] }
+            })),
+        );
+
+        let parts = id.split(':').collect::<Vec<&str>>();
+        let vid = verify_did_peer(&parts).unwrap();
+
+        assert_eq!(vid.endpoint().as_str(), "https://example.com/didcomm");
+        assert!(vid.alternative_endpoints().is_empty());
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn reject_invalid_parts() {
+        for id in [
+            "did:peer",
+            "did:peer:0z6Mk",
+            "did:peer:2.",
+            "did:peer:2.Xz6Mk",
+        ] {
+            let parts = id.split(':').collect::<Vec<&str>>();
+            assert!(verify_did_peer(&parts).is_err());
+        }
+    }
 }
@@ -1,10 +1,14 @@
 use crate::definitions::{VerifiedVid, PUBLIC_KEY_SIZE, PUBLIC_VERIFICATION_KEY_SIZE};
-use base64ct::{Base64UrlUnpadded, Encoding};
 use serde::Deserialize;
 use serde_json::json;
 use url::Url;
 
-use crate::vid::{error::VidError, OwnedVid, Vid};
+use crate::vid::{
+    error::VidError,
+    jwk::Jwk,
+    metadata::{ServiceEntry, VidMetadata},
+    OwnedVid, Vid,
+};
 
 pub(crate) const SCHEME: &str = "web";
 
@@ -18,13 +22,27 @@ const DOCUMENT: &str = "did.json";
 pub struct DidDocument {
     #[serde(rename = "@context")]
     pub context: Vec<String>,
-    pub authentication: Vec<String>,
+    #[serde(default)]
+    pub authentication: Vec<VerificationMethodRef>,
     pub id: String,
-    pub key_agreement: Vec<String>,
+    #[serde(default)]
+    pub also_known_as: Vec<String>,
+    #[serde(default)]
+    pub key_agreement: Vec<VerificationMethodRef>,
     pub service: Vec<Service>,
+    #[serde(default)]
     pub verification_method: Vec<VerificationMethod>,
 }
 
+/// An entry of a verification relationship like `authentication`: either the id of a
+/// verification method, possibly relative like `#key-1`, or an embedded method
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum VerificationMethodRef {
+    Reference(String),
+    Embedded(VerificationMethod),
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -41,23 +59,12 @@ pub struct Service {
 pub struct VerificationMethod {
     pub controller: String,
     pub id: String,
-    pub public_key_jwk: PublicKeyJwk,
+    pub public_key_jwk: Jwk,
     #[serde(rename = "type")]
     pub method_type: String,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct PublicKeyJwk {
-    pub crv: String,
-    pub kty: String,
-    #[serde(rename = "use")]
-    pub usage: String,
-    pub x: String,
-}
-
-pub async fn resolve(id: &str, parts: Vec<&str>) -> Result<Vid, VidError> {
+pub async fn resolve(id: &str, parts: Vec<&str>) -> Result<(Vid, VidMetadata), VidError> {
     #[cfg(test)]
     {
         let did_doc = std::fs::read_to_string(format!(
@@ -67,8 +74,9 @@ pub async fn resolve(id: &str, parts: Vec<&str>) -> Result<Vid, VidError> {
         .map_err(|_| VidError::ResolveVid("JSON not found in test dir"))?;
 
         let did_doc: DidDocument = serde_json::from_str(&did_doc).unwrap();
+        let metadata = document_metadata(&did_doc);
 
-        resolve_document(did_doc, id)
+        Ok((resolve_document(did_doc, id)?, metadata))
     }
 
     #[cfg(not(test))]
@@ -86,8 +94,82 @@ pub async fn resolve(id: &str, parts: Vec<&str>) -> Result<Vid, VidError> {
                 .map_err(|e| VidError::Json(url.to_string(), e))?,
             Err(e) => Err(VidError::Http(url.to_string(), e))?,
         };
+        let metadata = document_metadata(&did_document);
+
+        Ok((resolve_document(did_document, id)?, metadata))
+    }
+}
+
+/// The result of [fetch_document]
+#[cfg(feature = "async")]
+pub(crate) enum FetchedDocument {
+    /// The document did not change since it was fetched with the given entity tag
+    #[cfg_attr(test, allow(dead_code))]
+    NotModified,
+    /// The raw JSON of the DID document, and its entity tag if the server sent one
+    Document {
+        document: String,
+        etag: Option<String>,
+    },
+}
+
+/// Fetch the raw DID document for `id`, revalidating it if an entity tag is known
+#[cfg(feature = "async")]
+pub(crate) async fn fetch_document(
+    id: &str,
+    etag: Option<&str>,
+) -> Result<FetchedDocument, VidError> {
+    let parts = id.split(':').collect::<Vec<&str>>();
+
+    #[cfg(test)]
+    {
+        let _ = etag;
+        let document = std::fs::read_to_string(format!(
+            "../examples/test/{}-did.json",
+            parts.get(4).unwrap_or(&"invalid")
+        ))
+        .map_err(|_| VidError::ResolveVid("JSON not found in test dir"))?;
+
+        Ok(FetchedDocument::Document {
+            document,
+            etag: None,
+        })
+    }
+
+    #[cfg(not(test))]
+    {
+        let url = resolve_url(&parts)?;
 
-        resolve_document(did_document, id)
+        let mut request = reqwest::Client::new().get(url.as_ref());
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| VidError::Http(url.to_string(), e))?;
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(FetchedDocument::NotModified);
+        }
+
+        let response = response
+            .error_for_status()
+            .map_err(|e| VidError::Http(url.to_string(), e))?;
+
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        let document = response
+            .text()
+            .await
+            .map_err(|e| VidError::Http(url.to_string(), e))?;
+
+        Ok(FetchedDocument::Document { document, etag })
     }
 }
 
@@ -103,29 +185,81 @@ pub fn resolve_url(parts: &[&str]) -> Result<Url, VidError> {
     .map_err(|_| VidError::InvalidVid(parts.join(":")))
 }
 
-pub fn find_first_key<const N: usize>(
+/// The absolute id of a verification method, resolving fragments like `#key-1` against
+/// the id of the DID document
+fn absolute_method_id(did_document: &DidDocument, id: &str) -> String {
+    if id.starts_with('#') {
+        format!("{}{id}", did_document.id)
+    } else {
+        id.to_string()
+    }
+}
+
+/// Find the first key listed in the verification `relationship` that `key` accepts,
+/// returning the absolute id of its verification method with it; methods on other
+/// curves, with another `use`, or with malformed keys are skipped
+pub fn find_key<const N: usize>(
     did_document: &DidDocument,
-    method: &[String],
-    curve: &str,
-    usage: &str,
-) -> Option<[u8; N]> {
-    method
+    relationship: &[VerificationMethodRef],
+    key: fn(&Jwk) -> Result<[u8; N], VidError>,
+) -> Option<(String, [u8; N])> {
+    relationship
         .iter()
-        .next()
-        .and_then(|id| {
-            did_document
-                .verification_method
-                .iter()
-                .find(|item| &item.id == id)
-        })
-        .and_then(|method| {
-            if method.public_key_jwk.crv == curve && method.public_key_jwk.usage == usage {
-                Base64UrlUnpadded::decode_vec(&method.public_key_jwk.x).ok()
-            } else {
-                None
+        .filter_map(|entry| match entry {
+            VerificationMethodRef::Reference(id) => {
+                let id = absolute_method_id(did_document, id);
+
+                did_document
+                    .verification_method
+                    .iter()
+                    .find(|method| absolute_method_id(did_document, &method.id) == id)
             }
+            VerificationMethodRef::Embedded(method) => Some(method),
         })
-        .and_then(|key| <[u8; N]>::try_from(key).ok())
+        .find_map(|method| {
+            let key = key(&method.public_key_jwk).ok()?;
+
+            Some((absolute_method_id(did_document, &method.id), key))
+        })
+}
+
+fn find_verification_key(
+    did_document: &DidDocument,
+) -> Option<(String, [u8; PUBLIC_VERIFICATION_KEY_SIZE])> {
+    find_key(
+        did_document,
+        &did_document.authentication,
+        Jwk::verification_key,
+    )
+}
+
+fn find_encryption_key(did_document: &DidDocument) -> Option<(String, [u8; PUBLIC_KEY_SIZE])> {
+    find_key(
+        did_document,
+        &did_document.key_agreement,
+        Jwk::encryption_key,
+    )
+}
+
+/// The metadata of a DID document that is not needed to verify the VID; the
+/// resolution timestamp is left for the caller to fill in
+pub fn document_metadata(did_document: &DidDocument) -> VidMetadata {
+    VidMetadata {
+        services: did_document
+            .service
+            .iter()
+            .map(|service| ServiceEntry {
+                id: service.id.clone(),
+                service_type: service.service_type.clone(),
+                endpoint: service.service_endpoint.clone(),
+            })
+            .collect(),
+        also_known_as: did_document.also_known_as.clone(),
+        resolved_at: None,
+        verification_key_id: find_verification_key(did_document).map(|(id, _)| id),
+        encryption_key_id: find_encryption_key(did_document).map(|(id, _)| id),
+        referred_by: None,
+    }
 }
 
 pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vid, VidError> {
@@ -133,29 +267,21 @@ pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vi
         return Err(VidError::ResolveVid("Invalid id specified in DID document"));
     }
 
-    let Some(public_sigkey) = find_first_key::<PUBLIC_VERIFICATION_KEY_SIZE>(
-        &did_document,
-        &did_document.authentication,
-        "Ed25519",
-        "sig",
-    ) else {
+    let Some((_, public_sigkey)) = find_verification_key(&did_document) else {
         return Err(VidError::ResolveVid(
             "No valid sign key found in DID document",
         ));
     };
 
-    let Some(public_enckey) = find_first_key::<PUBLIC_KEY_SIZE>(
-        &did_document,
-        &did_document.key_agreement,
-        "X25519",
-        "enc",
-    ) else {
+    let Some((_, public_enckey)) = find_encryption_key(&did_document) else {
         return Err(VidError::ResolveVid(
             "No valid encryption key found in DID document",
         ));
     };
 
-    let transport = match did_document.service.into_iter().next().and_then(|service| {
+    let mut services = did_document.service.into_iter();
+
+    let transport = match services.next().and_then(|service| {
         if service.service_type == "TSPTransport" {
             Some(service)
         } else {
@@ -170,9 +296,16 @@ pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vi
         }
     };
 
+    // any further transport services are alternative endpoints
+    let alternative_transports = services
+        .filter(|service| service.service_type == "TSPTransport")
+        .map(|service| service.service_endpoint)
+        .collect();
+
     Ok(Vid {
         id: did_document.id,
         transport,
+        alternative_transports,
         public_sigkey: public_sigkey.into(),
         public_enckey: public_enckey.into(),
     })
@@ -180,6 +313,19 @@ pub fn resolve_document(did_document: DidDocument, target_id: &str) -> Result<Vi
 
 pub fn vid_to_did_document(vid: &Vid) -> serde_json::Value {
     let id = vid.identifier();
+    let (verification_jwk, encryption_jwk) = vid.to_jwks();
+
+    let alternative_services =
+        vid.alternative_transports
+            .iter()
+            .enumerate()
+            .map(|(index, transport)| {
+                json!({
+                    "id": format!("#tsp-transport-{}", index + 1),
+                    "type": "TSPTransport",
+                    "serviceEndpoint": transport.to_string()
+                })
+            });
 
     json!({
         "@context": [
@@ -192,23 +338,13 @@ pub fn vid_to_did_document(vid: &Vid) -> serde_json::Value {
                 "id": format!("{id}#verification-key"),
                 "type": "JsonWebKey2020",
                 "controller":  format!("{id}"),
-                "publicKeyJwk": {
-                    "kty": "OKP",
-                    "crv": "Ed25519",
-                    "use": "sig",
-                    "x": Base64UrlUnpadded::encode_string(vid.verifying_key().as_ref()),
-                }
+                "publicKeyJwk": verification_jwk,
             },
             {
                 "id": format!("{id}#encryption-key"),
                 "type": "JsonWebKey2020",
                 "controller": format!("{id}"),
-                "publicKeyJwk": {
-                    "kty": "OKP",
-                    "crv": "X25519",
-                    "use": "enc",
-                    "x": Base64UrlUnpadded::encode_string(vid.encryption_key().as_ref()),
-                }
+                "publicKeyJwk": encryption_jwk,
             },
         ],
         "authentication": [
@@ -217,11 +353,13 @@ pub fn vid_to_did_document(vid: &Vid) -> serde_json::Value {
         "keyAgreement": [
             format!("{id}#encryption-key"),
         ],
-        "service": [{
+        "service": std::iter::once(json!({
             "id": "#tsp-transport",
             "type": "TSPTransport",
             "serviceEndpoint": vid.transport.to_string()
-        }]
+        }))
+        .chain(alternative_services)
+        .collect::<Vec<_>>()
     })
 }
 
@@ -238,6 +376,60 @@ pub fn create_did_web(
     (did_doc, private_doc, private_vid)
 }
 
+/// Where to publish a DID document with [publish_did_document]
+#[derive(Clone, Debug)]
+pub enum PublishEndpoint {
+    /// The `/add-vid` upload API of the TSP test server, e.g. `https://did.tsp-test.org/add-vid`
+    TestServer(Url),
+    /// Any server that accepts an HTTP PUT of the `did.json` document, e.g. a WebDAV share
+    Put(Url),
+}
+
+/// Publish the DID document of `vid`, optionally authenticating with a bearer token
+pub async fn publish_did_document(
+    endpoint: &PublishEndpoint,
+    vid: &Vid,
+    auth: Option<&str>,
+) -> Result<(), VidError> {
+    let client = reqwest::Client::new();
+
+    let (url, request) = match endpoint {
+        PublishEndpoint::TestServer(url) => (url, client.post(url.as_ref()).json(vid)),
+        PublishEndpoint::Put(url) => (
+            url,
+            client.put(url.as_ref()).json(&vid_to_did_document(vid)),
+        ),
+    };
+
+    let request = match auth {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| VidError::Http(url.to_string(), e))?;
+
+    Ok(())
+}
+
+/// Create a did:web identity like [create_did_web] and publish its DID document
+pub async fn create_and_publish_did_web(
+    name: &str,
+    domain: &str,
+    transport: &str,
+    endpoint: &PublishEndpoint,
+    auth: Option<&str>,
+) -> Result<OwnedVid, VidError> {
+    let (_, _, private_vid) = create_did_web(name, domain, transport);
+
+    publish_did_document(endpoint, private_vid.vid(), auth).await?;
+
+    Ok(private_vid)
+}
+
 #[cfg(test)]
 mod tests {
     use super::resolve_url;
@@ -307,4 +499,145 @@ mod tests {
             "did:web:did.tsp-test.org:user:bob"
         );
     }
+
+    #[cfg(not(feature = "pq"))]
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_alternative_transports() {
+        use crate::{
+            vid::did::web::{resolve_document, vid_to_did_document, DidDocument},
+            OwnedVid, VerifiedVid,
+        };
+
+        let id = "did:web:example.com:user:alice";
+        let alternatives = ["quic://example.com:4433", "tcp://example.com:1337"]
+            .map(|transport| Url::parse(transport).unwrap());
+
+        let alice = OwnedVid::bind(id, Url::parse("https://example.com/alice").unwrap())
+            .with_alternative_endpoints(alternatives.clone());
+
+        let did_doc: DidDocument =
+            serde_json::from_value(vid_to_did_document(alice.vid())).unwrap();
+        assert_eq!(did_doc.service.len(), 3);
+
+        let resolved = resolve_document(did_doc, id).unwrap();
+        assert_eq!(resolved.endpoint(), alice.endpoint());
+        assert_eq!(resolved.alternative_endpoints(), alternatives);
+    }
+
+    #[cfg(not(feature = "pq"))]
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_multiple_verification_methods() {
+        use crate::{
+            vid::did::web::{document_metadata, resolve_document, DidDocument},
+            OwnedVid, VerifiedVid,
+        };
+        use base64ct::{Base64UrlUnpadded, Encoding};
+
+        let id = "did:web:example.com:user:alice";
+        let alice = OwnedVid::bind(id, Url::parse("https://example.com/alice").unwrap());
+        let sigkey = Base64UrlUnpadded::encode_string(alice.verifying_key().as_ref());
+        let enckey = Base64UrlUnpadded::encode_string(alice.encryption_key().as_ref());
+
+        // a P-256 key comes first, the keys are referenced by fragment or embedded,
+        // and one of the JWKs does not specify its use
+        let did_doc: DidDocument = serde_json::from_value(serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": id,
+            "verificationMethod": [
+                {
+                    "id": "#p256",
+                    "type": "JsonWebKey2020",
+                    "controller": id,
+                    "publicKeyJwk": { "kty": "EC", "crv": "P-256", "use": "sig", "x": "AAAA" }
+                },
+                {
+                    "id": format!("{id}#ed25519"),
+                    "type": "JsonWebKey2020",
+                    "controller": id,
+                    "publicKeyJwk": { "kty": "OKP", "crv": "Ed25519", "x": sigkey }
+                },
+            ],
+            "authentication": ["#p256", "#ed25519"],
+            "keyAgreement": [
+                {
+                    "id": "#x25519",
+                    "type": "JsonWebKey2020",
+                    "controller": id,
+                    "publicKeyJwk": { "kty": "OKP", "crv": "X25519", "use": "enc", "x": enckey }
+                }
+            ],
+            "service": [{
+                "id": "#tsp-transport",
+                "type": "TSPTransport",
+                "serviceEndpoint": "https://example.com/alice"
+            }]
+        }))
+        .unwrap();
+
+        let metadata = document_metadata(&did_doc);
+        assert_eq!(
+            metadata.verification_key_id.as_deref(),
+            Some("did:web:example.com:user:alice#ed25519")
+        );
+        assert_eq!(
+            metadata.encryption_key_id.as_deref(),
+            Some("did:web:example.com:user:alice#x25519")
+        );
+
+        let resolved = resolve_document(did_doc, id).unwrap();
+        assert_eq!(resolved.verifying_key(), alice.verifying_key());
+        assert_eq!(resolved.encryption_key(), alice.encryption_key());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_publish_did_document() {
+        use super::{create_and_publish_did_web, PublishEndpoint};
+        use crate::VerifiedVid;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+
+            // read until the complete JSON body has arrived
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+
+            stream
+                .write_all(b"HTTP/1.1 201 Created\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        let endpoint = PublishEndpoint::Put(
+            Url::parse(&format!("http://{address}/user/alice/did.json")).unwrap(),
+        );
+        let alice = create_and_publish_did_web(
+            "alice",
+            "example.com",
+            "tcp://127.0.0.1:1337",
+            &endpoint,
+            Some("secret"),
+        )
+        .await
+        .unwrap();
+
+        let request = server.await.unwrap();
+        let lowercase = request.to_lowercase();
+
+        assert!(request.starts_with("PUT /user/alice/did.json HTTP/1.1"));
+        assert!(lowercase.contains("authorization: bearer secret"));
+        assert!(request.contains(&format!(r#""id":"{}""#, alice.identifier())));
+    }
 }
@@ -12,4 +12,12 @@ pub enum VidError {
     InvalidVid(String),
     #[error("could not resolve VID '{0}'")]
     ResolveVid(&'static str),
+    #[error("no codec is registered for custom VIDs of type '{0}'")]
+    UnknownCodec(String),
+    #[error("invalid JWK: {0}")]
+    InvalidJwk(&'static str),
+    #[error("invalid DID document for '{0}': {1}")]
+    Document(String, serde_json::Error),
+    #[error("accessing the resolver cache '{0}' failed: {1}")]
+    Cache(String, std::io::Error),
 }
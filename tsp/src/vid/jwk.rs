@@ -0,0 +1,244 @@
+//! Keys of a VID as JSON Web Keys (RFC 8037), for exchanging them with other systems
+//!
+//! Verification keys are `OKP` keys on the `Ed25519` curve, encryption keys are `OKP` keys
+//! on the `X25519` curve, or on the `X25519Kyber768Draft00` curve with the `pq` feature.
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::{error::VidError, OwnedVid, Vid};
+use crate::definitions::{
+    PrivateVid, VerifiedVid, PRIVATE_KEY_SIZE, PRIVATE_SIGNING_KEY_SIZE, PUBLIC_KEY_SIZE,
+    PUBLIC_VERIFICATION_KEY_SIZE,
+};
+
+const KEY_TYPE: &str = "OKP";
+
+/// The curve of verification keys
+pub const SIGNING_CURVE: &str = "Ed25519";
+
+/// The curve of encryption keys
+#[cfg(not(feature = "pq"))]
+pub const ENCRYPTION_CURVE: &str = "X25519";
+
+/// The curve of encryption keys
+#[cfg(feature = "pq")]
+pub const ENCRYPTION_CURVE: &str = "X25519Kyber768Draft00";
+
+/// A JSON Web Key; the private key `d` is only present for keys of an [OwnedVid]
+/// and is wiped from memory on drop
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<String>,
+    pub x: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+}
+
+impl std::fmt::Debug for Jwk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jwk")
+            .field("kty", &self.kty)
+            .field("crv", &self.crv)
+            .field("use", &self.usage)
+            .field("x", &self.x)
+            .field("d", &self.d.as_ref().map(|_| crate::secret::REDACTED))
+            .finish()
+    }
+}
+
+impl Drop for Jwk {
+    fn drop(&mut self) {
+        self.d.zeroize();
+    }
+}
+
+impl Jwk {
+    fn new(curve: &str, usage: &str, public: &[u8], private: Option<&[u8]>) -> Self {
+        Self {
+            kty: KEY_TYPE.to_string(),
+            crv: curve.to_string(),
+            usage: Some(usage.to_string()),
+            x: Base64UrlUnpadded::encode_string(public),
+            d: private.map(Base64UrlUnpadded::encode_string),
+        }
+    }
+
+    /// Check the key type, curve and (if present) use of this key
+    fn check(&self, curves: &[&str], usage: &str) -> Result<(), VidError> {
+        if self.kty != KEY_TYPE {
+            return Err(VidError::InvalidJwk("unsupported key type"));
+        }
+
+        if !curves.contains(&self.crv.as_str()) {
+            return Err(VidError::InvalidJwk("unsupported curve"));
+        }
+
+        if self.usage.as_deref().is_some_and(|value| value != usage) {
+            return Err(VidError::InvalidJwk("key is meant for another use"));
+        }
+
+        Ok(())
+    }
+
+    fn public_key<const N: usize>(&self) -> Result<[u8; N], VidError> {
+        Base64UrlUnpadded::decode_vec(&self.x)
+            .map_err(|_| VidError::InvalidJwk("invalid base64url encoding"))?
+            .try_into()
+            .map_err(|_| VidError::InvalidJwk("public key has incorrect length"))
+    }
+
+    fn private_key<const N: usize>(&self) -> Result<Zeroizing<[u8; N]>, VidError> {
+        let d = self
+            .d
+            .as_deref()
+            .ok_or(VidError::InvalidJwk("missing private key"))?;
+        let key = Zeroizing::new(
+            Base64UrlUnpadded::decode_vec(d)
+                .map_err(|_| VidError::InvalidJwk("invalid base64url encoding"))?,
+        );
+
+        key.as_slice()
+            .try_into()
+            .map(Zeroizing::new)
+            .map_err(|_| VidError::InvalidJwk("private key has incorrect length"))
+    }
+
+    /// The public key of an Ed25519 verification key
+    pub fn verification_key(&self) -> Result<[u8; PUBLIC_VERIFICATION_KEY_SIZE], VidError> {
+        self.check(&[SIGNING_CURVE], "sig")?;
+
+        self.public_key()
+    }
+
+    /// The public key of an encryption key; keys of post-quantum VIDs that are published
+    /// with the `X25519` curve are accepted as well
+    pub fn encryption_key(&self) -> Result<[u8; PUBLIC_KEY_SIZE], VidError> {
+        self.check(&["X25519", ENCRYPTION_CURVE], "enc")?;
+
+        self.public_key()
+    }
+}
+
+impl Vid {
+    /// The public verification and encryption keys of this VID as JWKs
+    pub fn to_jwks(&self) -> (Jwk, Jwk) {
+        (
+            Jwk::new(SIGNING_CURVE, "sig", self.verifying_key().as_ref(), None),
+            Jwk::new(
+                ENCRYPTION_CURVE,
+                "enc",
+                self.encryption_key().as_ref(),
+                None,
+            ),
+        )
+    }
+}
+
+impl OwnedVid {
+    /// Construct a private VID with the identifier `did`, reachable at `endpoint`, from
+    /// its signing and encryption keys; both JWKs must contain the private key `d`
+    pub fn from_jwks(
+        signing_jwk: &Jwk,
+        encryption_jwk: &Jwk,
+        did: impl Into<String>,
+        endpoint: Url,
+    ) -> Result<Self, VidError> {
+        let public_sigkey = signing_jwk.verification_key()?;
+        let public_enckey = encryption_jwk.encryption_key()?;
+        let sigkey = signing_jwk.private_key::<PRIVATE_SIGNING_KEY_SIZE>()?;
+        let enckey = encryption_jwk.private_key::<PRIVATE_KEY_SIZE>()?;
+
+        let derived = ed25519_dalek::SigningKey::from_bytes(&sigkey).verifying_key();
+        if derived.as_bytes() != &public_sigkey {
+            return Err(VidError::InvalidJwk(
+                "private signing key does not match the public key",
+            ));
+        }
+
+        #[cfg(not(feature = "pq"))]
+        if crypto_box::SecretKey::from(*enckey).public_key().as_bytes() != &public_enckey {
+            return Err(VidError::InvalidJwk(
+                "private encryption key does not match the public key",
+            ));
+        }
+
+        Ok(Self {
+            vid: Vid::new(did, endpoint, public_sigkey.into(), public_enckey.into()),
+            sigkey: (*sigkey).into(),
+            enckey: (*enckey).into(),
+            expires_at: None,
+        })
+    }
+
+    /// The signing and encryption keys of this VID as JWKs, including the private keys
+    pub fn to_jwks(&self) -> (Jwk, Jwk) {
+        (
+            Jwk::new(
+                SIGNING_CURVE,
+                "sig",
+                self.verifying_key().as_ref(),
+                Some(self.signing_key().expose_secret().as_slice()),
+            ),
+            Jwk::new(
+                ENCRYPTION_CURVE,
+                "enc",
+                self.encryption_key().as_ref(),
+                Some(self.decryption_key().expose_secret().as_slice()),
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_jwk_roundtrip() {
+        let endpoint: Url = "tcp://127.0.0.1:1337".parse().unwrap();
+        let alice = OwnedVid::bind("did:example:alice", endpoint.clone());
+
+        let (signing_jwk, encryption_jwk) = alice.to_jwks();
+        let json = serde_json::to_value(&signing_jwk).unwrap();
+        assert_eq!(json["kty"], "OKP");
+        assert_eq!(json["crv"], "Ed25519");
+        assert!(format!("{signing_jwk:?}").contains("[redacted]"));
+
+        let signing_jwk: Jwk = serde_json::from_value(json).unwrap();
+        let restored =
+            OwnedVid::from_jwks(&signing_jwk, &encryption_jwk, "did:example:alice", endpoint)
+                .unwrap();
+
+        assert_eq!(restored.verifying_key(), alice.verifying_key());
+        assert_eq!(restored.encryption_key(), alice.encryption_key());
+        assert_eq!(
+            restored.signing_key().expose_secret(),
+            alice.signing_key().expose_secret()
+        );
+
+        // public keys only, or a private key of another VID, are rejected
+        let (public_signing_jwk, _) = alice.vid().to_jwks();
+        let (bob_signing_jwk, _) =
+            OwnedVid::bind("did:example:bob", alice.endpoint().clone()).to_jwks();
+        let mut mismatched_jwk = signing_jwk.clone();
+        mismatched_jwk.d = bob_signing_jwk.d.clone();
+
+        for signing_jwk in [public_signing_jwk, mismatched_jwk] {
+            assert!(matches!(
+                OwnedVid::from_jwks(
+                    &signing_jwk,
+                    &encryption_jwk,
+                    "did:example:alice",
+                    alice.endpoint().clone()
+                ),
+                Err(VidError::InvalidJwk(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,73 @@
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// A service listed in the DID document of a VID
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceEntry {
+    pub id: String,
+    pub service_type: String,
+    pub endpoint: Url,
+}
+
+/// What was learned about a VID while resolving it, next to its keys and transports
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VidMetadata {
+    /// All services of the DID document, including those that are not TSP transports
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub services: Vec<ServiceEntry>,
+    /// The other identifiers of the subject, from `alsoKnownAs`
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub also_known_as: Vec<String>,
+    /// When the DID document was fetched, in seconds since the Unix epoch
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub resolved_at: Option<u64>,
+    /// The id of the verification method the signing key was taken from
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub verification_key_id: Option<String>,
+    /// The id of the verification method the encryption key was taken from
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub encryption_key_id: Option<String>,
+    /// The VID that referred to this VID, if it was added by accepting a referral
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub referred_by: Option<String>,
+}
+
+/// How a relationship with a VID is used, to spot relationships that went quiet
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VidStats {
+    /// The number of messages sealed for the VID
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub messages_sent: u64,
+    /// The number of messages opened from the VID
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub messages_received: u64,
+    /// When the last message from the VID was opened, in seconds since the Unix epoch
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub last_received: Option<u64>,
+    /// Why the last message to the VID could not be sent
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub last_error: Option<String>,
+}
+
+impl VidStats {
+    /// Whether nothing was recorded yet
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     definitions::{
-        PrivateKeyData, PrivateSigningKeyData, PrivateVid, PublicKeyData,
-        PublicVerificationKeyData, VerifiedVid,
+        CipherSuite, DigestAlgorithm, PrivateKeyData, PrivateSigningKeyData, PrivateVid,
+        PublicKeyData, PublicVerificationKeyData, VerifiedVid,
     },
     RelationshipStatus,
 };
@@ -12,23 +12,52 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "serialize")]
 pub mod deserialize;
 
+pub mod codec;
+
+pub(crate) mod compact;
+
 pub mod did;
 
 pub mod error;
 
+#[cfg(feature = "serialize")]
+pub mod jwk;
+
+pub mod metadata;
+
+pub mod policy;
+
 pub mod resolve;
 
+#[cfg(feature = "async")]
+pub mod registry;
+
+#[cfg(feature = "async")]
+pub mod resolver;
+
 #[cfg(feature = "resolve")]
-pub use did::web::{create_did_web, vid_to_did_document};
+pub use did::web::{
+    create_and_publish_did_web, create_did_web, publish_did_document, vid_to_did_document,
+    PublishEndpoint,
+};
 
 #[cfg(feature = "resolve")]
 pub use did::peer::{encode_did_peer, verify_did_peer};
 
+pub use codec::{CustomFields, VidCodec};
 pub use error::VidError;
+pub use metadata::{ServiceEntry, VidMetadata, VidStats};
+pub use policy::{AllowedDomains, Provenance, VerificationPolicy, VidOrigin};
 use url::Url;
 
 #[cfg(feature = "resolve")]
-pub use resolve::verify_vid;
+pub use resolve::{verify_vid, verify_vid_with_metadata};
+
+#[cfg(feature = "async")]
+pub use registry::{DidMethodRegistry, DidMethodResolver};
+
+#[cfg(feature = "async")]
+pub use resolver::{ResolverConfig, VidRefresh, VidResolver};
 
 /// A Vid represents a *verified* Identifier
 /// (so it doesn't carry any information that allows to verify it)
@@ -41,6 +70,11 @@ pub use resolve::verify_vid;
 pub struct Vid {
     id: String,
     transport: Url,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    alternative_transports: Vec<Url>,
     public_sigkey: PublicVerificationKeyData,
     public_enckey: PublicKeyData,
 }
@@ -55,8 +89,23 @@ pub struct Vid {
 pub struct OwnedVid {
     #[cfg_attr(feature = "serialize", serde(flatten))]
     vid: Vid,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "deserialize::expose_secret")
+    )]
     sigkey: PrivateSigningKeyData,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "deserialize::expose_secret")
+    )]
     enckey: PrivateKeyData,
+    /// When this one-shot VID expires, in seconds since the Unix epoch; see
+    /// [OwnedVid::new_ephemeral]
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    expires_at: Option<u64>,
 }
 
 /// A custom implementation of Debug for PrivateVid to avoid key material from leaking during panics.
@@ -66,6 +115,7 @@ impl std::fmt::Debug for OwnedVid {
             .field("vid", &self.vid)
             .field("sigkey", &"<secret>")
             .field("enckey", &"<secret>")
+            .field("expires_at", &self.expires_at)
             .finish()
     }
 }
@@ -79,6 +129,10 @@ impl VerifiedVid for Vid {
         &self.transport
     }
 
+    fn alternative_endpoints(&self) -> &[url::Url] {
+        &self.alternative_transports
+    }
+
     fn verifying_key(&self) -> &PublicVerificationKeyData {
         &self.public_sigkey
     }
@@ -97,6 +151,10 @@ impl VerifiedVid for OwnedVid {
         self.vid.endpoint()
     }
 
+    fn alternative_endpoints(&self) -> &[url::Url] {
+        self.vid.alternative_endpoints()
+    }
+
     fn verifying_key(&self) -> &PublicVerificationKeyData {
         self.vid.verifying_key()
     }
@@ -114,6 +172,10 @@ impl PrivateVid for OwnedVid {
     fn decryption_key(&self) -> &PrivateKeyData {
         &self.enckey
     }
+
+    fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
 }
 
 impl AsRef<[u8]> for Vid {
@@ -122,6 +184,32 @@ impl AsRef<[u8]> for Vid {
     }
 }
 
+impl Vid {
+    /// Construct a VID from already verified key material, e.g. in a
+    /// [`DidMethodResolver`](registry::DidMethodResolver) for another DID method
+    pub fn new(
+        id: impl Into<String>,
+        transport: Url,
+        public_sigkey: PublicVerificationKeyData,
+        public_enckey: PublicKeyData,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            transport,
+            alternative_transports: Vec::new(),
+            public_sigkey,
+            public_enckey,
+        }
+    }
+
+    /// Make this VID reachable at `endpoints` as well
+    pub fn with_alternative_endpoints(mut self, endpoints: impl IntoIterator<Item = Url>) -> Self {
+        self.alternative_transports.extend(endpoints);
+
+        self
+    }
+}
+
 impl OwnedVid {
     pub fn bind(id: impl Into<String>, transport: url::Url) -> Self {
         let (sigkey, public_sigkey) = crate::crypto::gen_sign_keypair();
@@ -131,11 +219,13 @@ impl OwnedVid {
             vid: Vid {
                 id: id.into(),
                 transport,
+                alternative_transports: Vec::new(),
                 public_sigkey,
                 public_enckey,
             },
             sigkey,
             enckey,
+            expires_at: None,
         }
     }
 
@@ -146,6 +236,7 @@ impl OwnedVid {
         let mut vid = Vid {
             id: Default::default(),
             transport,
+            alternative_transports: Vec::new(),
             public_sigkey,
             public_enckey,
         };
@@ -156,9 +247,34 @@ impl OwnedVid {
             vid,
             sigkey,
             enckey,
+            expires_at: None,
         }
     }
 
+    /// Create a one-shot `did:peer` VID reachable at `transport`, e.g. to send a single
+    /// inquiry from and receive the reply on without being correlated to our other VIDs.
+    /// A store forgets it, with its keys, once it opened a message sent to it or after
+    /// `ttl`, and never exports it; see [Store::forget_expired_vids](crate::Store::forget_expired_vids)
+    pub fn new_ephemeral(transport: Url, ttl: std::time::Duration) -> OwnedVid {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            expires_at: Some(now.saturating_add(ttl.as_secs())),
+            ..Self::new_did_peer(transport)
+        }
+    }
+
+    /// Make this VID reachable at `endpoints` as well, e.g. over QUIC next to HTTPS;
+    /// these are published as additional services in its DID document
+    pub fn with_alternative_endpoints(mut self, endpoints: impl IntoIterator<Item = Url>) -> Self {
+        self.vid.alternative_transports.extend(endpoints);
+
+        self
+    }
+
     pub fn vid(&self) -> &Vid {
         &self.vid
     }
@@ -173,14 +289,67 @@ impl OwnedVid {
 pub struct ExportVid {
     pub(crate) id: String,
     pub(crate) transport: Url,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub(crate) alternative_transports: Vec<Url>,
     pub(crate) public_sigkey: PublicVerificationKeyData,
     pub(crate) public_enckey: PublicKeyData,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "deserialize::expose_optional_secret")
+    )]
     pub(crate) sigkey: Option<PrivateSigningKeyData>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "deserialize::expose_optional_secret")
+    )]
     pub(crate) enckey: Option<PrivateKeyData>,
     pub(crate) relation_status: RelationshipStatus,
     pub(crate) relation_vid: Option<String>,
     pub(crate) parent_vid: Option<String>,
     pub(crate) tunnel: Option<Box<[String]>>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) metadata: Option<serde_json::Value>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) vid_metadata: Option<VidMetadata>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "VidStats::is_empty")
+    )]
+    pub(crate) stats: VidStats,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) digest_algorithm: Option<DigestAlgorithm>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) cipher_suite: Option<CipherSuite>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) provenance: Option<Provenance>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) transport_override: Option<Url>,
+    #[cfg_attr(
+        feature = "serialize",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) custom: Option<CustomFields>,
 }
 
 impl ExportVid {
@@ -188,17 +357,20 @@ impl ExportVid {
         Vid {
             id: self.id.clone(),
             transport: self.transport.clone(),
+            alternative_transports: self.alternative_transports.clone(),
             public_sigkey: self.public_sigkey.clone(),
             public_enckey: self.public_enckey.clone(),
         }
     }
 
-    pub(crate) fn private_vid(&self) -> Option<OwnedVid> {
-        match (&self.sigkey, &self.enckey) {
+    /// Move the private keys out of this export, instead of leaving a copy behind
+    pub(crate) fn take_private_vid(&mut self) -> Option<OwnedVid> {
+        match (self.sigkey.take(), self.enckey.take()) {
             (Some(sigkey), Some(enckey)) => Some(OwnedVid {
                 vid: self.verified_vid(),
-                sigkey: sigkey.clone(),
-                enckey: enckey.clone(),
+                sigkey,
+                enckey,
+                expires_at: None,
             }),
             _ => None,
         }
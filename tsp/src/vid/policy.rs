@@ -0,0 +1,145 @@
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// How a VID came to be considered for adding to a store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VidOrigin<'a> {
+    /// Explicitly resolved, e.g. by `AsyncStore::verify_vid`
+    Resolved,
+    /// Received as the nested VID of `parent` in a nested relationship request or accept
+    Nested { parent: &'a str },
+    /// Announced by `sender` as its new identifier
+    NewIdentifier { sender: &'a str },
+    /// Referred to by `sender` in a third party referral
+    Referral { sender: &'a str },
+    /// Announced by `sender` as a new member of `group`
+    GroupMember { group: &'a str, sender: &'a str },
+    /// The sender of a received message, resolved by the first contact policy of an
+    /// `AsyncStore`
+    FirstContact,
+}
+
+/// How a verified VID entered a store, recorded when it is added; see
+/// [Store::provenance](crate::Store::provenance)
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase", tag = "kind")
+)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Provenance {
+    /// Added with its keys by the application, e.g. with `Store::add_verified_vid`
+    Manual,
+    /// Imported from a trust registry by `AsyncStore::import_contacts`
+    Imported,
+    /// Resolved from its identifier, e.g. by `AsyncStore::verify_vid`
+    Resolved,
+    /// The nested VID of `parent` in a nested relationship request or accept
+    Nested { parent: String },
+    /// Announced by `sender` as its new identifier
+    NewIdentifier { sender: String },
+    /// Referred to by `sender` in a third party referral
+    Referral { sender: String },
+    /// Announced by `sender` as a new member of `group`
+    GroupMember { group: String, sender: String },
+    /// The sender of a received message, resolved by the first contact policy
+    FirstContact,
+}
+
+impl Provenance {
+    /// The VID that introduced this one, if it was learned from another VID
+    pub fn introduced_by(&self) -> Option<&str> {
+        match self {
+            Self::Nested { parent } => Some(parent),
+            Self::NewIdentifier { sender }
+            | Self::Referral { sender }
+            | Self::GroupMember { sender, .. } => Some(sender),
+            Self::Manual | Self::Imported | Self::Resolved | Self::FirstContact => None,
+        }
+    }
+}
+
+impl From<VidOrigin<'_>> for Provenance {
+    fn from(origin: VidOrigin<'_>) -> Self {
+        match origin {
+            VidOrigin::Resolved => Self::Resolved,
+            VidOrigin::Nested { parent } => Self::Nested {
+                parent: parent.to_string(),
+            },
+            VidOrigin::NewIdentifier { sender } => Self::NewIdentifier {
+                sender: sender.to_string(),
+            },
+            VidOrigin::Referral { sender } => Self::Referral {
+                sender: sender.to_string(),
+            },
+            VidOrigin::GroupMember { group, sender } => Self::GroupMember {
+                group: group.to_string(),
+                sender: sender.to_string(),
+            },
+            VidOrigin::FirstContact => Self::FirstContact,
+        }
+    }
+}
+
+/// Decides whether a VID may be trusted; consulted before a VID is added to a store,
+/// and before a new identifier or referral is reported to the application
+pub trait VerificationPolicy: Send + Sync {
+    /// Accept the VID identified by `vid`, or reject it with a reason
+    fn check(&self, vid: &str, origin: VidOrigin) -> Result<(), String>;
+}
+
+impl<F> VerificationPolicy for F
+where
+    F: Fn(&str, VidOrigin) -> Result<(), String> + Send + Sync,
+{
+    fn check(&self, vid: &str, origin: VidOrigin) -> Result<(), String> {
+        self(vid, origin)
+    }
+}
+
+/// Only accept did:web VIDs hosted on one of the listed domains
+#[derive(Clone, Debug, Default)]
+pub struct AllowedDomains(pub Vec<String>);
+
+impl VerificationPolicy for AllowedDomains {
+    fn check(&self, vid: &str, _origin: VidOrigin) -> Result<(), String> {
+        let Some(domain) = vid.strip_prefix("did:web:") else {
+            return Err("only did:web VIDs are allowed".to_string());
+        };
+
+        let domain = domain.split(':').next().unwrap_or_default();
+        // ports are percent-encoded in did:web identifiers
+        let domain = domain.replace("%3A", ":");
+
+        if self.0.contains(&domain) {
+            Ok(())
+        } else {
+            Err(format!("domain {domain} is not allowed"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_allowed_domains() {
+        let policy = AllowedDomains(vec!["did.tsp-test.org".into(), "localhost:3000".into()]);
+
+        assert!(policy
+            .check("did:web:did.tsp-test.org:user:alice", VidOrigin::Resolved)
+            .is_ok());
+        assert!(policy
+            .check("did:web:localhost%3A3000:user:bob", VidOrigin::Resolved)
+            .is_ok());
+        assert!(policy
+            .check("did:web:example.com:user:eve", VidOrigin::Resolved)
+            .is_err());
+        assert!(policy
+            .check("did:peer:2.Vz123", VidOrigin::Referral { sender: "bob" })
+            .is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use super::{did, error::VidError, Vid};
+use crate::definitions::VerifiedVid;
+use futures::future::BoxFuture;
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+/// Resolves and verifies the VIDs of a DID method this crate does not support itself,
+/// e.g. did:indy, did:cheqd or did:jwk; see [`DidMethodRegistry`]
+pub trait DidMethodResolver: Send + Sync {
+    /// Resolve and verify the VID identified by `id`
+    fn resolve<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Vid, VidError>>;
+}
+
+impl<F, Fut> DidMethodResolver for F
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Vid, VidError>> + Send + 'static,
+{
+    fn resolve<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Vid, VidError>> {
+        Box::pin(self(id.to_string()))
+    }
+}
+
+/// The application provided resolvers for DID methods, keyed by method name
+/// (e.g. "jwk" for did:jwk); these take precedence over the built-in methods
+#[derive(Clone, Default)]
+pub struct DidMethodRegistry {
+    methods: HashMap<String, Arc<dyn DidMethodResolver>>,
+}
+
+impl DidMethodRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve VIDs of the DID method `method` with `resolver`, replacing any
+    /// resolver registered earlier for `method`
+    pub fn register(
+        &mut self,
+        method: impl Into<String>,
+        resolver: impl DidMethodResolver + 'static,
+    ) {
+        self.methods.insert(method.into(), Arc::new(resolver));
+    }
+
+    /// Stop resolving VIDs of the DID method `method` with a registered resolver
+    pub fn unregister(&mut self, method: &str) {
+        self.methods.remove(method);
+    }
+
+    /// The names of the registered DID methods
+    pub fn methods(&self) -> impl Iterator<Item = &str> {
+        self.methods.keys().map(String::as_str)
+    }
+
+    /// The resolver registered for the DID method of `id`, if any
+    pub fn get(&self, id: &str) -> Option<&dyn DidMethodResolver> {
+        let mut parts = id.split(':');
+
+        match (parts.next(), parts.next()) {
+            (Some(did::SCHEME), Some(method)) => {
+                self.methods.get(method).map(|resolver| resolver.as_ref())
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolve `id` with the resolver registered for its DID method;
+    /// returns `None` if no resolver is registered for the method
+    pub async fn resolve(&self, id: &str) -> Option<Result<Vid, VidError>> {
+        let resolver = self.get(id)?;
+
+        let result = resolver.resolve(id).await.and_then(|vid| {
+            if vid.identifier() == id {
+                Ok(vid)
+            } else {
+                Err(VidError::ResolveVid(
+                    "the resolved VID has a different identifier",
+                ))
+            }
+        });
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OwnedVid;
+
+    #[tokio::test]
+    async fn test_registry() {
+        let jwk = OwnedVid::bind("did:jwk:alice", "tcp://127.0.0.1:1337".parse().unwrap());
+        let vid = jwk.vid().clone();
+
+        let mut registry = DidMethodRegistry::new();
+        // a misbehaving resolver, that returns alice for any identifier
+        registry.register("jwk", move |_id: String| {
+            let vid = vid.clone();
+            async move { Ok::<_, VidError>(vid) }
+        });
+
+        assert_eq!(registry.methods().collect::<Vec<_>>(), ["jwk"]);
+        assert!(registry.get("did:web:did.tsp-test.org").is_none());
+        assert!(registry.resolve("did:peer:2.Vz").await.is_none());
+
+        let resolved = registry.resolve("did:jwk:alice").await.unwrap().unwrap();
+        assert_eq!(resolved.verifying_key(), jwk.verifying_key());
+
+        assert!(registry.resolve("did:jwk:bob").await.unwrap().is_err());
+
+        registry.unregister("jwk");
+        assert!(registry.resolve("did:jwk:alice").await.is_none());
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "resolve")]
+use super::metadata::VidMetadata;
 use super::{
     did::{self, peer},
     error::VidError,
@@ -7,11 +9,20 @@ use crate::Vid;
 #[cfg(feature = "resolve")]
 /// Resolve and verify the vid identified by `id`, by using online and offline methods
 pub async fn verify_vid(id: &str) -> Result<Vid, VidError> {
+    verify_vid_with_metadata(id).await.map(|(vid, _)| vid)
+}
+
+#[cfg(feature = "resolve")]
+/// Resolve and verify the vid identified by `id` like [verify_vid], and also return
+/// the metadata of its DID document
+pub async fn verify_vid_with_metadata(id: &str) -> Result<(Vid, VidMetadata), VidError> {
     let parts = id.split(':').collect::<Vec<&str>>();
 
     match parts.get(0..2) {
         Some([did::SCHEME, did::web::SCHEME]) => did::web::resolve(id, parts).await,
-        Some([did::SCHEME, did::peer::SCHEME]) => peer::verify_did_peer(&parts),
+        Some([did::SCHEME, did::peer::SCHEME]) => {
+            Ok((peer::verify_did_peer(&parts)?, VidMetadata::default()))
+        }
         _ => Err(VidError::InvalidVid(id.to_string())),
     }
 }
@@ -0,0 +1,361 @@
+use super::{
+    did::{
+        self,
+        web::{document_metadata, fetch_document, resolve_document, DidDocument, FetchedDocument},
+    },
+    error::VidError,
+    metadata::VidMetadata,
+    resolve::verify_vid_offline,
+};
+use crate::{definitions::VerifiedVid, Vid};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Configures how a [VidResolver] caches DID documents
+#[derive(Clone, Debug)]
+pub struct ResolverConfig {
+    /// How long a cached DID document is used before it is revalidated
+    pub ttl: Duration,
+    /// Only ever use cached (pinned) DID documents, never access the network
+    pub offline: bool,
+    /// A file in which the cached DID documents are persisted
+    pub cache_file: Option<PathBuf>,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60),
+            offline: false,
+            cache_file: None,
+        }
+    }
+}
+
+/// The outcome of [VidResolver::refresh_vid]
+#[derive(Debug)]
+pub enum VidRefresh {
+    /// The keys of the VID did not change, or the VID was not known before
+    Unchanged(Vid),
+    /// The DID document now lists different keys; this may be a legitimate
+    /// key rotation, but should be confirmed before the new keys are trusted
+    KeysChanged(Vid),
+}
+
+impl VidRefresh {
+    /// Compare a freshly resolved VID with the previously known version
+    pub(crate) fn compare(previous: Option<&dyn VerifiedVid>, current: Vid) -> VidRefresh {
+        match previous {
+            Some(previous)
+                if previous.verifying_key().as_ref() != current.verifying_key().as_ref()
+                    || previous.encryption_key().as_ref() != current.encryption_key().as_ref() =>
+            {
+                VidRefresh::KeysChanged(current)
+            }
+            _ => VidRefresh::Unchanged(current),
+        }
+    }
+
+    /// The VID as resolved now
+    pub fn vid(&self) -> &Vid {
+        match self {
+            VidRefresh::Unchanged(vid) | VidRefresh::KeysChanged(vid) => vid,
+        }
+    }
+}
+
+const POISONED: VidError = VidError::ResolveVid("the resolver cache is poisoned");
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedDocument {
+    document: String,
+    etag: Option<String>,
+    fetched_at: u64,
+}
+
+/// Resolves VIDs like [verify_vid](super::verify_vid), but keeps the resolved
+/// DID documents in an in-memory cache that can be persisted to disk
+#[derive(Debug, Default)]
+pub struct VidResolver {
+    config: ResolverConfig,
+    cache: RwLock<HashMap<String, CachedDocument>>,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+impl VidResolver {
+    /// Create a resolver, loading the persisted cache if a cache file is configured
+    pub fn new(config: ResolverConfig) -> Result<Self, VidError> {
+        let cache = match &config.cache_file {
+            Some(path) if path.exists() => {
+                let cache_error = |e| VidError::Cache(path.display().to_string(), e);
+                let contents = std::fs::read_to_string(path).map_err(cache_error)?;
+
+                serde_json::from_str(&contents).map_err(|e| cache_error(e.into()))?
+            }
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            config,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// The configuration of this resolver
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// Resolve and verify the VID identified by `id`, using the cache if possible
+    pub async fn resolve(&self, id: &str) -> Result<Vid, VidError> {
+        self.resolve_with_metadata(id).await.map(|(vid, _)| vid)
+    }
+
+    /// Resolve and verify the VID identified by `id` like [VidResolver::resolve], and also
+    /// return the metadata of its DID document, as of when it was fetched
+    pub async fn resolve_with_metadata(&self, id: &str) -> Result<(Vid, VidMetadata), VidError> {
+        if !is_did_web(id) {
+            return Ok((verify_vid_offline(id)?, VidMetadata::default()));
+        }
+
+        if let Some(cached) = self.cached(id) {
+            let fresh = now().saturating_sub(cached.fetched_at) < self.config.ttl.as_secs();
+
+            if fresh || self.config.offline {
+                return parse_document(id, &cached.document, cached.fetched_at);
+            }
+        }
+
+        self.fetch(id).await
+    }
+
+    /// Fetch the DID document of `id` regardless of the cache, and report
+    /// whether its keys differ from the cached version
+    pub async fn refresh_vid(&self, id: &str) -> Result<VidRefresh, VidError> {
+        if !is_did_web(id) {
+            return verify_vid_offline(id).map(VidRefresh::Unchanged);
+        }
+
+        let previous = self
+            .cached(id)
+            .map(|cached| parse_document(id, &cached.document, cached.fetched_at))
+            .transpose()?
+            .map(|(vid, _)| vid);
+
+        let (current, _) = self.fetch(id).await?;
+
+        Ok(VidRefresh::compare(
+            previous.as_ref().map(|vid| vid as &dyn VerifiedVid),
+            current,
+        ))
+    }
+
+    /// Pin a DID document, so it can be used in offline mode
+    pub fn pin_document(&self, id: &str, document: String) -> Result<Vid, VidError> {
+        let fetched_at = now();
+        let (vid, _) = parse_document(id, &document, fetched_at)?;
+
+        self.store(
+            id,
+            CachedDocument {
+                document,
+                etag: None,
+                fetched_at,
+            },
+        )?;
+
+        Ok(vid)
+    }
+
+    /// Remove a DID document from the cache
+    pub fn forget(&self, id: &str) -> Result<(), VidError> {
+        let mut cache = self.cache.write().map_err(|_| POISONED)?;
+        cache.remove(id);
+
+        self.persist(&cache)
+    }
+
+    fn cached(&self, id: &str) -> Option<CachedDocument> {
+        self.cache.read().ok()?.get(id).cloned()
+    }
+
+    async fn fetch(&self, id: &str) -> Result<(Vid, VidMetadata), VidError> {
+        if self.config.offline {
+            return Err(VidError::ResolveVid(
+                "VID is not pinned in the offline cache",
+            ));
+        }
+
+        let cached = self.cached(id);
+        let etag = cached.as_ref().and_then(|cached| cached.etag.as_deref());
+
+        let (document, etag) = match fetch_document(id, etag).await? {
+            FetchedDocument::Document { document, etag } => (document, etag),
+            FetchedDocument::NotModified => match cached {
+                Some(cached) => (cached.document, cached.etag),
+                None => return Err(VidError::ResolveVid("unexpected 304 Not Modified")),
+            },
+        };
+
+        let fetched_at = now();
+        let resolved = parse_document(id, &document, fetched_at)?;
+
+        self.store(
+            id,
+            CachedDocument {
+                document,
+                etag,
+                fetched_at,
+            },
+        )?;
+
+        Ok(resolved)
+    }
+
+    fn store(&self, id: &str, cached: CachedDocument) -> Result<(), VidError> {
+        let mut cache = self.cache.write().map_err(|_| POISONED)?;
+        cache.insert(id.to_string(), cached);
+
+        self.persist(&cache)
+    }
+
+    fn persist(&self, cache: &HashMap<String, CachedDocument>) -> Result<(), VidError> {
+        let Some(path) = &self.config.cache_file else {
+            return Ok(());
+        };
+
+        let contents = serde_json::to_string(cache)
+            .map_err(|e| VidError::Cache(path.display().to_string(), e.into()))?;
+
+        std::fs::write(path, contents).map_err(|e| VidError::Cache(path.display().to_string(), e))
+    }
+}
+
+fn is_did_web(id: &str) -> bool {
+    matches!(
+        id.split(':').collect::<Vec<_>>().get(0..2),
+        Some([did::SCHEME, did::web::SCHEME])
+    )
+}
+
+fn parse_document(
+    id: &str,
+    document: &str,
+    fetched_at: u64,
+) -> Result<(Vid, VidMetadata), VidError> {
+    let did_document: DidDocument =
+        serde_json::from_str(document).map_err(|e| VidError::Document(id.to_string(), e))?;
+
+    let metadata = VidMetadata {
+        resolved_at: Some(fetched_at),
+        ..document_metadata(&did_document)
+    };
+
+    Ok((resolve_document(did_document, id)?, metadata))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{vid::vid_to_did_document, OwnedVid};
+
+    const ALICE: &str = "did:web:did.tsp-test.org:user:alice";
+
+    #[tokio::test]
+    async fn test_resolver_cache() {
+        let cache_file = std::env::temp_dir().join(format!("tsp-resolver-{}.json", now()));
+        let config = ResolverConfig {
+            cache_file: Some(cache_file.clone()),
+            ..Default::default()
+        };
+
+        let resolver = VidResolver::new(config.clone()).unwrap();
+        let alice = resolver.resolve(ALICE).await.unwrap();
+        assert_eq!(alice.identifier(), ALICE);
+
+        // a new resolver in offline mode can use the persisted document
+        let offline = VidResolver::new(ResolverConfig {
+            offline: true,
+            ..config
+        })
+        .unwrap();
+        let pinned = offline.resolve(ALICE).await.unwrap();
+        assert_eq!(pinned.verifying_key(), alice.verifying_key());
+
+        assert!(matches!(
+            offline
+                .resolve("did:web:did.tsp-test.org:user:bob")
+                .await
+                .unwrap_err(),
+            VidError::ResolveVid(_)
+        ));
+
+        std::fs::remove_file(cache_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_metadata() {
+        let resolver = VidResolver::new(ResolverConfig {
+            offline: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let alice = OwnedVid::bind(ALICE, "tcp://127.0.0.1:1337".parse().unwrap());
+        let mut document = vid_to_did_document(alice.vid());
+        document["alsoKnownAs"] = serde_json::json!(["https://alice.example.com"]);
+        document["service"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({
+                "id": "#profile",
+                "type": "LinkedDomains",
+                "serviceEndpoint": "https://alice.example.com/profile"
+            }));
+        resolver.pin_document(ALICE, document.to_string()).unwrap();
+
+        let (vid, metadata) = resolver.resolve_with_metadata(ALICE).await.unwrap();
+        assert_eq!(vid.endpoint(), alice.endpoint());
+        assert_eq!(metadata.also_known_as, ["https://alice.example.com"]);
+        assert_eq!(
+            metadata
+                .services
+                .iter()
+                .map(|service| service.service_type.as_str())
+                .collect::<Vec<_>>(),
+            ["TSPTransport", "LinkedDomains"]
+        );
+        assert!(metadata.resolved_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_vid() {
+        let resolver = VidResolver::default();
+
+        let rotated = OwnedVid::bind(ALICE, "tcp://127.0.0.1:1337".parse().unwrap());
+        let document = vid_to_did_document(rotated.vid()).to_string();
+        resolver.pin_document(ALICE, document).unwrap();
+
+        let VidRefresh::KeysChanged(alice) = resolver.refresh_vid(ALICE).await.unwrap() else {
+            panic!("expected a key change");
+        };
+        assert_ne!(alice.verifying_key(), rotated.verifying_key());
+
+        assert!(matches!(
+            resolver.refresh_vid(ALICE).await.unwrap(),
+            VidRefresh::Unchanged(_)
+        ));
+    }
+}
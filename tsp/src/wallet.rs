@@ -0,0 +1,210 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{AsyncStore, Error, Vault};
+
+/// An [AsyncStore] bound to the SQLite [Vault] its VIDs are loaded from and written to
+///
+/// [Wallet::open] creates the wallet if it does not exist, and returns it with its VIDs
+/// imported into the store; changes to nested relationships are written as soon as they
+/// are made, see [AsyncStore::set_vault]. Other changes are written by [Wallet::save], by
+/// [Wallet::close], and periodically with [Wallet::set_auto_persist].
+///
+/// # Example
+///
+/// ```no_run
+/// use tsp::{OwnedVid, Wallet};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), tsp::Error> {
+///     let wallet = Wallet::open("alice", b"password").await?;
+///
+///     let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+///     wallet.store().add_private_vid(alice)?;
+///
+///     wallet.close().await
+/// }
+/// ```
+pub struct Wallet {
+    store: AsyncStore,
+    vault: Arc<Vault>,
+    extra_data: Option<serde_json::Value>,
+    extra_data_changed: bool,
+    /// A failed write loses the changes it took from the store, so the next write has to
+    /// write every VID
+    resync: Arc<AtomicBool>,
+    auto_persist: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Wallet {
+    /// Open the SQLite wallet `name` with `password`, or create it if it does not exist
+    pub async fn open(name: &str, password: &[u8]) -> Result<Self, Error> {
+        let vault = match Vault::open_sqlite(name, password).await {
+            Ok(vault) => vault,
+            Err(_) => Vault::new_sqlite(name, password).await?,
+        };
+
+        let (vids, extra_data) = vault.load().await?;
+        let vault = Arc::new(vault);
+
+        let store = AsyncStore::new();
+        store.import(vids)?;
+        store.set_vault(vault.clone());
+
+        Ok(Self {
+            store,
+            vault,
+            extra_data,
+            extra_data_changed: false,
+            resync: Default::default(),
+            auto_persist: None,
+        })
+    }
+
+    /// The store with the VIDs of the wallet; clones of it share the VIDs
+    pub fn store(&self) -> &AsyncStore {
+        &self.store
+    }
+
+    /// The store, e.g. to configure it
+    pub fn store_mut(&mut self) -> &mut AsyncStore {
+        &mut self.store
+    }
+
+    /// The vault the wallet is written to, e.g. to [migrate](Vault::migrate) it
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
+    /// The application data kept next to the VIDs, e.g. aliases
+    pub fn extra_data(&self) -> Option<&serde_json::Value> {
+        self.extra_data.as_ref()
+    }
+
+    /// Replace the application data kept next to the VIDs; it is written with the next
+    /// [Wallet::save]
+    pub fn set_extra_data(&mut self, extra_data: serde_json::Value) {
+        self.extra_data = Some(extra_data);
+        self.extra_data_changed = true;
+    }
+
+    /// Write the VIDs that changed every `interval`, until the wallet is closed or dropped;
+    /// failures are logged and the VIDs are written again the next time. The
+    /// [extra data](Wallet::set_extra_data) is only written by [Wallet::save]
+    pub fn set_auto_persist(&mut self, interval: Duration) {
+        let (store, vault, resync) = (self.store.clone(), self.vault.clone(), self.resync.clone());
+
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+
+            loop {
+                ticks.tick().await;
+
+                if let Err(e) = Self::persist(&store, &vault, None, &resync).await {
+                    tracing::warn!("could not write wallet: {e}");
+                }
+            }
+        });
+
+        if let Some(previous) = self.auto_persist.replace(task) {
+            previous.abort();
+        }
+    }
+
+    /// Write the VIDs that changed since the wallet was opened or last written, and the
+    /// extra data if it was replaced
+    pub async fn save(&mut self) -> Result<(), Error> {
+        let extra_data = self
+            .extra_data_changed
+            .then(|| self.extra_data.clone())
+            .flatten();
+
+        Self::persist(&self.store, &self.vault, extra_data, &self.resync).await?;
+        self.extra_data_changed = false;
+
+        Ok(())
+    }
+
+    /// Write the changes and close the vault; clones of the store can still be used, but
+    /// their changes are no longer written
+    pub async fn close(mut self) -> Result<(), Error> {
+        if let Some(task) = self.auto_persist.take() {
+            task.abort();
+        }
+
+        self.save().await?;
+        self.vault.close_shared().await
+    }
+
+    async fn persist(
+        store: &AsyncStore,
+        vault: &Vault,
+        extra_data: Option<serde_json::Value>,
+        resync: &AtomicBool,
+    ) -> Result<(), Error> {
+        let changes = store.take_changes();
+
+        let result = if resync.load(Ordering::Acquire) {
+            vault.persist(store.export()?, extra_data).await
+        } else {
+            vault.persist_changes(changes, extra_data).await
+        };
+
+        resync.store(result.is_err(), Ordering::Release);
+
+        result
+    }
+}
+
+impl Drop for Wallet {
+    fn drop(&mut self) {
+        if let Some(task) = self.auto_persist.take() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(not(feature = "pq"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{OwnedVid, VerifiedVid};
+
+    #[tokio::test]
+    async fn test_wallet() {
+        let alice = OwnedVid::new_did_peer("tcp://127.0.0.1:1337".parse().unwrap());
+
+        let mut wallet = Wallet::open("test-wallet", b"password").await.unwrap();
+        assert!(wallet.store().export().unwrap().is_empty());
+        assert_eq!(wallet.extra_data(), None);
+
+        wallet.store().add_private_vid(alice.clone()).unwrap();
+        wallet.set_extra_data(serde_json::json!({ "alice": alice.identifier() }));
+        wallet.close().await.unwrap();
+
+        let wallet = Wallet::open("test-wallet", b"password").await.unwrap();
+        assert!(wallet
+            .store()
+            .as_store()
+            .has_private_vid(alice.identifier())
+            .unwrap());
+        assert_eq!(
+            wallet.extra_data(),
+            Some(&serde_json::json!({ "alice": alice.identifier() }))
+        );
+        wallet.close().await.unwrap();
+
+        Vault::open_sqlite("test-wallet", b"password")
+            .await
+            .unwrap()
+            .destroy()
+            .await
+            .unwrap();
+    }
+}
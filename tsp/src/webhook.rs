@@ -0,0 +1,393 @@
+use crate::{
+    definitions::{Digest, ReceivedTspMessage},
+    error::Error,
+    transport::TransportError,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use futures::{Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::time::Duration;
+use url::Url;
+
+/// The header with the HMAC-SHA256 signature of the request body, as `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "x-tsp-signature";
+
+/// Configures a [WebhookNotifier]
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    /// The URL every received message is POSTed to
+    pub url: Url,
+    /// The key used to sign the request body, see [SIGNATURE_HEADER]
+    pub secret: Option<Vec<u8>>,
+    /// Replace confidential payloads by their size
+    pub redact_confidential: bool,
+    /// How often a failed delivery is retried
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled on every next retry
+    pub retry_delay: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            secret: None,
+            redact_confidential: false,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Delivers received messages as JSON to a webhook, so web backends can consume
+/// TSP messages without embedding this library in their main process
+///
+/// # Example
+///
+/// ```no_run
+/// use tsp::{webhook::{WebhookConfig, WebhookNotifier}, AsyncStore};
+///
+/// # async fn example(db: AsyncStore) -> Result<(), tsp::Error> {
+/// let mut config = WebhookConfig::new("https://example.com/tsp-hook".parse().unwrap());
+/// config.secret = Some(b"shared secret".to_vec());
+/// config.redact_confidential = true;
+///
+/// let messages = db.receive("did:web:did.tsp-test.org:user:bob").await?;
+/// WebhookNotifier::new(config).run(messages).await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Deliver every message from `messages` until the stream ends; messages that
+    /// could not be delivered, and errors in the stream, are logged and skipped
+    pub async fn run(&self, messages: impl Stream<Item = Result<ReceivedTspMessage, Error>>) {
+        let mut messages = std::pin::pin!(messages);
+
+        while let Some(message) = messages.next().await {
+            let result = match message {
+                Ok(message) => self.notify(&message).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                tracing::error!("webhook delivery to {} failed: {e}", self.config.url);
+            }
+        }
+    }
+
+    /// Deliver a single message, retrying on connection errors and server errors
+    pub async fn notify(&self, message: &ReceivedTspMessage) -> Result<(), Error> {
+        let body = self.to_json(message).to_string();
+        let signature = self
+            .config
+            .secret
+            .as_deref()
+            .map(|secret| sign(secret, &body));
+
+        let mut delay = self.config.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            let mut request = self
+                .client
+                .post(self.config.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            let result = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.config.max_retries && is_transient(&e) => {
+                    tracing::debug!(
+                        "webhook delivery to {} failed, retrying: {e}",
+                        self.config.url
+                    );
+                }
+                Err(e) => {
+                    return Err(TransportError::Http(self.config.url.to_string(), e).into());
+                }
+            }
+
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// The JSON representation of `message`, as delivered to the webhook
+    pub fn to_json(&self, message: &ReceivedTspMessage) -> Value {
+        match message {
+            ReceivedTspMessage::GenericMessage {
+                sender,
+                nonconfidential_data,
+                message,
+                content_type,
+                segments,
+                in_reply_to,
+                digest,
+                message_type,
+            } => json!({
+                "type": "message",
+                "sender": sender,
+                "nonconfidentialData": nonconfidential_data.as_deref().map(encode),
+                "message": self.confidential(message),
+                "contentType": content_type,
+                "segments": segments
+                    .iter()
+                    .map(|(content_type, data)| json!({
+                        "contentType": content_type,
+                        "data": self.confidential(data),
+                    }))
+                    .collect::<Vec<_>>(),
+                "inReplyTo": in_reply_to.as_ref().map(encode_digest),
+                "digest": encode_digest(digest),
+                "cryptoType": format!("{:?}", message_type.crypto_type),
+                "signatureType": format!("{:?}", message_type.signature_type),
+            }),
+            ReceivedTspMessage::RequestRelationship {
+                sender,
+                nested_vid,
+                thread_id,
+                ..
+            } => json!({
+                "type": "requestRelationship",
+                "sender": sender,
+                "nestedVid": nested_vid,
+                "threadId": encode_digest(thread_id),
+            }),
+            ReceivedTspMessage::AcceptRelationship { sender, nested_vid } => json!({
+                "type": "acceptRelationship",
+                "sender": sender,
+                "nestedVid": nested_vid,
+            }),
+            ReceivedTspMessage::RejectRelationship {
+                sender,
+                thread_id,
+                reason,
+            } => json!({
+                "type": "rejectRelationship",
+                "sender": sender,
+                "threadId": encode_digest(thread_id),
+                "reason": reason,
+            }),
+            ReceivedTspMessage::RenewRelationship {
+                sender,
+                thread_id,
+                expires_at,
+            } => json!({
+                "type": "renewRelationship",
+                "sender": sender,
+                "threadId": encode_digest(thread_id),
+                "expiresAt": expires_at,
+            }),
+            ReceivedTspMessage::SessionEstablished { sender } => json!({
+                "type": "sessionEstablished",
+                "sender": sender,
+            }),
+            ReceivedTspMessage::Acknowledgement { sender, digest } => json!({
+                "type": "acknowledgement",
+                "sender": sender,
+                "digest": encode_digest(digest),
+            }),
+            ReceivedTspMessage::CancelRelationship { sender, nested_vid } => json!({
+                "type": "cancelRelationship",
+                "sender": sender,
+                "nestedVid": nested_vid,
+            }),
+            ReceivedTspMessage::ForwardRequest {
+                sender,
+                next_hop,
+                route,
+                opaque_payload,
+                ..
+            } => json!({
+                "type": "forwardRequest",
+                "sender": sender,
+                "nextHop": next_hop,
+                "hops": route.len(),
+                "size": opaque_payload.len(),
+            }),
+            ReceivedTspMessage::NewIdentifier { sender, new_vid } => json!({
+                "type": "newIdentifier",
+                "sender": sender,
+                "newVid": new_vid,
+            }),
+            ReceivedTspMessage::Referral {
+                sender,
+                referred_vid,
+            } => json!({
+                "type": "referral",
+                "sender": sender,
+                "referredVid": referred_vid,
+            }),
+            ReceivedTspMessage::GroupMessage {
+                sender,
+                group,
+                nonconfidential_data,
+                message,
+                message_type,
+            } => json!({
+                "type": "groupMessage",
+                "sender": sender,
+                "group": group,
+                "nonconfidentialData": nonconfidential_data.as_deref().map(encode),
+                "message": self.confidential(message),
+                "cryptoType": format!("{:?}", message_type.crypto_type),
+                "signatureType": format!("{:?}", message_type.signature_type),
+            }),
+            ReceivedTspMessage::GroupMembership {
+                sender,
+                group,
+                member,
+                change,
+            } => json!({
+                "type": "groupMembership",
+                "sender": sender,
+                "group": group,
+                "member": member,
+                "change": format!("{change:?}"),
+            }),
+            ReceivedTspMessage::Extension {
+                sender,
+                typecode,
+                message,
+            } => json!({
+                "type": "extension",
+                "sender": sender,
+                "typecode": typecode,
+                "message": self.confidential(message.to_string().as_bytes()),
+            }),
+            ReceivedTspMessage::UnknownControl {
+                sender,
+                typecode,
+                raw,
+            } => json!({
+                "type": "unknownControl",
+                "sender": sender,
+                "typecode": typecode,
+                "raw": self.confidential(raw),
+            }),
+            ReceivedTspMessage::PendingMessage {
+                unknown_vid,
+                payload,
+            } => json!({
+                "type": "pendingMessage",
+                "unknownVid": unknown_vid,
+                "size": payload.len(),
+            }),
+        }
+    }
+
+    /// A confidential payload, either base64url encoded or redacted to its size
+    fn confidential(&self, data: &[u8]) -> Value {
+        if self.config.redact_confidential {
+            json!({ "redacted": true, "size": data.len() })
+        } else {
+            json!(encode(data))
+        }
+    }
+}
+
+fn encode(data: &[u8]) -> String {
+    Base64UrlUnpadded::encode_string(data)
+}
+
+fn encode_digest(digest: &Digest) -> String {
+    encode(digest)
+}
+
+/// The value of the [SIGNATURE_HEADER] for `body`
+pub fn sign(secret: &[u8], body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+
+    let signature = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    format!("sha256={signature}")
+}
+
+/// Connection errors, timeouts, rate limiting and server errors may succeed on a retry
+fn is_transient(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => status.is_server_error() || status.as_u16() == 429,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::MessageType;
+
+    fn message() -> ReceivedTspMessage {
+        ReceivedTspMessage::GenericMessage {
+            sender: "did:example:alice".to_string(),
+            nonconfidential_data: Some(b"header".to_vec()),
+            message: b"secret".to_vec(),
+            content_type: Some(crate::definitions::content_type::TEXT.to_string()),
+            segments: Vec::new(),
+            in_reply_to: None,
+            digest: Default::default(),
+            message_type: MessageType {
+                crypto_type: crate::cesr::CryptoType::HpkeAuth,
+                signature_type: crate::cesr::SignatureType::Ed25519,
+                digest_algorithm: Default::default(),
+                compressed: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_redaction() {
+        let mut config = WebhookConfig::new("http://localhost:8080/hook".parse().unwrap());
+        let notifier = WebhookNotifier::new(config.clone());
+
+        let json = notifier.to_json(&message());
+        assert_eq!(json["type"], "message");
+        assert_eq!(json["sender"], "did:example:alice");
+        assert_eq!(json["message"], encode(b"secret"));
+        assert_eq!(json["nonconfidentialData"], encode(b"header"));
+
+        config.redact_confidential = true;
+        let json = WebhookNotifier::new(config).to_json(&message());
+        assert_eq!(json["message"], json!({ "redacted": true, "size": 6 }));
+        assert_eq!(json["nonconfidentialData"], encode(b"header"));
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign(b"Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}